@@ -0,0 +1,41 @@
+//! Decode throughput of the LMDB event codec against the JSON values older
+//! versions stored. Run with `cargo bench --bench event_codec`.
+
+use moar::storage::codec;
+use nostr::{EventBuilder, JsonUtil, Keys, Tag};
+use std::hint::black_box;
+use std::time::Instant;
+
+const ROUNDS: usize = 100_000;
+
+fn time(label: &str, mut f: impl FnMut()) {
+    let started = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    let elapsed = started.elapsed();
+    println!("{:<14} {:>8.0} ns/event", label, elapsed.as_nanos() as f64 / ROUNDS as f64);
+}
+
+fn main() {
+    let keys = Keys::generate();
+    let tags = [
+        Tag::hashtag("nostr"),
+        Tag::public_key(Keys::generate().public_key()),
+        Tag::parse(&["e", &"a".repeat(64), "wss://relay.example.com", "reply"]).unwrap(),
+    ];
+    let event = EventBuilder::text_note("GM nostr! ".repeat(20), tags).to_event(&keys).unwrap();
+    let json = event.as_json().into_bytes();
+    let binary = codec::encode_event(&event);
+    println!("json {} bytes, binary {} bytes", json.len(), binary.len());
+
+    time("decode json", || {
+        black_box(codec::decode_event(black_box(&json)).unwrap());
+    });
+    time("decode binary", || {
+        black_box(codec::decode_event(black_box(&binary)).unwrap());
+    });
+    time("encode binary", || {
+        black_box(codec::encode_event(black_box(&event)));
+    });
+}
@@ -1,6 +1,90 @@
-use nostr::Event;
+use base64::Engine;
+use nostr::{Event, EventId, PublicKey};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
 
+/// How far a NIP-98 event's `created_at` may be from now.
+pub const NIP98_WINDOW_SECS: u64 = 60;
+/// How far a NIP-42 AUTH event's `created_at` may be from now.
+pub const RELAY_AUTH_WINDOW_SECS: u64 = 600;
 
+/// Ids of auth events already accepted (NIP-98 logins and API calls, NIP-42
+/// AUTH, Blossom authorizations), each kept until its timestamp would fail
+/// the freshness check anyway, so a captured event can't be replayed.
+#[derive(Default)]
+pub struct ReplayGuard {
+    seen: Mutex<HashMap<EventId, u64>>,
+}
+
+impl ReplayGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `event` as used; it was valid for `window_secs` around its
+    /// `created_at`. Returns `false` if it had been used before.
+    pub fn mark_used(&self, event: &Event, window_secs: u64) -> bool {
+        let now = nostr::Timestamp::now().as_u64();
+        let expires = event.created_at.as_u64().saturating_add(window_secs);
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, exp| *exp >= now);
+        if seen.contains_key(&event.id) {
+            return false;
+        }
+        seen.insert(event.id, expires);
+        true
+    }
+
+    /// Whether `event` was already used, without recording it.
+    pub fn is_used(&self, event: &Event) -> bool {
+        self.seen.lock().unwrap().contains_key(&event.id)
+    }
+}
+
+/// NIP-42 AUTH events accepted by relays, kept while the connection that
+/// sent them is open. A blossom server paired with the relay accepts one
+/// back as upload authorization, so a user already authenticated on the
+/// relay can upload without signing again.
+#[derive(Default)]
+pub struct AuthGrants {
+    grants: Mutex<HashMap<EventId, (String, PublicKey)>>,
+}
+
+impl AuthGrants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `event` authenticated its author on `relay_id`.
+    pub fn grant(&self, event: &Event, relay_id: &str) {
+        self.grants
+            .lock()
+            .unwrap()
+            .insert(event.id, (relay_id.to_string(), event.pubkey));
+    }
+
+    /// Forget `id`, once its connection has closed.
+    pub fn revoke(&self, id: &EventId) {
+        self.grants.lock().unwrap().remove(id);
+    }
+
+    /// Who `event` authenticated on `relay_id`, if its connection is still
+    /// open.
+    pub fn pubkey(&self, event: &Event, relay_id: &str) -> Option<PublicKey> {
+        let grants = self.grants.lock().unwrap();
+        let (relay, pubkey) = grants.get(&event.id)?;
+        (relay == relay_id && *pubkey == event.pubkey).then_some(*pubkey)
+    }
+
+    pub fn len(&self) -> usize {
+        self.grants.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
 
 pub fn verify_auth_event(event: &Event, _url: &str, _method: &str) -> Result<(), String> {
     // 1. Verify signature
@@ -18,7 +102,7 @@ pub fn verify_auth_event(event: &Event, _url: &str, _method: &str) -> Result<(),
     } else {
         event.created_at.as_u64() - now.as_u64()
     };
-    if diff > 60 {
+    if diff > NIP98_WINDOW_SECS {
         return Err("Event too old or in future".to_string());
     }
 
@@ -33,6 +117,113 @@ pub fn verify_auth_event(event: &Event, _url: &str, _method: &str) -> Result<(),
     Ok(())
 }
 
+/// Verify a NIP-42 AUTH event (kind 22242) sent by a relay client in
+/// response to `challenge`. `relay_urls` are the URLs this relay is reached
+/// at; only hosts are compared so `ws`/`wss`/`https` variants all match.
+/// Returns the authenticated pubkey.
+pub fn verify_relay_auth(
+    event: &Event,
+    challenge: &str,
+    relay_urls: &[&str],
+) -> Result<nostr::PublicKey, String> {
+    event.verify().map_err(|_| "invalid signature".to_string())?;
+
+    if event.kind.as_u64() != 22242 {
+        return Err("auth event must be kind 22242".to_string());
+    }
+
+    // NIP-42 suggests a window of about ten minutes
+    let now = nostr::Timestamp::now().as_u64();
+    let created_at = event.created_at.as_u64();
+    if now.abs_diff(created_at) > RELAY_AUTH_WINDOW_SECS {
+        return Err("auth event too old or in future".to_string());
+    }
+
+    let tag_value = |name: &str| {
+        event.tags.iter().find_map(|tag| {
+            let v = tag.as_vec();
+            (v.len() >= 2 && v[0] == name).then(|| v[1].clone())
+        })
+    };
+
+    if tag_value("challenge").as_deref() != Some(challenge) {
+        return Err("challenge does not match".to_string());
+    }
+
+    if relay_urls.iter().any(|url| !url.is_empty()) {
+        let given = tag_value("relay").and_then(|r| nostr::Url::parse(&r).ok());
+        let matches = given.is_some_and(|given| {
+            relay_urls
+                .iter()
+                .filter_map(|url| nostr::Url::parse(url).ok())
+                .any(|expected| expected.host_str() == given.host_str())
+        });
+        if !matches {
+            return Err("relay tag does not match this relay".to_string());
+        }
+    }
+
+    Ok(event.pubkey)
+}
+
+/// Verify a NIP-98 `Authorization: Nostr <base64>` header for a single
+/// request. Unlike the login flow, the `u` tag must be one of `urls` (the
+/// public URLs the request was sent to, so a header signed for another
+/// instance is refused), the `method` tag must match, and a request with a
+/// body must carry its sha256 in the `payload` tag. Returns the signed event.
+pub fn verify_nip98_header(
+    headers: &axum::http::HeaderMap,
+    urls: &[String],
+    method: &str,
+    body: &[u8],
+) -> Result<Event, String> {
+    let auth_header = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .ok_or("Missing Authorization header")?;
+
+    let b64 = auth_header
+        .strip_prefix("Nostr ")
+        .ok_or("Authorization header must start with 'Nostr '")?;
+
+    let json_bytes = base64::engine::general_purpose::STANDARD
+        .decode(b64)
+        .map_err(|_| "Invalid base64 in Authorization header")?;
+
+    let event: Event =
+        serde_json::from_slice(&json_bytes).map_err(|_| "Invalid JSON in auth event")?;
+
+    verify_auth_event(&event, urls.first().map_or("", String::as_str), method)?;
+
+    let tag_value = |name: &str| {
+        event.tags.iter().find_map(|tag| {
+            let v = tag.as_vec();
+            (v.len() >= 2 && v[0] == name).then(|| v[1].clone())
+        })
+    };
+
+    // Parsing both sides normalizes default ports and percent-encoding
+    let url = tag_value("u").ok_or("Auth event missing 'u' tag")?;
+    let signed = nostr::Url::parse(&url).map_err(|_| "Auth event 'u' tag is not a URL")?;
+    if !urls.iter().filter_map(|u| nostr::Url::parse(u).ok()).any(|expected| expected == signed) {
+        return Err("Auth event 'u' tag does not match request URL".to_string());
+    }
+
+    let event_method = tag_value("method").ok_or("Auth event missing 'method' tag")?;
+    if !event_method.eq_ignore_ascii_case(method) {
+        return Err("Auth event 'method' tag does not match request method".to_string());
+    }
+
+    if !body.is_empty() {
+        let payload = tag_value("payload").ok_or("Auth event missing 'payload' tag")?;
+        if !payload.eq_ignore_ascii_case(&format!("{:x}", Sha256::digest(body))) {
+            return Err("Auth event 'payload' tag does not match request body".to_string());
+        }
+    }
+
+    Ok(event)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -45,6 +236,39 @@ mod tests {
             .unwrap()
     }
 
+    #[test]
+    fn replay_guard_rejects_reuse_until_expiry() {
+        let keys = Keys::generate();
+        let guard = ReplayGuard::new();
+        let event = make_auth_event(&keys, Timestamp::now());
+        assert!(!guard.is_used(&event));
+        assert!(guard.mark_used(&event, NIP98_WINDOW_SECS));
+        assert!(guard.is_used(&event));
+        assert!(!guard.mark_used(&event, NIP98_WINDOW_SECS));
+
+        // Entries past their window are pruned on the next use
+        let old = make_auth_event(&keys, Timestamp::from(Timestamp::now().as_u64() - 1000));
+        assert!(guard.mark_used(&old, NIP98_WINDOW_SECS));
+        assert!(guard.mark_used(&make_auth_event(&keys, Timestamp::now() + 1), NIP98_WINDOW_SECS));
+        assert!(!guard.is_used(&old));
+    }
+
+    #[test]
+    fn grants_are_scoped_to_their_relay_until_revoked() {
+        let keys = Keys::generate();
+        let grants = AuthGrants::new();
+        let auth = EventBuilder::new(Kind::from(22242u16), "", []).to_event(&keys).unwrap();
+        assert!(grants.pubkey(&auth, "public").is_none());
+
+        grants.grant(&auth, "public");
+        assert_eq!(grants.pubkey(&auth, "public"), Some(keys.public_key()));
+        assert!(grants.pubkey(&auth, "private").is_none());
+
+        grants.revoke(&auth.id);
+        assert!(grants.pubkey(&auth, "public").is_none());
+        assert!(grants.is_empty());
+    }
+
     #[test]
     fn valid_auth_event_succeeds() {
         let keys = Keys::generate();
@@ -77,6 +301,94 @@ mod tests {
         assert!(verify_auth_event(&event, "/api/login", "POST").is_ok());
     }
 
+    fn nip98_headers(keys: &Keys, url: &str, method: &str, payload: Option<&[u8]>) -> axum::http::HeaderMap {
+        let mut tags = vec![
+            nostr::Tag::parse(&["u", url]).unwrap(),
+            nostr::Tag::parse(&["method", method]).unwrap(),
+        ];
+        if let Some(body) = payload {
+            tags.push(nostr::Tag::parse(&["payload", &format!("{:x}", Sha256::digest(body))]).unwrap());
+        }
+        let event = EventBuilder::new(Kind::from(27235u16), "", tags).to_event(keys).unwrap();
+        let b64 = base64::engine::general_purpose::STANDARD.encode(nostr::JsonUtil::as_json(&event));
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert(
+            axum::http::header::AUTHORIZATION,
+            format!("Nostr {}", b64).parse().unwrap(),
+        );
+        headers
+    }
+
+    fn urls(url: &str) -> Vec<String> {
+        vec![url.to_string()]
+    }
+
+    #[test]
+    fn nip98_header_matching_request_accepted() {
+        let keys = Keys::generate();
+        let headers = nip98_headers(&keys, "https://example.com/api/relays?x=1", "GET", None);
+        let event = verify_nip98_header(&headers, &urls("https://example.com/api/relays?x=1"), "GET", b"").unwrap();
+        assert_eq!(event.author(), keys.public_key());
+        // The default port is implied
+        assert!(verify_nip98_header(&headers, &urls("https://example.com:443/api/relays?x=1"), "GET", b"").is_ok());
+    }
+
+    #[test]
+    fn nip98_header_wrong_url_or_method_rejected() {
+        let keys = Keys::generate();
+        let headers = nip98_headers(&keys, "https://example.com/api/relays", "GET", None);
+        assert!(verify_nip98_header(&headers, &urls("https://example.com/api/wots"), "GET", b"").is_err());
+        assert!(verify_nip98_header(&headers, &urls("https://example.com/api/relays"), "DELETE", b"").is_err());
+        assert!(verify_nip98_header(&axum::http::HeaderMap::new(), &urls("https://example.com/api/relays"), "GET", b"").is_err());
+    }
+
+    #[test]
+    fn nip98_header_for_another_host_rejected() {
+        let keys = Keys::generate();
+        let headers = nip98_headers(&keys, "https://other.example.org/api/relays", "GET", None);
+        assert!(verify_nip98_header(&headers, &urls("https://example.com/api/relays"), "GET", b"").is_err());
+        let headers = nip98_headers(&keys, "http://example.com/api/relays", "GET", None);
+        assert!(verify_nip98_header(&headers, &urls("https://example.com/api/relays"), "GET", b"").is_err());
+    }
+
+    #[test]
+    fn nip98_payload_must_match_the_body() {
+        let keys = Keys::generate();
+        let url = urls("https://example.com/api/relays");
+        let headers = nip98_headers(&keys, &url[0], "POST", Some(b"{\"id\":\"a\"}"));
+        assert!(verify_nip98_header(&headers, &url, "POST", b"{\"id\":\"a\"}").is_ok());
+        assert!(verify_nip98_header(&headers, &url, "POST", b"{\"id\":\"b\"}").is_err());
+
+        // A body without a payload tag isn't covered by the signature
+        let headers = nip98_headers(&keys, &url[0], "POST", None);
+        assert!(verify_nip98_header(&headers, &url, "POST", b"{}").is_err());
+        assert!(verify_nip98_header(&headers, &url, "POST", b"").is_ok());
+    }
+
+    fn relay_auth_event(keys: &Keys, challenge: &str, relay: &str) -> Event {
+        EventBuilder::auth(challenge, nostr::Url::parse(relay).unwrap())
+            .to_event(keys)
+            .unwrap()
+    }
+
+    #[test]
+    fn relay_auth_accepts_matching_challenge_and_host() {
+        let keys = Keys::generate();
+        let event = relay_auth_event(&keys, "abc", "wss://outbox.example.com");
+        let pk = verify_relay_auth(&event, "abc", &["https://outbox.example.com"]).unwrap();
+        assert_eq!(pk, keys.public_key());
+        // Also when reached at another address, such as the apex domain
+        assert!(verify_relay_auth(&event, "abc", &["https://example.com", "wss://outbox.example.com/"]).is_ok());
+    }
+
+    #[test]
+    fn relay_auth_rejects_wrong_challenge_or_relay() {
+        let keys = Keys::generate();
+        let event = relay_auth_event(&keys, "abc", "wss://outbox.example.com");
+        assert!(verify_relay_auth(&event, "xyz", &["https://outbox.example.com"]).is_err());
+        assert!(verify_relay_auth(&event, "abc", &["https://inbox.example.com"]).is_err());
+    }
+
     #[test]
     fn event_30s_in_past_accepted() {
         let keys = Keys::generate();
@@ -0,0 +1,406 @@
+//! Scheduled backups: every `interval_hours` each relay database and each
+//! blossom metadata store is snapshotted into a `scheduled-<unix time>`
+//! directory, optionally uploaded to S3, and runs beyond `retain` are removed.
+//! A run is only marked complete once every snapshot and upload succeeded;
+//! failed runs are discarded and never count toward `retain`.
+
+use crate::blossom::store::BlobStore;
+use crate::config::BackupConfig;
+use crate::s3::S3Client;
+use crate::storage::NostrStore;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+const RUN_PREFIX: &str = "scheduled-";
+/// Written into a run (and uploaded last) once everything else succeeded.
+const COMPLETE_MARKER: &str = "complete";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupFile {
+    pub name: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BackupStatus {
+    pub running: bool,
+    pub last_started: Option<u64>,
+    pub last_finished: Option<u64>,
+    /// Start time of the most recent run in which every snapshot and upload succeeded.
+    pub last_success: Option<u64>,
+    pub last_error: Option<String>,
+    pub last_files: Vec<BackupFile>,
+    pub next_run: Option<u64>,
+}
+
+pub struct BackupManager {
+    config: BackupConfig,
+    dir: PathBuf,
+    s3: Option<S3Client>,
+    relays: HashMap<String, Arc<dyn NostrStore>>,
+    blossoms: HashMap<String, Arc<BlobStore>>,
+    status: RwLock<BackupStatus>,
+}
+
+impl BackupManager {
+    /// `default_dir` is used when the config doesn't set its own `dir`.
+    pub fn new(
+        config: BackupConfig,
+        default_dir: &Path,
+        relays: HashMap<String, Arc<dyn NostrStore>>,
+        blossoms: HashMap<String, Arc<BlobStore>>,
+    ) -> Self {
+        let dir = config.dir.as_ref().map(PathBuf::from).unwrap_or_else(|| default_dir.to_path_buf());
+        let s3 = config.s3.clone().map(S3Client::new);
+        Self {
+            config,
+            dir,
+            s3,
+            relays,
+            blossoms,
+            status: RwLock::new(BackupStatus::default()),
+        }
+    }
+
+    pub fn config(&self) -> &BackupConfig {
+        &self.config
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub async fn status(&self) -> BackupStatus {
+        self.status.read().await.clone()
+    }
+
+    /// Spawn the scheduler. The first run is due one interval after the
+    /// newest existing local run, so restarts don't reset the schedule.
+    pub fn start(self: &Arc<Self>) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let interval = manager.config.interval_hours.max(1) * 3600;
+            let mut next = match list_runs(&manager.dir).await.iter().rev().find(|run| run.complete) {
+                Some(run) => run.ts + interval,
+                None => now_secs() + 60,
+            };
+            loop {
+                manager.status.write().await.next_run = Some(next);
+                let wait = next.saturating_sub(now_secs());
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+
+                let started = now_secs();
+                manager.run(started).await;
+                next = started + interval;
+            }
+        });
+    }
+
+    /// Perform one backup run named after `started`. A successful run is
+    /// marked complete and old runs are rotated; a failed one is removed.
+    pub async fn run(&self, started: u64) {
+        {
+            let mut status = self.status.write().await;
+            status.running = true;
+            status.last_started = Some(started);
+        }
+        tracing::info!(dir = %self.dir.display(), "Starting scheduled backup");
+
+        let run_name = format!("{}{}", RUN_PREFIX, started);
+        let run_dir = self.dir.join(&run_name);
+        let mut errors = Vec::new();
+        let mut files = Vec::new();
+
+        if let Err(e) = tokio::fs::create_dir_all(&run_dir).await {
+            errors.push(format!("create {}: {}", run_dir.display(), e));
+        } else {
+            for (name, result) in self.snapshot_all(&run_dir).await {
+                match result {
+                    Ok(bytes) => files.push(BackupFile { name, bytes }),
+                    Err(e) => errors.push(format!("{}: {}", name, e)),
+                }
+            }
+        }
+
+        if let Some(s3) = &self.s3 {
+            for file in &files {
+                let key = format!("{}/{}", run_name, file.name);
+                if let Err(e) = s3.put_file(&key, &run_dir.join(&file.name)).await {
+                    errors.push(format!("upload {}: {}", key, e));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            if let Err(e) = self.mark_complete(&run_name, &run_dir).await {
+                errors.push(format!("mark complete: {}", e));
+            }
+        }
+        if errors.is_empty() {
+            if let Err(e) = self.rotate(started).await {
+                errors.push(format!("rotate: {}", e));
+            }
+        } else {
+            self.discard(&run_name, &run_dir).await;
+        }
+
+        let mut status = self.status.write().await;
+        status.running = false;
+        status.last_finished = Some(now_secs());
+        status.last_files = files;
+        if errors.is_empty() {
+            tracing::info!(run = %run_name, "Scheduled backup finished");
+            status.last_success = Some(started);
+            status.last_error = None;
+        } else {
+            let message = errors.join("; ");
+            tracing::error!(run = %run_name, "Scheduled backup failed: {}", message);
+            status.last_error = Some(message);
+        }
+    }
+
+    /// Snapshot every relay and blossom store into `run_dir`, returning the
+    /// file name and its size (or the error) for each.
+    async fn snapshot_all(&self, run_dir: &Path) -> Vec<(String, Result<u64, String>)> {
+        let mut results = Vec::new();
+
+        for (id, store) in &self.relays {
+            let name = format!("relay-{}.mdb", id);
+            let dest = run_dir.join(&name);
+            let store = store.clone();
+            let result = snapshot_blocking(dest, move |d| store.snapshot_to(d).map_err(|e| e.to_string())).await;
+            results.push((name, result));
+        }
+        for (id, store) in &self.blossoms {
+            let name = format!("blossom-{}.mdb", id);
+            let dest = run_dir.join(&name);
+            let store = store.clone();
+            let result = snapshot_blocking(dest, move |d| store.snapshot_to(d).map_err(|e| e.to_string())).await;
+            results.push((name, result));
+        }
+
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        results
+    }
+
+    async fn mark_complete(&self, run_name: &str, run_dir: &Path) -> Result<(), anyhow::Error> {
+        tokio::fs::write(run_dir.join(COMPLETE_MARKER), b"").await?;
+        if let Some(s3) = &self.s3 {
+            s3.put_object(&format!("{}/{}", run_name, COMPLETE_MARKER), Vec::new(), "text/plain").await?;
+        }
+        Ok(())
+    }
+
+    /// Remove what a failed run left behind, locally and in S3.
+    async fn discard(&self, run_name: &str, run_dir: &Path) {
+        if let Err(e) = tokio::fs::remove_dir_all(run_dir).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!(run = %run_name, "Failed to remove failed backup run: {}", e);
+            }
+        }
+        if let Some(s3) = &self.s3 {
+            let keys = match s3.list_keys(&format!("{}/", run_name)).await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    tracing::warn!(run = %run_name, "Failed to list failed backup run in S3: {}", e);
+                    return;
+                }
+            };
+            for key in keys {
+                if let Err(e) = s3.delete_object(&key).await {
+                    tracing::warn!(run = %run_name, "Failed to delete {} from S3: {}", key, e);
+                }
+            }
+        }
+    }
+
+    /// Delete all but the newest `retain` complete runs, locally and in S3,
+    /// along with incomplete runs other than `current`.
+    async fn rotate(&self, current: u64) -> Result<(), anyhow::Error> {
+        let runs = list_runs(&self.dir).await;
+        let complete = runs.iter().filter(|run| run.complete).count();
+        let mut excess = complete.saturating_sub(self.config.retain);
+        for run in &runs {
+            let expired = run.complete && excess > 0;
+            if expired {
+                excess -= 1;
+            }
+            if expired || (!run.complete && run.ts != current) {
+                tokio::fs::remove_dir_all(&run.path).await?;
+            }
+        }
+
+        if let Some(s3) = &self.s3 {
+            let mut remote: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+            for key in s3.list_keys(RUN_PREFIX).await? {
+                let ts = key
+                    .split('/')
+                    .next()
+                    .and_then(|run| run.strip_prefix(RUN_PREFIX))
+                    .and_then(|ts| ts.parse::<u64>().ok());
+                if let Some(ts) = ts {
+                    remote.entry(ts).or_default().push(key);
+                }
+            }
+            let is_complete =
+                |keys: &[String]| keys.iter().any(|key| key.rsplit('/').next() == Some(COMPLETE_MARKER));
+            let complete = remote.values().filter(|keys| is_complete(keys)).count();
+            let mut excess = complete.saturating_sub(self.config.retain);
+            for (ts, keys) in remote {
+                let complete = is_complete(&keys);
+                let expired = complete && excess > 0;
+                if expired {
+                    excess -= 1;
+                }
+                if expired || (!complete && ts != current) {
+                    for key in keys {
+                        s3.delete_object(&key).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn snapshot_blocking<F>(dest: PathBuf, snapshot: F) -> Result<u64, String>
+where
+    F: FnOnce(&Path) -> Result<(), String> + Send + 'static,
+{
+    let target = dest.clone();
+    tokio::task::spawn_blocking(move || snapshot(&target))
+        .await
+        .map_err(|e| e.to_string())??;
+    tokio::fs::metadata(&dest)
+        .await
+        .map(|m| m.len())
+        .map_err(|e| e.to_string())
+}
+
+struct Run {
+    ts: u64,
+    path: PathBuf,
+    complete: bool,
+}
+
+/// Local run directories in `dir`, oldest first.
+async fn list_runs(dir: &Path) -> Vec<Run> {
+    let mut runs = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return runs;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name();
+        let ts = name
+            .to_str()
+            .and_then(|n| n.strip_prefix(RUN_PREFIX))
+            .and_then(|ts| ts.parse::<u64>().ok());
+        if let Some(ts) = ts {
+            let path = entry.path();
+            let complete = tokio::fs::try_exists(path.join(COMPLETE_MARKER)).await.unwrap_or(false);
+            runs.push(Run { ts, path, complete });
+        }
+    }
+    runs.sort_by_key(|run| run.ts);
+    runs
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::lmdb::LmdbStore;
+    use nostr::{EventBuilder, Keys};
+
+    #[tokio::test]
+    async fn snapshots_every_store_and_keeps_newest_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let relay = LmdbStore::new(dir.path().join("relay")).unwrap();
+        let event = EventBuilder::text_note("hello", []).to_event(&Keys::generate()).unwrap();
+        relay.save_event(&event).unwrap();
+        let blobs = BlobStore::new(dir.path().join("blobs")).unwrap();
+
+        let config = BackupConfig {
+            interval_hours: 24,
+            dir: None,
+            retain: 2,
+            s3: None,
+        };
+        let backup_dir = dir.path().join("backups");
+        let manager = BackupManager::new(
+            config,
+            &backup_dir,
+            HashMap::from([("outbox".to_string(), Arc::new(relay) as Arc<dyn NostrStore>)]),
+            HashMap::from([("media".to_string(), Arc::new(blobs))]),
+        );
+
+        for started in [100, 200, 300] {
+            manager.run(started).await;
+        }
+
+        let status = manager.status().await;
+        assert_eq!(status.last_error, None);
+        assert_eq!(status.last_success, Some(300));
+        let names: Vec<&str> = status.last_files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["blossom-media.mdb", "relay-outbox.mdb"]);
+
+        let runs: Vec<u64> = list_runs(&backup_dir).await.into_iter().map(|run| run.ts).collect();
+        assert_eq!(runs, vec![200, 300]);
+
+        let restore_dir = dir.path().join("restore");
+        std::fs::create_dir_all(&restore_dir).unwrap();
+        std::fs::copy(
+            backup_dir.join("scheduled-300/relay-outbox.mdb"),
+            restore_dir.join("data.mdb"),
+        )
+        .unwrap();
+        assert_eq!(LmdbStore::new(&restore_dir).unwrap().event_count().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_runs_are_discarded_and_never_rotate_out_good_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let relay = LmdbStore::new(dir.path().join("relay")).unwrap();
+        let config = BackupConfig {
+            interval_hours: 24,
+            dir: None,
+            retain: 2,
+            s3: None,
+        };
+        let backup_dir = dir.path().join("backups");
+        let manager = BackupManager::new(
+            config,
+            &backup_dir,
+            HashMap::from([("outbox".to_string(), Arc::new(relay) as Arc<dyn NostrStore>)]),
+            HashMap::new(),
+        );
+        let runs = || async { list_runs(&backup_dir).await.into_iter().map(|run| run.ts).collect::<Vec<u64>>() };
+
+        manager.run(100).await;
+        manager.run(200).await;
+        // A directory where the snapshot should go makes the run fail
+        for started in [300, 400] {
+            std::fs::create_dir_all(backup_dir.join(format!("scheduled-{}/relay-outbox.mdb", started))).unwrap();
+            manager.run(started).await;
+            assert!(manager.status().await.last_error.is_some());
+        }
+        assert_eq!(runs().await, vec![100, 200]);
+        assert_eq!(manager.status().await.last_success, Some(200));
+
+        // Leftovers of an interrupted run are cleaned up by the next good one
+        std::fs::create_dir_all(backup_dir.join("scheduled-450")).unwrap();
+        manager.run(500).await;
+        assert_eq!(manager.status().await.last_error, None);
+        assert_eq!(runs().await, vec![200, 500]);
+    }
+}
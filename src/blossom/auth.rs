@@ -1,14 +1,12 @@
 use base64::Engine;
 use nostr::Event;
 
-/// Verify a Blossom authorization header (kind 24242).
-///
-/// Expects the `Authorization: Nostr <base64>` header value.
-/// `expected_action` should be "upload", "delete", or "list".
-pub fn verify_blossom_auth(
-    headers: &axum::http::HeaderMap,
-    expected_action: &str,
-) -> Result<Event, String> {
+/// How far in the future a Blossom authorization's `created_at` may be, to
+/// allow for clock skew.
+pub const BLOSSOM_AUTH_SKEW_SECS: u64 = 60;
+
+/// The event in an `Authorization: Nostr <base64>` header, unverified.
+pub fn decode_auth_header(headers: &axum::http::HeaderMap) -> Result<Event, String> {
     let auth_header = headers
         .get("authorization")
         .and_then(|v| v.to_str().ok())
@@ -22,8 +20,18 @@ pub fn verify_blossom_auth(
         .decode(b64)
         .map_err(|_| "Invalid base64 in Authorization header")?;
 
-    let event: Event =
-        serde_json::from_slice(&json_bytes).map_err(|_| "Invalid JSON in auth event")?;
+    serde_json::from_slice(&json_bytes).map_err(|_| "Invalid JSON in auth event".to_string())
+}
+
+/// Verify a Blossom authorization header (kind 24242).
+///
+/// Expects the `Authorization: Nostr <base64>` header value.
+/// `expected_action` should be "upload", "delete", or "list".
+pub fn verify_blossom_auth(
+    headers: &axum::http::HeaderMap,
+    expected_action: &str,
+) -> Result<Event, String> {
+    let event = decode_auth_header(headers)?;
 
     // 1. Verify signature
     event
@@ -35,15 +43,15 @@ pub fn verify_blossom_auth(
         return Err("Event kind must be 24242".to_string());
     }
 
-    // 3. Verify timestamp within 60s
-    let now = nostr::Timestamp::now();
-    let diff = if now > event.created_at {
-        now.as_u64() - event.created_at.as_u64()
-    } else {
-        event.created_at.as_u64() - now.as_u64()
-    };
-    if diff > 60 {
-        return Err("Auth event too old or in future".to_string());
+    // 3. Verify created_at is in the past and the expiration in the future (BUD-01)
+    let now = nostr::Timestamp::now().as_u64();
+    if event.created_at.as_u64() > now + BLOSSOM_AUTH_SKEW_SECS {
+        return Err("Auth event created in the future".to_string());
+    }
+    match expiration(&event) {
+        Some(expires) if expires > now => {}
+        Some(_) => return Err("Auth event has expired".to_string()),
+        None => return Err("Auth event missing 'expiration' tag".to_string()),
     }
 
     // 4. Verify `t` tag matches expected action
@@ -65,13 +73,92 @@ pub fn verify_blossom_auth(
     Ok(event)
 }
 
-/// Extract the `x` tag value (sha256 hash) from a Blossom auth event.
-pub fn get_x_tag(event: &Event) -> Option<String> {
-    for tag in event.tags.iter() {
+/// Values of every `name` tag in `event`.
+fn tag_values<'a>(event: &'a Event, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    event.tags.iter().filter_map(move |tag| {
         let v = tag.as_vec();
-        if v.len() >= 2 && v[0] == "x" {
-            return Some(v[1].clone());
-        }
+        (v.len() >= 2 && v[0] == name).then(|| v[1].as_str())
+    })
+}
+
+/// The `expiration` tag of a Blossom auth event, as a unix timestamp.
+pub fn expiration(event: &Event) -> Option<u64> {
+    tag_values(event, "expiration").next()?.parse().ok()
+}
+
+/// Whether one of the event's `x` tags names `sha256`. An auth event may
+/// cover several blobs.
+pub fn has_x_tag(event: &Event, sha256: &str) -> bool {
+    tag_values(event, "x").any(|x| x.eq_ignore_ascii_case(sha256))
+}
+
+/// Whether the event has any `x` tag.
+pub fn has_any_x_tag(event: &Event) -> bool {
+    tag_values(event, "x").next().is_some()
+}
+
+/// The blob size declared in the event's `size` tag, if any.
+pub fn size_tag(event: &Event) -> Result<Option<u64>, String> {
+    tag_values(event, "size")
+        .next()
+        .map(|s| s.parse().map_err(|_| "Auth event 'size' tag is not a number".to_string()))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+    use nostr::{EventBuilder, JsonUtil, Keys, Kind, Tag, TagKind, Timestamp};
+
+    fn auth_header(tags: Vec<Tag>, created_at: Timestamp) -> HeaderMap {
+        let event = EventBuilder::new(Kind::from(24242u16), "Upload", tags)
+            .custom_created_at(created_at)
+            .to_event(&Keys::generate())
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        let value = format!("Nostr {}", base64::engine::general_purpose::STANDARD.encode(event.as_json()));
+        headers.insert("authorization", value.parse().unwrap());
+        headers
+    }
+
+    fn tag(name: &str, value: &str) -> Tag {
+        Tag::custom(TagKind::Custom(name.to_string().into()), [value.to_string()])
+    }
+
+    fn expires_in(secs: i64) -> Tag {
+        tag("expiration", &(Timestamp::now().as_u64() as i64 + secs).to_string())
+    }
+
+    #[test]
+    fn requires_an_unexpired_expiration_tag() {
+        let now = Timestamp::now();
+        assert!(verify_blossom_auth(&auth_header(vec![tag("t", "upload"), expires_in(60)], now), "upload").is_ok());
+
+        let missing = verify_blossom_auth(&auth_header(vec![tag("t", "upload")], now), "upload");
+        assert!(missing.unwrap_err().contains("expiration"));
+
+        let expired = verify_blossom_auth(&auth_header(vec![tag("t", "upload"), expires_in(-1)], now), "upload");
+        assert!(expired.unwrap_err().contains("expired"));
+
+        // Long-lived authorizations are fine as long as they haven't expired
+        let old = Timestamp::from(now.as_u64() - 3600);
+        assert!(verify_blossom_auth(&auth_header(vec![tag("t", "upload"), expires_in(60)], old), "upload").is_ok());
+
+        let future = Timestamp::from(now.as_u64() + 600);
+        assert!(verify_blossom_auth(&auth_header(vec![tag("t", "upload"), expires_in(900)], future), "upload").is_err());
+    }
+
+    #[test]
+    fn reads_x_and_size_tags() {
+        let headers = auth_header(
+            vec![tag("t", "upload"), expires_in(60), tag("x", "AA"), tag("x", "bb"), tag("size", "42")],
+            Timestamp::now(),
+        );
+        let event = verify_blossom_auth(&headers, "upload").unwrap();
+        assert!(has_x_tag(&event, "aa"));
+        assert!(has_x_tag(&event, "bb"));
+        assert!(!has_x_tag(&event, "cc"));
+        assert_eq!(size_tag(&event), Ok(Some(42)));
     }
-    None
 }
@@ -0,0 +1,106 @@
+//! Where blob contents live. Metadata always stays in the local LMDB of the
+//! `BlobStore`; only the bytes go through a `BlobBackend`.
+
+use crate::error::{Error, Result};
+use crate::s3::S3Client;
+use crate::storage::BoxFuture;
+use axum::body::{Body, Bytes};
+use futures_util::TryStreamExt;
+use std::path::PathBuf;
+
+pub trait BlobBackend: Send + Sync {
+    fn put<'a>(&'a self, sha256: &'a str, data: Bytes) -> BoxFuture<'a, Result<()>>;
+    /// Stream a blob's contents, or `None` if it isn't stored.
+    fn open<'a>(&'a self, sha256: &'a str) -> BoxFuture<'a, Result<Option<Body>>>;
+    fn delete<'a>(&'a self, sha256: &'a str) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Blob files on the local filesystem, sharded by the first 2 hex chars.
+pub struct LocalBackend {
+    blobs_dir: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(blobs_dir: PathBuf) -> Self {
+        Self { blobs_dir }
+    }
+
+    pub fn blob_path(&self, sha256: &str) -> PathBuf {
+        let prefix = &sha256[..2.min(sha256.len())];
+        self.blobs_dir.join(prefix).join(sha256)
+    }
+}
+
+impl BlobBackend for LocalBackend {
+    fn put<'a>(&'a self, sha256: &'a str, data: Bytes) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let path = self.blob_path(sha256);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&path, &data).await?;
+            Ok(())
+        })
+    }
+
+    fn open<'a>(&'a self, sha256: &'a str) -> BoxFuture<'a, Result<Option<Body>>> {
+        Box::pin(async move {
+            match tokio::fs::File::open(self.blob_path(sha256)).await {
+                Ok(file) => Ok(Some(Body::from_stream(tokio_util::io::ReaderStream::new(file)))),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, sha256: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.blob_path(sha256)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+/// Blob contents in an S3-compatible bucket, one object per sha256.
+pub struct S3Backend {
+    client: S3Client,
+}
+
+impl S3Backend {
+    pub fn new(client: S3Client) -> Self {
+        Self { client }
+    }
+}
+
+impl BlobBackend for S3Backend {
+    fn put<'a>(&'a self, sha256: &'a str, data: Bytes) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.client
+                .put_object(sha256, data.to_vec(), "application/octet-stream")
+                .await
+                .map_err(|e| Error::ObjectStorage(e.to_string()))
+        })
+    }
+
+    fn open<'a>(&'a self, sha256: &'a str) -> BoxFuture<'a, Result<Option<Body>>> {
+        Box::pin(async move {
+            let stream = self
+                .client
+                .get_object_stream(sha256)
+                .await
+                .map_err(|e| Error::ObjectStorage(e.to_string()))?;
+            Ok(stream.map(|s| Body::from_stream(s.map_err(std::io::Error::other))))
+        })
+    }
+
+    fn delete<'a>(&'a self, sha256: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.client
+                .delete_object(sha256)
+                .await
+                .map_err(|e| Error::ObjectStorage(e.to_string()))
+        })
+    }
+}
@@ -0,0 +1,509 @@
+//! Resumable uploads for large blobs. The client announces the blob with an
+//! upload authorization (`POST /upload/chunked`), sends it in numbered parts
+//! that can each be retried (`PUT /upload/chunked/:id/:index`), and finally
+//! asks for it to be assembled and verified against the announced hash
+//! (`POST /upload/chunked/:id/complete`). Parts are kept under
+//! `<storage_path>/uploads/<id>/` until then; the upload id is the
+//! capability for the later calls.
+
+use crate::blossom::auth::{has_x_tag, size_tag};
+use crate::blossom::handlers::{authorize_upload, hex, mirror_upload, BlobDescriptor, BlossomState};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Request, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use nostr::PublicKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Most parts a single upload may have.
+pub const MAX_PARTS: u32 = 10_000;
+/// Unfinished uploads are discarded after this long.
+const UPLOAD_TTL_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub sha256: String,
+    pub size: u64,
+    pub mime_type: String,
+    pub uploader: String,
+    pub created_at: u64,
+    /// The signed upload authorization, kept to mirror the finished blob
+    /// with. Relay AUTH grants aren't stored since other servers wouldn't
+    /// accept them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization: Option<String>,
+}
+
+/// The temp area holding unfinished uploads.
+pub struct ChunkedUploads {
+    dir: PathBuf,
+}
+
+impl ChunkedUploads {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Directory of upload `id`, or `None` if `id` isn't one we could have
+    /// handed out.
+    fn upload_dir(&self, id: &str) -> Option<PathBuf> {
+        let valid = !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+        valid.then(|| self.dir.join(id))
+    }
+
+    /// Start an upload and return its id. Expired uploads are swept first.
+    pub async fn create(&self, session: &UploadSession) -> std::io::Result<String> {
+        self.sweep().await;
+        let id = uuid::Uuid::new_v4().to_string();
+        let dir = self.dir.join(&id);
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::write(dir.join("session.json"), serde_json::to_vec(session)?).await?;
+        Ok(id)
+    }
+
+    pub async fn session(&self, id: &str) -> std::io::Result<Option<UploadSession>> {
+        let Some(dir) = self.upload_dir(id) else {
+            return Ok(None);
+        };
+        match tokio::fs::read(dir.join("session.json")).await {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Store part `index`, replacing any earlier attempt. The part only
+    /// becomes visible once fully written.
+    pub async fn put_part(&self, id: &str, index: u32, data: &[u8]) -> std::io::Result<()> {
+        let dir = self.upload_dir(id).ok_or(std::io::ErrorKind::NotFound)?;
+        let tmp = dir.join(format!("part-{:05}.tmp", index));
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, dir.join(format!("part-{:05}", index))).await
+    }
+
+    /// Received parts as (index, size), in order.
+    pub async fn parts(&self, id: &str) -> std::io::Result<Vec<(u32, u64)>> {
+        let dir = self.upload_dir(id).ok_or(std::io::ErrorKind::NotFound)?;
+        let mut parts = Vec::new();
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let Some(index) = name.to_str().and_then(|n| n.strip_prefix("part-")).and_then(|n| n.parse().ok()) else {
+                continue;
+            };
+            parts.push((index, entry.metadata().await?.len()));
+        }
+        parts.sort_unstable();
+        Ok(parts)
+    }
+
+    /// Concatenate the parts and check them against the announced size and
+    /// hash. `Ok(Err(reason))` means the parts don't add up to the blob.
+    pub async fn assemble(&self, id: &str, session: &UploadSession) -> std::io::Result<Result<Bytes, String>> {
+        let dir = self.upload_dir(id).ok_or(std::io::ErrorKind::NotFound)?;
+        let parts = self.parts(id).await?;
+        if let Some((pos, _)) = parts.iter().enumerate().find(|(pos, (index, _))| *pos as u32 != *index) {
+            return Ok(Err(format!("part {} is missing", pos)));
+        }
+        let total: u64 = parts.iter().map(|(_, size)| size).sum();
+        if total != session.size {
+            return Ok(Err(format!("received {} of {} bytes", total, session.size)));
+        }
+
+        let mut data = Vec::with_capacity(session.size as usize);
+        for (index, _) in &parts {
+            data.extend_from_slice(&tokio::fs::read(dir.join(format!("part-{:05}", index))).await?);
+        }
+        let sha256 = hex::encode(Sha256::digest(&data));
+        if sha256 != session.sha256 {
+            return Ok(Err(format!("assembled blob has sha256 {}", sha256)));
+        }
+        Ok(Ok(Bytes::from(data)))
+    }
+
+    pub async fn remove(&self, id: &str) -> std::io::Result<()> {
+        match self.upload_dir(id) {
+            Some(dir) => tokio::fs::remove_dir_all(dir).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Delete uploads older than the TTL.
+    async fn sweep(&self) {
+        let Ok(mut entries) = tokio::fs::read_dir(&self.dir).await else {
+            return;
+        };
+        let cutoff = now().saturating_sub(UPLOAD_TTL_SECS);
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Some(id) = entry.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            let expired = match self.session(&id).await {
+                Ok(Some(session)) => session.created_at < cutoff,
+                // Leftovers without a readable session
+                _ => true,
+            };
+            if expired {
+                let _ = tokio::fs::remove_dir_all(entry.path()).await;
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, message.into()).into_response()
+}
+
+fn storage_error(e: std::io::Error) -> Response {
+    tracing::error!("Chunked upload storage error: {}", e);
+    error(StatusCode::INTERNAL_SERVER_ERROR, "Storage error")
+}
+
+#[derive(Deserialize)]
+pub struct InitiateRequest {
+    sha256: String,
+    size: u64,
+    #[serde(rename = "type")]
+    mime_type: Option<String>,
+}
+
+#[derive(Serialize)]
+struct InitiateResponse {
+    id: String,
+    expires: u64,
+}
+
+/// `POST /upload/chunked`: announce a blob. If it is already stored, its
+/// descriptor is returned right away.
+pub async fn initiate(State(state): State<Arc<BlossomState>>, request: Request<Body>) -> Response {
+    let uploader = match authorize_upload(&state, request.headers(), false) {
+        Ok(uploader) => uploader,
+        Err((status, e)) => return error(status, e),
+    };
+    let pubkey = uploader.pubkey.to_hex();
+    let authorization = uploader
+        .event
+        .is_some()
+        .then(|| request.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()))
+        .flatten()
+        .map(str::to_string);
+
+    let body = match axum::body::to_bytes(request.into_body(), 4096).await {
+        Ok(b) => b,
+        Err(_) => return error(StatusCode::PAYLOAD_TOO_LARGE, "Request too large"),
+    };
+    let req: InitiateRequest = match serde_json::from_slice(&body) {
+        Ok(r) => r,
+        Err(e) => return error(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e)),
+    };
+    let sha256 = req.sha256.to_ascii_lowercase();
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return error(StatusCode::BAD_REQUEST, "sha256 must be 64 hex characters");
+    }
+    if let Some(event) = &uploader.event {
+        if !has_x_tag(event, &sha256) {
+            return error(StatusCode::FORBIDDEN, "Auth event 'x' tag must match the blob sha256");
+        }
+        match size_tag(event) {
+            Ok(Some(size)) if size != req.size => {
+                return error(StatusCode::BAD_REQUEST, "Auth event 'size' tag does not match the announced size")
+            }
+            Err(e) => return error(StatusCode::BAD_REQUEST, e),
+            _ => {}
+        }
+    }
+    let max_size = state.config.policy.max_file_size.unwrap_or(100 * 1024 * 1024);
+    if req.size > max_size {
+        crate::blossom::handlers::report_too_large(&state, &uploader.pubkey, Some(req.size), max_size);
+        return error(StatusCode::PAYLOAD_TOO_LARGE, "File too large");
+    }
+
+    if let Ok(Some(meta)) = state.store.get_meta(&sha256) {
+        return Json(BlobDescriptor::from_meta(&meta, &state.base_url)).into_response();
+    }
+
+    let session = UploadSession {
+        sha256,
+        size: req.size,
+        mime_type: req.mime_type.unwrap_or_else(|| "application/octet-stream".to_string()),
+        uploader: pubkey,
+        created_at: now(),
+        authorization,
+    };
+    match state.uploads.create(&session).await {
+        Ok(id) => (
+            StatusCode::CREATED,
+            Json(InitiateResponse {
+                id,
+                expires: session.created_at + UPLOAD_TTL_SECS,
+            }),
+        )
+            .into_response(),
+        Err(e) => storage_error(e),
+    }
+}
+
+/// `PUT /upload/chunked/:id/:index`: store one part.
+pub async fn put_part(
+    State(state): State<Arc<BlossomState>>,
+    Path((id, index)): Path<(String, u32)>,
+    request: Request<Body>,
+) -> Response {
+    let session = match state.uploads.session(&id).await {
+        Ok(Some(s)) => s,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Unknown upload"),
+        Err(e) => return storage_error(e),
+    };
+    if index >= MAX_PARTS {
+        return error(StatusCode::BAD_REQUEST, format!("Part index must be below {}", MAX_PARTS));
+    }
+    let data = match axum::body::to_bytes(request.into_body(), session.size as usize).await {
+        Ok(b) => b,
+        Err(_) => return error(StatusCode::PAYLOAD_TOO_LARGE, "Part larger than the blob"),
+    };
+
+    let others: u64 = match state.uploads.parts(&id).await {
+        Ok(parts) => parts.iter().filter(|(i, _)| *i != index).map(|(_, size)| size).sum(),
+        Err(e) => return storage_error(e),
+    };
+    if others + data.len() as u64 > session.size {
+        return error(StatusCode::PAYLOAD_TOO_LARGE, "Parts exceed the announced size");
+    }
+    match state.uploads.put_part(&id, index, &data).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => storage_error(e),
+    }
+}
+
+#[derive(Serialize)]
+struct PartInfo {
+    index: u32,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct UploadStatus {
+    sha256: String,
+    size: u64,
+    received: u64,
+    parts: Vec<PartInfo>,
+}
+
+/// `GET /upload/chunked/:id`: which parts have arrived, to resume after an
+/// interruption.
+pub async fn status(State(state): State<Arc<BlossomState>>, Path(id): Path<String>) -> Response {
+    let session = match state.uploads.session(&id).await {
+        Ok(Some(s)) => s,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Unknown upload"),
+        Err(e) => return storage_error(e),
+    };
+    let parts = match state.uploads.parts(&id).await {
+        Ok(p) => p,
+        Err(e) => return storage_error(e),
+    };
+    Json(UploadStatus {
+        sha256: session.sha256,
+        size: session.size,
+        received: parts.iter().map(|(_, size)| size).sum(),
+        parts: parts.into_iter().map(|(index, size)| PartInfo { index, size }).collect(),
+    })
+    .into_response()
+}
+
+/// `POST /upload/chunked/:id/complete`: assemble, verify and store the blob.
+pub async fn complete(State(state): State<Arc<BlossomState>>, Path(id): Path<String>) -> Response {
+    let session = match state.uploads.session(&id).await {
+        Ok(Some(s)) => s,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Unknown upload"),
+        Err(e) => return storage_error(e),
+    };
+    let data = match state.uploads.assemble(&id, &session).await {
+        Ok(Ok(data)) => data,
+        Ok(Err(reason)) => return error(StatusCode::BAD_REQUEST, reason),
+        Err(e) => return storage_error(e),
+    };
+
+    let meta = match state.store.get_meta(&session.sha256) {
+        Ok(Some(meta)) => meta,
+        _ => {
+            let mirror_data = state.config.mirror_to_user_servers.then(|| data.clone());
+            let meta = match state
+                .store
+                .save_blob(&session.sha256, data, &session.mime_type, &session.uploader)
+                .await
+            {
+                Ok(meta) => meta,
+                Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to save blob: {}", e)),
+            };
+            let uploader = PublicKey::from_hex(&session.uploader).ok();
+            if let (Some(data), Some(authorization), Some(uploader)) = (mirror_data, session.authorization, uploader) {
+                mirror_upload(&state, &uploader, authorization, session.sha256, data, session.mime_type);
+            }
+            meta
+        }
+    };
+    if let Err(e) = state.uploads.remove(&id).await {
+        tracing::warn!("Failed to remove finished upload {}: {}", id, e);
+    }
+    Json(BlobDescriptor::from_meta(&meta, &state.base_url)).into_response()
+}
+
+/// `DELETE /upload/chunked/:id`: abandon an upload.
+pub async fn abort(State(state): State<Arc<BlossomState>>, Path(id): Path<String>) -> Response {
+    match state.uploads.session(&id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Unknown upload"),
+        Err(e) => return storage_error(e),
+    }
+    match state.uploads.remove(&id).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => storage_error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(data: &[u8]) -> UploadSession {
+        UploadSession {
+            sha256: hex::encode(Sha256::digest(data)),
+            size: data.len() as u64,
+            mime_type: "video/mp4".into(),
+            uploader: "alice".into(),
+            created_at: now(),
+            authorization: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn parts_assemble_in_order_and_can_be_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let uploads = ChunkedUploads::new(dir.path().join("uploads"));
+        let blob = b"hello chunked world";
+        let session = session(blob);
+        let id = uploads.create(&session).await.unwrap();
+
+        uploads.put_part(&id, 1, b"chunked").await.unwrap();
+        assert_eq!(uploads.assemble(&id, &session).await.unwrap().unwrap_err(), "part 0 is missing");
+
+        uploads.put_part(&id, 0, b"garbage").await.unwrap();
+        uploads.put_part(&id, 2, b" world").await.unwrap();
+        assert!(uploads.assemble(&id, &session).await.unwrap().is_err());
+
+        // Retrying a part replaces it
+        uploads.put_part(&id, 0, b"hello ").await.unwrap();
+        assert_eq!(uploads.parts(&id).await.unwrap(), vec![(0, 6), (1, 7), (2, 6)]);
+        let data = uploads.assemble(&id, &session).await.unwrap().unwrap();
+        assert_eq!(&data[..], blob);
+
+        uploads.remove(&id).await.unwrap();
+        assert!(uploads.session(&id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn upload_flow_through_the_router() {
+        use crate::auth::ReplayGuard;
+        use crate::blossom::handlers::create_blossom_router;
+        use crate::blossom::store::BlobStore;
+        use crate::config::BlossomConfig;
+        use base64::Engine;
+        use nostr::{EventBuilder, JsonUtil, Keys, Kind, Tag, TagKind, Timestamp};
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let config: BlossomConfig = toml::from_str(&format!(
+            "name = \"b\"\nsubdomain = \"b\"\nstorage_path = \"{}\"",
+            dir.path().display()
+        ))
+        .unwrap();
+        let router = create_blossom_router(BlossomState {
+            store: Arc::new(BlobStore::new(dir.path()).unwrap()),
+            server_id: "b".into(),
+            base_url: "http://b.localhost".into(),
+            auth_replay: Arc::new(ReplayGuard::new()),
+            uploads: Arc::new(ChunkedUploads::new(dir.path().join("uploads"))),
+            relays: Vec::new(),
+            auth_grants: Arc::new(crate::auth::AuthGrants::new()),
+            auth_relay: None,
+            notifier: Arc::new(crate::webhook::Notifier::default()),
+            config,
+        });
+        let call = |method: &str, uri: &str, headers: Vec<(&str, String)>, body: Vec<u8>| {
+            let mut request = axum::http::Request::builder().method(method).uri(uri);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            router.clone().oneshot(request.body(Body::from(body)).unwrap())
+        };
+
+        let blob = b"a large video, in two parts".to_vec();
+        let sha256 = hex::encode(Sha256::digest(&blob));
+        let tag = |name: &str, value: String| Tag::custom(TagKind::Custom(name.to_string().into()), [value]);
+        let auth = EventBuilder::new(
+            Kind::from(24242u16),
+            "Upload",
+            [
+                tag("t", "upload".into()),
+                tag("x", sha256.clone()),
+                tag("expiration", (Timestamp::now().as_u64() + 60).to_string()),
+            ],
+        )
+        .to_event(&Keys::generate())
+        .unwrap();
+        let auth = format!("Nostr {}", base64::engine::general_purpose::STANDARD.encode(auth.as_json()));
+
+        let body = serde_json::json!({"sha256": sha256, "size": blob.len(), "type": "video/mp4"});
+        let response = call("POST", "/upload/chunked", vec![("authorization", auth)], body.to_string().into_bytes())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let created: serde_json::Value =
+            serde_json::from_slice(&axum::body::to_bytes(response.into_body(), 1024).await.unwrap()).unwrap();
+        let id = created["id"].as_str().unwrap().to_string();
+        // The signed authorization is kept to mirror the finished blob with
+        let session = ChunkedUploads::new(dir.path().join("uploads")).session(&id).await.unwrap().unwrap();
+        assert!(session.authorization.is_some_and(|a| a.starts_with("Nostr ")));
+
+        let (first, second) = blob.split_at(10);
+        for (index, part) in [(1, second), (0, first)] {
+            let uri = format!("/upload/chunked/{}/{}", id, index);
+            let response = call("PUT", &uri, vec![], part.to_vec()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        }
+        let response = call("GET", &format!("/upload/chunked/{}", id), vec![], vec![]).await.unwrap();
+        let status: serde_json::Value =
+            serde_json::from_slice(&axum::body::to_bytes(response.into_body(), 1024).await.unwrap()).unwrap();
+        assert_eq!(status["received"], blob.len());
+
+        let uri = format!("/upload/chunked/{}/complete", id);
+        let response = call("POST", &uri, vec![], vec![]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call("GET", &format!("/{}", sha256), vec![], vec![]).await.unwrap();
+        let stored = axum::body::to_bytes(response.into_body(), 1024).await.unwrap();
+        assert_eq!(&stored[..], &blob[..]);
+    }
+
+    #[tokio::test]
+    async fn ids_cannot_escape_the_upload_area() {
+        let dir = tempfile::tempdir().unwrap();
+        let uploads = ChunkedUploads::new(dir.path().join("uploads"));
+        assert!(uploads.session("../db").await.unwrap().is_none());
+        assert!(uploads.put_part("../db", 0, b"x").await.is_err());
+    }
+}
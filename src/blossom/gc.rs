@@ -0,0 +1,175 @@
+//! Garbage collection of orphaned blobs: a blob is orphaned when its sha256
+//! appears in no event (content or tag values) on any relay of the instance
+//! and it was uploaded longer ago than the grace period.
+
+use crate::blossom::store::{BlobMeta, BlobStore};
+use crate::config::BlobGcConfig;
+use crate::storage::NostrStore;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GcReport {
+    /// Orphaned blobs found (and deleted, unless this was a dry run).
+    pub sha256s: Vec<String>,
+    pub blobs: usize,
+    pub bytes: u64,
+    pub dry_run: bool,
+}
+
+/// Blobs older than `grace_secs` that no event on `relays` refers to.
+pub fn find_orphans(
+    store: &BlobStore,
+    relays: &[Arc<dyn NostrStore>],
+    grace_secs: u64,
+    now: u64,
+) -> crate::error::Result<Vec<BlobMeta>> {
+    let candidates: Vec<BlobMeta> = store
+        .list_all()?
+        .into_iter()
+        .filter(|m| m.uploaded.saturating_add(grace_secs) <= now)
+        .collect();
+    if candidates.is_empty() {
+        return Ok(candidates);
+    }
+
+    let mut referenced = HashSet::new();
+    for relay in relays {
+        relay.for_each_event(&mut |event| {
+            collect_hashes(&event.content, &mut referenced);
+            for tag in event.tags.iter() {
+                for value in tag.as_vec().iter().skip(1) {
+                    collect_hashes(value, &mut referenced);
+                }
+            }
+        })?;
+    }
+
+    Ok(candidates
+        .into_iter()
+        .filter(|m| !referenced.contains(&m.sha256))
+        .collect())
+}
+
+/// Find orphans and, unless `dry_run`, delete them.
+pub async fn collect(
+    store: Arc<BlobStore>,
+    relays: Vec<Arc<dyn NostrStore>>,
+    grace_hours: u64,
+    dry_run: bool,
+) -> crate::error::Result<GcReport> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let scan_store = store.clone();
+    let orphans =
+        tokio::task::spawn_blocking(move || find_orphans(&scan_store, &relays, grace_hours * 3600, now))
+            .await??;
+
+    let mut report = GcReport {
+        dry_run,
+        ..Default::default()
+    };
+    for meta in orphans {
+        if !dry_run && !store.delete_blob(&meta.sha256).await? {
+            continue;
+        }
+        report.blobs += 1;
+        report.bytes += meta.size;
+        report.sha256s.push(meta.sha256);
+    }
+    Ok(report)
+}
+
+/// Run `collect` every `interval_hours` for one blossom server.
+pub fn start(id: String, store: Arc<BlobStore>, relays: Vec<Arc<dyn NostrStore>>, config: BlobGcConfig) {
+    tokio::spawn(async move {
+        let period = Duration::from_secs(config.interval_hours.max(1) * 3600);
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            interval.tick().await;
+            match collect(store.clone(), relays.clone(), config.grace_hours, false).await {
+                Ok(report) => tracing::info!(
+                    blossom = %id,
+                    blobs = report.blobs,
+                    bytes = report.bytes,
+                    "Collected orphaned blobs"
+                ),
+                Err(e) => tracing::error!("Blob GC for blossom server '{}' failed: {}", id, e),
+            }
+        }
+    });
+}
+
+/// Add every run of exactly 64 hex characters in `text` to `out`, lowercased;
+/// this catches `x` tags, `imeta` tags and blob URLs in content alike.
+fn collect_hashes(text: &str, out: &mut HashSet<String>) {
+    let bytes = text.as_bytes();
+    let mut start = 0;
+    while start < bytes.len() {
+        if !bytes[start].is_ascii_hexdigit() {
+            start += 1;
+            continue;
+        }
+        let mut end = start;
+        while end < bytes.len() && bytes[end].is_ascii_hexdigit() {
+            end += 1;
+        }
+        if end - start == 64 {
+            out.insert(text[start..end].to_ascii_lowercase());
+        }
+        start = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::lmdb::LmdbStore;
+    use axum::body::Bytes;
+    use nostr::{EventBuilder, Keys, Tag};
+
+    #[test]
+    fn finds_hashes_in_urls_and_tags() {
+        let mut found = HashSet::new();
+        let sha = "ab".repeat(32);
+        collect_hashes(&format!("look https://blossom.example.com/{}.png", sha.to_uppercase()), &mut found);
+        collect_hashes(&"c".repeat(65), &mut found);
+        assert_eq!(found, HashSet::from([sha]));
+    }
+
+    #[tokio::test]
+    async fn collects_only_unreferenced_blobs_past_grace() {
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = Arc::new(BlobStore::new(dir.path().join("blobs")).unwrap());
+        let used = "a".repeat(64);
+        let orphan = "b".repeat(64);
+        for sha in [&used, &orphan] {
+            blobs.save_blob(sha, Bytes::from_static(b"data"), "text/plain", "pk").await.unwrap();
+        }
+
+        let relay = LmdbStore::new(dir.path().join("relay")).unwrap();
+        let event = EventBuilder::text_note("photo", [Tag::parse(&["x", &used]).unwrap()])
+            .to_event(&Keys::generate())
+            .unwrap();
+        relay.save_event(&event).unwrap();
+        let relays: Vec<Arc<dyn NostrStore>> = vec![Arc::new(relay)];
+
+        // Everything was just uploaded, so the grace period protects it
+        let report = collect(blobs.clone(), relays.clone(), 1, true).await.unwrap();
+        assert_eq!(report.blobs, 0);
+
+        let report = collect(blobs.clone(), relays.clone(), 0, true).await.unwrap();
+        assert_eq!(report.sha256s, vec![orphan.clone()]);
+        assert_eq!(report.bytes, 4);
+        assert!(blobs.has_blob(&orphan).unwrap());
+
+        let report = collect(blobs.clone(), relays, 0, false).await.unwrap();
+        assert_eq!(report.blobs, 1);
+        assert!(!blobs.has_blob(&orphan).unwrap());
+        assert!(blobs.has_blob(&used).unwrap());
+    }
+}
@@ -1,19 +1,25 @@
-use crate::blossom::auth::{get_x_tag, verify_blossom_auth};
+use crate::auth::{AuthGrants, ReplayGuard};
+use crate::blossom::chunked::{self, ChunkedUploads};
+use crate::blossom::auth::{decode_auth_header, expiration, has_any_x_tag, has_x_tag, size_tag, verify_blossom_auth};
 use crate::blossom::store::{BlobMeta, BlobStore};
+use crate::blossom::server_list::{self, user_servers};
 use crate::config::BlossomConfig;
+use crate::policy::{PolicyEngine, PolicyResult};
+use crate::storage::NostrStore;
+use crate::webhook::{Alert, AlertKind, Notifier};
 use axum::{
-    body::Body,
+    body::{Body, Bytes},
     extract::{Path, Request, State},
     http::{header, Method, StatusCode},
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{get, post, put},
     Json, Router,
 };
+use arc_swap::ArcSwap;
+use nostr::{Event, Kind, PublicKey};
 use serde::Serialize;
 use sha2::{Digest, Sha256};
 use std::sync::Arc;
-use tokio::fs::File;
-use tokio_util::io::ReaderStream;
 use tower_http::cors::{Any, CorsLayer};
 
 #[derive(Clone)]
@@ -22,6 +28,141 @@ pub struct BlossomState {
     pub store: Arc<BlobStore>,
     pub server_id: String,
     pub base_url: String,
+    /// Auth events already used, shared with the rest of the gateway.
+    pub auth_replay: Arc<ReplayGuard>,
+    /// Unfinished chunked uploads.
+    pub uploads: Arc<ChunkedUploads>,
+    /// The instance's relay stores, where user server lists are looked up.
+    pub relays: Vec<Arc<dyn NostrStore>>,
+    /// NIP-42 AUTH events accepted by the instance's relays.
+    pub auth_grants: Arc<AuthGrants>,
+    /// The `auth_relay` and its policy: users authenticated on it may upload
+    /// without signing, and its write identity checks apply to every upload.
+    pub auth_relay: Option<(String, Arc<ArcSwap<PolicyEngine>>)>,
+    /// Told about uploads refused for exceeding `max_file_size`.
+    pub notifier: Arc<Notifier>,
+}
+
+/// Report an upload refused for exceeding the size limit; `size` is unknown
+/// when the body was cut off while reading.
+pub(crate) fn report_too_large(state: &BlossomState, uploader: &PublicKey, size: Option<u64>, max_size: u64) {
+    state.notifier.notify(Alert::new(
+        AlertKind::BlossomQuotaExceeded,
+        format!(
+            "Blossom server '{}' refused an upload from {} over its {} byte limit",
+            state.server_id,
+            uploader.to_hex(),
+            max_size
+        ),
+        serde_json::json!({
+            "blossom_id": state.server_id,
+            "pubkey": uploader.to_hex(),
+            "size": size,
+            "max_file_size": max_size,
+        }),
+    ));
+}
+
+/// [`verify_blossom_auth`], then record the event so it can't be replayed.
+pub(crate) fn verify_auth_once(state: &BlossomState, headers: &axum::http::HeaderMap, action: &str) -> Result<nostr::Event, String> {
+    let event = verify_blossom_auth(headers, action)?;
+    // Remember it until it expires
+    let lifetime = expiration(&event)
+        .unwrap_or_default()
+        .saturating_sub(event.created_at.as_u64());
+    if !state.auth_replay.mark_used(&event, lifetime) {
+        return Err("Auth event already used".to_string());
+    }
+    Ok(event)
+}
+
+/// Who an upload is authorized for. `event` is their kind 24242
+/// authorization, absent when they reused a NIP-42 AUTH from the paired
+/// relay.
+pub(crate) struct Uploader {
+    pub pubkey: PublicKey,
+    pub event: Option<Event>,
+}
+
+/// Authorize an upload, by a signed authorization or by the AUTH of an open
+/// connection to the `auth_relay`, then check the uploader is allowed. A
+/// `preflight` check doesn't use up the authorization.
+pub(crate) fn authorize_upload(
+    state: &BlossomState,
+    headers: &axum::http::HeaderMap,
+    preflight: bool,
+) -> Result<Uploader, (StatusCode, String)> {
+    let unauthorized = |e: String| (StatusCode::UNAUTHORIZED, e);
+    let uploader = match relay_grant(state, headers) {
+        Some(pubkey) => Uploader {
+            pubkey: pubkey.map_err(unauthorized)?,
+            event: None,
+        },
+        None => {
+            let event = if preflight {
+                let event = verify_blossom_auth(headers, "upload").map_err(unauthorized)?;
+                if state.auth_replay.is_used(&event) {
+                    return Err(unauthorized("Auth event already used".to_string()));
+                }
+                event
+            } else {
+                verify_auth_once(state, headers, "upload").map_err(unauthorized)?
+            };
+            Uploader {
+                pubkey: event.pubkey,
+                event: Some(event),
+            }
+        }
+    };
+
+    if !is_upload_allowed(&state.config, &uploader.pubkey.to_hex()) {
+        return Err((StatusCode::FORBIDDEN, "Upload not allowed for this pubkey".to_string()));
+    }
+    if let Some((_, policy)) = &state.auth_relay {
+        if let PolicyResult::Deny(reason) = policy.load().can_write_as(&uploader.pubkey) {
+            return Err((StatusCode::FORBIDDEN, reason));
+        }
+    }
+    Ok(uploader)
+}
+
+/// Upload a newly stored blob in the background to the other servers on the
+/// uploader's list, reusing their upload authorization.
+pub(crate) fn mirror_upload(
+    state: &BlossomState,
+    uploader: &PublicKey,
+    authorization: String,
+    sha256: String,
+    data: Bytes,
+    content_type: String,
+) {
+    match user_servers(&state.relays, uploader) {
+        Ok(servers) => {
+            let others = servers.into_iter().filter(|s| *s != state.base_url).collect();
+            server_list::mirror(others, authorization, sha256, data, content_type);
+        }
+        Err(e) => tracing::warn!("Failed to look up server list for {}: {}", uploader.to_hex(), e),
+    }
+}
+
+/// If the Authorization header holds a NIP-42 AUTH event rather than a
+/// Blossom authorization: the pubkey it authenticated on the `auth_relay`,
+/// as long as that connection is still open.
+fn relay_grant(state: &BlossomState, headers: &axum::http::HeaderMap) -> Option<Result<PublicKey, String>> {
+    let (relay_id, _) = state.auth_relay.as_ref()?;
+    let event = decode_auth_header(headers).ok()?;
+    if event.kind != Kind::Authentication {
+        return None;
+    }
+    if event.verify().is_err() {
+        return Some(Err("Invalid event signature".to_string()));
+    }
+    Some(
+        state
+            .auth_grants
+            .pubkey(&event, relay_id)
+            .ok_or_else(|| format!("Not authenticated on relay '{}'", relay_id)),
+    )
 }
 
 #[derive(Serialize)]
@@ -82,6 +223,7 @@ pub fn create_blossom_router(state: BlossomState) -> Router {
             Method::GET,
             Method::HEAD,
             Method::PUT,
+            Method::POST,
             Method::DELETE,
             Method::OPTIONS,
         ])
@@ -93,7 +235,12 @@ pub fn create_blossom_router(state: BlossomState) -> Router {
 
     Router::new()
         .route("/upload", get(head_upload).put(put_upload))
+        .route("/upload/chunked", post(chunked::initiate))
+        .route("/upload/chunked/:id", get(chunked::status).delete(chunked::abort))
+        .route("/upload/chunked/:id/complete", post(chunked::complete))
+        .route("/upload/chunked/:id/:index", put(chunked::put_part))
         .route("/list/:pubkey", get(list_blobs))
+        .route("/servers/:pubkey", get(list_user_servers))
         .route("/:sha256", get(get_blob).head(head_blob).delete(delete_blob))
         .layer(cors)
         .with_state(Arc::new(state))
@@ -114,15 +261,14 @@ async fn get_blob(
         }
     };
 
-    let blob_path = state.store.get_blob_path(sha256);
-    let file = match File::open(&blob_path).await {
-        Ok(f) => f,
-        Err(_) => return (StatusCode::NOT_FOUND, "Blob file not found").into_response(),
+    let body = match state.store.open_blob(sha256).await {
+        Ok(Some(body)) => body,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Blob file not found").into_response(),
+        Err(_) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Storage error").into_response()
+        }
     };
 
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
-
     Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, &meta.mime_type)
@@ -159,15 +305,10 @@ async fn head_upload(
     State(state): State<Arc<BlossomState>>,
     request: Request<Body>,
 ) -> Response {
-    match verify_blossom_auth(request.headers(), "upload") {
-        Ok(event) => {
-            let pubkey = event.author().to_hex();
-            if !is_upload_allowed(&state.config, &pubkey) {
-                return StatusCode::FORBIDDEN.into_response();
-            }
-            StatusCode::OK.into_response()
-        }
-        Err(_) => StatusCode::UNAUTHORIZED.into_response(),
+    // A preflight check: the same authorization is then used for the PUT
+    match authorize_upload(&state, request.headers(), true) {
+        Ok(_) => StatusCode::OK.into_response(),
+        Err((status, _)) => status.into_response(),
     }
 }
 
@@ -175,15 +316,17 @@ async fn put_upload(
     State(state): State<Arc<BlossomState>>,
     request: Request<Body>,
 ) -> Response {
-    let event = match verify_blossom_auth(request.headers(), "upload") {
-        Ok(e) => e,
-        Err(e) => return (StatusCode::UNAUTHORIZED, e).into_response(),
+    let Uploader { pubkey, event } = match authorize_upload(&state, request.headers(), false) {
+        Ok(uploader) => uploader,
+        Err(e) => return e.into_response(),
     };
-
-    let pubkey = event.author().to_hex();
-    if !is_upload_allowed(&state.config, &pubkey) {
-        return (StatusCode::FORBIDDEN, "Upload not allowed for this pubkey").into_response();
-    }
+    let pubkey_hex = pubkey.to_hex();
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
 
     // Get content type from request
     let content_type = request
@@ -195,12 +338,36 @@ async fn put_upload(
 
     let max_size = state.config.policy.max_file_size.unwrap_or(100 * 1024 * 1024);
 
+    // A declared size lets us refuse a mismatched upload before reading it
+    let declared_size = match event.as_ref().map(size_tag).transpose() {
+        Ok(size) => size.flatten(),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if let (Some(declared), Some(length)) = (declared_size, content_length) {
+        if declared != length {
+            return (StatusCode::BAD_REQUEST, "Auth event 'size' tag does not match Content-Length").into_response();
+        }
+    }
+    if declared_size.is_some_and(|size| size > max_size) {
+        report_too_large(&state, &pubkey, declared_size, max_size);
+        return (StatusCode::PAYLOAD_TOO_LARGE, "File too large").into_response();
+    }
+
     let body_bytes = match axum::body::to_bytes(request.into_body(), max_size as usize).await {
         Ok(b) => b,
         Err(_) => {
+            report_too_large(&state, &pubkey, content_length, max_size);
             return (StatusCode::PAYLOAD_TOO_LARGE, "File too large").into_response();
         }
     };
+    if declared_size.is_some_and(|size| size != body_bytes.len() as u64) {
+        return (StatusCode::BAD_REQUEST, "Auth event 'size' tag does not match the upload").into_response();
+    }
 
     // Compute SHA-256
     let mut hasher = Sha256::new();
@@ -208,6 +375,16 @@ async fn put_upload(
     let hash = hasher.finalize();
     let sha256 = hex::encode(hash);
 
+    // BUD-02: the authorization must name the blob being uploaded
+    if let Some(event) = event.as_ref().filter(|event| !has_x_tag(event, &sha256)) {
+        let reason = if has_any_x_tag(event) {
+            "Auth event 'x' tag does not match the uploaded blob"
+        } else {
+            "Auth event missing 'x' tag"
+        };
+        return (StatusCode::FORBIDDEN, reason).into_response();
+    }
+
     // Check if blob already exists
     match state.store.has_blob(&sha256) {
         Ok(true) => {
@@ -221,15 +398,23 @@ async fn put_upload(
         _ => {}
     }
 
+    // Other servers wouldn't accept a relay AUTH, so only signed uploads are mirrored
+    let mirror_data = (state.config.mirror_to_user_servers && event.is_some()).then(|| body_bytes.clone());
     match state
         .store
-        .save_blob(&sha256, &body_bytes, &content_type, &pubkey)
+        .save_blob(&sha256, body_bytes, &content_type, &pubkey_hex)
+        .await
     {
-        Ok(meta) => (
-            StatusCode::OK,
-            Json(BlobDescriptor::from_meta(&meta, &state.base_url)),
-        )
-            .into_response(),
+        Ok(meta) => {
+            if let Some(data) = mirror_data {
+                mirror_upload(&state, &pubkey, authorization, sha256, data, content_type);
+            }
+            (
+                StatusCode::OK,
+                Json(BlobDescriptor::from_meta(&meta, &state.base_url)),
+            )
+                .into_response()
+        }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Failed to save blob: {}", e),
@@ -244,7 +429,7 @@ async fn list_blobs(
     request: Request<Body>,
 ) -> Response {
     if state.config.policy.list.require_auth {
-        match verify_blossom_auth(request.headers(), "list") {
+        match verify_auth_once(&state, request.headers(), "list") {
             Ok(event) => {
                 let auth_pubkey = event.author().to_hex();
                 if let Some(allowed) = &state.config.policy.list.allowed_pubkeys {
@@ -269,26 +454,39 @@ async fn list_blobs(
     }
 }
 
+/// BUD-03: the blossom servers a user prefers, from their newest kind
+/// 10063 list on this instance's relays.
+async fn list_user_servers(
+    State(state): State<Arc<BlossomState>>,
+    Path(pubkey): Path<String>,
+) -> Response {
+    let pubkey = match nostr::PublicKey::parse(&pubkey) {
+        Ok(pk) => pk,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid pubkey").into_response(),
+    };
+    match user_servers(&state.relays, &pubkey) {
+        Ok(servers) => Json(servers).into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Storage error").into_response(),
+    }
+}
+
 async fn delete_blob(
     State(state): State<Arc<BlossomState>>,
     Path(sha256): Path<String>,
     request: Request<Body>,
 ) -> Response {
-    let event = match verify_blossom_auth(request.headers(), "delete") {
+    let event = match verify_auth_once(&state, request.headers(), "delete") {
         Ok(e) => e,
         Err(e) => return (StatusCode::UNAUTHORIZED, e).into_response(),
     };
 
     // Verify x tag matches the sha256
-    match get_x_tag(&event) {
-        Some(x) if x == sha256 => {}
-        _ => {
-            return (
-                StatusCode::FORBIDDEN,
-                "Auth event 'x' tag must match the blob sha256",
-            )
-                .into_response()
-        }
+    if !has_x_tag(&event, &sha256) {
+        return (
+            StatusCode::FORBIDDEN,
+            "Auth event 'x' tag must match the blob sha256",
+        )
+            .into_response();
     }
 
     let pubkey = event.author().to_hex();
@@ -306,14 +504,14 @@ async fn delete_blob(
         }
     }
 
-    match state.store.delete_blob(&sha256) {
+    match state.store.delete_blob(&sha256).await {
         Ok(true) => StatusCode::NO_CONTENT.into_response(),
         Ok(false) => (StatusCode::NOT_FOUND, "Blob not found").into_response(),
         Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Delete failed").into_response(),
     }
 }
 
-fn is_upload_allowed(config: &BlossomConfig, pubkey: &str) -> bool {
+pub(crate) fn is_upload_allowed(config: &BlossomConfig, pubkey: &str) -> bool {
     match &config.policy.upload.allowed_pubkeys {
         Some(allowed) => allowed.contains(&pubkey.to_string()),
         None => true,
@@ -321,7 +519,7 @@ fn is_upload_allowed(config: &BlossomConfig, pubkey: &str) -> bool {
 }
 
 /// Hex encode bytes — using a simple implementation to avoid adding another dep.
-mod hex {
+pub(crate) mod hex {
     pub fn encode(bytes: impl AsRef<[u8]>) -> String {
         bytes
             .as_ref()
@@ -1,3 +1,8 @@
 pub mod auth;
+pub mod backend;
+pub mod chunked;
+pub mod gc;
 pub mod handlers;
+pub mod pool;
+pub mod server_list;
 pub mod store;
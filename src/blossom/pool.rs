@@ -0,0 +1,199 @@
+//! Shared blob pools: several blossom servers keep blob contents in one
+//! place, so a file uploaded to each of them is stored once. Every server
+//! keeps its own metadata and ownership; the pool only counts which servers
+//! reference each blob and deletes the contents when the last one lets go.
+
+use crate::blossom::backend::{BlobBackend, LocalBackend, S3Backend};
+use crate::config::BlobPoolConfig;
+use crate::error::Result;
+use crate::s3::S3Client;
+use crate::storage::BoxFuture;
+use axum::body::{Body, Bytes};
+use heed::byteorder::BigEndian;
+use heed::types::*;
+use heed::{Database, Env, EnvOpenOptions};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+pub struct BlobPool {
+    env: Env,
+    /// "sha256:server" → blob size
+    refs_db: Database<Str, U64<BigEndian>>,
+    backend: Arc<dyn BlobBackend>,
+    /// Held across a reference check and the backend write or delete that
+    /// follows it, so one server can't delete contents another has just
+    /// referenced.
+    refs_lock: tokio::sync::Mutex<()>,
+}
+
+/// How much a pool saves by storing shared blobs once.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PoolUsage {
+    /// Distinct blobs stored.
+    pub blobs: u64,
+    pub stored_bytes: u64,
+    /// Blobs summed over every server that holds them.
+    pub references: u64,
+    pub referenced_bytes: u64,
+}
+
+impl BlobPool {
+    /// Open the pool's reference counts under `<path>/db`. Contents go to
+    /// the pool's S3 bucket if it has one, `<path>/blobs` otherwise.
+    pub fn open(config: &BlobPoolConfig) -> Result<Self> {
+        let backend: Arc<dyn BlobBackend> = match &config.s3 {
+            Some(s3) => Arc::new(S3Backend::new(S3Client::new(s3.clone()))),
+            None => {
+                let blobs_dir = Path::new(&config.path).join("blobs");
+                fs::create_dir_all(&blobs_dir)?;
+                Arc::new(LocalBackend::new(blobs_dir))
+            }
+        };
+        Self::with_backend(&config.path, backend)
+    }
+
+    pub fn with_backend<P: AsRef<Path>>(path: P, backend: Arc<dyn BlobBackend>) -> Result<Self> {
+        let db_dir = path.as_ref().join("db");
+        fs::create_dir_all(&db_dir)?;
+        let mut env_builder = EnvOpenOptions::new();
+        env_builder.max_dbs(2);
+        env_builder.map_size(256 * 1024 * 1024);
+        let env = unsafe { env_builder.open(&db_dir)? };
+        let mut wtxn = env.write_txn()?;
+        let refs_db = env.create_database(&mut wtxn, Some("refs"))?;
+        wtxn.commit()?;
+        Ok(Self {
+            env,
+            refs_db,
+            backend,
+            refs_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// The backend a blossom server uses to keep its blobs in this pool.
+    pub fn backend_for(self: &Arc<Self>, server_id: &str) -> Arc<dyn BlobBackend> {
+        Arc::new(PooledBackend {
+            pool: self.clone(),
+            server_id: server_id.to_string(),
+        })
+    }
+
+    /// Whether any server references `sha256`.
+    fn is_referenced(&self, sha256: &str) -> Result<bool> {
+        let rtxn = self.env.read_txn()?;
+        let mut iter = self.refs_db.prefix_iter(&rtxn, &format!("{}:", sha256))?;
+        Ok(iter.next().transpose()?.is_some())
+    }
+
+    fn add_ref(&self, sha256: &str, server_id: &str, size: u64) -> Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+        self.refs_db.put(&mut wtxn, &format!("{}:{}", sha256, server_id), &size)?;
+        wtxn.commit()?;
+        Ok(())
+    }
+
+    /// Drop a server's reference. Returns `true` if no references remain.
+    fn remove_ref(&self, sha256: &str, server_id: &str) -> Result<bool> {
+        let mut wtxn = self.env.write_txn()?;
+        self.refs_db.delete(&mut wtxn, &format!("{}:{}", sha256, server_id))?;
+        let remaining = self.refs_db.prefix_iter(&wtxn, &format!("{}:", sha256))?.next().is_some();
+        wtxn.commit()?;
+        Ok(!remaining)
+    }
+
+    pub fn usage(&self) -> Result<PoolUsage> {
+        let rtxn = self.env.read_txn()?;
+        let mut usage = PoolUsage::default();
+        let mut last_sha = String::new();
+        for entry in self.refs_db.iter(&rtxn)? {
+            let (key, size) = entry?;
+            let sha = key.split(':').next().unwrap_or(key);
+            usage.references += 1;
+            usage.referenced_bytes += size;
+            if sha != last_sha {
+                usage.blobs += 1;
+                usage.stored_bytes += size;
+                last_sha = sha.to_string();
+            }
+        }
+        Ok(usage)
+    }
+}
+
+/// One blossom server's view of a [`BlobPool`].
+struct PooledBackend {
+    pool: Arc<BlobPool>,
+    server_id: String,
+}
+
+impl BlobBackend for PooledBackend {
+    fn put<'a>(&'a self, sha256: &'a str, data: Bytes) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let _guard = self.pool.refs_lock.lock().await;
+            // Contents are addressed by hash, so a referenced blob is already there
+            if !self.pool.is_referenced(sha256)? {
+                self.pool.backend.put(sha256, data.clone()).await?;
+            }
+            self.pool.add_ref(sha256, &self.server_id, data.len() as u64)
+        })
+    }
+
+    fn open<'a>(&'a self, sha256: &'a str) -> BoxFuture<'a, Result<Option<Body>>> {
+        self.pool.backend.open(sha256)
+    }
+
+    fn delete<'a>(&'a self, sha256: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let _guard = self.pool.refs_lock.lock().await;
+            if self.pool.remove_ref(sha256, &self.server_id)? {
+                self.pool.backend.delete(sha256).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blossom::store::BlobStore;
+
+    #[tokio::test]
+    async fn shared_blobs_are_stored_once_and_kept_while_referenced() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = Arc::new(
+            BlobPool::open(&BlobPoolConfig {
+                path: dir.path().join("pool").display().to_string(),
+                s3: None,
+            })
+            .unwrap(),
+        );
+        let a = BlobStore::with_backend(dir.path().join("a"), pool.backend_for("a")).unwrap();
+        let b = BlobStore::with_backend(dir.path().join("b"), pool.backend_for("b")).unwrap();
+        let sha = "d".repeat(64);
+
+        a.save_blob(&sha, Bytes::from_static(b"shared"), "text/plain", "alice").await.unwrap();
+        b.save_blob(&sha, Bytes::from_static(b"shared"), "text/plain", "bob").await.unwrap();
+        a.save_blob(&"e".repeat(64), Bytes::from_static(b"solo"), "text/plain", "alice").await.unwrap();
+        assert_eq!(
+            pool.usage().unwrap(),
+            PoolUsage {
+                blobs: 2,
+                stored_bytes: 10,
+                references: 3,
+                referenced_bytes: 16,
+            }
+        );
+        // Ownership stays per server
+        assert_eq!(b.get_meta(&sha).unwrap().unwrap().uploader, "bob");
+
+        assert!(a.delete_blob(&sha).await.unwrap());
+        assert!(b.open_blob(&sha).await.unwrap().is_some());
+
+        assert!(b.delete_blob(&sha).await.unwrap());
+        assert!(b.open_blob(&sha).await.unwrap().is_none());
+        assert_eq!(pool.usage().unwrap().blobs, 1);
+    }
+}
@@ -0,0 +1,103 @@
+//! BUD-03 user server lists (kind 10063): looking up a user's preferred
+//! blossom servers on the instance's relays, and mirroring uploads to the
+//! other servers on that list.
+
+use crate::error::Result;
+use crate::storage::NostrStore;
+use axum::body::Bytes;
+use nostr::{Filter, Kind, PublicKey};
+use std::sync::Arc;
+
+pub const SERVER_LIST_KIND: u16 = 10063;
+
+/// The servers on `pubkey`'s newest server list found on any of `relays`,
+/// most preferred first.
+pub fn user_servers(relays: &[Arc<dyn NostrStore>], pubkey: &PublicKey) -> Result<Vec<String>> {
+    let filter = Filter::new()
+        .author(*pubkey)
+        .kind(Kind::from(SERVER_LIST_KIND))
+        .limit(1);
+    let mut lists = Vec::new();
+    for relay in relays {
+        lists.extend(relay.query(&filter)?);
+    }
+    let newest = lists.into_iter().max_by_key(|e| e.created_at);
+
+    let mut servers: Vec<String> = Vec::new();
+    for tag in newest.iter().flat_map(|e| e.tags.iter()) {
+        let v = tag.as_vec();
+        if v.len() >= 2 && v[0] == "server" {
+            let url = v[1].trim_end_matches('/').to_string();
+            if !servers.contains(&url) {
+                servers.push(url);
+            }
+        }
+    }
+    Ok(servers)
+}
+
+/// Upload a blob to each of `servers` in the background, reusing the
+/// uploader's own authorization. Its `x` tag names the blob, so other
+/// servers accept it just as this one did.
+pub fn mirror(servers: Vec<String>, authorization: String, sha256: String, data: Bytes, content_type: String) {
+    if servers.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        for server in servers {
+            let result = client
+                .put(format!("{}/upload", server))
+                .header("Authorization", &authorization)
+                .header("Content-Type", &content_type)
+                .body(data.clone())
+                .send()
+                .await;
+            match result {
+                Ok(response) if response.status().is_success() => {
+                    tracing::info!(sha256 = %sha256, "Mirrored blob to {}", server);
+                }
+                Ok(response) => {
+                    tracing::warn!(sha256 = %sha256, "Mirroring blob to {} failed: HTTP {}", server, response.status());
+                }
+                Err(e) => {
+                    tracing::warn!(sha256 = %sha256, "Mirroring blob to {} failed: {}", server, e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::lmdb::LmdbStore;
+    use nostr::{EventBuilder, Keys, Tag, TagKind, Timestamp};
+
+    #[test]
+    fn newest_list_wins_across_relays() {
+        let dir = tempfile::tempdir().unwrap();
+        let a: Arc<dyn NostrStore> = Arc::new(LmdbStore::new(dir.path().join("a")).unwrap());
+        let b: Arc<dyn NostrStore> = Arc::new(LmdbStore::new(dir.path().join("b")).unwrap());
+        let keys = Keys::generate();
+        let list = |servers: &[&str], at: u64| {
+            let tags = servers
+                .iter()
+                .map(|s| Tag::custom(TagKind::Custom("server".into()), [s.to_string()]));
+            EventBuilder::new(Kind::from(SERVER_LIST_KIND), "", tags)
+                .custom_created_at(Timestamp::from(at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        a.save_event(&list(&["https://old.example.com"], 1000)).unwrap();
+        b.save_event(&list(&["https://cdn.example.com/", "https://backup.example.com", "https://cdn.example.com"], 2000))
+            .unwrap();
+
+        let relays = [a, b];
+        assert_eq!(
+            user_servers(&relays, &keys.public_key()).unwrap(),
+            ["https://cdn.example.com", "https://backup.example.com"]
+        );
+        assert!(user_servers(&relays, &Keys::generate().public_key()).unwrap().is_empty());
+    }
+}
@@ -1,8 +1,12 @@
+use crate::blossom::backend::{BlobBackend, LocalBackend, S3Backend};
+use crate::config::BlossomConfig;
+use crate::s3::S3Client;
+use axum::body::Body;
 use heed::types::*;
 use heed::{Database, Env, EnvOpenOptions};
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,6 +18,86 @@ pub struct BlobMeta {
     pub uploader: String,
 }
 
+/// Order of a [`BlobQuery`]'s results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlobSort {
+    #[default]
+    Newest,
+    Oldest,
+    Largest,
+    Smallest,
+}
+
+impl BlobSort {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "newest" => Some(Self::Newest),
+            "oldest" => Some(Self::Oldest),
+            "largest" => Some(Self::Largest),
+            "smallest" => Some(Self::Smallest),
+            _ => None,
+        }
+    }
+
+    /// The sort key of `meta`, ascending in result order, with the sha256
+    /// breaking ties so every blob has a distinct position.
+    fn key(self, meta: &BlobMeta) -> (i128, &str) {
+        let value = match self {
+            Self::Newest => -(meta.uploaded as i128),
+            Self::Oldest => meta.uploaded as i128,
+            Self::Largest => -(meta.size as i128),
+            Self::Smallest => meta.size as i128,
+        };
+        (value, &meta.sha256)
+    }
+}
+
+/// One page of blobs for [`BlobStore::list_page`].
+#[derive(Debug, Clone, Default)]
+pub struct BlobQuery {
+    /// Only blobs uploaded by this pubkey (hex).
+    pub uploader: Option<String>,
+    /// Only blobs of this MIME type, or of any type under a prefix ending in
+    /// `/`, like `image/`.
+    pub mime_type: Option<String>,
+    pub sort: BlobSort,
+    /// The `next_cursor` of the previous page, with the same sort.
+    pub cursor: Option<BlobCursor>,
+    pub limit: usize,
+}
+
+/// Position of the last blob of a page: its sort key and sha256.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobCursor {
+    value: i128,
+    sha256: String,
+}
+
+impl BlobCursor {
+    pub fn parse(cursor: &str) -> Option<Self> {
+        let (value, sha256) = cursor.split_once(':')?;
+        Some(Self {
+            value: value.parse().ok()?,
+            sha256: sha256.to_string(),
+        })
+    }
+}
+
+impl std::fmt::Display for BlobCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.value, self.sha256)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlobPage {
+    pub blobs: Vec<BlobMeta>,
+    /// Blobs matching the filters, across all pages.
+    pub total: usize,
+    /// Where the next page starts, if there is one.
+    pub next_cursor: Option<BlobCursor>,
+}
+
 #[derive(Clone)]
 pub struct BlobStore {
     env: Arc<Env>,
@@ -21,16 +105,36 @@ pub struct BlobStore {
     blobs_db: Database<Str, Bytes>,
     /// "pubkey:sha256" → unit, for listing by uploader
     uploaders_db: Database<Str, Unit>,
-    /// Root directory for blob files
-    storage_dir: PathBuf,
+    /// Where blob contents are kept
+    backend: Arc<dyn BlobBackend>,
 }
 
 impl BlobStore {
+    /// Store with blob files under `<storage_path>/blobs`.
     pub fn new<P: AsRef<Path>>(storage_path: P) -> crate::error::Result<Self> {
-        let storage_dir = storage_path.as_ref().to_path_buf();
-        let db_dir = storage_dir.join("db");
+        let blobs_dir = storage_path.as_ref().join("blobs");
+        fs::create_dir_all(&blobs_dir)?;
+        Self::with_backend(storage_path, Arc::new(LocalBackend::new(blobs_dir)))
+    }
+
+    /// Store for a configured blossom server: S3 if it has an `s3` section,
+    /// local files otherwise. Metadata is always under `storage_path`.
+    pub fn from_config(config: &BlossomConfig) -> crate::error::Result<Self> {
+        match &config.s3 {
+            Some(s3) => Self::with_backend(
+                &config.storage_path,
+                Arc::new(S3Backend::new(S3Client::new(s3.clone()))),
+            ),
+            None => Self::new(&config.storage_path),
+        }
+    }
+
+    pub fn with_backend<P: AsRef<Path>>(
+        storage_path: P,
+        backend: Arc<dyn BlobBackend>,
+    ) -> crate::error::Result<Self> {
+        let db_dir = storage_path.as_ref().join("db");
         fs::create_dir_all(&db_dir)?;
-        fs::create_dir_all(storage_dir.join("blobs"))?;
 
         let mut env_builder = EnvOpenOptions::new();
         env_builder.max_dbs(5);
@@ -46,14 +150,13 @@ impl BlobStore {
             env: Arc::new(env),
             blobs_db,
             uploaders_db,
-            storage_dir,
+            backend,
         })
     }
 
-    /// Get the filesystem path for a blob, sharded by first 2 hex chars.
-    pub fn get_blob_path(&self, sha256: &str) -> PathBuf {
-        let prefix = &sha256[..2.min(sha256.len())];
-        self.storage_dir.join("blobs").join(prefix).join(sha256)
+    /// Stream a blob's contents, or `None` if the backend doesn't have it.
+    pub async fn open_blob(&self, sha256: &str) -> crate::error::Result<Option<Body>> {
+        self.backend.open(sha256).await
     }
 
     pub fn has_blob(&self, sha256: &str) -> crate::error::Result<bool> {
@@ -69,23 +172,19 @@ impl BlobStore {
         }
     }
 
-    pub fn save_blob(
+    pub async fn save_blob(
         &self,
         sha256: &str,
-        data: &[u8],
+        data: axum::body::Bytes,
         mime_type: &str,
         uploader: &str,
     ) -> crate::error::Result<BlobMeta> {
-        // Write file to disk
-        let blob_path = self.get_blob_path(sha256);
-        if let Some(parent) = blob_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-        fs::write(&blob_path, data)?;
+        let size = data.len() as u64;
+        self.backend.put(sha256, data).await?;
 
         let meta = BlobMeta {
             sha256: sha256.to_string(),
-            size: data.len() as u64,
+            size,
             mime_type: mime_type.to_string(),
             uploaded: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
@@ -122,7 +221,7 @@ impl BlobStore {
             }
         }
 
-        results.sort_by(|a, b| b.uploaded.cmp(&a.uploaded));
+        results.sort_by_key(|m| std::cmp::Reverse(m.uploaded));
         Ok(results)
     }
 
@@ -137,24 +236,72 @@ impl BlobStore {
             results.push(meta);
         }
 
-        results.sort_by(|a, b| b.uploaded.cmp(&a.uploaded));
+        results.sort_by_key(|m| std::cmp::Reverse(m.uploaded));
         Ok(results)
     }
 
-    pub fn delete_blob(&self, sha256: &str) -> crate::error::Result<bool> {
+    /// A page of blobs matching `query`'s filters, in its order.
+    pub fn list_page(&self, query: &BlobQuery) -> crate::error::Result<BlobPage> {
+        let mut metas = match &query.uploader {
+            Some(pubkey) => self.list_by_pubkey(pubkey)?,
+            None => self.list_all()?,
+        };
+        if let Some(mime_type) = &query.mime_type {
+            metas.retain(|m| match mime_type.strip_suffix('/') {
+                Some(prefix) => m.mime_type.split('/').next() == Some(prefix),
+                None => m.mime_type == *mime_type,
+            });
+        }
+        let total = metas.len();
+
+        metas.sort_by(|a, b| query.sort.key(a).cmp(&query.sort.key(b)));
+        let start = match &query.cursor {
+            Some(after) => metas.partition_point(|m| query.sort.key(m) <= (after.value, after.sha256.as_str())),
+            None => 0,
+        };
+        let limit = query.limit.max(1);
+        let blobs: Vec<BlobMeta> = metas.drain(start..).take(limit).collect();
+        let next_cursor = (start + blobs.len() < total)
+            .then(|| blobs.last())
+            .flatten()
+            .map(|last| {
+                let (value, sha256) = query.sort.key(last);
+                BlobCursor {
+                    value,
+                    sha256: sha256.to_string(),
+                }
+            });
+        Ok(BlobPage {
+            blobs,
+            total,
+            next_cursor,
+        })
+    }
+
+    /// Number of stored blobs and their total size in bytes.
+    pub fn usage(&self) -> crate::error::Result<(u64, u64)> {
         let rtxn = self.env.read_txn()?;
-        let meta = match self.blobs_db.get(&rtxn, sha256)? {
-            Some(raw) => {
-                let m: BlobMeta = serde_json::from_slice(raw)?;
-                m
-            }
+        let mut count = 0;
+        let mut bytes = 0;
+        for result in self.blobs_db.iter(&rtxn)? {
+            let (_, raw) = result?;
+            let meta: BlobMeta = serde_json::from_slice(raw)?;
+            count += 1;
+            bytes += meta.size;
+        }
+        Ok((count, bytes))
+    }
+
+    pub async fn delete_blob(&self, sha256: &str) -> crate::error::Result<bool> {
+        let meta = match self.get_meta(sha256)? {
+            Some(m) => m,
             None => return Ok(false),
         };
-        drop(rtxn);
 
-        // Remove file
-        let blob_path = self.get_blob_path(sha256);
-        let _ = fs::remove_file(&blob_path);
+        // Remove contents
+        if let Err(e) = self.backend.delete(sha256).await {
+            tracing::warn!("Failed to delete blob {} from backend: {}", sha256, e);
+        }
 
         // Remove from DB
         let uploader_key = format!("{}:{}", meta.uploader, sha256);
@@ -165,4 +312,78 @@ impl BlobStore {
 
         Ok(true)
     }
+
+    /// Write a compacted copy of the metadata database to `dest` while the
+    /// store stays online. Blob files themselves aren't included.
+    pub fn snapshot_to<P: AsRef<Path>>(&self, dest: P) -> crate::error::Result<()> {
+        self.env.copy_to_file(dest, heed::CompactionOption::Enabled)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn usage_counts_blobs_and_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BlobStore::new(dir.path()).unwrap();
+        assert_eq!(store.usage().unwrap(), (0, 0));
+
+        store.save_blob(&"a".repeat(64), axum::body::Bytes::from_static(b"hello"), "text/plain", "pk").await.unwrap();
+        store.save_blob(&"b".repeat(64), axum::body::Bytes::from_static(b"world!"), "text/plain", "pk").await.unwrap();
+        assert_eq!(store.usage().unwrap(), (2, 11));
+    }
+
+    #[tokio::test]
+    async fn saved_blobs_can_be_read_back_and_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BlobStore::new(dir.path()).unwrap();
+        let sha = "c".repeat(64);
+        store.save_blob(&sha, axum::body::Bytes::from_static(b"contents"), "text/plain", "pk").await.unwrap();
+
+        let body = store.open_blob(&sha).await.unwrap().unwrap();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        assert_eq!(&bytes[..], b"contents");
+
+        assert!(store.delete_blob(&sha).await.unwrap());
+        assert!(store.open_blob(&sha).await.unwrap().is_none());
+        assert!(!store.has_blob(&sha).unwrap());
+    }
+
+    #[tokio::test]
+    async fn pages_are_sorted_filtered_and_resumable() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = BlobStore::new(dir.path()).unwrap();
+        let blobs = [("a", "image/png", "alice", 3), ("b", "image/jpeg", "bob", 1), ("c", "video/mp4", "alice", 5), ("d", "image/png", "alice", 2)];
+        for (sha, mime, uploader, size) in blobs {
+            let data = axum::body::Bytes::from(vec![0u8; size]);
+            store.save_blob(&sha.repeat(64), data, mime, uploader).await.unwrap();
+        }
+        let shas = |page: &BlobPage| page.blobs.iter().map(|m| m.sha256[..1].to_string()).collect::<Vec<_>>();
+
+        let mut query = BlobQuery {
+            sort: BlobSort::Largest,
+            limit: 2,
+            ..Default::default()
+        };
+        let first = store.list_page(&query).unwrap();
+        assert_eq!((shas(&first), first.total), (vec!["c".to_string(), "a".to_string()], 4));
+        query.cursor = Some(BlobCursor::parse(&first.next_cursor.unwrap().to_string()).unwrap());
+        let second = store.list_page(&query).unwrap();
+        assert_eq!(shas(&second), ["d", "b"]);
+        assert!(second.next_cursor.is_none());
+
+        let images = BlobQuery {
+            uploader: Some("alice".into()),
+            mime_type: Some("image/".into()),
+            sort: BlobSort::Smallest,
+            limit: 10,
+            ..Default::default()
+        };
+        let page = store.list_page(&images).unwrap();
+        assert_eq!((shas(&page), page.total), (vec!["d".to_string(), "a".to_string()], 2));
+        assert!(BlobCursor::parse("not a cursor").is_none());
+    }
 }
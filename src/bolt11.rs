@@ -0,0 +1,160 @@
+//! Just enough of BOLT 11 to read what an invoice commits to: when it was
+//! made and expires, its amount, payment hash and description hash. The checksum and
+//! signature aren't checked; callers trust whoever handed them the invoice
+//! (their own wallet, or a zap provider whose receipt they verified).
+
+const CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Expiry an invoice has when it doesn't state one.
+const DEFAULT_EXPIRY_SECS: u64 = 3600;
+
+/// Tagged field types
+const FIELD_PAYMENT_HASH: u64 = 1; // p
+const FIELD_EXPIRY: u64 = 6; // x
+const FIELD_DESCRIPTION_HASH: u64 = 23; // h
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invoice {
+    /// Unix time the invoice was made.
+    pub timestamp: u64,
+    /// Unix time it expires.
+    pub expires_at: u64,
+    /// Amount in millisatoshis, unless the payer picks it.
+    pub amount_msats: Option<u64>,
+    pub payment_hash: Option<[u8; 32]>,
+    /// SHA-256 of the description, for invoices that commit to one by hash.
+    pub description_hash: Option<[u8; 32]>,
+}
+
+pub fn parse(invoice: &str) -> Option<Invoice> {
+    let invoice = invoice.trim().to_lowercase();
+    let invoice = invoice.strip_prefix("lightning:").unwrap_or(&invoice);
+    let (hrp, data) = invoice.rsplit_once('1')?;
+    let amount_msats = parse_amount(hrp.strip_prefix("ln")?)?;
+    let words: Vec<u64> = data
+        .chars()
+        .map(|c| CHARSET.find(c).map(|w| w as u64))
+        .collect::<Option<_>>()?;
+
+    // Timestamp (7 words), then tagged fields, a 104-word signature and a
+    // 6-word checksum
+    let fields_end = words.len().checked_sub(104 + 6)?;
+    if fields_end < 7 {
+        return None;
+    }
+    let timestamp = read(&words[..7]);
+    let mut expiry = DEFAULT_EXPIRY_SECS;
+    let mut payment_hash = None;
+    let mut description_hash = None;
+    let mut at = 7;
+    while at + 3 <= fields_end {
+        let (field, len) = (words[at], read(&words[at + 1..at + 3]) as usize);
+        let value = words.get(at + 3..at + 3 + len)?;
+        match field {
+            FIELD_PAYMENT_HASH if len == 52 => payment_hash = to_bytes(value).try_into().ok(),
+            FIELD_EXPIRY if len <= 12 => expiry = read(value),
+            FIELD_DESCRIPTION_HASH if len == 52 => description_hash = to_bytes(value).try_into().ok(),
+            _ => {}
+        }
+        at += 3 + len;
+    }
+    Some(Invoice {
+        timestamp,
+        expires_at: timestamp.checked_add(expiry)?,
+        amount_msats,
+        payment_hash,
+        description_hash,
+    })
+}
+
+/// The amount in the human-readable part after `ln`: a currency, then
+/// optionally digits and a multiplier. `None` if it is malformed.
+fn parse_amount(hrp: &str) -> Option<Option<u64>> {
+    let Some(start) = hrp.find(|c: char| c.is_ascii_digit()) else {
+        return Some(None);
+    };
+    let amount = &hrp[start..];
+    let (digits, multiplier) = match amount.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&amount[..i], Some(c)),
+        _ => (amount, None),
+    };
+    let value: u64 = digits.parse().ok()?;
+    let msats = match multiplier {
+        None => value.checked_mul(100_000_000_000),
+        Some('m') => value.checked_mul(100_000_000),
+        Some('u') => value.checked_mul(100_000),
+        Some('n') => value.checked_mul(100),
+        Some('p') if value.is_multiple_of(10) => Some(value / 10),
+        _ => None,
+    }?;
+    Some(Some(msats))
+}
+
+fn read(words: &[u64]) -> u64 {
+    words.iter().fold(0u64, |acc, w| acc << 5 | w)
+}
+
+/// Regroup 5-bit words into bytes, dropping the padding bits at the end.
+fn to_bytes(words: &[u64]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(words.len() * 5 / 8);
+    let (mut acc, mut bits) = (0u64, 0);
+    for w in words {
+        acc = acc << 5 | w;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(words: &[u64]) -> String {
+        words.iter().map(|&w| CHARSET.as_bytes()[w as usize] as char).collect()
+    }
+
+    fn timestamp(at: u64) -> String {
+        encode(&(0..7).rev().map(|i| (at >> (5 * i)) & 31).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn reads_timestamp_amount_expiry_and_description_hash() {
+        let tail = "q".repeat(104 + 6);
+        // A description field (d, 2 words) then an expiry field (x, 60 seconds)
+        let fields = encode(&[13, 0, 2, 1, 2, 6, 0, 2, 1, 28]);
+        let with_expiry = format!("lnbc10u1{}{}{}", timestamp(1_700_000_000), fields, tail);
+        let invoice = parse(&with_expiry).unwrap();
+        assert_eq!(invoice.expires_at, 1_700_000_060);
+        assert_eq!(invoice.amount_msats, Some(1_000_000));
+        assert_eq!(invoice.description_hash, None);
+        assert_eq!(invoice.payment_hash, None);
+
+        let without = format!("LNBC1{}{}", timestamp(1_700_000_000).to_uppercase(), tail.to_uppercase());
+        let invoice = parse(&without).unwrap();
+        assert_eq!((invoice.expires_at, invoice.amount_msats), (1_700_003_600, None));
+
+        // The payment and description hash fields of the BOLT 11 examples
+        let hashed = format!(
+            "lnbc20m1{}pp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqhp58yjmdan79s6qqdhdzgynm4zwqd5d7xmw5fk98klysy043l2ahrqs{}",
+            timestamp(1_496_314_658),
+            tail
+        );
+        let invoice = parse(&hashed).unwrap();
+        assert_eq!(invoice.amount_msats, Some(2_000_000_000));
+        let hash: String = invoice.description_hash.unwrap().iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hash, "3925b6f67e2c340036ed12093dd44e0368df1b6ea26c53dbe4811f58fd5db8c1");
+        let hash: String = invoice.payment_hash.unwrap().iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hash, "0001020304050607080900010203040506070809000102030405060708090102");
+
+        // Picosatoshi amounts must be whole millisatoshis
+        assert_eq!(parse(&format!("lnbc2500p1{}{}", timestamp(1), tail)).unwrap().amount_msats, Some(250));
+        assert_eq!(parse(&format!("lnbc2501p1{}{}", timestamp(1), tail)), None);
+        assert_eq!(parse("lnbc1qqqq"), None);
+        assert_eq!(parse("not an invoice"), None);
+    }
+}
@@ -0,0 +1,664 @@
+//! Just enough Cashu (NUT-00 to NUT-03) for paywalls to take ecash: decode
+//! the tokens users paste at checkout and swap their proofs at the mint for
+//! fresh ones only the relay knows, so the payer can't spend them again.
+
+use crate::config::PaywallCashuConfig;
+use base64::engine::general_purpose::{STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use nostr::secp256k1::{PublicKey, Scalar, SecretKey};
+use nostr::SECP256K1;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const DOMAIN_SEPARATOR: &[u8] = b"Secp256k1_HashToCurve_Cashu_";
+
+/// One ecash note: `C` is the mint's signature on `secret`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Proof {
+    pub amount: u64,
+    /// Keyset the mint signed with.
+    pub id: String,
+    pub secret: String,
+    #[serde(rename = "C")]
+    pub c: String,
+}
+
+/// Proofs from one mint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Token {
+    pub mint: String,
+    pub unit: String,
+    pub proofs: Vec<Proof>,
+}
+
+impl Token {
+    /// Total value of the proofs, or `None` if it doesn't fit in a `u64`.
+    pub fn amount(&self) -> Option<u64> {
+        self.proofs.iter().try_fold(0u64, |sum, p| sum.checked_add(p.amount))
+    }
+}
+
+/// Decode a `cashuA` (JSON) or `cashuB` (CBOR) token. Tokens spanning
+/// several mints aren't supported.
+pub fn decode(token: &str) -> Result<Token, String> {
+    let token = token.trim();
+    let token = token.strip_prefix("cashu:").unwrap_or(token);
+    let decoded = if let Some(data) = token.strip_prefix("cashuA") {
+        decode_v3(&base64_decode(data)?)?
+    } else if let Some(data) = token.strip_prefix("cashuB") {
+        decode_v4(&base64_decode(data)?)?
+    } else {
+        return Err("not a Cashu token".to_string());
+    };
+    if decoded.proofs.is_empty() {
+        return Err("token has no proofs".to_string());
+    }
+    if decoded.amount().is_none() {
+        return Err("token amount overflows".to_string());
+    }
+    Ok(decoded)
+}
+
+fn base64_decode(data: &str) -> Result<Vec<u8>, String> {
+    let data = data.trim_end_matches('=');
+    URL_SAFE_NO_PAD
+        .decode(data)
+        .or_else(|_| STANDARD_NO_PAD.decode(data))
+        .map_err(|_| "malformed token".to_string())
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenV3 {
+    token: Vec<TokenV3Entry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenV3Entry {
+    mint: String,
+    proofs: Vec<Proof>,
+}
+
+fn decode_v3(data: &[u8]) -> Result<Token, String> {
+    let token: TokenV3 = serde_json::from_slice(data).map_err(|_| "malformed token")?;
+    let mut entries = token.token.into_iter();
+    let first = entries.next().ok_or("token has no proofs")?;
+    let mut proofs = first.proofs;
+    for entry in entries {
+        if entry.mint != first.mint {
+            return Err("tokens from several mints aren't supported".to_string());
+        }
+        proofs.extend(entry.proofs);
+    }
+    Ok(Token {
+        mint: first.mint,
+        unit: token.unit.unwrap_or_else(|| "sat".to_string()),
+        proofs,
+    })
+}
+
+fn decode_v4(data: &[u8]) -> Result<Token, String> {
+    let malformed = || "malformed token".to_string();
+    let (value, _) = cbor::read(data).ok_or_else(malformed)?;
+    let mint = value.get("m").and_then(|v| v.text()).ok_or_else(malformed)?;
+    let unit = value.get("u").and_then(|v| v.text()).unwrap_or("sat");
+    let mut proofs = Vec::new();
+    for keyset in value.get("t").and_then(|v| v.array()).ok_or_else(malformed)? {
+        let id = keyset.get("i").and_then(|v| v.bytes()).ok_or_else(malformed)?;
+        for proof in keyset.get("p").and_then(|v| v.array()).ok_or_else(malformed)? {
+            proofs.push(Proof {
+                amount: proof.get("a").and_then(|v| v.uint()).ok_or_else(malformed)?,
+                id: hex(id),
+                secret: proof.get("s").and_then(|v| v.text()).ok_or_else(malformed)?.to_string(),
+                c: hex(proof.get("c").and_then(|v| v.bytes()).ok_or_else(malformed)?),
+            });
+        }
+    }
+    Ok(Token {
+        mint: mint.to_string(),
+        unit: unit.to_string(),
+        proofs,
+    })
+}
+
+/// Encode tokens from any number of mints as one `cashuA` token.
+pub fn encode(tokens: &[Token], unit: &str) -> String {
+    let token = TokenV3 {
+        token: tokens
+            .iter()
+            .map(|t| TokenV3Entry {
+                mint: t.mint.clone(),
+                proofs: t.proofs.clone(),
+            })
+            .collect(),
+        unit: Some(unit.to_string()),
+    };
+    format!("cashuA{}", URL_SAFE.encode(serde_json::to_vec(&token).unwrap_or_default()))
+}
+
+/// Check a paywall's mint list: at least one mint, each an http(s) URL.
+pub fn validate_mints(config: &PaywallCashuConfig) -> Result<(), String> {
+    if config.mints.is_empty() {
+        return Err("cashu requires at least one mint".to_string());
+    }
+    for mint in &config.mints {
+        if !reqwest::Url::parse(mint).is_ok_and(|url| matches!(url.scheme(), "http" | "https")) {
+            return Err(format!("cashu mint URL '{}' is invalid", mint));
+        }
+    }
+    Ok(())
+}
+
+/// Whether two mint URLs name the same mint.
+pub fn same_mint(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The curve point a secret is blinded from (NUT-00).
+fn hash_to_curve(message: &[u8]) -> PublicKey {
+    let msg_hash = Sha256::new()
+        .chain_update(DOMAIN_SEPARATOR)
+        .chain_update(message)
+        .finalize();
+    let mut counter: u32 = 0;
+    loop {
+        let hash = Sha256::new()
+            .chain_update(msg_hash)
+            .chain_update(counter.to_le_bytes())
+            .finalize();
+        let mut point = [2u8; 33];
+        point[1..].copy_from_slice(&hash);
+        if let Ok(pk) = PublicKey::from_slice(&point) {
+            return pk;
+        }
+        counter += 1;
+    }
+}
+
+/// An output to have the mint sign: `secret` blinded by `r`.
+struct BlindedOutput {
+    amount: u64,
+    secret: String,
+    r: SecretKey,
+    blinded: PublicKey,
+}
+
+impl BlindedOutput {
+    fn new(amount: u64) -> Self {
+        let secret = hex(&rand::random::<[u8; 32]>());
+        let r = SecretKey::new(&mut rand::thread_rng());
+        let blinded = hash_to_curve(secret.as_bytes())
+            .combine(&r.public_key(&SECP256K1))
+            .expect("a random blinding factor doesn't cancel the point");
+        Self {
+            amount,
+            secret,
+            r,
+            blinded,
+        }
+    }
+
+    /// The proof for this output, from the mint's blind signature and its
+    /// key for the amount.
+    fn unblind(self, keyset: &str, signature: &PublicKey, key: &PublicKey) -> Result<Proof, String> {
+        let r_k = key
+            .mul_tweak(&SECP256K1, &Scalar::from(self.r))
+            .map_err(|_| "invalid mint key")?;
+        let c = signature
+            .combine(&r_k.negate(&SECP256K1))
+            .map_err(|_| "invalid mint signature")?;
+        Ok(Proof {
+            amount: self.amount,
+            id: keyset.to_string(),
+            secret: self.secret,
+            c: hex(&c.serialize()),
+        })
+    }
+}
+
+/// Powers of two summing to `amount`, the denominations mints sign.
+fn split(amount: u64) -> Vec<u64> {
+    (0..64).map(|bit| 1u64 << bit).filter(|a| amount & a != 0).collect()
+}
+
+#[derive(Deserialize)]
+struct Keysets {
+    keysets: Vec<KeysetInfo>,
+}
+
+#[derive(Deserialize)]
+struct KeysetInfo {
+    id: String,
+    unit: String,
+    active: bool,
+    #[serde(default)]
+    input_fee_ppk: u64,
+}
+
+#[derive(Deserialize)]
+struct Keys {
+    keysets: Vec<KeysetKeys>,
+}
+
+#[derive(Deserialize)]
+struct KeysetKeys {
+    keys: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct BlindSignature {
+    amount: u64,
+    #[serde(rename = "C_")]
+    c: String,
+}
+
+#[derive(Deserialize)]
+struct SwapResponse {
+    signatures: Vec<BlindSignature>,
+}
+
+/// Swap the token's proofs at its mint for fresh ones (NUT-03), spending
+/// the originals. Returns the new proofs, worth the token's amount less the
+/// mint's input fees. Nothing is spent if that would come to less than
+/// `min_amount`.
+pub async fn redeem(token: &Token, min_amount: u64) -> Result<Token, anyhow::Error> {
+    let mint = token.mint.trim_end_matches('/');
+    let client = reqwest::Client::new();
+
+    let keysets: Keysets = get_json(&client, &format!("{}/v1/keysets", mint)).await?;
+    let fee_ppk: u64 = token
+        .proofs
+        .iter()
+        .map(|p| {
+            keysets
+                .keysets
+                .iter()
+                .find(|k| k.id == p.id)
+                .map_or(0, |k| k.input_fee_ppk)
+        })
+        .sum();
+    let fee = fee_ppk.div_ceil(1000);
+    let amount = token
+        .amount()
+        .ok_or_else(|| anyhow::anyhow!("token amount overflows"))?
+        .checked_sub(fee)
+        .filter(|&a| a > 0)
+        .ok_or_else(|| anyhow::anyhow!("token doesn't cover the mint's fee"))?;
+    if amount < min_amount {
+        anyhow::bail!(
+            "token is worth {} after the mint's fee of {}, {} needed",
+            amount,
+            fee,
+            min_amount
+        );
+    }
+
+    let keyset = keysets
+        .keysets
+        .iter()
+        .find(|k| k.active && k.unit == token.unit)
+        .ok_or_else(|| anyhow::anyhow!("mint has no active {} keyset", token.unit))?;
+    let keys: Keys = get_json(&client, &format!("{}/v1/keys/{}", mint, keyset.id)).await?;
+    let keys = keys
+        .keysets
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("mint returned no keys"))?
+        .keys;
+
+    let outputs: Vec<BlindedOutput> = split(amount).into_iter().map(BlindedOutput::new).collect();
+    let request = serde_json::json!({
+        "inputs": token.proofs,
+        "outputs": outputs
+            .iter()
+            .map(|o| serde_json::json!({
+                "amount": o.amount,
+                "id": keyset.id,
+                "B_": hex(&o.blinded.serialize()),
+            }))
+            .collect::<Vec<_>>(),
+    });
+    let response = client
+        .post(format!("{}/v1/swap", mint))
+        .header("content-type", "application/json")
+        .body(request.to_string())
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await?;
+    let status = response.status();
+    let body = response.bytes().await?;
+    if !status.is_success() {
+        // Mints explain rejections (e.g. already spent) in `detail`
+        let detail = serde_json::from_slice::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|v| v["detail"].as_str().map(String::from))
+            .unwrap_or_else(|| status.to_string());
+        anyhow::bail!("{}", detail);
+    }
+    let swapped: SwapResponse = serde_json::from_slice(&body)?;
+    if swapped.signatures.len() != outputs.len() {
+        anyhow::bail!("mint returned {} signatures for {} outputs", swapped.signatures.len(), outputs.len());
+    }
+
+    let mut proofs = Vec::with_capacity(outputs.len());
+    for (output, signature) in outputs.into_iter().zip(swapped.signatures) {
+        let key = keys
+            .get(&signature.amount.to_string())
+            .and_then(|k| parse_point(k))
+            .ok_or_else(|| anyhow::anyhow!("mint has no key for amount {}", signature.amount))?;
+        let c = parse_point(&signature.c).ok_or_else(|| anyhow::anyhow!("invalid mint signature"))?;
+        proofs.push(output.unblind(&keyset.id, &c, &key).map_err(anyhow::Error::msg)?);
+    }
+    Ok(Token {
+        mint: token.mint.clone(),
+        unit: token.unit.clone(),
+        proofs,
+    })
+}
+
+fn parse_point(s: &str) -> Option<PublicKey> {
+    PublicKey::from_slice(&unhex(s)?).ok()
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+) -> Result<T, anyhow::Error> {
+    let body = client
+        .get(url)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// The CBOR subset `cashuB` tokens use: definite-length items only.
+mod cbor {
+    pub enum Value {
+        Uint(u64),
+        Bytes(Vec<u8>),
+        Text(String),
+        Array(Vec<Value>),
+        Map(Vec<(Value, Value)>),
+        Simple,
+    }
+
+    impl Value {
+        pub fn get(&self, key: &str) -> Option<&Value> {
+            match self {
+                Value::Map(entries) => entries
+                    .iter()
+                    .find(|(k, _)| k.text() == Some(key))
+                    .map(|(_, v)| v),
+                _ => None,
+            }
+        }
+
+        pub fn text(&self) -> Option<&str> {
+            match self {
+                Value::Text(s) => Some(s),
+                _ => None,
+            }
+        }
+
+        pub fn bytes(&self) -> Option<&[u8]> {
+            match self {
+                Value::Bytes(b) => Some(b),
+                _ => None,
+            }
+        }
+
+        pub fn uint(&self) -> Option<u64> {
+            match self {
+                Value::Uint(n) => Some(*n),
+                _ => None,
+            }
+        }
+
+        pub fn array(&self) -> Option<&[Value]> {
+            match self {
+                Value::Array(items) => Some(items),
+                _ => None,
+            }
+        }
+    }
+
+    /// Read one item, returning it and the bytes after it.
+    pub fn read(data: &[u8]) -> Option<(Value, &[u8])> {
+        let (&initial, rest) = data.split_first()?;
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        let (arg, mut rest) = match info {
+            0..=23 => (info as u64, rest),
+            24..=27 => {
+                let len = 1 << (info - 24);
+                let (bytes, rest) = (rest.get(..len)?, &rest[len..]);
+                (bytes.iter().fold(0u64, |acc, &b| acc << 8 | b as u64), rest)
+            }
+            _ => return None,
+        };
+        let value = match major {
+            0 => Value::Uint(arg),
+            2 | 3 => {
+                let len = usize::try_from(arg).ok()?;
+                let bytes = rest.get(..len)?.to_vec();
+                rest = &rest[len..];
+                if major == 2 {
+                    Value::Bytes(bytes)
+                } else {
+                    Value::Text(String::from_utf8(bytes).ok()?)
+                }
+            }
+            4 => {
+                let mut items = Vec::new();
+                for _ in 0..arg {
+                    let (item, next) = read(rest)?;
+                    items.push(item);
+                    rest = next;
+                }
+                Value::Array(items)
+            }
+            5 => {
+                let mut entries = Vec::new();
+                for _ in 0..arg {
+                    let (key, next) = read(rest)?;
+                    let (value, next) = read(next)?;
+                    entries.push((key, value));
+                    rest = next;
+                }
+                Value::Map(entries)
+            }
+            7 => Value::Simple,
+            _ => return None,
+        };
+        Some((value, rest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+
+    #[test]
+    fn hash_to_curve_matches_the_spec_vectors() {
+        let zero = [0u8; 32];
+        assert_eq!(
+            hex(&hash_to_curve(&zero).serialize()),
+            "024cce997d3b518f739663b757deaec95bcd9473c30a14ac2fd04023a739d1a725"
+        );
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        assert_eq!(
+            hex(&hash_to_curve(&one).serialize()),
+            "022e7158e11c9506f1aa4248bf531298daa7febd6194f003edcd9b93ade6253acf"
+        );
+    }
+
+    /// CBOR for the test tokens: major type and length header, then data.
+    fn cbor_head(major: u8, len: usize) -> Vec<u8> {
+        match len {
+            0..=23 => vec![major << 5 | len as u8],
+            24..=255 => vec![major << 5 | 24, len as u8],
+            _ => vec![major << 5 | 25, (len >> 8) as u8, len as u8],
+        }
+    }
+
+    fn cbor_text(s: &str) -> Vec<u8> {
+        [cbor_head(3, s.len()), s.as_bytes().to_vec()].concat()
+    }
+
+    fn cbor_bytes(b: &[u8]) -> Vec<u8> {
+        [cbor_head(2, b.len()), b.to_vec()].concat()
+    }
+
+    #[test]
+    fn decodes_both_token_versions() {
+        let proof = Proof {
+            amount: 8,
+            id: "00ad268c4d1f5826".to_string(),
+            secret: "acc12435e7b8484c3cf1850149218af90f716a52bf4a5ed347e48ecc13f77388".to_string(),
+            c: "0244538319de485d55bed3b29a642bee5879375ab9e7a620e11e48ba482421f3cf".to_string(),
+        };
+        let token = Token {
+            mint: "https://mint.example.com".to_string(),
+            unit: "sat".to_string(),
+            proofs: vec![proof.clone(), Proof { amount: 2, ..proof.clone() }],
+        };
+        let encoded = encode(std::slice::from_ref(&token), "sat");
+        assert_eq!(decode(&encoded).unwrap(), token);
+        assert_eq!(decode(&format!("cashu:{}", encoded.trim_end_matches('='))).unwrap().amount(), Some(10));
+
+        let mut v4 = cbor_head(5, 3);
+        v4.extend(cbor_text("m"));
+        v4.extend(cbor_text(&token.mint));
+        v4.extend(cbor_text("u"));
+        v4.extend(cbor_text("sat"));
+        v4.extend(cbor_text("t"));
+        v4.extend(cbor_head(4, 1));
+        v4.extend(cbor_head(5, 2));
+        v4.extend(cbor_text("i"));
+        v4.extend(cbor_bytes(&unhex(&proof.id).unwrap()));
+        v4.extend(cbor_text("p"));
+        v4.extend(cbor_head(4, 2));
+        for amount in [8u8, 2] {
+            v4.extend(cbor_head(5, 3));
+            v4.extend(cbor_text("a"));
+            v4.push(amount);
+            v4.extend(cbor_text("s"));
+            v4.extend(cbor_text(&proof.secret));
+            v4.extend(cbor_text("c"));
+            v4.extend(cbor_bytes(&unhex(&proof.c).unwrap()));
+        }
+        let encoded = format!("cashuB{}", URL_SAFE_NO_PAD.encode(&v4));
+        assert_eq!(decode(&encoded).unwrap(), token);
+
+        assert!(decode("cashuBnotcbor").is_err());
+        assert!(decode("lnbc1...").is_err());
+    }
+
+    #[test]
+    fn tokens_worth_more_than_a_u64_are_rejected() {
+        let proof = Proof {
+            amount: u64::MAX,
+            id: "00ad268c4d1f5826".to_string(),
+            secret: "secret".to_string(),
+            c: "02".to_string(),
+        };
+        let token = Token {
+            mint: "https://mint.example.com".to_string(),
+            unit: "sat".to_string(),
+            proofs: vec![proof.clone(), Proof { amount: 1, ..proof }],
+        };
+        assert_eq!(token.amount(), None);
+        assert_eq!(decode(&encode(&[token], "sat")).unwrap_err(), "token amount overflows");
+    }
+
+    #[tokio::test]
+    async fn redeem_swaps_proofs_for_ones_the_mint_signed() {
+        // A mint with one key per amount, all the same secret for brevity
+        let k = SecretKey::new(&mut rand::thread_rng());
+        let mint_key = hex(&k.public_key(&SECP256K1).serialize());
+        let keys: HashMap<String, String> = (0..8).map(|i| ((1u64 << i).to_string(), mint_key.clone())).collect();
+        let app = Router::new()
+            .route(
+                "/v1/keysets",
+                get(|| async {
+                    Json(serde_json::json!({ "keysets": [
+                        { "id": "old", "unit": "sat", "active": false, "input_fee_ppk": 600 },
+                        { "id": "new", "unit": "sat", "active": true },
+                    ]}))
+                }),
+            )
+            .route(
+                "/v1/keys/new",
+                get(move || async move { Json(serde_json::json!({ "keysets": [{ "id": "new", "unit": "sat", "keys": keys }] })) }),
+            )
+            .route(
+                "/v1/swap",
+                post(move |Json(req): Json<serde_json::Value>| async move {
+                    let signatures: Vec<_> = req["outputs"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|o| {
+                            let blinded = parse_point(o["B_"].as_str().unwrap()).unwrap();
+                            let signed = blinded.mul_tweak(&SECP256K1, &Scalar::from(k)).unwrap();
+                            serde_json::json!({ "amount": o["amount"], "id": o["id"], "C_": hex(&signed.serialize()) })
+                        })
+                        .collect();
+                    Json(serde_json::json!({ "signatures": signatures }))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mint = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let input = |amount| Proof {
+            amount,
+            id: "old".to_string(),
+            secret: hex(&rand::random::<[u8; 32]>()),
+            c: mint_key.clone(),
+        };
+        let token = Token {
+            mint: mint.clone(),
+            unit: "sat".to_string(),
+            proofs: vec![input(8), input(4)],
+        };
+        // Fees count against the amount needed, before anything is spent
+        let err = redeem(&token, 12).await.unwrap_err();
+        assert!(err.to_string().contains("12 needed"), "{}", err);
+        let redeemed = redeem(&token, 10).await.unwrap();
+
+        // Two inputs at 600 ppk cost 2 sats
+        assert_eq!(redeemed.amount(), Some(10));
+        assert_eq!(redeemed.proofs.iter().map(|p| p.amount).collect::<Vec<_>>(), vec![2, 8]);
+        for proof in &redeemed.proofs {
+            assert_eq!(proof.id, "new");
+            let expected = hash_to_curve(proof.secret.as_bytes())
+                .mul_tweak(&SECP256K1, &Scalar::from(k))
+                .unwrap();
+            assert_eq!(proof.c, hex(&expected.serialize()));
+        }
+    }
+}
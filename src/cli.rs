@@ -0,0 +1,314 @@
+//! Offline administration commands that operate directly on the config file
+//! and LMDB stores, without the HTTP server running.
+
+use crate::config::{ListenAddr, MoarConfig, RelayConfig};
+use crate::storage::lmdb::LmdbStore;
+use crate::storage::NostrStore;
+use nostr::{Event, Filter, Kind, PublicKey, Timestamp};
+use rayon::prelude::*;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+pub fn load_config(path: &Path) -> Result<MoarConfig, anyhow::Error> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    toml::from_str(&content).map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))
+}
+
+pub(crate) fn relay_config<'a>(config: &'a MoarConfig, relay_id: &str) -> Result<&'a RelayConfig, anyhow::Error> {
+    config
+        .relays
+        .get(relay_id)
+        .ok_or_else(|| anyhow::anyhow!("Relay '{}' not found in config", relay_id))
+}
+
+/// Every event in `store` matching `filter`, whose limit is ignored.
+pub fn matching_events(store: &dyn NostrStore, filter: &Filter) -> crate::error::Result<Vec<Event>> {
+    let mut events = store.iter_all()?;
+    if !filter.is_empty() {
+        let mut filter = filter.clone();
+        filter.limit = None;
+        events.retain(|event| filter.match_event(event));
+    }
+    Ok(events)
+}
+
+/// Build an export filter from the CLI's `--kinds`, `--authors` (hex or
+/// npub), `--since` and `--until` options.
+pub fn export_filter(
+    kinds: &[u16],
+    authors: &[String],
+    since: Option<u64>,
+    until: Option<u64>,
+) -> Result<Filter, anyhow::Error> {
+    let mut filter = Filter::new();
+    if !kinds.is_empty() {
+        filter = filter.kinds(kinds.iter().map(|&k| Kind::from(k)));
+    }
+    if !authors.is_empty() {
+        let authors = authors
+            .iter()
+            .map(|a| PublicKey::parse(a).map_err(|_| anyhow::anyhow!("Invalid author '{}'", a)))
+            .collect::<Result<Vec<_>, _>>()?;
+        filter = filter.authors(authors);
+    }
+    filter.since = since.map(Timestamp::from);
+    filter.until = until.map(Timestamp::from);
+    Ok(filter)
+}
+
+/// Write the events of a relay matching `filter` as JSONL to `output`, or
+/// stdout if `None`.
+pub fn export(config: &MoarConfig, relay_id: &str, output: Option<&Path>, filter: &Filter) -> Result<(), anyhow::Error> {
+    let relay = relay_config(config, relay_id)?;
+    let store = LmdbStore::new(&relay.db_path)?;
+    let events = matching_events(&store, filter)?;
+
+    let writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut writer = BufWriter::new(writer);
+    for event in &events {
+        serde_json::to_writer(&mut writer, event)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+
+    eprintln!("Exported {} events from relay '{}'", events.len(), relay_id);
+    Ok(())
+}
+
+/// Events written per transaction when importing.
+pub const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// Parse a batch of JSONL lines, checking signatures in parallel unless
+/// `verify` is off. Returns the events that passed and how many lines didn't.
+pub fn parse_import_batch(lines: &[String], verify: bool) -> (Vec<Event>, usize) {
+    let parsed: Vec<Option<Event>> = lines
+        .par_iter()
+        .map(|line| {
+            let event: Event = serde_json::from_str(line).ok()?;
+            if verify && event.verify().is_err() {
+                return None;
+            }
+            Some(event)
+        })
+        .collect();
+    let errors = parsed.iter().filter(|e| e.is_none()).count();
+    (parsed.into_iter().flatten().collect(), errors)
+}
+
+/// What loading a stream of events into a store did.
+#[derive(Debug, Default)]
+pub struct ImportCounts {
+    pub imported: usize,
+    /// Events in batches the store refused.
+    pub skipped: usize,
+    /// Lines that didn't parse or failed signature checks.
+    pub errors: usize,
+}
+
+/// Save JSONL `lines` into `store` in batches of [`IMPORT_BATCH_SIZE`].
+/// With a `total`, progress is printed to stderr after every batch.
+pub fn import_lines(
+    store: &LmdbStore,
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    verify: bool,
+    total: Option<u64>,
+) -> Result<ImportCounts, anyhow::Error> {
+    let mut counts = ImportCounts::default();
+    let mut read = 0u64;
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut save = |batch: &mut Vec<String>, read: u64| {
+        let (events, bad) = parse_import_batch(batch, verify);
+        batch.clear();
+        counts.errors += bad;
+        match store.save_events(&events) {
+            Ok(()) => counts.imported += events.len(),
+            Err(_) => counts.skipped += events.len(),
+        }
+        if let Some(total) = total {
+            eprint!("\r{}/{} events", read, total);
+        }
+    };
+
+    for line in lines {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        read += 1;
+        batch.push(line.to_string());
+        if batch.len() >= IMPORT_BATCH_SIZE {
+            save(&mut batch, read);
+        }
+    }
+    if !batch.is_empty() {
+        save(&mut batch, read);
+    }
+    if total.is_some() {
+        eprintln!();
+    }
+    Ok(counts)
+}
+
+/// Import events from a JSONL file into a relay. Signatures are verified
+/// unless `verify` is off, for files exported from a trusted relay.
+pub fn import(config: &MoarConfig, relay_id: &str, input: &Path, verify: bool) -> Result<(), anyhow::Error> {
+    let relay = relay_config(config, relay_id)?;
+    let store = LmdbStore::new(&relay.db_path)?;
+    let reader = BufReader::new(std::fs::File::open(input)?);
+    let counts = import_lines(&store, reader.lines(), verify, None)?;
+    print_import_summary(&counts, relay_id);
+    Ok(())
+}
+
+pub fn print_import_summary(counts: &ImportCounts, relay_id: &str) {
+    println!(
+        "Imported {} events into relay '{}' ({} skipped, {} errors)",
+        counts.imported, relay_id, counts.skipped, counts.errors
+    );
+}
+
+/// Rewrite a relay's LMDB file without free pages, reclaiming disk space
+/// left behind by deletions. Refuses to run while a server or any other
+/// process has the database open, since their writes would be lost.
+pub fn compact(config: &MoarConfig, relay_id: &str) -> Result<(), anyhow::Error> {
+    let relay = relay_config(config, relay_id)?;
+    let db_dir = Path::new(&relay.db_path);
+    let data_file = db_dir.join("data.mdb");
+    let compact_file = db_dir.join("data.mdb.compact");
+
+    let before = std::fs::metadata(&data_file)?.len();
+    let _ = std::fs::remove_file(&compact_file);
+
+    // Held until the new file is in place, so nothing opens the old one
+    let store = LmdbStore::open_exclusive(&relay.db_path).map_err(|e| match e {
+        crate::error::Error::InUse(_) => anyhow::anyhow!(
+            "Relay '{}' is in use; stop the server before compacting",
+            relay_id
+        ),
+        e => e.into(),
+    })?;
+    store.compact_to(&compact_file)?;
+    std::fs::rename(&compact_file, &data_file)?;
+    drop(store);
+    let after = std::fs::metadata(&data_file)?.len();
+
+    println!(
+        "Compacted relay '{}': {} -> {} bytes",
+        relay_id, before, after
+    );
+    Ok(())
+}
+
+/// Print event counts and on-disk size for every configured relay.
+pub fn stats(config: &MoarConfig) -> Result<(), anyhow::Error> {
+    let mut ids: Vec<&String> = config.relays.keys().collect();
+    ids.sort();
+
+    println!("{:<20} {:>12} {:>14}  PATH", "RELAY", "EVENTS", "SIZE (bytes)");
+    for id in ids {
+        let relay = &config.relays[id];
+        let data_file = Path::new(&relay.db_path).join("data.mdb");
+        if !data_file.exists() {
+            println!("{:<20} {:>12} {:>14}  {}", id, "-", "-", relay.db_path);
+            continue;
+        }
+        let store = LmdbStore::new(&relay.db_path)?;
+        let count = store.event_count()?;
+        let size = std::fs::metadata(&data_file)?.len();
+        println!("{:<20} {:>12} {:>14}  {}", id, count, size, relay.db_path);
+    }
+    Ok(())
+}
+
+/// Problems with the machine the config would run on: storage
+/// directories that can't be written and listen ports already taken.
+pub fn environment_problems(config: &MoarConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let mut dirs: Vec<(String, std::path::PathBuf)> = vec![
+        ("data_dir".to_string(), config.data_dir.clone().into()),
+        ("pages_dir".to_string(), config.pages_dir.clone().into()),
+    ];
+    if config.backup.is_some() {
+        dirs.push(("backup_dir".to_string(), config.backup_dir()));
+    }
+    for (id, relay) in &config.relays {
+        dirs.push((format!("relay '{}' db_path", id), relay.db_path.clone().into()));
+    }
+    for (id, blossom) in &config.blossoms {
+        dirs.push((format!("blossom server '{}' storage_path", id), blossom.storage_path.clone().into()));
+    }
+    for (id, pool) in &config.blob_pools {
+        dirs.push((format!("blob pool '{}' path", id), pool.path.clone().into()));
+    }
+    for listener in &config.listeners {
+        if let Ok(ListenAddr::Unix(path)) = listener.addr() {
+            let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            dirs.push((format!("listener '{}' directory", listener.bind), dir));
+        }
+    }
+    dirs.sort();
+    for (owner, dir) in dirs {
+        if let Err(e) = check_writable(&dir) {
+            problems.push(format!("{} '{}' is not writable: {}", owner, dir.display(), e));
+        }
+    }
+
+    for (name, addr) in config.tcp_listen_addrs() {
+        if let Err(e) = std::net::TcpListener::bind(addr) {
+            problems.push(format!("{} {} cannot be bound: {}", name, addr, e));
+        }
+    }
+
+    problems
+}
+
+/// Whether files can be created in `dir`, or in the closest existing
+/// ancestor it would be created under. Creates nothing that isn't removed.
+fn check_writable(dir: &Path) -> std::io::Result<()> {
+    let mut existing = dir;
+    while !existing.exists() {
+        match existing.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => existing = parent,
+            _ => {
+                existing = Path::new(".");
+                break;
+            }
+        }
+    }
+    if !existing.is_dir() {
+        return Err(std::io::Error::other(format!("'{}' is not a directory", existing.display())));
+    }
+    let probe = existing.join(format!(".moar-write-check-{}", std::process::id()));
+    std::fs::File::create(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
+/// Load and check the config file, printing every problem found.
+/// Returns the config if there were none.
+pub fn check_config(path: &Path) -> Option<MoarConfig> {
+    let config = match load_config(path) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return None;
+        }
+    };
+
+    let mut problems = config.validate();
+    problems.extend(environment_problems(&config));
+    if problems.is_empty() {
+        return Some(config);
+    }
+
+    for problem in &problems {
+        eprintln!("error: {}", problem);
+    }
+    eprintln!("{} problem(s) found in {}", problems.len(), path.display());
+    None
+}
@@ -5,6 +5,31 @@ use std::collections::HashMap;
 pub struct MoarConfig {
     pub domain: String,
     pub port: u16,
+    /// Interface the HTTP and HTTPS listeners bind to (default: "0.0.0.0").
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    /// Additional listeners, e.g. a localhost-only one for the admin API or
+    /// a Unix socket for a local reverse proxy.
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+    /// Move the admin UI off the apex domain.
+    #[serde(default)]
+    pub admin: AdminConfig,
+    /// How relays and blossom servers are reached: on their own subdomains
+    /// (the default), or under `/relay/<id>` and `/blossom/<id>` on the
+    /// domain itself, for deployments without wildcard DNS.
+    #[serde(default, skip_serializing_if = "RoutingMode::is_subdomain")]
+    pub routing: RoutingMode,
+    /// Origin clients reach the domain at when it differs from the listener,
+    /// e.g. `https://example.com:8443` behind a proxy on a nonstandard port.
+    /// Relay, blossom and checkout URLs keep its scheme and port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_url: Option<String>,
+    /// Relay (a key of `relays`) that WebSocket and NIP-11 requests to the
+    /// apex domain's root reach, so it works without subdomain DNS, e.g. at
+    /// `ws://localhost:8080/` while developing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_relay: Option<String>,
     /// Hex pubkey of the admin allowed to access the admin UI.
     /// Only this pubkey can log in via NIP-98 auth.
     pub admin_pubkey: String,
@@ -12,6 +37,14 @@ pub struct MoarConfig {
     /// Each relay can have a `{relay_id}.html` file in this directory.
     #[serde(default = "default_pages_dir")]
     pub pages_dir: String,
+    /// Directory for runtime state: WoT sets, paywall whitelists, relay keys,
+    /// mirror queues and admin sessions (default: "data"). Give each instance
+    /// its own when several share a working directory.
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
+    /// Directory that relay snapshots are written to (default: "<data_dir>/backups").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_dir: Option<String>,
     #[serde(default)]
     pub discovery_relays: Vec<String>,
     #[serde(default)]
@@ -21,6 +54,480 @@ pub struct MoarConfig {
     pub relays: HashMap<String, RelayConfig>,
     #[serde(default)]
     pub blossoms: HashMap<String, BlossomConfig>,
+    /// Shared blob storage that several blossom servers can point at, keyed
+    /// by pool id.
+    #[serde(default)]
+    pub blob_pools: HashMap<String, BlobPoolConfig>,
+    /// NIP-05 names served from `/.well-known/nostr.json`, keyed by name.
+    #[serde(default)]
+    pub nip05: HashMap<String, Nip05Entry>,
+    /// Lightning addresses (`name@domain`) served by the gateway, keyed by name.
+    #[serde(default)]
+    pub lightning_addresses: HashMap<String, LightningAddressConfig>,
+    /// Scheduled backups of every relay and blossom store (disabled if absent).
+    #[serde(default)]
+    pub backup: Option<BackupConfig>,
+    /// NIP-66 discovery events for every hosted relay (disabled if absent).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<MonitorConfig>,
+    /// NIP-65 relay lists kept published for hosted users (disabled if absent).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_lists: Option<RelayListConfig>,
+    /// Thresholds for the health alerts sent to `webhooks`.
+    #[serde(default)]
+    pub alerts: AlertConfig,
+    /// URLs notified of operator events, keyed by webhook id.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub webhooks: HashMap<String, WebhookConfig>,
+    /// NIP-17 DMs to `admin_pubkey` on critical conditions (disabled if absent).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dm_alerts: Option<DmAlertConfig>,
+    /// Peers allowed to set the client address via `X-Forwarded-For`, as IPs
+    /// or CIDR ranges (default: loopback and private networks).
+    #[serde(default = "default_trusted_proxies")]
+    pub trusted_proxies: Vec<String>,
+    /// Per-IP limits across all relays and blossom servers together.
+    #[serde(default)]
+    pub rate_limit: GatewayRateLimitConfig,
+    /// IP, ASN and country allow/block lists for relay and blossom subdomains.
+    #[serde(default)]
+    pub network: NetworkConfig,
+    /// Built-in HTTPS listener, for running without a reverse proxy.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Limits on how relays use their LMDB databases.
+    #[serde(default)]
+    pub storage: StorageConfig,
+}
+
+impl MoarConfig {
+    pub fn data_path(&self, name: &str) -> std::path::PathBuf {
+        std::path::Path::new(&self.data_dir).join(name)
+    }
+
+    pub fn backup_dir(&self) -> std::path::PathBuf {
+        match &self.backup_dir {
+            Some(dir) => dir.into(),
+            None => self.data_path("backups"),
+        }
+    }
+
+    /// Check the configuration for problems that would only surface at
+    /// runtime: dangling WoT/paywall references, duplicate subdomains and
+    /// storage paths, and unparseable pubkeys. Returns one message per
+    /// problem found. Filesystem and port checks are in
+    /// [`crate::cli::environment_problems`].
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if nostr::PublicKey::parse(&self.admin_pubkey).is_err() {
+            problems.push(format!("admin_pubkey '{}' is not a valid pubkey", self.admin_pubkey));
+        }
+
+        let mut subdomains: HashMap<String, String> = HashMap::new();
+        let mut claim_subdomain = |subdomain: &str, owner: String, problems: &mut Vec<String>| {
+            if let Some(existing) = subdomains.insert(subdomain.to_string(), owner.clone()) {
+                problems.push(format!(
+                    "{} uses subdomain '{}' which is already used by {}",
+                    owner, subdomain, existing
+                ));
+            }
+        };
+        // Two stores opening the same LMDB environment would corrupt each other
+        let mut paths: HashMap<String, String> = HashMap::new();
+        let mut claim_path = |path: &str, owner: String, problems: &mut Vec<String>| {
+            let normalized = path.trim_end_matches('/').to_string();
+            if let Some(existing) = paths.insert(normalized, owner.clone()) {
+                problems.push(format!("{} uses path '{}' which is already used by {}", owner, path, existing));
+            }
+        };
+
+        if let Some(subdomain) = &self.admin.subdomain {
+            if subdomain.is_empty() {
+                problems.push("admin subdomain is empty".to_string());
+            }
+            claim_subdomain(subdomain, "the admin UI".to_string(), &mut problems);
+        }
+        if let Some(path) = &self.admin.path {
+            let mut reserved = vec!["/api", "/.well-known", "/lnurlp"];
+            if self.routing == RoutingMode::Path {
+                reserved.extend(["/relay/", "/blossom/"]);
+            }
+            if !path.starts_with('/') || path == "/" || reserved.iter().any(|r| path.starts_with(r)) {
+                problems.push(format!("admin path '{}' must start with '/' and not be '/' or under {}", path, reserved.join(", ")));
+            }
+            if !self.admin_on_apex() {
+                problems.push("admin path has no effect with admin.subdomain or an admin_only listener".to_string());
+            }
+        }
+        if self.admin.landing_page.is_some() && self.dashboard_path() == Some("/") {
+            problems.push("admin landing_page needs admin.subdomain, admin.path or an admin_only listener".to_string());
+        }
+
+        if let Some(public_url) = &self.public_url {
+            match nostr::Url::parse(public_url) {
+                Ok(url) if !matches!(url.scheme(), "http" | "https") => {
+                    problems.push(format!("public_url '{}' must be http or https", public_url))
+                }
+                Ok(url) if url.host_str() != Some(self.domain.as_str()) => {
+                    problems.push(format!("public_url '{}' must be on domain '{}'", public_url, self.domain))
+                }
+                Ok(url) if url.path() != "/" || url.query().is_some() => {
+                    problems.push(format!("public_url '{}' must not have a path or query", public_url))
+                }
+                Ok(_) => {}
+                Err(_) => problems.push(format!("public_url '{}' is not a valid URL", public_url)),
+            }
+        }
+
+        if let Some(relay) = &self.default_relay {
+            if !self.relays.contains_key(relay) {
+                problems.push(format!("default_relay references unknown relay '{}'", relay));
+            }
+        }
+
+        for (id, relay) in &self.relays {
+            let owner = format!("relay '{}'", id);
+            if relay.name.is_empty() {
+                problems.push(format!("{} has an empty name", owner));
+            }
+            if relay.subdomain.is_empty() {
+                problems.push(format!("{} has an empty subdomain", owner));
+            }
+            claim_subdomain(&relay.subdomain, owner.clone(), &mut problems);
+            claim_path(&relay.db_path, owner.clone(), &mut problems);
+
+            let policy = &relay.policy;
+            for wot in [&policy.write.wot, &policy.read.wot].into_iter().flatten() {
+                if !self.wots.contains_key(wot) {
+                    problems.push(format!("{} references unknown WoT '{}'", owner, wot));
+                }
+            }
+            for paywall in [&policy.write.paywall, &policy.read.paywall].into_iter().flatten() {
+                if !self.paywalls.contains_key(paywall) {
+                    problems.push(format!("{} references unknown paywall '{}'", owner, paywall));
+                }
+            }
+
+            for url in &relay.mirror_to {
+                if !url.starts_with("ws://") && !url.starts_with("wss://") {
+                    problems.push(format!("{} mirror_to contains invalid relay URL '{}'", owner, url));
+                }
+            }
+            for source in &relay.sync_from {
+                if !source.url.starts_with("ws://") && !source.url.starts_with("wss://") {
+                    problems.push(format!("{} sync_from contains invalid relay URL '{}'", owner, source.url));
+                }
+            }
+
+            let pubkey_lists = [
+                ("write.allowed_pubkeys", &policy.write.allowed_pubkeys),
+                ("write.blocked_pubkeys", &policy.write.blocked_pubkeys),
+                ("write.shadow_banned_pubkeys", &policy.write.shadow_banned_pubkeys),
+                ("write.tagged_pubkeys", &policy.write.tagged_pubkeys),
+                ("read.allowed_pubkeys", &policy.read.allowed_pubkeys),
+            ];
+            for (field, list) in pubkey_lists {
+                for pk in list.iter().flatten() {
+                    if nostr::PublicKey::parse(pk).is_err() {
+                        problems.push(format!("{} {} contains invalid pubkey '{}'", owner, field, pk));
+                    }
+                }
+            }
+
+            let nip11 = &relay.nip11;
+            for country in nip11.relay_countries.iter().flatten() {
+                if country != "*" && !(country.len() == 2 && country.chars().all(|c| c.is_ascii_uppercase())) {
+                    problems.push(format!("{} nip11.relay_countries contains invalid country code '{}'", owner, country));
+                }
+            }
+            for (field, url) in [("posting_policy", &nip11.posting_policy), ("payments_url", &nip11.payments_url)] {
+                if let Some(url) = url {
+                    if reqwest::Url::parse(url).is_err() {
+                        problems.push(format!("{} nip11.{} '{}' is not a valid URL", owner, field, url));
+                    }
+                }
+            }
+
+            if let Some(plugin) = &policy.write.plugin {
+                if plugin.command.is_empty() {
+                    problems.push(format!("{} write.plugin command is empty", owner));
+                }
+                if plugin.timeout_ms == 0 {
+                    problems.push(format!("{} write.plugin timeout_ms must be greater than 0", owner));
+                }
+            }
+
+            for pattern in policy.events.blocked_content_patterns.iter().flatten() {
+                if let Err(e) = regex::Regex::new(pattern) {
+                    problems.push(format!("{} events.blocked_content_patterns has invalid regex '{}': {}", owner, pattern, e));
+                }
+            }
+            if policy.events.max_emoji_ratio.is_some_and(|r| !(0.0..=1.0).contains(&r)) {
+                problems.push(format!("{} events.max_emoji_ratio must be between 0.0 and 1.0", owner));
+            }
+            for (i, limits) in policy.events.kind_limits.iter().enumerate() {
+                if limits.kinds.is_empty() {
+                    problems.push(format!("{} events.kind_limits entry {} lists no kinds", owner, i + 1));
+                }
+            }
+            for script in policy.events.allowed_scripts.iter().flatten() {
+                if crate::policy::Script::parse(script).is_none() {
+                    problems.push(format!("{} events.allowed_scripts contains unknown script '{}'", owner, script));
+                }
+            }
+        }
+
+        for (id, blossom) in &self.blossoms {
+            let owner = format!("blossom server '{}'", id);
+            claim_subdomain(&blossom.subdomain, owner.clone(), &mut problems);
+            claim_path(&blossom.storage_path, owner.clone(), &mut problems);
+            if let Some(s3) = &blossom.s3 {
+                if reqwest::Url::parse(&s3.endpoint).is_err() {
+                    problems.push(format!("{} s3 endpoint '{}' is not a valid URL", owner, s3.endpoint));
+                }
+                if s3.bucket.is_empty() {
+                    problems.push(format!("{} s3 bucket is empty", owner));
+                }
+            }
+            if let Some(pool) = &blossom.pool {
+                if !self.blob_pools.contains_key(pool) {
+                    problems.push(format!("{} references unknown blob pool '{}'", owner, pool));
+                }
+                if blossom.s3.is_some() {
+                    problems.push(format!("{} sets both s3 and pool; configure s3 on the pool instead", owner));
+                }
+            }
+            if let Some(relay) = &blossom.auth_relay {
+                if !self.relays.contains_key(relay) {
+                    problems.push(format!("{} auth_relay references unknown relay '{}'", owner, relay));
+                }
+            }
+            if blossom.gc.as_ref().is_some_and(|gc| gc.interval_hours == 0) {
+                problems.push(format!("{} gc interval_hours must be greater than 0", owner));
+            }
+        }
+
+        for (id, pool) in &self.blob_pools {
+            if pool.path.is_empty() {
+                problems.push(format!("blob pool '{}' path is empty", id));
+            }
+            claim_path(&pool.path, format!("blob pool '{}'", id), &mut problems);
+            if let Some(s3) = &pool.s3 {
+                if reqwest::Url::parse(&s3.endpoint).is_err() {
+                    problems.push(format!("blob pool '{}' s3 endpoint '{}' is not a valid URL", id, s3.endpoint));
+                }
+                if s3.bucket.is_empty() {
+                    problems.push(format!("blob pool '{}' s3 bucket is empty", id));
+                }
+            }
+        }
+
+        for (id, wot) in &self.wots {
+            if nostr::PublicKey::parse(&wot.seed).is_err() {
+                problems.push(format!("WoT '{}' has invalid seed pubkey '{}'", id, wot.seed));
+            }
+            for pk in &wot.exclude_pubkeys {
+                if nostr::PublicKey::parse(pk).is_err() {
+                    problems.push(format!("WoT '{}' exclude_pubkeys contains invalid pubkey '{}'", id, pk));
+                }
+            }
+        }
+
+        for (id, paywall) in &self.paywalls {
+            if paywall.price_sats == 0 {
+                problems.push(format!("paywall '{}' must have a price greater than 0", id));
+            }
+            if let Err(e) = crate::nwc::NwcClient::from_connection_string(&paywall.nwc_string) {
+                problems.push(format!("paywall '{}' has an invalid NWC string: {}", id, e));
+            }
+            if let Some(zaps) = &paywall.zaps {
+                if let Err(e) = crate::zap::ZapTerms::parse(zaps) {
+                    problems.push(format!("paywall '{}' has an {}", id, e));
+                }
+            }
+            if let Some(cashu) = &paywall.cashu {
+                if let Err(e) = crate::cashu::validate_mints(cashu) {
+                    problems.push(format!("paywall '{}' {}", id, e));
+                }
+            }
+        }
+
+        for (name, entry) in &self.nip05 {
+            if let Err(e) = crate::nip05::validate_name(name) {
+                problems.push(format!("NIP-05 name '{}' is invalid: {}", name, e));
+            }
+            if nostr::PublicKey::from_hex(&entry.pubkey).is_err() {
+                problems.push(format!("NIP-05 name '{}' has invalid hex pubkey '{}'", name, entry.pubkey));
+            }
+            for url in &entry.relays {
+                if !url.starts_with("ws://") && !url.starts_with("wss://") {
+                    problems.push(format!("NIP-05 name '{}' has invalid relay URL '{}'", name, url));
+                }
+            }
+        }
+
+        for (name, address) in &self.lightning_addresses {
+            if let Err(e) = crate::nip05::validate_name(name) {
+                problems.push(format!("lightning address '{}' is invalid: {}", name, e));
+            }
+            match (&address.nwc_string, &address.paywall) {
+                (Some(nwc), None) => {
+                    if let Err(e) = crate::nwc::NwcClient::from_connection_string(nwc) {
+                        problems.push(format!("lightning address '{}' has an invalid NWC string: {}", name, e));
+                    }
+                }
+                (None, Some(paywall)) => {
+                    if !self.paywalls.contains_key(paywall) {
+                        problems.push(format!("lightning address '{}' references unknown paywall '{}'", name, paywall));
+                    }
+                }
+                _ => problems.push(format!(
+                    "lightning address '{}' must set exactly one of nwc_string or paywall",
+                    name
+                )),
+            }
+            if address.min_sendable_msats > address.max_sendable_msats {
+                problems.push(format!(
+                    "lightning address '{}' has min_sendable_msats above max_sendable_msats",
+                    name
+                ));
+            }
+        }
+
+        if let Some(backup) = &self.backup {
+            if backup.interval_hours == 0 {
+                problems.push("backup interval_hours must be greater than 0".to_string());
+            }
+            if backup.retain == 0 {
+                problems.push("backup retain must be greater than 0".to_string());
+            }
+            if let Some(s3) = &backup.s3 {
+                if reqwest::Url::parse(&s3.endpoint).is_err() {
+                    problems.push(format!("backup s3 endpoint '{}' is not a valid URL", s3.endpoint));
+                }
+                if s3.bucket.is_empty() {
+                    problems.push("backup s3 bucket is empty".to_string());
+                }
+            }
+        }
+
+        if let Some(monitor) = &self.monitor {
+            if monitor.interval_minutes == 0 {
+                problems.push("monitor interval_minutes must be greater than 0".to_string());
+            }
+            if self.monitor_targets().is_empty() {
+                problems.push("monitor has no relays to publish to (set publish_to or discovery_relays)".to_string());
+            }
+            for url in &monitor.publish_to {
+                if !url.starts_with("ws://") && !url.starts_with("wss://") {
+                    problems.push(format!("monitor publish_to '{}' is not a ws:// or wss:// URL", url));
+                }
+            }
+        }
+
+        if let Some(lists) = &self.relay_lists {
+            if lists.interval_hours == 0 {
+                problems.push("relay_lists interval_hours must be greater than 0".to_string());
+            }
+            if self.relay_list_targets().is_empty() {
+                problems.push("relay_lists has no relays to publish to (set publish_to or discovery_relays)".to_string());
+            }
+            for url in &lists.publish_to {
+                if !url.starts_with("ws://") && !url.starts_with("wss://") {
+                    problems.push(format!("relay_lists publish_to '{}' is not a ws:// or wss:// URL", url));
+                }
+            }
+            for user in &lists.users {
+                if nostr::PublicKey::parse(&user.pubkey).is_err() {
+                    problems.push(format!("relay_lists user '{}' is not a valid pubkey", user.pubkey));
+                }
+                if user.relays.is_empty() {
+                    problems.push(format!("relay_lists user '{}' has no relays", user.pubkey));
+                }
+                for relay in &user.relays {
+                    if !self.relays.contains_key(relay) {
+                        problems.push(format!("relay_lists user '{}' references unknown relay '{}'", user.pubkey, relay));
+                    }
+                }
+            }
+        }
+
+        if !(1..=100).contains(&self.alerts.disk_full_percent) {
+            problems.push("alerts disk_full_percent must be between 1 and 100".to_string());
+        }
+        if !(1..=100).contains(&self.alerts.rejected_spike_percent) {
+            problems.push("alerts rejected_spike_percent must be between 1 and 100".to_string());
+        }
+        for (id, webhook) in &self.webhooks {
+            if !reqwest::Url::parse(&webhook.url).is_ok_and(|url| matches!(url.scheme(), "http" | "https")) {
+                problems.push(format!("webhook '{}' url '{}' is not an http:// or https:// URL", id, webhook.url));
+            }
+            for event in &webhook.events {
+                if crate::webhook::AlertKind::parse(event).is_none() {
+                    problems.push(format!("webhook '{}' has unknown event '{}'", id, event));
+                }
+            }
+        }
+
+        if let Some(dm) = &self.dm_alerts {
+            if nostr::Keys::parse(&dm.secret_key).is_err() {
+                problems.push("dm_alerts secret_key is not a valid secret key".to_string());
+            }
+            if dm.relays.is_empty() {
+                problems.push("dm_alerts has no relays to publish to".to_string());
+            }
+            for url in &dm.relays {
+                if !url.starts_with("ws://") && !url.starts_with("wss://") {
+                    problems.push(format!("dm_alerts relay '{}' is not a ws:// or wss:// URL", url));
+                }
+            }
+            for event in &dm.events {
+                if crate::webhook::AlertKind::parse(event).is_none() {
+                    problems.push(format!("dm_alerts has unknown event '{}'", event));
+                }
+            }
+        }
+
+        for entry in self.network.allow.iter().chain(&self.network.block) {
+            match crate::network::NetworkEntry::parse(entry) {
+                Ok(crate::network::NetworkEntry::Asn(_)) if self.network.asn_db.is_none() => {
+                    problems.push(format!("network entry '{}' needs asn_db to be set", entry))
+                }
+                Ok(crate::network::NetworkEntry::Country(_)) if self.network.country_db.is_none() => {
+                    problems.push(format!("network entry '{}' needs country_db to be set", entry))
+                }
+                Ok(_) => {}
+                Err(e) => problems.push(format!("network entry {}", e)),
+            }
+        }
+
+        for entry in &self.trusted_proxies {
+            if crate::rate_limit::parse_net(entry).is_none() {
+                problems.push(format!("trusted_proxies entry '{}' is not an IP address or CIDR range", entry));
+            }
+        }
+
+        problems.extend(self.listener_problems());
+
+        if let Some(tls) = &self.tls {
+            let has_static = tls.cert_path.is_some() || tls.key_path.is_some() || !tls.certs.is_empty();
+            match (&tls.acme, has_static) {
+                (Some(_), true) => problems.push("tls must use either acme or cert_path/key_path/certs, not both".to_string()),
+                (None, false) => problems.push("tls needs an acme section or cert_path and key_path".to_string()),
+                (Some(_), false) if self.domain == "localhost" => {
+                    problems.push("tls acme needs a public domain, not localhost".to_string())
+                }
+                _ => {}
+            }
+            if tls.cert_path.is_some() != tls.key_path.is_some() {
+                problems.push("tls cert_path and key_path must be set together".to_string());
+            }
+        }
+
+        problems.sort();
+        problems
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,8 +535,68 @@ pub struct WotConfig {
     pub seed: String,
     #[serde(default = "default_wot_depth")]
     pub depth: u8,
+    /// How often contact lists changed since the last refresh are applied.
     #[serde(default = "default_update_interval")]
     pub update_interval_hours: u64,
+    /// How often the WoT is rebuilt from scratch instead.
+    #[serde(default = "default_full_rebuild")]
+    pub full_rebuild_hours: u64,
+    #[serde(default)]
+    pub outbox: WotOutboxConfig,
+    /// Whose kind-10000 mute lists are subtracted from the WoT.
+    #[serde(default)]
+    pub mutes: WotMutes,
+    /// Pubkeys (hex or npub) never included in the WoT.
+    #[serde(default)]
+    pub exclude_pubkeys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WotMutes {
+    #[default]
+    None,
+    /// The seed's mute list.
+    Seed,
+    /// The mute lists of the seed and everyone it follows.
+    SeedAndFollows,
+}
+
+/// Follow lists are also fetched from each pubkey's NIP-65 write relays,
+/// not just the discovery relays.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WotOutboxConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Most outbox relays queried per depth, preferring those shared by the
+    /// most pubkeys.
+    #[serde(default = "default_outbox_max_relays")]
+    pub max_relays: usize,
+    /// Outbox relays queried at the same time.
+    #[serde(default = "default_outbox_concurrency")]
+    pub concurrency: usize,
+}
+
+impl Default for WotOutboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_relays: default_outbox_max_relays(),
+            concurrency: default_outbox_concurrency(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_outbox_max_relays() -> usize {
+    50
+}
+
+fn default_outbox_concurrency() -> usize {
+    10
 }
 
 fn default_wot_depth() -> u8 {
@@ -40,22 +607,94 @@ fn default_update_interval() -> u64 {
     24
 }
 
+fn default_full_rebuild() -> u64 {
+    168
+}
+
+fn default_bind_address() -> String {
+    "0.0.0.0".to_string()
+}
+
 fn default_pages_dir() -> String {
     "pages".to_string()
 }
 
+fn default_data_dir() -> String {
+    "data".to_string()
+}
+
+fn default_trusted_proxies() -> Vec<String> {
+    ["127.0.0.0/8", "::1/128", "10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "fc00::/7"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaywallConfig {
     pub nwc_string: String,
     pub price_sats: u64,
     #[serde(default = "default_period_days")]
     pub period_days: u32,
+    /// Optional DM reminders sent to users whose access is about to expire.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminder: Option<PaywallReminderConfig>,
+    /// Also let users pay by zapping (NIP-57).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zaps: Option<PaywallZapConfig>,
+    /// Also let users pay with Cashu ecash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cashu: Option<PaywallCashuConfig>,
 }
 
 fn default_period_days() -> u32 {
     30
 }
 
+/// Renewal reminders sent as NIP-04 encrypted DMs from a relay-owned key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaywallReminderConfig {
+    /// Secret key (hex or nsec) used to sign the reminder DMs.
+    pub secret_key: String,
+    /// Relays the reminder DMs are published to.
+    pub relays: Vec<String>,
+    /// Link to the checkout page included in the reminder.
+    pub checkout_url: String,
+    /// Send the reminder this many days before access expires.
+    #[serde(default = "default_reminder_days")]
+    pub days_before: u32,
+}
+
+fn default_reminder_days() -> u32 {
+    3
+}
+
+/// Access paid by zapping a lightning address: a zap of at least the price
+/// from a pubkey lets that pubkey in for the paywall's period.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaywallZapConfig {
+    /// Lightning address (`name@domain`) users zap.
+    pub lightning_address: String,
+    /// Pubkey (hex or npub) the zaps are addressed to.
+    pub pubkey: String,
+    /// Pubkey that signs the address's zap receipts. Looked up from the
+    /// address (its LNURL `nostrPubkey`) when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipt_pubkey: Option<String>,
+    /// Relays to watch for zap receipts, besides the relays behind the
+    /// paywall, which see the receipts sent to them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relays: Vec<String>,
+}
+
+/// Access paid with Cashu tokens, which are swapped at their mint so they
+/// can't be spent twice.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaywallCashuConfig {
+    /// Mint URLs whose tokens are accepted.
+    pub mints: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RelayConfig {
     pub name: String,
@@ -66,6 +705,65 @@ pub struct RelayConfig {
     pub policy: PolicyConfig,
     #[serde(default)]
     pub nip11: Nip11Config,
+    /// Give this relay its own signing keypair, generated on first start.
+    /// Its pubkey is advertised in NIP-11 instead of the admin pubkey.
+    #[serde(default)]
+    pub signing_key: bool,
+    /// External relay URLs that every accepted event is forwarded to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirror_to: Vec<String>,
+    /// Upstream relays whose matching events are pulled into this relay.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sync_from: Vec<SyncSource>,
+    /// Live events queued per connection before a slow one starts missing
+    /// them (default 1024).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broadcast_capacity: Option<usize>,
+    /// Cache the results of up to this many distinct REQs (off if unset).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query_cache: Option<usize>,
+    /// Store events sent over WebSocket without checking their id and
+    /// signature. Only for relays fed by a trusted pipeline that already
+    /// verifies them.
+    #[serde(default)]
+    pub skip_verify: bool,
+    /// Refuse new events from clients while still serving reads
+    /// (`"read_only"`), or refuse reads too (`"paused"`), e.g. during an
+    /// import or migration. Toggled live from the admin API.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance_mode: Option<MaintenanceMode>,
+}
+
+/// What a relay in maintenance turns away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceMode {
+    /// EVENTs are refused; REQs are served as usual.
+    ReadOnly,
+    /// EVENTs and REQs are refused and live events are held back.
+    Paused,
+}
+
+/// An upstream relay to subscribe to, with the filters to request.
+/// An empty filter list requests everything.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncSource {
+    pub url: String,
+    #[serde(default, deserialize_with = "deserialize_filters")]
+    pub filters: Vec<nostr::Filter>,
+}
+
+/// `nostr::Filter` only deserializes kinds from unsigned integers, while TOML
+/// yields signed ones, so go through JSON values first.
+fn deserialize_filters<'de, D>(deserializer: D) -> Result<Vec<nostr::Filter>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let values = Vec::<serde_json::Value>::deserialize(deserializer)?;
+    values
+        .into_iter()
+        .map(|v| serde_json::from_value(v).map_err(serde::de::Error::custom))
+        .collect()
 }
 
 /// Optional NIP-11 relay information fields and limit overrides.
@@ -83,6 +781,51 @@ pub struct Nip11Config {
     pub default_limit: Option<u64>,
     pub created_at_lower_limit: Option<u64>,
     pub created_at_upper_limit: Option<u64>,
+    /// Most events returned for one REQ, summed over all of its filters.
+    pub max_events_per_req: Option<u64>,
+    /// Time budget in milliseconds for answering one REQ from storage.
+    pub max_query_ms: Option<u64>,
+    /// ISO 3166-1 alpha-2 codes of the countries whose laws apply, or "*".
+    #[serde(default)]
+    pub relay_countries: Option<Vec<String>>,
+    /// IETF language tags of the content the relay is meant for, e.g. "en".
+    #[serde(default)]
+    pub language_tags: Option<Vec<String>>,
+    /// Community and content tags, e.g. "sfw-only".
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// URL of a page describing what may be posted.
+    #[serde(default)]
+    pub posting_policy: Option<String>,
+    /// Where to pay. Defaults to the relay's checkout page if it has a paywall.
+    #[serde(default)]
+    pub payments_url: Option<String>,
+    /// Advertised fees. Defaults to the paywall's subscription price.
+    #[serde(default)]
+    pub fees: Option<Nip11Fees>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Nip11Fees {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admission: Vec<Nip11Fee>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subscription: Vec<Nip11Fee>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub publication: Vec<Nip11Fee>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Nip11Fee {
+    pub amount: u64,
+    /// e.g. "msats" or "sats".
+    pub unit: String,
+    /// Seconds a subscription lasts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<u64>,
+    /// Kinds a publication fee applies to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kinds: Option<Vec<u64>>,
 }
 
 impl Default for Nip11Config {
@@ -100,6 +843,14 @@ impl Default for Nip11Config {
             default_limit: Some(100),
             created_at_lower_limit: Some(94608000),
             created_at_upper_limit: Some(900),
+            max_events_per_req: Some(10000),
+            max_query_ms: Some(2000),
+            relay_countries: None,
+            language_tags: None,
+            tags: None,
+            posting_policy: None,
+            payments_url: None,
+            fees: None,
         }
     }
 }
@@ -116,10 +867,51 @@ pub struct PolicyConfig {
     pub events: EventPolicy,
     #[serde(default)]
     pub rate_limit: RateLimitConfig,
+    /// Stricter limits for pubkeys new to this relay.
+    pub greylist: Option<GreylistConfig>,
 }
 
-/// Controls who is allowed to publish events (EVENT messages).
+/// Pubkeys stay greylisted until they have published `first_events` events
+/// on the relay and were first seen at least `first_secs` ago. Either
+/// condition can be switched off with 0.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GreylistConfig {
+    #[serde(default = "default_greylist_events")]
+    pub first_events: u32,
+    #[serde(default = "default_greylist_secs")]
+    pub first_secs: u64,
+    /// Events per minute while greylisted.
+    #[serde(default = "default_greylist_writes_per_minute")]
+    pub writes_per_minute: Option<u32>,
+    /// Proof-of-work difficulty (NIP-13) required while greylisted.
+    pub min_pow: Option<u8>,
+}
+
+impl Default for GreylistConfig {
+    fn default() -> Self {
+        Self {
+            first_events: default_greylist_events(),
+            first_secs: default_greylist_secs(),
+            writes_per_minute: default_greylist_writes_per_minute(),
+            min_pow: None,
+        }
+    }
+}
+
+fn default_greylist_events() -> u32 {
+    10
+}
+
+fn default_greylist_secs() -> u64 {
+    3600
+}
+
+fn default_greylist_writes_per_minute() -> Option<u32> {
+    Some(2)
+}
+
+/// Controls who is allowed to publish events (EVENT messages).
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct WritePolicy {
     /// If true, the client must complete NIP-42 AUTH before sending EVENTs.
     #[serde(default)]
@@ -128,6 +920,9 @@ pub struct WritePolicy {
     pub allowed_pubkeys: Option<Vec<String>>,
     /// If set, these pubkeys are explicitly blocked from writing.
     pub blocked_pubkeys: Option<Vec<String>>,
+    /// Pubkeys whose events get `OK true` but are neither stored nor
+    /// broadcast.
+    pub shadow_banned_pubkeys: Option<Vec<String>>,
     /// If set, events are only accepted if they contain a `p` tag referencing
     /// one of these pubkeys.  Useful for inbox/DM relays.
     pub tagged_pubkeys: Option<Vec<String>>,
@@ -135,23 +930,48 @@ pub struct WritePolicy {
     pub wot: Option<String>,
     /// If set, only pubkeys in the referenced paywall whitelist are allowed to write.
     pub paywall: Option<String>,
+    /// If true, pubkeys that redeem an invite code may write, alongside
+    /// `allowed_pubkeys`; without an allow-list, only members may.
+    #[serde(default)]
+    pub invites: bool,
+    /// If true, users can ask for write access on the relay's home page;
+    /// pubkeys the admin approves may write like redeemed invites.
+    #[serde(default)]
+    pub access_requests: bool,
+    /// External command consulted for every event that passes the built-in
+    /// rules (strfry plugin protocol).
+    pub plugin: Option<PluginConfig>,
 }
 
-impl Default for WritePolicy {
-    fn default() -> Self {
-        Self {
-            require_auth: false,
-            allowed_pubkeys: None,
-            blocked_pubkeys: None,
-            tagged_pubkeys: None,
-            wot: None,
-            paywall: None,
-        }
-    }
+/// A strfry-compatible write policy plugin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginConfig {
+    /// Program to run; it is started once and kept running.
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// How long to wait for the plugin's answer to one event.
+    #[serde(default = "default_plugin_timeout_ms")]
+    pub timeout_ms: u64,
+    /// What to do with an event when the plugin fails or times out.
+    #[serde(default)]
+    pub on_error: PluginFailureMode,
+}
+
+fn default_plugin_timeout_ms() -> u64 {
+    2000
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginFailureMode {
+    Accept,
+    #[default]
+    Reject,
 }
 
 /// Controls who is allowed to query events (REQ messages).
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ReadPolicy {
     /// If true, the client must complete NIP-42 AUTH before sending REQs.
     #[serde(default)]
@@ -162,21 +982,13 @@ pub struct ReadPolicy {
     pub wot: Option<String>,
     /// If set, only pubkeys in the referenced paywall whitelist are allowed to read.
     pub paywall: Option<String>,
-}
-
-impl Default for ReadPolicy {
-    fn default() -> Self {
-        Self {
-            require_auth: false,
-            allowed_pubkeys: None,
-            wot: None,
-            paywall: None,
-        }
-    }
+    /// Event kinds (e.g. DMs: 4, 1059) that are only returned to an
+    /// authenticated client that is the author or is `p`-tagged in the event.
+    pub protected_kinds: Option<Vec<u64>>,
 }
 
 /// Controls which events are accepted based on their content.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct EventPolicy {
     /// If set, only these event kinds are accepted.
     pub allowed_kinds: Option<Vec<u64>>,
@@ -186,25 +998,107 @@ pub struct EventPolicy {
     pub min_pow: Option<u8>,
     /// Maximum `content` field length in bytes.
     pub max_content_length: Option<usize>,
+    /// Events whose content contains any of these words (case-insensitive)
+    /// are rejected as spam.
+    pub blocked_words: Option<Vec<String>>,
+    /// Events whose content matches any of these regular expressions are
+    /// rejected as spam.
+    pub blocked_content_patterns: Option<Vec<String>>,
+    /// Largest share (0.0–1.0) of emoji among the non-whitespace characters
+    /// of the content. Content under 10 characters is exempt.
+    pub max_emoji_ratio: Option<f32>,
+    /// If set, content whose letters are mostly in other scripts is rejected.
+    /// Known scripts: latin, cyrillic, greek, arabic, hebrew, devanagari,
+    /// thai, han, hiragana, katakana, hangul.
+    pub allowed_scripts: Option<Vec<String>>,
+    /// Limits for specific kinds, replacing the relay-wide ones. The first
+    /// entry listing an event's kind applies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kind_limits: Vec<KindLimits>,
+}
+
+/// Per-kind overrides of [`EventPolicy`] limits. Unset fields keep the
+/// relay-wide setting; 0 lifts it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KindLimits {
+    pub kinds: Vec<u64>,
+    /// Maximum `content` length in bytes.
+    pub max_content_length: Option<usize>,
+    /// Maximum number of tags (overrides `nip11.max_event_tags`).
+    pub max_tags: Option<u64>,
+    /// Minimum proof-of-work difficulty bits (NIP-13).
+    pub min_pow: Option<u8>,
 }
 
-impl Default for EventPolicy {
+/// Network-level access control, enforced by the gateway for every relay and
+/// blossom subdomain. Entries are IPs, CIDR ranges, AS numbers ("AS13335")
+/// or ISO country codes ("DE").
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    /// If non-empty, only clients matching an entry are served.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// Clients matching an entry are refused.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub block: Vec<String>,
+    /// MaxMind GeoLite2/GeoIP2 Country database, needed for country entries.
+    pub country_db: Option<String>,
+    /// MaxMind GeoLite2/GeoIP2 ASN database, needed for AS entries.
+    pub asn_db: Option<String>,
+}
+
+/// Gateway-wide rate limiting: one budget per client IP shared by every
+/// relay and blossom subdomain. The admin domain is exempt. Both limits are
+/// off unless set. IPs are refused outright with `[network] block`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayRateLimitConfig {
+    /// Open WebSocket connections across all relays.
+    pub max_connections: Option<u32>,
+    /// HTTP requests (including WebSocket upgrades) across all subdomains.
+    pub requests_per_minute: Option<u32>,
+    /// How often idle IPs are dropped from the gateway and relay trackers.
+    #[serde(default = "default_tracker_cleanup_interval")]
+    pub cleanup_interval_secs: u64,
+    /// How long an IP without open connections stays tracked, and so keeps
+    /// its rate-limit history.
+    #[serde(default = "default_tracker_idle")]
+    pub idle_secs: u64,
+}
+
+impl Default for GatewayRateLimitConfig {
     fn default() -> Self {
         Self {
-            allowed_kinds: None,
-            blocked_kinds: None,
-            min_pow: None,
-            max_content_length: None,
+            max_connections: None,
+            requests_per_minute: None,
+            cleanup_interval_secs: default_tracker_cleanup_interval(),
+            idle_secs: default_tracker_idle(),
         }
     }
 }
 
+fn default_tracker_cleanup_interval() -> u64 {
+    300
+}
+
+fn default_tracker_idle() -> u64 {
+    600
+}
+
 /// Per-relay rate limiting configuration.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RateLimitConfig {
     pub writes_per_minute: Option<u32>,
     pub reads_per_minute: Option<u32>,
     pub max_connections: Option<u32>,
+    /// Events per minute from a single author, across all their IPs.
+    #[serde(default)]
+    pub pubkey_writes_per_minute: Option<u32>,
+    /// Events per rolling 24 hours from a single author.
+    #[serde(default)]
+    pub pubkey_writes_per_day: Option<u32>,
+    /// Bytes that may be queued for a single slow client before it is
+    /// disconnected (default 16 MiB).
+    pub max_outbound_bytes: Option<usize>,
 }
 
 impl Default for RateLimitConfig {
@@ -213,10 +1107,523 @@ impl Default for RateLimitConfig {
             writes_per_minute: Some(20),
             reads_per_minute: Some(60),
             max_connections: Some(5),
+            pubkey_writes_per_minute: None,
+            pubkey_writes_per_day: None,
+            max_outbound_bytes: None,
+        }
+    }
+}
+
+/// Gateway-wide LMDB settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// Read transactions open longer than this are logged, and scans among
+    /// them stop with what they found so far, since an open reader keeps
+    /// LMDB from reusing freed pages (default: 60; 0 disables).
+    #[serde(default = "default_max_read_secs")]
+    pub max_read_secs: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            max_read_secs: default_max_read_secs(),
+        }
+    }
+}
+
+fn default_max_read_secs() -> u64 {
+    60
+}
+
+// ---------------------------------------------------------------------------
+// Routing
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoutingMode {
+    #[default]
+    Subdomain,
+    Path,
+}
+
+impl RoutingMode {
+    fn is_subdomain(&self) -> bool {
+        *self == RoutingMode::Subdomain
+    }
+}
+
+// ---------------------------------------------------------------------------
+// NIP-05 configuration
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Nip05Entry {
+    /// Hex pubkey the name resolves to.
+    pub pubkey: String,
+    /// Relay hints advertised for the pubkey.
+    #[serde(default)]
+    pub relays: Vec<String>,
+}
+
+// ---------------------------------------------------------------------------
+// Lightning address configuration
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightningAddressConfig {
+    /// Wallet that issues the invoices.
+    pub nwc_string: Option<String>,
+    /// Use this paywall's wallet instead of a separate `nwc_string`.
+    pub paywall: Option<String>,
+    #[serde(default = "default_min_sendable")]
+    pub min_sendable_msats: u64,
+    #[serde(default = "default_max_sendable")]
+    pub max_sendable_msats: u64,
+    /// Text shown to the payer (default: "Payment to name@domain").
+    pub description: Option<String>,
+}
+
+fn default_min_sendable() -> u64 {
+    1_000
+}
+
+fn default_max_sendable() -> u64 {
+    100_000_000
+}
+
+// ---------------------------------------------------------------------------
+// Listeners
+// ---------------------------------------------------------------------------
+
+/// By default the admin UI and API are served on the apex domain. Setting
+/// `subdomain` (or adding an `admin_only` listener) moves both away, and
+/// `path` moves just the dashboard; the apex root then serves a landing page.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AdminConfig {
+    /// Serve the admin UI on `<subdomain>.<domain>` only, e.g. "admin".
+    #[serde(default)]
+    pub subdomain: Option<String>,
+    /// Serve the dashboard at this path of the apex domain, e.g. "/admin".
+    #[serde(default)]
+    pub path: Option<String>,
+    /// HTML file, or directory of static files with an `index.html`, that
+    /// the apex domain serves once the dashboard has moved (default: the
+    /// page managed in the admin UI, or a list of relays and media servers).
+    #[serde(default)]
+    pub landing_page: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListenerConfig {
+    /// "127.0.0.1:8081", "[::1]:8081" or "unix:/run/moar/moar.sock".
+    pub bind: String,
+    /// Serve only the admin UI and API, for any Host header. While such a
+    /// listener exists, the other listeners no longer serve them.
+    #[serde(default)]
+    pub admin_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    Tcp(std::net::SocketAddr),
+    Unix(std::path::PathBuf),
+}
+
+impl std::fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl ListenerConfig {
+    pub fn addr(&self) -> Result<ListenAddr, String> {
+        match self.bind.strip_prefix("unix:") {
+            Some("") => Err("listener unix socket path is empty".to_string()),
+            Some(path) => Ok(ListenAddr::Unix(path.into())),
+            None => self
+                .bind
+                .parse()
+                .map(ListenAddr::Tcp)
+                .map_err(|_| format!("listener bind '{}' is not an IP:port or unix:<path>", self.bind)),
+        }
+    }
+}
+
+impl MoarConfig {
+    /// The HTTP listener's address, or `None` if `bind_address` is invalid.
+    pub fn http_addr(&self) -> Option<std::net::SocketAddr> {
+        let ip: std::net::IpAddr = self.bind_address.parse().ok()?;
+        Some((ip, self.port).into())
+    }
+
+    /// Whether the admin UI and API are served only by `admin_only` listeners.
+    pub fn admin_listener_only(&self) -> bool {
+        self.listeners.iter().any(|l| l.admin_only)
+    }
+
+    /// Whether the apex domain serves the admin API.
+    pub fn admin_on_apex(&self) -> bool {
+        self.admin.subdomain.is_none() && !self.admin_listener_only()
+    }
+
+    /// Where the apex domain serves the dashboard, if it does.
+    pub fn dashboard_path(&self) -> Option<&str> {
+        self.admin_on_apex().then(|| self.admin.path.as_deref().unwrap_or("/"))
+    }
+
+    /// The admin UI's own hostname, if `admin.subdomain` is set and no
+    /// `admin_only` listener takes the admin UI off the public listeners.
+    pub fn admin_host(&self) -> Option<String> {
+        if self.admin_listener_only() {
+            return None;
+        }
+        self.admin.subdomain.as_ref().map(|sub| format!("{}.{}", sub, self.domain))
+    }
+
+    /// `scheme://host` the admin UI is published at: its own subdomain if
+    /// one is set, else the apex domain.
+    pub fn admin_origin(&self) -> String {
+        self.origin(self.admin.subdomain.as_deref())
+    }
+
+    /// `scheme://host` of the domain, or of `subdomain` on it. Local
+    /// development has no proxy in front, so localhost URLs carry the port;
+    /// `public_url` overrides both the scheme and the port.
+    pub fn origin(&self, subdomain: Option<&str>) -> String {
+        let host = match subdomain {
+            Some(sub) => format!("{}.{}", sub, self.domain),
+            None => self.domain.clone(),
+        };
+        if let Some(public) = self.public_url.as_deref().and_then(|u| nostr::Url::parse(u).ok()) {
+            return match public.port() {
+                Some(port) => format!("{}://{}:{}", public.scheme(), host, port),
+                None => format!("{}://{}", public.scheme(), host),
+            };
+        }
+        if self.domain == "localhost" {
+            format!("http://{}:{}", host, self.port)
+        } else {
+            format!("https://{}", host)
+        }
+    }
+
+    /// Public http(s) URL of relay `id`.
+    pub fn relay_url(&self, id: &str, relay: &RelayConfig) -> String {
+        match self.routing {
+            RoutingMode::Subdomain => self.origin(Some(&relay.subdomain)),
+            RoutingMode::Path => format!("{}/relay/{}", self.origin(None), id),
+        }
+    }
+
+    /// Public URL of blossom server `id`, which blob URLs start with.
+    pub fn blossom_url(&self, id: &str, blossom: &BlossomConfig) -> String {
+        match self.routing {
+            RoutingMode::Subdomain => self.origin(Some(&blossom.subdomain)),
+            RoutingMode::Path => format!("{}/blossom/{}", self.origin(None), id),
+        }
+    }
+
+    /// Every TCP address the gateway listens on, with a label for messages.
+    pub fn tcp_listen_addrs(&self) -> Vec<(String, std::net::SocketAddr)> {
+        let mut addrs = Vec::new();
+        if let Some(addr) = self.http_addr() {
+            addrs.push(("HTTP listener".to_string(), addr));
+            if let Some(tls) = &self.tls {
+                addrs.push(("TLS listener".to_string(), (addr.ip(), tls.port).into()));
+            }
+        }
+        for listener in &self.listeners {
+            if let Ok(ListenAddr::Tcp(addr)) = listener.addr() {
+                addrs.push((format!("listener '{}'", listener.bind), addr));
+            }
+        }
+        addrs
+    }
+
+    fn listener_problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.http_addr().is_none() {
+            problems.push(format!("bind_address '{}' is not an IP address", self.bind_address));
+        }
+        let mut sockets = Vec::new();
+        for listener in &self.listeners {
+            match listener.addr() {
+                Ok(ListenAddr::Unix(path)) if sockets.contains(&path) => {
+                    problems.push(format!("unix socket '{}' is used by more than one listener", path.display()));
+                }
+                Ok(ListenAddr::Unix(path)) => sockets.push(path),
+                Ok(ListenAddr::Tcp(_)) => {}
+                Err(e) => problems.push(e),
+            }
+        }
+        // Same port on the same or a wildcard address
+        let addrs = self.tcp_listen_addrs();
+        for (i, (name, addr)) in addrs.iter().enumerate() {
+            for (other, other_addr) in &addrs[..i] {
+                let overlaps = addr.ip() == other_addr.ip() || addr.ip().is_unspecified() || other_addr.ip().is_unspecified();
+                if addr.port() == other_addr.port() && overlaps {
+                    problems.push(format!("{} port {} is already used by the {}", name, addr.port(), other));
+                }
+            }
+        }
+        problems
+    }
+}
+
+// ---------------------------------------------------------------------------
+// TLS configuration
+// ---------------------------------------------------------------------------
+
+/// Either `acme`, or a default `cert_path`/`key_path` and/or per-hostname
+/// `certs` picked by SNI.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// HTTPS listen port; plain HTTP stays on the top-level `port`.
+    #[serde(default = "default_tls_port")]
+    pub port: u16,
+    /// PEM certificate chain used when no entry in `certs` matches.
+    pub cert_path: Option<String>,
+    pub key_path: Option<String>,
+    /// Hostname (e.g. "outbox.relay.example.com") → certificate for it.
+    #[serde(default)]
+    pub certs: HashMap<String, TlsCertConfig>,
+    /// Obtain certificates for the domain and every relay and blossom
+    /// subdomain from an ACME CA (Let's Encrypt by default).
+    pub acme: Option<TlsAcmeConfig>,
+}
+
+fn default_tls_port() -> u16 {
+    443
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsCertConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsAcmeConfig {
+    /// Contact emails given to the CA.
+    #[serde(default)]
+    pub contact: Vec<String>,
+    /// Use the Let's Encrypt staging environment.
+    #[serde(default)]
+    pub staging: bool,
+    /// Where the account key and certificates are cached (default: "<data_dir>/acme").
+    pub cache_dir: Option<String>,
+}
+
+// ---------------------------------------------------------------------------
+// Monitor configuration
+// ---------------------------------------------------------------------------
+
+/// Self-monitoring: every hosted relay is probed through its public URL and
+/// described in a NIP-66 discovery event (kind 30166).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorConfig {
+    /// Relays the events are published to (defaults to `discovery_relays`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub publish_to: Vec<String>,
+    #[serde(default = "default_monitor_interval")]
+    pub interval_minutes: u64,
+}
+
+fn default_monitor_interval() -> u64 {
+    60
+}
+
+impl MoarConfig {
+    /// Where monitor events go: `monitor.publish_to`, or `discovery_relays`
+    /// if that is empty.
+    pub fn monitor_targets(&self) -> Vec<String> {
+        match &self.monitor {
+            Some(monitor) if !monitor.publish_to.is_empty() => monitor.publish_to.clone(),
+            Some(_) => self.discovery_relays.clone(),
+            None => Vec::new(),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Relay list configuration
+// ---------------------------------------------------------------------------
+
+/// Hosted users whose NIP-65 relay list (kind 10002) the gateway keeps
+/// published. Lists must be signed by the users themselves: they submit
+/// them through the admin API, or publish them to a hosted relay.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayListConfig {
+    /// Relays the lists are published to (defaults to `discovery_relays`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub publish_to: Vec<String>,
+    /// How often every list is republished (default: 24).
+    #[serde(default = "default_relay_list_interval")]
+    pub interval_hours: u64,
+    #[serde(default)]
+    pub users: Vec<RelayListUser>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayListUser {
+    /// Hex or npub.
+    pub pubkey: String,
+    /// Relays (keys of `relays`) the user's list must point at, for both
+    /// reading and writing.
+    pub relays: Vec<String>,
+}
+
+fn default_relay_list_interval() -> u64 {
+    24
+}
+
+impl MoarConfig {
+    /// Where relay lists go: `relay_lists.publish_to`, or `discovery_relays`
+    /// if that is empty.
+    pub fn relay_list_targets(&self) -> Vec<String> {
+        match &self.relay_lists {
+            Some(lists) if !lists.publish_to.is_empty() => lists.publish_to.clone(),
+            Some(_) => self.discovery_relays.clone(),
+            None => Vec::new(),
         }
     }
 }
 
+// ---------------------------------------------------------------------------
+// Alerts and webhooks
+// ---------------------------------------------------------------------------
+
+/// When the gateway's periodic health checks raise an alert.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertConfig {
+    /// Alert when disk usage reaches this percentage (default: 90).
+    #[serde(default = "default_disk_full_percent")]
+    pub disk_full_percent: u8,
+    /// Alert when a relay rejects at least this percentage of the events
+    /// submitted in a minute (default: 50)...
+    #[serde(default = "default_rejected_spike_percent")]
+    pub rejected_spike_percent: u8,
+    /// ...and at least this many of them (default: 50).
+    #[serde(default = "default_rejected_spike_min")]
+    pub rejected_spike_min: u64,
+    /// Flag a relay database or blossom storage path in `/healthz` once it
+    /// takes up this many bytes on disk (off if unset).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage_warn_bytes: Option<u64>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            disk_full_percent: default_disk_full_percent(),
+            rejected_spike_percent: default_rejected_spike_percent(),
+            rejected_spike_min: default_rejected_spike_min(),
+            storage_warn_bytes: None,
+        }
+    }
+}
+
+fn default_disk_full_percent() -> u8 {
+    90
+}
+
+fn default_rejected_spike_percent() -> u8 {
+    50
+}
+
+fn default_rejected_spike_min() -> u64 {
+    50
+}
+
+/// An endpoint that operator events are POSTed to as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Events to send: `payment_completed`, `wot_build_failed`,
+    /// `rejected_spike`, `disk_near_full`, `blossom_quota_exceeded`,
+    /// `started`, `map_full`, `nwc_failed`, `paywall_watcher_failed`,
+    /// `access_requested` (all if empty).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<String>,
+    /// Sign each body with HMAC-SHA256, sent as `X-Moar-Signature: sha256=<hex>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    /// Retries of a failed delivery, waiting 5 seconds and doubling after
+    /// each (default: 5).
+    #[serde(default = "default_webhook_retries")]
+    pub max_retries: u32,
+}
+
+fn default_webhook_retries() -> u32 {
+    5
+}
+
+/// Alerts DMed to the admin pubkey, as an alternative to webhooks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DmAlertConfig {
+    /// The instance's secret key (nsec or hex) the DMs are sent from.
+    pub secret_key: String,
+    /// Relays the gift-wrapped DMs are published to; the admin's client
+    /// must read from one of them.
+    pub relays: Vec<String>,
+    /// Events to DM, named as for webhooks (default: `started`, `map_full`,
+    /// `nwc_failed`, `paywall_watcher_failed`).
+    #[serde(default = "default_dm_alert_events")]
+    pub events: Vec<String>,
+}
+
+fn default_dm_alert_events() -> Vec<String> {
+    ["started", "map_full", "nwc_failed", "paywall_watcher_failed"].map(String::from).to_vec()
+}
+
+// ---------------------------------------------------------------------------
+// Backup configuration
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupConfig {
+    #[serde(default = "default_backup_interval")]
+    pub interval_hours: u64,
+    /// Local directory for scheduled backups (defaults to `backup_dir`).
+    pub dir: Option<String>,
+    /// Number of backup runs to keep, locally and in S3.
+    #[serde(default = "default_backup_retain")]
+    pub retain: usize,
+    /// Optional S3-compatible bucket each run is also uploaded to.
+    pub s3: Option<S3Config>,
+}
+
+fn default_backup_interval() -> u64 {
+    24
+}
+
+fn default_backup_retain() -> usize {
+    7
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    /// Service URL, e.g. "https://s3.us-east-1.amazonaws.com" or a MinIO host.
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Prepended to every object key, e.g. "moar/".
+    #[serde(default)]
+    pub prefix: String,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
 // ---------------------------------------------------------------------------
 // Blossom (media server) configuration
 // ---------------------------------------------------------------------------
@@ -226,9 +1633,66 @@ pub struct BlossomConfig {
     pub name: String,
     pub description: Option<String>,
     pub subdomain: String,
+    /// Local directory for metadata, and for blob files unless `s3` is set.
     pub storage_path: String,
     #[serde(default)]
     pub policy: BlossomPolicyConfig,
+    /// Keep blob contents in an S3-compatible bucket instead of on local disk.
+    #[serde(default)]
+    pub s3: Option<S3Config>,
+    /// Keep blob contents in this shared pool (a key of `blob_pools`), so a
+    /// blob uploaded to several servers using the pool is stored once.
+    #[serde(default)]
+    pub pool: Option<String>,
+    /// Periodically delete blobs no event on this instance's relays refers to.
+    #[serde(default)]
+    pub gc: Option<BlobGcConfig>,
+    /// Upload accepted blobs to the other servers on the uploader's kind
+    /// 10063 server list, as found on this instance's relays.
+    #[serde(default)]
+    pub mirror_to_user_servers: bool,
+    /// A relay (a key of `relays`) whose NIP-42 authenticated users may
+    /// upload by presenting their AUTH event instead of signing, while their
+    /// connection is open. Its write allow-list, WoT, paywall and block-list
+    /// then apply to every upload.
+    #[serde(default)]
+    pub auth_relay: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobPoolConfig {
+    /// Local directory for the pool's reference counts, and for blob files
+    /// unless `s3` is set.
+    pub path: String,
+    #[serde(default)]
+    pub s3: Option<S3Config>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobGcConfig {
+    #[serde(default = "default_gc_interval")]
+    pub interval_hours: u64,
+    /// Blobs uploaded more recently than this are never collected, so a
+    /// client has time to publish the event that uses them.
+    #[serde(default = "default_gc_grace")]
+    pub grace_hours: u64,
+}
+
+impl Default for BlobGcConfig {
+    fn default() -> Self {
+        Self {
+            interval_hours: default_gc_interval(),
+            grace_hours: default_gc_grace(),
+        }
+    }
+}
+
+fn default_gc_interval() -> u64 {
+    24
+}
+
+fn default_gc_grace() -> u64 {
+    72
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
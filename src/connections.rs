@@ -0,0 +1,157 @@
+//! Open WebSocket connections of a relay, for the admin API: who is
+//! connected, how much they are doing, and a way to close one. Each
+//! connection registers itself on upgrade and is removed when it closes.
+
+use nostr::PublicKey;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+/// One open connection, updated by its socket task as it goes.
+pub struct Connection {
+    pub id: u64,
+    pub ip: IpAddr,
+    started: Instant,
+    connected_at: u64,
+    authed: Mutex<Option<PublicKey>>,
+    pub subscriptions: AtomicUsize,
+    pub bytes_rx: AtomicU64,
+    pub bytes_tx: AtomicU64,
+    kick: Notify,
+}
+
+impl Connection {
+    pub fn authenticated(&self, pubkey: PublicKey) {
+        *self.authed.lock().unwrap() = Some(pubkey);
+    }
+
+    /// Resolves once the admin asks for the connection to be closed, even
+    /// if they asked before this was awaited.
+    pub async fn kicked(&self) {
+        self.kick.notified().await
+    }
+}
+
+/// A connection as listed by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionSummary {
+    pub id: u64,
+    pub ip: String,
+    /// Hex pubkey the connection authenticated as (NIP-42), if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<String>,
+    pub subscriptions: usize,
+    pub bytes_rx: u64,
+    pub bytes_tx: u64,
+    /// Unix time the connection was opened.
+    pub connected_at: u64,
+    pub connected_secs: u64,
+}
+
+#[derive(Default)]
+pub struct ConnectionRegistry {
+    connections: Mutex<HashMap<u64, Arc<Connection>>>,
+    next_id: AtomicU64,
+}
+
+/// Keeps a connection listed until dropped with its socket task.
+pub struct Registration {
+    registry: Arc<ConnectionRegistry>,
+    pub connection: Arc<Connection>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.connections.lock().unwrap().remove(&self.connection.id);
+    }
+}
+
+impl ConnectionRegistry {
+    pub fn register(self: &Arc<Self>, ip: IpAddr) -> Registration {
+        let connection = Arc::new(Connection {
+            id: self.next_id.fetch_add(1, Relaxed) + 1,
+            ip,
+            started: Instant::now(),
+            connected_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            authed: Mutex::new(None),
+            subscriptions: AtomicUsize::new(0),
+            bytes_rx: AtomicU64::new(0),
+            bytes_tx: AtomicU64::new(0),
+            kick: Notify::new(),
+        });
+        self.connections.lock().unwrap().insert(connection.id, connection.clone());
+        Registration {
+            registry: self.clone(),
+            connection,
+        }
+    }
+
+    /// Open connections, oldest first.
+    pub fn list(&self) -> Vec<ConnectionSummary> {
+        let mut list: Vec<ConnectionSummary> = self
+            .connections
+            .lock()
+            .unwrap()
+            .values()
+            .map(|c| ConnectionSummary {
+                id: c.id,
+                ip: c.ip.to_string(),
+                pubkey: c.authed.lock().unwrap().map(|pk| pk.to_hex()),
+                subscriptions: c.subscriptions.load(Relaxed),
+                bytes_rx: c.bytes_rx.load(Relaxed),
+                bytes_tx: c.bytes_tx.load(Relaxed),
+                connected_at: c.connected_at,
+                connected_secs: c.started.elapsed().as_secs(),
+            })
+            .collect();
+        list.sort_by_key(|c| c.id);
+        list
+    }
+
+    /// Ask connection `id` to close. Returns `false` if it isn't open.
+    pub fn kick(&self, id: u64) -> bool {
+        match self.connections.lock().unwrap().get(&id) {
+            Some(connection) => {
+                connection.kick.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn connections_are_listed_until_dropped_and_can_be_kicked() {
+        let registry = Arc::new(ConnectionRegistry::default());
+        let first = registry.register("203.0.113.7".parse().unwrap());
+        let second = registry.register("198.51.100.2".parse().unwrap());
+        let pubkey = nostr::Keys::generate().public_key();
+        second.connection.authenticated(pubkey);
+        second.connection.subscriptions.store(3, Relaxed);
+        second.connection.bytes_rx.fetch_add(120, Relaxed);
+
+        let list = registry.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!((list[0].ip.as_str(), list[0].pubkey.as_ref()), ("203.0.113.7", None));
+        assert_eq!(list[1].pubkey, Some(pubkey.to_hex()));
+        assert_eq!((list[1].subscriptions, list[1].bytes_rx), (3, 120));
+
+        // A kick sent before anyone waits for it isn't lost
+        assert!(registry.kick(first.connection.id));
+        tokio::time::timeout(std::time::Duration::from_secs(1), first.connection.kicked())
+            .await
+            .unwrap();
+
+        drop(first);
+        assert_eq!(registry.list().len(), 1);
+        assert!(!registry.kick(list[0].id));
+    }
+}
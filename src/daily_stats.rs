@@ -0,0 +1,223 @@
+//! Per-relay daily totals, kept in LMDB so activity history survives
+//! restarts. The stats loop adds what each relay's counters grew by since
+//! its last sample to the current UTC day; authors of saved events and
+//! authenticated readers are remembered for the day so each one is counted
+//! once.
+
+use crate::error::Result;
+use crate::stats::RelayStats;
+use heed::types::*;
+use heed::{Database, Env, EnvOpenOptions};
+use serde::{Deserialize, Serialize};
+use std::ops::Bound::{Excluded, Included};
+use std::path::Path;
+use std::sync::atomic::Ordering::Relaxed;
+
+const DAY_SECS: u64 = 86_400;
+/// Days kept before being pruned.
+pub const RETENTION_DAYS: u64 = 366;
+
+/// One relay's activity over one UTC day.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailyStats {
+    /// Unix timestamp of the day's start.
+    pub day: u64,
+    pub connections: u64,
+    pub events_saved: u64,
+    pub events_rejected: u64,
+    /// Distinct authors of saved events.
+    #[serde(alias = "unique_pubkeys")]
+    pub unique_writers: u64,
+    /// Distinct authenticated pubkeys served a REQ.
+    #[serde(default)]
+    pub unique_readers: u64,
+    pub queries_served: u64,
+    pub bytes_rx: u64,
+    pub bytes_tx: u64,
+}
+
+/// Counter values at a relay's previous flush.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counters {
+    connections: u64,
+    events_saved: u64,
+    events_rejected: u64,
+    queries_served: u64,
+    bytes_rx: u64,
+    bytes_tx: u64,
+}
+
+impl Counters {
+    pub fn read(stats: &RelayStats) -> Self {
+        Self {
+            connections: stats.total_connections.load(Relaxed),
+            events_saved: stats.events_saved.load(Relaxed),
+            events_rejected: stats.events_rejected.load(Relaxed),
+            queries_served: stats.queries_served.load(Relaxed),
+            bytes_rx: stats.bytes_rx.load(Relaxed),
+            bytes_tx: stats.bytes_tx.load(Relaxed),
+        }
+    }
+}
+
+pub struct DailyStatsStore {
+    env: Env,
+    /// "relay:day" (day zero-padded, so keys sort by date) → DailyStats JSON
+    days: Database<Str, Bytes>,
+    /// "relay:day:pubkey" (writers) and "relay:day:r:pubkey" (readers) →
+    /// unit, for the current day only
+    authors: Database<Str, Unit>,
+}
+
+fn day_key(relay_id: &str, day: u64) -> String {
+    format!("{}:{:020}", relay_id, day)
+}
+
+impl DailyStatsStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        std::fs::create_dir_all(&path)?;
+        let mut options = EnvOpenOptions::new();
+        options.max_dbs(2);
+        options.map_size(256 * 1024 * 1024);
+        let env = unsafe { options.open(path)? };
+        let mut wtxn = env.write_txn()?;
+        let days = env.create_database(&mut wtxn, Some("days"))?;
+        let authors = env.create_database(&mut wtxn, Some("authors"))?;
+        wtxn.commit()?;
+        Ok(Self { env, days, authors })
+    }
+
+    /// Add what `stats` grew by since `previous` to the day containing
+    /// `now`, with the writers and readers seen since, and prune old days.
+    /// Returns the
+    /// counters to pass next time.
+    pub fn flush(&self, relay_id: &str, stats: &RelayStats, previous: Counters, now: u64) -> Result<Counters> {
+        let current = Counters::read(stats);
+        let writers = stats.take_authors();
+        let readers = stats.take_readers();
+        let day = now - now % DAY_SECS;
+        let key = day_key(relay_id, day);
+
+        let mut wtxn = self.env.write_txn()?;
+        let mut entry = match self.days.get(&wtxn, &key)? {
+            Some(raw) => serde_json::from_slice(raw)?,
+            None => DailyStats {
+                day,
+                ..Default::default()
+            },
+        };
+        // Counters restart from zero with the process
+        let grew = |now: u64, before: u64| now.saturating_sub(before);
+        entry.connections += grew(current.connections, previous.connections);
+        entry.events_saved += grew(current.events_saved, previous.events_saved);
+        entry.events_rejected += grew(current.events_rejected, previous.events_rejected);
+        entry.queries_served += grew(current.queries_served, previous.queries_served);
+        entry.bytes_rx += grew(current.bytes_rx, previous.bytes_rx);
+        entry.bytes_tx += grew(current.bytes_tx, previous.bytes_tx);
+        for writer in writers {
+            let writer_key = format!("{}:{}", key, writer.to_hex());
+            if self.authors.get(&wtxn, &writer_key)?.is_none() {
+                self.authors.put(&mut wtxn, &writer_key, &())?;
+                entry.unique_writers += 1;
+            }
+        }
+        for reader in readers {
+            let reader_key = format!("{}:r:{}", key, reader.to_hex());
+            if self.authors.get(&wtxn, &reader_key)?.is_none() {
+                self.authors.put(&mut wtxn, &reader_key, &())?;
+                entry.unique_readers += 1;
+            }
+        }
+        self.days.put(&mut wtxn, &key, &serde_json::to_vec(&entry)?)?;
+
+        // Earlier days' authors are no longer needed, nor days past retention
+        let first = day_key(relay_id, 0);
+        self.authors.delete_range(&mut wtxn, &(Included(first.as_str()), Excluded(key.as_str())))?;
+        let oldest = day_key(relay_id, day.saturating_sub(RETENTION_DAYS * DAY_SECS));
+        self.days.delete_range(&mut wtxn, &(Included(first.as_str()), Excluded(oldest.as_str())))?;
+        wtxn.commit()?;
+        Ok(current)
+    }
+
+    /// A relay's recorded days from the last `days` days up to `now`,
+    /// oldest first. Days without activity are left out.
+    pub fn history(&self, relay_id: &str, days: u64, now: u64) -> Result<Vec<DailyStats>> {
+        let today = now - now % DAY_SECS;
+        let from = day_key(relay_id, today.saturating_sub(days.saturating_sub(1) * DAY_SECS));
+        let to = day_key(relay_id, today);
+        let rtxn = self.env.read_txn()?;
+        let mut history = Vec::new();
+        for result in self.days.range(&rtxn, &(Included(from.as_str()), Included(to.as_str())))? {
+            let (_, raw) = result?;
+            history.push(serde_json::from_slice(raw)?);
+        }
+        Ok(history)
+    }
+
+    /// Forget a relay's history, when it is deleted.
+    pub fn remove(&self, relay_id: &str) -> Result<()> {
+        let first = day_key(relay_id, 0);
+        let last = day_key(relay_id, u64::MAX);
+        let mut wtxn = self.env.write_txn()?;
+        self.days.delete_range(&mut wtxn, &(Included(first.as_str()), Included(last.as_str())))?;
+        // Author keys extend past the day key, so bound them by the separator
+        let end = format!("{};", relay_id);
+        self.authors.delete_range(&mut wtxn, &(Included(first.as_str()), Excluded(end.as_str())))?;
+        wtxn.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::Keys;
+
+    #[test]
+    fn days_accumulate_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let day = 20_000 * DAY_SECS;
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+
+        {
+            let store = DailyStatsStore::open(dir.path()).unwrap();
+            let stats = RelayStats::new();
+            stats.event_saved(&alice);
+            stats.event_saved(&bob);
+            stats.event_saved(&bob);
+            let counters = store.flush("public", &stats, Counters::default(), day + 100).unwrap();
+            stats.event_saved(&alice);
+            stats.reader_served(&bob);
+            store.flush("public", &stats, counters, day + 200).unwrap();
+        }
+
+        // A restarted process starts its counters from zero
+        let store = DailyStatsStore::open(dir.path()).unwrap();
+        let stats = RelayStats::new();
+        stats.events_saved.fetch_add(2, Relaxed);
+        stats.queries_served.fetch_add(5, Relaxed);
+        stats.reader_served(&bob);
+        stats.reader_served(&alice);
+        let counters = store.flush("public", &stats, Counters::default(), day + 300).unwrap();
+        stats.events_saved.fetch_add(1, Relaxed);
+        store.flush("public", &stats, counters, day + DAY_SECS + 10).unwrap();
+
+        let history = store.history("public", 30, day + DAY_SECS + 10).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].day, day);
+        assert_eq!((history[0].events_saved, history[0].unique_writers, history[0].queries_served), (6, 2, 5));
+        assert_eq!(history[0].unique_readers, 2);
+        assert_eq!(history[1].unique_readers, 0);
+        assert_eq!(history[1].events_saved, 1);
+        assert_eq!(store.history("public", 1, day + DAY_SECS + 10).unwrap().len(), 1);
+        assert!(store.history("other", 30, day + DAY_SECS).unwrap().is_empty());
+
+        // Days written before readers were tracked still read
+        let old: DailyStats = serde_json::from_str(r#"{"day":0,"connections":1,"events_saved":1,"events_rejected":0,"unique_pubkeys":3,"queries_served":0,"bytes_rx":0,"bytes_tx":0}"#).unwrap();
+        assert_eq!((old.unique_writers, old.unique_readers), (3, 0));
+
+        store.remove("public").unwrap();
+        assert!(store.history("public", 30, day + DAY_SECS).unwrap().is_empty());
+    }
+}
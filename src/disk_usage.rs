@@ -0,0 +1,137 @@
+//! On-disk size of every relay's LMDB directory and every blossom server's
+//! storage path, measured in the background since walking a large blob
+//! directory takes a while. A store over `alerts.storage_warn_bytes` is
+//! flagged, and the flag shows up in `/healthz`.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often the sizes are measured.
+const MEASURE_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DiskReport {
+    /// Bytes on disk per relay id.
+    pub relays: BTreeMap<String, u64>,
+    /// Bytes on disk per blossom id.
+    pub blossoms: BTreeMap<String, u64>,
+    /// Stores over the warning threshold, as `relay:<id>` or `blossom:<id>`.
+    pub over_threshold: Vec<String>,
+    pub warn_bytes: Option<u64>,
+    /// Unix time of the measurement; 0 until the first one finishes.
+    pub measured_at: u64,
+}
+
+impl DiskReport {
+    pub fn warning(&self) -> bool {
+        !self.over_threshold.is_empty()
+    }
+}
+
+pub struct DiskUsage {
+    relays: Vec<(String, PathBuf)>,
+    blossoms: Vec<(String, PathBuf)>,
+    warn_bytes: Option<u64>,
+    report: RwLock<DiskReport>,
+}
+
+impl DiskUsage {
+    pub fn new(relays: Vec<(String, PathBuf)>, blossoms: Vec<(String, PathBuf)>, warn_bytes: Option<u64>) -> Self {
+        Self {
+            relays,
+            blossoms,
+            warn_bytes,
+            report: RwLock::new(DiskReport { warn_bytes, ..Default::default() }),
+        }
+    }
+
+    /// Spawn the measuring loop. The first measurement runs right away.
+    pub fn start(self: std::sync::Arc<Self>) {
+        tokio::spawn(async move {
+            loop {
+                let usage = self.clone();
+                match tokio::task::spawn_blocking(move || usage.measure()).await {
+                    Ok(report) => *self.report.write().unwrap() = report,
+                    Err(e) => tracing::warn!("Failed to measure disk usage: {}", e),
+                }
+                tokio::time::sleep(MEASURE_INTERVAL).await;
+            }
+        });
+    }
+
+    /// The latest measurement.
+    pub fn report(&self) -> DiskReport {
+        self.report.read().unwrap().clone()
+    }
+
+    fn measure(&self) -> DiskReport {
+        let mut report = DiskReport {
+            warn_bytes: self.warn_bytes,
+            measured_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            ..Default::default()
+        };
+        for (kind, stores, sizes) in [
+            ("relay", &self.relays, &mut report.relays),
+            ("blossom", &self.blossoms, &mut report.blossoms),
+        ] {
+            for (id, path) in stores {
+                let bytes = dir_size(path);
+                if self.warn_bytes.is_some_and(|warn| bytes >= warn) {
+                    report.over_threshold.push(format!("{}:{}", kind, id));
+                }
+                sizes.insert(id.clone(), bytes);
+            }
+        }
+        if report.warning() {
+            tracing::warn!("Storage over the warning threshold: {}", report.over_threshold.join(", "));
+        }
+        report
+    }
+}
+
+/// Total size of the files under `path`, not following symlinks. Missing
+/// or unreadable entries count as empty.
+pub fn dir_size(path: &Path) -> u64 {
+    let Ok(meta) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries.flatten().map(|entry| dir_size(&entry.path())).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_over_the_threshold_are_flagged() {
+        let dir = tempfile::tempdir().unwrap();
+        let relay = dir.path().join("relay");
+        let blobs = dir.path().join("blobs");
+        std::fs::create_dir_all(blobs.join("ab")).unwrap();
+        std::fs::create_dir_all(&relay).unwrap();
+        std::fs::write(relay.join("data.mdb"), vec![0u8; 4096]).unwrap();
+        std::fs::write(blobs.join("ab").join("blob"), vec![0u8; 100]).unwrap();
+        std::fs::write(blobs.join("meta"), vec![0u8; 20]).unwrap();
+
+        let usage = DiskUsage::new(
+            vec![("outbox".into(), relay)],
+            vec![("media".into(), blobs), ("gone".into(), dir.path().join("missing"))],
+            Some(1000),
+        );
+        assert!(!usage.report().warning());
+        let report = usage.measure();
+        assert_eq!(report.relays["outbox"], 4096);
+        assert_eq!(report.blossoms["media"], 120);
+        assert_eq!(report.blossoms["gone"], 0);
+        assert_eq!(report.over_threshold, vec!["relay:outbox".to_string()]);
+    }
+}
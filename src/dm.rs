@@ -0,0 +1,98 @@
+use futures_util::{SinkExt, StreamExt};
+use nostr::{Event, EventBuilder, JsonUtil, Keys, PublicKey};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Send a NIP-04 encrypted direct message from `keys` to `recipient`,
+/// publishing it to every relay in `relays`.
+///
+/// Returns the number of relays that accepted the event. Fails only if the
+/// event could not be built or no relay accepted it.
+pub async fn send_dm(
+    keys: &Keys,
+    recipient: PublicKey,
+    content: &str,
+    relays: &[String],
+) -> Result<usize, anyhow::Error> {
+    // NIP-04 is deprecated in favour of NIP-17, but kind 4 DMs are still the
+    // most widely supported by clients.
+    #[allow(deprecated)]
+    let event = EventBuilder::encrypted_direct_msg(keys, recipient, content, None)
+        .and_then(|b| b.to_event(keys))
+        .map_err(|e| anyhow::anyhow!("Failed to build DM event: {}", e))?;
+
+    publish_dm(&event, relays).await
+}
+
+/// Send a NIP-17 private direct message (a gift-wrapped kind 14) from
+/// `keys` to `recipient`, publishing it to every relay in `relays`.
+///
+/// Returns the number of relays that accepted the event, like [`send_dm`].
+pub async fn send_private_dm(
+    keys: &Keys,
+    recipient: PublicKey,
+    content: &str,
+    relays: &[String],
+) -> Result<usize, anyhow::Error> {
+    let rumor = EventBuilder::private_msg_rumor(recipient, content, None).to_unsigned_event(keys.public_key());
+    let event = EventBuilder::gift_wrap(keys, &recipient, rumor, None)
+        .map_err(|e| anyhow::anyhow!("Failed to build DM event: {}", e))?;
+    publish_dm(&event, relays).await
+}
+
+async fn publish_dm(event: &Event, relays: &[String]) -> Result<usize, anyhow::Error> {
+    let msg = format!(r#"["EVENT",{}]"#, event.as_json());
+    let event_id = event.id.to_hex();
+
+    let mut accepted = 0;
+    for relay in relays {
+        match publish(relay, &msg, &event_id).await {
+            Ok(()) => accepted += 1,
+            Err(e) => tracing::warn!(relay = %relay, "Failed to publish DM: {}", e),
+        }
+    }
+
+    if accepted == 0 {
+        anyhow::bail!("No relay accepted the DM");
+    }
+    Ok(accepted)
+}
+
+/// Send one `["EVENT", ...]` message to `relay` and wait for its `OK`.
+pub(crate) async fn publish(relay: &str, msg: &str, event_id: &str) -> Result<(), anyhow::Error> {
+    let (mut ws, _) = tokio::time::timeout(
+        Duration::from_secs(10),
+        tokio_tungstenite::connect_async(relay),
+    )
+    .await
+    .map_err(|_| anyhow::anyhow!("Connection timeout"))??;
+
+    ws.send(Message::Text(msg.to_string().into())).await?;
+
+    let result = tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(msg) = ws.next().await {
+            let text = match msg? {
+                Message::Text(t) => t.to_string(),
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let parsed: serde_json::Value = match serde_json::from_str(&text) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if parsed[0] == "OK" && parsed[1] == event_id {
+                if parsed[2].as_bool() == Some(true) {
+                    return Ok(());
+                }
+                let reason = parsed[3].as_str().unwrap_or("rejected").to_string();
+                return Err(anyhow::anyhow!(reason));
+            }
+        }
+        Err(anyhow::anyhow!("Connection closed before OK"))
+    })
+    .await
+    .map_err(|_| anyhow::anyhow!("Timed out waiting for OK"))?;
+
+    let _ = ws.close(None).await;
+    result
+}
@@ -10,8 +10,30 @@ pub enum Error {
     Json(#[from] serde_json::Error),
     #[error("TOML serialization error: {0}")]
     Toml(#[from] toml::ser::Error),
+    #[error("Storage task failed: {0}")]
+    StorageTask(#[from] tokio::task::JoinError),
+    #[error("Storage writer thread stopped")]
+    WriterStopped,
+    #[error("Database has been closed")]
+    Closed,
+    #[error("Database {0} is in use by another process")]
+    InUse(String),
+    #[error("Corrupt stored event: {0}")]
+    CorruptEvent(String),
+    #[error("Object storage error: {0}")]
+    ObjectStorage(String),
+    #[error("TLS error: {0}")]
+    Tls(String),
     #[error("Nostr error: {0}")]
     Nostr(#[from] nostr::types::url::ParseError), // approximate placeholder
 }
 
+impl Error {
+    /// Whether an LMDB environment has run out of map space, so nothing more
+    /// can be written to it.
+    pub fn is_map_full(&self) -> bool {
+        matches!(self, Error::Heed(heed::Error::Mdb(heed::MdbError::MapFull)))
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,157 @@
+//! Live feed of a relay's write decisions for operators: every accepted
+//! event, and every rejected one with the reason it was turned away.
+//! Nothing is buffered or cloned while nobody is watching, except a short
+//! log of the latest rejections, kept so a misconfigured policy or a spam
+//! wave can be spotted after the fact.
+
+use nostr::Event;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+use crate::plugin::WriteSource;
+
+/// How many entries a slow watcher may fall behind before it misses some.
+const FIREHOSE_CAPACITY: usize = 1024;
+/// Rejections kept in the log; older ones are dropped.
+const REJECTION_LOG_CAPACITY: usize = 500;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FirehoseEntry {
+    pub accepted: bool,
+    /// The `OK` message sent to the client, for rejected events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Client IP, or the upstream relay an event was synced from.
+    pub source: String,
+    pub event: Event,
+}
+
+/// A rejected event, as kept in the rejection log.
+#[derive(Debug, Clone, Serialize)]
+pub struct Rejection {
+    /// Unix time of the rejection.
+    pub at: u64,
+    pub id: String,
+    pub pubkey: String,
+    pub kind: u16,
+    /// The `OK` message sent to the client.
+    pub reason: String,
+    pub source: String,
+}
+
+pub struct Firehose {
+    tx: broadcast::Sender<Arc<FirehoseEntry>>,
+    rejections: Mutex<VecDeque<Rejection>>,
+}
+
+impl Default for Firehose {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Firehose {
+    pub fn new() -> Self {
+        let (tx, _rx) = broadcast::channel(FIREHOSE_CAPACITY);
+        Self {
+            tx,
+            rejections: Mutex::new(VecDeque::with_capacity(REJECTION_LOG_CAPACITY)),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<FirehoseEntry>> {
+        self.tx.subscribe()
+    }
+
+    pub fn accepted(&self, event: &Event, source: &WriteSource) {
+        self.publish(event, source, None);
+    }
+
+    pub fn rejected(&self, event: &Event, source: &WriteSource, reason: &str) {
+        let mut log = self.rejections.lock().unwrap();
+        if log.len() == REJECTION_LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(Rejection {
+            at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            id: event.id.to_hex(),
+            pubkey: event.pubkey.to_hex(),
+            kind: event.kind.as_u16(),
+            reason: reason.to_string(),
+            source: source_name(source),
+        });
+        drop(log);
+        self.publish(event, source, Some(reason));
+    }
+
+    /// Up to `limit` of the latest rejections, newest first.
+    pub fn rejections(&self, limit: usize) -> Vec<Rejection> {
+        self.rejections.lock().unwrap().iter().rev().take(limit).cloned().collect()
+    }
+
+    fn publish(&self, event: &Event, source: &WriteSource, reason: Option<&str>) {
+        if self.tx.receiver_count() == 0 {
+            return;
+        }
+        let source = source_name(source);
+        let _ = self.tx.send(Arc::new(FirehoseEntry {
+            accepted: reason.is_none(),
+            reason: reason.map(str::to_string),
+            source,
+            event: event.clone(),
+        }));
+    }
+}
+
+fn source_name(source: &WriteSource) -> String {
+    match source {
+        WriteSource::Ip(ip) => ip.to_string(),
+        WriteSource::Stream(url) => url.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys};
+
+    #[test]
+    fn delivers_decisions_to_watchers() {
+        let firehose = Firehose::new();
+        let event = EventBuilder::text_note("hi", []).to_event(&Keys::generate()).unwrap();
+        let source = WriteSource::Ip("203.0.113.7".parse().unwrap());
+
+        // No watchers: dropped silently
+        firehose.accepted(&event, &source);
+
+        let mut rx = firehose.subscribe();
+        firehose.rejected(&event, &source, "blocked: kind not allowed");
+        let entry = rx.try_recv().unwrap();
+        assert!(!entry.accepted);
+        assert_eq!(entry.reason.as_deref(), Some("blocked: kind not allowed"));
+        assert_eq!(entry.source, "203.0.113.7");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn keeps_the_latest_rejections_without_watchers() {
+        let firehose = Firehose::new();
+        let keys = Keys::generate();
+        let source = WriteSource::Stream("wss://upstream.example".into());
+        for i in 0..REJECTION_LOG_CAPACITY + 5 {
+            let event = EventBuilder::text_note(format!("spam {}", i), []).to_event(&keys).unwrap();
+            firehose.rejected(&event, &source, &format!("blocked: {}", i));
+        }
+        firehose.accepted(&EventBuilder::text_note("fine", []).to_event(&keys).unwrap(), &source);
+
+        let latest = firehose.rejections(2);
+        assert_eq!(latest.len(), 2);
+        assert_eq!(latest[0].reason, format!("blocked: {}", REJECTION_LOG_CAPACITY + 4));
+        assert_eq!((latest[0].kind, latest[0].source.as_str()), (1, "wss://upstream.example"));
+        assert_eq!(latest[0].pubkey, keys.public_key().to_hex());
+        assert_eq!(firehose.rejections(usize::MAX).len(), REJECTION_LOG_CAPACITY);
+    }
+}
@@ -1,24 +1,45 @@
-use crate::auth::verify_auth_event;
+use crate::auth::{verify_auth_event, verify_nip98_header, AuthGrants, ReplayGuard};
+use crate::backup::{BackupManager, BackupStatus};
 use crate::blossom::handlers::{self as blossom_handlers, BlossomState};
-use crate::blossom::store::BlobStore;
-use crate::config::{BlossomConfig, MoarConfig, PaywallConfig, RelayConfig, WotConfig};
+use crate::blossom::pool::{BlobPool, PoolUsage};
+use crate::blossom::store::{BlobCursor, BlobQuery, BlobSort, BlobStore};
+use crate::config::{
+    BlobPoolConfig, BlossomConfig, ListenAddr, MaintenanceMode, MoarConfig, Nip05Entry, PaywallCashuConfig, PaywallConfig,
+    PaywallReminderConfig, PaywallZapConfig, RelayConfig, RoutingMode, WotConfig, WotMutes, WotOutboxConfig,
+};
+use crate::firehose::Firehose;
+use crate::invite::InviteManager;
+use crate::query_cache::QueryCache;
+use crate::relay_list::RelayListService;
+use crate::network::NetworkPolicy;
 use crate::paywall::PaywallManager;
-use crate::policy::PolicyEngine;
+use crate::policy::{PolicyEngine, ShadowBans};
 use crate::server::{self, RelayState};
+use crate::template::RelayTemplate;
+use crate::session::{SessionInfo, SessionStore};
 use crate::stats::{RelayStats, SharedSystemStats, TimeSeriesRing};
-use crate::storage::NostrStore;
+use crate::daily_stats::DailyStatsStore;
+use crate::disk_usage::DiskUsage;
+use crate::storage::browse::{self, EventCursor};
+use crate::storage::{BoxFuture, NostrStore};
+use crate::webhook::Notifier;
 use crate::wot::WotManager;
+use arc_swap::ArcSwap;
 use axum::{
     body::Body,
-    extract::{FromRequest, Host, Path, Query, Request, State},
+    extract::{ConnectInfo, FromRequest, Host, Path, Query, Request, State},
     http::{header, StatusCode, Uri},
-    response::{Html, IntoResponse, Response},
-    routing::{delete as delete_route, get, post},
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        Html, IntoResponse, Response,
+    },
+    routing::{delete as delete_route, get, post, put},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -34,62 +55,144 @@ pub struct GatewayState {
     pub relay_stores: HashMap<String, Arc<dyn NostrStore>>,
     pub blossom_routers: HashMap<String, Router>,
     pub blossom_stores: HashMap<String, Arc<BlobStore>>,
+    /// Shared blob pools, keyed by pool id.
+    pub blob_pools: HashMap<String, Arc<BlobPool>>,
     pub config: Arc<RwLock<MoarConfig>>,
     pub config_path: PathBuf,
     pub pages_dir: PathBuf,
     pub pending_restart: Arc<RwLock<bool>>,
-    pub sessions: Arc<RwLock<HashMap<String, SessionInfo>>>,
+    pub sessions: Arc<SessionStore>,
+    /// Auth events already used anywhere in the gateway.
+    pub auth_replay: Arc<ReplayGuard>,
+    /// Relay AUTH events that paired blossom servers accept for uploads.
+    pub auth_grants: Arc<AuthGrants>,
     pub wot_manager: Arc<WotManager>,
     pub paywall_manager: Arc<PaywallManager>,
+    pub invites: Arc<InviteManager>,
+    /// Signing keys of relays with `signing_key` on, keyed by relay id.
+    pub relay_keys: HashMap<String, nostr::Keys>,
+    /// Sends operator events to the configured webhooks.
+    pub notifier: Arc<Notifier>,
     pub relay_stats: HashMap<String, Arc<RelayStats>>,
     pub time_series: HashMap<String, Arc<RwLock<TimeSeriesRing>>>,
     pub system_stats: SharedSystemStats,
+    /// Per-relay daily totals, kept across restarts.
+    pub daily_stats: Option<Arc<DailyStatsStore>>,
     pub start_time: u64,
+    /// Outstanding purge confirmations: relay id → (token, expires at).
+    pub purge_tokens: Arc<RwLock<HashMap<String, (String, u64)>>>,
+    /// Scheduled backups, if a `[backup]` section is configured.
+    pub backup: Option<Arc<BackupManager>>,
+    /// Per-relay IP trackers, keyed by relay id.
+    pub relay_ip_trackers: HashMap<String, Arc<crate::rate_limit::IpTracker>>,
+    /// Per-relay write decision feeds, keyed by relay id.
+    pub firehoses: HashMap<String, Arc<Firehose>>,
+    /// Per-relay REQ result caches, for relays that have one.
+    pub query_caches: HashMap<String, Arc<QueryCache>>,
+    /// Per-relay open WebSocket connections, keyed by relay id.
+    pub relay_connections: HashMap<String, Arc<crate::connections::ConnectionRegistry>>,
+    /// Running relays, keyed by relay id.
+    pub relays: HashMap<String, Arc<RelayState>>,
+    /// Per-relay shadow-ban lists, shared with the relays' policies.
+    pub relay_shadow_bans: HashMap<String, ShadowBans>,
+    /// Gateway-wide per-IP limits and bans, across all subdomains.
+    pub ip_tracker: Arc<crate::rate_limit::IpTracker>,
+    /// IP/ASN/country allow and block lists for relay and blossom subdomains.
+    pub network: Arc<NetworkPolicy>,
+    /// Whether the apex domain serves the admin API.
+    pub admin_on_apex: bool,
+    /// Where the apex domain serves the dashboard; its root shows the
+    /// landing page otherwise.
+    pub dashboard_path: Option<String>,
+    /// The admin UI's dedicated hostname, if `admin.subdomain` is set and
+    /// there is no `admin_only` listener.
+    pub admin_host: Option<String>,
+    /// Subdomain of the `default_relay`, which the apex root also serves.
+    pub default_relay: Option<String>,
+    /// With path routing, the subdomain of the relay or blossom server
+    /// mounted at `/relay/<id>` or `/blossom/<id>`, keyed by id.
+    pub relay_paths: HashMap<String, String>,
+    pub blossom_paths: HashMap<String, String>,
+    /// NIP-65 relay lists kept published, if `relay_lists` is configured.
+    pub relay_lists: Option<Arc<RelayListService>>,
+    /// On-disk size of every relay and blossom store.
+    pub disk_usage: Arc<DiskUsage>,
+    /// Subdomains of relays deleted with their database, no longer routed
+    /// even though their routers live until restart.
+    pub purged_relays: Arc<std::sync::RwLock<HashSet<String>>>,
 }
 
-#[derive(Clone, Debug)]
-pub struct SessionInfo {
-    pub pubkey: String,
-    pub created_at: u64,
-}
-
-impl SessionInfo {
-    fn is_expired(&self) -> bool {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        now - self.created_at > 24 * 60 * 60
+impl GatewayState {
+    /// The router of the relay on `subdomain`, unless it has been purged.
+    fn relay_router(&self, subdomain: &str) -> Option<&Router> {
+        if self.purged_relays.read().unwrap().contains(subdomain) {
+            return None;
+        }
+        self.relay_routers.get(subdomain)
     }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub async fn start_gateway(
     port: u16,
     domain: String,
     relays: HashMap<String, (RelayConfig, Arc<dyn NostrStore>, Arc<PolicyEngine>, Arc<RelayStats>, Arc<RwLock<TimeSeriesRing>>)>,
     blossoms: HashMap<String, (BlossomConfig, Arc<BlobStore>)>,
+    blob_pools: HashMap<String, Arc<BlobPool>>,
     config: MoarConfig,
     config_path: PathBuf,
     wot_manager: Arc<WotManager>,
     paywall_manager: Arc<PaywallManager>,
+    invites: Arc<InviteManager>,
+    notifier: Arc<Notifier>,
 ) -> crate::error::Result<()> {
     let pages_dir = PathBuf::from(&config.pages_dir);
     // Ensure the pages directory exists
     let _ = tokio::fs::create_dir_all(&pages_dir).await;
 
+    let trusted_proxies = crate::rate_limit::TrustedProxies::parse(&config.trusted_proxies).unwrap_or_else(|e| {
+        tracing::error!("Ignoring trusted_proxies: {}", e);
+        crate::rate_limit::TrustedProxies::default()
+    });
+
+    let network = Arc::new(NetworkPolicy::new(&config.network).map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("network policy: {}", e))
+    })?);
+
+    let auth_replay = Arc::new(ReplayGuard::new());
+    let auth_grants = Arc::new(AuthGrants::new());
+    // Relays some blossom server accepts the AUTH of
+    let auth_relays: std::collections::HashSet<&String> =
+        blossoms.values().filter_map(|(b, _)| b.auth_relay.as_ref()).collect();
+    let mut policy_map: HashMap<String, Arc<ArcSwap<PolicyEngine>>> = HashMap::new();
+    let mut shadow_ban_map: HashMap<String, ShadowBans> = HashMap::new();
+    let mut relay_state_map: HashMap<String, Arc<RelayState>> = HashMap::new();
+    let gateway_tracker = Arc::new(crate::rate_limit::IpTracker::with_trusted_proxies(trusted_proxies.clone()));
+    let cleanup_interval = std::time::Duration::from_secs(config.rate_limit.cleanup_interval_secs);
+    let cleanup_idle = std::time::Duration::from_secs(config.rate_limit.idle_secs);
+    gateway_tracker.start_cleanup(cleanup_interval, cleanup_idle);
+
     let mut router_map = HashMap::new();
     let mut config_map = HashMap::new();
     let mut store_map: HashMap<String, Arc<dyn NostrStore>> = HashMap::new();
     let mut stats_map: HashMap<String, Arc<RelayStats>> = HashMap::new();
+    let mut tracker_map: HashMap<String, Arc<crate::rate_limit::IpTracker>> = HashMap::new();
+    let mut relay_keys: HashMap<String, nostr::Keys> = HashMap::new();
+    let mut firehose_map: HashMap<String, Arc<Firehose>> = HashMap::new();
+    let mut query_cache_map: HashMap<String, Arc<QueryCache>> = HashMap::new();
+    let mut connections_map = HashMap::new();
     let mut ts_map: HashMap<String, Arc<RwLock<TimeSeriesRing>>> = HashMap::new();
     let mut bg_relay_data = Vec::new();
+    let mut relay_states = Vec::new();
+    let mut default_relay = None;
+    let mut relay_paths = HashMap::new();
+    let mut blossom_paths = HashMap::new();
 
     for (key, (relay_config, store, policy, stats, ts_ring)) in relays {
-        let scheme = if domain == "localhost" { "http" } else { "https" };
-        let relay_url = format!(
-            "{}://{}.{}",
-            scheme, relay_config.subdomain, domain
-        );
+        let relay_url = config.relay_url(&key, &relay_config);
+        if config.routing == RoutingMode::Path {
+            relay_paths.insert(key.clone(), relay_config.subdomain.clone());
+        }
         store_map.insert(key.clone(), store.clone());
         let db_path = store.db_path().to_string();
         stats_map.insert(key.clone(), stats.clone());
@@ -105,21 +208,47 @@ pub async fn start_gateway(
             .or(relay_config.policy.read.paywall.as_ref())
             .cloned();
 
-        let ip_tracker = Arc::new(crate::rate_limit::IpTracker::new());
+        let ip_tracker = Arc::new(crate::rate_limit::IpTracker::with_trusted_proxies(trusted_proxies.clone()));
+        ip_tracker.start_cleanup(cleanup_interval, cleanup_idle);
+        tracker_map.insert(key.clone(), ip_tracker.clone());
 
-        // Spawn periodic cleanup for stale IP tracking entries
-        {
-            let tracker = ip_tracker.clone();
-            tokio::spawn(async move {
-                let mut interval = tokio::time::interval(std::time::Duration::from_secs(300));
-                loop {
-                    interval.tick().await;
-                    tracker.cleanup();
+        let keys = if relay_config.signing_key {
+            match crate::identity::load_or_generate(&config.data_path("keys"), &key) {
+                Ok(keys) => Some(keys),
+                Err(e) => {
+                    tracing::error!("Failed to load signing key for relay '{}': {}", key, e);
+                    None
                 }
-            });
+            }
+        } else {
+            None
+        };
+        if let Some(keys) = &keys {
+            relay_keys.insert(key.clone(), keys.clone());
         }
 
-        let state = Arc::new(RelayState::new(
+        let mirror = if relay_config.mirror_to.is_empty() {
+            None
+        } else {
+            let queue_dir = config.data_path("mirror").join(&key);
+            match crate::mirror::MirrorPublisher::start(&key, queue_dir, relay_config.mirror_to.clone()) {
+                Ok(m) => Some(m),
+                Err(e) => {
+                    tracing::error!("Failed to start mirroring for relay '{}': {}", key, e);
+                    None
+                }
+            }
+        };
+
+        let greylist = relay_config.policy.greylist.clone().map(|greylist| {
+            let path = config.data_path("greylist").join(format!("{}.json", key));
+            let greylist = Arc::new(crate::greylist::Greylist::load(greylist, path));
+            greylist.start_flush(std::time::Duration::from_secs(60));
+            greylist
+        });
+
+        shadow_ban_map.insert(key.clone(), policy.shadow_bans().clone());
+        let mut state = RelayState::new(
             relay_config.clone(),
             store,
             policy,
@@ -131,34 +260,141 @@ pub async fn start_gateway(
             paywall_id,
             stats,
             ip_tracker,
-        ));
+            keys,
+            mirror,
+            greylist,
+            auth_replay.clone(),
+        );
+        if auth_relays.contains(&key) {
+            state.auth_grants = Some(auth_grants.clone());
+        }
+        if config.default_relay.as_ref() == Some(&key) {
+            state.aliases = vec![config.origin(None), "http://localhost".to_string()];
+            default_relay = Some(relay_config.subdomain.clone());
+        }
+        state.notifier = Some(notifier.clone());
+        if relay_config.policy.write.invites || relay_config.policy.write.access_requests {
+            state.invites = Some(invites.clone());
+        }
+        let state = Arc::new(state);
+        policy_map.insert(key.clone(), state.policy.clone());
+        state.pubkey_tracker.start_cleanup(cleanup_interval);
+        firehose_map.insert(key.clone(), state.firehose.clone());
+        connections_map.insert(key.clone(), state.connections.clone());
+        if let Some(cache) = &state.query_cache {
+            query_cache_map.insert(key.clone(), cache.clone());
+        }
+        crate::sync::start(state.clone());
+        relay_states.push(state.clone());
+        relay_state_map.insert(key.clone(), state.clone());
         let app = server::create_relay_router(state);
         router_map.insert(relay_config.subdomain.clone(), app);
         config_map.insert(relay_config.subdomain.clone(), relay_config);
     }
 
+    let relay_lists = config.relay_lists.as_ref().map(|lists| {
+        let urls = config
+            .relays
+            .iter()
+            .map(|(id, r)| (id.clone(), crate::monitor::websocket_url(&config.relay_url(id, r))))
+            .collect();
+        let relays = relay_states.iter().map(|r| (r.relay_id.clone(), r.clone())).collect();
+        let service = Arc::new(RelayListService::new(
+            lists,
+            urls,
+            relays,
+            config.relay_list_targets(),
+            config.data_path("relay_lists"),
+        ));
+        service.clone().start();
+        service
+    });
+
+    if let Some(monitor) = config.monitor.clone() {
+        match crate::identity::load_or_generate(&config.data_path("keys"), "monitor") {
+            Ok(keys) => crate::monitor::start(monitor, config.monitor_targets(), relay_states, keys),
+            Err(e) => tracing::error!("Failed to load monitor signing key: {}", e),
+        }
+    }
+
     let mut blossom_router_map = HashMap::new();
     let mut blossom_store_map = HashMap::new();
 
     for (key, (blossom_config, store)) in blossoms {
-        let scheme = if domain == "localhost" { "http" } else { "https" };
-        let base_url = if domain == "localhost" {
-            format!("{}://{}.{}:{}", scheme, blossom_config.subdomain, domain, port)
-        } else {
-            format!("{}://{}.{}", scheme, blossom_config.subdomain, domain)
-        };
+        let base_url = config.blossom_url(&key, &blossom_config);
+        if config.routing == RoutingMode::Path {
+            blossom_paths.insert(key.clone(), blossom_config.subdomain.clone());
+        }
         let blossom_state = BlossomState {
             config: blossom_config.clone(),
             store: store.clone(),
             server_id: key.clone(),
             base_url,
+            auth_replay: auth_replay.clone(),
+            uploads: Arc::new(crate::blossom::chunked::ChunkedUploads::new(
+                std::path::Path::new(&blossom_config.storage_path).join("uploads"),
+            )),
+            relays: store_map.values().cloned().collect(),
+            auth_grants: auth_grants.clone(),
+            auth_relay: blossom_config
+                .auth_relay
+                .as_ref()
+                .and_then(|id| Some((id.clone(), policy_map.get(id)?.clone()))),
+            notifier: notifier.clone(),
         };
         let app = blossom_handlers::create_blossom_router(blossom_state);
         blossom_router_map.insert(blossom_config.subdomain.clone(), app);
+        if let Some(gc_config) = blossom_config.gc.clone() {
+            let relays = store_map.values().cloned().collect();
+            crate::blossom::gc::start(key.clone(), store.clone(), relays, gc_config);
+        }
         blossom_store_map.insert(key, store);
     }
 
+    let sessions = SessionStore::load(config.data_path("sessions.json")).await;
+    let tls = config
+        .tls
+        .clone()
+        .map(|tls| (tls, crate::tls::hostnames(&config), config.data_path("acme")));
+    let http_addr = config.http_addr().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("bind_address '{}' is not an IP address", config.bind_address),
+        )
+    })?;
+    let listeners = config.listeners.clone();
+    let admin_on_apex = config.admin_on_apex();
+    let dashboard_path = config.dashboard_path().map(str::to_string);
+    let admin_host = config.admin_host();
+
+    let backup = config.backup.clone().map(|backup_config| {
+        let manager = Arc::new(BackupManager::new(
+            backup_config,
+            &config.backup_dir(),
+            store_map.clone(),
+            blossom_store_map.clone(),
+        ));
+        manager.start();
+        manager
+    });
+
+    let disk_usage = Arc::new(DiskUsage::new(
+        config.relays.iter().map(|(id, r)| (id.clone(), PathBuf::from(&r.db_path))).collect(),
+        config.blossoms.iter().map(|(id, b)| (id.clone(), PathBuf::from(&b.storage_path))).collect(),
+        config.alerts.storage_warn_bytes,
+    ));
+    disk_usage.clone().start();
+
+    let alerts = config.alerts.clone();
+    let max_read_secs = config.storage.max_read_secs;
     let system_stats = crate::stats::SharedSystemStats::default();
+    let daily_stats = match DailyStatsStore::open(config.data_path("stats")) {
+        Ok(store) => Some(Arc::new(store)),
+        Err(e) => {
+            tracing::error!("Failed to open daily stats store, history will not be kept: {}", e);
+            None
+        }
+    };
     let start_time = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
@@ -172,49 +408,208 @@ pub async fn start_gateway(
         relay_stores: store_map,
         blossom_routers: blossom_router_map,
         blossom_stores: blossom_store_map,
+        blob_pools,
         config: Arc::new(RwLock::new(config)),
         config_path,
         pages_dir,
         pending_restart: Arc::new(RwLock::new(false)),
-        sessions: Arc::new(RwLock::new(HashMap::new())),
+        sessions: Arc::new(sessions),
+        auth_replay,
+        auth_grants,
         wot_manager,
         paywall_manager,
+        invites,
+        relay_keys,
+        notifier: notifier.clone(),
         relay_stats: stats_map,
+        relay_ip_trackers: tracker_map,
+        firehoses: firehose_map,
+        query_caches: query_cache_map,
+        relay_connections: connections_map,
+        relays: relay_state_map,
+        relay_shadow_bans: shadow_ban_map,
         time_series: ts_map,
         system_stats: system_stats.clone(),
+        daily_stats: daily_stats.clone(),
         start_time,
+        purge_tokens: Arc::new(RwLock::new(HashMap::new())),
+        backup,
+        ip_tracker: gateway_tracker,
+        network,
+        admin_on_apex,
+        dashboard_path,
+        admin_host,
+        default_relay,
+        relay_paths,
+        blossom_paths,
+        relay_lists,
+        disk_usage,
+        purged_relays: Arc::new(std::sync::RwLock::new(HashSet::new())),
     });
 
+    if max_read_secs > 0 {
+        let max = std::time::Duration::from_secs(max_read_secs);
+        crate::stats::start_read_watchdog(state.relay_stores.values().cloned().collect(), max);
+    }
+
     // Spawn stats background task
     tokio::spawn(crate::stats::stats_background_loop(
         bg_relay_data,
         system_stats,
+        daily_stats,
+        notifier,
+        alerts,
     ));
 
-    let app = Router::new().fallback(handler).with_state(state);
+    let app = Router::new().fallback(handler).with_state(state.clone());
+    let admin_app = Router::new().fallback(admin_listener_handler).with_state(state);
 
-    let addr = format!("0.0.0.0:{}", port);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    tracing::info!(
-        "Gateway listening on http://{}:{} (domain: {})",
-        "0.0.0.0",
-        port,
-        domain
-    );
-    axum::serve(listener, app).await?;
+    let mut servers: Vec<BoxFuture<'static, crate::error::Result<()>>> = Vec::new();
+    let listener = tokio::net::TcpListener::bind(http_addr).await?;
+    tracing::info!("Gateway listening on http://{} (domain: {})", http_addr, domain);
+    servers.push(Box::pin(serve_tcp(listener, app.clone())));
+    if let Some((tls, hosts, acme_cache)) = tls {
+        servers.push(Box::pin(crate::tls::serve(tls, http_addr.ip(), hosts, acme_cache, app.clone())));
+    }
+    for extra in listeners {
+        let (listener_app, kind) = if extra.admin_only {
+            (admin_app.clone(), "Admin")
+        } else {
+            (app.clone(), "Gateway")
+        };
+        match extra.addr().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))? {
+            ListenAddr::Tcp(addr) => {
+                let listener = tokio::net::TcpListener::bind(addr).await?;
+                tracing::info!("{} listener on http://{}", kind, addr);
+                servers.push(Box::pin(serve_tcp(listener, listener_app)));
+            }
+            ListenAddr::Unix(path) => {
+                // A socket file left behind by a previous run would make bind fail
+                let _ = std::fs::remove_file(&path);
+                let listener = tokio::net::UnixListener::bind(&path)?;
+                tracing::info!("{} listener on unix:{}", kind, path.display());
+                servers.push(Box::pin(serve_unix(listener, listener_app)));
+            }
+        }
+    }
+    futures_util::future::try_join_all(servers).await?;
+
+    Ok(())
+}
 
+async fn serve_tcp(listener: tokio::net::TcpListener, app: Router) -> crate::error::Result<()> {
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
     Ok(())
 }
 
+/// Serve `app` on a Unix socket. There is no peer address, so clients are
+/// treated as connecting from localhost and `X-Forwarded-For` is honored
+/// if loopback is a trusted proxy (the default).
+async fn serve_unix(listener: tokio::net::UnixListener, app: Router) -> crate::error::Result<()> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::service::TowerToHyperService;
+
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            if let Err(e) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(socket), service)
+                .await
+            {
+                tracing::debug!("Unix socket connection error: {}", e);
+            }
+        });
+    }
+}
+
+/// Requests on an `admin_only` listener, or for the admin subdomain, go to
+/// the admin router whatever their Host header.
+async fn admin_listener_handler(State(state): State<Arc<GatewayState>>, request: Request<Body>) -> Response {
+    match admin_router().with_state(state).oneshot(request).await {
+        Ok(res) => res,
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Admin Router error").into_response(),
+    }
+}
+
 async fn handler(
     State(state): State<Arc<GatewayState>>,
     Host(host): Host,
     _uri: Uri,
-    request: Request<Body>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    mut request: Request<Body>,
 ) -> Response {
     let hostname = host.split(':').next().unwrap_or(&host);
+    let is_root = hostname == state.domain || hostname == "localhost";
+
+    if state.admin_host.as_deref() == Some(hostname) {
+        return admin_listener_handler(State(state), request).await;
+    }
+
+    if request.uri().path() == "/.well-known/nostr.json" {
+        let is_relay = hostname
+            .strip_suffix(&state.domain)
+            .and_then(|sub| sub.strip_suffix('.'))
+            .is_some_and(|sub| state.relay_router(sub).is_some());
+        if is_root || is_relay {
+            return serve_nostr_json(&state, request.uri()).await;
+        }
+    }
+
+    if is_root && request.uri().path() == "/" && is_relay_request(&request) {
+        if let Some(router) = state.default_relay.as_ref().and_then(|sub| state.relay_router(sub)) {
+            let router = router.clone();
+            let peer = connect_info.map_or(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), |ConnectInfo(addr)| {
+                addr.ip()
+            });
+            if let Err(resp) = check_gateway_limits(&state, peer, &mut request).await {
+                return resp;
+            }
+            return match router.oneshot(request).await {
+                Ok(res) => res,
+                Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Router error").into_response(),
+            };
+        }
+    }
+
+    if is_root {
+        if let Some((router, rest)) = mounted_service(&state, request.uri().path()) {
+            let router = router.clone();
+            let mut uri = rest;
+            if let Some(query) = request.uri().query() {
+                uri = format!("{}?{}", uri, query);
+            }
+            match uri.parse() {
+                Ok(uri) => *request.uri_mut() = uri,
+                Err(_) => return (StatusCode::BAD_REQUEST, "Invalid path").into_response(),
+            }
+            let peer = connect_info.map_or(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), |ConnectInfo(addr)| {
+                addr.ip()
+            });
+            if let Err(resp) = check_gateway_limits(&state, peer, &mut request).await {
+                return resp;
+            }
+            return match router.oneshot(request).await {
+                Ok(res) => res,
+                Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Router error").into_response(),
+            };
+        }
 
-    if hostname == state.domain || hostname == "localhost" {
+        // Once the dashboard has moved, the apex root is a public landing page
+        let path = request.uri().path();
+        match state.dashboard_path.as_deref() {
+            Some("/") => {}
+            Some(dashboard) if path == dashboard => return serve_index().await.into_response(),
+            _ => {
+                if let Some(resp) = serve_landing_page(&state, path).await {
+                    return resp;
+                }
+            }
+        }
+        if !state.admin_on_apex && path.starts_with("/api/") {
+            return (StatusCode::NOT_FOUND, "Not found").into_response();
+        }
         let router = admin_router().with_state(state.clone());
         match router.oneshot(request).await {
             Ok(res) => return res,
@@ -225,13 +620,18 @@ async fn handler(
     }
 
     if let Some(subdomain) = hostname.strip_suffix(&state.domain) {
-        let sub = if subdomain.ends_with('.') {
-            &subdomain[..subdomain.len() - 1]
-        } else {
-            subdomain
-        };
+        let sub = subdomain.strip_suffix('.').unwrap_or(subdomain);
+
+        if state.relay_router(sub).is_some() || state.blossom_routers.contains_key(sub) {
+            let peer = connect_info.map_or(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), |ConnectInfo(addr)| {
+                addr.ip()
+            });
+            if let Err(resp) = check_gateway_limits(&state, peer, &mut request).await {
+                return resp;
+            }
+        }
 
-        if let Some(router) = state.relay_routers.get(sub) {
+        if let Some(router) = state.relay_router(sub) {
             let router = router.clone();
             match router.oneshot(request).await {
                 Ok(res) => return res,
@@ -259,6 +659,99 @@ async fn handler(
         .into_response()
 }
 
+/// With path routing, the relay or blossom router mounted at the start of
+/// `path`, and the rest of the path as that router sees it.
+fn mounted_service<'a>(state: &'a GatewayState, path: &str) -> Option<(&'a Router, String)> {
+    let (is_relay, rest) = if let Some(rest) = path.strip_prefix("/relay/") {
+        (true, rest)
+    } else if let Some(rest) = path.strip_prefix("/blossom/") {
+        (false, rest)
+    } else {
+        return None;
+    };
+    let (id, rest) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let router = if is_relay {
+        state.relay_router(state.relay_paths.get(id)?)?
+    } else {
+        state.blossom_routers.get(state.blossom_paths.get(id)?)?
+    };
+    Some((router, rest.to_string()))
+}
+
+/// A WebSocket upgrade or NIP-11 request, which the apex root hands to the
+/// `default_relay` rather than the admin UI.
+fn is_relay_request(request: &Request<Body>) -> bool {
+    let header_has = |name: header::HeaderName, value: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.to_ascii_lowercase().contains(value))
+    };
+    header_has(header::UPGRADE, "websocket") || header_has(header::ACCEPT, "application/nostr+json")
+}
+
+/// Apply the gateway-wide ban list, network policy and per-IP limits to a relay or blossom
+/// request. A WebSocket upgrade takes a connection slot, which is handed to
+/// the relay in the request extensions and held for the connection's life.
+async fn check_gateway_limits(
+    state: &GatewayState,
+    peer: std::net::IpAddr,
+    request: &mut Request<Body>,
+) -> Result<(), Response> {
+    let tracker = &state.ip_tracker;
+    let ip = tracker.client_ip(peer, request.headers());
+    if !state.network.is_allowed(ip) {
+        return Err((StatusCode::FORBIDDEN, "blocked: your network is not allowed").into_response());
+    }
+
+    let (max_connections, requests_per_minute) = {
+        let config = state.config.read().await;
+        (config.rate_limit.max_connections, config.rate_limit.requests_per_minute)
+    };
+    if !tracker.check_request_rate(ip, requests_per_minute) {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "rate limited: too many requests from your IP").into_response());
+    }
+
+    let is_upgrade = request
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+    if is_upgrade {
+        match tracker.acquire(ip, max_connections) {
+            Some(slot) => {
+                request.extensions_mut().insert(Arc::new(slot));
+            }
+            None => {
+                return Err((StatusCode::SERVICE_UNAVAILABLE, "too many connections from your IP").into_response())
+            }
+        }
+    }
+    Ok(())
+}
+
+/// NIP-05 lookup: `?name=<name>` returns that name only, no query returns all.
+async fn serve_nostr_json(state: &GatewayState, uri: &Uri) -> Response {
+    let name = uri.query().and_then(|q| {
+        q.split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| *k == "name")
+            .map(|(_, v)| v.to_string())
+    });
+    let config = state.config.read().await;
+    let doc = crate::nip05::nostr_json(&config.nip05, name.as_deref());
+    drop(config);
+    (
+        [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        Json(doc),
+    )
+        .into_response()
+}
+
 // --- Admin Router ---
 
 pub fn admin_router() -> Router<Arc<GatewayState>> {
@@ -267,7 +760,12 @@ pub fn admin_router() -> Router<Arc<GatewayState>> {
         .route("/api/login", post(login_handler))
         .route("/api/logout", post(logout_handler))
         .route("/api/status", get(status_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/api/disk-usage", get(disk_usage_handler))
+        .route("/.well-known/lnurlp/:name", get(lnurlp_handler))
+        .route("/lnurlp/:name/callback", get(lnurlp_callback))
         .route("/api/relays", get(list_relays).post(create_relay))
+        .route("/api/relay-templates", get(list_relay_templates))
         .route(
             "/api/relays/:id",
             get(get_relay).put(update_relay).delete(delete_relay),
@@ -277,16 +775,52 @@ pub fn admin_router() -> Router<Arc<GatewayState>> {
             get(get_relay_page).put(put_relay_page).delete(delete_relay_page),
         )
         .route("/api/relays/:id/export", get(export_relay))
+        .route("/api/relays/:id/events", get(browse_relay_events))
+        .route("/api/relays/:id/events/:event_id", delete_route(delete_relay_event))
+        .route("/api/relays/:id/pubkeys/:pubkey/events", delete_route(erase_pubkey))
+        .route("/api/relays/:id/kinds", get(relay_kinds))
+        .route("/api/relays/:id/connections", get(list_relay_connections))
+        .route("/api/relays/:id/shadow-bans", get(list_shadow_bans).post(add_shadow_ban))
+        .route("/api/relays/:id/maintenance", get(get_maintenance).put(set_maintenance))
+        .route("/api/relays/:id/shadow-bans/:pubkey", delete_route(remove_shadow_ban))
+        .route("/api/relays/:id/connections/:conn_id/kick", post(kick_relay_connection))
+        .route("/api/relays/:id/invites", get(list_invites).post(create_invite))
+        .route("/api/relays/:id/invites/:code", delete_route(revoke_invite))
+        .route("/api/relays/:id/access-requests", get(list_access_requests))
+        .route("/api/relays/:id/access-requests/:pubkey", post(decide_access_request))
+        .route("/api/relays/:id/members/:pubkey", delete_route(remove_invited_member))
+        .route(
+            "/api/landing-page",
+            get(get_landing_page).put(put_landing_page).delete(delete_landing_page),
+        )
         .route("/api/relays/:id/import", post(import_relay))
+        .route("/api/relays/:id/purge", post(purge_relay))
+        .route("/api/relays/:id/compact", post(compact_relay))
+        .route("/api/relays/:id/backup", get(backup_relay))
+        .route("/api/backups", get(backup_status))
+        .route("/api/rate-limit", get(get_rate_limit))
+        .route("/api/network", get(get_network))
+        .route("/api/network/:list", post(add_network_entry))
+        .route("/api/network/:list/:entry", delete_route(remove_network_entry))
+        .route("/api/nip05", get(list_nip05))
+        .route(
+            "/api/nip05/:name",
+            get(get_nip05).put(put_nip05).delete(delete_nip05),
+        )
         .route("/api/wots", get(list_wots).post(create_wot))
         .route(
             "/api/wots/:id",
             get(get_wot).put(update_wot).delete(delete_wot),
         )
+        .route("/api/wots/:id/rebuild", post(rebuild_wot))
+        .route("/api/wots/:id/progress", get(get_wot_progress))
+        .route("/api/wots/:id/contains", post(wot_contains_bulk))
+        .route("/api/wots/:id/contains/:pubkey", get(wot_contains))
         .route(
             "/api/discovery-relays",
             get(get_discovery_relays).put(put_discovery_relays),
         )
+        .route("/api/discovery-relays/health", get(get_discovery_relay_health))
         .route("/api/blossoms", get(list_blossoms).post(create_blossom))
         .route(
             "/api/blossoms/:id",
@@ -296,19 +830,173 @@ pub fn admin_router() -> Router<Arc<GatewayState>> {
         )
         .route("/api/blossoms/:id/media", get(list_blossom_media).post(upload_blossom_media))
         .route("/api/blossoms/:id/media/:sha256", delete_route(delete_blossom_media))
+        .route("/api/blossoms/:id/gc", get(blossom_gc_dry_run).post(blossom_gc))
+        .route("/api/blob-pools", get(list_blob_pools))
         .route("/api/paywalls", get(list_paywalls).post(create_paywall))
         .route(
             "/api/paywalls/:id",
             get(get_paywall).put(update_paywall).delete(delete_paywall),
         )
         .route("/api/paywalls/:id/verify-nwc", post(verify_nwc_handler))
-        .route("/api/paywalls/:id/whitelist", get(get_paywall_whitelist))
+        .route(
+            "/api/paywalls/:id/whitelist",
+            get(get_paywall_whitelist).post(add_paywall_whitelist_entry),
+        )
+        .route(
+            "/api/paywalls/:id/whitelist/:pubkey",
+            delete_route(remove_paywall_whitelist_entry),
+        )
+        .route("/api/paywalls/:id/ecash/withdraw", post(withdraw_paywall_ecash))
+        .route("/api/webhooks/:id/test", post(test_webhook))
+        .route("/api/relay-lists", get(list_relay_lists))
+        .route("/api/relay-lists/:pubkey", put(submit_relay_list))
+        .route("/api/overview", get(overview_handler))
         .route("/api/stats", get(global_stats_handler))
         .route("/api/stats/:relay_id", get(relay_stats_handler))
+        .route("/api/relays/:id/timeseries", get(relay_timeseries_handler))
+        .route("/api/relays/:id/stats", get(relay_daily_stats_handler))
+        .route("/api/relays/:id/firehose", get(relay_firehose_handler))
+        .route("/api/relays/:id/rejections", get(relay_rejections_handler))
         .route("/api/restart", post(restart_handler))
         .route("/api/update", post(update_handler))
         .route("/api/update-status", get(update_status_handler))
         .route("/.well-known/caddy-ask", get(caddy_ask_handler))
+        .layer(axum::middleware::from_fn(buffer_signed_body))
+}
+
+/// The largest body a NIP-98 signed request may carry: axum's default body
+/// limit, which the admin API's multipart uploads are held to anyway.
+const SIGNED_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// The body of a request authorized by a NIP-98 header, kept so
+/// [`require_auth`] can check it against the event's `payload` tag.
+#[derive(Clone)]
+struct SignedBody(axum::body::Bytes);
+
+/// Buffer the body of requests with an `Authorization` header, then hand
+/// it on to the handler unchanged.
+async fn buffer_signed_body(request: Request<Body>, next: axum::middleware::Next) -> Response {
+    if !request.headers().contains_key(header::AUTHORIZATION) {
+        return next.run(request).await;
+    }
+    let (mut parts, body) = request.into_parts();
+    let bytes = match axum::body::to_bytes(body, SIGNED_BODY_LIMIT).await {
+        Ok(bytes) => bytes,
+        Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response(),
+    };
+    parts.extensions.insert(SignedBody(bytes.clone()));
+    next.run(Request::from_parts(parts, Body::from(bytes))).await
+}
+
+/// Name of the landing page managed through the admin API, in `pages_dir`.
+const LANDING_PAGE_FILE: &str = "_landing.html";
+
+/// The apex domain's public pages when the dashboard lives elsewhere. `/`
+/// is the configured `admin.landing_page` (a file, or a directory whose
+/// other files are served too), else the page managed in the admin UI,
+/// else a list of relays and media servers. Returns `None` for paths that
+/// aren't part of the landing page.
+async fn serve_landing_page(state: &GatewayState, path: &str) -> Option<Response> {
+    let config = state.config.read().await;
+    if let Some(landing) = &config.admin.landing_page {
+        let landing = std::path::Path::new(landing);
+        if landing.is_dir() {
+            let file = if path == "/" { Some("index.html") } else { static_file_name(path) };
+            let file = landing.join(file?);
+            if let Ok(content) = tokio::fs::read(&file).await {
+                let mime = mime_guess::from_path(&file).first_or_octet_stream();
+                return Some(([(header::CONTENT_TYPE, mime.to_string())], content).into_response());
+            }
+            if path != "/" {
+                return None;
+            }
+        } else if path == "/" {
+            match tokio::fs::read_to_string(landing).await {
+                Ok(content) => return Some(Html(content).into_response()),
+                Err(e) => tracing::warn!("Failed to read landing page {}: {}", landing.display(), e),
+            }
+        }
+    }
+    if path != "/" {
+        return None;
+    }
+    if let Ok(content) = tokio::fs::read_to_string(state.pages_dir.join(LANDING_PAGE_FILE)).await {
+        return Some(Html(content).into_response());
+    }
+
+
+    let list = |mut entries: Vec<(&String, String)>| -> String {
+        entries.sort();
+        entries
+            .iter()
+            .map(|(name, url)| {
+                format!(
+                    "<li><strong>{}</strong><code>{}</code></li>",
+                    server::html_escape(name),
+                    server::html_escape(url)
+                )
+            })
+            .collect()
+    };
+    let relays = list(
+        config
+            .relays
+            .iter()
+            .map(|(id, r)| (&r.name, crate::monitor::websocket_url(&config.relay_url(id, r))))
+            .collect(),
+    );
+    let blossoms = list(config.blossoms.iter().map(|(id, b)| (&b.name, config.blossom_url(id, b))).collect());
+    let blossom_section = if blossoms.is_empty() {
+        String::new()
+    } else {
+        format!("<h2>Media servers</h2><ul>{}</ul>", blossoms)
+    };
+    let domain = server::html_escape(&state.domain);
+
+    Some(
+        Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="UTF-8">
+<meta name="viewport" content="width=device-width, initial-scale=1.0">
+<title>{domain}</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box}}
+body{{background:#0a0a0a;color:#fff;font-family:-apple-system,BlinkMacSystemFont,'Segoe UI',Roboto,sans-serif;display:flex;align-items:center;justify-content:center;min-height:100vh}}
+.container{{max-width:560px;padding:2rem}}
+h1{{font-size:1.5rem;margin-bottom:1rem;text-align:center}}
+h2{{font-size:1rem;color:#888;margin:1.5rem 0 0.5rem}}
+ul{{list-style:none}}
+li{{display:flex;justify-content:space-between;gap:1rem;padding:0.5rem 0;border-bottom:1px solid #222}}
+code{{color:#aaa;font-size:0.85rem}}
+</style>
+</head>
+<body>
+<div class="container">
+<h1>{domain}</h1>
+<h2>Relays</h2>
+<ul>{relays}</ul>
+{blossom_section}
+</div>
+</body>
+</html>"#
+        ))
+        .into_response(),
+    )
+}
+
+/// The file a landing page path refers to, relative to the landing
+/// directory. Rejects traversal, hidden files and the apex's own routes.
+fn static_file_name(path: &str) -> Option<&str> {
+    let name = path.strip_prefix('/')?;
+    let mut parts = name.split('/');
+    if matches!(parts.clone().next(), Some("api" | "lnurlp")) {
+        return None;
+    }
+    parts
+        .all(|part| !part.is_empty() && !part.starts_with('.') && !part.contains('\\'))
+        .then_some(name)
 }
 
 async fn serve_index() -> impl IntoResponse {
@@ -328,26 +1016,49 @@ fn extract_session_token(request_headers: &axum::http::HeaderMap) -> Option<Stri
     None
 }
 
-async fn require_auth(
-    headers: &axum::http::HeaderMap,
-    sessions: &Arc<RwLock<HashMap<String, SessionInfo>>>,
-) -> Result<String, Response> {
-    let token = extract_session_token(headers).ok_or_else(|| {
-        (StatusCode::UNAUTHORIZED, "Not authenticated").into_response()
-    })?;
+/// Authorize an admin API request, either by session cookie or by a
+/// per-request NIP-98 `Authorization` header signed by the admin pubkey.
+fn require_auth<'a>(
+    state: &'a GatewayState,
+    request: &Request<Body>,
+) -> impl std::future::Future<Output = Result<String, Response>> + Send + 'a {
+    // The request body isn't `Sync`, so copy out what we need before awaiting.
+    let headers = request.headers().clone();
+    let method = request.method().clone();
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "/".to_string());
+    let body = request.extensions().get::<SignedBody>().map(|b| b.0.clone()).unwrap_or_default();
+
+    async move {
+        if headers.contains_key(header::AUTHORIZATION) {
+            let config = state.config.read().await;
+            let url = format!("{}{}", config.admin_origin(), path_and_query);
+            let event = verify_nip98_header(&headers, &[url], method.as_str(), &body)
+                .map_err(|e| (StatusCode::UNAUTHORIZED, e).into_response())?;
 
-    let sessions_read = sessions.read().await;
-    let session = sessions_read.get(&token).ok_or_else(|| {
-        (StatusCode::UNAUTHORIZED, "Invalid session").into_response()
-    })?;
+            let pubkey = event.author().to_hex();
+            if pubkey != config.admin_pubkey {
+                return Err((StatusCode::FORBIDDEN, "Not authorized as admin").into_response());
+            }
+            if !state.auth_replay.mark_used(&event, crate::auth::NIP98_WINDOW_SECS) {
+                return Err((StatusCode::UNAUTHORIZED, "Auth event already used").into_response());
+            }
+            return Ok(pubkey);
+        }
 
-    if session.is_expired() {
-        drop(sessions_read);
-        sessions.write().await.remove(&token);
-        return Err((StatusCode::UNAUTHORIZED, "Session expired").into_response());
-    }
+        let token = extract_session_token(&headers).ok_or_else(|| {
+            (StatusCode::UNAUTHORIZED, "Not authenticated").into_response()
+        })?;
 
-    Ok(session.pubkey.clone())
+        let session = state.sessions.get(&token).await.ok_or_else(|| {
+            (StatusCode::UNAUTHORIZED, "Invalid or expired session").into_response()
+        })?;
+
+        Ok(session.pubkey)
+    }
 }
 
 // --- Handlers ---
@@ -359,6 +1070,9 @@ async fn login_handler(
     if let Err(e) = verify_auth_event(&event, "/api/login", "POST") {
         return (StatusCode::UNAUTHORIZED, e).into_response();
     }
+    if !state.auth_replay.mark_used(&event, crate::auth::NIP98_WINDOW_SECS) {
+        return (StatusCode::UNAUTHORIZED, "Auth event already used").into_response();
+    }
 
     let pubkey = event.author().to_hex();
 
@@ -379,7 +1093,7 @@ async fn login_handler(
             .as_secs(),
     };
 
-    state.sessions.write().await.insert(token.clone(), session);
+    state.sessions.insert(token.clone(), session).await;
 
     let cookie = format!(
         "moar_session={}; HttpOnly; Path=/; SameSite=Strict",
@@ -399,7 +1113,7 @@ async fn logout_handler(
     request: Request<Body>,
 ) -> impl IntoResponse {
     if let Some(token) = extract_session_token(request.headers()) {
-        state.sessions.write().await.remove(&token);
+        state.sessions.remove(&token).await;
     }
 
     let cookie = "moar_session=; HttpOnly; Path=/; SameSite=Strict; Max-Age=0";
@@ -428,6 +1142,49 @@ async fn status_handler(State(state): State<Arc<GatewayState>>) -> impl IntoResp
     })
 }
 
+#[derive(Serialize)]
+struct HealthResponse {
+    /// `ok`, or `warning` when the disk is nearly full or a store is over
+    /// `alerts.storage_warn_bytes`.
+    status: &'static str,
+    disk_near_full: bool,
+    /// Stores over the storage warning threshold.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    storage_over_threshold: Vec<String>,
+}
+
+/// Liveness for load balancers and monitoring; always 200 while the
+/// gateway is serving, with warnings in the body.
+async fn healthz_handler(State(state): State<Arc<GatewayState>>) -> impl IntoResponse {
+    let (used, total) = {
+        let system = state.system_stats.read().await;
+        (system.disk_used_bytes, system.disk_total_bytes)
+    };
+    let disk_near_full = crate::webhook::disk_near_full(&state.config.read().await.alerts, used, total);
+    let storage_over_threshold = state.disk_usage.report().over_threshold;
+    let status = if disk_near_full || !storage_over_threshold.is_empty() {
+        "warning"
+    } else {
+        "ok"
+    };
+    Json(HealthResponse {
+        status,
+        disk_near_full,
+        storage_over_threshold,
+    })
+}
+
+/// On-disk size of each relay's database and blossom server's storage.
+async fn disk_usage_handler(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    Json(state.disk_usage.report()).into_response()
+}
+
 #[derive(Serialize)]
 struct RelayResponse {
     id: String,
@@ -435,15 +1192,34 @@ struct RelayResponse {
     config: RelayConfig,
 }
 
-async fn list_relays(State(state): State<Arc<GatewayState>>) -> impl IntoResponse {
+/// A write plugin runs a command on the host, so it can only be set in the
+/// config file.
+const PLUGIN_CONFIG_ONLY: &str = "policy.write.plugin can only be set in the config file";
+
+impl RelayResponse {
+    /// Anyone can list relays, but only the admin sees who is blocked or
+    /// shadow-banned; a spammer who could would just switch keys. The
+    /// plugin command is never shown.
+    fn new(id: String, mut config: RelayConfig, admin: bool) -> Self {
+        config.policy.write.plugin = None;
+        if !admin {
+            config.policy.write.blocked_pubkeys = None;
+            config.policy.write.shadow_banned_pubkeys = None;
+        }
+        Self { id, config }
+    }
+}
+
+async fn list_relays(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    let admin = require_auth(&state, &request).await.is_ok();
     let config = state.config.read().await;
     let relays: Vec<RelayResponse> = config
         .relays
         .iter()
-        .map(|(id, cfg)| RelayResponse {
-            id: id.clone(),
-            config: cfg.clone(),
-        })
+        .map(|(id, cfg)| RelayResponse::new(id.clone(), cfg.clone(), admin))
         .collect();
     Json(relays)
 }
@@ -451,14 +1227,12 @@ async fn list_relays(State(state): State<Arc<GatewayState>>) -> impl IntoRespons
 async fn get_relay(
     State(state): State<Arc<GatewayState>>,
     Path(id): Path<String>,
+    request: Request<Body>,
 ) -> impl IntoResponse {
+    let admin = require_auth(&state, &request).await.is_ok();
     let config = state.config.read().await;
     match config.relays.get(&id) {
-        Some(cfg) => Json(RelayResponse {
-            id: id.clone(),
-            config: cfg.clone(),
-        })
-        .into_response(),
+        Some(cfg) => Json(RelayResponse::new(id.clone(), cfg.clone(), admin)).into_response(),
         None => (StatusCode::NOT_FOUND, "Relay not found").into_response(),
     }
 }
@@ -480,6 +1254,7 @@ fn validate_relay_config(
     config: &RelayConfig,
     existing_relays: &HashMap<String, RelayConfig>,
     existing_blossoms: &HashMap<String, BlossomConfig>,
+    admin_subdomain: Option<&str>,
     exclude_id: Option<&str>,
 ) -> Result<(), String> {
     if config.name.is_empty() {
@@ -488,6 +1263,9 @@ fn validate_relay_config(
     if config.subdomain.is_empty() {
         return Err("Subdomain cannot be empty".to_string());
     }
+    if admin_subdomain == Some(config.subdomain.as_str()) {
+        return Err(format!("Subdomain '{}' is used by the admin UI", config.subdomain));
+    }
     // Check subdomain uniqueness across relays and blossoms
     for (id, existing) in existing_relays {
         if Some(id.as_str()) == exclude_id {
@@ -512,6 +1290,14 @@ fn validate_relay_config(
 }
 
 async fn save_config(state: &GatewayState, config: &MoarConfig) -> Result<(), Response> {
+    write_config(state, config).await?;
+    *state.pending_restart.write().await = true;
+    Ok(())
+}
+
+/// Persist the config without flagging a restart, for settings that are read
+/// live from `state.config`.
+async fn write_config(state: &GatewayState, config: &MoarConfig) -> Result<(), Response> {
     let toml_str = toml::to_string_pretty(config).map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -529,16 +1315,28 @@ async fn save_config(state: &GatewayState, config: &MoarConfig) -> Result<(), Re
             )
                 .into_response()
         })?;
-
-    *state.pending_restart.write().await = true;
     Ok(())
 }
 
+/// The relay presets `POST /api/relays` takes as `template`, expanded with
+/// the current config.
+async fn list_relay_templates(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    let config = state.config.read().await;
+    let templates: Vec<_> = RelayTemplate::ALL.into_iter().map(|t| t.info(&config)).collect();
+    Json(templates).into_response()
+}
+
 async fn create_relay(
     State(state): State<Arc<GatewayState>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -554,7 +1352,17 @@ async fn create_relay(
         config: RelayConfig,
     }
 
-    let payload: CreateRelayRequest = match serde_json::from_slice(&body) {
+    let mut body: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
+        }
+    };
+    // A `template` expands into the policy under whatever the payload sets
+    if let Err(e) = crate::template::apply(&mut body, &*state.config.read().await) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let payload: CreateRelayRequest = match serde_json::from_value(body) {
         Ok(p) => p,
         Err(e) => {
             return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
@@ -565,6 +1373,10 @@ async fn create_relay(
         return (StatusCode::BAD_REQUEST, e).into_response();
     }
 
+    if payload.config.policy.write.plugin.is_some() {
+        return (StatusCode::BAD_REQUEST, PLUGIN_CONFIG_ONLY).into_response();
+    }
+
     let mut config = state.config.write().await;
 
     if config.relays.contains_key(&payload.id) {
@@ -575,7 +1387,7 @@ async fn create_relay(
             .into_response();
     }
 
-    if let Err(e) = validate_relay_config(&payload.config, &config.relays, &config.blossoms, None) {
+    if let Err(e) = validate_relay_config(&payload.config, &config.relays, &config.blossoms, config.admin.subdomain.as_deref(), None) {
         return (StatusCode::BAD_REQUEST, e).into_response();
     }
 
@@ -589,10 +1401,7 @@ async fn create_relay(
 
     (
         StatusCode::CREATED,
-        Json(RelayResponse {
-            id: payload.id,
-            config: payload.config,
-        }),
+        Json(RelayResponse::new(payload.id, payload.config, true)),
     )
         .into_response()
 }
@@ -602,7 +1411,7 @@ async fn update_relay(
     Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -611,55 +1420,112 @@ async fn update_relay(
         .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid body").into_response())
         .unwrap();
 
-    let new_config: RelayConfig = match serde_json::from_slice(&body) {
+    let mut new_config: RelayConfig = match serde_json::from_slice(&body) {
         Ok(p) => p,
         Err(e) => {
             return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
         }
     };
+    if new_config.policy.write.plugin.is_some() {
+        return (StatusCode::BAD_REQUEST, PLUGIN_CONFIG_ONLY).into_response();
+    }
 
     let mut config = state.config.write().await;
 
-    if !config.relays.contains_key(&id) {
+    let Some(existing) = config.relays.get(&id) else {
         return (StatusCode::NOT_FOUND, "Relay not found").into_response();
-    }
+    };
+    // Responses leave the plugin out, so keep the configured one
+    new_config.policy.write.plugin = existing.policy.write.plugin.clone();
 
-    if let Err(e) = validate_relay_config(&new_config, &config.relays, &config.blossoms, Some(&id)) {
+    if let Err(e) = validate_relay_config(&new_config, &config.relays, &config.blossoms, config.admin.subdomain.as_deref(), Some(&id)) {
         return (StatusCode::BAD_REQUEST, e).into_response();
     }
 
+    // Policy-only edits are applied to the running relay; anything else
+    // waits for a restart
+    let running = state.relays.get(&id);
+    let live = running.is_some_and(|relay| !needs_restart(&relay.config(), &new_config));
     let old_config = config.relays.insert(id.clone(), new_config.clone());
 
-    if let Err(resp) = save_config(&state, &config).await {
+    let saved = if live { write_config(&state, &config).await } else { save_config(&state, &config).await };
+    if let Err(resp) = saved {
         // Rollback
         if let Some(old) = old_config {
             config.relays.insert(id.clone(), old);
         }
         return resp;
     }
+    drop(config);
 
-    Json(RelayResponse {
-        id,
-        config: new_config,
-    })
-    .into_response()
+    if let (true, Some(relay)) = (live, running) {
+        let policy = PolicyEngine::for_relay(
+            &id,
+            &new_config,
+            &state.wot_manager,
+            &state.paywall_manager,
+            &state.invites,
+            relay.stats.clone(),
+        )
+        .await;
+        relay.reload(new_config.clone(), policy);
+        tracing::info!("Applied new policy to relay '{}' without a restart", id);
+    }
+
+    Json(RelayResponse::new(id, new_config, true)).into_response()
+}
+
+/// Whether a running relay can only move from `old` to `new` by restarting.
+/// Naming, NIP-11 and policy rules are swapped in place; storage, routing,
+/// keys, mirroring and sync, and the parts of the policy that set up their
+/// own state (greylist, invites, access requests, paywalls, the store's
+/// `max_limit` cap) are set up once at startup.
+fn needs_restart(old: &RelayConfig, new: &RelayConfig) -> bool {
+    let fixed = |relay: &RelayConfig| {
+        let mut value = serde_json::to_value(relay).unwrap_or_default();
+        if let Some(fields) = value.as_object_mut() {
+            for field in ["name", "description", "nip11", "policy", "skip_verify", "maintenance_mode"] {
+                fields.remove(field);
+            }
+        }
+        let policy = &relay.policy;
+        value["fixed_policy"] = serde_json::json!({
+            "greylist": policy.greylist,
+            "invites": policy.write.invites,
+            "access_requests": policy.write.access_requests,
+            "write_paywall": policy.write.paywall,
+            "read_paywall": policy.read.paywall,
+            "max_limit": relay.nip11.max_limit,
+        });
+        value
+    };
+    fixed(old) != fixed(new)
 }
 
 async fn delete_relay(
     State(state): State<Arc<GatewayState>>,
     Path(id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
-    let mut config = state.config.write().await;
-
+    // `?purge=<token>` also deletes the relay's database from disk
+    let purge = params.get("purge").cloned();
+    if let Some(token) = &purge {
+        if !take_purge_token(&state, &id, token).await {
+            return (StatusCode::FORBIDDEN, "Invalid or expired purge confirmation token").into_response();
+        }
+    }
+
+    let mut config = state.config.write().await;
+
     let removed = config.relays.remove(&id);
-    if removed.is_none() {
+    let Some(removed_relay) = removed.clone() else {
         return (StatusCode::NOT_FOUND, "Relay not found").into_response();
-    }
+    };
 
     if let Err(resp) = save_config(&state, &config).await {
         // Rollback
@@ -668,16 +1534,197 @@ async fn delete_relay(
         }
         return resp;
     }
+    drop(config);
 
     // Clean up custom page file if it exists
     let page_path = state.pages_dir.join(format!("{}.html", id));
     let _ = tokio::fs::remove_file(&page_path).await;
 
-    StatusCode::NO_CONTENT.into_response()
+    if purge.is_none() {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    // Stop routing to the relay, close its connections and its store, and
+    // only then remove the files, so nothing writes into unlinked ones.
+    state.purged_relays.write().unwrap().insert(removed_relay.subdomain.clone());
+    if let Some(connections) = state.relay_connections.get(&id) {
+        for connection in connections.list() {
+            connections.kick(connection.id);
+        }
+    }
+    if let Some(store) = state.relay_stores.get(&id) {
+        store.close();
+        if let Some(stats) = state.relay_stats.get(&id) {
+            stats.event_count.store(0, Relaxed);
+        }
+        if let Some(cache) = state.query_caches.get(&id) {
+            cache.clear();
+        }
+    }
+    if let Some(daily) = &state.daily_stats {
+        if let Err(e) = daily.remove(&id) {
+            tracing::warn!("Failed to remove relay '{}' daily stats: {}", id, e);
+        }
+    }
+    let db_dir = PathBuf::from(&removed_relay.db_path);
+    let reclaimed_bytes = dir_size(&db_dir).await;
+    if let Err(e) = tokio::fs::remove_dir_all(&db_dir).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Relay deleted but its database could not be removed: {}", e),
+            )
+                .into_response();
+        }
+    }
+    tracing::info!(relay = %id, reclaimed_bytes, "Relay deleted and its database purged");
+
+    Json(PurgeResult {
+        purged: true,
+        reclaimed_bytes,
+    })
+    .into_response()
+}
+
+// --- Relay Purge ---
+
+/// How long a purge confirmation token stays valid.
+const PURGE_TOKEN_TTL_SECS: u64 = 300;
+
+#[derive(Deserialize, Default)]
+struct PurgeRequest {
+    confirm: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PurgeConfirmation {
+    confirm_token: String,
+    expires_at: u64,
+    event_count: u64,
+}
+
+#[derive(Serialize)]
+struct PurgeResult {
+    purged: bool,
+    reclaimed_bytes: u64,
+}
+
+async fn issue_purge_token(state: &GatewayState, id: &str) -> (String, u64) {
+    let token = uuid::Uuid::new_v4().to_string();
+    let expires_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + PURGE_TOKEN_TTL_SECS;
+    state
+        .purge_tokens
+        .write()
+        .await
+        .insert(id.to_string(), (token.clone(), expires_at));
+    (token, expires_at)
+}
+
+/// Consume the relay's purge token if `token` matches and hasn't expired.
+async fn take_purge_token(state: &GatewayState, id: &str, token: &str) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut tokens = state.purge_tokens.write().await;
+    match tokens.get(id) {
+        Some((expected, expires_at)) if expected == token && *expires_at > now => {
+            tokens.remove(id);
+            true
+        }
+        _ => false,
+    }
+}
+
+async fn dir_size(dir: &std::path::Path) -> u64 {
+    let mut total = 0;
+    if let Ok(mut entries) = tokio::fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if let Ok(meta) = entry.metadata().await {
+                if meta.is_file() {
+                    total += meta.len();
+                }
+            }
+        }
+    }
+    total
+}
+
+/// Wipe all events of a relay, keeping its configuration. The first call
+/// returns a confirmation token; repeating the call with
+/// `{"confirm": "<token>"}` within five minutes performs the purge.
+async fn purge_relay(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let store = match state.relay_stores.get(&id) {
+        Some(s) => s.clone(),
+        None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    };
+
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 64).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Failed to read body").into_response(),
+    };
+    let req: PurgeRequest = if body.is_empty() {
+        PurgeRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(r) => r,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response(),
+        }
+    };
+
+    let Some(token) = req.confirm else {
+        let (confirm_token, expires_at) = issue_purge_token(&state, &id).await;
+        return Json(PurgeConfirmation {
+            confirm_token,
+            expires_at,
+            event_count: store.event_count().unwrap_or(0),
+        })
+        .into_response();
+    };
+
+    if !take_purge_token(&state, &id, &token).await {
+        return (StatusCode::FORBIDDEN, "Invalid or expired purge confirmation token").into_response();
+    }
+
+    let reclaimed_bytes = match tokio::task::spawn_blocking(move || store.purge()).await {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Purge failed: {}", e)).into_response()
+        }
+        Err(e) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Purge failed: {}", e)).into_response()
+        }
+    };
+    if let Some(stats) = state.relay_stats.get(&id) {
+        stats.event_count.store(0, Relaxed);
+    }
+    if let Some(cache) = state.query_caches.get(&id) {
+        cache.clear();
+    }
+    tracing::info!(relay = %id, reclaimed_bytes, "Relay events purged");
+
+    Json(PurgeResult {
+        purged: true,
+        reclaimed_bytes,
+    })
+    .into_response()
 }
 
 // --- Relay Page Handlers ---
 
+#[allow(clippy::result_large_err)]
 fn sanitize_relay_id_for_path(id: &str) -> Result<(), Response> {
     // Prevent path traversal
     if id.contains('.') || id.contains('/') || id.contains('\\') {
@@ -718,7 +1765,7 @@ async fn put_relay_page(
     Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -762,7 +1809,7 @@ async fn delete_relay_page(
     Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -776,68 +1823,108 @@ async fn delete_relay_page(
     StatusCode::NO_CONTENT.into_response()
 }
 
-// --- Relay Import/Export Handlers ---
+async fn get_landing_page(State(state): State<Arc<GatewayState>>) -> impl IntoResponse {
+    match tokio::fs::read_to_string(state.pages_dir.join(LANDING_PAGE_FILE)).await {
+        Ok(content) => Json(serde_json::json!({ "html": content })),
+        Err(_) => Json(serde_json::json!({ "html": serde_json::Value::Null })),
+    }
+}
 
-async fn export_relay(
+async fn put_landing_page(
     State(state): State<Arc<GatewayState>>,
-    Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
-    let store = match state.relay_stores.get(&id) {
-        Some(s) => s.clone(),
-        None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 512).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Body too large (max 512KB)").into_response(),
     };
-
-    let events = match store.iter_all() {
-        Ok(e) => e,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to read events: {}", e),
-            )
-                .into_response()
-        }
+    let payload: PagePayload = match serde_json::from_slice(&body) {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response(),
     };
 
-    let mut body = String::new();
-    for event in &events {
-        if let Ok(json) = serde_json::to_string(event) {
-            body.push_str(&json);
-            body.push('\n');
-        }
+    let _ = tokio::fs::create_dir_all(&state.pages_dir).await;
+    match tokio::fs::write(state.pages_dir.join(LANDING_PAGE_FILE), &payload.html).await {
+        Ok(_) => (StatusCode::OK, "Page saved").into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to write page: {}", e),
+        )
+            .into_response(),
     }
+}
 
-    let filename = format!("{}.jsonl", id);
-    (
-        [
-            (header::CONTENT_TYPE, "application/jsonl".to_string()),
-            (
-                header::CONTENT_DISPOSITION,
-                format!("attachment; filename=\"{}\"", filename),
-            ),
-        ],
-        body,
-    )
-        .into_response()
+async fn delete_landing_page(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    let _ = tokio::fs::remove_file(state.pages_dir.join(LANDING_PAGE_FILE)).await;
+    StatusCode::NO_CONTENT.into_response()
 }
 
+// --- Relay Import/Export Handlers ---
+
+const DEFAULT_EVENT_PAGE: usize = 50;
+const MAX_EVENT_PAGE: usize = 500;
+
 #[derive(Serialize)]
-struct ImportResult {
-    imported: usize,
-    skipped: usize,
-    errors: usize,
+struct EventBrowserPage {
+    events: Vec<nostr::Event>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
 }
 
-async fn import_relay(
+/// A comma-separated query parameter, each item parsed with `parse`.
+fn list_param<T>(params: &HashMap<String, String>, name: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Vec<T>, String> {
+    let Some(value) = params.get(name) else {
+        return Ok(Vec::new());
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| parse(item).ok_or_else(|| format!("Invalid {} entry '{}'", name, item)))
+        .collect()
+}
+
+/// The filter described by the event browser's query parameters.
+fn browser_filter(params: &HashMap<String, String>) -> Result<nostr::Filter, String> {
+    let mut filter = nostr::Filter::new();
+    let authors = list_param(params, "authors", |a| nostr::PublicKey::parse(a).ok())?;
+    if !authors.is_empty() {
+        filter = filter.authors(authors);
+    }
+    let kinds = list_param(params, "kinds", |k| k.parse::<u16>().ok().map(nostr::Kind::from))?;
+    if !kinds.is_empty() {
+        filter = filter.kinds(kinds);
+    }
+    for (name, bound) in [("since", &mut filter.since), ("until", &mut filter.until)] {
+        if let Some(value) = params.get(name) {
+            let secs = value.parse::<u64>().map_err(|_| format!("{} must be a unix timestamp", name))?;
+            *bound = Some(nostr::Timestamp::from(secs));
+        }
+    }
+    Ok(filter)
+}
+
+/// Browse a relay's events, newest first: `?authors=` (hex or npub) and
+/// `?kinds=`, comma-separated, `?since=`/`?until=`, `?search=` (content,
+/// case-insensitive), `?limit=` (default 50, at most 500) and `?cursor=`
+/// from the previous page.
+async fn browse_relay_events(
     State(state): State<Arc<GatewayState>>,
     Path(id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -846,114 +1933,1364 @@ async fn import_relay(
         None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
     };
 
-    let mut multipart = match axum::extract::Multipart::from_request(request, &()).await {
-        Ok(m) => m,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Expected multipart form data").into_response(),
-    };
-
-    let field = match multipart.next_field().await {
-        Ok(Some(f)) => f,
-        Ok(None) => return (StatusCode::BAD_REQUEST, "No file field found").into_response(),
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid multipart data").into_response(),
+    let filter = match browser_filter(&params) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
     };
-
-    let data = match field.bytes().await {
-        Ok(b) => b,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Failed to read file data").into_response(),
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        None => DEFAULT_EVENT_PAGE,
+        Some(Ok(limit)) if limit > 0 => limit.min(MAX_EVENT_PAGE),
+        Some(_) => return (StatusCode::BAD_REQUEST, "limit must be a positive number").into_response(),
     };
-
-    let content = match String::from_utf8(data.to_vec()) {
-        Ok(s) => s,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid UTF-8 content").into_response(),
+    let cursor = match params.get("cursor").map(|c| EventCursor::parse(c)) {
+        None => None,
+        Some(Some(cursor)) => Some(cursor),
+        Some(None) => return (StatusCode::BAD_REQUEST, "Invalid cursor").into_response(),
     };
+    let search = params.get("search").filter(|s| !s.is_empty()).cloned();
 
-    let mut imported = 0usize;
-    let mut skipped = 0usize;
-    let mut errors = 0usize;
-
-    for line in content.lines() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
-        }
+    let page = tokio::task::spawn_blocking(move || {
+        browse::browse(store.as_ref(), &filter, search.as_deref(), cursor, limit)
+    })
+    .await;
+    match page {
+        Ok(Ok(page)) => Json(EventBrowserPage {
+            events: page.events,
+            next_cursor: page.next_cursor.map(|c| c.to_string()),
+        })
+        .into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read events: {}", e)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read events: {}", e)).into_response(),
+    }
+}
 
-        let event: nostr::Event = match serde_json::from_str(line) {
-            Ok(e) => e,
-            Err(_) => {
-                errors += 1;
-                continue;
-            }
-        };
+/// Delete one event from a relay.
+async fn delete_relay_event(
+    State(state): State<Arc<GatewayState>>,
+    Path((id, event_id)): Path<(String, String)>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
 
-        if event.verify().is_err() {
-            errors += 1;
-            continue;
-        }
+    let store = match state.relay_stores.get(&id) {
+        Some(s) => s.clone(),
+        None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    };
+    let event_id = match nostr::EventId::parse(&event_id) {
+        Ok(event_id) => event_id,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid event id").into_response(),
+    };
 
-        match store.save_event(&event) {
-            Ok(()) => imported += 1,
-            Err(_) => {
-                skipped += 1;
+    match tokio::task::spawn_blocking(move || store.delete_event(event_id.as_bytes())).await {
+        Ok(Ok(true)) => {
+            if let Some(cache) = state.query_caches.get(&id) {
+                cache.clear();
             }
+            tracing::info!(relay = %id, "Admin deleted event {}", event_id.to_hex());
+            StatusCode::NO_CONTENT.into_response()
         }
+        Ok(Ok(false)) => (StatusCode::NOT_FOUND, "Event not found").into_response(),
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete event: {}", e)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete event: {}", e)).into_response(),
     }
-
-    Json(ImportResult {
-        imported,
-        skipped,
-        errors,
-    })
-    .into_response()
 }
 
-// --- WoT Handlers ---
+// --- Invite Codes ---
 
-async fn list_wots(
+/// A relay's invite codes and the pubkeys that redeemed one.
+async fn list_invites(
     State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
+    match state.invites.book(&id).await {
+        Some(book) => Json(book).into_response(),
+        None => (StatusCode::NOT_FOUND, "Invites are not enabled for this relay").into_response(),
+    }
+}
 
-    let wots = state.wot_manager.list_wots().await;
-    Json(wots).into_response()
+#[derive(Deserialize, Default)]
+struct CreateInviteRequest {
+    /// How many pubkeys may redeem the code (default: 1).
+    max_uses: Option<u32>,
+    /// Hours until the code expires (default: never).
+    expires_in_hours: Option<u64>,
 }
 
-async fn get_wot(
+async fn create_invite(
     State(state): State<Arc<GatewayState>>,
     Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 64).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Failed to read body").into_response(),
+    };
+    let req: CreateInviteRequest = if body.is_empty() {
+        CreateInviteRequest::default()
+    } else {
+        match serde_json::from_slice(&body) {
+            Ok(r) => r,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response(),
+        }
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let expires_at = req.expires_in_hours.map(|hours| now + hours * 3600);
+    match state.invites.create(&id, req.max_uses.unwrap_or(1), expires_at).await {
+        Ok(invite) => (StatusCode::CREATED, Json(invite)).into_response(),
+        Err(e) if !state.invites.is_enabled(&id).await => (StatusCode::NOT_FOUND, e).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
 
-    let wots = state.wot_manager.list_wots().await;
-    match wots.into_iter().find(|w| w.id == id) {
-        Some(wot) => Json(wot).into_response(),
-        None => (StatusCode::NOT_FOUND, "WoT not found").into_response(),
+async fn revoke_invite(
+    State(state): State<Arc<GatewayState>>,
+    Path((id, code)): Path<(String, String)>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    match state.invites.revoke(&id, &code).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Invite code not found").into_response(),
+        Err(e) if !state.invites.is_enabled(&id).await => (StatusCode::NOT_FOUND, e).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
     }
 }
 
-#[derive(Deserialize)]
-struct CreateWotRequest {
-    id: String,
-    seed: String,
-    #[serde(default = "default_depth")]
-    depth: u8,
-    #[serde(default = "default_interval")]
-    update_interval_hours: u64,
+/// Take write access away from a pubkey that redeemed an invite.
+async fn remove_invited_member(
+    State(state): State<Arc<GatewayState>>,
+    Path((id, pubkey)): Path<(String, String)>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    let pubkey = match nostr::PublicKey::parse(&pubkey) {
+        Ok(pubkey) => pubkey,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid pubkey").into_response(),
+    };
+    match state.invites.remove_member(&id, &pubkey).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Not a member").into_response(),
+        Err(e) if !state.invites.is_enabled(&id).await => (StatusCode::NOT_FOUND, e).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Pending requests for write access to a relay, oldest first.
+async fn list_access_requests(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    let enabled = state.config.read().await.relays.get(&id).is_some_and(|r| r.policy.write.access_requests);
+    match state.invites.book(&id).await {
+        Some(book) if enabled => Json(book.requests).into_response(),
+        _ => (StatusCode::NOT_FOUND, "Access requests are not enabled for this relay").into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct AccessDecision {
+    approve: bool,
+    /// DM the user the outcome from the relay's signing key.
+    #[serde(default)]
+    notify: bool,
+}
+
+#[derive(Serialize)]
+struct AccessDecisionResult {
+    approved: bool,
+    /// Whether a DM to the user was queued.
+    notified: bool,
+}
+
+/// Approve or deny a pending access request. Approved pubkeys may write
+/// right away and are appended to the relay's `write.allowed_pubkeys`.
+async fn decide_access_request(
+    State(state): State<Arc<GatewayState>>,
+    Path((id, pubkey)): Path<(String, String)>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    let pubkey = match nostr::PublicKey::parse(&pubkey) {
+        Ok(pubkey) => pubkey,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid pubkey").into_response(),
+    };
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 64).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Failed to read body").into_response(),
+    };
+    let decision: AccessDecision = match serde_json::from_slice(&body) {
+        Ok(d) => d,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response(),
+    };
+    if !state.config.read().await.relays.get(&id).is_some_and(|r| r.policy.write.access_requests) {
+        return (StatusCode::NOT_FOUND, "Access requests are not enabled for this relay").into_response();
+    }
+    match state.invites.decide(&id, &pubkey, decision.approve).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "No pending request from this pubkey").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+
+    if decision.approve {
+        let mut config = state.config.write().await;
+        if let Some(relay) = config.relays.get_mut(&id) {
+            let allowed = relay.policy.write.allowed_pubkeys.get_or_insert_with(Vec::new);
+            if !allowed.iter().any(|pk| nostr::PublicKey::parse(pk).ok() == Some(pubkey)) {
+                allowed.push(pubkey.to_hex());
+            }
+        }
+        // The pubkey can already write, so the saved list takes over at the
+        // next restart without one being needed now
+        if let Err(resp) = write_config(&state, &config).await {
+            return resp;
+        }
+    }
+
+    let mut notified = false;
+    if decision.notify {
+        let Some(keys) = state.relay_keys.get(&id).cloned() else {
+            return (StatusCode::BAD_REQUEST, "The relay has no signing key to send a DM from").into_response();
+        };
+        let config = state.config.read().await;
+        let relays = config.discovery_relays.clone();
+        let name = config.relays.get(&id).map(|r| r.name.clone()).unwrap_or_else(|| id.clone());
+        drop(config);
+        let content = if decision.approve {
+            format!("Your request to write to {} was approved. Welcome!", name)
+        } else {
+            format!("Your request to write to {} was declined.", name)
+        };
+        if !relays.is_empty() {
+            tokio::spawn(async move {
+                if let Err(e) = crate::dm::send_private_dm(&keys, pubkey, &content, &relays).await {
+                    tracing::warn!("Failed to DM access decision to {}: {}", pubkey.to_hex(), e);
+                }
+            });
+            notified = true;
+        }
+    }
+
+    Json(AccessDecisionResult {
+        approved: decision.approve,
+        notified,
+    })
+    .into_response()
+}
+
+#[derive(Serialize)]
+struct KindCount {
+    kind: u16,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct KindBreakdown {
+    total: u64,
+    /// Most stored kinds first.
+    kinds: Vec<KindCount>,
+}
+
+/// How many events of each kind a relay stores.
+async fn relay_kinds(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let store = match state.relay_stores.get(&id) {
+        Some(s) => s.clone(),
+        None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    };
+    let counts = match tokio::task::spawn_blocking(move || store.kind_counts()).await {
+        Ok(Ok(counts)) => counts,
+        Ok(Err(e)) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to count kinds: {}", e)).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to count kinds: {}", e)).into_response(),
+    };
+    let mut kinds: Vec<KindCount> = counts.into_iter().map(|(kind, count)| KindCount { kind, count }).collect();
+    kinds.sort_by(|a, b| b.count.cmp(&a.count).then(a.kind.cmp(&b.kind)));
+    Json(KindBreakdown {
+        total: kinds.iter().map(|k| k.count).sum(),
+        kinds,
+    })
+    .into_response()
+}
+
+/// Open WebSocket connections of a relay, oldest first.
+async fn list_relay_connections(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    match state.relay_connections.get(&id) {
+        Some(connections) => Json(connections.list()).into_response(),
+        None => (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    }
+}
+
+/// Close one of a relay's connections. The client is sent a NOTICE first
+/// and may reconnect; ban its IP to keep it out.
+async fn kick_relay_connection(
+    State(state): State<Arc<GatewayState>>,
+    Path((id, conn_id)): Path<(String, String)>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    let Some(connections) = state.relay_connections.get(&id) else {
+        return (StatusCode::NOT_FOUND, "Relay not found").into_response();
+    };
+    match conn_id.parse::<u64>() {
+        Ok(conn_id) if connections.kick(conn_id) => StatusCode::NO_CONTENT.into_response(),
+        _ => (StatusCode::NOT_FOUND, "Connection not found").into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct ErasureResult {
+    events: u64,
+    blobs: u64,
+    blob_bytes: u64,
+}
+
+/// Delete every event a pubkey (hex or npub) authored on a relay and, with
+/// `?blobs=true`, every blob they uploaded to any blossom server.
+async fn erase_pubkey(
+    State(state): State<Arc<GatewayState>>,
+    Path((id, pubkey)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let store = match state.relay_stores.get(&id) {
+        Some(s) => s.clone(),
+        None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    };
+    let pubkey = match nostr::PublicKey::parse(&pubkey) {
+        Ok(pubkey) => pubkey,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid pubkey").into_response(),
+    };
+
+    let events = match tokio::task::spawn_blocking(move || store.delete_author(&pubkey)).await {
+        Ok(Ok(n)) => n,
+        Ok(Err(e)) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete events: {}", e)).into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete events: {}", e)).into_response(),
+    };
+    if let Some(cache) = state.query_caches.get(&id) {
+        cache.clear();
+    }
+
+    let mut result = ErasureResult { events, blobs: 0, blob_bytes: 0 };
+    if params.get("blobs").is_some_and(|v| v == "true" || v == "1") {
+        let pubkey_hex = pubkey.to_hex();
+        for (blossom, blob_store) in &state.blossom_stores {
+            let blobs = match blob_store.list_by_pubkey(&pubkey_hex) {
+                Ok(blobs) => blobs,
+                Err(e) => {
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to list blobs on '{}': {}", blossom, e),
+                    )
+                        .into_response()
+                }
+            };
+            for meta in blobs {
+                if let Ok(true) = blob_store.delete_blob(&meta.sha256).await {
+                    result.blobs += 1;
+                    result.blob_bytes += meta.size;
+                }
+            }
+        }
+    }
+
+    tracing::info!(
+        relay = %id,
+        "Admin erased {}: {} events, {} blobs",
+        pubkey.to_hex(),
+        result.events,
+        result.blobs
+    );
+    Json(result).into_response()
+}
+
+/// Download a relay's events as JSONL, optionally only those matching
+/// `?kinds=`, `?authors=`, `?since=` and `?until=` (as in the event browser).
+async fn export_relay(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let store = match state.relay_stores.get(&id) {
+        Some(s) => s.clone(),
+        None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    };
+    let filter = match browser_filter(&params) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let events = match crate::cli::matching_events(store.as_ref(), &filter) {
+        Ok(e) => e,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read events: {}", e),
+            )
+                .into_response()
+        }
+    };
+
+    let mut body = String::new();
+    for event in &events {
+        if let Ok(json) = serde_json::to_string(event) {
+            body.push_str(&json);
+            body.push('\n');
+        }
+    }
+
+    let filename = format!("{}.jsonl", id);
+    (
+        [
+            (header::CONTENT_TYPE, "application/jsonl".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+// --- Relay Snapshots ---
+
+#[derive(Serialize)]
+struct SnapshotResult {
+    path: String,
+    snapshot_bytes: u64,
+    live_bytes: u64,
+}
+
+/// Write a compacted snapshot of a relay's database into the backup
+/// directory as `<id>-<unix time>.mdb`. Returns the snapshot path.
+async fn write_snapshot(
+    state: &GatewayState,
+    id: &str,
+    store: Arc<dyn NostrStore>,
+) -> Result<PathBuf, Response> {
+    let backup_dir = state.config.read().await.backup_dir();
+    if let Err(e) = tokio::fs::create_dir_all(&backup_dir).await {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to create backup directory: {}", e),
+        )
+            .into_response());
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let dest = backup_dir.join(format!("{}-{}.mdb", id, now));
+    let _ = tokio::fs::remove_file(&dest).await;
+
+    let target = dest.clone();
+    match tokio::task::spawn_blocking(move || store.snapshot_to(&target)).await {
+        Ok(Ok(())) => Ok(dest),
+        Ok(Err(e)) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Snapshot failed: {}", e)).into_response()),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Snapshot failed: {}", e)).into_response()),
+    }
+}
+
+/// Write a compacted snapshot of a live relay to the backup directory.
+/// The live file isn't shrunk; restoring the snapshot (or `moar compact`
+/// while stopped) does that.
+async fn compact_relay(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let store = match state.relay_stores.get(&id) {
+        Some(s) => s.clone(),
+        None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    };
+    let live_path = std::path::Path::new(store.db_path()).join("data.mdb");
+
+    let dest = match write_snapshot(&state, &id, store).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let snapshot_bytes = tokio::fs::metadata(&dest).await.map(|m| m.len()).unwrap_or(0);
+    let live_bytes = tokio::fs::metadata(&live_path).await.map(|m| m.len()).unwrap_or(0);
+    tracing::info!(relay = %id, path = %dest.display(), snapshot_bytes, live_bytes, "Wrote relay snapshot");
+
+    Json(SnapshotResult {
+        path: dest.to_string_lossy().into_owned(),
+        snapshot_bytes,
+        live_bytes,
+    })
+    .into_response()
+}
+
+/// Stream a consistent, compacted snapshot of a live relay's database.
+async fn backup_relay(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let store = match state.relay_stores.get(&id) {
+        Some(s) => s.clone(),
+        None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    };
+
+    let dest = match write_snapshot(&state, &id, store).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let file = match tokio::fs::File::open(&dest).await {
+        Ok(f) => f,
+        Err(e) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to open snapshot: {}", e))
+                .into_response()
+        }
+    };
+    let len = file.metadata().await.map(|m| m.len()).unwrap_or(0);
+    // The open handle keeps the data readable; the download isn't kept
+    let _ = tokio::fs::remove_file(&dest).await;
+
+    let filename = dest
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| format!("{}.mdb", id));
+    (
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_LENGTH, len.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(tokio_util::io::ReaderStream::new(file)),
+    )
+        .into_response()
+}
+
+#[derive(Serialize)]
+struct BackupStatusResponse {
+    enabled: bool,
+    interval_hours: Option<u64>,
+    retain: Option<usize>,
+    dir: Option<String>,
+    s3_bucket: Option<String>,
+    #[serde(flatten)]
+    status: BackupStatus,
+}
+
+/// Schedule and outcome of the scheduled backups.
+async fn backup_status(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let Some(backup) = &state.backup else {
+        return Json(BackupStatusResponse {
+            enabled: false,
+            interval_hours: None,
+            retain: None,
+            dir: None,
+            s3_bucket: None,
+            status: BackupStatus::default(),
+        })
+        .into_response();
+    };
+    let config = backup.config();
+    Json(BackupStatusResponse {
+        enabled: true,
+        interval_hours: Some(config.interval_hours),
+        retain: Some(config.retain),
+        dir: Some(backup.dir().to_string_lossy().into_owned()),
+        s3_bucket: config.s3.as_ref().map(|s3| s3.bucket.clone()),
+        status: backup.status().await,
+    })
+    .into_response()
+}
+
+#[derive(Serialize)]
+struct ImportResult {
+    imported: usize,
+    skipped: usize,
+    errors: usize,
+}
+
+async fn import_relay(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let store = match state.relay_stores.get(&id) {
+        Some(s) => s.clone(),
+        None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    };
+
+    let mut multipart = match axum::extract::Multipart::from_request(request, &()).await {
+        Ok(m) => m,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Expected multipart form data").into_response(),
+    };
+
+    let field = match multipart.next_field().await {
+        Ok(Some(f)) => f,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "No file field found").into_response(),
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid multipart data").into_response(),
+    };
+
+    let data = match field.bytes().await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Failed to read file data").into_response(),
+    };
+
+    let content = match String::from_utf8(data.to_vec()) {
+        Ok(s) => s,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid UTF-8 content").into_response(),
+    };
+
+    let verify = params.get("skip_verify").map(String::as_str) != Some("true");
+    let lines: Vec<&str> = content.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+
+    let mut imported = 0usize;
+    let mut skipped = 0usize;
+    let mut errors = 0usize;
+
+    for chunk in lines.chunks(crate::cli::IMPORT_BATCH_SIZE) {
+        // Signature checks dominate; keep them off the async workers
+        let chunk: Vec<String> = chunk.iter().map(|l| l.to_string()).collect();
+        let (events, bad) = match tokio::task::spawn_blocking(move || crate::cli::parse_import_batch(&chunk, verify)).await {
+            Ok(parsed) => parsed,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("Import failed: {}", e)).into_response(),
+        };
+        errors += bad;
+        let results = match store.save_events_batch(events.clone()).await {
+            Ok(results) => results.into_iter().map(Ok).collect(),
+            // Save one at a time so only the events that fail count as errors
+            Err(_) => {
+                let mut results = Vec::with_capacity(events.len());
+                for event in events {
+                    results.push(store.save_events_batch(vec![event]).await.map(|r| r[0]));
+                }
+                results
+            }
+        };
+        for result in results {
+            match result {
+                Ok(result) if result.is_stored() => imported += 1,
+                Ok(_) => skipped += 1,
+                Err(_) => errors += 1,
+            }
+        }
+    }
+    if let Some(cache) = state.query_caches.get(&id) {
+        cache.clear();
+    }
+
+    Json(ImportResult {
+        imported,
+        skipped,
+        errors,
+    })
+    .into_response()
+}
+
+// --- WoT Handlers ---
+
+async fn list_wots(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let wots = state.wot_manager.list_wots().await;
+    Json(wots).into_response()
+}
+
+async fn get_wot(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let wots = state.wot_manager.list_wots().await;
+    match wots.into_iter().find(|w| w.id == id) {
+        Some(wot) => Json(wot).into_response(),
+        None => (StatusCode::NOT_FOUND, "WoT not found").into_response(),
+    }
+}
+
+async fn rebuild_wot(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    match state.wot_manager.request_rebuild(&id).await {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e).into_response(),
+    }
+}
+
+async fn get_wot_progress(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    match state.wot_manager.get_progress(&id).await {
+        Some(progress) => Json(progress).into_response(),
+        None => (StatusCode::NOT_FOUND, "WoT not found").into_response(),
+    }
+}
+
+async fn wot_contains(
+    State(state): State<Arc<GatewayState>>,
+    Path((id, pubkey)): Path<(String, String)>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let pubkey = match nostr::PublicKey::parse(&pubkey) {
+        Ok(pk) => pk,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid pubkey").into_response(),
+    };
+    match state.wot_manager.lookup(&id, &[pubkey]).await {
+        Some(mut results) => Json(results.remove(0)).into_response(),
+        None => (StatusCode::NOT_FOUND, "WoT not found").into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct WotContainsRequest {
+    pubkeys: Vec<String>,
+}
+
+async fn wot_contains_bulk(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 1024).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid body").into_response(),
+    };
+    let payload: WotContainsRequest = match serde_json::from_slice(&body) {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response(),
+    };
+    let mut pubkeys = Vec::with_capacity(payload.pubkeys.len());
+    for pk in &payload.pubkeys {
+        match nostr::PublicKey::parse(pk) {
+            Ok(pk) => pubkeys.push(pk),
+            Err(_) => return (StatusCode::BAD_REQUEST, format!("Invalid pubkey: {}", pk)).into_response(),
+        }
+    }
+
+    match state.wot_manager.lookup(&id, &pubkeys).await {
+        Some(results) => Json(results).into_response(),
+        None => (StatusCode::NOT_FOUND, "WoT not found").into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct CreateWotRequest {
+    id: String,
+    seed: String,
+    #[serde(default = "default_depth")]
+    depth: u8,
+    #[serde(default = "default_interval")]
+    update_interval_hours: u64,
+    #[serde(default = "default_full_rebuild")]
+    full_rebuild_hours: u64,
+    #[serde(default)]
+    outbox: WotOutboxConfig,
+    #[serde(default)]
+    mutes: WotMutes,
+    #[serde(default)]
+    exclude_pubkeys: Vec<String>,
+}
+
+fn default_depth() -> u8 { 1 }
+fn default_interval() -> u64 { 24 }
+fn default_full_rebuild() -> u64 { 168 }
+
+async fn create_wot(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 64).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid body").into_response(),
+    };
+
+    let payload: CreateWotRequest = match serde_json::from_slice(&body) {
+        Ok(p) => p,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
+        }
+    };
+
+    if let Err(e) = validate_relay_id(&payload.id) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    if payload.depth < 1 || payload.depth > 4 {
+        return (StatusCode::BAD_REQUEST, "Depth must be 1-4").into_response();
+    }
+
+    // Validate seed is a valid pubkey
+    if nostr::PublicKey::parse(&payload.seed).is_err() {
+        return (StatusCode::BAD_REQUEST, "Invalid seed pubkey").into_response();
+    }
+    if let Some(pk) = payload.exclude_pubkeys.iter().find(|pk| nostr::PublicKey::parse(pk).is_err()) {
+        return (StatusCode::BAD_REQUEST, format!("Invalid excluded pubkey '{}'", pk)).into_response();
+    }
+
+    let wot_config = WotConfig {
+        seed: payload.seed,
+        depth: payload.depth,
+        update_interval_hours: payload.update_interval_hours,
+        full_rebuild_hours: payload.full_rebuild_hours,
+        outbox: payload.outbox,
+        mutes: payload.mutes,
+        exclude_pubkeys: payload.exclude_pubkeys,
+    };
+
+    if let Err(e) = state.wot_manager.add_wot(payload.id.clone(), wot_config.clone()).await {
+        return (StatusCode::CONFLICT, e).into_response();
+    }
+
+    // Save to config
+    let mut config = state.config.write().await;
+    config.wots.insert(payload.id.clone(), wot_config);
+    if let Err(resp) = save_config(&state, &config).await {
+        return resp;
+    }
+
+    (StatusCode::CREATED, "WoT created").into_response()
+}
+
+#[derive(Deserialize)]
+struct UpdateWotRequest {
+    seed: String,
+    #[serde(default = "default_depth")]
+    depth: u8,
+    #[serde(default = "default_interval")]
+    update_interval_hours: u64,
+    #[serde(default = "default_full_rebuild")]
+    full_rebuild_hours: u64,
+    #[serde(default)]
+    outbox: WotOutboxConfig,
+    #[serde(default)]
+    mutes: WotMutes,
+    #[serde(default)]
+    exclude_pubkeys: Vec<String>,
+}
+
+async fn update_wot(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 64).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid body").into_response(),
+    };
+
+    let payload: UpdateWotRequest = match serde_json::from_slice(&body) {
+        Ok(p) => p,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
+        }
+    };
+
+    if payload.depth < 1 || payload.depth > 4 {
+        return (StatusCode::BAD_REQUEST, "Depth must be 1-4").into_response();
+    }
+
+    if nostr::PublicKey::parse(&payload.seed).is_err() {
+        return (StatusCode::BAD_REQUEST, "Invalid seed pubkey").into_response();
+    }
+    if let Some(pk) = payload.exclude_pubkeys.iter().find(|pk| nostr::PublicKey::parse(pk).is_err()) {
+        return (StatusCode::BAD_REQUEST, format!("Invalid excluded pubkey '{}'", pk)).into_response();
+    }
+
+    let wot_config = WotConfig {
+        seed: payload.seed,
+        depth: payload.depth,
+        update_interval_hours: payload.update_interval_hours,
+        full_rebuild_hours: payload.full_rebuild_hours,
+        outbox: payload.outbox,
+        mutes: payload.mutes,
+        exclude_pubkeys: payload.exclude_pubkeys,
+    };
+
+    if let Err(e) = state.wot_manager.update_wot(&id, wot_config.clone()).await {
+        return (StatusCode::NOT_FOUND, e).into_response();
+    }
+
+    let mut config = state.config.write().await;
+    config.wots.insert(id, wot_config);
+    if let Err(resp) = save_config(&state, &config).await {
+        return resp;
+    }
+
+    (StatusCode::OK, "WoT updated").into_response()
+}
+
+async fn delete_wot(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    // Check if any relay policies reference this WoT
+    let config = state.config.read().await;
+    let mut referencing_relays = Vec::new();
+    for (relay_id, relay_conf) in &config.relays {
+        if relay_conf.policy.write.wot.as_deref() == Some(&id)
+            || relay_conf.policy.read.wot.as_deref() == Some(&id)
+        {
+            referencing_relays.push(relay_id.clone());
+        }
+    }
+    drop(config);
+
+    if !referencing_relays.is_empty() {
+        return (
+            StatusCode::CONFLICT,
+            format!(
+                "WoT '{}' is referenced by relay policies: {}. Remove the WoT references first.",
+                id,
+                referencing_relays.join(", ")
+            ),
+        )
+            .into_response();
+    }
+
+    if let Err(e) = state.wot_manager.remove_wot(&id).await {
+        return (StatusCode::NOT_FOUND, e).into_response();
+    }
+
+    let mut config = state.config.write().await;
+    config.wots.remove(&id);
+    if let Err(resp) = save_config(&state, &config).await {
+        return resp;
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// --- Discovery Relay Handlers ---
+
+async fn get_discovery_relays(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let relays = state.wot_manager.get_discovery_relays().await;
+    Json(relays).into_response()
+}
+
+async fn get_discovery_relay_health(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    Json(state.wot_manager.discovery_relay_health().await).into_response()
+}
+
+#[derive(Deserialize)]
+struct DiscoveryRelaysPayload {
+    relays: Vec<String>,
+}
+
+async fn put_discovery_relays(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 64).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid body").into_response(),
+    };
+
+    let payload: DiscoveryRelaysPayload = match serde_json::from_slice(&body) {
+        Ok(p) => p,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
+        }
+    };
+
+    state
+        .wot_manager
+        .set_discovery_relays(payload.relays.clone())
+        .await;
+
+    let mut config = state.config.write().await;
+    config.discovery_relays = payload.relays;
+    if let Err(resp) = save_config(&state, &config).await {
+        return resp;
+    }
+
+    (StatusCode::OK, "Discovery relays updated").into_response()
+}
+
+// --- NIP-05 Handlers ---
+
+async fn list_nip05(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let config = state.config.read().await;
+    Json(config.nip05.clone()).into_response()
+}
+
+async fn get_nip05(
+    State(state): State<Arc<GatewayState>>,
+    Path(name): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let config = state.config.read().await;
+    match config.nip05.get(&name) {
+        Some(entry) => Json(entry.clone()).into_response(),
+        None => (StatusCode::NOT_FOUND, "NIP-05 name not found").into_response(),
+    }
+}
+
+/// Create or replace a name. The pubkey may be hex or npub; it is stored as hex.
+async fn put_nip05(
+    State(state): State<Arc<GatewayState>>,
+    Path(name): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    if let Err(e) = crate::nip05::validate_name(&name) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 64).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid body").into_response(),
+    };
+
+    let mut entry: Nip05Entry = match serde_json::from_slice(&body) {
+        Ok(e) => e,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
+        }
+    };
+
+    entry.pubkey = match nostr::PublicKey::parse(&entry.pubkey) {
+        Ok(pk) => pk.to_hex(),
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid pubkey").into_response(),
+    };
+    if let Some(url) = entry
+        .relays
+        .iter()
+        .find(|u| !u.starts_with("ws://") && !u.starts_with("wss://"))
+    {
+        return (StatusCode::BAD_REQUEST, format!("Invalid relay URL '{}'", url)).into_response();
+    }
+
+    let mut config = state.config.write().await;
+    config.nip05.insert(name, entry);
+    if let Err(resp) = write_config(&state, &config).await {
+        return resp;
+    }
+
+    (StatusCode::OK, "NIP-05 name saved").into_response()
+}
+
+async fn delete_nip05(
+    State(state): State<Arc<GatewayState>>,
+    Path(name): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let mut config = state.config.write().await;
+    if config.nip05.remove(&name).is_none() {
+        return (StatusCode::NOT_FOUND, "NIP-05 name not found").into_response();
+    }
+    if let Err(resp) = write_config(&state, &config).await {
+        return resp;
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// --- Gateway Rate Limit Handlers ---
+
+#[derive(Serialize)]
+struct ClientConnections {
+    ip: String,
+    connections: u32,
+}
+
+#[derive(Serialize)]
+struct RateLimitStatus {
+    max_connections: Option<u32>,
+    requests_per_minute: Option<u32>,
+    /// IPs with open WebSocket connections, busiest first (top 100).
+    clients: Vec<ClientConnections>,
+}
+
+async fn get_rate_limit(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let limits = state.config.read().await.rate_limit.clone();
+    let clients = state
+        .ip_tracker
+        .connections()
+        .into_iter()
+        .take(100)
+        .map(|(ip, connections)| ClientConnections {
+            ip: ip.to_string(),
+            connections,
+        })
+        .collect();
+    Json(RateLimitStatus {
+        max_connections: limits.max_connections,
+        requests_per_minute: limits.requests_per_minute,
+        clients,
+    })
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct ShadowBanRequest {
+    pubkey: String,
+}
+
+/// Persist a relay's shadow-ban list and apply it to new events immediately.
+async fn save_shadow_bans(state: &GatewayState, id: &str, config: &MoarConfig) -> Result<(), Response> {
+    write_config(state, config).await?;
+    if let (Some(bans), Some(relay)) = (state.relay_shadow_bans.get(id), config.relays.get(id)) {
+        bans.replace(relay.policy.write.shadow_banned_pubkeys.as_deref().unwrap_or_default());
+    }
+    Ok(())
+}
+
+/// A relay's shadow-banned pubkeys, as configured (npub or hex).
+async fn list_shadow_bans(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    match state.config.read().await.relays.get(&id) {
+        Some(relay) => Json(relay.policy.write.shadow_banned_pubkeys.clone().unwrap_or_default()).into_response(),
+        None => (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    }
+}
+
+async fn add_shadow_ban(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 64).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid body").into_response(),
+    };
+    let payload: ShadowBanRequest = match serde_json::from_slice(&body) {
+        Ok(p) => p,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response(),
+    };
+    let Ok(pubkey) = nostr::PublicKey::parse(payload.pubkey.trim()) else {
+        return (StatusCode::BAD_REQUEST, "Invalid pubkey").into_response();
+    };
+
+    let mut config = state.config.write().await;
+    let Some(relay) = config.relays.get_mut(&id) else {
+        return (StatusCode::NOT_FOUND, "Relay not found").into_response();
+    };
+    let banned = relay.policy.write.shadow_banned_pubkeys.get_or_insert_with(Vec::new);
+    if !banned.iter().any(|pk| nostr::PublicKey::parse(pk).ok() == Some(pubkey)) {
+        banned.push(pubkey.to_hex());
+    }
+    if let Err(resp) = save_shadow_bans(&state, &id, &config).await {
+        return resp;
+    }
+
+    (StatusCode::OK, "Pubkey shadow-banned").into_response()
+}
+
+async fn remove_shadow_ban(
+    State(state): State<Arc<GatewayState>>,
+    Path((id, pubkey)): Path<(String, String)>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    let Ok(pubkey) = nostr::PublicKey::parse(&pubkey) else {
+        return (StatusCode::BAD_REQUEST, "Invalid pubkey").into_response();
+    };
+
+    let mut config = state.config.write().await;
+    let Some(relay) = config.relays.get_mut(&id) else {
+        return (StatusCode::NOT_FOUND, "Relay not found").into_response();
+    };
+    let banned = relay.policy.write.shadow_banned_pubkeys.get_or_insert_with(Vec::new);
+    let before = banned.len();
+    banned.retain(|pk| nostr::PublicKey::parse(pk).ok() != Some(pubkey));
+    if banned.len() == before {
+        return (StatusCode::NOT_FOUND, "Pubkey not shadow-banned").into_response();
+    }
+    if let Err(resp) = save_shadow_bans(&state, &id, &config).await {
+        return resp;
+    }
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+struct MaintenanceBody {
+    mode: Option<MaintenanceMode>,
+}
+
+async fn get_maintenance(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    match state.config.read().await.relays.get(&id) {
+        Some(relay) => Json(MaintenanceBody {
+            mode: relay.maintenance_mode,
+        })
+        .into_response(),
+        None => (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    }
 }
 
-fn default_depth() -> u8 { 1 }
-fn default_interval() -> u64 { 24 }
-
-async fn create_wot(
+/// Put a relay into maintenance (`{"mode": "read_only"}` or `"paused"`) or
+/// back out of it (`{"mode": null}`), without a restart.
+async fn set_maintenance(
     State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -961,62 +3298,68 @@ async fn create_wot(
         Ok(b) => b,
         Err(_) => return (StatusCode::BAD_REQUEST, "Invalid body").into_response(),
     };
-
-    let payload: CreateWotRequest = match serde_json::from_slice(&body) {
+    let payload: MaintenanceBody = match serde_json::from_slice(&body) {
         Ok(p) => p,
-        Err(e) => {
-            return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
-        }
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response(),
     };
 
-    if let Err(e) = validate_relay_id(&payload.id) {
-        return (StatusCode::BAD_REQUEST, e).into_response();
+    let mut config = state.config.write().await;
+    let Some(relay) = config.relays.get_mut(&id) else {
+        return (StatusCode::NOT_FOUND, "Relay not found").into_response();
+    };
+    relay.maintenance_mode = payload.mode;
+    if let Err(resp) = write_config(&state, &config).await {
+        return resp;
     }
-
-    if payload.depth < 1 || payload.depth > 4 {
-        return (StatusCode::BAD_REQUEST, "Depth must be 1-4").into_response();
+    if let Some(relay) = state.relays.get(&id) {
+        relay.set_maintenance(payload.mode);
     }
+    tracing::info!("Relay '{}' maintenance mode: {:?}", id, payload.mode);
 
-    // Validate seed is a valid pubkey
-    if nostr::PublicKey::parse(&payload.seed).is_err() {
-        return (StatusCode::BAD_REQUEST, "Invalid seed pubkey").into_response();
-    }
+    Json(payload).into_response()
+}
 
-    let wot_config = WotConfig {
-        seed: payload.seed,
-        depth: payload.depth,
-        update_interval_hours: payload.update_interval_hours,
-    };
+// --- Network Policy Handlers ---
 
-    if let Err(e) = state.wot_manager.add_wot(payload.id.clone(), wot_config.clone()).await {
-        return (StatusCode::CONFLICT, e).into_response();
+async fn get_network(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
     }
 
-    // Save to config
-    let mut config = state.config.write().await;
-    config.wots.insert(payload.id.clone(), wot_config);
-    if let Err(resp) = save_config(&state, &config).await {
-        return resp;
+    Json(state.config.read().await.network.clone()).into_response()
+}
+
+fn network_list<'a>(config: &'a mut MoarConfig, list: &str) -> Option<&'a mut Vec<String>> {
+    match list {
+        "allow" => Some(&mut config.network.allow),
+        "block" => Some(&mut config.network.block),
+        _ => None,
     }
+}
 
-    (StatusCode::CREATED, "WoT created").into_response()
+/// Persist the network lists and apply them to new requests immediately.
+async fn save_network(state: &GatewayState, config: &MoarConfig) -> Result<(), Response> {
+    state
+        .network
+        .set_lists(&config.network.allow, &config.network.block)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())?;
+    write_config(state, config).await
 }
 
 #[derive(Deserialize)]
-struct UpdateWotRequest {
-    seed: String,
-    #[serde(default = "default_depth")]
-    depth: u8,
-    #[serde(default = "default_interval")]
-    update_interval_hours: u64,
+struct NetworkEntryRequest {
+    entry: String,
 }
 
-async fn update_wot(
+async fn add_network_entry(
     State(state): State<Arc<GatewayState>>,
-    Path(id): Path<String>,
+    Path(list): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1024,138 +3367,138 @@ async fn update_wot(
         Ok(b) => b,
         Err(_) => return (StatusCode::BAD_REQUEST, "Invalid body").into_response(),
     };
-
-    let payload: UpdateWotRequest = match serde_json::from_slice(&body) {
+    let payload: NetworkEntryRequest = match serde_json::from_slice(&body) {
         Ok(p) => p,
         Err(e) => {
             return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
         }
     };
-
-    if payload.depth < 1 || payload.depth > 4 {
-        return (StatusCode::BAD_REQUEST, "Depth must be 1-4").into_response();
-    }
-
-    if nostr::PublicKey::parse(&payload.seed).is_err() {
-        return (StatusCode::BAD_REQUEST, "Invalid seed pubkey").into_response();
-    }
-
-    let wot_config = WotConfig {
-        seed: payload.seed,
-        depth: payload.depth,
-        update_interval_hours: payload.update_interval_hours,
+    let entry = match state.network.parse_entry(&payload.entry) {
+        Ok(e) => e.canonical(),
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
     };
 
-    if let Err(e) = state.wot_manager.update_wot(&id, wot_config.clone()).await {
-        return (StatusCode::NOT_FOUND, e).into_response();
-    }
-
     let mut config = state.config.write().await;
-    config.wots.insert(id, wot_config);
-    if let Err(resp) = save_config(&state, &config).await {
+    let Some(entries) = network_list(&mut config, &list) else {
+        return (StatusCode::NOT_FOUND, "List must be 'allow' or 'block'").into_response();
+    };
+    if !entries.contains(&entry) {
+        entries.push(entry);
+    }
+    if let Err(resp) = save_network(&state, &config).await {
         return resp;
     }
 
-    (StatusCode::OK, "WoT updated").into_response()
+    (StatusCode::OK, "Network entry added").into_response()
 }
 
-async fn delete_wot(
+async fn remove_network_entry(
     State(state): State<Arc<GatewayState>>,
-    Path(id): Path<String>,
+    Path((list, entry)): Path<(String, String)>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
-    // Check if any relay policies reference this WoT
-    let config = state.config.read().await;
-    let mut referencing_relays = Vec::new();
-    for (relay_id, relay_conf) in &config.relays {
-        if relay_conf.policy.write.wot.as_deref() == Some(&id)
-            || relay_conf.policy.read.wot.as_deref() == Some(&id)
-        {
-            referencing_relays.push(relay_id.clone());
-        }
-    }
-    drop(config);
-
-    if !referencing_relays.is_empty() {
-        return (
-            StatusCode::CONFLICT,
-            format!(
-                "WoT '{}' is referenced by relay policies: {}. Remove the WoT references first.",
-                id,
-                referencing_relays.join(", ")
-            ),
-        )
-            .into_response();
-    }
-
-    if let Err(e) = state.wot_manager.remove_wot(&id).await {
-        return (StatusCode::NOT_FOUND, e).into_response();
-    }
-
+    let canonical = |e: &str| crate::network::NetworkEntry::parse(e).map_or(e.to_string(), |p| p.canonical());
+    let target = canonical(&entry);
     let mut config = state.config.write().await;
-    config.wots.remove(&id);
-    if let Err(resp) = save_config(&state, &config).await {
+    let Some(entries) = network_list(&mut config, &list) else {
+        return (StatusCode::NOT_FOUND, "List must be 'allow' or 'block'").into_response();
+    };
+    let before = entries.len();
+    entries.retain(|e| canonical(e) != target);
+    if entries.len() == before {
+        return (StatusCode::NOT_FOUND, "Entry not found").into_response();
+    }
+    if let Err(resp) = save_network(&state, &config).await {
         return resp;
     }
 
     StatusCode::NO_CONTENT.into_response()
 }
 
-// --- Discovery Relay Handlers ---
+// --- Lightning Address Handlers ---
 
-async fn get_discovery_relays(
+/// LNURL-pay request for `name@domain`.
+async fn lnurlp_handler(
     State(state): State<Arc<GatewayState>>,
-    request: Request<Body>,
+    Path(name): Path<String>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
-        return resp;
-    }
-
-    let relays = state.wot_manager.get_discovery_relays().await;
-    Json(relays).into_response()
-}
+    let config = state.config.read().await;
+    let Some(address) = config.lightning_addresses.get(&name) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(crate::lnurl::LnurlError::new("Unknown lightning address")),
+        )
+            .into_response();
+    };
 
-#[derive(Deserialize)]
-struct DiscoveryRelaysPayload {
-    relays: Vec<String>,
+    let callback = format!("{}/lnurlp/{}/callback", config.origin(None), name);
+    (
+        [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        Json(crate::lnurl::pay_request(address, &name, &state.domain, callback)),
+    )
+        .into_response()
 }
 
-async fn put_discovery_relays(
+/// LNURL-pay callback: request an invoice for `?amount=<msats>` from the
+/// address's NWC wallet.
+async fn lnurlp_callback(
     State(state): State<Arc<GatewayState>>,
-    request: Request<Body>,
+    Path(name): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
-        return resp;
-    }
+    let cors = [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")];
+    let config = state.config.read().await;
+    let Some(address) = config.lightning_addresses.get(&name) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(crate::lnurl::LnurlError::new("Unknown lightning address")),
+        )
+            .into_response();
+    };
 
-    let body = match axum::body::to_bytes(request.into_body(), 1024 * 64).await {
-        Ok(b) => b,
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid body").into_response(),
+    let amount = match crate::lnurl::check_amount(address, params.get("amount").and_then(|a| a.parse().ok())) {
+        Ok(a) => a,
+        Err(e) => return (cors, Json(e)).into_response(),
     };
+    let (nwc_string, paywall) = (address.nwc_string.clone(), address.paywall.clone());
+    let metadata = crate::lnurl::metadata(address, &name, &state.domain);
+    drop(config);
 
-    let payload: DiscoveryRelaysPayload = match serde_json::from_slice(&body) {
-        Ok(p) => p,
-        Err(e) => {
-            return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
+    // A paywall's wallet is reached over the paywall's own connection
+    let client = match (nwc_string, paywall) {
+        (Some(nwc), _) => crate::nwc::NwcClient::from_connection_string(&nwc).ok(),
+        (None, Some(paywall)) => state.paywall_manager.nwc_client(&paywall).await,
+        (None, None) => None,
+    };
+    let client = match client {
+        Some(c) => c,
+        None => {
+            tracing::error!("Lightning address '{}' has no usable NWC wallet", name);
+            return (cors, Json(crate::lnurl::LnurlError::new("Wallet not configured"))).into_response();
         }
     };
 
-    state
-        .wot_manager
-        .set_discovery_relays(payload.relays.clone())
-        .await;
-
-    let mut config = state.config.write().await;
-    config.discovery_relays = payload.relays;
-    if let Err(resp) = save_config(&state, &config).await {
-        return resp;
+    match client
+        .make_invoice_with_description_hash(amount, &crate::lnurl::description_hash(&metadata))
+        .await
+    {
+        Ok(invoice) => (
+            cors,
+            Json(crate::lnurl::PayResponse {
+                pr: invoice.invoice,
+                routes: Vec::new(),
+            }),
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Lightning address '{}' invoice failed: {}", name, e);
+            (cors, Json(crate::lnurl::LnurlError::new("Failed to create invoice"))).into_response()
+        }
     }
-
-    (StatusCode::OK, "Discovery relays updated").into_response()
 }
 
 // --- Blossom Handlers ---
@@ -1167,15 +3510,22 @@ struct BlossomResponse {
     config: BlossomConfig,
 }
 
+impl BlossomResponse {
+    /// The S3 secret key never leaves the server; it comes back blank.
+    fn new(id: String, mut config: BlossomConfig) -> Self {
+        if let Some(s3) = config.s3.as_mut() {
+            s3.secret_key.clear();
+        }
+        Self { id, config }
+    }
+}
+
 async fn list_blossoms(State(state): State<Arc<GatewayState>>) -> impl IntoResponse {
     let config = state.config.read().await;
     let blossoms: Vec<BlossomResponse> = config
         .blossoms
         .iter()
-        .map(|(id, cfg)| BlossomResponse {
-            id: id.clone(),
-            config: cfg.clone(),
-        })
+        .map(|(id, cfg)| BlossomResponse::new(id.clone(), cfg.clone()))
         .collect();
     Json(blossoms)
 }
@@ -1186,11 +3536,7 @@ async fn get_blossom(
 ) -> impl IntoResponse {
     let config = state.config.read().await;
     match config.blossoms.get(&id) {
-        Some(cfg) => Json(BlossomResponse {
-            id: id.clone(),
-            config: cfg.clone(),
-        })
-        .into_response(),
+        Some(cfg) => Json(BlossomResponse::new(id.clone(), cfg.clone())).into_response(),
         None => (StatusCode::NOT_FOUND, "Blossom server not found").into_response(),
     }
 }
@@ -1199,6 +3545,8 @@ fn validate_blossom_config(
     config: &BlossomConfig,
     existing_blossoms: &HashMap<String, BlossomConfig>,
     existing_relays: &HashMap<String, RelayConfig>,
+    blob_pools: &HashMap<String, BlobPoolConfig>,
+    admin_subdomain: Option<&str>,
     exclude_id: Option<&str>,
 ) -> Result<(), String> {
     if config.name.is_empty() {
@@ -1207,9 +3555,25 @@ fn validate_blossom_config(
     if config.subdomain.is_empty() {
         return Err("Subdomain cannot be empty".to_string());
     }
+    if admin_subdomain == Some(config.subdomain.as_str()) {
+        return Err(format!("Subdomain '{}' is used by the admin UI", config.subdomain));
+    }
     if config.storage_path.is_empty() {
         return Err("Storage path cannot be empty".to_string());
     }
+    if let Some(relay) = &config.auth_relay {
+        if !existing_relays.contains_key(relay) {
+            return Err(format!("Unknown auth relay '{}'", relay));
+        }
+    }
+    if let Some(pool) = &config.pool {
+        if !blob_pools.contains_key(pool) {
+            return Err(format!("Unknown blob pool '{}'", pool));
+        }
+        if config.s3.is_some() {
+            return Err("A blossom server in a blob pool cannot also set s3".to_string());
+        }
+    }
     // Check subdomain uniqueness across both blossoms and relays
     for (id, existing) in existing_blossoms {
         if Some(id.as_str()) == exclude_id {
@@ -1237,7 +3601,7 @@ async fn create_blossom(
     State(state): State<Arc<GatewayState>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1274,7 +3638,7 @@ async fn create_blossom(
             .into_response();
     }
 
-    if let Err(e) = validate_blossom_config(&payload.config, &config.blossoms, &config.relays, None) {
+    if let Err(e) = validate_blossom_config(&payload.config, &config.blossoms, &config.relays, &config.blob_pools, config.admin.subdomain.as_deref(), None) {
         return (StatusCode::BAD_REQUEST, e).into_response();
     }
 
@@ -1289,10 +3653,7 @@ async fn create_blossom(
 
     (
         StatusCode::CREATED,
-        Json(BlossomResponse {
-            id: payload.id,
-            config: payload.config,
-        }),
+        Json(BlossomResponse::new(payload.id, payload.config)),
     )
         .into_response()
 }
@@ -1302,7 +3663,7 @@ async fn update_blossom(
     Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1311,7 +3672,7 @@ async fn update_blossom(
         Err(_) => return (StatusCode::BAD_REQUEST, "Invalid body").into_response(),
     };
 
-    let new_config: BlossomConfig = match serde_json::from_slice(&body) {
+    let mut new_config: BlossomConfig = match serde_json::from_slice(&body) {
         Ok(p) => p,
         Err(e) => {
             return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
@@ -1320,11 +3681,17 @@ async fn update_blossom(
 
     let mut config = state.config.write().await;
 
-    if !config.blossoms.contains_key(&id) {
+    let Some(existing) = config.blossoms.get(&id) else {
         return (StatusCode::NOT_FOUND, "Blossom server not found").into_response();
+    };
+    // Responses blank the S3 secret, so a blank one sent back means "unchanged".
+    if let (Some(new_s3), Some(old_s3)) = (new_config.s3.as_mut(), existing.s3.as_ref()) {
+        if new_s3.secret_key.is_empty() {
+            new_s3.secret_key = old_s3.secret_key.clone();
+        }
     }
 
-    if let Err(e) = validate_blossom_config(&new_config, &config.blossoms, &config.relays, Some(&id)) {
+    if let Err(e) = validate_blossom_config(&new_config, &config.blossoms, &config.relays, &config.blob_pools, config.admin.subdomain.as_deref(), Some(&id)) {
         return (StatusCode::BAD_REQUEST, e).into_response();
     }
 
@@ -1337,11 +3704,7 @@ async fn update_blossom(
         return resp;
     }
 
-    Json(BlossomResponse {
-        id,
-        config: new_config,
-    })
-    .into_response()
+    Json(BlossomResponse::new(id, new_config)).into_response()
 }
 
 async fn delete_blossom(
@@ -1349,7 +3712,7 @@ async fn delete_blossom(
     Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1372,12 +3735,35 @@ async fn delete_blossom(
 
 // --- Blossom Media Handlers (Admin) ---
 
+const DEFAULT_MEDIA_PAGE: usize = 100;
+const MAX_MEDIA_PAGE: usize = 1000;
+
+#[derive(Serialize)]
+struct MediaItem {
+    #[serde(flatten)]
+    descriptor: blossom_handlers::BlobDescriptor,
+    uploader: String,
+}
+
+#[derive(Serialize)]
+struct MediaPage {
+    blobs: Vec<MediaItem>,
+    total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+/// A page of a blossom server's blobs. `?limit=` (default 100, at most
+/// 1000), `?cursor=` from the previous page, `?sort=newest|oldest|largest|smallest`,
+/// and filters `?uploader=` (hex or npub) and `?type=` (`image/png`, or
+/// `image/` for any image).
 async fn list_blossom_media(
     State(state): State<Arc<GatewayState>>,
     Path(id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1386,33 +3772,63 @@ async fn list_blossom_media(
         None => return (StatusCode::NOT_FOUND, "Blossom server not found").into_response(),
     };
 
-    match store.list_all() {
-        Ok(metas) => {
+    let mut query = BlobQuery {
+        mime_type: params.get("type").cloned(),
+        limit: DEFAULT_MEDIA_PAGE,
+        ..Default::default()
+    };
+    if let Some(limit) = params.get("limit") {
+        match limit.parse::<usize>() {
+            Ok(limit) if limit > 0 => query.limit = limit.min(MAX_MEDIA_PAGE),
+            _ => return (StatusCode::BAD_REQUEST, "limit must be a positive number").into_response(),
+        }
+    }
+    if let Some(sort) = params.get("sort") {
+        match BlobSort::parse(sort) {
+            Some(sort) => query.sort = sort,
+            None => {
+                return (StatusCode::BAD_REQUEST, "sort must be newest, oldest, largest or smallest").into_response()
+            }
+        }
+    }
+    if let Some(cursor) = params.get("cursor") {
+        match BlobCursor::parse(cursor) {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => return (StatusCode::BAD_REQUEST, "Invalid cursor").into_response(),
+        }
+    }
+    if let Some(uploader) = params.get("uploader") {
+        match nostr::PublicKey::parse(uploader) {
+            Ok(pk) => query.uploader = Some(pk.to_hex()),
+            Err(_) => return (StatusCode::BAD_REQUEST, "Invalid uploader pubkey").into_response(),
+        }
+    }
+
+    match tokio::task::spawn_blocking(move || store.list_page(&query)).await {
+        Ok(Ok(page)) => {
             let config = state.config.read().await;
             let base_url = match config.blossoms.get(&id) {
-                Some(cfg) => {
-                    let scheme = if state.domain == "localhost" {
-                        "http"
-                    } else {
-                        "https"
-                    };
-                    if state.domain == "localhost" {
-                        format!("{}://{}.{}:{}", scheme, cfg.subdomain, state.domain, state.port)
-                    } else {
-                        format!("{}://{}.{}", scheme, cfg.subdomain, state.domain)
-                    }
-                }
+                Some(cfg) => config.blossom_url(&id, cfg),
                 None => String::new(),
             };
             drop(config);
 
-            let descriptors: Vec<blossom_handlers::BlobDescriptor> = metas
+            let blobs = page
+                .blobs
                 .iter()
-                .map(|m| blossom_handlers::BlobDescriptor::from_meta(m, &base_url))
+                .map(|m| MediaItem {
+                    descriptor: blossom_handlers::BlobDescriptor::from_meta(m, &base_url),
+                    uploader: m.uploader.clone(),
+                })
                 .collect();
-            Json(descriptors).into_response()
+            Json(MediaPage {
+                blobs,
+                total: page.total,
+                next_cursor: page.next_cursor.map(|c| c.to_string()),
+            })
+            .into_response()
         }
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Storage error").into_response(),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "Storage error").into_response(),
     }
 }
 
@@ -1421,7 +3837,7 @@ async fn upload_blossom_media(
     Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1486,22 +3902,11 @@ async fn upload_blossom_media(
         content_type
     };
 
-    match store.save_blob(&sha256, &data, &mime, &admin_pubkey) {
+    match store.save_blob(&sha256, data, &mime, &admin_pubkey).await {
         Ok(meta) => {
             let config = state.config.read().await;
             let base_url = match config.blossoms.get(&id) {
-                Some(cfg) => {
-                    let scheme = if state.domain == "localhost" {
-                        "http"
-                    } else {
-                        "https"
-                    };
-                    if state.domain == "localhost" {
-                        format!("{}://{}.{}:{}", scheme, cfg.subdomain, state.domain, state.port)
-                    } else {
-                        format!("{}://{}.{}", scheme, cfg.subdomain, state.domain)
-                    }
-                }
+                Some(cfg) => config.blossom_url(&id, cfg),
                 None => String::new(),
             };
             drop(config);
@@ -1517,25 +3922,123 @@ async fn upload_blossom_media(
     }
 }
 
-async fn delete_blossom_media(
+async fn delete_blossom_media(
+    State(state): State<Arc<GatewayState>>,
+    Path((id, sha256)): Path<(String, String)>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let store = match state.blossom_stores.get(&id) {
+        Some(s) => s.clone(),
+        None => return (StatusCode::NOT_FOUND, "Blossom server not found").into_response(),
+    };
+
+    match store.delete_blob(&sha256).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Blob not found").into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Delete failed").into_response(),
+    }
+}
+
+/// Report the orphaned blobs a GC run would delete, without deleting them.
+async fn blossom_gc_dry_run(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    run_blossom_gc(&state, &id, true).await
+}
+
+/// Delete orphaned blobs now.
+async fn blossom_gc(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    run_blossom_gc(&state, &id, false).await
+}
+
+async fn run_blossom_gc(state: &GatewayState, id: &str, dry_run: bool) -> Response {
+    let store = match state.blossom_stores.get(id) {
+        Some(s) => s.clone(),
+        None => return (StatusCode::NOT_FOUND, "Blossom server not found").into_response(),
+    };
+    let grace_hours = state
+        .config
+        .read()
+        .await
+        .blossoms
+        .get(id)
+        .and_then(|b| b.gc.clone())
+        .unwrap_or_default()
+        .grace_hours;
+    let relays = state.relay_stores.values().cloned().collect();
+
+    match crate::blossom::gc::collect(store, relays, grace_hours, dry_run).await {
+        Ok(report) => {
+            if !dry_run {
+                tracing::info!(blossom = %id, blobs = report.blobs, bytes = report.bytes, "Collected orphaned blobs");
+            }
+            Json(report).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Blob GC failed: {}", e)).into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct BlobPoolInfo {
+    id: String,
+    /// Blossom servers keeping their blobs in this pool.
+    blossoms: Vec<String>,
+    #[serde(flatten)]
+    usage: PoolUsage,
+    /// Bytes not stored thanks to deduplication.
+    saved_bytes: u64,
+}
+
+/// Dedup accounting for every shared blob pool.
+async fn list_blob_pools(
     State(state): State<Arc<GatewayState>>,
-    Path((id, sha256)): Path<(String, String)>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
-    let store = match state.blossom_stores.get(&id) {
-        Some(s) => s.clone(),
-        None => return (StatusCode::NOT_FOUND, "Blossom server not found").into_response(),
-    };
-
-    match store.delete_blob(&sha256) {
-        Ok(true) => StatusCode::NO_CONTENT.into_response(),
-        Ok(false) => (StatusCode::NOT_FOUND, "Blob not found").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Delete failed").into_response(),
+    let blossom_configs = state.config.read().await.blossoms.clone();
+    let mut pools = Vec::new();
+    for (id, pool) in &state.blob_pools {
+        let usage = match pool.usage() {
+            Ok(usage) => usage,
+            Err(e) => {
+                return (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to read blob pool '{}': {}", id, e))
+                    .into_response()
+            }
+        };
+        let mut blossoms: Vec<String> = blossom_configs
+            .iter()
+            .filter(|(_, b)| b.pool.as_deref() == Some(id.as_str()))
+            .map(|(blossom_id, _)| blossom_id.clone())
+            .collect();
+        blossoms.sort();
+        pools.push(BlobPoolInfo {
+            id: id.clone(),
+            blossoms,
+            saved_bytes: usage.referenced_bytes - usage.stored_bytes,
+            usage,
+        });
     }
+    pools.sort_by(|a, b| a.id.cmp(&b.id));
+    Json(pools).into_response()
 }
 
 // --- Paywall Handlers ---
@@ -1544,7 +4047,7 @@ async fn list_paywalls(
     State(state): State<Arc<GatewayState>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1557,7 +4060,7 @@ async fn get_paywall(
     Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1574,6 +4077,12 @@ struct CreatePaywallRequest {
     price_sats: u64,
     #[serde(default = "default_period")]
     period_days: u32,
+    #[serde(default)]
+    reminder: Option<PaywallReminderConfig>,
+    #[serde(default)]
+    zaps: Option<PaywallZapConfig>,
+    #[serde(default)]
+    cashu: Option<PaywallCashuConfig>,
 }
 
 fn default_period() -> u32 {
@@ -1584,7 +4093,7 @@ async fn create_paywall(
     State(state): State<Arc<GatewayState>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1612,6 +4121,9 @@ async fn create_paywall(
         nwc_string: payload.nwc_string,
         price_sats: payload.price_sats,
         period_days: payload.period_days,
+        reminder: payload.reminder,
+        zaps: payload.zaps,
+        cashu: payload.cashu,
     };
 
     if let Err(e) = state
@@ -1638,6 +4150,12 @@ struct UpdatePaywallRequest {
     price_sats: u64,
     #[serde(default = "default_period")]
     period_days: u32,
+    #[serde(default)]
+    reminder: Option<PaywallReminderConfig>,
+    #[serde(default)]
+    zaps: Option<PaywallZapConfig>,
+    #[serde(default)]
+    cashu: Option<PaywallCashuConfig>,
 }
 
 async fn update_paywall(
@@ -1645,7 +4163,7 @@ async fn update_paywall(
     Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1665,10 +4183,28 @@ async fn update_paywall(
         return (StatusCode::BAD_REQUEST, "Price must be greater than 0").into_response();
     }
 
+    // Keep the existing reminder, zap and Cashu settings unless new ones are provided
+    let existing = state.paywall_manager.get_config(&id).await;
+    let reminder = match payload.reminder {
+        Some(r) => Some(r),
+        None => existing.as_ref().and_then(|c| c.reminder.clone()),
+    };
+    let zaps = match payload.zaps {
+        Some(z) => Some(z),
+        None => existing.as_ref().and_then(|c| c.zaps.clone()),
+    };
+    let cashu = match payload.cashu {
+        Some(c) => Some(c),
+        None => existing.and_then(|c| c.cashu),
+    };
+
     let paywall_config = PaywallConfig {
         nwc_string: payload.nwc_string,
         price_sats: payload.price_sats,
         period_days: payload.period_days,
+        reminder,
+        zaps,
+        cashu,
     };
 
     if let Err(e) = state
@@ -1693,7 +4229,7 @@ async fn delete_paywall(
     Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1739,12 +4275,72 @@ struct VerifyNwcRequest {
     nwc_string: String,
 }
 
+/// Fire a test alert at a webhook and report whether it was accepted.
+async fn test_webhook(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    match state.notifier.test(&id).await {
+        Some(Ok(())) => (StatusCode::OK, "Test notification delivered").into_response(),
+        Some(Err(e)) => (StatusCode::BAD_GATEWAY, format!("Test notification failed: {}", e)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Webhook not found").into_response(),
+    }
+}
+
+// --- NIP-65 Relay Lists ---
+
+/// Every configured user's relay list, what it leaves out, and a template
+/// to sign if they have none.
+async fn list_relay_lists(State(state): State<Arc<GatewayState>>, request: Request<Body>) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    let Some(service) = &state.relay_lists else {
+        return (StatusCode::NOT_FOUND, "Relay lists are not configured").into_response();
+    };
+    Json(service.status().await).into_response()
+}
+
+/// Store and publish a relay list the user signed.
+async fn submit_relay_list(
+    State(state): State<Arc<GatewayState>>,
+    Path(pubkey): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+    let Some(service) = state.relay_lists.clone() else {
+        return (StatusCode::NOT_FOUND, "Relay lists are not configured").into_response();
+    };
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 64).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Failed to read body").into_response(),
+    };
+    let event: nostr::Event = match serde_json::from_slice(&body) {
+        Ok(event) => event,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid event: {}", e)).into_response(),
+    };
+    if nostr::PublicKey::parse(&pubkey).ok() != Some(event.pubkey) {
+        return (StatusCode::BAD_REQUEST, "Event is not signed by this pubkey").into_response();
+    }
+    match service.submit(event).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
 async fn verify_nwc_handler(
     State(state): State<Arc<GatewayState>>,
     Path(_id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1775,7 +4371,7 @@ async fn get_paywall_whitelist(
     Path(id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1785,13 +4381,127 @@ async fn get_paywall_whitelist(
     }
 }
 
+/// Hand over the ecash a paywall has collected from Cashu payments as one
+/// token, and stop holding it.
+async fn withdraw_paywall_ecash(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    match state.paywall_manager.withdraw_ecash(&id).await {
+        Ok(Some((token, amount_sats))) => {
+            Json(serde_json::json!({ "token": token, "amount_sats": amount_sats })).into_response()
+        }
+        Ok(None) => Json(serde_json::json!({ "token": null, "amount_sats": 0 })).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct WhitelistEntryRequest {
+    pubkey: String,
+    /// Absolute expiration (unix seconds). Takes precedence over `days`.
+    expires_at: Option<u64>,
+    /// Access duration from now. Defaults to the paywall's `period_days`.
+    days: Option<u32>,
+}
+
+async fn add_paywall_whitelist_entry(
+    State(state): State<Arc<GatewayState>>,
+    Path(id): Path<String>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let body = match axum::body::to_bytes(request.into_body(), 1024 * 64).await {
+        Ok(b) => b,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid body").into_response(),
+    };
+
+    let payload: WhitelistEntryRequest = match serde_json::from_slice(&body) {
+        Ok(p) => p,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response()
+        }
+    };
+
+    let pubkey = match nostr::PublicKey::parse(&payload.pubkey) {
+        Ok(pk) => pk,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid pubkey").into_response(),
+    };
+
+    let period_days = match state.paywall_manager.get_paywall_info(&id).await {
+        Some(info) => info.period_days,
+        None => return (StatusCode::NOT_FOUND, "Paywall not found").into_response(),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let expires_at = match (payload.expires_at, payload.days) {
+        (Some(ts), _) => ts,
+        (None, Some(days)) => now + days as u64 * 24 * 3600,
+        (None, None) => now + period_days as u64 * 24 * 3600,
+    };
+
+    if expires_at <= now {
+        return (StatusCode::BAD_REQUEST, "Expiration must be in the future").into_response();
+    }
+
+    if let Err(e) = state
+        .paywall_manager
+        .add_to_whitelist(&id, pubkey, expires_at)
+        .await
+    {
+        return (StatusCode::NOT_FOUND, e).into_response();
+    }
+
+    Json(crate::paywall::WhitelistEntry {
+        pubkey: pubkey.to_hex(),
+        expires_at,
+    })
+    .into_response()
+}
+
+async fn remove_paywall_whitelist_entry(
+    State(state): State<Arc<GatewayState>>,
+    Path((id, pubkey)): Path<(String, String)>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let pubkey = match nostr::PublicKey::parse(&pubkey) {
+        Ok(pk) => pk,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid pubkey").into_response(),
+    };
+
+    match state
+        .paywall_manager
+        .remove_from_whitelist(&id, &pubkey)
+        .await
+    {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Pubkey not on whitelist").into_response(),
+        Err(e) => (StatusCode::NOT_FOUND, e).into_response(),
+    }
+}
+
 // --- Restart Handler ---
 
 async fn restart_handler(
     State(state): State<Arc<GatewayState>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1812,7 +4522,7 @@ async fn update_handler(
     State(state): State<Arc<GatewayState>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1855,7 +4565,7 @@ async fn update_status_handler(
     State(state): State<Arc<GatewayState>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1913,13 +4623,27 @@ struct RelayStatsResponse {
     events_stored: u64,
     events_saved: u64,
     events_rejected: u64,
+    spam_rejected: u64,
     queries_served: u64,
     bytes_rx: u64,
     bytes_tx: u64,
     storage_bytes: u64,
+    slow_clients_closed: u64,
+    broadcast_dropped: u64,
+    storage_reads: u64,
+    storage_writes: u64,
+    query_cache_hits: u64,
+    query_cache_misses: u64,
+    /// Mean latency of storage calls from connections, in milliseconds.
+    avg_read_ms: f64,
+    avg_write_ms: f64,
+    /// IPs held in the relay's rate-limit tracker.
+    tracked_ips: usize,
+    /// Open LMDB read transactions and the age of the oldest.
+    read_txns: crate::storage::readers::ReadTxnStats,
 }
 
-fn read_relay_stats(relay_id: &str, stats: &RelayStats) -> RelayStatsResponse {
+fn read_relay_stats(state: &GatewayState, relay_id: &str, stats: &RelayStats) -> RelayStatsResponse {
     RelayStatsResponse {
         relay_id: relay_id.to_string(),
         active_connections: stats.active_connections.load(Relaxed),
@@ -1927,10 +4651,28 @@ fn read_relay_stats(relay_id: &str, stats: &RelayStats) -> RelayStatsResponse {
         events_stored: stats.event_count.load(Relaxed),
         events_saved: stats.events_saved.load(Relaxed),
         events_rejected: stats.events_rejected.load(Relaxed),
+        spam_rejected: stats.spam_rejected.load(Relaxed),
         queries_served: stats.queries_served.load(Relaxed),
         bytes_rx: stats.bytes_rx.load(Relaxed),
         bytes_tx: stats.bytes_tx.load(Relaxed),
         storage_bytes: stats.storage_bytes.load(Relaxed),
+        slow_clients_closed: stats.slow_clients_closed.load(Relaxed),
+        broadcast_dropped: stats.broadcast_dropped.load(Relaxed),
+        storage_reads: stats.storage_reads.load(Relaxed),
+        storage_writes: stats.storage_writes.load(Relaxed),
+        query_cache_hits: stats.query_cache_hits.load(Relaxed),
+        query_cache_misses: stats.query_cache_misses.load(Relaxed),
+        avg_read_ms: avg_ms(&stats.storage_reads, &stats.storage_read_micros),
+        avg_write_ms: avg_ms(&stats.storage_writes, &stats.storage_write_micros),
+        tracked_ips: state.relay_ip_trackers.get(relay_id).map_or(0, |t| t.len()),
+        read_txns: state.relay_stores.get(relay_id).map(|s| s.read_txns()).unwrap_or_default(),
+    }
+}
+
+fn avg_ms(count: &std::sync::atomic::AtomicU64, micros: &std::sync::atomic::AtomicU64) -> f64 {
+    match count.load(Relaxed) {
+        0 => 0.0,
+        n => micros.load(Relaxed) as f64 / n as f64 / 1000.0,
     }
 }
 
@@ -1943,6 +4685,8 @@ struct GlobalStatsResponse {
     total_bytes_rx: u64,
     total_bytes_tx: u64,
     relay_count: usize,
+    /// IPs held in the gateway-wide rate-limit tracker.
+    gateway_tracked_ips: usize,
     relays: Vec<RelayStatsResponse>,
     system: crate::stats::SystemStats,
 }
@@ -1951,7 +4695,7 @@ async fn global_stats_handler(
     State(state): State<Arc<GatewayState>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -1968,7 +4712,7 @@ async fn global_stats_handler(
     let mut total_tx: u64 = 0;
 
     for (id, stats) in &state.relay_stats {
-        let r = read_relay_stats(id, stats);
+        let r = read_relay_stats(&state, id, stats);
         total_active += r.active_connections;
         total_events += r.events_stored;
         total_storage += r.storage_bytes;
@@ -1987,12 +4731,165 @@ async fn global_stats_handler(
         total_bytes_rx: total_rx,
         total_bytes_tx: total_tx,
         relay_count: relays.len(),
+        gateway_tracked_ips: state.ip_tracker.len(),
         relays,
         system,
     })
     .into_response()
 }
 
+#[derive(Serialize)]
+struct OverviewRelay {
+    id: String,
+    name: String,
+    subdomain: String,
+    event_count: u64,
+    db_size_bytes: u64,
+    /// Everything in the relay's database directory, as last measured.
+    disk_bytes: u64,
+    active_connections: i64,
+}
+
+#[derive(Serialize)]
+struct OverviewBlossom {
+    id: String,
+    name: String,
+    subdomain: String,
+    blob_count: u64,
+    storage_bytes: u64,
+    /// Everything under the storage path, as last measured.
+    disk_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct OverviewWot {
+    id: String,
+    ready: bool,
+    status: crate::wot::WotStatus,
+    pubkey_count: usize,
+}
+
+#[derive(Serialize)]
+struct OverviewResponse {
+    relays: Vec<OverviewRelay>,
+    blossoms: Vec<OverviewBlossom>,
+    wots: Vec<OverviewWot>,
+    paywalls: Vec<crate::paywall::PaywallInfo>,
+    total_events: u64,
+    total_db_bytes: u64,
+    total_active_connections: i64,
+    total_blobs: u64,
+    total_blob_bytes: u64,
+    total_revenue_sats: u64,
+    /// Stores over `alerts.storage_warn_bytes`.
+    storage_over_threshold: Vec<String>,
+}
+
+/// Everything the dashboard landing page shows, in one round trip.
+async fn overview_handler(
+    State(state): State<Arc<GatewayState>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let disk = state.disk_usage.report();
+    let mut relays = Vec::new();
+    for (id, relay_config) in &state.relay_configs {
+        let Some(store) = state.relay_stores.get(id) else {
+            continue;
+        };
+        let mdb_path = std::path::Path::new(store.db_path()).join("data.mdb");
+        let store = store.clone();
+        let event_count = match tokio::task::spawn_blocking(move || store.event_count()).await {
+            Ok(Ok(count)) => count,
+            _ => 0,
+        };
+        let db_size_bytes = tokio::fs::metadata(&mdb_path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0);
+        let active_connections = state
+            .relay_stats
+            .get(id)
+            .map(|s| s.active_connections.load(Relaxed))
+            .unwrap_or(0);
+        relays.push(OverviewRelay {
+            id: id.clone(),
+            name: relay_config.name.clone(),
+            subdomain: relay_config.subdomain.clone(),
+            event_count,
+            db_size_bytes,
+            disk_bytes: disk.relays.get(id).copied().unwrap_or(0),
+            active_connections,
+        });
+    }
+    relays.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let blossom_configs = state.config.read().await.blossoms.clone();
+    let mut blossoms = Vec::new();
+    for (id, store) in &state.blossom_stores {
+        let Some(blossom_config) = blossom_configs.get(id) else {
+            continue;
+        };
+        // Walks every blob's metadata
+        let store = store.clone();
+        let (blob_count, storage_bytes) = match tokio::task::spawn_blocking(move || store.usage()).await {
+            Ok(Ok(usage)) => usage,
+            Ok(Err(e)) => {
+                tracing::warn!("Failed to read blossom '{}' usage: {}", id, e);
+                (0, 0)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to read blossom '{}' usage: {}", id, e);
+                (0, 0)
+            }
+        };
+        blossoms.push(OverviewBlossom {
+            id: id.clone(),
+            name: blossom_config.name.clone(),
+            subdomain: blossom_config.subdomain.clone(),
+            blob_count,
+            storage_bytes,
+            disk_bytes: disk.blossoms.get(id).copied().unwrap_or(0),
+        });
+    }
+    blossoms.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut wots: Vec<OverviewWot> = state
+        .wot_manager
+        .list_wots()
+        .await
+        .into_iter()
+        .map(|w| OverviewWot {
+            ready: matches!(w.status, crate::wot::WotStatus::Ready),
+            id: w.id,
+            status: w.status,
+            pubkey_count: w.pubkey_count,
+        })
+        .collect();
+    wots.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let mut paywalls = state.paywall_manager.list_paywalls().await;
+    paywalls.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Json(OverviewResponse {
+        total_events: relays.iter().map(|r| r.event_count).sum(),
+        total_db_bytes: relays.iter().map(|r| r.db_size_bytes).sum(),
+        total_active_connections: relays.iter().map(|r| r.active_connections).sum(),
+        total_blobs: blossoms.iter().map(|b| b.blob_count).sum(),
+        total_blob_bytes: blossoms.iter().map(|b| b.storage_bytes).sum(),
+        total_revenue_sats: paywalls.iter().map(|p| p.revenue_sats).sum(),
+        storage_over_threshold: disk.over_threshold,
+        relays,
+        blossoms,
+        wots,
+        paywalls,
+    })
+    .into_response()
+}
+
 #[derive(Serialize)]
 struct RelayStatsDetailResponse {
     #[serde(flatten)]
@@ -2005,7 +4902,7 @@ async fn relay_stats_handler(
     Path(relay_id): Path<String>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    if let Err(resp) = require_auth(request.headers(), &state.sessions).await {
+    if let Err(resp) = require_auth(&state, &request).await {
         return resp;
     }
 
@@ -2014,7 +4911,7 @@ async fn relay_stats_handler(
         None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
     };
 
-    let r = read_relay_stats(&relay_id, stats);
+    let r = read_relay_stats(&state, &relay_id, stats);
 
     let history = match state.time_series.get(&relay_id) {
         Some(ts) => ts.read().await.entries(),
@@ -2028,6 +4925,194 @@ async fn relay_stats_handler(
     .into_response()
 }
 
+const DEFAULT_HISTORY_DAYS: u64 = 30;
+
+#[derive(Serialize)]
+struct DailyStatsResponse {
+    relay_id: String,
+    days: u64,
+    history: Vec<crate::daily_stats::DailyStats>,
+}
+
+/// A relay's daily totals for the last `?days=` days (default 30), oldest
+/// first, from the persisted history.
+async fn relay_daily_stats_handler(
+    State(state): State<Arc<GatewayState>>,
+    Path(relay_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    if !state.relay_stats.contains_key(&relay_id) {
+        return (StatusCode::NOT_FOUND, "Relay not found").into_response();
+    }
+    let Some(daily) = state.daily_stats.clone() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Daily stats are unavailable").into_response();
+    };
+    let days = match params.get("days").map(|d| d.parse::<u64>()) {
+        None => DEFAULT_HISTORY_DAYS,
+        Some(Ok(days)) if days > 0 => days.min(crate::daily_stats::RETENTION_DAYS),
+        Some(_) => return (StatusCode::BAD_REQUEST, "days must be a positive number").into_response(),
+    };
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let id = relay_id.clone();
+    match tokio::task::spawn_blocking(move || daily.history(&id, days, now)).await {
+        Ok(Ok(history)) => Json(DailyStatsResponse {
+            relay_id,
+            days,
+            history,
+        })
+        .into_response(),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read daily stats").into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct TimeSeriesResponse {
+    relay_id: String,
+    range_secs: u64,
+    interval_secs: u64,
+    points: Vec<crate::stats::TimeSeriesPoint>,
+}
+
+async fn relay_timeseries_handler(
+    State(state): State<Arc<GatewayState>>,
+    Path(relay_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let ring = match state.time_series.get(&relay_id) {
+        Some(r) => r,
+        None => return (StatusCode::NOT_FOUND, "Relay not found").into_response(),
+    };
+
+    let range_secs = match params.get("range") {
+        Some(r) => match crate::stats::parse_range(r) {
+            Some(secs) => secs,
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "Invalid range (expected e.g. 30m, 6h, 24h)",
+                )
+                    .into_response()
+            }
+        },
+        None => crate::stats::MAX_RANGE_SECS,
+    };
+
+    let since = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .saturating_sub(range_secs);
+    let points = ring.read().await.rates_since(since);
+
+    Json(TimeSeriesResponse {
+        relay_id,
+        range_secs,
+        interval_secs: crate::stats::SAMPLE_INTERVAL_SECS,
+        points,
+    })
+    .into_response()
+}
+
+/// Stream a relay's accepted events as server-sent events. With
+/// `?rejected=true`, rejected events and their reasons are included;
+/// `?kinds=1,7` limits the stream to those kinds.
+/// The relay's latest rejected events, newest first. `?limit=` caps how
+/// many (all that are kept by default); `?pubkey=` keeps one author's.
+async fn relay_rejections_handler(
+    State(state): State<Arc<GatewayState>>,
+    Path(relay_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let Some(firehose) = state.firehoses.get(&relay_id) else {
+        return (StatusCode::NOT_FOUND, "Relay not found").into_response();
+    };
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        None => usize::MAX,
+        Some(Ok(limit)) if limit > 0 => limit,
+        Some(_) => return (StatusCode::BAD_REQUEST, "limit must be a positive number").into_response(),
+    };
+    let pubkey = match params.get("pubkey").map(nostr::PublicKey::parse) {
+        None => None,
+        Some(Ok(pk)) => Some(pk.to_hex()),
+        Some(Err(_)) => return (StatusCode::BAD_REQUEST, "Invalid pubkey").into_response(),
+    };
+    let rejections: Vec<_> = firehose
+        .rejections(usize::MAX)
+        .into_iter()
+        .filter(|r| pubkey.as_ref().is_none_or(|pk| r.pubkey == *pk))
+        .take(limit)
+        .collect();
+    Json(rejections).into_response()
+}
+
+async fn relay_firehose_handler(
+    State(state): State<Arc<GatewayState>>,
+    Path(relay_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    request: Request<Body>,
+) -> impl IntoResponse {
+    if let Err(resp) = require_auth(&state, &request).await {
+        return resp;
+    }
+
+    let Some(firehose) = state.firehoses.get(&relay_id) else {
+        return (StatusCode::NOT_FOUND, "Relay not found").into_response();
+    };
+    let include_rejected = params.get("rejected").is_some_and(|v| v == "true" || v == "1");
+    let kinds = match params.get("kinds") {
+        Some(list) => match list.split(',').map(|k| k.trim().parse::<u64>()).collect::<Result<Vec<_>, _>>() {
+            Ok(kinds) => Some(kinds),
+            Err(_) => return (StatusCode::BAD_REQUEST, "Invalid kinds (expected e.g. 1,7)").into_response(),
+        },
+        None => None,
+    };
+
+    let stream = futures_util::stream::unfold(firehose.subscribe(), move |mut rx| {
+        let kinds = kinds.clone();
+        async move {
+            loop {
+                let sse = match rx.recv().await {
+                    Ok(entry) => {
+                        if !entry.accepted && !include_rejected {
+                            continue;
+                        }
+                        if kinds.as_ref().is_some_and(|k| !k.contains(&entry.event.kind.as_u64())) {
+                            continue;
+                        }
+                        SseEvent::default()
+                            .event(if entry.accepted { "accepted" } else { "rejected" })
+                            .json_data(entry.as_ref())
+                            .unwrap_or_default()
+                    }
+                    // Tell the watcher how much it missed rather than silently skipping
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                        SseEvent::default().event("lagged").data(missed.to_string())
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+                };
+                return Some((Ok::<_, std::convert::Infallible>(sse), rx));
+            }
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
 // --- Caddy On-Demand TLS ---
 
 async fn caddy_ask_handler(
@@ -2043,14 +5128,16 @@ async fn caddy_ask_handler(
         return StatusCode::OK;
     }
 
-    // Check relay/blossom subdomains
+    // Check relay/blossom subdomains, which path routing doesn't use
     let expected_suffix = format!(".{}", state.domain);
     if domain.ends_with(&expected_suffix) {
         let subdomain = &domain[..domain.len() - expected_suffix.len()];
         let config = state.config.read().await;
-        let is_relay = config.relays.values().any(|r| r.subdomain == subdomain);
-        let is_blossom = config.blossoms.values().any(|b| b.subdomain == subdomain);
-        if is_relay || is_blossom {
+        let by_subdomain = config.routing == RoutingMode::Subdomain;
+        let is_relay = by_subdomain && config.relays.values().any(|r| r.subdomain == subdomain);
+        let is_blossom = by_subdomain && config.blossoms.values().any(|b| b.subdomain == subdomain);
+        let is_admin = config.admin.subdomain.as_deref() == Some(subdomain);
+        if is_relay || is_blossom || is_admin {
             return StatusCode::OK;
         }
     }
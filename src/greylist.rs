@@ -0,0 +1,306 @@
+//! Greylisting: pubkeys new to a relay write under stricter limits until
+//! they have published enough events and been around long enough. First
+//! sightings are kept in a small JSON file per relay, flushed periodically.
+//! Only pubkeys still on probation are kept: graduates are dropped (their
+//! stored events vouch for them if they're seen again), as are newcomers
+//! that went quiet, and the list is capped.
+
+use nostr::{Event, PublicKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::GreylistConfig;
+use crate::policy::leading_zero_bits;
+use crate::rate_limit::PubkeyTracker;
+
+/// Most pubkeys on probation tracked per relay; beyond it the oldest
+/// sightings are dropped and new pubkeys stay newcomers without an entry.
+const MAX_ENTRIES: usize = 100_000;
+/// How long a newcomer that never graduates is remembered.
+const STALE_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct FirstSeen {
+    first_seen: u64,
+    events: u32,
+}
+
+pub struct Greylist {
+    config: GreylistConfig,
+    entries: Mutex<HashMap<PublicKey, FirstSeen>>,
+    newcomer_rates: PubkeyTracker,
+    path: Option<PathBuf>,
+    dirty: AtomicBool,
+}
+
+impl Greylist {
+    /// Load first sightings from `path`, starting empty if it doesn't exist.
+    pub fn load(config: GreylistConfig, path: PathBuf) -> Self {
+        let entries = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice::<HashMap<String, FirstSeen>>(&data)
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Failed to parse greylist file {}: {}", path.display(), e);
+                    HashMap::new()
+                })
+                .into_iter()
+                .filter_map(|(pk, seen)| Some((PublicKey::from_hex(&pk).ok()?, seen)))
+                .collect(),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            config,
+            entries: Mutex::new(entries),
+            newcomer_rates: PubkeyTracker::new(),
+            path: Some(path),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// A greylist that is never written to disk.
+    pub fn in_memory(config: GreylistConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+            newcomer_rates: PubkeyTracker::new(),
+            path: None,
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether `pubkey` has been seen on this relay before.
+    pub fn has_seen(&self, pubkey: &PublicKey) -> bool {
+        self.entries.lock().unwrap().contains_key(pubkey)
+    }
+
+    /// Record the first sighting of `pubkey`. Authors that already have
+    /// events stored (e.g. from before greylisting was enabled) start out
+    /// graduated.
+    pub fn first_seen(&self, pubkey: &PublicKey, has_events: bool) {
+        let seen = if has_events {
+            FirstSeen {
+                first_seen: 0,
+                events: self.config.first_events,
+            }
+        } else {
+            FirstSeen {
+                first_seen: now(),
+                events: 0,
+            }
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() < MAX_ENTRIES {
+            entries.entry(*pubkey).or_insert(seen);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether `pubkey` is still on probation.
+    pub fn is_newcomer(&self, pubkey: &PublicKey) -> bool {
+        let Some(seen) = self.entries.lock().unwrap().get(pubkey).copied() else {
+            return true;
+        };
+        !self.graduated(&seen, now())
+    }
+
+    fn graduated(&self, seen: &FirstSeen, now: u64) -> bool {
+        seen.events >= self.config.first_events && now.saturating_sub(seen.first_seen) >= self.config.first_secs
+    }
+
+    /// Apply the newcomer limits to `event`. Returns the NIP-01 rejection
+    /// message if it breaks one. Established authors always pass.
+    pub fn check(&self, event: &Event) -> Result<(), String> {
+        if !self.is_newcomer(&event.pubkey) {
+            return Ok(());
+        }
+        if let Some(min_pow) = self.config.min_pow {
+            let pow = leading_zero_bits(event.id.as_bytes());
+            if pow < min_pow {
+                return Err(format!("pow: new pubkeys need {} bits of proof-of-work ({} given)", min_pow, pow));
+            }
+        }
+        if !self
+            .newcomer_rates
+            .check_write_rate(&event.pubkey, self.config.writes_per_minute, None)
+        {
+            return Err("rate-limited: new pubkeys are limited while greylisted".into());
+        }
+        Ok(())
+    }
+
+    /// Count an accepted event towards its author's graduation.
+    pub fn record_accepted(&self, pubkey: &PublicKey) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(pubkey) {
+            return;
+        }
+        let seen = entries.entry(*pubkey).or_insert(FirstSeen {
+            first_seen: now(),
+            events: 0,
+        });
+        if seen.events < self.config.first_events {
+            seen.events += 1;
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Drop graduates and newcomers not heard from in `STALE_SECS`, then the
+    /// oldest sightings beyond `MAX_ENTRIES`.
+    pub fn prune(&self) {
+        let now = now();
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, seen| {
+            !self.graduated(seen, now) && now.saturating_sub(seen.first_seen) < self.config.first_secs.max(STALE_SECS)
+        });
+        if entries.len() > MAX_ENTRIES {
+            let mut by_age: Vec<(u64, PublicKey)> = entries.iter().map(|(pk, seen)| (seen.first_seen, *pk)).collect();
+            by_age.sort_unstable();
+            for (_, pk) in &by_age[..entries.len() - MAX_ENTRIES] {
+                entries.remove(pk);
+            }
+        }
+        if entries.len() != before {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Write the first sightings to disk if they changed since the last flush.
+    pub async fn flush(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let snapshot: HashMap<String, FirstSeen> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(pk, seen)| (pk.to_hex(), *seen))
+            .collect();
+        if let Some(parent) = path.parent() {
+            let _ = tokio::fs::create_dir_all(parent).await;
+        }
+        let result = match serde_json::to_vec(&snapshot) {
+            Ok(data) => tokio::fs::write(path, data).await.map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            self.dirty.store(true, Ordering::Relaxed);
+            tracing::warn!("Failed to save greylist to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Prune and flush every `interval` and drop stale newcomer rate
+    /// entries, for as long as the greylist is alive elsewhere.
+    pub fn start_flush(self: &Arc<Self>, interval: Duration) {
+        let greylist = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval.max(Duration::from_secs(1)));
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(greylist) = greylist.upgrade() else { break };
+                greylist.prune();
+                greylist.flush().await;
+                greylist.newcomer_rates.cleanup();
+            }
+        });
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys};
+
+    fn config() -> GreylistConfig {
+        GreylistConfig {
+            first_events: 2,
+            first_secs: 0,
+            writes_per_minute: Some(1),
+            min_pow: None,
+        }
+    }
+
+    fn note(keys: &Keys) -> Event {
+        EventBuilder::text_note("hi", []).to_event(keys).unwrap()
+    }
+
+    #[test]
+    fn newcomers_graduate_after_enough_events() {
+        let greylist = Greylist::in_memory(config());
+        let keys = Keys::generate();
+        greylist.first_seen(&keys.public_key(), false);
+
+        assert!(greylist.check(&note(&keys)).is_ok());
+        greylist.record_accepted(&keys.public_key());
+        let err = greylist.check(&note(&keys)).unwrap_err();
+        assert!(err.starts_with("rate-limited"), "{}", err);
+
+        greylist.record_accepted(&keys.public_key());
+        assert!(!greylist.is_newcomer(&keys.public_key()));
+        assert!(greylist.check(&note(&keys)).is_ok());
+    }
+
+    #[test]
+    fn existing_authors_and_pow() {
+        let greylist = Greylist::in_memory(GreylistConfig {
+            min_pow: Some(200),
+            ..config()
+        });
+        let veteran = Keys::generate();
+        greylist.first_seen(&veteran.public_key(), true);
+        assert!(greylist.check(&note(&veteran)).is_ok());
+
+        let newcomer = Keys::generate();
+        greylist.first_seen(&newcomer.public_key(), false);
+        assert!(greylist.check(&note(&newcomer)).unwrap_err().starts_with("pow:"));
+    }
+
+    #[tokio::test]
+    async fn sightings_survive_a_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("greylist").join("relay.json");
+        let keys = Keys::generate();
+
+        let greylist = Greylist::load(config(), path.clone());
+        greylist.first_seen(&keys.public_key(), false);
+        greylist.record_accepted(&keys.public_key());
+        greylist.flush().await;
+
+        let reloaded = Greylist::load(config(), path);
+        assert!(reloaded.has_seen(&keys.public_key()));
+        reloaded.record_accepted(&keys.public_key());
+        assert!(!reloaded.is_newcomer(&keys.public_key()));
+    }
+
+    #[test]
+    fn graduates_and_stale_newcomers_are_pruned() {
+        let greylist = Greylist::in_memory(config());
+        let (graduate, newcomer, stale) = (Keys::generate(), Keys::generate(), Keys::generate());
+        greylist.first_seen(&graduate.public_key(), true);
+        greylist.first_seen(&newcomer.public_key(), false);
+        greylist.first_seen(&stale.public_key(), false);
+        greylist.entries.lock().unwrap().get_mut(&stale.public_key()).unwrap().first_seen = now() - STALE_SECS;
+
+        greylist.prune();
+        assert!(!greylist.has_seen(&graduate.public_key()));
+        assert!(greylist.has_seen(&newcomer.public_key()));
+        assert!(!greylist.has_seen(&stale.public_key()));
+        // Forgotten pubkeys are newcomers again until they're seen
+        assert!(greylist.is_newcomer(&stale.public_key()));
+    }
+}
@@ -0,0 +1,47 @@
+use nostr::Keys;
+use std::path::Path;
+
+/// Load the relay's signing keypair from `<dir>/<relay_id>.key`, generating
+/// and storing a new one on first start.
+pub fn load_or_generate(dir: &Path, relay_id: &str) -> Result<Keys, anyhow::Error> {
+    let path = dir.join(format!("{}.key", relay_id));
+
+    if path.exists() {
+        let secret = std::fs::read_to_string(&path)?;
+        return Keys::parse(secret.trim())
+            .map_err(|e| anyhow::anyhow!("Invalid relay key in {}: {}", path.display(), e));
+    }
+
+    std::fs::create_dir_all(dir)?;
+    let keys = Keys::generate();
+    let secret = keys.secret_key()?.to_secret_hex();
+    std::fs::write(&path, secret)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    tracing::info!(
+        "Generated signing key for relay '{}': {}",
+        relay_id,
+        keys.public_key().to_hex()
+    );
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_is_generated_once_and_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = load_or_generate(dir.path(), "outbox").unwrap();
+        let second = load_or_generate(dir.path(), "outbox").unwrap();
+        assert_eq!(first.public_key(), second.public_key());
+
+        let other = load_or_generate(dir.path(), "inbox").unwrap();
+        assert_ne!(first.public_key(), other.public_key());
+    }
+}
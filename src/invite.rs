@@ -0,0 +1,311 @@
+//! Members of private relays: pubkeys that may write alongside the
+//! relay's allow-list. With `policy.write.invites` on, the admin creates
+//! codes and whoever redeems one on the relay's page with their npub becomes
+//! a member; with `policy.write.access_requests` on, users ask for access on
+//! that page and the admin approves or denies them. Codes, pending requests
+//! and members are kept in `<data_dir>/invites/<relay_id>.json`.
+
+use nostr::PublicKey;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// Letters and digits that can't be mistaken for one another.
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LEN: usize = 10;
+
+/// Pending access requests kept per relay; new ones are refused beyond it.
+const MAX_PENDING_REQUESTS: usize = 1000;
+/// Longest message accepted with an access request, in characters.
+const MAX_REQUEST_MESSAGE: usize = 500;
+
+/// A relay's members, shared with its policy.
+#[derive(Clone, Default)]
+pub struct InviteSet {
+    inner: Arc<std::sync::RwLock<HashSet<PublicKey>>>,
+}
+
+impl InviteSet {
+    pub fn contains(&self, pk: &PublicKey) -> bool {
+        self.inner.read().unwrap().contains(pk)
+    }
+
+    fn replace(&self, members: HashSet<PublicKey>) {
+        *self.inner.write().unwrap() = members;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InviteCode {
+    pub code: String,
+    pub created_at: u64,
+    /// Unix time after which the code can't be redeemed; `None` never expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    pub max_uses: u32,
+    #[serde(default)]
+    pub uses: u32,
+}
+
+impl InviteCode {
+    fn usable(&self, now: u64) -> bool {
+        self.uses < self.max_uses && self.expires_at.is_none_or(|at| now < at)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccessRequest {
+    /// Hex pubkey asking for write access.
+    pub pubkey: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub message: String,
+    pub requested_at: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct InviteBook {
+    pub codes: Vec<InviteCode>,
+    /// Hex pubkeys that redeemed a code or were approved.
+    pub members: Vec<String>,
+    /// Access requests awaiting the admin, oldest first.
+    #[serde(default)]
+    pub requests: Vec<AccessRequest>,
+}
+
+struct Entry {
+    book: InviteBook,
+    set: InviteSet,
+}
+
+pub struct InviteManager {
+    dir: PathBuf,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl InviteManager {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Enable members for `relay_id`, loading its codes, requests and
+    /// members, and return the set its policy checks.
+    pub async fn enable(&self, relay_id: &str) -> InviteSet {
+        let mut entries = self.entries.lock().await;
+        if let Some(entry) = entries.get(relay_id) {
+            return entry.set.clone();
+        }
+        let book: InviteBook = match tokio::fs::read(self.path(relay_id)).await {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                tracing::warn!(relay = %relay_id, "Ignoring unreadable invite file: {}", e);
+                InviteBook::default()
+            }),
+            Err(_) => InviteBook::default(),
+        };
+        let set = InviteSet::default();
+        set.replace(members(&book));
+        entries.insert(relay_id.to_string(), Entry { book, set: set.clone() });
+        set
+    }
+
+    pub async fn is_enabled(&self, relay_id: &str) -> bool {
+        self.entries.lock().await.contains_key(relay_id)
+    }
+
+    /// A relay's codes, requests and members, or `None` if it has none.
+    pub async fn book(&self, relay_id: &str) -> Option<InviteBook> {
+        let entries = self.entries.lock().await;
+        let entry = entries.get(relay_id)?;
+        Some(InviteBook {
+            codes: entry.book.codes.clone(),
+            members: entry.book.members.clone(),
+            requests: entry.book.requests.clone(),
+        })
+    }
+
+    /// Create a code redeemable `max_uses` times until `expires_at`.
+    pub async fn create(&self, relay_id: &str, max_uses: u32, expires_at: Option<u64>) -> Result<InviteCode, String> {
+        let mut entries = self.entries.lock().await;
+        let entry = entries.get_mut(relay_id).ok_or("Invites are not enabled for this relay")?;
+        let invite = InviteCode {
+            code: new_code(),
+            created_at: now_secs(),
+            expires_at,
+            max_uses: max_uses.max(1),
+            uses: 0,
+        };
+        entry.book.codes.push(invite.clone());
+        self.persist(relay_id, &entry.book).await?;
+        Ok(invite)
+    }
+
+    /// Delete a code. Returns `false` if there was no such code.
+    pub async fn revoke(&self, relay_id: &str, code: &str) -> Result<bool, String> {
+        let mut entries = self.entries.lock().await;
+        let entry = entries.get_mut(relay_id).ok_or("Invites are not enabled for this relay")?;
+        let before = entry.book.codes.len();
+        entry.book.codes.retain(|c| !c.code.eq_ignore_ascii_case(code));
+        if entry.book.codes.len() == before {
+            return Ok(false);
+        }
+        self.persist(relay_id, &entry.book).await?;
+        Ok(true)
+    }
+
+    /// Take a member's write access away. Returns `false` if they weren't one.
+    pub async fn remove_member(&self, relay_id: &str, pubkey: &PublicKey) -> Result<bool, String> {
+        let mut entries = self.entries.lock().await;
+        let entry = entries.get_mut(relay_id).ok_or("Invites are not enabled for this relay")?;
+        let hex = pubkey.to_hex();
+        let before = entry.book.members.len();
+        entry.book.members.retain(|m| *m != hex);
+        if entry.book.members.len() == before {
+            return Ok(false);
+        }
+        entry.set.replace(members(&entry.book));
+        self.persist(relay_id, &entry.book).await?;
+        Ok(true)
+    }
+
+    /// Use up one redemption of `code` to make `pubkey` a member. Redeeming
+    /// while already a member leaves the code untouched.
+    pub async fn redeem(&self, relay_id: &str, code: &str, pubkey: &PublicKey) -> Result<(), String> {
+        let mut entries = self.entries.lock().await;
+        let entry = entries.get_mut(relay_id).ok_or("Invites are not enabled for this relay")?;
+        let now = now_secs();
+        let invite = entry
+            .book
+            .codes
+            .iter_mut()
+            .find(|c| c.code.eq_ignore_ascii_case(code.trim()) && c.usable(now))
+            .ok_or("Invalid or expired invite code")?;
+        if entry.set.contains(pubkey) {
+            return Ok(());
+        }
+        invite.uses += 1;
+        entry.book.members.push(pubkey.to_hex());
+        entry.set.replace(members(&entry.book));
+        self.persist(relay_id, &entry.book).await?;
+        tracing::info!(relay = %relay_id, "Invite redeemed by {}", pubkey.to_hex());
+        Ok(())
+    }
+
+    /// Queue a request for write access, replacing an earlier one from the
+    /// same pubkey.
+    pub async fn request_access(&self, relay_id: &str, pubkey: &PublicKey, message: &str) -> Result<(), String> {
+        let mut entries = self.entries.lock().await;
+        let entry = entries.get_mut(relay_id).ok_or("Access requests are not enabled for this relay")?;
+        if entry.set.contains(pubkey) {
+            return Err("This pubkey can already write to the relay".to_string());
+        }
+        let hex = pubkey.to_hex();
+        entry.book.requests.retain(|r| r.pubkey != hex);
+        if entry.book.requests.len() >= MAX_PENDING_REQUESTS {
+            return Err("Too many pending requests, try again later".to_string());
+        }
+        entry.book.requests.push(AccessRequest {
+            pubkey: hex,
+            message: message.trim().chars().take(MAX_REQUEST_MESSAGE).collect(),
+            requested_at: now_secs(),
+        });
+        self.persist(relay_id, &entry.book).await
+    }
+
+    /// Settle `pubkey`'s pending request, making them a member if
+    /// `approve`. Returns `false` if they had no pending request.
+    pub async fn decide(&self, relay_id: &str, pubkey: &PublicKey, approve: bool) -> Result<bool, String> {
+        let mut entries = self.entries.lock().await;
+        let entry = entries.get_mut(relay_id).ok_or("Access requests are not enabled for this relay")?;
+        let hex = pubkey.to_hex();
+        let before = entry.book.requests.len();
+        entry.book.requests.retain(|r| r.pubkey != hex);
+        if entry.book.requests.len() == before {
+            return Ok(false);
+        }
+        if approve && !entry.set.contains(pubkey) {
+            entry.book.members.push(hex);
+            entry.set.replace(members(&entry.book));
+        }
+        self.persist(relay_id, &entry.book).await?;
+        Ok(true)
+    }
+
+    fn path(&self, relay_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", relay_id))
+    }
+
+    async fn persist(&self, relay_id: &str, book: &InviteBook) -> Result<(), String> {
+        let write = async {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(self.path(relay_id), serde_json::to_vec_pretty(book)?).await
+        };
+        write.await.map_err(|e| format!("Failed to save invites: {}", e))
+    }
+}
+
+fn new_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..CODE_LEN)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+fn members(book: &InviteBook) -> HashSet<PublicKey> {
+    book.members.iter().filter_map(|m| PublicKey::from_hex(m).ok()).collect()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::Keys;
+
+    #[tokio::test]
+    async fn codes_are_limited_and_members_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let invites = InviteManager::new(dir.path().to_path_buf());
+        let set = invites.enable("private").await;
+        let (alice, bob, carol) = (Keys::generate().public_key(), Keys::generate().public_key(), Keys::generate().public_key());
+
+        let code = invites.create("private", 2, None).await.unwrap().code;
+        invites.redeem("private", &code.to_lowercase(), &alice).await.unwrap();
+        // A member redeeming again doesn't use the code up
+        invites.redeem("private", &code, &alice).await.unwrap();
+        invites.redeem("private", &code, &bob).await.unwrap();
+        assert!(invites.redeem("private", &code, &carol).await.is_err());
+        assert!(set.contains(&alice) && set.contains(&bob) && !set.contains(&carol));
+
+        let expired = invites.create("private", 1, Some(now_secs() - 1)).await.unwrap().code;
+        assert!(invites.redeem("private", &expired, &carol).await.is_err());
+        assert!(invites.redeem("public", &code, &carol).await.is_err());
+
+        assert!(invites.remove_member("private", &bob).await.unwrap());
+        assert!(!set.contains(&bob));
+
+        // Access requests wait for the admin
+        invites.request_access("private", &carol, "hi, it's carol").await.unwrap();
+        invites.request_access("private", &bob, "").await.unwrap();
+        assert!(invites.request_access("private", &alice, "").await.is_err());
+        assert_eq!(invites.book("private").await.unwrap().requests.len(), 2);
+        assert!(invites.decide("private", &carol, true).await.unwrap());
+        assert!(invites.decide("private", &bob, false).await.unwrap());
+        assert!(!invites.decide("private", &bob, true).await.unwrap());
+        assert!(set.contains(&carol) && !set.contains(&bob));
+
+        let reloaded = InviteManager::new(dir.path().to_path_buf());
+        let set = reloaded.enable("private").await;
+        assert!(set.contains(&alice) && set.contains(&carol) && !set.contains(&bob));
+        let book = reloaded.book("private").await.unwrap();
+        assert_eq!(book.codes[0].uses, 2);
+        assert!(book.requests.is_empty());
+    }
+}
@@ -1,13 +1,42 @@
 pub mod auth;
+pub mod backup;
 pub mod blossom;
+pub mod bolt11;
+pub mod cashu;
+pub mod cli;
 pub mod config;
+pub mod connections;
+pub mod daily_stats;
+pub mod disk_usage;
+pub mod dm;
 pub mod error;
+pub mod firehose;
 pub mod gateway;
+pub mod greylist;
+pub mod identity;
+pub mod invite;
+pub mod lnurl;
+pub mod migrate;
+pub mod mirror;
+pub mod monitor;
+pub mod network;
+pub mod nip05;
 pub mod nwc;
 pub mod paywall;
+pub mod plugin;
 pub mod policy;
+pub mod query_cache;
 pub mod rate_limit;
+pub mod relay_list;
+pub mod s3;
 pub mod server;
+pub mod session;
 pub mod stats;
 pub mod storage;
+pub mod subscriptions;
+pub mod sync;
+pub mod template;
+pub mod tls;
+pub mod webhook;
 pub mod wot;
+pub mod zap;
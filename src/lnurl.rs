@@ -0,0 +1,110 @@
+//! Lightning addresses (LUD-16): `name@domain` resolves through
+//! `/.well-known/lnurlp/<name>` to an LNURL-pay request (LUD-06) whose
+//! invoices are issued by an NWC wallet.
+
+use crate::config::LightningAddressConfig;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayRequest {
+    pub callback: String,
+    pub max_sendable: u64,
+    pub min_sendable: u64,
+    /// JSON-encoded metadata array; invoices commit to its sha256.
+    pub metadata: String,
+    pub tag: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PayResponse {
+    pub pr: String,
+    pub routes: Vec<String>,
+}
+
+/// LUD-06 error body, returned with a 200 status.
+#[derive(Debug, Serialize)]
+pub struct LnurlError {
+    pub status: &'static str,
+    pub reason: String,
+}
+
+impl LnurlError {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            status: "ERROR",
+            reason: reason.into(),
+        }
+    }
+}
+
+/// The metadata string for `name@domain`. It must be byte-identical between
+/// the pay request and the callback, since the invoice commits to its hash.
+pub fn metadata(config: &LightningAddressConfig, name: &str, domain: &str) -> String {
+    let identifier = format!("{}@{}", name, domain);
+    let text = config
+        .description
+        .clone()
+        .unwrap_or_else(|| format!("Payment to {}", identifier));
+    serde_json::json!([["text/plain", text], ["text/identifier", identifier]]).to_string()
+}
+
+pub fn pay_request(config: &LightningAddressConfig, name: &str, domain: &str, callback: String) -> PayRequest {
+    PayRequest {
+        callback,
+        max_sendable: config.max_sendable_msats,
+        min_sendable: config.min_sendable_msats,
+        metadata: metadata(config, name, domain),
+        tag: "payRequest",
+    }
+}
+
+/// Check a callback `amount` (msats) against the configured bounds.
+pub fn check_amount(config: &LightningAddressConfig, amount: Option<u64>) -> Result<u64, LnurlError> {
+    match amount {
+        None => Err(LnurlError::new("Missing amount")),
+        Some(a) if a < config.min_sendable_msats || a > config.max_sendable_msats => Err(LnurlError::new(format!(
+            "Amount must be between {} and {} msats",
+            config.min_sendable_msats, config.max_sendable_msats
+        ))),
+        Some(a) => Ok(a),
+    }
+}
+
+pub fn description_hash(metadata: &str) -> String {
+    format!("{:x}", Sha256::digest(metadata.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LightningAddressConfig {
+        LightningAddressConfig {
+            nwc_string: None,
+            paywall: Some("main".to_string()),
+            min_sendable_msats: 1_000,
+            max_sendable_msats: 1_000_000,
+            description: None,
+        }
+    }
+
+    #[test]
+    fn builds_pay_request_and_checks_amounts() {
+        let config = config();
+        let req = pay_request(&config, "bob", "relay.example.com", "https://relay.example.com/cb".to_string());
+        assert_eq!(
+            req.metadata,
+            r#"[["text/plain","Payment to bob@relay.example.com"],["text/identifier","bob@relay.example.com"]]"#
+        );
+        let json = serde_json::to_value(&req).unwrap();
+        assert_eq!(json["tag"], "payRequest");
+        assert_eq!(json["minSendable"], 1_000);
+
+        assert!(check_amount(&config, None).is_err());
+        assert!(check_amount(&config, Some(999)).is_err());
+        assert!(check_amount(&config, Some(1_000_001)).is_err());
+        assert_eq!(check_amount(&config, Some(21_000)).unwrap(), 21_000);
+    }
+}
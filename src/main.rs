@@ -1,12 +1,16 @@
+use moar::blossom::pool::BlobPool;
 use moar::blossom::store::BlobStore;
-use moar::config::MoarConfig;
+use moar::cli;
 use moar::gateway::start_gateway;
+use moar::invite::InviteManager;
 use moar::paywall::PaywallManager;
 use moar::policy::PolicyEngine;
 use moar::stats::{RelayStats, TimeSeriesRing};
 use moar::storage::lmdb::LmdbStore;
+use moar::webhook::{Alert, AlertKind, Notifier};
 use moar::wot::WotManager;
 use clap::{Parser, Subcommand};
+use nostr::{Keys, PublicKey};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -27,6 +31,89 @@ enum Commands {
         #[arg(short, long, default_value = "moar.toml")]
         config: PathBuf,
     },
+    /// Export a relay's events as JSONL
+    Export {
+        /// Relay ID from the config file
+        relay_id: String,
+        /// Output file (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Only these kinds, comma-separated
+        #[arg(long, value_delimiter = ',')]
+        kinds: Vec<u16>,
+        /// Only events by these pubkeys (hex or npub), comma-separated
+        #[arg(long, value_delimiter = ',')]
+        authors: Vec<String>,
+        /// Only events created at or after this unix timestamp
+        #[arg(long)]
+        since: Option<u64>,
+        /// Only events created at or before this unix timestamp
+        #[arg(long)]
+        until: Option<u64>,
+        #[arg(short, long, default_value = "moar.toml")]
+        config: PathBuf,
+    },
+    /// Import events from a JSONL file into a relay
+    Import {
+        /// Relay ID from the config file
+        relay_id: String,
+        /// JSONL file with one event per line
+        file: PathBuf,
+        /// Don't check signatures (only for dumps from a relay that already did)
+        #[arg(long)]
+        skip_verify: bool,
+        #[arg(short, long, default_value = "moar.toml")]
+        config: PathBuf,
+    },
+    /// Load events from another relay implementation's database into a relay
+    Migrate {
+        #[command(subcommand)]
+        source: MigrateSource,
+    },
+    /// Compact a relay's database to reclaim free space (stop the server first)
+    Compact {
+        /// Relay ID from the config file
+        relay_id: String,
+        #[arg(short, long, default_value = "moar.toml")]
+        config: PathBuf,
+    },
+    /// Show event counts and database sizes for all relays
+    Stats {
+        #[arg(short, long, default_value = "moar.toml")]
+        config: PathBuf,
+    },
+    /// Check the configuration file, storage paths and ports for problems
+    #[command(alias = "validate-config")]
+    Check {
+        #[arg(short, long, default_value = "moar.toml")]
+        config: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum MigrateSource {
+    /// strfry's database directory, or a file written by `strfry export`
+    Strfry {
+        path: PathBuf,
+        /// Relay ID from the config file
+        relay_id: String,
+        /// Don't check signatures (the source relay already did)
+        #[arg(long)]
+        skip_verify: bool,
+        #[arg(short, long, default_value = "moar.toml")]
+        config: PathBuf,
+    },
+    /// nostr-rs-relay's SQLite database file
+    NostrRsRelay {
+        path: PathBuf,
+        /// Relay ID from the config file
+        relay_id: String,
+        /// Don't check signatures (the source relay already did)
+        #[arg(long)]
+        skip_verify: bool,
+        #[arg(short, long, default_value = "moar.toml")]
+        config: PathBuf,
+    },
 }
 
 #[tokio::main]
@@ -37,51 +124,64 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     match cli.command {
         Commands::Start { config: config_path } => {
-            let config_content = std::fs::read_to_string(&config_path)?;
-            let config: MoarConfig = toml::from_str(&config_content)?;
+            // Report every problem up front rather than failing on the first one mid-startup
+            let Some(config) = cli::check_config(&config_path) else {
+                std::process::exit(1);
+            };
+
+            let mut notifier = Notifier::new(config.webhooks.clone());
+            if let Some(dm) = config.dm_alerts.clone() {
+                let keys = Keys::parse(&dm.secret_key)?;
+                let admin = PublicKey::parse(&config.admin_pubkey)?;
+                notifier = notifier.with_dm(keys, admin, dm);
+            }
+            let notifier = Arc::new(notifier);
+            notifier.notify(Alert::new(
+                AlertKind::Started,
+                format!("moar {} started on {}", env!("CARGO_PKG_VERSION"), config.domain),
+                serde_json::json!({ "version": env!("CARGO_PKG_VERSION"), "domain": config.domain }),
+            ));
 
             // Create WoT manager and start background builders
             let wot_manager = WotManager::new(
+                config.data_path("wot"),
                 config.discovery_relays.clone(),
                 config.wots.clone(),
+                notifier.clone(),
             );
             wot_manager.start_all().await;
 
             // Create Paywall manager and start background tasks
-            let paywall_manager = PaywallManager::new(config.paywalls.clone())?;
+            let paywall_manager = PaywallManager::new(config.data_path("paywall"), config.paywalls.clone(), notifier.clone())?;
             paywall_manager.start_all().await;
 
+            let invites = Arc::new(InviteManager::new(config.data_path("invites")));
+
             let mut processed_relays = std::collections::HashMap::new();
 
             for (key, relay_conf) in config.relays.clone() {
                 let store: Arc<dyn moar::storage::NostrStore> =
-                    Arc::new(LmdbStore::new(&relay_conf.db_path)?);
-                let write_wot = match &relay_conf.policy.write.wot {
-                    Some(id) => wot_manager.get_set(id).await,
-                    None => None,
-                };
-                let read_wot = match &relay_conf.policy.read.wot {
-                    Some(id) => wot_manager.get_set(id).await,
-                    None => None,
-                };
-                let write_paywall = match &relay_conf.policy.write.paywall {
-                    Some(id) => paywall_manager.get_set(id).await,
-                    None => None,
-                };
-                let read_paywall = match &relay_conf.policy.read.paywall {
-                    Some(id) => paywall_manager.get_set(id).await,
-                    None => None,
-                };
-                let policy = Arc::new(PolicyEngine::new(relay_conf.policy.clone(), relay_conf.nip11.clone(), write_wot, read_wot, write_paywall, read_paywall));
+                    Arc::new(LmdbStore::new(&relay_conf.db_path)?.with_max_limit(relay_conf.nip11.max_limit.map(|max| max as usize)));
                 let stats = Arc::new(RelayStats::new());
+                let policy =
+                    PolicyEngine::for_relay(&key, &relay_conf, &wot_manager, &paywall_manager, &invites, stats.clone()).await;
+                let policy = Arc::new(policy);
                 let ts_ring = Arc::new(RwLock::new(TimeSeriesRing::new()));
                 processed_relays.insert(key, (relay_conf, store, policy, stats, ts_ring));
             }
 
+            let mut blob_pools = std::collections::HashMap::new();
+            for (key, pool_conf) in &config.blob_pools {
+                blob_pools.insert(key.clone(), Arc::new(BlobPool::open(pool_conf)?));
+            }
+
             let mut processed_blossoms = std::collections::HashMap::new();
             for (key, blossom_conf) in config.blossoms.clone() {
-                let store = Arc::new(BlobStore::new(&blossom_conf.storage_path)?);
-                processed_blossoms.insert(key, (blossom_conf, store));
+                let store = match blossom_conf.pool.as_ref().and_then(|id| blob_pools.get(id)) {
+                    Some(pool) => BlobStore::with_backend(&blossom_conf.storage_path, pool.backend_for(&key))?,
+                    None => BlobStore::from_config(&blossom_conf)?,
+                };
+                processed_blossoms.insert(key, (blossom_conf, Arc::new(store)));
             }
 
             start_gateway(
@@ -89,13 +189,49 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 config.domain.clone(),
                 processed_relays,
                 processed_blossoms,
+                blob_pools,
                 config,
                 config_path,
                 wot_manager,
                 paywall_manager,
+                invites,
+                notifier,
             )
             .await?;
         }
+        Commands::Export { relay_id, output, kinds, authors, since, until, config } => {
+            let config = cli::load_config(&config)?;
+            let filter = cli::export_filter(&kinds, &authors, since, until)?;
+            cli::export(&config, &relay_id, output.as_deref(), &filter)?;
+        }
+        Commands::Import { relay_id, file, skip_verify, config } => {
+            let config = cli::load_config(&config)?;
+            cli::import(&config, &relay_id, &file, !skip_verify)?;
+        }
+        Commands::Migrate { source } => match source {
+            MigrateSource::Strfry { path, relay_id, skip_verify, config } => {
+                let config = cli::load_config(&config)?;
+                moar::migrate::strfry(&config, &relay_id, &path, !skip_verify)?;
+            }
+            MigrateSource::NostrRsRelay { path, relay_id, skip_verify, config } => {
+                let config = cli::load_config(&config)?;
+                moar::migrate::nostr_rs_relay(&config, &relay_id, &path, !skip_verify)?;
+            }
+        },
+        Commands::Compact { relay_id, config } => {
+            let config = cli::load_config(&config)?;
+            cli::compact(&config, &relay_id)?;
+        }
+        Commands::Stats { config } => {
+            let config = cli::load_config(&config)?;
+            cli::stats(&config)?;
+        }
+        Commands::Check { config: config_path } => {
+            if cli::check_config(&config_path).is_none() {
+                std::process::exit(1);
+            }
+            println!("{} is valid", config_path.display());
+        }
     }
 
     Ok(())
@@ -0,0 +1,196 @@
+//! Bulk-loading events from other relays' databases, so operators can move
+//! to moar without writing their own conversion scripts.
+
+use crate::cli::{self, ImportCounts};
+use crate::config::MoarConfig;
+use crate::storage::lmdb::LmdbStore;
+use heed::types::Bytes;
+use heed::{DatabaseFlags, EnvFlags, EnvOpenOptions};
+use std::io::BufRead;
+use std::path::Path;
+
+/// Load a strfry database into relay `relay_id`. `path` is either strfry's
+/// database directory (the one holding `data.mdb`) or a JSONL file written
+/// by `strfry export`.
+pub fn strfry(config: &MoarConfig, relay_id: &str, path: &Path, verify: bool) -> Result<(), anyhow::Error> {
+    let relay = cli::relay_config(config, relay_id)?;
+    let store = LmdbStore::new(&relay.db_path)?;
+    let counts = if path.is_dir() {
+        load_strfry(&store, path, verify)?
+    } else {
+        let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        cli::import_lines(&store, reader.lines(), verify, None)?
+    };
+    cli::print_import_summary(&counts, relay_id);
+    Ok(())
+}
+
+/// Load a nostr-rs-relay SQLite database into relay `relay_id`. Events the
+/// relay hid after a deletion are left behind.
+pub fn nostr_rs_relay(config: &MoarConfig, relay_id: &str, path: &Path, verify: bool) -> Result<(), anyhow::Error> {
+    let relay = cli::relay_config(config, relay_id)?;
+    let store = LmdbStore::new(&relay.db_path)?;
+    let counts = load_nostr_rs_relay(&store, path, verify)?;
+    cli::print_import_summary(&counts, relay_id);
+    Ok(())
+}
+
+/// Name of the table strfry keeps event JSON in. golpe prefixes table
+/// names with the database's, so only the suffix is fixed.
+const STRFRY_PAYLOAD_TABLE: &str = "__EventPayload";
+
+/// strfry payloads start with a format byte: 0 for plain JSON, 1 for JSON
+/// compressed with one of its zstd dictionaries.
+const STRFRY_PLAIN: u8 = 0;
+
+fn load_strfry(store: &LmdbStore, dir: &Path, verify: bool) -> Result<ImportCounts, anyhow::Error> {
+    if !dir.join("data.mdb").exists() {
+        anyhow::bail!("{} is not a strfry database directory (no data.mdb)", dir.display());
+    }
+    let size = std::fs::metadata(dir.join("data.mdb"))?.len() as usize;
+    let mut options = EnvOpenOptions::new();
+    options.max_dbs(64);
+    // A multiple of every page size LMDB runs with, and at least the file
+    options.map_size((size + 1).next_multiple_of(1 << 16));
+    let env = unsafe {
+        options.flags(EnvFlags::READ_ONLY);
+        options.open(dir)?
+    };
+
+    let rtxn = env.read_txn()?;
+    let root = env
+        .open_database::<Bytes, Bytes>(&rtxn, None)?
+        .ok_or_else(|| anyhow::anyhow!("{} has no tables", dir.display()))?;
+    let mut table = None;
+    for entry in root.iter(&rtxn)? {
+        let (name, _) = entry?;
+        match std::str::from_utf8(name) {
+            Ok(name) if name.ends_with(STRFRY_PAYLOAD_TABLE) => table = Some(name.to_string()),
+            _ => {}
+        }
+    }
+    let name = table.ok_or_else(|| anyhow::anyhow!("{} has no strfry EventPayload table", dir.display()))?;
+    let payloads = env
+        .database_options()
+        .types::<Bytes, Bytes>()
+        .flags(DatabaseFlags::INTEGER_KEY)
+        .name(&name)
+        .open(&rtxn)?
+        .ok_or_else(|| anyhow::anyhow!("failed to open table {}", name))?;
+
+    let total = payloads.len(&rtxn)?;
+    let mut compressed = 0u64;
+    let lines = payloads.iter(&rtxn)?.filter_map(|entry| {
+        let (_, payload) = match entry {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(std::io::Error::other(e))),
+        };
+        match payload.split_first() {
+            Some((&STRFRY_PLAIN, json)) => Some(Ok(String::from_utf8_lossy(json).into_owned())),
+            _ => {
+                compressed += 1;
+                None
+            }
+        }
+    });
+    let counts = cli::import_lines(store, lines, verify, Some(total))?;
+    if compressed > 0 {
+        eprintln!(
+            "{} compressed events were left out; load the output of `strfry export` to include them",
+            compressed
+        );
+    }
+    Ok(counts)
+}
+
+fn load_nostr_rs_relay(store: &LmdbStore, path: &Path, verify: bool) -> Result<ImportCounts, anyhow::Error> {
+    if !path.is_file() {
+        anyhow::bail!("{} is not a nostr-rs-relay database file", path.display());
+    }
+    let conn = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    // `content` holds the whole serialized event, not just its content field
+    let total: u64 = conn.query_row("SELECT COUNT(*) FROM event WHERE hidden IS NOT 1", [], |row| row.get(0))?;
+    let mut stmt = conn.prepare("SELECT content FROM event WHERE hidden IS NOT 1 ORDER BY id")?;
+    let rows = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .map(|row| row.map_err(std::io::Error::other));
+    cli::import_lines(store, rows, verify, Some(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::NostrStore;
+    use heed::types::U64;
+    use nostr::{EventBuilder, JsonUtil, Keys};
+
+    fn events(n: usize) -> Vec<nostr::Event> {
+        let keys = Keys::generate();
+        (0..n)
+            .map(|i| EventBuilder::text_note(format!("note {}", i), []).to_event(&keys).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn loads_strfry_payloads() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let events = events(3);
+        {
+            let mut options = EnvOpenOptions::new();
+            options.max_dbs(4);
+            let env = unsafe { options.open(src.path()).unwrap() };
+            let mut wtxn = env.write_txn().unwrap();
+            let db = env
+                .database_options()
+                .types::<U64<heed::byteorder::NativeEndian>, Bytes>()
+                .flags(DatabaseFlags::INTEGER_KEY)
+                .name("rasgueadb_defaultDb__EventPayload")
+                .create(&mut wtxn)
+                .unwrap();
+            for (i, event) in events.iter().enumerate() {
+                let mut payload = vec![STRFRY_PLAIN];
+                payload.extend_from_slice(event.as_json().as_bytes());
+                db.put(&mut wtxn, &(i as u64 + 1), &payload).unwrap();
+            }
+            // A dictionary-compressed payload can't be read without strfry
+            db.put(&mut wtxn, &9, &[1, 0, 0, 0, 0, 42]).unwrap();
+            wtxn.commit().unwrap();
+            env.prepare_for_closing().wait();
+        }
+
+        let store = LmdbStore::new(dst.path()).unwrap();
+        let counts = load_strfry(&store, src.path(), true).unwrap();
+        assert_eq!((counts.imported, counts.errors), (3, 0));
+        assert_eq!(store.event_count().unwrap(), 3);
+    }
+
+    #[test]
+    fn loads_visible_nostr_rs_relay_events() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let path = src.path().join("nostr.db");
+        let events = events(3);
+        {
+            let conn = rusqlite::Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE event (id INTEGER PRIMARY KEY, created_at INTEGER NOT NULL, \
+                 hidden INTEGER DEFAULT 0, content TEXT NOT NULL);",
+            )
+            .unwrap();
+            for (i, event) in events.iter().enumerate() {
+                conn.execute(
+                    "INSERT INTO event (created_at, hidden, content) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![event.created_at.as_u64(), (i == 2) as i64, event.as_json()],
+                )
+                .unwrap();
+            }
+            conn.execute("INSERT INTO event (created_at, content) VALUES (0, 'not json')", []).unwrap();
+        }
+
+        let store = LmdbStore::new(dst.path()).unwrap();
+        let counts = load_nostr_rs_relay(&store, &path, true).unwrap();
+        assert_eq!((counts.imported, counts.errors), (2, 1));
+        assert!(store.iter_all().unwrap().iter().all(|e| e.id != events[2].id));
+    }
+}
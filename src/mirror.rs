@@ -0,0 +1,308 @@
+//! Forwarding of accepted events to external relays (`mirror_to`).
+//!
+//! Events are written to a small LMDB-backed outbound queue before being
+//! published, so nothing is lost if a target relay is down or the server
+//! restarts. Each target relay has its own worker that drains its part of the
+//! queue in order, reconnecting with exponential backoff.
+
+use futures_util::{SinkExt, StreamExt};
+use heed::types::Bytes;
+use heed::{Database, Env, EnvOpenOptions};
+use nostr::{Event, JsonUtil};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+
+const BATCH_SIZE: usize = 100;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+pub struct MirrorPublisher {
+    env: Env,
+    queue: Database<Bytes, Bytes>,
+    targets: Vec<String>,
+    next_seq: AtomicU64,
+    notify: Vec<Arc<Notify>>,
+}
+
+/// Queue keys are `<target url>\0<seq: u64 BE>` so each target's entries are
+/// contiguous and ordered by enqueue time.
+fn queue_key(target: &str, seq: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(target.len() + 9);
+    key.extend_from_slice(target.as_bytes());
+    key.push(0);
+    key.extend_from_slice(&seq.to_be_bytes());
+    key
+}
+
+fn target_prefix(target: &str) -> Vec<u8> {
+    let mut prefix = target.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+impl MirrorPublisher {
+    /// Open (or create) the outbound queue in `queue_dir` and spawn one
+    /// publisher task per target relay.
+    pub fn start<P: AsRef<Path>>(
+        relay_id: &str,
+        queue_dir: P,
+        targets: Vec<String>,
+    ) -> Result<Arc<Self>, anyhow::Error> {
+        std::fs::create_dir_all(&queue_dir)?;
+        let mut env_builder = EnvOpenOptions::new();
+        env_builder.max_dbs(1);
+        env_builder.map_size(1024 * 1024 * 1024); // 1 GB
+        let env = unsafe { env_builder.open(queue_dir)? };
+
+        let mut wtxn = env.write_txn()?;
+        let queue: Database<Bytes, Bytes> = env.create_database(&mut wtxn, Some("queue"))?;
+        wtxn.commit()?;
+
+        // Resume sequence numbers after the highest queued entry
+        let mut next_seq = 0;
+        {
+            let rtxn = env.read_txn()?;
+            for item in queue.iter(&rtxn)? {
+                let (key, _) = item?;
+                if key.len() >= 8 {
+                    let seq = u64::from_be_bytes(key[key.len() - 8..].try_into().unwrap());
+                    next_seq = next_seq.max(seq + 1);
+                }
+            }
+        }
+
+        let notify = targets.iter().map(|_| Arc::new(Notify::new())).collect();
+        let publisher = Arc::new(Self {
+            env,
+            queue,
+            targets,
+            next_seq: AtomicU64::new(next_seq),
+            notify,
+        });
+
+        for (i, target) in publisher.targets.iter().enumerate() {
+            let pending = publisher.pending(target).unwrap_or(0);
+            tracing::info!(
+                relay = %relay_id,
+                target = %target,
+                pending,
+                "Mirroring events to external relay"
+            );
+            let publisher = Arc::clone(&publisher);
+            let target = target.clone();
+            let notify = Arc::clone(&publisher.notify[i]);
+            let relay_id = relay_id.to_string();
+            tokio::spawn(async move {
+                publisher.run_worker(&relay_id, &target, &notify).await;
+            });
+        }
+
+        Ok(publisher)
+    }
+
+    /// Queue an accepted event for delivery to every target relay. The queue
+    /// is committed to disk before this returns, on a blocking thread so the
+    /// fsync doesn't stall the connection's runtime worker.
+    pub async fn enqueue(self: &Arc<Self>, event: &Event) -> Result<(), anyhow::Error> {
+        let publisher = self.clone();
+        let raw = event.as_json();
+        tokio::task::spawn_blocking(move || publisher.write_queue(&raw)).await?
+    }
+
+    fn write_queue(&self, raw: &str) -> Result<(), anyhow::Error> {
+        let mut wtxn = self.env.write_txn()?;
+        for target in &self.targets {
+            let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+            self.queue
+                .put(&mut wtxn, &queue_key(target, seq), raw.as_bytes())?;
+        }
+        wtxn.commit()?;
+        for notify in &self.notify {
+            notify.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Number of events waiting to be delivered to `target`.
+    pub fn pending(&self, target: &str) -> Result<usize, anyhow::Error> {
+        let rtxn = self.env.read_txn()?;
+        let count = self
+            .queue
+            .prefix_iter(&rtxn, &target_prefix(target))?
+            .count();
+        Ok(count)
+    }
+
+    fn next_batch(&self, target: &str) -> Result<Vec<(Vec<u8>, String)>, anyhow::Error> {
+        let rtxn = self.env.read_txn()?;
+        let mut batch = Vec::new();
+        for item in self
+            .queue
+            .prefix_iter(&rtxn, &target_prefix(target))?
+            .take(BATCH_SIZE)
+        {
+            let (key, value) = item?;
+            batch.push((key.to_vec(), String::from_utf8_lossy(value).into_owned()));
+        }
+        Ok(batch)
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), anyhow::Error> {
+        let mut wtxn = self.env.write_txn()?;
+        self.queue.delete(&mut wtxn, key)?;
+        wtxn.commit()?;
+        Ok(())
+    }
+
+    async fn run_worker(&self, relay_id: &str, target: &str, notify: &Notify) {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let batch = match self.next_batch(target) {
+                Ok(b) => b,
+                Err(e) => {
+                    tracing::error!(relay = %relay_id, target = %target, "Mirror queue read failed: {}", e);
+                    tokio::time::sleep(MAX_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            if batch.is_empty() {
+                notify.notified().await;
+                continue;
+            }
+
+            match self.deliver(target, &batch).await {
+                Ok(()) => backoff = MIN_BACKOFF,
+                Err(e) => {
+                    tracing::warn!(
+                        relay = %relay_id,
+                        target = %target,
+                        retry_in_secs = backoff.as_secs(),
+                        "Mirror delivery failed: {}",
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Publish a batch over a single connection, removing each event from the
+    /// queue once the target has accepted it or rejected it for good. A
+    /// transient rejection stops the batch, to be retried after a backoff.
+    async fn deliver(&self, target: &str, batch: &[(Vec<u8>, String)]) -> Result<(), anyhow::Error> {
+        let (mut ws, _) = tokio::time::timeout(
+            Duration::from_secs(10),
+            tokio_tungstenite::connect_async(target),
+        )
+        .await
+        .map_err(|_| anyhow::anyhow!("Connection timeout"))??;
+
+        for (key, raw) in batch {
+            let event = match Event::from_json(raw) {
+                Ok(e) => e,
+                Err(_) => {
+                    // Corrupt entry: drop it rather than blocking the queue
+                    self.remove(key)?;
+                    continue;
+                }
+            };
+            let event_id = event.id.to_hex();
+
+            ws.send(Message::Text(format!(r#"["EVENT",{}]"#, raw).into()))
+                .await?;
+
+            let ok = tokio::time::timeout(Duration::from_secs(10), async {
+                while let Some(msg) = ws.next().await {
+                    let text = match msg? {
+                        Message::Text(t) => t.to_string(),
+                        Message::Close(_) => break,
+                        _ => continue,
+                    };
+                    let parsed: serde_json::Value = match serde_json::from_str(&text) {
+                        Ok(v) => v,
+                        Err(_) => continue,
+                    };
+                    if parsed[0] == "OK" && parsed[1] == event_id.as_str() {
+                        return Ok(parsed);
+                    }
+                }
+                Err(anyhow::anyhow!("Connection closed before OK"))
+            })
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out waiting for OK"))??;
+
+            if ok[2].as_bool() != Some(true) {
+                let reason = ok[3].as_str().unwrap_or("");
+                if is_transient_rejection(reason) {
+                    let _ = ws.close(None).await;
+                    return Err(anyhow::anyhow!("Target rejected event {} for now: {}", event_id, reason));
+                }
+                tracing::debug!(
+                    target = %target,
+                    event_id = %event_id,
+                    reason = %reason,
+                    "Mirror target rejected event"
+                );
+            }
+            self.remove(key)?;
+        }
+
+        let _ = ws.close(None).await;
+        Ok(())
+    }
+}
+
+/// Whether an `OK false` reason may succeed later. Other rejections
+/// (`invalid:`, `blocked:`, `duplicate:` and the like) would fail again, so
+/// the event is dropped.
+fn is_transient_rejection(reason: &str) -> bool {
+    reason.starts_with("rate-limited:") || reason.starts_with("error:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys};
+
+    #[tokio::test]
+    async fn enqueue_fans_out_to_every_target_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = Keys::generate();
+        let a = EventBuilder::text_note("a", []).to_event(&keys).unwrap();
+        let b = EventBuilder::text_note("b", []).to_event(&keys).unwrap();
+        // Unroutable targets so the workers never drain the queue
+        let targets = vec!["ws://127.0.0.1:1".to_string(), "ws://127.0.0.1:2".to_string()];
+
+        let publisher = MirrorPublisher::start("test", dir.path(), targets.clone()).unwrap();
+        publisher.enqueue(&a).await.unwrap();
+        publisher.enqueue(&b).await.unwrap();
+        assert_eq!(publisher.pending(&targets[0]).unwrap(), 2);
+        let batch = publisher.next_batch(&targets[1]).unwrap();
+        assert!(batch[0].1.contains(&a.id.to_hex()));
+        assert!(batch[1].1.contains(&b.id.to_hex()));
+    }
+
+    #[test]
+    fn queue_keys_sort_by_sequence_within_target() {
+        let t = "wss://relay.example.com";
+        assert!(queue_key(t, 1) < queue_key(t, 2));
+        assert!(queue_key(t, 255) < queue_key(t, 256));
+        assert!(queue_key(t, 7).starts_with(&target_prefix(t)));
+    }
+
+    #[test]
+    fn only_rate_limits_and_errors_are_retried() {
+        assert!(is_transient_rejection("rate-limited: slow down"));
+        assert!(is_transient_rejection("error: could not save"));
+        assert!(!is_transient_rejection("invalid: bad signature"));
+        assert!(!is_transient_rejection("blocked: not allowed"));
+        assert!(!is_transient_rejection("duplicate: already have it"));
+        assert!(!is_transient_rejection(""));
+    }
+}
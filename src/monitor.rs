@@ -0,0 +1,203 @@
+//! NIP-66 self-monitoring: every `interval_minutes` each hosted relay is
+//! probed through its public URL, and a relay discovery event (kind 30166)
+//! describing it — round-trip times, supported NIPs, requirements and
+//! topics — is published to the configured discovery relays, so
+//! relay-monitor clients can find it.
+
+use crate::config::MonitorConfig;
+use crate::server::RelayState;
+use futures_util::{SinkExt, StreamExt};
+use nostr::{Event, EventBuilder, JsonUtil, Keys, Kind, Tag, TagKind};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Relay discovery event, one per relay (addressed by its URL).
+pub const DISCOVERY_KIND: u16 = 30166;
+/// The monitor's own announcement: what it checks and how often.
+pub const ANNOUNCEMENT_KIND: u16 = 10166;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Round-trip times in milliseconds; `None` if the check failed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rtt {
+    pub open: Option<u64>,
+    pub read: Option<u64>,
+}
+
+/// Spawn the publisher. The first round runs right away.
+pub fn start(config: MonitorConfig, targets: Vec<String>, relays: Vec<Arc<RelayState>>, keys: Keys) {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(config.interval_minutes.max(1) * 60);
+        tracing::info!(
+            "Publishing NIP-66 discovery events for {} relays as {} every {} minutes",
+            relays.len(),
+            keys.public_key().to_hex(),
+            config.interval_minutes
+        );
+        loop {
+            publish_round(&targets, &relays, &keys, interval).await;
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+async fn publish_round(targets: &[String], relays: &[Arc<RelayState>], keys: &Keys, interval: Duration) {
+    let mut events = Vec::new();
+    match announcement(keys, interval) {
+        Ok(event) => events.push(event),
+        Err(e) => tracing::error!("Failed to sign monitor announcement: {}", e),
+    }
+    for relay in relays {
+        let url = websocket_url(&relay.relay_url);
+        let rtt = probe(&url).await;
+        let nip11 = crate::server::nip11_json(relay).await;
+        let nip11 = String::from_utf8_lossy(&nip11);
+        match discovery_event(keys, &url, &nip11, rtt) {
+            Ok(event) => events.push(event),
+            Err(e) => tracing::error!(relay = %relay.relay_id, "Failed to sign discovery event: {}", e),
+        }
+    }
+
+    for target in targets {
+        for event in &events {
+            let msg = format!(r#"["EVENT",{}]"#, event.as_json());
+            if let Err(e) = crate::dm::publish(target, &msg, &event.id.to_hex()).await {
+                tracing::warn!(target = %target, "Failed to publish monitor event: {}", e);
+            }
+        }
+    }
+}
+
+/// A relay's `https://` address as the `wss://` URL clients connect to.
+pub fn websocket_url(relay_url: &str) -> String {
+    let url = if let Some(rest) = relay_url.strip_prefix("https://") {
+        format!("wss://{}", rest)
+    } else if let Some(rest) = relay_url.strip_prefix("http://") {
+        format!("ws://{}", rest)
+    } else {
+        relay_url.to_string()
+    };
+    format!("{}/", url.trim_end_matches('/'))
+}
+
+/// Time a connection to `url`, then a one-event REQ until its EOSE.
+async fn probe(url: &str) -> Rtt {
+    let started = Instant::now();
+    let mut ws = match tokio::time::timeout(PROBE_TIMEOUT, tokio_tungstenite::connect_async(url)).await {
+        Ok(Ok((ws, _))) => ws,
+        Ok(Err(e)) => {
+            tracing::debug!(url = %url, "Monitor probe failed to connect: {}", e);
+            return Rtt::default();
+        }
+        Err(_) => return Rtt::default(),
+    };
+    let mut rtt = Rtt {
+        open: Some(started.elapsed().as_millis() as u64),
+        read: None,
+    };
+
+    let started = Instant::now();
+    let read = tokio::time::timeout(PROBE_TIMEOUT, async {
+        ws.send(Message::Text(r#"["REQ","moar-monitor",{"limit":1}]"#.into())).await.ok()?;
+        while let Some(Ok(msg)) = ws.next().await {
+            let Message::Text(text) = msg else {
+                continue;
+            };
+            if text.starts_with(r#"["EOSE""#) || text.starts_with(r#"["CLOSED""#) {
+                return Some(());
+            }
+        }
+        None
+    })
+    .await;
+    if let Ok(Some(())) = read {
+        rtt.read = Some(started.elapsed().as_millis() as u64);
+    }
+    let _ = ws.close(None).await;
+    rtt
+}
+
+fn tag(name: &str, values: &[&str]) -> Tag {
+    Tag::custom(TagKind::Custom(name.into()), values.iter().map(|v| v.to_string()))
+}
+
+fn announcement(keys: &Keys, interval: Duration) -> Result<Event, nostr::event::builder::Error> {
+    let tags = [
+        tag("frequency", &[&interval.as_secs().to_string()]),
+        tag("c", &["open"]),
+        tag("c", &["read"]),
+        tag("timeout", &["open", &PROBE_TIMEOUT.as_millis().to_string()]),
+        tag("timeout", &["read", &PROBE_TIMEOUT.as_millis().to_string()]),
+    ];
+    EventBuilder::new(Kind::from(ANNOUNCEMENT_KIND), "", tags).to_event(keys)
+}
+
+/// The tags of a relay's discovery event, read from its NIP-11 document.
+pub fn discovery_tags(url: &str, nip11: &serde_json::Value, rtt: Rtt) -> Vec<Tag> {
+    let mut tags = vec![Tag::identifier(url), tag("n", &["clearnet"])];
+    if let Some(ms) = rtt.open {
+        tags.push(tag("rtt-open", &[&ms.to_string()]));
+    }
+    if let Some(ms) = rtt.read {
+        tags.push(tag("rtt-read", &[&ms.to_string()]));
+    }
+    for nip in nip11["supported_nips"].as_array().into_iter().flatten() {
+        tags.push(tag("N", &[&nip.to_string()]));
+    }
+
+    let limitation = &nip11["limitation"];
+    let requirement = |required: bool, name: &str| {
+        let value = if required { name.to_string() } else { format!("!{}", name) };
+        Tag::custom(TagKind::Custom("R".into()), [value])
+    };
+    tags.push(requirement(limitation["auth_required"].as_bool() == Some(true), "auth"));
+    tags.push(requirement(limitation["payment_required"].as_bool() == Some(true), "payment"));
+    tags.push(requirement(limitation["restricted_writes"].as_bool() == Some(true), "writes"));
+    tags.push(requirement(limitation["min_pow_difficulty"].as_u64().unwrap_or(0) > 0, "pow"));
+
+    for topic in nip11["tags"].as_array().into_iter().flatten().filter_map(|t| t.as_str()) {
+        tags.push(tag("t", &[topic]));
+    }
+    for language in nip11["language_tags"].as_array().into_iter().flatten().filter_map(|t| t.as_str()) {
+        tags.push(tag("l", &[language, "ISO-639-1"]));
+    }
+    tags
+}
+
+fn discovery_event(keys: &Keys, url: &str, nip11: &str, rtt: Rtt) -> Result<Event, nostr::event::builder::Error> {
+    let document: serde_json::Value = serde_json::from_str(nip11).unwrap_or_default();
+    EventBuilder::new(Kind::from(DISCOVERY_KIND), nip11, discovery_tags(url, &document, rtt)).to_event(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovery_tags_describe_the_relay() {
+        let nip11 = serde_json::json!({
+            "supported_nips": [1, 11, 42],
+            "tags": ["bitcoin"],
+            "limitation": {"auth_required": true, "payment_required": false, "restricted_writes": true},
+        });
+        let url = websocket_url("https://outbox.example.com");
+        assert_eq!(url, "wss://outbox.example.com/");
+
+        let tags: Vec<Vec<String>> = discovery_tags(&url, &nip11, Rtt { open: Some(42), read: None })
+            .iter()
+            .map(|t| t.as_vec().to_vec())
+            .collect();
+        let has = |t: &[&str]| tags.iter().any(|tag| tag == t);
+        assert!(has(&["d", "wss://outbox.example.com/"]));
+        assert!(has(&["rtt-open", "42"]));
+        assert!(!tags.iter().any(|t| t[0] == "rtt-read"));
+        assert!(has(&["N", "42"]));
+        assert!(has(&["R", "auth"]));
+        assert!(has(&["R", "!payment"]));
+        assert!(has(&["R", "writes"]));
+        assert!(has(&["R", "!pow"]));
+        assert!(has(&["t", "bitcoin"]));
+    }
+}
@@ -0,0 +1,197 @@
+//! Network-level access control applied by the gateway before a request
+//! reaches a relay or blossom router. Entries are IPs or CIDR ranges,
+//! autonomous systems ("AS13335") or ISO country codes ("DE"); the latter two
+//! are resolved through MaxMind databases.
+
+use crate::config::NetworkConfig;
+use crate::rate_limit::parse_net;
+use ipnet::IpNet;
+use maxminddb::{geoip2, Reader};
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkEntry {
+    Net(IpNet),
+    Asn(u32),
+    Country(String),
+}
+
+impl NetworkEntry {
+    pub fn parse(entry: &str) -> Result<Self, String> {
+        let entry = entry.trim();
+        if let Some(net) = parse_net(entry) {
+            return Ok(Self::Net(net));
+        }
+        if let Some(number) = entry.strip_prefix("AS").or_else(|| entry.strip_prefix("as")) {
+            if let Ok(asn) = number.parse() {
+                return Ok(Self::Asn(asn));
+            }
+        }
+        if entry.len() == 2 && entry.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Ok(Self::Country(entry.to_ascii_uppercase()));
+        }
+        Err(format!(
+            "'{}' is not an IP, CIDR range, AS number (e.g. AS13335) or country code (e.g. DE)",
+            entry
+        ))
+    }
+
+    /// Canonical spelling, used to store and match entries.
+    pub fn canonical(&self) -> String {
+        match self {
+            Self::Net(net) => net.to_string(),
+            Self::Asn(asn) => format!("AS{}", asn),
+            Self::Country(code) => code.clone(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Lists {
+    allow: Vec<NetworkEntry>,
+    block: Vec<NetworkEntry>,
+}
+
+pub struct NetworkPolicy {
+    lists: RwLock<Lists>,
+    country_db: Option<Reader<Vec<u8>>>,
+    asn_db: Option<Reader<Vec<u8>>>,
+}
+
+/// What the databases know about a client, looked up at most once.
+struct Client<'a> {
+    ip: IpAddr,
+    policy: &'a NetworkPolicy,
+    country: Option<Option<String>>,
+    asn: Option<Option<u32>>,
+}
+
+impl Client<'_> {
+    fn matches(&mut self, entry: &NetworkEntry) -> bool {
+        match entry {
+            NetworkEntry::Net(net) => net.contains(&self.ip),
+            NetworkEntry::Asn(asn) => {
+                let (ip, db) = (self.ip, &self.policy.asn_db);
+                let found = *self.asn.get_or_insert_with(|| {
+                    db.as_ref()?
+                        .lookup::<geoip2::Asn>(ip)
+                        .ok()?
+                        .autonomous_system_number
+                });
+                found == Some(*asn)
+            }
+            NetworkEntry::Country(code) => {
+                let (ip, db) = (self.ip, &self.policy.country_db);
+                let found = self.country.get_or_insert_with(|| {
+                    let record = db.as_ref()?.lookup::<geoip2::Country>(ip).ok()?;
+                    record.country?.iso_code.map(str::to_string)
+                });
+                found.as_deref() == Some(code.as_str())
+            }
+        }
+    }
+}
+
+impl NetworkPolicy {
+    pub fn new(config: &NetworkConfig) -> Result<Self, String> {
+        let open = |path: &Option<String>| -> Result<Option<Reader<Vec<u8>>>, String> {
+            path.as_ref()
+                .map(|p| Reader::open_readfile(p).map_err(|e| format!("failed to open GeoIP database {}: {}", p, e)))
+                .transpose()
+        };
+        let policy = Self {
+            lists: RwLock::new(Lists::default()),
+            country_db: open(&config.country_db)?,
+            asn_db: open(&config.asn_db)?,
+        };
+        policy.set_lists(&config.allow, &config.block)?;
+        Ok(policy)
+    }
+
+    /// Parse an entry and check that the database it needs is loaded.
+    pub fn parse_entry(&self, entry: &str) -> Result<NetworkEntry, String> {
+        let parsed = NetworkEntry::parse(entry)?;
+        match parsed {
+            NetworkEntry::Asn(_) if self.asn_db.is_none() => {
+                Err(format!("'{}' needs network.asn_db to be configured", entry))
+            }
+            NetworkEntry::Country(_) if self.country_db.is_none() => {
+                Err(format!("'{}' needs network.country_db to be configured", entry))
+            }
+            _ => Ok(parsed),
+        }
+    }
+
+    /// Replace the allow and block lists, e.g. after an admin edit.
+    pub fn set_lists(&self, allow: &[String], block: &[String]) -> Result<(), String> {
+        let parse = |entries: &[String]| entries.iter().map(|e| self.parse_entry(e)).collect::<Result<Vec<_>, _>>();
+        let lists = Lists {
+            allow: parse(allow)?,
+            block: parse(block)?,
+        };
+        *self.lists.write().unwrap() = lists;
+        Ok(())
+    }
+
+    /// Whether `ip` may be served: it must match the allow list, if there is
+    /// one, and must not match the block list.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let lists = self.lists.read().unwrap();
+        let mut client = Client {
+            ip,
+            policy: self,
+            country: None,
+            asn: None,
+        };
+        if !lists.allow.is_empty() && !lists.allow.iter().any(|e| client.matches(e)) {
+            return false;
+        }
+        !lists.block.iter().any(|e| client.matches(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(allow: &[&str], block: &[&str]) -> NetworkPolicy {
+        NetworkPolicy::new(&NetworkConfig {
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            block: block.iter().map(|s| s.to_string()).collect(),
+            country_db: None,
+            asn_db: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_entry_kinds() {
+        assert_eq!(NetworkEntry::parse("as13335").unwrap().canonical(), "AS13335");
+        assert_eq!(NetworkEntry::parse("de").unwrap(), NetworkEntry::Country("DE".to_string()));
+        assert_eq!(NetworkEntry::parse("10.0.0.1").unwrap().canonical(), "10.0.0.1/32");
+        assert!(NetworkEntry::parse("ASN").is_err());
+        assert!(NetworkEntry::parse("example.com").is_err());
+    }
+
+    #[test]
+    fn allow_list_then_block_list() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        let open = policy(&[], &["203.0.113.0/24"]);
+        assert!(open.is_allowed(ip("198.51.100.1")));
+        assert!(!open.is_allowed(ip("203.0.113.9")));
+
+        let closed = policy(&["10.0.0.0/8"], &["10.6.6.6"]);
+        assert!(closed.is_allowed(ip("10.1.2.3")));
+        assert!(!closed.is_allowed(ip("10.6.6.6")));
+        assert!(!closed.is_allowed(ip("198.51.100.1")));
+
+        // Country and ASN entries need their database
+        assert!(NetworkPolicy::new(&NetworkConfig {
+            block: vec!["CN".to_string()],
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,84 @@
+//! NIP-05 identities served from `/.well-known/nostr.json` on the gateway
+//! domain and every relay subdomain.
+
+use crate::config::Nip05Entry;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct NostrJson {
+    pub names: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub relays: BTreeMap<String, Vec<String>>,
+}
+
+/// NIP-05 local parts: lowercase letters, digits, `-`, `_` and `.`.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > 64 {
+        return Err("Name must be 1-64 characters".to_string());
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err("Name may only contain a-z, 0-9, '-', '_' and '.'".to_string());
+    }
+    Ok(())
+}
+
+/// The `nostr.json` document for `name`, or for every entry if `name` is
+/// `None`. Lookups are case-insensitive, as NIP-05 names are.
+pub fn nostr_json(entries: &HashMap<String, Nip05Entry>, name: Option<&str>) -> NostrJson {
+    let mut doc = NostrJson {
+        names: BTreeMap::new(),
+        relays: BTreeMap::new(),
+    };
+    let wanted = name.map(str::to_ascii_lowercase);
+    for (entry_name, entry) in entries {
+        if wanted.as_ref().is_some_and(|w| w != entry_name) {
+            continue;
+        }
+        doc.names.insert(entry_name.clone(), entry.pubkey.clone());
+        if !entry.relays.is_empty() {
+            doc.relays.insert(entry.pubkey.clone(), entry.relays.clone());
+        }
+    }
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_one_name_or_all() {
+        let entries = HashMap::from([
+            (
+                "bob".to_string(),
+                Nip05Entry {
+                    pubkey: "b".repeat(64),
+                    relays: vec!["wss://outbox.example.com".to_string()],
+                },
+            ),
+            (
+                "_".to_string(),
+                Nip05Entry {
+                    pubkey: "a".repeat(64),
+                    relays: Vec::new(),
+                },
+            ),
+        ]);
+
+        let doc = nostr_json(&entries, Some("BOB"));
+        assert_eq!(doc.names.len(), 1);
+        assert_eq!(doc.names["bob"], "b".repeat(64));
+        assert_eq!(doc.relays[&"b".repeat(64)], vec!["wss://outbox.example.com"]);
+
+        assert!(nostr_json(&entries, Some("carol")).names.is_empty());
+        assert_eq!(nostr_json(&entries, None).names.len(), 2);
+
+        assert!(validate_name("bob.smith_1").is_ok());
+        assert!(validate_name("Bob").is_err());
+        assert!(validate_name("").is_err());
+    }
+}
@@ -1,21 +1,35 @@
 use futures_util::{SinkExt, StreamExt};
+use nostr::nips::nip04;
 use nostr::nips::nip47::{
     LookupInvoiceRequestParams, MakeInvoiceRequestParams, NostrWalletConnectURI, Request, Response,
 };
-use nostr::{Event, JsonUtil, Keys};
+use nostr::{Event, EventId, JsonUtil, Keys};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 
 #[derive(Debug, Clone)]
 pub struct NwcClient {
     uri: NostrWalletConnectURI,
+    /// Queue to the task holding the wallet relay connection, shared by
+    /// every clone so requests reuse one connection.
+    connection: Arc<OnceLock<mpsc::UnboundedSender<Outgoing>>>,
+    signals: WalletSignals,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InvoiceResponse {
     pub invoice: String,
     pub payment_hash: String,
+    /// Unix time the invoice expires, read from the invoice itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -35,11 +49,11 @@ impl NwcClient {
             wallet_pubkey = %uri.public_key.to_hex(),
             "Parsed NWC connection string"
         );
-        Ok(Self { uri })
-    }
-
-    fn keys(&self) -> Keys {
-        Keys::new(self.uri.secret.clone())
+        Ok(Self {
+            uri,
+            connection: Arc::new(OnceLock::new()),
+            signals: WalletSignals::new(),
+        })
     }
 
     async fn send_and_wait(&self, request: Request) -> Result<Response, anyhow::Error> {
@@ -48,139 +62,44 @@ impl NwcClient {
 
     async fn send_and_wait_timeout(&self, request: Request, timeout_secs: u64) -> Result<Response, anyhow::Error> {
         let method = format!("{:?}", request.method);
-        tracing::info!(method = %method, relay = %self.uri.relay_url, "NWC: sending request");
+        tracing::debug!(method = %method, relay = %self.uri.relay_url, "NWC: sending request");
 
         let event = request
             .to_event(&self.uri)
             .map_err(|e| anyhow::anyhow!("Failed to build NWC event: {}", e))?;
 
-        let event_id = event.id;
-        let our_pk = self.keys().public_key();
-
-        tracing::debug!(
-            event_id = %event_id.to_hex(),
-            our_pubkey = %our_pk.to_hex(),
-            wallet_pubkey = %self.uri.public_key.to_hex(),
-            "NWC: connecting to relay"
-        );
-
-        let (mut ws, _) = tokio::time::timeout(
-            std::time::Duration::from_secs(10),
-            tokio_tungstenite::connect_async(self.uri.relay_url.as_str()),
-        )
-        .await
-        .map_err(|_| anyhow::anyhow!("Connection timeout to NWC relay: {}", self.uri.relay_url))?
-        .map_err(|e| anyhow::anyhow!("WS connect failed to {}: {}", self.uri.relay_url, e))?;
-
-        tracing::debug!("NWC: connected to relay, subscribing for response first");
-
-        // Subscribe BEFORE sending the event to avoid race conditions.
-        // If the wallet responds faster than we can subscribe (e.g. lookup_invoice
-        // is just a DB lookup), and the relay treats NWC events as ephemeral,
-        // we'd miss the response entirely.
-        let sub = serde_json::json!(["REQ", "nwc-resp", {
-            "kinds": [23195],
-            "#p": [our_pk.to_hex()],
-            "#e": [event_id.to_hex()],
-            "limit": 1,
-        }]);
-        ws.send(Message::Text(sub.to_string().into())).await?;
-
-        // Now send the request event
-        let event_json = event.as_json();
-        let req = format!(r#"["EVENT",{}]"#, event_json);
-        ws.send(Message::Text(req.into())).await?;
-
-        tracing::debug!("NWC: EVENT sent after subscription active");
-
-        tracing::debug!(timeout_secs = timeout_secs, "NWC: subscribed, waiting for response");
-
-        // Wait for response
-        let result = tokio::time::timeout(std::time::Duration::from_secs(timeout_secs), async {
-            while let Some(msg) = ws.next().await {
-                let msg = msg?;
-                let text = match msg {
-                    Message::Text(t) => t.to_string(),
-                    Message::Close(frame) => {
-                        tracing::warn!("NWC: relay closed connection: {:?}", frame);
-                        break;
-                    }
-                    _ => continue,
-                };
+        let (reply, response) = oneshot::channel();
+        self.connection()
+            .send(Outgoing { event, reply })
+            .map_err(|_| anyhow::anyhow!("NWC connection to {} stopped", self.uri.relay_url))?;
 
-                let parsed: serde_json::Value = match serde_json::from_str(&text) {
-                    Ok(v) => v,
-                    Err(_) => continue,
-                };
-
-                let arr = match parsed.as_array() {
-                    Some(a) if !a.is_empty() => a,
-                    _ => continue,
-                };
+        let response = tokio::time::timeout(Duration::from_secs(timeout_secs), response)
+            .await
+            .map_err(|_| {
+                tracing::error!(method = %method, relay = %self.uri.relay_url, "NWC: timeout waiting for response");
+                anyhow::anyhow!("Timeout waiting for NWC {} response from {}", method, self.uri.relay_url)
+            })?
+            .map_err(|_| anyhow::anyhow!("NWC connection to {} stopped", self.uri.relay_url))?
+            .map_err(|e| anyhow::anyhow!(e))?;
+
+        tracing::debug!(method = %method, "NWC: got response");
+        if let Some(ref err) = response.error {
+            tracing::error!(
+                code = ?err.code,
+                message = %err.message,
+                "NWC: wallet returned error"
+            );
+        }
+        Ok(response)
+    }
 
-                let msg_type = arr[0].as_str().unwrap_or("");
-                tracing::debug!(msg_type = %msg_type, "NWC: received relay message");
-
-                match msg_type {
-                    "OK" => {
-                        let accepted = arr.get(2).and_then(|v| v.as_bool()).unwrap_or(false);
-                        let reason = arr.get(3).and_then(|v| v.as_str()).unwrap_or("");
-                        if accepted {
-                            tracing::debug!("NWC: EVENT accepted by relay");
-                        } else {
-                            tracing::error!(reason = %reason, "NWC: EVENT rejected by relay");
-                            return Err(anyhow::anyhow!("NWC relay rejected event: {}", reason));
-                        }
-                    }
-                    "EOSE" => {
-                        tracing::debug!("NWC: EOSE received, no cached response - waiting for live event");
-                    }
-                    "NOTICE" => {
-                        let notice = arr.get(1).and_then(|v| v.as_str()).unwrap_or("");
-                        tracing::warn!(notice = %notice, "NWC: relay NOTICE");
-                    }
-                    "EVENT" if arr.len() >= 3 => {
-                        tracing::debug!("NWC: received response EVENT, decrypting");
-                        let event_value = &arr[2];
-                        let resp_event: Event = match Event::from_value(event_value.clone()) {
-                            Ok(e) => e,
-                            Err(e) => {
-                                tracing::warn!("NWC: failed to parse response event: {}", e);
-                                continue;
-                            }
-                        };
-
-                        match Response::from_event(&self.uri, &resp_event) {
-                            Ok(response) => {
-                                tracing::info!(method = %method, "NWC: got response");
-                                if let Some(ref err) = response.error {
-                                    tracing::error!(
-                                        code = ?err.code,
-                                        message = %err.message,
-                                        "NWC: wallet returned error"
-                                    );
-                                }
-                                return Ok(response);
-                            }
-                            Err(e) => {
-                                tracing::error!("NWC: failed to decrypt/parse response: {}", e);
-                                continue;
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            Err(anyhow::anyhow!("Connection closed without response"))
+    /// The queue to this wallet's relay connection, started on first use.
+    fn connection(&self) -> &mpsc::UnboundedSender<Outgoing> {
+        self.connection.get_or_init(|| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            tokio::spawn(run_connection(self.uri.clone(), rx, self.signals.clone()));
+            tx
         })
-        .await
-        .map_err(|_| {
-            tracing::error!(method = %method, relay = %self.uri.relay_url, "NWC: timeout waiting for response");
-            anyhow::anyhow!("Timeout waiting for NWC {} response from {}", method, self.uri.relay_url)
-        })??;
-
-        let _ = ws.close(None).await;
-        Ok(result)
     }
 
     pub async fn make_invoice(
@@ -190,14 +109,35 @@ impl NwcClient {
     ) -> Result<InvoiceResponse, anyhow::Error> {
         tracing::info!(amount_msats = amount_msats, memo = %memo, "NWC: requesting invoice");
 
-        let request = Request::make_invoice(MakeInvoiceRequestParams {
+        self.request_invoice(MakeInvoiceRequestParams {
             amount: amount_msats,
             description: Some(memo.to_string()),
             description_hash: None,
             expiry: None,
-        });
+        })
+        .await
+    }
 
-        let response = self.send_and_wait(request).await?;
+    /// Request an invoice committing to `description_hash` (hex sha256), as
+    /// LNURL-pay requires for the metadata it served.
+    pub async fn make_invoice_with_description_hash(
+        &self,
+        amount_msats: u64,
+        description_hash: &str,
+    ) -> Result<InvoiceResponse, anyhow::Error> {
+        tracing::info!(amount_msats = amount_msats, "NWC: requesting invoice for description hash");
+
+        self.request_invoice(MakeInvoiceRequestParams {
+            amount: amount_msats,
+            description: None,
+            description_hash: Some(description_hash.to_string()),
+            expiry: None,
+        })
+        .await
+    }
+
+    async fn request_invoice(&self, params: MakeInvoiceRequestParams) -> Result<InvoiceResponse, anyhow::Error> {
+        let response = self.send_and_wait(Request::make_invoice(params)).await?;
 
         let result = response
             .to_make_invoice()
@@ -209,6 +149,7 @@ impl NwcClient {
         tracing::info!(payment_hash = %payment_hash, "NWC: invoice created");
 
         Ok(InvoiceResponse {
+            expires_at: crate::bolt11::parse(&invoice).map(|i| i.expires_at),
             invoice,
             payment_hash,
         })
@@ -261,188 +202,405 @@ impl NwcClient {
         Ok(InvoiceStatus::Pending)
     }
 
-    pub async fn subscribe_and_watch_invoice(
+    /// Watch an invoice until it is paid or expires. Wallets that push
+    /// `payment_received` notifications settle it as soon as they do; for
+    /// others it is looked up every 15 seconds. With `expires_at` the
+    /// invoice is marked expired at that time, after a last lookup; without
+    /// it the watch gives up after an hour.
+    pub async fn watch_invoice(
         &self,
         payment_hash: String,
+        expires_at: Option<u64>,
         status_tx: tokio::sync::watch::Sender<InvoiceStatus>,
     ) -> Result<(), anyhow::Error> {
         let max_retries = 3u32;
-        let mut attempt = 0u32;
+        let mut failures = 0u32;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let lifetime = expires_at.map_or(3600, |at| at.saturating_sub(now));
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(lifetime);
+        let poll = Duration::from_secs(15);
+        let mut poll_interval = tokio::time::interval_at(tokio::time::Instant::now() + poll, poll);
+        // Notifications are ephemeral and a dropped one is never resent, so
+        // keep looking the invoice up now and then even when they arrive
+        let fallback = Duration::from_secs(60);
+        let mut fallback_interval = tokio::time::interval_at(tokio::time::Instant::now() + fallback, fallback);
+
+        // Listen before the first lookup, so a payment between the two isn't missed
+        let mut notifications = self.signals.events.subscribe();
+        let mut pushed = false;
+        // The first lookup catches an invoice that's already paid
+        let mut check = true;
 
         loop {
-            match self
-                .watch_invoice_connection(&payment_hash, &status_tx)
-                .await
-            {
-                Ok(()) => return Ok(()),
-                Err(e) => {
-                    // If we already sent a terminal status, we're done
-                    if *status_tx.borrow() != InvoiceStatus::Pending {
+            if check {
+                check = false;
+                match self.lookup_invoice(&payment_hash).await {
+                    Ok(InvoiceStatus::Pending) => failures = 0,
+                    Ok(status) => {
+                        tracing::info!(payment_hash = %payment_hash, status = ?status, "NWC: watch detected settlement");
+                        let _ = status_tx.send(status);
                         return Ok(());
                     }
-                    attempt += 1;
-                    if attempt > max_retries {
-                        tracing::error!(
+                    Err(e) => {
+                        failures += 1;
+                        if failures > max_retries {
+                            tracing::error!(
+                                payment_hash = %payment_hash,
+                                error = %e,
+                                "NWC: watch giving up after {} retries",
+                                max_retries
+                            );
+                            return Err(e);
+                        }
+                        tracing::warn!(
                             payment_hash = %payment_hash,
+                            attempt = failures,
                             error = %e,
-                            "NWC: watch giving up after {} retries",
-                            max_retries
+                            "NWC: watch lookup failed, retrying"
                         );
-                        return Err(e);
                     }
-                    let backoff = std::time::Duration::from_secs(2u64.pow(attempt));
-                    tracing::warn!(
-                        payment_hash = %payment_hash,
-                        attempt = attempt,
-                        backoff_secs = backoff.as_secs(),
-                        error = %e,
-                        "NWC: watch connection failed, retrying"
-                    );
-                    tokio::time::sleep(backoff).await;
+                }
+                // Known by now: the lookup connected and got the wallet's info
+                pushed = failures == 0 && self.pushes_payments().await;
+            }
+
+            tokio::select! {
+                _ = poll_interval.tick(), if !pushed => check = true,
+                _ = fallback_interval.tick(), if pushed => check = true,
+                notification = notifications.recv(), if pushed => match notification {
+                    Ok(WalletEvent::PaymentReceived(hash)) if hash == payment_hash => {
+                        tracing::info!(payment_hash = %payment_hash, "NWC: wallet notified payment");
+                        let _ = status_tx.send(InvoiceStatus::Paid);
+                        return Ok(());
+                    }
+                    Ok(WalletEvent::PaymentReceived(_)) => {}
+                    // Notifications may have been missed meanwhile
+                    Ok(WalletEvent::Reconnected) | Err(broadcast::error::RecvError::Lagged(_)) => check = true,
+                    Err(broadcast::error::RecvError::Closed) => pushed = false,
+                },
+                _ = tokio::time::sleep_until(deadline) => {
+                    if expires_at.is_none() {
+                        tracing::info!(payment_hash = %payment_hash, "NWC: watch max lifetime reached");
+                        return Ok(());
+                    }
+                    // Don't miss a payment that landed since the last poll
+                    let status = match self.lookup_invoice(&payment_hash).await {
+                        Ok(InvoiceStatus::Paid) => InvoiceStatus::Paid,
+                        _ => InvoiceStatus::Expired,
+                    };
+                    tracing::info!(payment_hash = %payment_hash, status = ?status, "NWC: watch reached invoice expiry");
+                    let _ = status_tx.send(status);
+                    return Ok(());
                 }
             }
         }
     }
 
-    async fn watch_invoice_connection(
-        &self,
-        payment_hash: &str,
-        status_tx: &tokio::sync::watch::Sender<InvoiceStatus>,
-    ) -> Result<(), anyhow::Error> {
-        tracing::info!(payment_hash = %payment_hash, "NWC: watch starting persistent connection");
-
-        let (mut ws, _) = tokio::time::timeout(
-            std::time::Duration::from_secs(10),
-            tokio_tungstenite::connect_async(self.uri.relay_url.as_str()),
-        )
-        .await
-        .map_err(|_| anyhow::anyhow!("Connection timeout to NWC relay"))?
-        .map_err(|e| anyhow::anyhow!("WS connect failed: {}", e))?;
+    /// Whether the wallet advertises `payment_received` notifications in its
+    /// info event (NIP-47). Waits briefly for the connection to learn it.
+    pub async fn pushes_payments(&self) -> bool {
+        let mut capability = self.signals.capability.subscribe();
+        let supported = tokio::time::timeout(Duration::from_secs(5), capability.wait_for(Option::is_some)).await;
+        let supported = matches!(supported, Ok(Ok(supported)) if *supported == Some(true));
+        supported
+    }
 
-        let our_pk = self.keys().public_key();
+    pub async fn get_info(&self) -> Result<(), anyhow::Error> {
+        let request = Request::get_info();
+        let response = self.send_and_wait(request).await?;
+        let _info = response
+            .to_get_info()
+            .map_err(|e| anyhow::anyhow!("NWC get_info failed: {}", e))?;
+        Ok(())
+    }
+}
 
-        // Subscribe for NWC responses (23195) and notifications (23197)
-        let sub = serde_json::json!(["REQ", "nwc-watch", {
-            "kinds": [23195, 23197],
-            "#p": [our_pk.to_hex()],
-        }]);
-        ws.send(Message::Text(sub.to_string().into())).await?;
+// ---------------------------------------------------------------------------
+// Wallet relay connection, shared by every request of a client
+// ---------------------------------------------------------------------------
 
-        // Send initial lookup_invoice to catch already-paid
-        self.send_lookup_on_ws(&mut ws, payment_hash).await?;
+/// A request on its way to the wallet, with where to send its response.
+struct Outgoing {
+    event: Event,
+    reply: oneshot::Sender<Result<Response, String>>,
+}
 
-        let mut poll_interval = tokio::time::interval(std::time::Duration::from_secs(15));
-        poll_interval.tick().await; // consume the immediate first tick
-        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(3600);
+/// Something the wallet relay connection saw besides a response.
+#[derive(Debug, Clone)]
+enum WalletEvent {
+    /// The wallet received a payment with this payment hash (kind 23197).
+    PaymentReceived(String),
+    /// The connection was (re)opened; notifications sent while it was
+    /// down are lost.
+    Reconnected,
+}
 
-        loop {
-            tokio::select! {
-                msg = ws.next() => {
-                    let msg = match msg {
-                        Some(Ok(m)) => m,
-                        Some(Err(e)) => return Err(anyhow::anyhow!("WS error: {}", e)),
-                        None => return Err(anyhow::anyhow!("WS connection closed")),
-                    };
-                    let text = match msg {
-                        Message::Text(t) => t.to_string(),
-                        Message::Close(_) => return Err(anyhow::anyhow!("WS closed by relay")),
-                        _ => continue,
-                    };
+/// What the connection learns about the wallet, for anyone listening.
+#[derive(Debug, Clone)]
+struct WalletSignals {
+    /// Whether the wallet's info event (kind 13194) lists
+    /// `payment_received` notifications; `None` until it has been looked for.
+    capability: Arc<watch::Sender<Option<bool>>>,
+    events: broadcast::Sender<WalletEvent>,
+}
 
-                    let parsed: serde_json::Value = match serde_json::from_str(&text) {
-                        Ok(v) => v,
-                        Err(_) => continue,
-                    };
-                    let arr = match parsed.as_array() {
-                        Some(a) if a.len() >= 3 && a[0].as_str() == Some("EVENT") => a,
-                        _ => continue,
-                    };
+impl WalletSignals {
+    fn new() -> Self {
+        Self {
+            capability: Arc::new(watch::Sender::new(None)),
+            events: broadcast::channel(64).0,
+        }
+    }
+}
 
-                    let resp_event: Event = match Event::from_value(arr[2].clone()) {
-                        Ok(e) => e,
-                        Err(_) => continue,
-                    };
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
-                    let response = match Response::from_event(&self.uri, &resp_event) {
-                        Ok(r) => r,
-                        Err(_) => continue,
-                    };
+/// Failed connection attempts in a row before queued requests are failed.
+const CONNECT_ATTEMPTS: u32 = 3;
 
-                    // Check if this response is about our payment_hash
-                    if let Ok(result) = response.to_lookup_invoice() {
-                        if result.payment_hash == payment_hash {
-                            if result.settled_at.is_some() || result.preimage.as_ref().is_some_and(|p| !p.is_empty()) {
-                                tracing::info!(payment_hash = %payment_hash, "NWC: watch detected payment");
-                                let _ = status_tx.send(InvoiceStatus::Paid);
-                                let _ = ws.close(None).await;
-                                return Ok(());
-                            }
-                            if let Some(expires_at) = result.expires_at {
-                                let now = std::time::SystemTime::now()
-                                    .duration_since(std::time::UNIX_EPOCH)
-                                    .unwrap()
-                                    .as_secs();
-                                if now > expires_at.as_u64() {
-                                    tracing::info!(payment_hash = %payment_hash, "NWC: watch detected expiry");
-                                    let _ = status_tx.send(InvoiceStatus::Expired);
-                                    let _ = ws.close(None).await;
-                                    return Ok(());
-                                }
-                            }
-                        }
-                    }
+/// Keep one connection to the wallet relay for `requests`, opening it when
+/// there is something to send or someone listening for notifications, and
+/// reopening it (with backoff) when it drops. Ends once every client
+/// sharing it is gone.
+async fn run_connection(
+    uri: NostrWalletConnectURI,
+    mut requests: mpsc::UnboundedReceiver<Outgoing>,
+    signals: WalletSignals,
+) {
+    // Requests sent and not answered yet, by request event id. They are
+    // sent again on a new connection.
+    let mut waiting: HashMap<EventId, Outgoing> = HashMap::new();
+    let mut failures = 0u32;
+
+    loop {
+        waiting.retain(|_, o| !o.reply.is_closed());
+        if waiting.is_empty() && signals.events.receiver_count() == 0 {
+            match requests.recv().await {
+                Some(o) => {
+                    waiting.insert(o.event.id, o);
                 }
-                _ = poll_interval.tick() => {
-                    tracing::debug!(payment_hash = %payment_hash, "NWC: watch re-polling");
-                    if let Err(e) = self.send_lookup_on_ws(&mut ws, payment_hash).await {
-                        tracing::warn!(error = %e, "NWC: watch re-poll send failed");
-                        return Err(e.into());
+                None => return,
+            }
+        }
+
+        let mut ws = match connect(&uri).await {
+            Ok(ws) => ws,
+            Err(e) => {
+                failures += 1;
+                tracing::warn!(relay = %uri.relay_url, attempt = failures, error = %e, "NWC: connection failed");
+                if failures.is_multiple_of(CONNECT_ATTEMPTS) {
+                    for (_, o) in waiting.drain() {
+                        let _ = o.reply.send(Err(e.to_string()));
                     }
                 }
-                _ = tokio::time::sleep_until(deadline) => {
-                    tracing::info!(payment_hash = %payment_hash, "NWC: watch max lifetime reached");
-                    let _ = ws.close(None).await;
-                    return Ok(());
-                }
+                tokio::time::sleep(Duration::from_secs(2u64.pow(failures.min(5)))).await;
+                continue;
+            }
+        };
+        failures = 0;
+        let _ = signals.events.send(WalletEvent::Reconnected);
+
+        match serve(&uri, &mut ws, &mut requests, &mut waiting, &signals).await {
+            Ok(()) => {
+                let _ = ws.close(None).await;
+                return;
             }
+            Err(e) => tracing::warn!(relay = %uri.relay_url, error = %e, "NWC: connection lost"),
         }
     }
+}
 
-    async fn send_lookup_on_ws<S>(&self, ws: &mut S, payment_hash: &str) -> Result<(), anyhow::Error>
-    where
-        S: SinkExt<Message> + Unpin,
-        S::Error: std::fmt::Display,
-    {
-        let request = Request::lookup_invoice(LookupInvoiceRequestParams {
-            payment_hash: Some(payment_hash.to_string()),
-            invoice: None,
-        });
+/// Open a connection to the wallet relay, subscribed to every response and
+/// notification addressed to this client and to the wallet's info event.
+async fn connect(uri: &NostrWalletConnectURI) -> Result<WsStream, anyhow::Error> {
+    tracing::debug!(relay = %uri.relay_url, "NWC: connecting to relay");
+    let (mut ws, _) = tokio::time::timeout(
+        Duration::from_secs(10),
+        tokio_tungstenite::connect_async(uri.relay_url.as_str()),
+    )
+    .await
+    .map_err(|_| anyhow::anyhow!("Connection timeout to NWC relay: {}", uri.relay_url))?
+    .map_err(|e| anyhow::anyhow!("WS connect failed to {}: {}", uri.relay_url, e))?;
+
+    // Subscribe before sending anything, so a fast wallet's response isn't
+    // missed; responses are ephemeral and never replayed
+    let our_pk = Keys::new(uri.secret.clone()).public_key();
+    let since = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        .saturating_sub(60);
+    let sub = serde_json::json!(["REQ", "nwc-resp", {
+        "kinds": [23195, 23197],
+        "#p": [our_pk.to_hex()],
+        "since": since,
+    }]);
+    ws.send(Message::Text(sub.to_string().into())).await?;
+    let info = serde_json::json!(["REQ", "nwc-info", {
+        "kinds": [13194],
+        "authors": [uri.public_key.to_hex()],
+        "limit": 1,
+    }]);
+    ws.send(Message::Text(info.to_string().into())).await?;
+    Ok(ws)
+}
 
-        let event = request
-            .to_event(&self.uri)
-            .map_err(|e| anyhow::anyhow!("Failed to build NWC event: {}", e))?;
+/// Send requests and route responses on `ws` until it fails (`Err`) or the
+/// clients are gone (`Ok`).
+async fn serve(
+    uri: &NostrWalletConnectURI,
+    ws: &mut WsStream,
+    requests: &mut mpsc::UnboundedReceiver<Outgoing>,
+    waiting: &mut HashMap<EventId, Outgoing>,
+    signals: &WalletSignals,
+) -> Result<(), anyhow::Error> {
+    for o in waiting.values() {
+        send_event(ws, &o.event).await?;
+    }
 
-        let event_json = event.as_json();
-        let msg = format!(r#"["EVENT",{}]"#, event_json);
-        ws.send(Message::Text(msg.into()))
-            .await
-            .map_err(|e| anyhow::anyhow!("WS send failed: {}", e))?;
-        Ok(())
+    let mut prune = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        tokio::select! {
+            request = requests.recv() => {
+                let Some(o) = request else {
+                    return Ok(());
+                };
+                let id = o.event.id;
+                waiting.insert(id, o);
+                send_event(ws, &waiting[&id].event).await?;
+            }
+            msg = ws.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(t))) => t.to_string(),
+                    Some(Ok(Message::Close(frame))) => return Err(anyhow::anyhow!("relay closed connection: {:?}", frame)),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(anyhow::anyhow!("WS error: {}", e)),
+                    None => return Err(anyhow::anyhow!("WS connection closed")),
+                };
+                route_message(uri, &text, waiting, signals);
+            }
+            // Drop requests whose caller stopped waiting
+            _ = prune.tick() => waiting.retain(|_, o| !o.reply.is_closed()),
+        }
     }
+}
 
-    pub async fn get_info(&self) -> Result<(), anyhow::Error> {
-        let request = Request::get_info();
-        let response = self.send_and_wait(request).await?;
-        let _info = response
-            .to_get_info()
-            .map_err(|e| anyhow::anyhow!("NWC get_info failed: {}", e))?;
-        Ok(())
+async fn send_event(ws: &mut WsStream, event: &Event) -> Result<(), anyhow::Error> {
+    let msg = format!(r#"["EVENT",{}]"#, event.as_json());
+    ws.send(Message::Text(msg.into()))
+        .await
+        .map_err(|e| anyhow::anyhow!("WS send failed: {}", e))
+}
+
+/// Hand a relay message to whoever it is for: a response from the wallet
+/// names its request in an `e` tag, a rejection in the OK message, and
+/// notifications and the wallet's info go to the client's signals.
+fn route_message(
+    uri: &NostrWalletConnectURI,
+    text: &str,
+    waiting: &mut HashMap<EventId, Outgoing>,
+    signals: &WalletSignals,
+) {
+    let Ok(serde_json::Value::Array(msg)) = serde_json::from_str::<serde_json::Value>(text) else {
+        return;
+    };
+    match msg.first().and_then(|v| v.as_str()) {
+        Some("OK") => {
+            let accepted = msg.get(2).and_then(|v| v.as_bool()).unwrap_or(false);
+            if accepted {
+                return;
+            }
+            let reason = msg.get(3).and_then(|v| v.as_str()).unwrap_or("");
+            let id = msg.get(1).and_then(|v| v.as_str()).and_then(|id| EventId::from_hex(id).ok());
+            if let Some(o) = id.and_then(|id| waiting.remove(&id)) {
+                tracing::error!(reason = %reason, "NWC: EVENT rejected by relay");
+                let _ = o.reply.send(Err(format!("NWC relay rejected event: {}", reason)));
+            }
+        }
+        Some("NOTICE") => {
+            let notice = msg.get(1).and_then(|v| v.as_str()).unwrap_or("");
+            tracing::warn!(notice = %notice, "NWC: relay NOTICE");
+        }
+        // No info event stored: the wallet doesn't advertise notifications
+        Some("EOSE") if msg.get(1).and_then(|v| v.as_str()) == Some("nwc-info") => {
+            signals.capability.send_if_modified(|supported| {
+                let unknown = supported.is_none();
+                if unknown {
+                    *supported = Some(false);
+                }
+                unknown
+            });
+        }
+        Some("EVENT") if msg.len() >= 3 => {
+            let Ok(event) = Event::from_value(msg[2].clone()) else {
+                return;
+            };
+            // The info event is unencrypted, so only the signature shows
+            // the wallet sent it
+            if event.pubkey != uri.public_key || event.verify().is_err() {
+                return;
+            }
+            match event.kind.as_u16() {
+                13194 => {
+                    let supported = notification_types(&event).any(|t| t == "payment_received");
+                    tracing::debug!(supported = supported, "NWC: wallet info received");
+                    signals.capability.send_replace(Some(supported));
+                }
+                23197 => {
+                    if let Some(hash) = received_payment_hash(uri, &event) {
+                        tracing::debug!(payment_hash = %hash, "NWC: wallet notified payment received");
+                        let _ = signals.events.send(WalletEvent::PaymentReceived(hash));
+                    }
+                }
+                _ => {
+                    let request_id = event.tags.iter().find_map(|tag| {
+                        let tag = tag.as_vec();
+                        (tag.len() >= 2 && tag[0] == "e").then(|| EventId::from_hex(&tag[1]).ok())?
+                    });
+                    let Some(o) = request_id.and_then(|id| waiting.remove(&id)) else {
+                        return;
+                    };
+                    let response = Response::from_event(uri, &event)
+                        .map_err(|e| format!("Failed to decrypt/parse NWC response: {}", e));
+                    let _ = o.reply.send(response);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The notification types a wallet's info event lists in its
+/// `notifications` tag.
+fn notification_types(info: &Event) -> impl Iterator<Item = String> + '_ {
+    info.tags.iter().flat_map(|tag| {
+        let tag = tag.as_vec();
+        let types = match tag.first().map(String::as_str) {
+            Some("notifications") => tag[1..].join(" "),
+            _ => String::new(),
+        };
+        types.split_whitespace().map(str::to_string).collect::<Vec<_>>()
+    })
+}
+
+/// The payment hash of a `payment_received` notification.
+fn received_payment_hash(uri: &NostrWalletConnectURI, event: &Event) -> Option<String> {
+    let json = nip04::decrypt(&uri.secret, &event.pubkey, &event.content).ok()?;
+    let notification: serde_json::Value = serde_json::from_str(&json).ok()?;
+    if notification["notification_type"] != "payment_received" {
+        return None;
     }
+    notification["notification"]["payment_hash"].as_str().map(str::to_string)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use nostr::{EventBuilder, Kind, Tag};
 
     #[test]
     fn parse_connection_string() {
@@ -455,6 +613,175 @@ mod tests {
         );
     }
 
+    /// An event from the wallet to the sender of `request`, answering it.
+    fn from_wallet(wallet: &Keys, kind: u16, request: &Event, json: &str) -> Event {
+        let content = nip04::encrypt(wallet.secret_key().unwrap(), &request.pubkey, json).unwrap();
+        EventBuilder::new(
+            Kind::from(kind),
+            content,
+            [Tag::public_key(request.pubkey), Tag::event(request.id)],
+        )
+        .to_event(wallet)
+        .unwrap()
+    }
+
+    /// The payment hash a lookup_invoice request asks about.
+    fn requested_hash(wallet: &Keys, request: &Event) -> String {
+        let json = nip04::decrypt(wallet.secret_key().unwrap(), &request.pubkey, &request.content).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        json["params"]["payment_hash"].as_str().unwrap().to_string()
+    }
+
+    fn connection_string(wallet: &Keys, port: u16) -> String {
+        format!(
+            "nostr+walletconnect://{}?relay=ws://127.0.0.1:{}&secret={}",
+            wallet.public_key().to_hex(),
+            port,
+            Keys::generate().secret_key().unwrap().to_secret_hex()
+        )
+    }
+
+    #[tokio::test]
+    async fn requests_share_one_connection_and_get_their_own_responses() {
+        use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+        let wallet = Keys::generate();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        // A relay that is also the wallet: invoices whose hash starts with
+        // "aa" are paid. It answers requests in pairs, the later one first.
+        let accepted = connections.clone();
+        let wallet_keys = wallet.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, SeqCst);
+                let wallet = wallet_keys.clone();
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                    let mut requests = Vec::new();
+                    while let Some(Ok(Message::Text(text))) = ws.next().await {
+                        let msg: Vec<serde_json::Value> = serde_json::from_str(&text).unwrap();
+                        if msg[0] != "EVENT" {
+                            continue;
+                        }
+                        requests.push(Event::from_value(msg[1].clone()).unwrap());
+                        if requests.len() < 2 {
+                            continue;
+                        }
+                        for request in requests.drain(..).rev() {
+                            let hash = requested_hash(&wallet, &request);
+                            let settled = if hash.starts_with("aa") { r#","settled_at":2"# } else { "" };
+                            let result = format!(
+                                r#"{{"result_type":"lookup_invoice","result":{{"payment_hash":"{}","amount":1000,"fees_paid":0,"created_at":1{}}}}}"#,
+                                hash, settled
+                            );
+                            let response = from_wallet(&wallet, 23195, &request, &result);
+                            let msg = format!(r#"["EVENT","nwc-resp",{}]"#, response.as_json());
+                            ws.send(Message::Text(msg.into())).await.unwrap();
+                        }
+                    }
+                });
+            }
+        });
+
+        let client = NwcClient::from_connection_string(&connection_string(&wallet, port)).unwrap();
+        let other = client.clone();
+        for _ in 0..2 {
+            let (paid, pending) = tokio::join!(client.lookup_invoice("aa01"), other.lookup_invoice("bb02"));
+            assert_eq!(paid.unwrap(), InvoiceStatus::Paid);
+            assert_eq!(pending.unwrap(), InvoiceStatus::Pending);
+        }
+        assert_eq!(connections.load(SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn payment_notifications_settle_a_watched_invoice_before_the_next_lookup() {
+        let wallet = Keys::generate();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // A wallet that advertises notifications, reports the invoice unpaid
+        // and then pushes its payment
+        let wallet_keys = wallet.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+            while let Some(Ok(Message::Text(text))) = ws.next().await {
+                let msg: Vec<serde_json::Value> = serde_json::from_str(&text).unwrap();
+                let mut replies = Vec::new();
+                if msg[0] == "REQ" && msg[1] == "nwc-info" {
+                    let info = EventBuilder::new(
+                        Kind::from(13194),
+                        "get_info lookup_invoice make_invoice notifications",
+                        [Tag::parse(&["notifications", "payment_received payment_sent"]).unwrap()],
+                    )
+                    .to_event(&wallet_keys)
+                    .unwrap();
+                    replies.push(format!(r#"["EVENT","nwc-info",{}]"#, info.as_json()));
+                    replies.push(r#"["EOSE","nwc-info"]"#.to_string());
+                } else if msg[0] == "EVENT" {
+                    let request = Event::from_value(msg[1].clone()).unwrap();
+                    let hash = requested_hash(&wallet_keys, &request);
+                    let pending = format!(
+                        r#"{{"result_type":"lookup_invoice","result":{{"payment_hash":"{}","amount":1000,"fees_paid":0,"created_at":1}}}}"#,
+                        hash
+                    );
+                    let paid = format!(
+                        r#"{{"notification_type":"payment_received","notification":{{"payment_hash":"{}","amount":1000,"fees_paid":0,"created_at":1,"settled_at":2}}}}"#,
+                        hash
+                    );
+                    for (kind, json) in [(23195, pending), (23197, paid)] {
+                        let event = from_wallet(&wallet_keys, kind, &request, &json);
+                        replies.push(format!(r#"["EVENT","nwc-resp",{}]"#, event.as_json()));
+                    }
+                }
+                for reply in replies {
+                    ws.send(Message::Text(reply.into())).await.unwrap();
+                }
+            }
+        });
+
+        let client = NwcClient::from_connection_string(&connection_string(&wallet, port)).unwrap();
+        let (status_tx, status_rx) = tokio::sync::watch::channel(InvoiceStatus::Pending);
+        let watch = client.watch_invoice("cc03".to_string(), None, status_tx);
+        tokio::time::timeout(Duration::from_secs(5), watch).await.unwrap().unwrap();
+        assert!(client.pushes_payments().await);
+        assert_eq!(*status_rx.borrow(), InvoiceStatus::Paid);
+    }
+
+    #[test]
+    fn forged_wallet_events_are_ignored() {
+        let wallet = Keys::generate();
+        let uri = NwcClient::from_connection_string(&connection_string(&wallet, 1)).unwrap().uri;
+        let signals = WalletSignals::new();
+        let info = EventBuilder::new(
+            Kind::from(13194),
+            "get_info lookup_invoice",
+            [Tag::parse(&["notifications", "payment_received"]).unwrap()],
+        )
+        .to_event(&Keys::generate())
+        .unwrap();
+
+        // The wallet's pubkey on someone else's signature
+        let mut forged = info.as_json().replace(&info.pubkey.to_hex(), &wallet.public_key().to_hex());
+        forged = format!(r#"["EVENT","nwc-info",{}]"#, forged);
+        route_message(&uri, &forged, &mut HashMap::new(), &signals);
+        assert_eq!(*signals.capability.borrow(), None);
+
+        let info = EventBuilder::new(
+            Kind::from(13194),
+            "get_info lookup_invoice",
+            [Tag::parse(&["notifications", "payment_received"]).unwrap()],
+        )
+        .to_event(&wallet)
+        .unwrap();
+        route_message(&uri, &format!(r#"["EVENT","nwc-info",{}]"#, info.as_json()), &mut HashMap::new(), &signals);
+        assert_eq!(*signals.capability.borrow(), Some(true));
+    }
+
     #[test]
     fn parse_invalid_prefix() {
         let result = NwcClient::from_connection_string("invalid://test");
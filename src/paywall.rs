@@ -1,10 +1,15 @@
-use crate::config::PaywallConfig;
+use crate::cashu::Token;
+use crate::config::{PaywallConfig, PaywallReminderConfig};
 use crate::nwc::{InvoiceStatus, NwcClient};
-use nostr::PublicKey;
+use crate::policy::SetDirectory;
+use crate::webhook::{Alert, AlertKind, Notifier};
+use crate::zap::ZapTerms;
+use nostr::{Event, Keys, PublicKey};
 use serde::Serialize;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
@@ -52,6 +57,16 @@ impl PaywallSet {
         }
     }
 
+    /// Set the expiration for a pubkey, overriding any existing entry.
+    pub fn set(&self, pk: PublicKey, expires_at: u64) {
+        self.inner.write().unwrap().insert(pk, expires_at);
+    }
+
+    /// Remove a pubkey from the set. Returns `true` if it was present.
+    pub fn remove(&self, pk: &PublicKey) -> bool {
+        self.inner.write().unwrap().remove(pk).is_some()
+    }
+
     pub fn remove_expired(&self) -> usize {
         let mut map = self.inner.write().unwrap();
         let now = SystemTime::now()
@@ -67,6 +82,10 @@ impl PaywallSet {
         self.inner.read().unwrap().len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.inner.read().unwrap().is_empty()
+    }
+
     pub fn list_entries(&self) -> Vec<(PublicKey, u64)> {
         self.inner
             .read()
@@ -89,10 +108,11 @@ struct PendingPayment {
     pubkey: PublicKey,
     #[allow(dead_code)]
     payment_hash: String,
-    #[allow(dead_code)]
     amount_sats: u64,
     period_days: u32,
     created_at: u64,
+    /// When the invoice itself expires, if it could be read from it.
+    expires_at: Option<u64>,
     status: tokio::sync::watch::Receiver<InvoiceStatus>,
     _listener_handle: JoinHandle<()>,
 }
@@ -106,7 +126,23 @@ struct PaywallEntry {
     set: PaywallSet,
     nwc_client: NwcClient,
     pending_payments: Arc<RwLock<HashMap<String, PendingPayment>>>,
+    revenue: Revenue,
     handle: Option<JoinHandle<()>>,
+    zaps: Option<ZapTerms>,
+    /// Who signs the zap receipts, once known.
+    zap_signer: Arc<OnceLock<PublicKey>>,
+    /// Payment hashes of the zaps already credited, with the access expiry
+    /// each granted. Kept on disk until then, so replayed receipts are
+    /// ignored across restarts.
+    credited_zaps: HashMap<[u8; 32], u64>,
+    zap_handle: Option<JoinHandle<()>>,
+}
+
+/// Lifetime totals of settled invoices for one paywall.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Revenue {
+    sats: u64,
+    payments: u64,
 }
 
 // ---------------------------------------------------------------------------
@@ -115,7 +151,15 @@ struct PaywallEntry {
 
 pub struct PaywallManager {
     entries: RwLock<HashMap<String, PaywallEntry>>,
+    /// Each paywall's whitelist by id, for relay policies to resolve at
+    /// check time.
+    sets: Arc<SetDirectory<PaywallSet>>,
     data_dir: PathBuf,
+    /// Bumped whenever a paywall is added, changed or removed.
+    generation: AtomicU64,
+    notifier: Arc<Notifier>,
+    /// Held while reading or writing the ecash collected from Cashu payments.
+    ecash_lock: tokio::sync::Mutex<()>,
 }
 
 #[derive(Serialize)]
@@ -124,6 +168,12 @@ pub struct PaywallInfo {
     pub price_sats: u64,
     pub period_days: u32,
     pub whitelist_count: usize,
+    /// Sats received from settled invoices since the paywall was created.
+    pub revenue_sats: u64,
+    pub payments: u64,
+    /// Lightning address that takes zaps for access, if enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zap_address: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -133,28 +183,50 @@ pub struct WhitelistEntry {
 }
 
 impl PaywallManager {
-    pub fn new(paywalls: HashMap<String, PaywallConfig>) -> Result<Arc<Self>, anyhow::Error> {
-        let data_dir = PathBuf::from("data/paywall");
+    /// Whitelists are persisted under `data_dir`.
+    pub fn new(
+        data_dir: PathBuf,
+        paywalls: HashMap<String, PaywallConfig>,
+        notifier: Arc<Notifier>,
+    ) -> Result<Arc<Self>, anyhow::Error> {
         let mut entries = HashMap::new();
+        let sets = Arc::new(SetDirectory::default());
 
         for (id, config) in paywalls {
             let nwc_client = NwcClient::from_connection_string(&config.nwc_string)
                 .map_err(|e| anyhow::anyhow!("Paywall '{}' invalid NWC string: {}", id, e))?;
+            validate_reminder(&config)
+                .map_err(|e| anyhow::anyhow!("Paywall '{}' {}", id, e))?;
+            let zaps = parse_zaps(&config)
+                .map_err(|e| anyhow::anyhow!("Paywall '{}' {}", id, e))?;
+            validate_cashu(&config)
+                .map_err(|e| anyhow::anyhow!("Paywall '{}' {}", id, e))?;
+            let set = PaywallSet::new();
+            sets.insert(&id, set.clone());
             entries.insert(
                 id,
                 PaywallEntry {
                     config,
-                    set: PaywallSet::new(),
+                    set,
                     nwc_client,
                     pending_payments: Arc::new(RwLock::new(HashMap::new())),
+                    revenue: Revenue::default(),
                     handle: None,
+                    zaps,
+                    zap_signer: Arc::new(OnceLock::new()),
+                    credited_zaps: HashMap::new(),
+                    zap_handle: None,
                 },
             );
         }
 
         Ok(Arc::new(Self {
             entries: RwLock::new(entries),
+            sets,
             data_dir,
+            generation: AtomicU64::new(0),
+            notifier,
+            ecash_lock: tokio::sync::Mutex::new(()),
         }))
     }
 
@@ -165,6 +237,31 @@ impl PaywallManager {
         for id in ids {
             self.start_background_task(&id).await;
         }
+
+        // Check each wallet is reachable, so a broken one is reported before
+        // a customer finds it
+        for (id, entry) in self.entries.read().await.iter() {
+            let manager = Arc::clone(self);
+            let id = id.clone();
+            let nwc = entry.nwc_client.clone();
+            tokio::spawn(async move {
+                if let Err(e) = nwc.get_info().await {
+                    manager.report_nwc_failure(Some(&id), &e);
+                }
+            });
+        }
+    }
+
+    fn report_nwc_failure(&self, id: Option<&str>, e: &anyhow::Error) {
+        let message = match id {
+            Some(id) => format!("Paywall '{}' NWC wallet check failed: {}", id, e),
+            None => format!("NWC wallet check failed: {}", e),
+        };
+        self.notifier.notify(Alert::new(
+            AlertKind::NwcFailed,
+            message,
+            serde_json::json!({ "paywall_id": id, "error": e.to_string() }),
+        ));
     }
 
     async fn start_background_task(self: &Arc<Self>, id: &str) {
@@ -181,12 +278,27 @@ impl PaywallManager {
             entry.set.replace(loaded);
             tracing::info!("Paywall '{}' loaded from disk: {} entries", id, count);
         }
+        let revenue_path = self.data_dir.join(format!("{}.revenue.bin", id));
+        if let Ok(revenue) = load_revenue(&revenue_path).await {
+            entry.revenue = revenue;
+        }
+        let zaps_path = self.data_dir.join(format!("{}.zaps.bin", id));
+        if let Ok(credited) = load_credited_zaps(&zaps_path).await {
+            entry.credited_zaps = credited;
+        }
 
         let set = entry.set.clone();
         let pending = Arc::clone(&entry.pending_payments);
         let disk_path = self.data_dir.join(format!("{}.bin", id));
         let paywall_id = id.to_string();
 
+        // Renewal reminders: keys were validated when the paywall was configured
+        let reminder = entry.config.reminder.clone().and_then(|r| {
+            Keys::parse(&r.secret_key).ok().map(|keys| (r, keys))
+        });
+        let reminders_path = self.data_dir.join(format!("{}.reminders.bin", id));
+        let mut reminded = load_from_disk(&reminders_path).await.unwrap_or_default();
+
         let handle = tokio::spawn(async move {
             loop {
                 tokio::time::sleep(std::time::Duration::from_secs(3600)).await;
@@ -201,7 +313,7 @@ impl PaywallManager {
                     );
                 }
 
-                // Clean up old pending payments (older than 1 hour)
+                // Clean up old pending payments (an hour old, or expired)
                 {
                     let mut pending_map = pending.write().await;
                     let now = SystemTime::now()
@@ -210,7 +322,7 @@ impl PaywallManager {
                         .as_secs();
                     let stale_keys: Vec<String> = pending_map
                         .iter()
-                        .filter(|(_, p)| now - p.created_at >= 3600)
+                        .filter(|(_, p)| now >= p.expires_at.unwrap_or(p.created_at + 3600))
                         .map(|(k, _)| k.clone())
                         .collect();
                     for key in stale_keys {
@@ -226,10 +338,71 @@ impl PaywallManager {
                 if let Err(e) = save_to_disk(&disk_path, &entries).await {
                     tracing::warn!("Failed to save paywall '{}' to disk: {}", paywall_id, e);
                 }
+
+                // Remind users whose access is about to expire
+                if let Some((config, keys)) = &reminder {
+                    send_reminders(&paywall_id, config, keys, &entries, &mut reminded).await;
+                    let reminded_entries: Vec<(PublicKey, u64)> =
+                        reminded.iter().map(|(pk, &exp)| (*pk, exp)).collect();
+                    if let Err(e) = save_to_disk(&reminders_path, &reminded_entries).await {
+                        tracing::warn!("Failed to save paywall '{}' reminders: {}", paywall_id, e);
+                    }
+                }
             }
         });
 
         entry.handle = Some(handle);
+
+        // Zap receipts: learn who signs them, then watch the confirmation
+        // relays. Receipts sent to the paywalled relays themselves come in
+        // through `record_zap_receipt` as they are published.
+        if let Some(zaps) = entry.zaps.clone() {
+            let signer = Arc::clone(&entry.zap_signer);
+            let manager = Arc::downgrade(self);
+            let paywall_id = id.to_string();
+            entry.zap_handle = Some(tokio::spawn(async move {
+                let mut retry_secs = 60;
+                while signer.get().is_none() {
+                    let found = match zaps.receipt_signer {
+                        Some(pk) => Ok(pk),
+                        None => crate::zap::lookup_receipt_signer(&zaps.lightning_address).await,
+                    };
+                    match found {
+                        Ok(pk) => {
+                            let _ = signer.set(pk);
+                        }
+                        Err(e) => {
+                            tracing::warn!("Paywall '{}': zap receipt signer lookup failed: {}", paywall_id, e);
+                            tokio::time::sleep(std::time::Duration::from_secs(retry_secs)).await;
+                            retry_secs = (retry_secs * 2).min(3600);
+                        }
+                    }
+                }
+
+                let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+                let watch = crate::zap::watch_relays(zaps.relays, zaps.recipient, tx);
+                let record = async {
+                    while let Some(receipt) = rx.recv().await {
+                        let Some(manager) = manager.upgrade() else {
+                            return;
+                        };
+                        manager.record_zap_receipt(&paywall_id, &receipt).await;
+                    }
+                };
+                tokio::join!(watch, record);
+            }));
+        }
+    }
+
+    /// Every paywall's whitelist by id, kept current as paywalls are added
+    /// and removed.
+    pub fn sets(&self) -> Arc<SetDirectory<PaywallSet>> {
+        self.sets.clone()
+    }
+
+    /// The paywall's wallet client; clones share its relay connection.
+    pub async fn nwc_client(&self, id: &str) -> Option<NwcClient> {
+        self.entries.read().await.get(id).map(|e| e.nwc_client.clone())
     }
 
     pub async fn get_set(&self, id: &str) -> Option<PaywallSet> {
@@ -258,9 +431,17 @@ impl PaywallManager {
         let (status_tx, status_rx) = tokio::sync::watch::channel(InvoiceStatus::Pending);
         let nwc = entry.nwc_client.clone();
         let ph = response.payment_hash.clone();
+        let expires_at = response.expires_at;
+        let notifier = self.notifier.clone();
+        let paywall_id = id.to_string();
         let listener_handle = tokio::spawn(async move {
-            if let Err(e) = nwc.subscribe_and_watch_invoice(ph.clone(), status_tx).await {
+            if let Err(e) = nwc.watch_invoice(ph.clone(), expires_at, status_tx).await {
                 tracing::warn!(payment_hash = %ph, error = %e, "NWC: watch task ended with error");
+                notifier.notify(Alert::new(
+                    AlertKind::PaywallWatcherFailed,
+                    format!("Paywall '{}' stopped watching invoice {}: {}", paywall_id, ph, e),
+                    serde_json::json!({ "paywall_id": paywall_id, "payment_hash": ph, "error": e.to_string() }),
+                ));
             }
         });
 
@@ -274,6 +455,7 @@ impl PaywallManager {
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            expires_at,
             status: status_rx,
             _listener_handle: listener_handle,
         };
@@ -287,6 +469,31 @@ impl PaywallManager {
         Ok(response)
     }
 
+    /// Replace the unpaid invoice `payment_hash` with a new one for the same
+    /// pubkey, typically once it expired. `None` if there is no such invoice
+    /// waiting (unknown, already paid, or long gone).
+    pub async fn reissue_invoice(
+        &self,
+        id: &str,
+        payment_hash: &str,
+    ) -> Result<Option<crate::nwc::InvoiceResponse>, anyhow::Error> {
+        let pubkey = {
+            let entries = self.entries.read().await;
+            let entry = entries
+                .get(id)
+                .ok_or_else(|| anyhow::anyhow!("Paywall '{}' not found", id))?;
+            let mut pending_map = entry.pending_payments.write().await;
+            match pending_map.get(payment_hash) {
+                Some(pending) if *pending.status.borrow() != InvoiceStatus::Paid => {}
+                _ => return Ok(None),
+            }
+            let old = pending_map.remove(payment_hash).unwrap();
+            old._listener_handle.abort();
+            old.pubkey
+        };
+        self.create_invoice(id, pubkey).await.map(Some)
+    }
+
     pub async fn check_payment(
         &self,
         id: &str,
@@ -307,6 +514,12 @@ impl PaywallManager {
         };
 
         if status == InvoiceStatus::Paid {
+            drop(entries);
+            let mut entries = self.entries.write().await;
+            let Some(entry) = entries.get_mut(id) else {
+                return Ok(status);
+            };
+
             // Remove from pending and add pubkey to the whitelist
             let mut pending_map = entry.pending_payments.write().await;
             if let Some(pending) = pending_map.remove(payment_hash) {
@@ -325,21 +538,273 @@ impl PaywallManager {
                     tracing::warn!("Failed to persist paywall '{}' after payment: {}", id, e);
                 }
 
+                entry.revenue.sats += pending.amount_sats;
+                entry.revenue.payments += 1;
+                let revenue_path = self.data_dir.join(format!("{}.revenue.bin", id));
+                if let Err(e) = save_revenue(&revenue_path, entry.revenue).await {
+                    tracing::warn!("Failed to persist paywall '{}' revenue: {}", id, e);
+                }
+
                 tracing::info!(
                     "Paywall '{}': pubkey {} granted access until {}",
                     id,
                     pending.pubkey.to_hex(),
                     expires_at
                 );
+                self.notifier.notify(Alert::new(
+                    AlertKind::PaymentCompleted,
+                    format!("Paywall '{}' received {} sats", id, pending.amount_sats),
+                    serde_json::json!({
+                        "paywall_id": id,
+                        "pubkey": pending.pubkey.to_hex(),
+                        "amount_sats": pending.amount_sats,
+                        "expires_at": expires_at,
+                    }),
+                ));
             }
         }
 
         Ok(status)
     }
 
+    /// Grant access for a zap receipt, if it is a valid zap of at least the
+    /// price to paywall `id` that hasn't been credited yet. Access runs for
+    /// the paywall's period from when the zap was paid. Returns whether it
+    /// granted access.
+    pub async fn record_zap_receipt(&self, id: &str, receipt: &Event) -> bool {
+        let mut entries = self.entries.write().await;
+        let Some(entry) = entries.get_mut(id) else {
+            return false;
+        };
+        let (recipient, signer) = match (&entry.zaps, entry.zap_signer.get()) {
+            (Some(zaps), Some(signer)) => (zaps.recipient, *signer),
+            _ => return false,
+        };
+        let zap = match crate::zap::verify_receipt(receipt, &recipient, &signer) {
+            Ok(zap) => zap,
+            Err(reason) => {
+                tracing::debug!(receipt = %receipt.id, "Paywall '{}': ignoring zap receipt: {}", id, reason);
+                return false;
+            }
+        };
+        if zap.amount_msats < entry.config.price_sats * 1000 {
+            tracing::debug!(receipt = %receipt.id, "Paywall '{}': zap below the price", id);
+            return false;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let expires_at = zap.paid_at + (entry.config.period_days as u64) * 24 * 3600;
+        if expires_at <= now {
+            return false;
+        }
+
+        // The same payment can arrive from several relays, in several
+        // receipts, or be replayed later by anyone
+        entry.credited_zaps.retain(|_, until| *until > now);
+        if entry.credited_zaps.contains_key(&zap.payment_hash) {
+            return false;
+        }
+        entry.credited_zaps.insert(zap.payment_hash, expires_at);
+        let zaps_path = self.data_dir.join(format!("{}.zaps.bin", id));
+        if let Err(e) = save_credited_zaps(&zaps_path, &entry.credited_zaps).await {
+            tracing::warn!("Failed to persist paywall '{}' credited zaps: {}", id, e);
+        }
+
+        entry.set.add(zap.payer, expires_at);
+        self.persist(id, &entry.set).await;
+
+        let amount_sats = zap.amount_msats / 1000;
+        entry.revenue.sats += amount_sats;
+        entry.revenue.payments += 1;
+        let revenue_path = self.data_dir.join(format!("{}.revenue.bin", id));
+        if let Err(e) = save_revenue(&revenue_path, entry.revenue).await {
+            tracing::warn!("Failed to persist paywall '{}' revenue: {}", id, e);
+        }
+
+        tracing::info!(
+            "Paywall '{}': pubkey {} zapped for access until {}",
+            id,
+            zap.payer.to_hex(),
+            expires_at
+        );
+        self.notifier.notify(Alert::new(
+            AlertKind::PaymentCompleted,
+            format!("Paywall '{}' received a {} sat zap", id, amount_sats),
+            serde_json::json!({
+                "paywall_id": id,
+                "pubkey": zap.payer.to_hex(),
+                "amount_sats": amount_sats,
+                "expires_at": expires_at,
+                "zap_receipt": receipt.id.to_hex(),
+            }),
+        ));
+        true
+    }
+
+    /// Take a Cashu token as payment for `pubkey`'s access. Its proofs are
+    /// swapped at the mint for fresh ones the paywall keeps, so the token
+    /// can't be spent again. Returns when the access expires.
+    pub async fn redeem_cashu(
+        &self,
+        id: &str,
+        pubkey: PublicKey,
+        token: &str,
+    ) -> Result<u64, String> {
+        let (mints, price_sats, period_days) = {
+            let entries = self.entries.read().await;
+            let entry = entries
+                .get(id)
+                .ok_or_else(|| format!("Paywall '{}' not found", id))?;
+            let cashu = entry
+                .config
+                .cashu
+                .as_ref()
+                .ok_or("This paywall doesn't accept Cashu")?;
+            (cashu.mints.clone(), entry.config.price_sats, entry.config.period_days)
+        };
+
+        let token = crate::cashu::decode(token).map_err(|e| format!("Invalid token: {}", e))?;
+        if !mints.iter().any(|m| crate::cashu::same_mint(m, &token.mint)) {
+            return Err(format!("Tokens from {} aren't accepted here", token.mint));
+        }
+        if token.unit != "sat" {
+            return Err("Only sat tokens are accepted".to_string());
+        }
+        let worth = token.amount().ok_or("Invalid token: token amount overflows")?;
+        if worth < price_sats {
+            return Err(format!(
+                "Token is worth {} sats but access costs {}",
+                worth, price_sats
+            ));
+        }
+        // The mint's fees come out of the token, so what counts is what is
+        // left after the swap
+        let received = crate::cashu::redeem(&token, price_sats)
+            .await
+            .map_err(|e| format!("Mint refused the token: {}", e))?;
+
+        // Keep the new proofs before anything else can go wrong
+        self.keep_ecash(id, &received).await;
+        // The swap only returns denominations of an amount that fit already
+        let amount_sats = received.amount().unwrap_or(0);
+        if amount_sats < price_sats {
+            return Err(format!(
+                "Mint returned {} sats but access costs {}",
+                amount_sats, price_sats
+            ));
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let expires_at = now + (period_days as u64) * 24 * 3600;
+        let mut entries = self.entries.write().await;
+        let entry = entries
+            .get_mut(id)
+            .ok_or_else(|| format!("Paywall '{}' not found", id))?;
+        entry.set.add(pubkey, expires_at);
+        self.persist(id, &entry.set).await;
+
+        entry.revenue.sats += amount_sats;
+        entry.revenue.payments += 1;
+        let revenue_path = self.data_dir.join(format!("{}.revenue.bin", id));
+        if let Err(e) = save_revenue(&revenue_path, entry.revenue).await {
+            tracing::warn!("Failed to persist paywall '{}' revenue: {}", id, e);
+        }
+
+        tracing::info!(
+            "Paywall '{}': pubkey {} paid with Cashu for access until {}",
+            id,
+            pubkey.to_hex(),
+            expires_at
+        );
+        self.notifier.notify(Alert::new(
+            AlertKind::PaymentCompleted,
+            format!("Paywall '{}' received {} sats in ecash", id, amount_sats),
+            serde_json::json!({
+                "paywall_id": id,
+                "pubkey": pubkey.to_hex(),
+                "amount_sats": amount_sats,
+                "expires_at": expires_at,
+                "mint": token.mint,
+            }),
+        ));
+        Ok(expires_at)
+    }
+
+    /// Add redeemed ecash to paywall `id`'s store. If that fails it goes to a
+    /// recovery file of its own instead; the proofs are bearer tokens, so
+    /// they are never logged.
+    async fn keep_ecash(&self, id: &str, token: &Token) {
+        let _guard = self.ecash_lock.lock().await;
+        let path = self.data_dir.join(format!("{}.ecash.jsonl", id));
+        let Err(e) = append_ecash(&path, token).await else {
+            return;
+        };
+        let recovery = self.data_dir.join(format!(
+            "{}.ecash-recovery-{}.jsonl",
+            id,
+            uuid::Uuid::new_v4()
+        ));
+        let sats = token.amount().map_or_else(|| "an overflowing amount of".to_string(), |a| a.to_string());
+        match append_ecash(&recovery, token).await {
+            Ok(()) => tracing::error!(
+                "Failed to persist paywall '{}' ecash ({}); {} sats saved to {}",
+                id,
+                e,
+                sats,
+                recovery.display()
+            ),
+            Err(e2) => tracing::error!(
+                "Failed to persist paywall '{}' ecash: {}; recovery file failed too: {}; {} sats lost",
+                id,
+                e,
+                e2,
+                sats
+            ),
+        }
+    }
+
+    /// The ecash paywall `id` has collected, as one token with its value in
+    /// sats, or `None` if there is none. The paywall stops holding it; a
+    /// copy is kept on disk in case the token goes astray.
+    pub async fn withdraw_ecash(&self, id: &str) -> Result<Option<(String, u64)>, String> {
+        let _guard = self.ecash_lock.lock().await;
+        let path = self.data_dir.join(format!("{}.ecash.jsonl", id));
+        let held = match load_ecash(&path).await {
+            Ok(held) if !held.is_empty() => held,
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read ecash: {}", e)),
+        };
+        let amount_sats = held
+            .iter()
+            .try_fold(0u64, |sum, token| sum.checked_add(token.amount()?))
+            .ok_or("Held ecash amount overflows")?;
+        let token = crate::cashu::encode(&held, "sat");
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let archive = self.data_dir.join(format!("{}.ecash.withdrawn-{}.jsonl", id, now));
+        tokio::fs::rename(&path, &archive)
+            .await
+            .map_err(|e| format!("Failed to archive withdrawn ecash: {}", e))?;
+        tracing::info!("Paywall '{}': withdrew {} sats of ecash", id, amount_sats);
+        Ok(Some((token, amount_sats)))
+    }
+
     pub async fn verify_nwc(&self, nwc_string: &str) -> Result<(), anyhow::Error> {
         let client = NwcClient::from_connection_string(nwc_string)?;
-        client.get_info().await
+        let result = client.get_info().await;
+        if let Err(e) = &result {
+            self.report_nwc_failure(None, e);
+        }
+        result
     }
 
     pub async fn add_paywall(
@@ -349,22 +814,33 @@ impl PaywallManager {
     ) -> Result<(), String> {
         let nwc_client = NwcClient::from_connection_string(&config.nwc_string)
             .map_err(|e| format!("Invalid NWC string: {}", e))?;
+        validate_reminder(&config)?;
+        let zaps = parse_zaps(&config)?;
+        validate_cashu(&config)?;
 
         let mut entries = self.entries.write().await;
         if entries.contains_key(&id) {
             return Err(format!("Paywall '{}' already exists", id));
         }
+        let set = PaywallSet::new();
+        self.sets.insert(&id, set.clone());
         entries.insert(
             id.clone(),
             PaywallEntry {
                 config,
-                set: PaywallSet::new(),
+                set,
                 nwc_client,
                 pending_payments: Arc::new(RwLock::new(HashMap::new())),
+                revenue: Revenue::default(),
                 handle: None,
+                zaps,
+                zap_signer: Arc::new(OnceLock::new()),
+                credited_zaps: HashMap::new(),
+                zap_handle: None,
             },
         );
         drop(entries);
+        self.generation.fetch_add(1, Ordering::Relaxed);
         self.start_background_task(&id).await;
         Ok(())
     }
@@ -376,20 +852,29 @@ impl PaywallManager {
     ) -> Result<(), String> {
         let nwc_client = NwcClient::from_connection_string(&config.nwc_string)
             .map_err(|e| format!("Invalid NWC string: {}", e))?;
+        validate_reminder(&config)?;
+        let zaps = parse_zaps(&config)?;
+        validate_cashu(&config)?;
 
         let mut entries = self.entries.write().await;
         let entry = entries
             .get_mut(id)
             .ok_or_else(|| format!("Paywall '{}' not found", id))?;
 
-        // Abort existing background task
+        // Abort existing background tasks
         if let Some(handle) = entry.handle.take() {
             handle.abort();
         }
+        if let Some(handle) = entry.zap_handle.take() {
+            handle.abort();
+        }
 
         entry.config = config;
         entry.nwc_client = nwc_client;
+        entry.zaps = zaps;
+        entry.zap_signer = Arc::new(OnceLock::new());
         drop(entries);
+        self.generation.fetch_add(1, Ordering::Relaxed);
         self.start_background_task(id).await;
         Ok(())
     }
@@ -399,18 +884,35 @@ impl PaywallManager {
         let mut entry = entries
             .remove(id)
             .ok_or_else(|| format!("Paywall '{}' not found", id))?;
+        self.sets.remove(id);
 
         if let Some(handle) = entry.handle.take() {
             handle.abort();
         }
+        if let Some(handle) = entry.zap_handle.take() {
+            handle.abort();
+        }
+        self.generation.fetch_add(1, Ordering::Relaxed);
 
-        // Remove disk file
+        // Remove disk files
         let disk_path = self.data_dir.join(format!("{}.bin", id));
         let _ = tokio::fs::remove_file(&disk_path).await;
+        let reminders_path = self.data_dir.join(format!("{}.reminders.bin", id));
+        let _ = tokio::fs::remove_file(&reminders_path).await;
+        let revenue_path = self.data_dir.join(format!("{}.revenue.bin", id));
+        let _ = tokio::fs::remove_file(&revenue_path).await;
+        let zaps_path = self.data_dir.join(format!("{}.zaps.bin", id));
+        let _ = tokio::fs::remove_file(&zaps_path).await;
 
         Ok(entry.config)
     }
 
+    /// Changes whenever a paywall's settings do, so callers can cache
+    /// anything derived from them.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
     pub async fn list_paywalls(&self) -> Vec<PaywallInfo> {
         let entries = self.entries.read().await;
         entries
@@ -420,6 +922,9 @@ impl PaywallManager {
                 price_sats: entry.config.price_sats,
                 period_days: entry.config.period_days,
                 whitelist_count: entry.set.len(),
+                revenue_sats: entry.revenue.sats,
+                payments: entry.revenue.payments,
+                zap_address: entry.zaps.as_ref().map(|z| z.lightning_address.clone()),
             })
             .collect()
     }
@@ -431,6 +936,9 @@ impl PaywallManager {
             price_sats: entry.config.price_sats,
             period_days: entry.config.period_days,
             whitelist_count: entry.set.len(),
+            revenue_sats: entry.revenue.sats,
+            payments: entry.revenue.payments,
+            zap_address: entry.zaps.as_ref().map(|z| z.lightning_address.clone()),
         })
     }
 
@@ -449,11 +957,152 @@ impl PaywallManager {
         })
     }
 
+    /// Manually grant a pubkey access until `expires_at`, overriding any
+    /// existing expiration, and persist the whitelist to disk.
+    pub async fn add_to_whitelist(
+        &self,
+        id: &str,
+        pubkey: PublicKey,
+        expires_at: u64,
+    ) -> Result<(), String> {
+        let entries = self.entries.read().await;
+        let entry = entries
+            .get(id)
+            .ok_or_else(|| format!("Paywall '{}' not found", id))?;
+
+        entry.set.set(pubkey, expires_at);
+        self.persist(id, &entry.set).await;
+
+        tracing::info!(
+            "Paywall '{}': pubkey {} manually granted access until {}",
+            id,
+            pubkey.to_hex(),
+            expires_at
+        );
+        Ok(())
+    }
+
+    /// Revoke a pubkey's access and persist the whitelist to disk.
+    /// Returns `Ok(false)` if the pubkey was not on the whitelist.
+    pub async fn remove_from_whitelist(
+        &self,
+        id: &str,
+        pubkey: &PublicKey,
+    ) -> Result<bool, String> {
+        let entries = self.entries.read().await;
+        let entry = entries
+            .get(id)
+            .ok_or_else(|| format!("Paywall '{}' not found", id))?;
+
+        if !entry.set.remove(pubkey) {
+            return Ok(false);
+        }
+        self.persist(id, &entry.set).await;
+
+        tracing::info!(
+            "Paywall '{}': pubkey {} access revoked",
+            id,
+            pubkey.to_hex()
+        );
+        Ok(true)
+    }
+
+    async fn persist(&self, id: &str, set: &PaywallSet) {
+        let disk_path = self.data_dir.join(format!("{}.bin", id));
+        if let Err(e) = save_to_disk(&disk_path, &set.list_entries()).await {
+            tracing::warn!("Failed to persist paywall '{}': {}", id, e);
+        }
+    }
+
     pub async fn get_config(&self, id: &str) -> Option<PaywallConfig> {
         self.entries.read().await.get(id).map(|e| e.config.clone())
     }
 }
 
+// ---------------------------------------------------------------------------
+// Expiry reminders — encrypted DMs sent shortly before access runs out
+// ---------------------------------------------------------------------------
+
+fn validate_reminder(config: &PaywallConfig) -> Result<(), String> {
+    let Some(reminder) = &config.reminder else {
+        return Ok(());
+    };
+    Keys::parse(&reminder.secret_key)
+        .map_err(|e| format!("invalid reminder secret key: {}", e))?;
+    if reminder.relays.is_empty() {
+        return Err("reminder requires at least one relay".to_string());
+    }
+    if reminder.days_before == 0 {
+        return Err("reminder days_before must be greater than 0".to_string());
+    }
+    Ok(())
+}
+
+fn validate_cashu(config: &PaywallConfig) -> Result<(), String> {
+    match &config.cashu {
+        Some(cashu) => crate::cashu::validate_mints(cashu),
+        None => Ok(()),
+    }
+}
+
+fn parse_zaps(config: &PaywallConfig) -> Result<Option<ZapTerms>, String> {
+    config.zaps.as_ref().map(ZapTerms::parse).transpose()
+}
+
+/// Entries expiring within `days_before` days that have not yet been reminded
+/// for their current expiration. A renewal changes the expiration, so the user
+/// is reminded again before the new one.
+fn due_reminders(
+    entries: &[(PublicKey, u64)],
+    reminded: &HashMap<PublicKey, u64>,
+    days_before: u32,
+    now: u64,
+) -> Vec<(PublicKey, u64)> {
+    let window = days_before as u64 * 86400;
+    entries
+        .iter()
+        .filter(|(pk, expires_at)| {
+            *expires_at > now
+                && *expires_at - now <= window
+                && reminded.get(pk) != Some(expires_at)
+        })
+        .copied()
+        .collect()
+}
+
+async fn send_reminders(
+    paywall_id: &str,
+    config: &PaywallReminderConfig,
+    keys: &Keys,
+    entries: &[(PublicKey, u64)],
+    reminded: &mut HashMap<PublicKey, u64>,
+) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    reminded.retain(|_, expires_at| *expires_at > now);
+
+    for (pk, expires_at) in due_reminders(entries, reminded, config.days_before, now) {
+        let days_left = (expires_at - now).div_ceil(86400);
+        let content = format!(
+            "Your paid access expires in {} day{}. Renew here: {}",
+            days_left,
+            if days_left == 1 { "" } else { "s" },
+            config.checkout_url
+        );
+        match crate::dm::send_dm(keys, pk, &content, &config.relays).await {
+            Ok(_) => {
+                reminded.insert(pk, expires_at);
+                tracing::info!(pubkey = %pk.to_hex(), "Paywall '{}': sent expiry reminder", paywall_id);
+            }
+            Err(e) => {
+                tracing::warn!(pubkey = %pk.to_hex(), "Paywall '{}': failed to send expiry reminder: {}", paywall_id, e);
+            }
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Disk persistence — binary format (32-byte pubkey + 8-byte LE u64 per entry)
 // ---------------------------------------------------------------------------
@@ -496,3 +1145,278 @@ async fn load_from_disk(path: &Path) -> Result<HashMap<PublicKey, u64>, anyhow::
     }
     Ok(map)
 }
+
+/// Revenue totals are stored as two little-endian u64s: sats, then payments.
+async fn save_revenue(path: &Path, revenue: Revenue) -> Result<(), anyhow::Error> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut buf = Vec::with_capacity(16);
+    buf.extend_from_slice(&revenue.sats.to_le_bytes());
+    buf.extend_from_slice(&revenue.payments.to_le_bytes());
+    tokio::fs::write(path, buf).await?;
+    Ok(())
+}
+
+async fn load_revenue(path: &Path) -> Result<Revenue, anyhow::Error> {
+    let data = tokio::fs::read(path).await?;
+    if data.len() != 16 {
+        return Err(anyhow::anyhow!("Invalid paywall revenue file size"));
+    }
+    Ok(Revenue {
+        sats: u64::from_le_bytes(data[..8].try_into().unwrap()),
+        payments: u64::from_le_bytes(data[8..].try_into().unwrap()),
+    })
+}
+
+/// Credited zaps: 32-byte payment hash + 8-byte LE expiry per entry.
+async fn save_credited_zaps(path: &Path, credited: &HashMap<[u8; 32], u64>) -> Result<(), anyhow::Error> {
+    let mut buf = Vec::with_capacity(credited.len() * 40);
+    for (hash, until) in credited {
+        buf.extend_from_slice(hash);
+        buf.extend_from_slice(&until.to_le_bytes());
+    }
+    tokio::fs::write(path, buf).await?;
+    Ok(())
+}
+
+async fn load_credited_zaps(path: &Path) -> Result<HashMap<[u8; 32], u64>, anyhow::Error> {
+    let data = tokio::fs::read(path).await?;
+    if data.len() % 40 != 0 {
+        return Err(anyhow::anyhow!("Invalid credited zaps file size"));
+    }
+    Ok(data
+        .chunks_exact(40)
+        .map(|chunk| {
+            let hash: [u8; 32] = chunk[..32].try_into().unwrap();
+            (hash, u64::from_le_bytes(chunk[32..].try_into().unwrap()))
+        })
+        .collect())
+}
+
+// ---------------------------------------------------------------------------
+// Ecash from Cashu payments — one JSON token per line, only ever appended to
+// ---------------------------------------------------------------------------
+
+async fn append_ecash(path: &Path, token: &Token) -> Result<(), anyhow::Error> {
+    use tokio::io::AsyncWriteExt;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut line = serde_json::to_vec(token)?;
+    line.push(b'\n');
+    let mut options = tokio::fs::OpenOptions::new();
+    options.create(true).append(true);
+    // Anyone who can read the proofs can spend them
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+    }
+    file.write_all(&line).await?;
+    file.sync_data().await?;
+    Ok(())
+}
+
+async fn load_ecash(path: &Path) -> Result<Vec<Token>, std::io::Error> {
+    let data = tokio::fs::read_to_string(path).await?;
+    Ok(data
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(token) => Some(token),
+            Err(e) => {
+                tracing::warn!("Skipping unreadable ecash entry in {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn revenue_round_trips_through_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pw.revenue.bin");
+        let revenue = Revenue { sats: 21_000, payments: 7 };
+        save_revenue(&path, revenue).await.unwrap();
+        assert_eq!(load_revenue(&path).await.unwrap(), revenue);
+    }
+
+    use super::*;
+    use nostr::Keys;
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn set_overrides_later_expiration() {
+        let set = PaywallSet::new();
+        let pk = Keys::generate().public_key();
+        set.add(pk, now() + 1000);
+        set.set(pk, now() + 10);
+        let entries = set.list_entries();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].1 <= now() + 10);
+    }
+
+    #[test]
+    fn remove_revokes_access() {
+        let set = PaywallSet::new();
+        let pk = Keys::generate().public_key();
+        set.add(pk, now() + 1000);
+        assert!(set.contains(&pk));
+        assert!(set.remove(&pk));
+        assert!(!set.contains(&pk));
+        assert!(!set.remove(&pk));
+    }
+
+    #[tokio::test]
+    async fn whitelist_mutations_persist_to_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = Arc::new(PaywallManager {
+            entries: RwLock::new(HashMap::new()),
+            sets: Arc::new(SetDirectory::default()),
+            data_dir: dir.path().to_path_buf(),
+            generation: AtomicU64::new(0),
+            notifier: Arc::new(Notifier::default()),
+            ecash_lock: tokio::sync::Mutex::new(()),
+        });
+        let config = PaywallConfig {
+            nwc_string: "nostr+walletconnect://b889ff5b1513b641e2a139f661a661364979c5beee91842f8f0ef42ab558e9d4?relay=wss%3A%2F%2Frelay.example.com&secret=71a8c14c1407c113601079c4302dab36460f0ccd0ad506f1f2dc73b5100e4f3c".to_string(),
+            price_sats: 1000,
+            period_days: 30,
+            reminder: None,
+            zaps: None,
+            cashu: None,
+        };
+        manager.add_paywall("test".into(), config).await.unwrap();
+
+        let pk = Keys::generate().public_key();
+        let expires_at = now() + 3600;
+        manager.add_to_whitelist("test", pk, expires_at).await.unwrap();
+
+        let loaded = load_from_disk(&dir.path().join("test.bin")).await.unwrap();
+        assert_eq!(loaded.get(&pk), Some(&expires_at));
+
+        assert!(manager.remove_from_whitelist("test", &pk).await.unwrap());
+        let loaded = load_from_disk(&dir.path().join("test.bin")).await.unwrap();
+        assert!(loaded.is_empty());
+
+        assert!(manager.add_to_whitelist("missing", pk, expires_at).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn zap_receipts_are_credited_once_even_across_restarts() {
+        use crate::zap::test_support::{invoice, receipt, zap_request};
+
+        let dir = tempfile::tempdir().unwrap();
+        let (payer, provider, recipient) = (Keys::generate(), Keys::generate(), Keys::generate());
+        let config = PaywallConfig {
+            nwc_string: "nostr+walletconnect://b889ff5b1513b641e2a139f661a661364979c5beee91842f8f0ef42ab558e9d4?relay=wss%3A%2F%2Frelay.example.com&secret=71a8c14c1407c113601079c4302dab36460f0ccd0ad506f1f2dc73b5100e4f3c".to_string(),
+            price_sats: 21,
+            period_days: 30,
+            reminder: None,
+            zaps: Some(crate::config::PaywallZapConfig {
+                lightning_address: "relay@example.com".to_string(),
+                pubkey: recipient.public_key().to_hex(),
+                receipt_pubkey: Some(provider.public_key().to_hex()),
+                relays: vec![],
+            }),
+            cashu: None,
+        };
+        let request = zap_request(&payer, &recipient.public_key(), Some(21_000));
+        let bolt11 = invoice("210n", &request);
+        let first = receipt(&provider, &recipient.public_key(), &bolt11, &request);
+        // The provider can issue another receipt for the same payment
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let second = receipt(&provider, &recipient.public_key(), &bolt11, &request);
+
+        let start = || async {
+            let manager = PaywallManager::new(dir.path().to_path_buf(), HashMap::new(), Arc::new(Notifier::default())).unwrap();
+            manager.add_paywall("test".into(), config.clone()).await.unwrap();
+            // The configured receipt signer is taken up in the background
+            while manager.entries.read().await["test"].zap_signer.get().is_none() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            manager
+        };
+
+        let manager = start().await;
+        assert!(manager.record_zap_receipt("test", &first).await);
+        assert!(!manager.record_zap_receipt("test", &first).await);
+        assert!(!manager.record_zap_receipt("test", &second).await);
+        assert!(manager.get_set("test").await.unwrap().contains(&payer.public_key()));
+        assert_eq!(manager.get_paywall_info("test").await.unwrap().payments, 1);
+        drop(manager);
+
+        let manager = start().await;
+        assert!(!manager.record_zap_receipt("test", &first).await);
+        assert_eq!(manager.get_paywall_info("test").await.unwrap().payments, 1);
+    }
+
+    #[tokio::test]
+    async fn withdrawing_ecash_hands_over_everything_held_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = PaywallManager::new(dir.path().to_path_buf(), HashMap::new(), Arc::new(Notifier::default())).unwrap();
+        assert_eq!(manager.withdraw_ecash("test").await.unwrap(), None);
+
+        let path = dir.path().join("test.ecash.jsonl");
+        let proof = |amount| crate::cashu::Proof {
+            amount,
+            id: "00ad268c4d1f5826".to_string(),
+            secret: format!("secret-{}", amount),
+            c: "02".to_string(),
+        };
+        let tokens = [
+            Token { mint: "https://a.example.com".into(), unit: "sat".into(), proofs: vec![proof(8), proof(2)] },
+            Token { mint: "https://b.example.com".into(), unit: "sat".into(), proofs: vec![proof(1)] },
+        ];
+        for token in &tokens {
+            append_ecash(&path, token).await.unwrap();
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let (token, amount) = manager.withdraw_ecash("test").await.unwrap().unwrap();
+        assert_eq!(amount, 11);
+        assert_eq!(token, crate::cashu::encode(&tokens, "sat"));
+        assert_eq!(manager.withdraw_ecash("test").await.unwrap(), None);
+    }
+
+    #[test]
+    fn due_reminders_respects_window_and_history() {
+        let now = now();
+        let soon = Keys::generate().public_key();
+        let later = Keys::generate().public_key();
+        let expired = Keys::generate().public_key();
+        let entries = vec![
+            (soon, now + 86400),
+            (later, now + 10 * 86400),
+            (expired, now - 1),
+        ];
+
+        let mut reminded = HashMap::new();
+        assert_eq!(due_reminders(&entries, &reminded, 3, now), vec![(soon, now + 86400)]);
+
+        // Already reminded for this expiration
+        reminded.insert(soon, now + 86400);
+        assert!(due_reminders(&entries, &reminded, 3, now).is_empty());
+
+        // Renewed, then close to expiring again
+        let renewed = vec![(soon, now + 2 * 86400)];
+        assert_eq!(due_reminders(&renewed, &reminded, 3, now).len(), 1);
+    }
+}
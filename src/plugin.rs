@@ -0,0 +1,221 @@
+//! External write-policy plugins speaking strfry's plugin protocol: the
+//! command reads one JSON request per event on stdin and answers each with a
+//! JSON line `{"id": ..., "action": "accept" | "reject" | "shadowReject",
+//! "msg": ...}` on stdout. The process is started on first use and restarted
+//! after it exits or stops answering in time.
+
+use nostr::{Event, JsonUtil};
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::process::Stdio;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+
+use crate::config::{PluginConfig, PluginFailureMode};
+
+/// Where an event came from, reported to the plugin as `sourceType` and
+/// `sourceInfo`.
+#[derive(Debug, Clone)]
+pub enum WriteSource {
+    /// Published by a client connected from this address.
+    Ip(IpAddr),
+    /// Pulled from an upstream relay (`sync_from`).
+    Stream(String),
+}
+
+impl WriteSource {
+    fn describe(&self) -> (&'static str, String) {
+        match self {
+            Self::Ip(IpAddr::V4(ip)) => ("IP4", ip.to_string()),
+            Self::Ip(IpAddr::V6(ip)) => ("IP6", ip.to_string()),
+            Self::Stream(url) => ("Stream", url.clone()),
+        }
+    }
+}
+
+/// The plugin's verdict on one event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PluginDecision {
+    Accept,
+    /// Rejected, with the message to show the client.
+    Reject(String),
+    /// Reported to the client as accepted, but not stored.
+    ShadowReject,
+}
+
+#[derive(Deserialize)]
+struct PluginReply {
+    id: String,
+    action: String,
+    #[serde(default)]
+    msg: String,
+}
+
+struct Process {
+    // Held so the process is killed when dropped
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+pub struct WritePlugin {
+    config: PluginConfig,
+    process: Mutex<Option<Process>>,
+}
+
+impl WritePlugin {
+    pub fn new(config: PluginConfig) -> Self {
+        Self {
+            config,
+            process: Mutex::new(None),
+        }
+    }
+
+    /// Ask the plugin about `event`. Failures (spawn errors, crashes,
+    /// malformed replies, timeouts) are logged and resolved by the
+    /// configured failure mode.
+    pub async fn decide(&self, event: &Event, source: &WriteSource) -> PluginDecision {
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let mut process = self.process.lock().await;
+        let result = match tokio::time::timeout(timeout, self.exchange(&mut process, event, source)).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("no reply within {} ms", self.config.timeout_ms)),
+        };
+        match result {
+            Ok(decision) => decision,
+            Err(e) => {
+                tracing::warn!(command = %self.config.command, "Write policy plugin failed: {}", e);
+                // Start afresh on the next event
+                *process = None;
+                match self.config.on_error {
+                    PluginFailureMode::Accept => PluginDecision::Accept,
+                    PluginFailureMode::Reject => PluginDecision::Reject("error: policy plugin unavailable".into()),
+                }
+            }
+        }
+    }
+
+    async fn exchange(
+        &self,
+        process: &mut Option<Process>,
+        event: &Event,
+        source: &WriteSource,
+    ) -> Result<PluginDecision, String> {
+        if process.is_none() {
+            *process = Some(self.spawn()?);
+        }
+        let proc = process.as_mut().expect("process was just started");
+
+        let (source_type, source_info) = source.describe();
+        let received_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let request = serde_json::json!({
+            "type": "new",
+            "event": serde_json::from_str::<serde_json::Value>(&event.as_json()).map_err(|e| e.to_string())?,
+            "receivedAt": received_at,
+            "sourceType": source_type,
+            "sourceInfo": source_info,
+        });
+        let mut line = request.to_string();
+        line.push('\n');
+        proc.stdin.write_all(line.as_bytes()).await.map_err(|e| e.to_string())?;
+        proc.stdin.flush().await.map_err(|e| e.to_string())?;
+
+        let id = event.id.to_hex();
+        loop {
+            let reply = proc
+                .stdout
+                .next_line()
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or("plugin exited")?;
+            let reply: PluginReply =
+                serde_json::from_str(&reply).map_err(|e| format!("malformed reply '{}': {}", reply, e))?;
+            if reply.id != id {
+                // A late answer to an event that already timed out
+                continue;
+            }
+            return match reply.action.as_str() {
+                "accept" => Ok(PluginDecision::Accept),
+                "reject" => Ok(PluginDecision::Reject(reply.msg)),
+                "shadowReject" => Ok(PluginDecision::ShadowReject),
+                other => Err(format!("unknown action '{}'", other)),
+            };
+        }
+    }
+
+    fn spawn(&self) -> Result<Process, String> {
+        let mut child = Command::new(&self.config.command)
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("failed to start: {}", e))?;
+        let stdin = child.stdin.take().ok_or("no stdin")?;
+        let stdout = child.stdout.take().ok_or("no stdout")?;
+        Ok(Process {
+            _child: child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys};
+
+    fn plugin(script: &str, on_error: PluginFailureMode) -> WritePlugin {
+        WritePlugin::new(PluginConfig {
+            command: "sh".into(),
+            args: vec!["-c".into(), script.into()],
+            timeout_ms: 500,
+            on_error,
+        })
+    }
+
+    fn note(content: &str) -> Event {
+        EventBuilder::text_note(content, []).to_event(&Keys::generate()).unwrap()
+    }
+
+    fn source() -> WriteSource {
+        WriteSource::Ip("127.0.0.1".parse().unwrap())
+    }
+
+    // Answers each request with the given action, echoing the event id.
+    fn responder(action: &str) -> String {
+        format!(
+            r#"while read -r line; do id=$(printf '%s' "$line" | sed 's/.*"id":"\([0-9a-f]*\)".*/\1/'); printf '{{"id":"%s","action":"{}","msg":"nope"}}\n' "$id"; done"#,
+            action
+        )
+    }
+
+    #[tokio::test]
+    async fn relays_plugin_actions() {
+        let accept = plugin(&responder("accept"), PluginFailureMode::Reject);
+        assert_eq!(accept.decide(&note("a"), &source()).await, PluginDecision::Accept);
+        assert_eq!(accept.decide(&note("b"), &source()).await, PluginDecision::Accept);
+
+        let reject = plugin(&responder("reject"), PluginFailureMode::Accept);
+        assert_eq!(reject.decide(&note("a"), &source()).await, PluginDecision::Reject("nope".into()));
+
+        let shadow = plugin(&responder("shadowReject"), PluginFailureMode::Accept);
+        assert_eq!(shadow.decide(&note("a"), &source()).await, PluginDecision::ShadowReject);
+    }
+
+    #[tokio::test]
+    async fn failures_follow_the_failure_mode() {
+        let silent = plugin("sleep 5", PluginFailureMode::Accept);
+        assert_eq!(silent.decide(&note("a"), &source()).await, PluginDecision::Accept);
+
+        let missing = WritePlugin::new(PluginConfig {
+            command: "/nonexistent/plugin".into(),
+            ..plugin("", PluginFailureMode::Reject).config
+        });
+        assert!(matches!(missing.decide(&note("a"), &source()).await, PluginDecision::Reject(_)));
+    }
+}
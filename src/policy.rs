@@ -1,10 +1,16 @@
 use nostr::{Event, Filter, Kind, PublicKey};
-use std::collections::HashSet;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, RwLock};
 
-use crate::config::{Nip11Config, PolicyConfig};
-use crate::paywall::PaywallSet;
-use crate::wot::WotSet;
+use crate::config::{EventPolicy, Nip11Config, PolicyConfig, RelayConfig};
+use crate::invite::{InviteManager, InviteSet};
+use crate::paywall::{PaywallManager, PaywallSet};
+use crate::plugin::{PluginDecision, WritePlugin, WriteSource};
+use crate::stats::RelayStats;
+use crate::wot::{WotManager, WotSet};
 
 /// Result of a policy check.
 #[derive(Debug, Clone)]
@@ -15,6 +21,9 @@ pub enum PolicyResult {
     Deny(String),
     /// The client must complete NIP-42 AUTH before retrying.
     AuthRequired,
+    /// The event is acknowledged as accepted but silently dropped (a
+    /// shadow-banned author, or a write plugin's `shadowReject`).
+    ShadowReject,
 }
 
 impl PolicyResult {
@@ -23,6 +32,105 @@ impl PolicyResult {
     }
 }
 
+/// Shadow-banned pubkeys of a relay: their events are acknowledged as
+/// accepted but never stored or broadcast, so a spammer doesn't notice and
+/// move to a fresh key. Shared with the admin API, which edits it live.
+#[derive(Clone, Default)]
+pub struct ShadowBans {
+    inner: Arc<RwLock<HashSet<PublicKey>>>,
+}
+
+impl ShadowBans {
+    pub fn contains(&self, pk: &PublicKey) -> bool {
+        self.inner.read().unwrap().contains(pk)
+    }
+
+    /// Replace the list with the pubkeys (npub or hex) in `keys`.
+    pub fn replace(&self, keys: &[String]) {
+        *self.inner.write().unwrap() = parse_pubkeys(keys);
+    }
+}
+
+/// The WoT or paywall sets of a manager by id, for policies to look up at
+/// check time. The epoch moves whenever a set is added or removed, so a
+/// policy only looks again after a change.
+pub struct SetDirectory<S> {
+    sets: RwLock<HashMap<String, S>>,
+    epoch: AtomicU64,
+}
+
+impl<S> Default for SetDirectory<S> {
+    fn default() -> Self {
+        Self {
+            sets: RwLock::new(HashMap::new()),
+            epoch: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<S: Clone> SetDirectory<S> {
+    pub fn insert(&self, id: &str, set: S) {
+        self.sets.write().unwrap().insert(id.to_string(), set);
+        self.epoch.fetch_add(1, Relaxed);
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.sets.write().unwrap().remove(id);
+        self.epoch.fetch_add(1, Relaxed);
+    }
+
+    pub fn get(&self, id: &str) -> Option<S> {
+        self.sets.read().unwrap().get(id).cloned()
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Relaxed)
+    }
+}
+
+/// A WoT or paywall set a policy checks against.
+enum SetRef<S> {
+    /// Handed to [`PolicyEngine::new`] directly.
+    Fixed(S),
+    /// Named by the relay config and resolved through the manager's
+    /// directory, so a set created or replaced later is picked up.
+    ById {
+        id: String,
+        directory: Arc<SetDirectory<S>>,
+        /// Directory epoch and what `id` resolved to at that epoch.
+        resolved: RwLock<(u64, Option<S>)>,
+    },
+}
+
+impl<S: Clone> SetRef<S> {
+    fn by_id(id: &str, directory: Arc<SetDirectory<S>>) -> Self {
+        Self::ById {
+            id: id.to_string(),
+            directory,
+            resolved: RwLock::new((u64::MAX, None)),
+        }
+    }
+
+    /// The set as of now; `None` if the named set doesn't exist, which the
+    /// checks treat as an empty set.
+    fn get(&self) -> Option<S> {
+        let (id, directory, resolved) = match self {
+            Self::Fixed(set) => return Some(set.clone()),
+            Self::ById { id, directory, resolved } => (id, directory, resolved),
+        };
+        let epoch = directory.epoch();
+        {
+            let cached = resolved.read().unwrap();
+            if cached.0 == epoch {
+                return cached.1.clone();
+            }
+        }
+        let set = directory.get(id);
+        *resolved.write().unwrap() = (epoch, set.clone());
+        set
+    }
+}
+
 /// A rule-based policy engine constructed from a [`PolicyConfig`].
 ///
 /// All access-control decisions flow through this struct — there are no
@@ -34,13 +142,19 @@ pub struct PolicyEngine {
     write_allowed: Option<HashSet<PublicKey>>,
     write_blocked: Option<HashSet<PublicKey>>,
     write_tagged: Option<HashSet<PublicKey>>,
+    write_invited: Option<InviteSet>,
+    shadow_banned: ShadowBans,
     read_allowed: Option<HashSet<PublicKey>>,
     allowed_kinds: Option<HashSet<Kind>>,
     blocked_kinds: Option<HashSet<Kind>>,
-    write_wot: Option<WotSet>,
-    read_wot: Option<WotSet>,
-    write_paywall: Option<PaywallSet>,
-    read_paywall: Option<PaywallSet>,
+    protected_kinds: Option<HashSet<Kind>>,
+    write_wot: Option<SetRef<WotSet>>,
+    read_wot: Option<SetRef<WotSet>>,
+    write_paywall: Option<SetRef<PaywallSet>>,
+    read_paywall: Option<SetRef<PaywallSet>>,
+    content_filter: ContentFilter,
+    plugin: Option<WritePlugin>,
+    stats: Option<Arc<RelayStats>>,
 }
 
 impl PolicyEngine {
@@ -88,20 +202,86 @@ impl PolicyEngine {
             .as_ref()
             .map(|kinds| kinds.iter().map(|&k| Kind::from(k as u16)).collect());
 
+        let protected_kinds = config
+            .read
+            .protected_kinds
+            .as_ref()
+            .map(|kinds| kinds.iter().map(|&k| Kind::from(k as u16)).collect());
+
+        let shadow_banned = ShadowBans::default();
+        shadow_banned.replace(config.write.shadow_banned_pubkeys.as_deref().unwrap_or_default());
+
+        let content_filter = ContentFilter::new(&config.events);
+        let plugin = config.write.plugin.clone().map(WritePlugin::new);
+
         Self {
             config,
             nip11,
             write_allowed,
             write_blocked,
             write_tagged,
+            write_invited: None,
+            shadow_banned,
             read_allowed,
             allowed_kinds,
             blocked_kinds,
-            write_wot,
-            read_wot,
-            write_paywall,
-            read_paywall,
+            protected_kinds,
+            write_wot: write_wot.map(SetRef::Fixed),
+            read_wot: read_wot.map(SetRef::Fixed),
+            write_paywall: write_paywall.map(SetRef::Fixed),
+            read_paywall: read_paywall.map(SetRef::Fixed),
+            content_filter,
+            plugin,
+            stats: None,
+        }
+    }
+
+    /// The policy of relay `relay_id` as configured, wired to the WoT and
+    /// paywall sets it references (looked up by id on each check) and, with
+    /// invites or access requests on, to the relay's members.
+    pub async fn for_relay(
+        relay_id: &str,
+        relay: &RelayConfig,
+        wots: &WotManager,
+        paywalls: &PaywallManager,
+        invites: &InviteManager,
+        stats: Arc<RelayStats>,
+    ) -> Self {
+        let policy = &relay.policy;
+        let mut engine = Self::new(policy.clone(), relay.nip11.clone(), None, None, None, None).with_stats(stats);
+        engine.write_wot = policy.write.wot.as_deref().map(|id| SetRef::by_id(id, wots.sets()));
+        engine.read_wot = policy.read.wot.as_deref().map(|id| SetRef::by_id(id, wots.sets()));
+        engine.write_paywall = policy.write.paywall.as_deref().map(|id| SetRef::by_id(id, paywalls.sets()));
+        engine.read_paywall = policy.read.paywall.as_deref().map(|id| SetRef::by_id(id, paywalls.sets()));
+        if policy.write.invites || policy.write.access_requests {
+            engine = engine.with_invites(invites.enable(relay_id).await);
         }
+        engine
+    }
+
+    /// Count content-filter rejections in `stats.spam_rejected`.
+    pub fn with_stats(mut self, stats: Arc<RelayStats>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Let the relay's members (redeemed invites, approved requests) write.
+    pub fn with_invites(mut self, invited: InviteSet) -> Self {
+        self.write_invited = Some(invited);
+        self
+    }
+
+    /// Use `bans` as the shadow-ban list, refilled from this policy's config,
+    /// so a rebuilt policy keeps the handle the admin API edits.
+    pub fn with_shadow_bans(mut self, bans: ShadowBans) -> Self {
+        bans.replace(self.config.write.shadow_banned_pubkeys.as_deref().unwrap_or_default());
+        self.shadow_banned = bans;
+        self
+    }
+
+    /// The relay's shadow-ban list, for the admin API to edit.
+    pub fn shadow_bans(&self) -> &ShadowBans {
+        &self.shadow_banned
     }
 
     /// Check whether an event may be written to this relay.
@@ -110,38 +290,18 @@ impl PolicyEngine {
     /// connection, or `None` if the client has not authenticated.
     pub fn can_write(&self, event: &Event, authed_pubkey: Option<&PublicKey>) -> PolicyResult {
         // Auth gate
-        if self.config.write.require_auth {
-            if authed_pubkey.is_none() {
-                return PolicyResult::AuthRequired;
-            }
-        }
-
-        // Pubkey allow-list (checked against event author)
-        if let Some(ref allowed) = self.write_allowed {
-            if !allowed.contains(&event.pubkey) {
-                return PolicyResult::Deny("pubkey not on write allow-list".into());
-            }
+        if self.config.write.require_auth && authed_pubkey.is_none() {
+            return PolicyResult::AuthRequired;
         }
 
-        // Web of Trust check (checked against event author, no auth needed)
-        if let Some(ref wot) = self.write_wot {
-            if !wot.contains(&event.pubkey) {
-                return PolicyResult::Deny("pubkey not in web of trust".into());
-            }
+        // Shadow-banned authors are told the event was accepted
+        if self.shadow_banned.contains(&event.pubkey) {
+            return PolicyResult::ShadowReject;
         }
 
-        // Paywall check (checked against event author, no auth needed)
-        if let Some(ref paywall) = self.write_paywall {
-            if !paywall.contains(&event.pubkey) {
-                return PolicyResult::Deny("payment required for write access".into());
-            }
-        }
-
-        // Pubkey block-list
-        if let Some(ref blocked) = self.write_blocked {
-            if blocked.contains(&event.pubkey) {
-                return PolicyResult::Deny("pubkey is blocked".into());
-            }
+        let author = self.can_write_as(&event.pubkey);
+        if !author.is_allowed() {
+            return author;
         }
 
         // Tagged pubkeys — event must contain a `p` tag referencing one of these
@@ -176,8 +336,15 @@ impl PolicyEngine {
             }
         }
 
+        // Per-kind overrides of the limits below
+        let kind = event.kind.as_u16() as u64;
+        let kind_limits = self.config.events.kind_limits.iter().find(|l| l.kinds.contains(&kind));
+        let max_content_length = kind_limit(kind_limits.and_then(|l| l.max_content_length), self.config.events.max_content_length);
+        let min_pow = kind_limit(kind_limits.and_then(|l| l.min_pow), self.config.events.min_pow);
+        let max_tags = kind_limit(kind_limits.and_then(|l| l.max_tags), self.nip11.max_event_tags);
+
         // Content length
-        if let Some(max_len) = self.config.events.max_content_length {
+        if let Some(max_len) = max_content_length {
             if event.content.len() > max_len {
                 return PolicyResult::Deny(format!(
                     "content too long ({} > {})",
@@ -187,8 +354,16 @@ impl PolicyEngine {
             }
         }
 
+        // Content filters: blocked words and patterns, spam heuristics
+        if let Some(reason) = self.content_filter.check(&event.content) {
+            if let Some(ref stats) = self.stats {
+                stats.spam_rejected.fetch_add(1, Relaxed);
+            }
+            return PolicyResult::Deny(reason);
+        }
+
         // PoW — NIP-13: count leading zero bits of the event ID
-        if let Some(min_pow) = self.config.events.min_pow {
+        if let Some(min_pow) = min_pow {
             let pow = leading_zero_bits(event.id.as_bytes());
             if pow < min_pow {
                 return PolicyResult::Deny(format!("insufficient PoW ({} < {})", pow, min_pow));
@@ -196,7 +371,7 @@ impl PolicyEngine {
         }
 
         // NIP-11: max event tags
-        if let Some(max_tags) = self.nip11.max_event_tags {
+        if let Some(max_tags) = max_tags {
             if event.tags.len() as u64 > max_tags {
                 return PolicyResult::Deny(format!(
                     "too many tags ({} > {})",
@@ -231,11 +406,71 @@ impl PolicyEngine {
         PolicyResult::Allow
     }
 
+    /// The identity checks of [`can_write`](Self::can_write): write
+    /// allow-list, web of trust, paywall and block-list. Paired blossom
+    /// servers apply them to uploaders.
+    pub fn can_write_as(&self, pubkey: &PublicKey) -> PolicyResult {
+        // Pubkey allow-list, extended by invited and approved members
+        let invited = self.write_invited.as_ref().map(|set| set.contains(pubkey));
+        match (&self.write_allowed, invited) {
+            (_, Some(true)) => {}
+            (Some(allowed), _) if allowed.contains(pubkey) => {}
+            (Some(_), _) => return PolicyResult::Deny("pubkey not on write allow-list".into()),
+            (None, Some(false)) => return PolicyResult::Deny("only members may write to this relay".into()),
+            (None, None) => {}
+        }
+
+        // Web of Trust check (no auth needed)
+        if let Some(ref wot) = self.write_wot {
+            if !wot.get().is_some_and(|set| set.contains(pubkey)) {
+                return PolicyResult::Deny("pubkey not in web of trust".into());
+            }
+        }
+
+        // Paywall check (no auth needed)
+        if let Some(ref paywall) = self.write_paywall {
+            if !paywall.get().is_some_and(|set| set.contains(pubkey)) {
+                return PolicyResult::Deny("payment required for write access".into());
+            }
+        }
+
+        // Pubkey block-list
+        if let Some(ref blocked) = self.write_blocked {
+            if blocked.contains(pubkey) {
+                return PolicyResult::Deny("pubkey is blocked".into());
+            }
+        }
+
+        PolicyResult::Allow
+    }
+
+    /// [`can_write`](Self::can_write), followed by the write plugin if one
+    /// is configured and the built-in rules allowed the event.
+    pub async fn check_write(&self, event: &Event, authed_pubkey: Option<&PublicKey>, source: WriteSource) -> PolicyResult {
+        let result = self.can_write(event, authed_pubkey);
+        let (PolicyResult::Allow, Some(plugin)) = (&result, &self.plugin) else {
+            return result;
+        };
+        match plugin.decide(event, &source).await {
+            PluginDecision::Accept => PolicyResult::Allow,
+            PluginDecision::Reject(msg) if msg.is_empty() => PolicyResult::Deny("rejected by policy plugin".into()),
+            // The relay adds its own "blocked: " prefix
+            PluginDecision::Reject(msg) => PolicyResult::Deny(msg.trim_start_matches("blocked: ").to_string()),
+            PluginDecision::ShadowReject => PolicyResult::ShadowReject,
+        }
+    }
+
     /// Check whether a REQ query is allowed on this relay.
-    pub fn can_read(&self, _filter: &Filter, authed_pubkey: Option<&PublicKey>) -> PolicyResult {
+    pub fn can_read(&self, filter: &Filter, authed_pubkey: Option<&PublicKey>) -> PolicyResult {
         // Auth gate
-        if self.config.read.require_auth {
-            if authed_pubkey.is_none() {
+        if self.config.read.require_auth && authed_pubkey.is_none() {
+            return PolicyResult::AuthRequired;
+        }
+
+        // A query for nothing but protected kinds can never return anything
+        // to an anonymous client, so ask it to authenticate instead.
+        if let (Some(protected), Some(kinds), None) = (&self.protected_kinds, &filter.kinds, authed_pubkey) {
+            if !kinds.is_empty() && kinds.iter().all(|k| protected.contains(k)) {
                 return PolicyResult::AuthRequired;
             }
         }
@@ -251,7 +486,7 @@ impl PolicyEngine {
         // Web of Trust check (requires auth to identify reader)
         if let Some(ref wot) = self.read_wot {
             match authed_pubkey {
-                Some(pk) if wot.contains(pk) => {}
+                Some(pk) if wot.get().is_some_and(|set| set.contains(pk)) => {}
                 Some(_) => {
                     return PolicyResult::Deny("pubkey not in web of trust".into())
                 }
@@ -262,7 +497,7 @@ impl PolicyEngine {
         // Paywall check (requires auth to identify reader)
         if let Some(ref paywall) = self.read_paywall {
             match authed_pubkey {
-                Some(pk) if paywall.contains(pk) => {}
+                Some(pk) if paywall.get().is_some_and(|set| set.contains(pk)) => {}
                 Some(_) => {
                     return PolicyResult::Deny("payment required for read access".into())
                 }
@@ -272,6 +507,22 @@ impl PolicyEngine {
 
         PolicyResult::Allow
     }
+
+    /// Check whether a stored event may be sent to a client. Events of a
+    /// protected kind are only delivered to their author or to a pubkey
+    /// `p`-tagged in the event.
+    pub fn can_deliver(&self, event: &Event, authed_pubkey: Option<&PublicKey>) -> bool {
+        let Some(ref protected) = self.protected_kinds else {
+            return true;
+        };
+        if !protected.contains(&event.kind) {
+            return true;
+        }
+        let Some(pk) = authed_pubkey else {
+            return false;
+        };
+        event.pubkey == *pk || event.public_keys().any(|p| p == pk)
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -289,8 +540,178 @@ fn parse_pubkeys(keys: &[String]) -> HashSet<PublicKey> {
         .collect()
 }
 
+/// A limit from a [`KindLimits`](crate::config::KindLimits) entry if it sets
+/// one (0 meaning none), otherwise the relay-wide `default`.
+fn kind_limit<T: Copy + Default + PartialEq>(kind: Option<T>, default: Option<T>) -> Option<T> {
+    match kind {
+        Some(limit) if limit == T::default() => None,
+        Some(limit) => Some(limit),
+        None => default,
+    }
+}
+
+/// Compiled form of the content rules in an [`EventPolicy`].
+#[derive(Default)]
+struct ContentFilter {
+    /// Lowercased blocked words.
+    words: Vec<String>,
+    patterns: Vec<Regex>,
+    max_emoji_ratio: Option<f32>,
+    allowed_scripts: Option<HashSet<Script>>,
+}
+
+/// Content shorter than this (in non-whitespace characters) is not judged by
+/// the emoji and script heuristics.
+const MIN_HEURISTIC_CHARS: usize = 10;
+
+impl ContentFilter {
+    /// Build the filter, skipping invalid patterns and unknown scripts
+    /// (config validation reports them).
+    fn new(events: &EventPolicy) -> Self {
+        let words = events
+            .blocked_words
+            .iter()
+            .flatten()
+            .filter(|w| !w.is_empty())
+            .map(|w| w.to_lowercase())
+            .collect();
+        let patterns = events
+            .blocked_content_patterns
+            .iter()
+            .flatten()
+            .filter_map(|p| match Regex::new(p) {
+                Ok(re) => Some(re),
+                Err(e) => {
+                    tracing::warn!("Ignoring invalid content pattern '{}': {}", p, e);
+                    None
+                }
+            })
+            .collect();
+        let allowed_scripts = events
+            .allowed_scripts
+            .as_ref()
+            .map(|scripts| scripts.iter().filter_map(|s| Script::parse(s)).collect());
+        Self {
+            words,
+            patterns,
+            max_emoji_ratio: events.max_emoji_ratio,
+            allowed_scripts,
+        }
+    }
+
+    /// The reason `content` is rejected, if it is.
+    fn check(&self, content: &str) -> Option<String> {
+        if !self.words.is_empty() {
+            let lower = content.to_lowercase();
+            if self.words.iter().any(|w| lower.contains(w.as_str())) {
+                return Some("content contains a blocked word".into());
+            }
+        }
+        if self.patterns.iter().any(|re| re.is_match(content)) {
+            return Some("content matches a blocked pattern".into());
+        }
+        if self.max_emoji_ratio.is_none() && self.allowed_scripts.is_none() {
+            return None;
+        }
+
+        let mut chars = 0usize;
+        let mut emoji = 0usize;
+        let mut letters = 0usize;
+        let mut allowed_letters = 0usize;
+        for c in content.chars().filter(|c| !c.is_whitespace()) {
+            chars += 1;
+            if is_emoji(c) {
+                emoji += 1;
+            } else if c.is_alphabetic() {
+                letters += 1;
+                let allowed = self.allowed_scripts.as_ref().is_none_or(|allowed| {
+                    // Letters outside the known scripts don't count against the content
+                    Script::of(c).is_none_or(|s| allowed.contains(&s))
+                });
+                if allowed {
+                    allowed_letters += 1;
+                }
+            }
+        }
+        if chars < MIN_HEURISTIC_CHARS {
+            return None;
+        }
+        if let Some(max) = self.max_emoji_ratio {
+            if emoji as f32 / chars as f32 > max {
+                return Some("content is mostly emoji".into());
+            }
+        }
+        if self.allowed_scripts.is_some() && allowed_letters * 2 < letters {
+            return Some("content language not accepted".into());
+        }
+        None
+    }
+}
+
+/// Writing systems recognised by the `allowed_scripts` heuristic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Script {
+    Latin,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Devanagari,
+    Thai,
+    Han,
+    Hiragana,
+    Katakana,
+    Hangul,
+}
+
+impl Script {
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "latin" => Self::Latin,
+            "cyrillic" => Self::Cyrillic,
+            "greek" => Self::Greek,
+            "arabic" => Self::Arabic,
+            "hebrew" => Self::Hebrew,
+            "devanagari" => Self::Devanagari,
+            "thai" => Self::Thai,
+            "han" => Self::Han,
+            "hiragana" => Self::Hiragana,
+            "katakana" => Self::Katakana,
+            "hangul" => Self::Hangul,
+            _ => return None,
+        })
+    }
+
+    /// The script a letter belongs to, by Unicode block.
+    fn of(c: char) -> Option<Self> {
+        Some(match c as u32 {
+            0x0041..=0x024F | 0x1E00..=0x1EFF => Self::Latin,
+            0x0370..=0x03FF | 0x1F00..=0x1FFF => Self::Greek,
+            0x0400..=0x052F => Self::Cyrillic,
+            0x0590..=0x05FF => Self::Hebrew,
+            0x0600..=0x06FF | 0x0750..=0x077F => Self::Arabic,
+            0x0900..=0x097F => Self::Devanagari,
+            0x0E00..=0x0E7F => Self::Thai,
+            0x3040..=0x309F => Self::Hiragana,
+            0x30A0..=0x30FF => Self::Katakana,
+            0x1100..=0x11FF | 0xAC00..=0xD7AF => Self::Hangul,
+            0x4E00..=0x9FFF | 0x3400..=0x4DBF => Self::Han,
+            _ => return None,
+        })
+    }
+}
+
+/// Whether `c` is an emoji or pictograph (including joiners and selectors
+/// used to build emoji sequences).
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0xFE0F | 0x200D
+    )
+}
+
 /// Count leading zero bits of a byte slice (NIP-13 PoW).
-fn leading_zero_bits(bytes: &[u8]) -> u8 {
+pub(crate) fn leading_zero_bits(bytes: &[u8]) -> u8 {
     let mut count: u8 = 0;
     for &b in bytes {
         if b == 0 {
@@ -306,7 +727,7 @@ fn leading_zero_bits(bytes: &[u8]) -> u8 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{EventPolicy, Nip11Config, PolicyConfig, ReadPolicy, WritePolicy};
+    use crate::config::{EventPolicy, KindLimits, Nip11Config, PolicyConfig, ReadPolicy, WritePolicy};
     use nostr::nips::nip19::ToBech32;
     use nostr::{EventBuilder, Keys, Kind};
 
@@ -484,6 +905,38 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn invited_pubkeys_may_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let invites = crate::invite::InviteManager::new(dir.path().to_path_buf());
+        let set = invites.enable("private").await;
+        let (listed, invited, other) = (Keys::generate(), Keys::generate(), Keys::generate());
+        let code = invites.create("private", 1, None).await.unwrap().code;
+        invites.redeem("private", &code, &invited.public_key()).await.unwrap();
+
+        // Invite-only
+        let engine = PolicyEngine::new(PolicyConfig::default(), default_nip11(), None, None, None, None)
+            .with_invites(set.clone());
+        assert!(engine.can_write(&make_event(&invited, "hi"), None).is_allowed());
+        assert!(matches!(
+            engine.can_write(&make_event(&other, "hi"), None),
+            PolicyResult::Deny(ref s) if s.contains("members")
+        ));
+
+        // Alongside an allow-list
+        let policy = PolicyConfig {
+            write: WritePolicy {
+                allowed_pubkeys: Some(vec![hex_pubkey(&listed)]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let engine = PolicyEngine::new(policy, default_nip11(), None, None, None, None).with_invites(set);
+        assert!(engine.can_write(&make_event(&listed, "hi"), None).is_allowed());
+        assert!(engine.can_write(&make_event(&invited, "hi"), None).is_allowed());
+        assert!(!engine.can_write(&make_event(&other, "hi"), None).is_allowed());
+    }
+
     #[test]
     fn write_block_list_rejects_listed() {
         let keys = Keys::generate();
@@ -603,6 +1056,42 @@ mod tests {
         assert!(engine.can_write(&event, None).is_allowed());
     }
 
+    #[test]
+    fn kind_limits_replace_relay_wide_limits() {
+        let keys = Keys::generate();
+        let long = "x".repeat(50);
+        let policy = PolicyConfig {
+            events: EventPolicy {
+                max_content_length: Some(10),
+                kind_limits: vec![
+                    KindLimits {
+                        kinds: vec![30023],
+                        max_content_length: Some(0),
+                        ..Default::default()
+                    },
+                    KindLimits {
+                        kinds: vec![7],
+                        max_content_length: Some(5),
+                        min_pow: Some(128),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let engine = PolicyEngine::new(policy, default_nip11(), None, None, None, None);
+        assert!(engine.can_write(&make_event_kind(&keys, 30023, &long), None).is_allowed());
+        assert!(matches!(
+            engine.can_write(&make_event(&keys, &long), None),
+            PolicyResult::Deny(ref s) if s.contains("content too long")
+        ));
+        assert!(matches!(
+            engine.can_write(&make_event_kind(&keys, 7, "+"), None),
+            PolicyResult::Deny(ref s) if s.contains("PoW")
+        ));
+    }
+
     #[test]
     fn max_content_length_at_limit() {
         let keys = Keys::generate();
@@ -638,6 +1127,53 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn blocked_words_and_patterns_count_as_spam() {
+        let keys = Keys::generate();
+        let policy = PolicyConfig {
+            events: EventPolicy {
+                blocked_words: Some(vec!["Airdrop".into()]),
+                blocked_content_patterns: Some(vec![r"https?://spam\.example/\S+".into()]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let stats = Arc::new(RelayStats::new());
+        let engine = PolicyEngine::new(policy, default_nip11(), None, None, None, None).with_stats(stats.clone());
+
+        assert!(engine.can_write(&make_event(&keys, "good morning"), None).is_allowed());
+        assert!(matches!(
+            engine.can_write(&make_event(&keys, "free AIRDROP now"), None),
+            PolicyResult::Deny(ref s) if s.contains("blocked word")
+        ));
+        assert!(matches!(
+            engine.can_write(&make_event(&keys, "see https://spam.example/x"), None),
+            PolicyResult::Deny(ref s) if s.contains("blocked pattern")
+        ));
+        assert_eq!(stats.spam_rejected.load(Relaxed), 2);
+    }
+
+    #[test]
+    fn emoji_and_script_heuristics() {
+        let keys = Keys::generate();
+        let policy = PolicyConfig {
+            events: EventPolicy {
+                max_emoji_ratio: Some(0.5),
+                allowed_scripts: Some(vec!["latin".into()]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let engine = PolicyEngine::new(policy, default_nip11(), None, None, None, None);
+
+        // Short content such as reactions is exempt
+        assert!(engine.can_write(&make_event(&keys, "🚀🚀🚀"), None).is_allowed());
+        assert!(engine.can_write(&make_event(&keys, "launch day 🚀🚀"), None).is_allowed());
+        assert!(!engine.can_write(&make_event(&keys, "🚀🚀🚀🚀🚀🚀🚀🚀 go go"), None).is_allowed());
+        assert!(engine.can_write(&make_event(&keys, "hello Москва, nice city"), None).is_allowed());
+        assert!(!engine.can_write(&make_event(&keys, "привет всем, hi"), None).is_allowed());
+    }
+
     #[test]
     fn min_pow_rejects_insufficient() {
         let keys = Keys::generate();
@@ -868,6 +1404,7 @@ mod tests {
                 allowed_pubkeys: Some(vec![hex_pubkey(&keys)]),
                 wot: None,
                 paywall: None,
+                protected_kinds: None,
             },
             ..Default::default()
         };
@@ -925,6 +1462,33 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn paywall_by_id_follows_the_directory() {
+        use crate::paywall::PaywallSet;
+        let keys = Keys::generate();
+        let event = make_event(&keys, "hello");
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let directory = Arc::new(SetDirectory::default());
+        let mut engine = PolicyEngine::new(open_policy(), default_nip11(), None, None, None, None);
+        engine.write_paywall = Some(SetRef::by_id("members", directory.clone()));
+
+        // Not created yet: nobody is whitelisted
+        assert!(!engine.can_write(&event, None).is_allowed());
+
+        let paywall = PaywallSet::new_for_test();
+        paywall.add(keys.public_key(), now + 3600);
+        directory.insert("members", paywall);
+        assert!(engine.can_write(&event, None).is_allowed());
+
+        // Recreated under the same id: the new, empty set applies
+        directory.remove("members");
+        directory.insert("members", PaywallSet::new_for_test());
+        assert!(!engine.can_write(&event, None).is_allowed());
+    }
+
     #[test]
     fn paywall_read_allows_whitelisted() {
         use crate::paywall::PaywallSet;
@@ -979,6 +1543,7 @@ mod tests {
                 allowed_pubkeys: Some(vec![hex_pubkey(&allowed_keys)]),
                 wot: None,
                 paywall: None,
+                protected_kinds: None,
             },
             ..Default::default()
         };
@@ -1090,4 +1655,36 @@ mod tests {
         let engine = PolicyEngine::new(open_policy(), nip11, None, None, None, None);
         assert!(engine.can_write(&event, None).is_allowed());
     }
+
+    #[test]
+    fn protected_kinds_delivered_only_to_participants() {
+        let author = Keys::generate();
+        let recipient = Keys::generate();
+        let outsider = Keys::generate();
+        let policy = PolicyConfig {
+            read: ReadPolicy {
+                protected_kinds: Some(vec![4]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let engine = PolicyEngine::new(policy, default_nip11(), None, None, None, None);
+
+        let dm = EventBuilder::new(Kind::EncryptedDirectMessage, "x", [nostr::Tag::public_key(recipient.public_key())])
+            .to_event(&author)
+            .unwrap();
+        assert!(!engine.can_deliver(&dm, None));
+        assert!(!engine.can_deliver(&dm, Some(&outsider.public_key())));
+        assert!(engine.can_deliver(&dm, Some(&author.public_key())));
+        assert!(engine.can_deliver(&dm, Some(&recipient.public_key())));
+
+        // Unprotected kinds are unaffected
+        let note = make_event(&author, "hello");
+        assert!(engine.can_deliver(&note, None));
+
+        // Filters asking only for protected kinds require auth
+        let dm_filter = Filter::new().kind(Kind::EncryptedDirectMessage);
+        assert!(matches!(engine.can_read(&dm_filter, None), PolicyResult::AuthRequired));
+        assert!(engine.can_read(&Filter::new(), None).is_allowed());
+    }
 }
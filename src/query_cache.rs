@@ -0,0 +1,287 @@
+//! Per-relay cache of REQ results. Popular clients send the same filters
+//! over and over (profiles and contact lists of the same pubkeys), so the
+//! result of a whole REQ is kept under its canonicalized filters until a
+//! newly stored event could change it.
+
+use crate::storage::filter::ReqFilter;
+use crate::storage::MultiQueryResult;
+use nostr::{Event, Kind};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+struct Entry {
+    filters: Vec<ReqFilter>,
+    result: Arc<MultiQueryResult>,
+    /// Tick of the last lookup, for least-recently-used eviction.
+    used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    /// Last-use tick → key, oldest first.
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    /// Bumped on every invalidation, so a query that raced a write isn't
+    /// cached with the write missing.
+    generation: u64,
+}
+
+impl Inner {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.order.remove(&entry.used);
+            entry.used = tick;
+            self.order.insert(tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.used);
+        }
+    }
+}
+
+pub struct QueryCache {
+    inner: Mutex<Inner>,
+    capacity: usize,
+}
+
+impl QueryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// The cache key for a REQ. Results don't depend on filter order or
+    /// repeats, so neither does the key.
+    pub fn key(filters: &[ReqFilter], max_total: usize) -> String {
+        let mut parts: Vec<String> = filters.iter().map(canonical).collect();
+        parts.sort_unstable();
+        parts.dedup();
+        format!("{}:[{}]", max_total, parts.join(","))
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<MultiQueryResult>> {
+        let mut inner = self.inner.lock().unwrap();
+        let result = inner.entries.get(key)?.result.clone();
+        inner.touch(key);
+        Some(result)
+    }
+
+    /// Read before running a query, and passed back to `insert`.
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+
+    /// Cache a complete result, unless an event was stored since
+    /// `generation` was read. Results cut short by the deadline aren't kept.
+    pub fn insert(&self, key: String, filters: Vec<ReqFilter>, result: MultiQueryResult, generation: u64) {
+        if result.timed_out {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation != generation {
+            return;
+        }
+        inner.remove(&key);
+        while inner.entries.len() >= self.capacity {
+            let Some((_, oldest)) = inner.order.pop_first() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+        }
+        inner.tick += 1;
+        let used = inner.tick;
+        inner.order.insert(used, key.clone());
+        inner.entries.insert(
+            key,
+            Entry {
+                filters,
+                result: Arc::new(result),
+                used,
+            },
+        );
+    }
+
+    /// Drop every result a newly stored event could change: those whose
+    /// filters match it, and those holding an older version it replaces or
+    /// an event it deletes.
+    pub fn invalidate(&self, event: &Event) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        let stale: Vec<String> = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.filters.iter().any(|f| f.match_event(event))
+                    || entry
+                        .result
+                        .events
+                        .iter()
+                        .any(|cached| replaces(event, cached) || deletes(event, cached))
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in stale {
+            inner.remove(&key);
+        }
+    }
+
+    /// Drop everything, after the store changed underneath the relay.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.entries.clear();
+        inner.order.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A filter's JSON with object keys and set members sorted; nostr keeps
+/// them in hash sets, so serializing equal filters can order them apart.
+fn canonical(filter: &ReqFilter) -> String {
+    let mut fields: BTreeMap<String, serde_json::Value> = match serde_json::to_value(&filter.filter) {
+        Ok(serde_json::Value::Object(fields)) => fields.into_iter().collect(),
+        _ => return String::new(),
+    };
+    for (name, values) in &filter.long_tags {
+        fields.insert(format!("#{}", name), values.iter().cloned().collect());
+    }
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                serde_json::Value::Array(values) => {
+                    let mut values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                    values.sort_unstable();
+                    format!("[{}]", values.join(","))
+                }
+                value => value.to_string(),
+            };
+            format!("{:?}:{}", name, value)
+        })
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+/// Whether storing `new` removes `old` as an older version of it.
+fn replaces(new: &Event, old: &Event) -> bool {
+    if new.pubkey != old.pubkey || new.kind != old.kind {
+        return false;
+    }
+    if new.kind.is_replaceable() {
+        return true;
+    }
+    new.kind.is_parameterized_replaceable() && new.identifier() == old.identifier()
+}
+
+/// Whether `new` is a NIP-09 deletion of `old` by its author.
+fn deletes(new: &Event, old: &Event) -> bool {
+    if new.kind != Kind::EventDeletion || new.pubkey != old.pubkey {
+        return false;
+    }
+    let id = old.id.to_hex();
+    let address = (old.kind.is_replaceable() || old.kind.is_parameterized_replaceable())
+        .then(|| format!("{}:{}:{}", old.kind.as_u16(), old.pubkey.to_hex(), old.identifier().unwrap_or_default()));
+    new.tags.iter().any(|tag| {
+        let tag = tag.as_vec();
+        tag.len() >= 2
+            && match tag[0].as_str() {
+                "e" => tag[1] == id,
+                "a" => address.as_deref() == Some(tag[1].as_str()),
+                _ => false,
+            }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Filter, Keys, Kind, Metadata, Timestamp};
+
+    fn cached(events: Vec<Event>) -> MultiQueryResult {
+        MultiQueryResult {
+            events,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn hits_ignore_filter_order_and_matching_writes_invalidate() {
+        let cache = QueryCache::new(8);
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let profiles = ReqFilter::from(Filter::new().author(alice.public_key()).kind(Kind::Metadata));
+        let notes = ReqFilter::from(Filter::new().author(bob.public_key()).kind(Kind::TextNote));
+        let profile = EventBuilder::metadata(&Metadata::new().name("alice"))
+            .custom_created_at(Timestamp::from(1000))
+            .to_event(&alice)
+            .unwrap();
+
+        let key = QueryCache::key(&[profiles.clone(), notes.clone()], 500);
+        assert_eq!(key, QueryCache::key(&[notes.clone(), profiles.clone(), notes.clone()], 500));
+        assert_ne!(key, QueryCache::key(&[profiles.clone(), notes.clone()], 10));
+        let authors = |keys: [&Keys; 2]| ReqFilter::from(Filter::new().authors(keys.map(|k| k.public_key())));
+        assert_eq!(QueryCache::key(&[authors([&alice, &bob])], 1), QueryCache::key(&[authors([&bob, &alice])], 1));
+
+        let generation = cache.generation();
+        cache.insert(key.clone(), vec![profiles.clone(), notes.clone()], cached(vec![profile.clone()]), generation);
+        assert_eq!(cache.get(&key).unwrap().events[0].id, profile.id);
+
+        // Unrelated writes leave it alone
+        cache.invalidate(&EventBuilder::text_note("hi", []).to_event(&alice).unwrap());
+        assert!(cache.get(&key).is_some());
+
+        // So does a deletion of something in another filter's results
+        let note = EventBuilder::text_note("hi", []).to_event(&bob).unwrap();
+        let notes_only = QueryCache::key(std::slice::from_ref(&notes), 500);
+        cache.insert(notes_only.clone(), vec![notes.clone()], cached(vec![note.clone()]), cache.generation());
+        cache.invalidate(&EventBuilder::delete([note.id]).to_event(&alice).unwrap());
+        assert!(cache.get(&notes_only).is_some());
+        cache.invalidate(&EventBuilder::delete([note.id]).to_event(&bob).unwrap());
+        assert!(cache.get(&notes_only).is_none());
+
+        // A newer profile replaces the cached one
+        let newer = EventBuilder::metadata(&Metadata::new().name("alice2")).to_event(&alice).unwrap();
+        cache.invalidate(&newer);
+        assert!(cache.get(&key).is_none());
+
+        // A result read before a write isn't cached after it
+        let by_id = ReqFilter::from(Filter::new().id(profile.id));
+        let generation = cache.generation();
+        cache.invalidate(&newer);
+        let key = QueryCache::key(std::slice::from_ref(&by_id), 500);
+        cache.insert(key, vec![by_id], cached(vec![profile]), generation);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = QueryCache::new(2);
+        let filter = |kind: u16| ReqFilter::from(Filter::new().kind(Kind::from(kind)));
+        for kind in [1, 2] {
+            let generation = cache.generation();
+            cache.insert(QueryCache::key(&[filter(kind)], 1), vec![filter(kind)], cached(vec![]), generation);
+        }
+        assert!(cache.get(&QueryCache::key(&[filter(1)], 1)).is_some());
+        let generation = cache.generation();
+        cache.insert(QueryCache::key(&[filter(3)], 1), vec![filter(3)], cached(vec![]), generation);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&QueryCache::key(&[filter(1)], 1)).is_some());
+        assert!(cache.get(&QueryCache::key(&[filter(2)], 1)).is_none());
+    }
+}
@@ -1,19 +1,73 @@
+use axum::http::HeaderMap;
 use dashmap::DashMap;
+use ipnet::IpNet;
+use nostr::PublicKey;
 use std::collections::VecDeque;
 use std::net::IpAddr;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
+/// Peers whose `X-Forwarded-For` header is believed, as IPs or CIDR ranges.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    nets: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Parse entries like "10.0.0.0/8" or "127.0.0.1".
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        let nets = entries
+            .iter()
+            .map(|entry| parse_net(entry).ok_or_else(|| format!("'{}' is not an IP address or CIDR range", entry)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { nets })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+
+    /// The client's address: the socket peer, unless the peer is a trusted
+    /// proxy, in which case the rightmost `X-Forwarded-For` hop that isn't
+    /// itself a trusted proxy. Hops left of that are client-controlled.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.contains(peer) {
+            return peer;
+        }
+        let mut client = peer;
+        for value in headers.get_all("x-forwarded-for").iter().rev() {
+            let Ok(value) = value.to_str() else { return client };
+            for hop in value.rsplit(',') {
+                match hop.trim().parse::<IpAddr>() {
+                    Ok(ip) if self.contains(ip) => client = ip,
+                    Ok(ip) => return ip,
+                    Err(_) => return client,
+                }
+            }
+        }
+        client
+    }
+}
+
+pub fn parse_net(entry: &str) -> Option<IpNet> {
+    entry
+        .parse::<IpNet>()
+        .ok()
+        .or_else(|| entry.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
 /// Per-IP connection and rate tracking shared across all WebSocket connections.
 pub struct IpTracker {
     map: DashMap<IpAddr, IpState>,
+    proxies: TrustedProxies,
 }
 
 struct IpState {
     connections: AtomicU32,
     write_timestamps: Mutex<VecDeque<Instant>>,
     read_timestamps: Mutex<VecDeque<Instant>>,
+    request_timestamps: Mutex<VecDeque<Instant>>,
     last_active: Mutex<Instant>,
 }
 
@@ -23,6 +77,7 @@ impl IpState {
             connections: AtomicU32::new(0),
             write_timestamps: Mutex::new(VecDeque::new()),
             read_timestamps: Mutex::new(VecDeque::new()),
+            request_timestamps: Mutex::new(VecDeque::new()),
             last_active: Mutex::new(Instant::now()),
         }
     }
@@ -34,11 +89,52 @@ impl IpState {
     }
 }
 
+impl Default for IpTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl IpTracker {
     pub fn new() -> Self {
+        Self::with_trusted_proxies(TrustedProxies::default())
+    }
+
+    pub fn with_trusted_proxies(proxies: TrustedProxies) -> Self {
         Self {
             map: DashMap::new(),
+            proxies,
+        }
+    }
+
+    /// Take one of `ip`'s connection slots, released when the returned slot
+    /// is dropped. `None` if the IP is already at `max_connections`.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr, max_connections: Option<u32>) -> Option<ConnectionSlot> {
+        if !self.try_connect(ip, max_connections) {
+            return None;
         }
+        Some(ConnectionSlot {
+            tracker: Arc::clone(self),
+            ip,
+        })
+    }
+
+    /// Open connections per IP, busiest first.
+    pub fn connections(&self) -> Vec<(IpAddr, u32)> {
+        let mut result: Vec<(IpAddr, u32)> = self
+            .map
+            .iter()
+            .map(|entry| (*entry.key(), entry.connections.load(Ordering::Relaxed)))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        result.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        result
+    }
+
+    /// Address to track a connection from `peer` under; see
+    /// [`TrustedProxies::client_ip`].
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        self.proxies.client_ip(peer, headers)
     }
 
     /// Try to register a new connection for this IP. Returns `true` if allowed.
@@ -100,10 +196,52 @@ impl IpTracker {
         check_rate(&entry.read_timestamps, limit)
     }
 
+    /// Sliding-window rate check for HTTP requests. Unlike the read and write
+    /// checks this doesn't need an open connection.
+    pub fn check_request_rate(&self, ip: IpAddr, limit: Option<u32>) -> bool {
+        let limit = match limit {
+            Some(l) => l,
+            None => return true,
+        };
+        let entry = self.map.entry(ip).or_insert_with(IpState::new);
+        entry.touch();
+        check_rate(&entry.request_timestamps, limit)
+    }
+
+    /// Number of IPs currently tracked.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Call `cleanup_idle(max_idle)` every `interval` for as long as the
+    /// tracker is alive elsewhere.
+    pub fn start_cleanup(self: &Arc<Self>, interval: Duration, max_idle: Duration) {
+        let tracker = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval.max(Duration::from_secs(1)));
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(tracker) = tracker.upgrade() else { break };
+                tracker.cleanup_idle(max_idle);
+            }
+        });
+    }
+
     /// Remove entries with 0 connections that have been inactive for over 10
     /// minutes.
     pub fn cleanup(&self) {
-        let cutoff = Instant::now() - Duration::from_secs(600);
+        self.cleanup_idle(Duration::from_secs(600));
+    }
+
+    /// Remove entries with 0 connections that have been inactive for longer
+    /// than `max_idle`.
+    pub fn cleanup_idle(&self, max_idle: Duration) {
+        let cutoff = Instant::now().checked_sub(max_idle).unwrap_or_else(Instant::now);
         self.map.retain(|_ip, state| {
             if state.connections.load(Ordering::Relaxed) > 0 {
                 return true;
@@ -117,20 +255,23 @@ impl IpTracker {
     }
 }
 
+/// One open connection counted against an IP in an [`IpTracker`].
+pub struct ConnectionSlot {
+    tracker: Arc<IpTracker>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.tracker.disconnect(self.ip);
+    }
+}
+
 /// Sliding window check: prune timestamps older than 60s, then check count < limit.
 /// Records a new timestamp if allowed.
 fn check_rate(timestamps: &Mutex<VecDeque<Instant>>, limit: u32) -> bool {
     let mut ts = timestamps.lock().unwrap();
-    let window = Instant::now() - Duration::from_secs(60);
-
-    // Remove expired entries
-    while let Some(&front) = ts.front() {
-        if front < window {
-            ts.pop_front();
-        } else {
-            break;
-        }
-    }
+    prune(&mut ts, MINUTE);
 
     if ts.len() as u32 >= limit {
         return false;
@@ -140,6 +281,93 @@ fn check_rate(timestamps: &Mutex<VecDeque<Instant>>, limit: u32) -> bool {
     true
 }
 
+const MINUTE: Duration = Duration::from_secs(60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Drop timestamps older than `window`.
+fn prune(ts: &mut VecDeque<Instant>, window: Duration) {
+    let Some(cutoff) = Instant::now().checked_sub(window) else { return };
+    while ts.front().is_some_and(|&front| front < cutoff) {
+        ts.pop_front();
+    }
+}
+
+/// Per-author write tracking, so one pubkey can't spread its writes over
+/// many IPs and users sharing an IP don't share a budget.
+#[derive(Default)]
+pub struct PubkeyTracker {
+    map: DashMap<PublicKey, PubkeyState>,
+}
+
+#[derive(Default)]
+struct PubkeyState {
+    minute: VecDeque<Instant>,
+    day: VecDeque<Instant>,
+}
+
+impl PubkeyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sliding-window check of an event by `pubkey` against both limits.
+    /// Returns `true` (and records the write) if neither is exceeded.
+    pub fn check_write_rate(&self, pubkey: &PublicKey, per_minute: Option<u32>, per_day: Option<u32>) -> bool {
+        if per_minute.is_none() && per_day.is_none() {
+            return true;
+        }
+        let mut state = self.map.entry(*pubkey).or_default();
+        prune(&mut state.minute, MINUTE);
+        prune(&mut state.day, DAY);
+        if per_minute.is_some_and(|limit| state.minute.len() as u32 >= limit)
+            || per_day.is_some_and(|limit| state.day.len() as u32 >= limit)
+        {
+            return false;
+        }
+        let now = Instant::now();
+        if per_minute.is_some() {
+            state.minute.push_back(now);
+        }
+        if per_day.is_some() {
+            state.day.push_back(now);
+        }
+        true
+    }
+
+    /// Number of pubkeys currently tracked.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Forget pubkeys with no writes inside either window.
+    pub fn cleanup(&self) {
+        self.map.retain(|_pk, state| {
+            prune(&mut state.minute, MINUTE);
+            prune(&mut state.day, DAY);
+            !state.minute.is_empty() || !state.day.is_empty()
+        });
+    }
+
+    /// Call `cleanup()` every `interval` for as long as the tracker is alive
+    /// elsewhere.
+    pub fn start_cleanup(self: &Arc<Self>, interval: Duration) {
+        let tracker = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval.max(Duration::from_secs(1)));
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(tracker) = tracker.upgrade() else { break };
+                tracker.cleanup();
+            }
+        });
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -153,6 +381,49 @@ mod tests {
         IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))
     }
 
+    fn xff(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn forwarded_for_only_honored_from_trusted_proxies() {
+        let proxies = TrustedProxies::parse(&["127.0.0.1".to_string(), "10.0.0.0/8".to_string()]).unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let spoofed: IpAddr = "198.51.100.1".parse().unwrap();
+
+        // Untrusted peers can't pick their address
+        assert_eq!(proxies.client_ip(other_ip(), &xff("203.0.113.7")), other_ip());
+        assert_eq!(proxies.client_ip(localhost(), &HeaderMap::new()), localhost());
+        // The rightmost untrusted hop wins over anything the client prepended
+        assert_eq!(proxies.client_ip(localhost(), &xff("198.51.100.1, 203.0.113.7, 10.1.2.3")), client);
+        assert_ne!(proxies.client_ip(localhost(), &xff("198.51.100.1, 203.0.113.7")), spoofed);
+        assert_eq!(proxies.client_ip(localhost(), &xff("garbage, 10.1.2.3")), "10.1.2.3".parse::<IpAddr>().unwrap());
+
+        assert!(TrustedProxies::parse(&["not-an-ip".to_string()]).is_err());
+    }
+
+    #[test]
+    fn slots_release_on_drop() {
+        let tracker = Arc::new(IpTracker::new());
+        let slot = tracker.acquire(localhost(), Some(1)).unwrap();
+        assert!(tracker.acquire(localhost(), Some(1)).is_none());
+        assert_eq!(tracker.connections(), vec![(localhost(), 1)]);
+        drop(slot);
+        assert!(tracker.connections().is_empty());
+        assert!(tracker.acquire(localhost(), Some(1)).is_some());
+    }
+
+    #[test]
+    fn request_rate_counts_without_connections() {
+        let tracker = IpTracker::new();
+        assert!(tracker.check_request_rate(other_ip(), Some(2)));
+        assert!(tracker.check_request_rate(other_ip(), Some(2)));
+        assert!(!tracker.check_request_rate(other_ip(), Some(2)));
+        assert!(tracker.check_request_rate(localhost(), Some(2)));
+    }
+
     #[test]
     fn connection_limit_allows_under_max() {
         let tracker = IpTracker::new();
@@ -259,4 +530,44 @@ mod tests {
         tracker.cleanup();
         assert!(tracker.map.contains_key(&localhost()));
     }
+
+    #[test]
+    fn cleanup_idle_uses_given_threshold() {
+        let tracker = IpTracker::new();
+        tracker.check_request_rate(localhost(), Some(10));
+        {
+            let entry = tracker.map.get(&localhost()).unwrap();
+            let mut t = entry.last_active.lock().unwrap();
+            *t = Instant::now() - Duration::from_secs(120);
+        }
+        tracker.cleanup_idle(Duration::from_secs(300));
+        assert_eq!(tracker.len(), 1);
+        tracker.cleanup_idle(Duration::from_secs(60));
+        assert!(tracker.is_empty());
+    }
+
+    #[test]
+    fn pubkey_limits_apply_per_author() {
+        let tracker = PubkeyTracker::new();
+        let alice = nostr::Keys::generate().public_key();
+        let bob = nostr::Keys::generate().public_key();
+
+        assert!(tracker.check_write_rate(&alice, Some(2), None));
+        assert!(tracker.check_write_rate(&alice, Some(2), None));
+        assert!(!tracker.check_write_rate(&alice, Some(2), None));
+        assert!(tracker.check_write_rate(&bob, Some(2), None));
+
+        // The daily cap counts writes the minute limit let through
+        let carol = nostr::Keys::generate().public_key();
+        assert!(tracker.check_write_rate(&carol, Some(10), Some(1)));
+        assert!(!tracker.check_write_rate(&carol, Some(10), Some(1)));
+
+        // Nothing is tracked without limits
+        let dave = nostr::Keys::generate().public_key();
+        assert!(tracker.check_write_rate(&dave, None, None));
+        assert_eq!(tracker.len(), 3);
+
+        tracker.cleanup();
+        assert_eq!(tracker.len(), 3);
+    }
 }
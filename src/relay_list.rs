@@ -0,0 +1,234 @@
+//! NIP-65 relay lists for hosted users. A user's list is signed by the user,
+//! either submitted through the admin API or published to one of their
+//! hosted relays; the newest one is stored to every hosted relay it names
+//! and republished to the configured relays every `interval_hours`, so
+//! outbox-model clients keep finding the user's relays.
+
+use crate::config::RelayListConfig;
+use crate::server::{RelayState, StorageOp};
+use nostr::nips::nip65;
+use nostr::{Event, EventBuilder, Filter, JsonUtil, Kind, PublicKey, UnsignedEvent, Url};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct RelayListService {
+    users: HashMap<PublicKey, Vec<String>>,
+    /// `wss://` URL of every hosted relay, by relay id.
+    urls: HashMap<String, String>,
+    relays: HashMap<String, Arc<RelayState>>,
+    targets: Vec<String>,
+    interval: Duration,
+    /// Submitted lists, as `<hex pubkey>.json`.
+    dir: PathBuf,
+}
+
+/// A configured user's list, for the admin API.
+#[derive(Debug, Serialize)]
+pub struct RelayListStatus {
+    pub pubkey: String,
+    /// Hosted relay URLs the list must contain.
+    pub relays: Vec<String>,
+    /// The newest signed list, if there is one.
+    pub list: Option<Event>,
+    /// Hosted relay URLs the newest list leaves out.
+    pub missing: Vec<String>,
+    /// An unsigned list for the user to sign when there is none yet.
+    pub template: UnsignedEvent,
+}
+
+impl RelayListService {
+    pub fn new(
+        config: &RelayListConfig,
+        urls: HashMap<String, String>,
+        relays: HashMap<String, Arc<RelayState>>,
+        targets: Vec<String>,
+        dir: PathBuf,
+    ) -> Self {
+        let users = config
+            .users
+            .iter()
+            .filter_map(|user| Some((PublicKey::parse(&user.pubkey).ok()?, user.relays.clone())))
+            .collect();
+        Self {
+            users,
+            urls,
+            relays,
+            targets,
+            interval: Duration::from_secs(config.interval_hours.max(1) * 3600),
+            dir,
+        }
+    }
+
+    /// Spawn the republisher. The first round runs right away.
+    pub fn start(self: Arc<Self>) {
+        tokio::spawn(async move {
+            tracing::info!(
+                "Publishing NIP-65 relay lists for {} users every {} hours",
+                self.users.len(),
+                self.interval.as_secs() / 3600
+            );
+            loop {
+                for pubkey in self.users.keys() {
+                    if let Some(list) = self.current(pubkey).await {
+                        self.publish(&list).await;
+                    }
+                }
+                tokio::time::sleep(self.interval).await;
+            }
+        });
+    }
+
+    /// Hosted relay URLs `pubkey`'s list must contain, or `None` if the
+    /// pubkey isn't configured.
+    fn expected(&self, pubkey: &PublicKey) -> Option<Vec<String>> {
+        let relays = self.users.get(pubkey)?;
+        Some(relays.iter().filter_map(|id| self.urls.get(id).cloned()).collect())
+    }
+
+    pub async fn status(&self) -> Vec<RelayListStatus> {
+        let mut statuses = Vec::new();
+        for (pubkey, expected) in self.users.keys().filter_map(|pk| Some((pk, self.expected(pk)?))) {
+            let list = self.current(pubkey).await;
+            let missing = list.as_ref().map_or_else(|| expected.clone(), |list| missing(list, &expected));
+            statuses.push(RelayListStatus {
+                pubkey: pubkey.to_hex(),
+                template: template(*pubkey, &expected),
+                relays: expected,
+                list,
+                missing,
+            });
+        }
+        statuses.sort_by(|a, b| a.pubkey.cmp(&b.pubkey));
+        statuses
+    }
+
+    /// Accept a list signed by a configured user, store it and publish it
+    /// right away.
+    pub async fn submit(&self, event: Event) -> Result<(), String> {
+        let expected = self.expected(&event.pubkey).ok_or("pubkey has no relay list configured")?;
+        check(&event, &expected)?;
+        if let Some(current) = self.current(&event.pubkey).await {
+            if current.created_at > event.created_at {
+                return Err("a newer relay list is already stored".to_string());
+            }
+        }
+        let path = self.dir.join(format!("{}.json", event.pubkey.to_hex()));
+        let write = async {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(&path, event.as_json()).await
+        };
+        write.await.map_err(|e| format!("failed to store relay list: {}", e))?;
+        self.publish(&event).await;
+        Ok(())
+    }
+
+    /// The newest list `pubkey` signed: the submitted one, or one they
+    /// published to a hosted relay.
+    async fn current(&self, pubkey: &PublicKey) -> Option<Event> {
+        let path = self.dir.join(format!("{}.json", pubkey.to_hex()));
+        let mut newest = tokio::fs::read_to_string(&path)
+            .await
+            .ok()
+            .and_then(|json| Event::from_json(json).ok());
+        for relay in self.users.get(pubkey)?.iter().filter_map(|id| self.relays.get(id)) {
+            let filter = Filter::new().author(*pubkey).kind(Kind::RelayList).limit(1);
+            let found = relay.with_store(StorageOp::Read, move |store| store.query(&filter)).await;
+            for event in found.unwrap_or_default() {
+                if newest.as_ref().is_none_or(|n| event.created_at > n.created_at) {
+                    newest = Some(event);
+                }
+            }
+        }
+        newest
+    }
+
+    /// Store `list` on the hosted relays it names and send it to the
+    /// publish targets.
+    async fn publish(&self, list: &Event) {
+        let named: Vec<Url> = nip65::extract_relay_list(list).map(|(url, _)| url.clone()).collect();
+        for (id, relay) in &self.relays {
+            let Some(url) = self.urls.get(id) else { continue };
+            if !named.iter().any(|u| same_url(u.as_str(), url)) {
+                continue;
+            }
+            let to_save = list.clone();
+            match relay.with_store(StorageOp::Write, move |store| store.save_event(&to_save)).await {
+                Ok(result) if result.is_stored() => relay.event_stored(list),
+                Ok(_) => {}
+                Err(e) => tracing::warn!(relay = %id, "Failed to store relay list: {}", e),
+            }
+        }
+
+        let msg = format!(r#"["EVENT",{}]"#, list.as_json());
+        for target in &self.targets {
+            if let Err(e) = crate::dm::publish(target, &msg, &list.id.to_hex()).await {
+                tracing::warn!(target = %target, "Failed to publish relay list: {}", e);
+            }
+        }
+    }
+}
+
+fn same_url(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
+/// The hosted relay URLs in `expected` that `list` leaves out.
+pub fn missing(list: &Event, expected: &[String]) -> Vec<String> {
+    let named: Vec<&Url> = nip65::extract_relay_list(list).map(|(url, _)| url).collect();
+    expected
+        .iter()
+        .filter(|url| !named.iter().any(|n| same_url(n.as_str(), url)))
+        .cloned()
+        .collect()
+}
+
+/// Whether `event` is a validly signed relay list naming every `expected`
+/// relay.
+pub fn check(event: &Event, expected: &[String]) -> Result<(), String> {
+    if event.kind != Kind::RelayList {
+        return Err(format!("expected a kind {} relay list", Kind::RelayList.as_u16()));
+    }
+    event.verify().map_err(|_| "invalid signature".to_string())?;
+    let missing = missing(event, expected);
+    if !missing.is_empty() {
+        return Err(format!("relay list is missing {}", missing.join(", ")));
+    }
+    Ok(())
+}
+
+/// An unsigned list naming `relays` for both reading and writing.
+pub fn template(pubkey: PublicKey, relays: &[String]) -> UnsignedEvent {
+    let relays = relays.iter().filter_map(|url| Url::parse(url).ok()).map(|url| (url, None));
+    EventBuilder::relay_list(relays).to_unsigned_event(pubkey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::nips::nip65::RelayMetadata;
+    use nostr::Keys;
+
+    #[test]
+    fn lists_must_name_every_hosted_relay() {
+        let keys = Keys::generate();
+        let expected = vec!["wss://outbox.example.com".to_string(), "wss://inbox.example.com".to_string()];
+
+        let list = template(keys.public_key(), &expected).sign(&keys).unwrap();
+        assert_eq!(check(&list, &expected), Ok(()));
+
+        let partial = EventBuilder::relay_list([
+            (Url::parse("wss://outbox.example.com/").unwrap(), Some(RelayMetadata::Write)),
+            (Url::parse("wss://elsewhere.example").unwrap(), None),
+        ])
+        .to_event(&keys)
+        .unwrap();
+        assert_eq!(missing(&partial, &expected), vec!["wss://inbox.example.com".to_string()]);
+        assert!(check(&partial, &expected).is_err());
+
+        let note = EventBuilder::text_note("hi", []).to_event(&keys).unwrap();
+        assert!(check(&note, &[]).is_err());
+    }
+}
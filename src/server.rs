@@ -1,8 +1,8 @@
 use axum::{
-    body::Body,
+    body::{Body, Bytes},
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Query, Request, State,
+        ConnectInfo, Extension, Query, Request, State,
     },
     http::{header, HeaderMap, StatusCode},
     response::{Html, IntoResponse},
@@ -10,40 +10,103 @@ use axum::{
     Json, Router,
 };
 use futures::{sink::SinkExt, stream::StreamExt};
-use nostr::{ClientMessage, Event, JsonUtil, PublicKey, RelayMessage};
-use std::str::FromStr;
+use nostr::nips::nip19::{FromBech32, Nip19Profile, ToBech32};
+use nostr::{ClientMessage, Event, Filter, JsonUtil, Keys, PublicKey, RelayMessage};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::broadcast;
+use std::sync::atomic::AtomicUsize;
+use arc_swap::ArcSwap;
+use tokio::sync::{mpsc, Semaphore};
 use tower_http::cors::{Any, CorsLayer};
 
-use crate::config::RelayConfig;
-use crate::paywall::PaywallManager;
+use crate::config::{MaintenanceMode, Nip11Fee, Nip11Fees, RelayConfig};
+use crate::paywall::{PaywallInfo, PaywallManager};
+use crate::auth::{AuthGrants, ReplayGuard};
+use crate::connections::{Connection, ConnectionRegistry};
+use crate::firehose::Firehose;
+use crate::greylist::Greylist;
+use crate::invite::InviteManager;
+use crate::plugin::WriteSource;
 use crate::policy::{PolicyEngine, PolicyResult};
-use crate::rate_limit::IpTracker;
+use crate::query_cache::QueryCache;
+use crate::rate_limit::{ConnectionSlot, IpTracker, PubkeyTracker};
+use crate::mirror::MirrorPublisher;
 use crate::stats::RelayStats;
-use crate::storage::NostrStore;
-use std::collections::HashSet;
-use std::net::IpAddr;
+use crate::storage::filter::ReqFilter;
+use crate::storage::{NostrStore, SaveResult};
+use crate::subscriptions::SubscriptionIndex;
+use crate::webhook::{Alert, AlertKind, Notifier};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::atomic::Ordering::Relaxed;
 
+/// Access requests accepted from one IP per minute.
+const ACCESS_REQUESTS_PER_MINUTE: u32 = 3;
+
+/// Live events queued per connection before a lagging subscriber starts missing them.
+const DEFAULT_BROADCAST_CAPACITY: usize = 1024;
+
+/// Storage calls per relay allowed on the blocking thread pool at once.
+const STORAGE_CONCURRENCY: usize = 16;
+
+/// Which latency counters a storage call is recorded under.
+#[derive(Debug, Clone, Copy)]
+pub enum StorageOp {
+    Read,
+    Write,
+}
+
 pub struct RelayState {
     pub store: Arc<dyn NostrStore>,
-    pub policy: Arc<PolicyEngine>,
-    pub config: RelayConfig,
+    /// The relay's policy, swapped in place when the admin edits it.
+    pub policy: Arc<ArcSwap<PolicyEngine>>,
+    /// Read through [`config`](Self::config); replaced by [`reload`](Self::reload).
+    config: ArcSwap<RelayConfig>,
     pub relay_id: String,
     pub pages_dir: PathBuf,
     pub admin_pubkey: String,
     pub relay_url: String,
-    pub tx: broadcast::Sender<Event>,
+    /// Other URLs clients reach the relay at, such as the apex domain for
+    /// the gateway's `default_relay`; accepted in AUTH relay tags.
+    pub aliases: Vec<String>,
+    /// Live subscriptions of every connection, for event fan-out.
+    pub subscriptions: Arc<SubscriptionIndex>,
+    /// Recent REQ results, if `query_cache` is configured.
+    pub query_cache: Option<Arc<QueryCache>>,
     pub paywall_manager: Option<Arc<PaywallManager>>,
     pub paywall_id: Option<String>,
     pub stats: Arc<RelayStats>,
     pub ip_tracker: Arc<IpTracker>,
+    /// Per-author write limits.
+    pub pubkey_tracker: Arc<PubkeyTracker>,
+    /// Stricter limits for new pubkeys, if `policy.greylist` is configured.
+    pub greylist: Option<Arc<Greylist>>,
+    /// Auth events already used, shared with the rest of the gateway.
+    pub auth_replay: Arc<ReplayGuard>,
+    /// Where accepted AUTH events are recorded, if a blossom server names
+    /// this relay as its `auth_relay`.
+    pub auth_grants: Option<Arc<AuthGrants>>,
+    /// Where a full LMDB map is reported, if the gateway set one.
+    pub notifier: Option<Arc<Notifier>>,
+    /// Where invite codes are redeemed and access requested, if
+    /// `policy.write.invites` or `policy.write.access_requests` is on.
+    pub invites: Option<Arc<InviteManager>>,
+    /// The relay's own signing identity, if configured.
+    pub keys: Option<Keys>,
+    /// Publisher forwarding accepted events to `mirror_to` relays.
+    pub mirror: Option<Arc<MirrorPublisher>>,
+    /// Live accept/reject decisions for the admin firehose.
+    pub firehose: Arc<Firehose>,
+    /// Open WebSocket connections, for the admin API.
+    pub connections: Arc<ConnectionRegistry>,
+    /// Serialized NIP-11 document and the paywall generation it reflects.
+    nip11_cache: std::sync::Mutex<Option<(u64, Bytes)>>,
+    /// Bounds concurrent storage calls on the blocking pool.
+    storage_permits: Arc<Semaphore>,
 }
 
 impl RelayState {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: RelayConfig,
         store: Arc<dyn NostrStore>,
@@ -56,22 +119,116 @@ impl RelayState {
         paywall_id: Option<String>,
         stats: Arc<RelayStats>,
         ip_tracker: Arc<IpTracker>,
+        keys: Option<Keys>,
+        mirror: Option<Arc<MirrorPublisher>>,
+        greylist: Option<Arc<Greylist>>,
+        auth_replay: Arc<ReplayGuard>,
     ) -> Self {
-        let (tx, _rx) = broadcast::channel(100);
+        let capacity = config.broadcast_capacity.unwrap_or(DEFAULT_BROADCAST_CAPACITY);
+        let query_cache = config.query_cache.map(|capacity| Arc::new(QueryCache::new(capacity)));
         Self {
             store,
-            policy,
-            config,
+            policy: Arc::new(ArcSwap::new(policy)),
+            config: ArcSwap::from_pointee(config),
             relay_id,
             pages_dir,
             admin_pubkey,
             relay_url,
-            tx,
+            aliases: Vec::new(),
+            subscriptions: Arc::new(SubscriptionIndex::new(capacity)),
+            query_cache,
             paywall_manager,
             paywall_id,
             stats,
             ip_tracker,
+            pubkey_tracker: Arc::new(PubkeyTracker::new()),
+            greylist,
+            auth_replay,
+            auth_grants: None,
+            notifier: None,
+            invites: None,
+            keys,
+            mirror,
+            firehose: Arc::new(Firehose::new()),
+            connections: Arc::new(ConnectionRegistry::default()),
+            nip11_cache: std::sync::Mutex::new(None),
+            storage_permits: Arc::new(Semaphore::new(STORAGE_CONCURRENCY)),
+        }
+    }
+
+    /// The relay's current config.
+    pub fn config(&self) -> Arc<RelayConfig> {
+        self.config.load_full()
+    }
+
+    /// Apply a config change that only touches policy, NIP-11 or naming
+    /// fields. Open connections stay up and their next message is checked
+    /// against the new rules; the shadow-ban list keeps its identity so the
+    /// admin API's handle stays live.
+    pub fn reload(&self, config: RelayConfig, policy: PolicyEngine) {
+        let policy = policy.with_shadow_bans(self.policy.load().shadow_bans().clone());
+        self.policy.store(Arc::new(policy));
+        self.config.store(Arc::new(config));
+        *self.nip11_cache.lock().unwrap() = None;
+    }
+
+    /// Put the relay into (or, with `None`, out of) maintenance. Takes effect
+    /// on every connection's next message.
+    pub fn set_maintenance(&self, mode: Option<MaintenanceMode>) {
+        self.config.rcu(|config| {
+            let mut config = RelayConfig::clone(config);
+            config.maintenance_mode = mode;
+            config
+        });
+    }
+
+    /// Fan a newly stored event out to live subscriptions and drop the
+    /// cached results it changes.
+    pub fn event_stored(&self, event: &Event) {
+        if let Some(cache) = &self.query_cache {
+            cache.invalidate(event);
+        }
+        self.subscriptions.publish(event);
+    }
+
+    /// Run a storage call on the blocking thread pool so a slow LMDB scan or
+    /// write doesn't stall every connection sharing the runtime thread.
+    /// Latency (including time spent waiting for a permit) is recorded in
+    /// the relay stats.
+    pub async fn with_store<T, F>(&self, op: StorageOp, f: F) -> crate::error::Result<T>
+    where
+        F: FnOnce(&dyn NostrStore) -> crate::error::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let started = std::time::Instant::now();
+        let _permit = self
+            .storage_permits
+            .acquire()
+            .await
+            .expect("storage semaphore is never closed");
+        let store = Arc::clone(&self.store);
+        let result = tokio::task::spawn_blocking(move || f(store.as_ref())).await?;
+
+        let micros = started.elapsed().as_micros() as u64;
+        let (count, total) = match op {
+            StorageOp::Read => (&self.stats.storage_reads, &self.stats.storage_read_micros),
+            StorageOp::Write => (&self.stats.storage_writes, &self.stats.storage_write_micros),
+        };
+        count.fetch_add(1, Relaxed);
+        total.fetch_add(micros, Relaxed);
+        if let (Err(e), Some(notifier)) = (&result, &self.notifier) {
+            if e.is_map_full() {
+                notifier.notify_throttled(
+                    &format!("map_full:{}", self.relay_id),
+                    Alert::new(
+                        AlertKind::MapFull,
+                        format!("Relay '{}' database is full and can't store more events", self.relay_id),
+                        serde_json::json!({ "relay_id": self.relay_id, "db_path": self.store.db_path() }),
+                    ),
+                );
+            }
         }
+        result
     }
 }
 
@@ -87,16 +244,31 @@ pub fn create_relay_router(state: Arc<RelayState>) -> Router {
 
     Router::new()
         .route("/", get(root_handler))
+        .route("/status", get(status_handler))
         .route("/checkout/info", get(checkout_info_handler))
         .route("/checkout", post(checkout_handler))
         .route("/checkout/status", get(checkout_status_handler))
+        .route("/checkout/reissue", post(checkout_reissue_handler))
+        .route("/checkout/cashu", post(checkout_cashu_handler))
+        .route("/invite", post(invite_handler))
+        .route("/access-request", post(access_request_handler))
         .layer(cors)
         .with_state(state)
 }
 
+/// The path the relay is mounted under, with no trailing slash: empty on
+/// its own subdomain, `/relay/<id>` with path routing.
+fn base_path(relay_url: &str) -> String {
+    nostr::Url::parse(relay_url)
+        .map(|url| url.path().trim_end_matches('/').to_string())
+        .unwrap_or_default()
+}
+
 /// Handles NIP-11 info document, WebSocket upgrades, and regular HTTP GET requests.
 async fn root_handler(
     ws: Option<WebSocketUpgrade>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    gateway_slot: Option<Extension<Arc<ConnectionSlot>>>,
     headers: HeaderMap,
     State(state): State<Arc<RelayState>>,
 ) -> impl IntoResponse {
@@ -104,8 +276,7 @@ async fn root_handler(
     if let Some(accept) = headers.get(header::ACCEPT) {
         if let Ok(accept_str) = accept.to_str() {
             if accept_str.contains("application/nostr+json") {
-                let doc = build_nip11(&state);
-                let json = serde_json::to_string(&doc).unwrap_or_default();
+                let json = nip11_json(&state).await;
                 return (
                     [(header::CONTENT_TYPE, "application/nostr+json")],
                     json,
@@ -115,18 +286,23 @@ async fn root_handler(
         }
     }
 
-    // Extract client IP from X-Forwarded-For header or fall back to loopback
-    let client_ip = extract_client_ip(&headers);
+    // The socket peer, or the forwarded client if the peer is a trusted proxy.
+    // Routers served without connect info (tests) report loopback.
+    let peer = connect_info.map_or(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), |ConnectInfo(addr)| addr.ip());
+    let client_ip = state.ip_tracker.client_ip(peer, &headers);
 
     // WebSocket upgrade takes priority
     if let Some(ws) = ws {
         // Enforce per-IP connection limit
-        let max_conn = state.config.policy.rate_limit.max_connections;
+        let max_conn = state.config().policy.rate_limit.max_connections;
         if !state.ip_tracker.try_connect(client_ip, max_conn) {
             return (StatusCode::SERVICE_UNAVAILABLE, "too many connections from your IP").into_response();
         }
         let ip = client_ip;
-        return ws.on_upgrade(move |socket| handle_socket(socket, state, ip)).into_response();
+        let gateway_slot = gateway_slot.map(|Extension(slot)| slot);
+        return ws
+            .on_upgrade(move |socket| handle_socket(socket, state, ip, gateway_slot))
+            .into_response();
     }
 
     // Serve custom home page if it exists
@@ -138,26 +314,47 @@ async fn root_handler(
     // If this relay has a paywall, serve the checkout page
     if let (Some(ref pm), Some(ref pw_id)) = (&state.paywall_manager, &state.paywall_id) {
         if let Some(info) = pm.get_paywall_info(pw_id).await {
-            let access_mode = determine_access_mode(&state.config);
+            let config = state.config();
+            let access_mode = determine_access_mode(&config);
+            let paywall = pm.get_config(pw_id).await;
+            let zap_option = match paywall.as_ref().and_then(|c| c.zaps.as_ref()) {
+                Some(zaps) => zap_option(zaps, info.price_sats),
+                None => String::new(),
+            };
+            let cashu_option = match paywall.as_ref().and_then(|c| c.cashu.as_ref()) {
+                Some(cashu) => cashu_option(cashu),
+                None => String::new(),
+            };
             let template = include_str!("web/checkout.html");
             let html = template
-                .replace("{{RELAY_NAME}}", &html_escape(&state.config.name))
+                .replace("{{RELAY_NAME}}", &html_escape(&config.name))
                 .replace("{{PRICE_SATS}}", &info.price_sats.to_string())
                 .replace("{{PERIOD_DAYS}}", &info.period_days.to_string())
-                .replace("{{ACCESS_MODE}}", access_mode);
+                .replace("{{ACCESS_MODE}}", access_mode)
+                .replace("{{CASHU_OPTION}}", &cashu_option)
+                .replace("{{ZAP_OPTION}}", &zap_option)
+                .replace("{{BASE_PATH}}", &base_path(&state.relay_url));
             return Html(html).into_response();
         }
     }
 
     // Default relay info page
-    let name = html_escape(&state.config.name);
-    let desc = state
-        .config
+    let config = state.config();
+    let name = html_escape(&config.name);
+    let desc = config
         .description
         .as_deref()
         .unwrap_or("A Nostr relay powered by MOAR");
     let desc = html_escape(desc);
 
+    let mut invite = String::new();
+    if state.invites.is_some() && config.policy.write.invites {
+        invite.push_str(&INVITE_FORM.replace("{{BASE_PATH}}", &base_path(&state.relay_url)));
+    }
+    if state.invites.is_some() && config.policy.write.access_requests {
+        invite.push_str(&ACCESS_REQUEST_FORM.replace("{{BASE_PATH}}", &base_path(&state.relay_url)));
+    }
+
     let html = format!(
         r#"<!DOCTYPE html>
 <html lang="en">
@@ -179,6 +376,7 @@ p{{color:#888;font-size:0.95rem;line-height:1.5}}
 <h1>{name}</h1>
 <p>{desc}</p>
 <span class="badge">Nostr Relay</span>
+{invite}
 </div>
 </body>
 </html>"#
@@ -187,23 +385,101 @@ p{{color:#888;font-size:0.95rem;line-height:1.5}}
     Html(html).into_response()
 }
 
-/// Extract client IP from X-Forwarded-For header, falling back to loopback.
-fn extract_client_ip(headers: &HeaderMap) -> IpAddr {
-    if let Some(xff) = headers.get("x-forwarded-for") {
-        if let Ok(xff_str) = xff.to_str() {
-            // Take the first (leftmost) IP — the original client
-            if let Some(first) = xff_str.split(',').next() {
-                if let Ok(ip) = first.trim().parse::<IpAddr>() {
-                    return ip;
-                }
-            }
-        }
-    }
-    // Fallback — peer address not available in this handler, use loopback
-    IpAddr::V4(std::net::Ipv4Addr::LOCALHOST)
+/// Redeem form on the default home page of relays that take invite codes.
+const INVITE_FORM: &str = r#"<form id="invite" style="margin-top:2rem;display:flex;flex-direction:column;gap:0.5rem">
+<input name="npub" placeholder="npub1..." required style="padding:0.5rem;background:#111;border:1px solid #333;border-radius:6px;color:#fff">
+<input name="code" placeholder="Invite code" required style="padding:0.5rem;background:#111;border:1px solid #333;border-radius:6px;color:#fff">
+<button style="padding:0.5rem;background:#7c3aed;border:0;border-radius:6px;color:#fff;cursor:pointer">Join</button>
+<p id="invite-result"></p>
+</form>
+<script>
+document.getElementById('invite').addEventListener('submit', async (e) => {
+  e.preventDefault();
+  const form = new FormData(e.target);
+  const res = await fetch('{{BASE_PATH}}/invite', {
+    method: 'POST',
+    headers: {'Content-Type': 'application/json'},
+    body: JSON.stringify({npub: form.get('npub'), code: form.get('code')}),
+  });
+  document.getElementById('invite-result').textContent = res.ok ? 'Welcome! You can now write to this relay.' : await res.text();
+});
+</script>"#;
+
+/// Request form on the default home page of relays that take access
+/// requests.
+const ACCESS_REQUEST_FORM: &str = r#"<form id="access-request" style="margin-top:2rem;display:flex;flex-direction:column;gap:0.5rem">
+<textarea name="message" placeholder="Anything the admin should know (optional)" maxlength="500" rows="3" style="padding:0.5rem;background:#111;border:1px solid #333;border-radius:6px;color:#fff;font-family:inherit"></textarea>
+<button style="padding:0.5rem;background:#7c3aed;border:0;border-radius:6px;color:#fff;cursor:pointer">Request access</button>
+<p id="access-request-result"></p>
+</form>
+<script>
+document.getElementById('access-request').addEventListener('submit', async (e) => {
+  e.preventDefault();
+  const result = document.getElementById('access-request-result');
+  if (!window.nostr) {
+    result.textContent = 'A Nostr signing extension (NIP-07) is needed to request access.';
+    return;
+  }
+  const form = new FormData(e.target);
+  const url = window.location.origin + '{{BASE_PATH}}/access-request';
+  const body = JSON.stringify({message: form.get('message')});
+  const digest = await crypto.subtle.digest('SHA-256', new TextEncoder().encode(body));
+  const payload = Array.from(new Uint8Array(digest), (b) => b.toString(16).padStart(2, '0')).join('');
+  const auth = await window.nostr.signEvent({
+    kind: 27235,
+    created_at: Math.floor(Date.now() / 1000),
+    tags: [['u', url], ['method', 'POST'], ['payload', payload]],
+    content: '',
+  });
+  const res = await fetch(url, {
+    method: 'POST',
+    headers: {'Content-Type': 'application/json', 'Authorization': 'Nostr ' + btoa(JSON.stringify(auth))},
+    body,
+  });
+  result.textContent = res.ok ? 'Request sent. The admin will review it.' : await res.text();
+});
+</script>"#;
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Checkout page form for paying with a pasted Cashu token.
+fn cashu_option(cashu: &crate::config::PaywallCashuConfig) -> String {
+    let mints: Vec<String> = cashu.mints.iter().map(|m| html_escape(m)).collect();
+    format!(
+        r#"<div class="cashu-option">
+      <div class="form-group">
+        <label for="cashu-input">Or paste a Cashu token from {}</label>
+        <textarea id="cashu-input" placeholder="cashuB..." spellcheck="false"></textarea>
+        <div class="error" id="cashu-error"></div>
+      </div>
+      <button id="cashu-btn" onclick="redeemCashu()">Pay with Cashu</button>
+    </div>"#,
+        mints.join(", ")
+    )
 }
 
-fn html_escape(s: &str) -> String {
+/// Checkout page instructions for paying with a zap instead of an invoice.
+fn zap_option(zaps: &crate::config::PaywallZapConfig, price_sats: u64) -> String {
+    let npub = PublicKey::parse(&zaps.pubkey)
+        .ok()
+        .and_then(|pk| pk.to_bech32().ok())
+        .unwrap_or_else(|| zaps.pubkey.clone());
+    format!(
+        r#"<div class="zap-option">
+      <p>Or zap at least {} sats to <strong>{}</strong> (<span class="npub">{}</span>) from the account you want to use. Access starts once the zap receipt is seen.</p>
+    </div>"#,
+        price_sats,
+        html_escape(&zaps.lightning_address),
+        html_escape(&npub)
+    )
+}
+
+pub(crate) fn html_escape(s: &str) -> String {
     s.replace('&', "&amp;")
         .replace('<', "&lt;")
         .replace('>', "&gt;")
@@ -231,6 +507,18 @@ struct Nip11Document {
     #[serde(skip_serializing_if = "Option::is_none")]
     terms_of_service: Option<String>,
     limitation: Nip11Limitation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    relay_countries: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language_tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    posting_policy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payments_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fees: Option<Nip11Fees>,
 }
 
 #[derive(Serialize)]
@@ -258,11 +546,35 @@ struct Nip11Limitation {
     created_at_lower_limit: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     created_at_upper_limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_events_per_req: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_query_ms: Option<u64>,
 }
 
-fn build_nip11(state: &RelayState) -> Nip11Document {
-    let policy = &state.config.policy;
-    let nip11 = &state.config.nip11;
+/// The serialized NIP-11 document. It is rebuilt only when the relay's
+/// paywall settings change; everything else is fixed until restart.
+pub(crate) async fn nip11_json(state: &RelayState) -> Bytes {
+    let generation = state.paywall_manager.as_ref().map_or(0, |pm| pm.generation());
+    if let Some((cached_generation, json)) = &*state.nip11_cache.lock().unwrap() {
+        if *cached_generation == generation {
+            return json.clone();
+        }
+    }
+
+    let paywall = match (&state.paywall_manager, &state.paywall_id) {
+        (Some(pm), Some(id)) => pm.get_paywall_info(id).await,
+        _ => None,
+    };
+    let json = Bytes::from(serde_json::to_vec(&build_nip11(state, paywall.as_ref())).unwrap_or_default());
+    *state.nip11_cache.lock().unwrap() = Some((generation, json.clone()));
+    json
+}
+
+fn build_nip11(state: &RelayState, paywall: Option<&PaywallInfo>) -> Nip11Document {
+    let config = state.config();
+    let policy = &config.policy;
+    let nip11 = &config.nip11;
 
     let auth_required = policy.write.require_auth || policy.read.require_auth;
     let restricted_writes = policy.write.allowed_pubkeys.is_some()
@@ -271,23 +583,40 @@ fn build_nip11(state: &RelayState) -> Nip11Document {
         || policy.write.paywall.is_some();
     let payment_required = policy.write.paywall.is_some() || policy.read.paywall.is_some();
 
-    let pubkey = if state.admin_pubkey.is_empty() {
-        None
-    } else {
-        Some(state.admin_pubkey.clone())
+    let pubkey = match &state.keys {
+        Some(keys) => Some(keys.public_key().to_hex()),
+        None if state.admin_pubkey.is_empty() => None,
+        None => Some(state.admin_pubkey.clone()),
     };
 
     Nip11Document {
-        name: state.config.name.clone(),
-        description: state.config.description.clone(),
+        name: config.name.clone(),
+        description: config.description.clone(),
         pubkey,
         contact: nip11.contact.clone(),
-        supported_nips: vec![1, 11, 13],
+        supported_nips: vec![1, 11, 13, 42],
         software: "https://github.com/barrydeen/moar".to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
         icon: nip11.icon.clone(),
         banner: nip11.banner.clone(),
         terms_of_service: nip11.terms_of_service.clone(),
+        relay_countries: nip11.relay_countries.clone(),
+        language_tags: nip11.language_tags.clone(),
+        tags: nip11.tags.clone(),
+        posting_policy: nip11.posting_policy.clone(),
+        // The checkout page is served at the relay's root
+        payments_url: nip11.payments_url.clone().or_else(|| paywall.map(|_| state.relay_url.clone())),
+        fees: nip11.fees.clone().or_else(|| {
+            paywall.map(|p| Nip11Fees {
+                subscription: vec![Nip11Fee {
+                    amount: p.price_sats * 1000,
+                    unit: "msats".to_string(),
+                    period: Some(p.period_days as u64 * 86400),
+                    kinds: None,
+                }],
+                ..Default::default()
+            })
+        }),
         limitation: Nip11Limitation {
             max_message_length: nip11.max_message_length,
             max_subscriptions: nip11.max_subscriptions,
@@ -302,10 +631,170 @@ fn build_nip11(state: &RelayState) -> Nip11Document {
             payment_required,
             created_at_lower_limit: nip11.created_at_lower_limit,
             created_at_upper_limit: nip11.created_at_upper_limit,
+            max_events_per_req: nip11.max_events_per_req,
+            max_query_ms: nip11.max_query_ms,
         },
     }
 }
 
+// --- Public Status Page ---
+
+#[derive(Serialize)]
+struct PolicySummary {
+    auth_required: bool,
+    restricted_writes: bool,
+    restricted_reads: bool,
+    payment_required: bool,
+    wot_required: bool,
+}
+
+#[derive(Serialize)]
+struct RelayStatusResponse {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    uptime_seconds: u64,
+    active_connections: i64,
+    events_stored: u64,
+    events_received: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_event_at: Option<u64>,
+    policy: PolicySummary,
+}
+
+fn build_policy_summary(config: &RelayConfig) -> PolicySummary {
+    let policy = &config.policy;
+    PolicySummary {
+        auth_required: policy.write.require_auth || policy.read.require_auth,
+        restricted_writes: policy.write.allowed_pubkeys.is_some()
+            || policy.write.wot.is_some()
+            || policy.write.tagged_pubkeys.is_some()
+            || policy.write.paywall.is_some(),
+        restricted_reads: policy.read.allowed_pubkeys.is_some()
+            || policy.read.wot.is_some()
+            || policy.read.paywall.is_some(),
+        payment_required: policy.write.paywall.is_some() || policy.read.paywall.is_some(),
+        wot_required: policy.write.wot.is_some() || policy.read.wot.is_some(),
+    }
+}
+
+fn build_relay_status(state: &RelayState) -> RelayStatusResponse {
+    let stats = &state.stats;
+    let events_stored = state
+        .store
+        .event_count()
+        .unwrap_or_else(|_| stats.event_count.load(Relaxed));
+    let last_event_at = match stats.last_event_at.load(Relaxed) {
+        0 => None,
+        ts => Some(ts),
+    };
+
+    let config = state.config();
+    RelayStatusResponse {
+        name: config.name.clone(),
+        description: config.description.clone(),
+        uptime_seconds: now_secs().saturating_sub(stats.started_at),
+        active_connections: stats.active_connections.load(Relaxed),
+        events_stored,
+        events_received: stats.events_saved.load(Relaxed),
+        last_event_at,
+        policy: build_policy_summary(&config),
+    }
+}
+
+#[derive(Deserialize)]
+struct StatusQuery {
+    format: Option<String>,
+}
+
+/// Public per-relay status page. Serves JSON when requested via `Accept:
+/// application/json` or `?format=json`, otherwise a small HTML page.
+async fn status_handler(
+    headers: HeaderMap,
+    Query(query): Query<StatusQuery>,
+    State(state): State<Arc<RelayState>>,
+) -> impl IntoResponse {
+    let status = build_relay_status(&state);
+
+    let wants_json = query.format.as_deref() == Some("json")
+        || headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.contains("application/json"));
+    if wants_json {
+        return Json(status).into_response();
+    }
+
+    let yes_no = |b: bool| if b { "Yes" } else { "No" };
+    let last_event = match status.last_event_at {
+        Some(ts) => format!("{}s ago", now_secs().saturating_sub(ts)),
+        None => "None since start".to_string(),
+    };
+    let name = html_escape(&status.name);
+    let uptime = format_duration(status.uptime_seconds);
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="UTF-8">
+<meta name="viewport" content="width=device-width, initial-scale=1.0">
+<title>{name} — Status</title>
+<style>
+*{{margin:0;padding:0;box-sizing:border-box}}
+body{{background:#0a0a0a;color:#fff;font-family:-apple-system,BlinkMacSystemFont,'Segoe UI',Roboto,sans-serif;display:flex;align-items:center;justify-content:center;min-height:100vh}}
+.container{{max-width:480px;width:100%;padding:2rem}}
+h1{{font-size:1.5rem;margin-bottom:1rem;text-align:center}}
+table{{width:100%;border-collapse:collapse;font-size:0.95rem}}
+td{{padding:0.5rem 0;border-bottom:1px solid #222}}
+td:first-child{{color:#888}}
+td:last-child{{text-align:right;font-family:monospace}}
+</style>
+</head>
+<body>
+<div class="container">
+<h1>{name}</h1>
+<table>
+<tr><td>Uptime</td><td>{uptime}</td></tr>
+<tr><td>Active connections</td><td>{connections}</td></tr>
+<tr><td>Events stored</td><td>{stored}</td></tr>
+<tr><td>Events received since start</td><td>{received}</td></tr>
+<tr><td>Last event</td><td>{last_event}</td></tr>
+<tr><td>Authentication required</td><td>{auth}</td></tr>
+<tr><td>Restricted writes</td><td>{writes}</td></tr>
+<tr><td>Restricted reads</td><td>{reads}</td></tr>
+<tr><td>Payment required</td><td>{payment}</td></tr>
+<tr><td>Web of Trust required</td><td>{wot}</td></tr>
+</table>
+</div>
+</body>
+</html>"#,
+        connections = status.active_connections,
+        stored = status.events_stored,
+        received = status.events_received,
+        auth = yes_no(status.policy.auth_required),
+        writes = yes_no(status.policy.restricted_writes),
+        reads = yes_no(status.policy.restricted_reads),
+        payment = yes_no(status.policy.payment_required),
+        wot = yes_no(status.policy.wot_required),
+    );
+
+    Html(html).into_response()
+}
+
+fn format_duration(secs: u64) -> String {
+    let days = secs / 86400;
+    let hours = (secs % 86400) / 3600;
+    let minutes = (secs % 3600) / 60;
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
 fn determine_access_mode(config: &RelayConfig) -> &'static str {
     let has_write = config.policy.write.paywall.is_some();
     let has_read = config.policy.read.paywall.is_some();
@@ -339,8 +828,8 @@ async fn checkout_info_handler(
         Some(info) => Json(CheckoutInfoResponse {
             price_sats: info.price_sats,
             period_days: info.period_days,
-            access_mode: determine_access_mode(&state.config).to_string(),
-            relay_name: state.config.name.clone(),
+            access_mode: determine_access_mode(&state.config()).to_string(),
+            relay_name: state.config().name.clone(),
         })
         .into_response(),
         None => (StatusCode::NOT_FOUND, "Paywall not found").into_response(),
@@ -358,6 +847,22 @@ struct CheckoutResponse {
     payment_hash: String,
     amount_sats: u64,
     qr_svg: String,
+    /// Unix time the invoice expires.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+}
+
+impl CheckoutResponse {
+    async fn new(pm: &PaywallManager, paywall_id: &str, invoice: crate::nwc::InvoiceResponse) -> Self {
+        let info = pm.get_paywall_info(paywall_id).await;
+        Self {
+            amount_sats: info.map(|i| i.price_sats).unwrap_or(0),
+            qr_svg: generate_qr_svg(&invoice.invoice),
+            invoice: invoice.invoice,
+            payment_hash: invoice.payment_hash,
+            expires_at: invoice.expires_at,
+        }
+    }
 }
 
 fn generate_qr_svg(data: &str) -> String {
@@ -390,12 +895,12 @@ async fn checkout_handler(
         }
     };
 
-    // Parse npub or hex pubkey
+    // Parse npub, nprofile or hex pubkey
     let pubkey = match PublicKey::parse(&payload.npub) {
         Ok(pk) => pk,
-        Err(_) => match PublicKey::from_str(&payload.npub) {
-            Ok(pk) => pk,
-            Err(e) => {
+        Err(e) => match Nip19Profile::from_bech32(&payload.npub) {
+            Ok(profile) => profile.public_key,
+            Err(_) => {
                 return (
                     StatusCode::BAD_REQUEST,
                     format!("Invalid pubkey: {}", e),
@@ -406,18 +911,7 @@ async fn checkout_handler(
     };
 
     match pm.create_invoice(pw_id, pubkey).await {
-        Ok(invoice_resp) => {
-            let info = pm.get_paywall_info(pw_id).await;
-            let amount_sats = info.map(|i| i.price_sats).unwrap_or(0);
-            let qr_svg = generate_qr_svg(&invoice_resp.invoice);
-            Json(CheckoutResponse {
-                invoice: invoice_resp.invoice,
-                payment_hash: invoice_resp.payment_hash,
-                amount_sats,
-                qr_svg,
-            })
-            .into_response()
-        }
+        Ok(invoice_resp) => Json(CheckoutResponse::new(pm, pw_id, invoice_resp).await).into_response(),
         Err(e) => {
             tracing::error!("Failed to create invoice: {}", e);
             (
@@ -429,6 +923,65 @@ async fn checkout_handler(
     }
 }
 
+#[derive(Deserialize)]
+struct CheckoutReissueRequest {
+    payment_hash: String,
+}
+
+/// Swap an expired (or abandoned) invoice for a new one for the same pubkey.
+async fn checkout_reissue_handler(
+    State(state): State<Arc<RelayState>>,
+    Json(payload): Json<CheckoutReissueRequest>,
+) -> impl IntoResponse {
+    let (pm, pw_id) = match (&state.paywall_manager, &state.paywall_id) {
+        (Some(pm), Some(id)) => (pm, id),
+        _ => return (StatusCode::NOT_FOUND, "No paywall configured").into_response(),
+    };
+
+    match pm.reissue_invoice(pw_id, &payload.payment_hash).await {
+        Ok(Some(invoice_resp)) => Json(CheckoutResponse::new(pm, pw_id, invoice_resp).await).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "No unpaid invoice with that payment hash").into_response(),
+        Err(e) => {
+            tracing::error!("Failed to reissue invoice: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": format!("Failed to create invoice: {}", e) })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CheckoutCashuRequest {
+    npub: String,
+    token: String,
+}
+
+/// Pay for access with a Cashu token instead of an invoice.
+async fn checkout_cashu_handler(
+    State(state): State<Arc<RelayState>>,
+    Json(payload): Json<CheckoutCashuRequest>,
+) -> impl IntoResponse {
+    let (pm, pw_id) = match (&state.paywall_manager, &state.paywall_id) {
+        (Some(pm), Some(id)) => (pm, id),
+        _ => return (StatusCode::NOT_FOUND, "No paywall configured").into_response(),
+    };
+
+    let pubkey = match PublicKey::parse(&payload.npub) {
+        Ok(pk) => pk,
+        Err(e) => match Nip19Profile::from_bech32(&payload.npub) {
+            Ok(profile) => profile.public_key,
+            Err(_) => return (StatusCode::BAD_REQUEST, format!("Invalid pubkey: {}", e)).into_response(),
+        },
+    };
+
+    match pm.redeem_cashu(pw_id, pubkey, &payload.token).await {
+        Ok(expires_at) => Json(serde_json::json!({ "expires_at": expires_at })).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
 #[derive(Deserialize)]
 struct CheckoutStatusQuery {
     payment_hash: String,
@@ -471,12 +1024,111 @@ async fn checkout_status_handler(
     }
 }
 
+// --- Invite Codes ---
+
+#[derive(Deserialize)]
+struct InviteRequest {
+    code: String,
+    npub: String,
+}
+
+/// Redeem an invite code, letting the given pubkey write to the relay.
+async fn invite_handler(State(state): State<Arc<RelayState>>, Json(payload): Json<InviteRequest>) -> impl IntoResponse {
+    let Some(invites) = &state.invites else {
+        return (StatusCode::NOT_FOUND, "Invites are not enabled").into_response();
+    };
+    let pubkey = match parse_npub(&payload.npub) {
+        Ok(pk) => pk,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+    match invites.redeem(&state.relay_id, &payload.code, &pubkey).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct AccessRequestPayload {
+    #[serde(default)]
+    message: String,
+}
+
+/// Ask the admin for write access to the relay. The request is signed with
+/// NIP-98 by the pubkey asking, so nobody can file one for someone else.
+async fn access_request_handler(
+    State(state): State<Arc<RelayState>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    let invites = match &state.invites {
+        Some(invites) if state.config().policy.write.access_requests => invites,
+        _ => return (StatusCode::NOT_FOUND, "Access requests are not enabled").into_response(),
+    };
+    let peer = connect_info.map_or(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), |ConnectInfo(addr)| addr.ip());
+    let client_ip = state.ip_tracker.client_ip(peer, &headers);
+    if !state.ip_tracker.check_request_rate(client_ip, Some(ACCESS_REQUESTS_PER_MINUTE)) {
+        return (StatusCode::TOO_MANY_REQUESTS, "Too many access requests, try again later").into_response();
+    }
+    let urls: Vec<String> = std::iter::once(&state.relay_url)
+        .chain(&state.aliases)
+        .map(|url| format!("{}/access-request", url.trim_end_matches('/')))
+        .collect();
+    let pubkey = match crate::auth::verify_nip98_header(&headers, &urls, "POST", &body) {
+        Ok(event) => event.pubkey,
+        Err(e) => return (StatusCode::UNAUTHORIZED, e).into_response(),
+    };
+    let payload: AccessRequestPayload = match serde_json::from_slice(&body) {
+        Ok(payload) => payload,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid JSON: {}", e)).into_response(),
+    };
+    match invites.request_access(&state.relay_id, &pubkey, &payload.message).await {
+        Ok(()) => {
+            if let Some(notifier) = &state.notifier {
+                notifier.notify_throttled(
+                    &format!("access_requested:{}", state.relay_id),
+                    Alert::new(
+                        AlertKind::AccessRequested,
+                        format!("{} asked for write access to relay '{}'", pubkey.to_hex(), state.relay_id),
+                        serde_json::json!({ "relay_id": state.relay_id, "pubkey": pubkey.to_hex() }),
+                    ),
+                );
+            }
+            StatusCode::ACCEPTED.into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+/// A pubkey given as npub, nprofile or hex.
+fn parse_npub(input: &str) -> Result<PublicKey, String> {
+    PublicKey::parse(input).or_else(|e| {
+        Nip19Profile::from_bech32(input)
+            .map(|profile| profile.public_key)
+            .map_err(|_| format!("Invalid pubkey: {}", e))
+    })
+}
+
+/// Check an event's id and signature on the blocking pool, keeping the
+/// schnorr verification off the connection's task.
+pub(crate) async fn verify_event(event: &Event) -> Result<(), String> {
+    let event = event.clone();
+    match tokio::task::spawn_blocking(move || event.verify()).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(nostr::event::Error::InvalidId)) => Err("invalid: event id does not match its content".into()),
+        Ok(Err(_)) => Err("invalid: bad signature".into()),
+        Err(e) => Err(format!("error: could not verify event: {}", e)),
+    }
+}
+
 // --- WebSocket Handler ---
 
 struct ConnectionGuard {
     stats: Arc<RelayStats>,
     ip_tracker: Arc<IpTracker>,
     ip: IpAddr,
+    /// The connection's slot in the gateway-wide limit, if any.
+    _gateway_slot: Option<Arc<ConnectionSlot>>,
 }
 
 impl Drop for ConnectionGuard {
@@ -486,13 +1138,88 @@ impl Drop for ConnectionGuard {
     }
 }
 
-async fn send_msg(sender: &mut futures::stream::SplitSink<WebSocket, Message>, msg: String, stats: &RelayStats) {
-    stats.bytes_tx.fetch_add(msg.len() as u64, Relaxed);
-    let _ = sender.send(Message::Text(msg.into())).await;
+/// Default cap on bytes queued for a single connection before it is treated
+/// as a slow consumer and disconnected.
+const DEFAULT_MAX_OUTBOUND_BYTES: usize = 16 * 1024 * 1024;
+
+/// Per-connection outbound queue. Messages are handed to a dedicated writer
+/// task so a slow client never blocks the receive loop; if more than
+/// `max_bytes` pile up unsent, the connection is marked overflowed and closed.
+struct Outbound {
+    tx: mpsc::UnboundedSender<String>,
+    queued_bytes: Arc<AtomicUsize>,
+    max_bytes: usize,
+    overflowed: bool,
 }
 
-async fn handle_socket(socket: WebSocket, state: Arc<RelayState>, client_ip: IpAddr) {
-    let (mut sender, mut receiver) = socket.split();
+impl Outbound {
+    fn send(&mut self, msg: String) {
+        if self.overflowed {
+            return;
+        }
+        let len = msg.len();
+        if self.queued_bytes.fetch_add(len, Relaxed) + len > self.max_bytes || self.tx.send(msg).is_err() {
+            self.overflowed = true;
+        }
+    }
+}
+
+fn spawn_writer(
+    mut sender: futures::stream::SplitSink<WebSocket, Message>,
+    stats: Arc<RelayStats>,
+    connection: Arc<Connection>,
+    max_bytes: usize,
+) -> (Outbound, tokio::task::JoinHandle<()>) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let queued_bytes = Arc::new(AtomicUsize::new(0));
+
+    let queued = queued_bytes.clone();
+    let handle = tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            let len = msg.len();
+            let result = sender.send(Message::Text(msg)).await;
+            queued.fetch_sub(len, Relaxed);
+            if result.is_err() {
+                break;
+            }
+            stats.bytes_tx.fetch_add(len as u64, Relaxed);
+            connection.bytes_tx.fetch_add(len as u64, Relaxed);
+        }
+        let _ = sender.close().await;
+    });
+
+    let outbound = Outbound {
+        tx,
+        queued_bytes,
+        max_bytes,
+        overflowed: false,
+    };
+    (outbound, handle)
+}
+
+/// A connection's AUTH grants, revoked when it closes, however it closes.
+struct Granted {
+    grants: Option<Arc<AuthGrants>>,
+    ids: Vec<nostr::EventId>,
+}
+
+impl Drop for Granted {
+    fn drop(&mut self) {
+        if let Some(grants) = &self.grants {
+            for id in &self.ids {
+                grants.revoke(id);
+            }
+        }
+    }
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    state: Arc<RelayState>,
+    client_ip: IpAddr,
+    gateway_slot: Option<Arc<ConnectionSlot>>,
+) {
+    let (sender, mut receiver) = socket.split();
 
     let stats = &state.stats;
     stats.active_connections.fetch_add(1, Relaxed);
@@ -501,171 +1228,397 @@ async fn handle_socket(socket: WebSocket, state: Arc<RelayState>, client_ip: IpA
         stats: stats.clone(),
         ip_tracker: state.ip_tracker.clone(),
         ip: client_ip,
+        _gateway_slot: gateway_slot,
     };
+    let registration = state.connections.register(client_ip);
+    let connection = registration.connection.clone();
 
-    let nip11 = &state.config.nip11;
-    let rate_limit = &state.config.policy.rate_limit;
-
-    // NIP-11: max_message_length for raw text check
-    let max_message_length = nip11.max_message_length.unwrap_or(524288) as usize;
-
-    // NIP-11: subscription limits (per-connection)
-    let max_subscriptions = nip11.max_subscriptions.unwrap_or(20) as usize;
-    let max_subid_length = nip11.max_subid_length.unwrap_or(64) as usize;
-    let max_limit = nip11.max_limit;
-    let default_limit = nip11.default_limit;
+    let max_outbound_bytes = state
+        .config()
+        .policy
+        .rate_limit
+        .max_outbound_bytes
+        .unwrap_or(DEFAULT_MAX_OUTBOUND_BYTES);
+    let (mut outbound, writer) = spawn_writer(sender, stats.clone(), connection.clone(), max_outbound_bytes);
 
     // NIP-42: the authenticated pubkey for this connection (None until AUTH)
-    let authed_pubkey: Option<nostr::PublicKey> = None;
-
-    // Track active subscriptions for this connection
-    let mut active_subs: HashSet<String> = HashSet::new();
+    let mut authed_pubkey: Option<nostr::PublicKey> = None;
+    // The challenge is only sent once the client first hits an auth-required response
+    let auth_challenge = uuid::Uuid::new_v4().to_string();
+    let mut challenge_sent = false;
+    // AUTH events shared with a paired blossom server, revoked on disconnect
+    let mut granted = Granted {
+        grants: state.auth_grants.clone(),
+        ids: Vec::new(),
+    };
 
-    let mut broadcast_rx = state.tx.subscribe();
+    // This connection's subscriptions, registered in the relay-wide index
+    let mut live = state.subscriptions.connect();
 
     loop {
+        if outbound.overflowed {
+            stats.slow_clients_closed.fetch_add(1, Relaxed);
+            tracing::debug!(ip = %client_ip, "Closing slow client: outbound queue over {} bytes", max_outbound_bytes);
+            writer.abort();
+            return;
+        }
+        connection.subscriptions.store(live.len(), Relaxed);
+
         tokio::select! {
-            Some(msg) = receiver.next() => {
-                match msg {
-                    Ok(Message::Text(text)) => {
-                        stats.bytes_rx.fetch_add(text.len() as u64, Relaxed);
-
-                        // NIP-11: max_message_length check before parsing
-                        if text.len() > max_message_length {
-                            send_msg(&mut sender, RelayMessage::notice(
-                                format!("message too large ({} > {})", text.len(), max_message_length)
-                            ).as_json(), stats).await;
-                            continue;
-                        }
+            msg = receiver.next() => {
+                let Some(msg) = msg else {
+                    break;
+                };
+
+                // Config and policy are read per message, so a reload applies to
+                // open connections too
+                let config = state.config();
+                let policy = state.policy.load_full();
+                let nip11 = &config.nip11;
+                let rate_limit = &config.policy.rate_limit;
+
+                // NIP-11: max_message_length for raw text check
+                let max_message_length = nip11.max_message_length.unwrap_or(524288) as usize;
+
+                // NIP-11: subscription limits (per-connection)
+                let max_subscriptions = nip11.max_subscriptions.unwrap_or(20) as usize;
+                let max_subid_length = nip11.max_subid_length.unwrap_or(64) as usize;
+                let max_limit = nip11.max_limit;
+                let default_limit = nip11.default_limit;
 
-                        match ClientMessage::from_json(&text) {
-                            Ok(client_msg) => {
-                                match client_msg {
-                                    ClientMessage::Event(event) => {
-                                        // Per-IP write rate limit
-                                        if !state.ip_tracker.check_write_rate(client_ip, rate_limit.writes_per_minute) {
-                                            send_msg(&mut sender, RelayMessage::ok(event.id, false, "rate-limited: too many writes per minute").as_json(), stats).await;
+                // Per-REQ query budget: total events across all filters and wall time
+                let max_events_per_req = nip11.max_events_per_req.map(|v| v as usize);
+                let max_query_time = nip11.max_query_ms.map(std::time::Duration::from_millis);
+
+                if let Ok(Message::Text(text)) = msg {
+                    stats.bytes_rx.fetch_add(text.len() as u64, Relaxed);
+                    connection.bytes_rx.fetch_add(text.len() as u64, Relaxed);
+
+                    // NIP-11: max_message_length check before parsing
+                    if text.len() > max_message_length {
+                        outbound.send(RelayMessage::notice(
+                            format!("message too large ({} > {})", text.len(), max_message_length)
+                        ).as_json());
+                        continue;
+                    }
+
+                    match ClientMessage::from_json(&text) {
+                        Ok(client_msg) => {
+                            match client_msg {
+                                ClientMessage::Event(event) => {
+                                    let source = WriteSource::Ip(client_ip);
+                                    let reject = |outbound: &mut Outbound, reason: String| {
+                                        state.firehose.rejected(&event, &source, &reason);
+                                        outbound.send(RelayMessage::ok(event.id, false, reason).as_json());
+                                    };
+
+                                    if config.maintenance_mode.is_some() {
+                                        reject(&mut outbound, "blocked: relay in maintenance".into());
+                                        continue;
+                                    }
+
+                                    // Per-IP write rate limit
+                                    if !state.ip_tracker.check_write_rate(client_ip, rate_limit.writes_per_minute) {
+                                        reject(&mut outbound, "rate-limited: too many writes per minute".into());
+                                        continue;
+                                    }
+
+                                    // Nothing below trusts the event until its id and signature check out
+                                    if !config.skip_verify {
+                                        if let Err(reason) = verify_event(&event).await {
+                                            reject(&mut outbound, reason);
                                             continue;
                                         }
+                                    }
 
-                                        match state.policy.can_write(&event, authed_pubkey.as_ref()) {
-                                            PolicyResult::Allow => {
-                                                if let Err(e) = state.store.save_event(&event) {
+                                    // Per-author write rate limit
+                                    if !state.pubkey_tracker.check_write_rate(&event.pubkey, rate_limit.pubkey_writes_per_minute, rate_limit.pubkey_writes_per_day) {
+                                        reject(&mut outbound, "rate-limited: too many events from this pubkey".into());
+                                        continue;
+                                    }
+
+                                    // Zap receipts for the paywall grant access even if the
+                                    // receipt itself is then refused by the write policy
+                                    if event.kind.as_u16() == 9735 {
+                                        if let (Some(pm), Some(pw_id)) = (&state.paywall_manager, &state.paywall_id) {
+                                            pm.record_zap_receipt(pw_id, &event).await;
+                                        }
+                                    }
+
+                                    // Greylisting: new pubkeys write under stricter limits
+                                    if let Some(greylist) = &state.greylist {
+                                        if !greylist.has_seen(&event.pubkey) {
+                                            let filter = Filter::new().author(event.pubkey).limit(1);
+                                            let has_events = state
+                                                .with_store(StorageOp::Read, move |store| store.query(&filter))
+                                                .await
+                                                .is_ok_and(|events| !events.is_empty());
+                                            greylist.first_seen(&event.pubkey, has_events);
+                                        }
+                                        if let Err(reason) = greylist.check(&event) {
+                                            reject(&mut outbound, reason);
+                                            continue;
+                                        }
+                                    }
+
+                                    match policy.check_write(&event, authed_pubkey.as_ref(), source.clone()).await {
+                                        PolicyResult::Allow => {
+                                            let to_save = event.as_ref().clone();
+                                            let saved = state.with_store(StorageOp::Write, move |store| store.save_event(&to_save)).await;
+                                            match saved {
+                                                Err(e) => {
                                                     tracing::error!("Failed to save event: {}", e);
-                                                    send_msg(&mut sender, RelayMessage::ok(event.id, false, "error saving").as_json(), stats).await;
-                                                } else {
-                                                    stats.events_saved.fetch_add(1, Relaxed);
-                                                    send_msg(&mut sender, RelayMessage::ok(event.id, true, "").as_json(), stats).await;
-                                                    let _ = state.tx.send(event.as_ref().clone());
+                                                    reject(&mut outbound, "error saving".into());
+                                                }
+                                                Ok(SaveResult::Duplicate) => {
+                                                    outbound.send(RelayMessage::ok(event.id, true, "duplicate: already have this event").as_json());
+                                                }
+                                                Ok(SaveResult::RejectedStale) => {
+                                                    reject(&mut outbound, "replaced: have a newer version of this event".into());
+                                                }
+                                                Ok(SaveResult::Deleted) => {
+                                                    reject(&mut outbound, "blocked: this event was deleted".into());
+                                                }
+                                                Ok(SaveResult::Saved | SaveResult::Replaced) => {
+                                                    stats.event_saved(&event.pubkey);
+                                                    if let Some(greylist) = &state.greylist {
+                                                        greylist.record_accepted(&event.pubkey);
+                                                    }
+                                                    if let Some(mirror) = &state.mirror {
+                                                        if let Err(e) = mirror.enqueue(&event).await {
+                                                            tracing::error!("Failed to queue event for mirroring: {}", e);
+                                                        }
+                                                    }
+                                                    state.firehose.accepted(&event, &source);
+                                                    outbound.send(RelayMessage::ok(event.id, true, "").as_json());
+                                                    state.event_stored(&event);
                                                 }
                                             }
-                                            PolicyResult::Deny(reason) => {
-                                                stats.events_rejected.fetch_add(1, Relaxed);
-                                                send_msg(&mut sender, RelayMessage::ok(event.id, false, &format!("blocked: {}", reason)).as_json(), stats).await;
-                                            }
-                                            PolicyResult::AuthRequired => {
-                                                send_msg(&mut sender, RelayMessage::ok(event.id, false, "auth-required: NIP-42 authentication required").as_json(), stats).await;
-                                                // TODO: send AUTH challenge
+                                        }
+                                        PolicyResult::ShadowReject => {
+                                            stats.events_rejected.fetch_add(1, Relaxed);
+                                            state.firehose.rejected(&event, &source, "shadow-rejected");
+                                            outbound.send(RelayMessage::ok(event.id, true, "").as_json());
+                                        }
+                                        PolicyResult::Deny(reason) => {
+                                            stats.events_rejected.fetch_add(1, Relaxed);
+                                            reject(&mut outbound, format!("blocked: {}", reason));
+                                        }
+                                        PolicyResult::AuthRequired => {
+                                            reject(&mut outbound, "auth-required: NIP-42 authentication required".into());
+                                            if !challenge_sent {
+                                                outbound.send(RelayMessage::auth(auth_challenge.clone()).as_json());
+                                                challenge_sent = true;
                                             }
                                         }
                                     }
-                                    ClientMessage::Req { subscription_id, filters } => {
-                                        let sub_id_str = subscription_id.to_string();
-
-                                        // NIP-11: max_subid_length
-                                        if sub_id_str.len() > max_subid_length {
-                                            send_msg(&mut sender, RelayMessage::notice(
-                                                format!("subscription ID too long ({} > {})", sub_id_str.len(), max_subid_length)
-                                            ).as_json(), stats).await;
-                                            continue;
-                                        }
+                                }
+                                ClientMessage::Req { subscription_id, filters } => {
+                                    let filters = ReqFilter::from_req(&text, filters);
+                                    let sub_id_str = subscription_id.to_string();
+                                    let close = |outbound: &mut Outbound, reason: String| {
+                                        outbound.send(RelayMessage::closed(subscription_id.clone(), reason).as_json());
+                                    };
 
-                                        // NIP-11: max_subscriptions (only count genuinely new subs)
-                                        if !active_subs.contains(&sub_id_str) && active_subs.len() >= max_subscriptions {
-                                            send_msg(&mut sender, RelayMessage::notice(
-                                                format!("too many subscriptions ({} max)", max_subscriptions)
-                                            ).as_json(), stats).await;
-                                            continue;
-                                        }
+                                    // A REQ reusing an id replaces that subscription, so the
+                                    // old filters stop matching even if the new REQ is refused
+                                    live.unsubscribe(&subscription_id);
 
-                                        // Per-IP read rate limit
-                                        if !state.ip_tracker.check_read_rate(client_ip, rate_limit.reads_per_minute) {
-                                            send_msg(&mut sender, RelayMessage::notice("rate-limited: too many reads per minute").as_json(), stats).await;
-                                            continue;
-                                        }
+                                    if config.maintenance_mode == Some(MaintenanceMode::Paused) {
+                                        close(&mut outbound, "blocked: relay in maintenance".into());
+                                        continue;
+                                    }
 
-                                        // Check read policy on each filter
-                                        let mut blocked = false;
-                                        for filter in &filters {
-                                            match state.policy.can_read(filter, authed_pubkey.as_ref()) {
-                                                PolicyResult::Allow => {}
-                                                PolicyResult::Deny(reason) => {
-                                                    send_msg(&mut sender, RelayMessage::notice(format!("blocked: {}", reason)).as_json(), stats).await;
-                                                    blocked = true;
-                                                    break;
-                                                }
-                                                PolicyResult::AuthRequired => {
-                                                    send_msg(&mut sender, RelayMessage::notice("auth-required: NIP-42 authentication required").as_json(), stats).await;
-                                                    blocked = true;
-                                                    break;
+                                    // NIP-11: max_subid_length
+                                    if sub_id_str.len() > max_subid_length {
+                                        close(&mut outbound, format!("invalid: subscription ID too long ({} > {})", sub_id_str.len(), max_subid_length));
+                                        continue;
+                                    }
+
+                                    // NIP-11: max_subscriptions
+                                    if live.len() >= max_subscriptions {
+                                        close(&mut outbound, format!("rate-limited: too many subscriptions ({} max)", max_subscriptions));
+                                        continue;
+                                    }
+
+                                    // Per-IP read rate limit
+                                    if !state.ip_tracker.check_read_rate(client_ip, rate_limit.reads_per_minute) {
+                                        close(&mut outbound, "rate-limited: too many reads per minute".into());
+                                        continue;
+                                    }
+
+                                    // Check read policy on each filter
+                                    let mut blocked = false;
+                                    for filter in &filters {
+                                        match policy.can_read(filter, authed_pubkey.as_ref()) {
+                                            PolicyResult::Allow | PolicyResult::ShadowReject => {}
+                                            PolicyResult::Deny(reason) => {
+                                                close(&mut outbound, format!("blocked: {}", reason));
+                                                blocked = true;
+                                                break;
+                                            }
+                                            PolicyResult::AuthRequired => {
+                                                close(&mut outbound, "auth-required: NIP-42 authentication required".into());
+                                                if !challenge_sent {
+                                                    outbound.send(RelayMessage::auth(auth_challenge.clone()).as_json());
+                                                    challenge_sent = true;
                                                 }
+                                                blocked = true;
+                                                break;
                                             }
                                         }
+                                    }
 
-                                        if !blocked {
-                                            active_subs.insert(sub_id_str);
+                                    if !blocked {
+                                        live.subscribe(subscription_id.clone(), filters.clone());
 
-                                            for filter in filters {
-                                                // NIP-11: clamp filter limit
-                                                let mut clamped_filter = filter;
-                                                match clamped_filter.limit {
+                                        // NIP-11: clamp filter limits
+                                        let clamped_filters: Vec<ReqFilter> = filters
+                                            .into_iter()
+                                            .map(|mut f| {
+                                                match f.limit {
                                                     Some(l) => {
                                                         if let Some(max) = max_limit {
                                                             if l as u64 > max {
-                                                                clamped_filter.limit = Some(max as usize);
+                                                                f.filter.limit = Some(max as usize);
                                                             }
                                                         }
                                                     }
                                                     None => {
                                                         if let Some(def) = default_limit {
-                                                            clamped_filter.limit = Some(def as usize);
+                                                            f.filter.limit = Some(def as usize);
                                                         }
                                                     }
                                                 }
+                                                f
+                                            })
+                                            .collect();
 
-                                                match state.store.query(&clamped_filter) {
-                                                    Ok(events) => {
-                                                        stats.queries_served.fetch_add(1, Relaxed);
-                                                        for event in events {
-                                                            send_msg(&mut sender, RelayMessage::event(subscription_id.clone(), event).as_json(), stats).await;
+                                        let deadline = max_query_time.map(|d| std::time::Instant::now() + d);
+                                        let max_total = max_events_per_req.unwrap_or(usize::MAX);
+                                        let mut truncated = None;
+
+                                        let filter_count = clamped_filters.len() as u64;
+                                        let cache = state
+                                            .query_cache
+                                            .clone()
+                                            .map(|cache| (cache, QueryCache::key(&clamped_filters, max_total)));
+                                        let hit = cache.as_ref().and_then(|(cache, key)| cache.get(key));
+                                        if cache.is_some() {
+                                            let counter = if hit.is_some() { &stats.query_cache_hits } else { &stats.query_cache_misses };
+                                            counter.fetch_add(1, Relaxed);
+                                        }
+                                        let queried = match hit {
+                                            Some(result) => Ok(result.as_ref().clone()),
+                                            None => {
+                                                let generation = cache.as_ref().map_or(0, |(cache, _)| cache.generation());
+                                                state
+                                                    .with_store(StorageOp::Read, move |store| {
+                                                        let result = store.query_multi(&clamped_filters, max_total, deadline)?;
+                                                        if let Some((cache, key)) = cache {
+                                                            cache.insert(key, clamped_filters, result.clone(), generation);
                                                         }
+                                                        Ok(result)
+                                                    })
+                                                    .await
+                                            }
+                                        };
+                                        match queried {
+                                            Ok(result) => {
+                                                stats.queries_served.fetch_add(filter_count, Relaxed);
+                                                if let Some(reader) = &authed_pubkey {
+                                                    stats.reader_served(reader);
+                                                }
+                                                for event in result.events {
+                                                    if !policy.can_deliver(&event, authed_pubkey.as_ref()) {
+                                                        continue;
                                                     }
-                                                    Err(e) => {
-                                                        tracing::error!("Query failed: {}", e);
-                                                        send_msg(&mut sender, RelayMessage::notice(format!("error: {}", e)).as_json(), stats).await;
-                                                    }
+                                                    outbound.send(RelayMessage::event(subscription_id.clone(), event).as_json());
+                                                }
+                                                if result.timed_out {
+                                                    truncated = Some("query took too long");
+                                                } else if result.capped {
+                                                    truncated = Some("result cap reached");
                                                 }
                                             }
-                                            send_msg(&mut sender, RelayMessage::eose(subscription_id).as_json(), stats).await;
+                                            Err(e) => {
+                                                tracing::error!("Query failed: {}", e);
+                                                live.unsubscribe(&subscription_id);
+                                                close(&mut outbound, format!("error: {}", e));
+                                                continue;
+                                            }
                                         }
+                                        if let Some(reason) = truncated {
+                                            outbound.send(RelayMessage::notice(format!(
+                                                "results truncated for {}: {}", subscription_id, reason
+                                            )).as_json());
+                                        }
+                                        outbound.send(RelayMessage::eose(subscription_id.clone()).as_json());
                                     }
-                                    ClientMessage::Close(sub_id) => {
-                                        active_subs.remove(&sub_id.to_string());
+                                }
+                                ClientMessage::Close(sub_id) => {
+                                    live.unsubscribe(&sub_id);
+                                }
+                                ClientMessage::Auth(event) => {
+                                    let urls: Vec<&str> = std::iter::once(&state.relay_url).chain(&state.aliases).map(String::as_str).collect();
+                                    let verified = crate::auth::verify_relay_auth(&event, &auth_challenge, &urls).and_then(|pk| {
+                                        if state.auth_replay.mark_used(&event, crate::auth::RELAY_AUTH_WINDOW_SECS) {
+                                            Ok(pk)
+                                        } else {
+                                            Err("auth event already used".to_string())
+                                        }
+                                    });
+                                    match verified {
+                                        Ok(pk) => {
+                                            authed_pubkey = Some(pk);
+                                            connection.authenticated(pk);
+                                            if let Some(grants) = &granted.grants {
+                                                grants.grant(&event, &state.relay_id);
+                                                granted.ids.push(event.id);
+                                            }
+                                            outbound.send(RelayMessage::ok(event.id, true, "").as_json());
+                                        }
+                                        Err(reason) => {
+                                            outbound.send(RelayMessage::ok(event.id, false, format!("invalid: {}", reason)).as_json());
+                                        }
                                     }
-                                    _ => {}
                                 }
+                                _ => {}
                             }
-                            Err(e) => {
-                                tracing::warn!("Invalid Nostr message: {}", e);
-                            }
+                        }
+                        Err(e) => {
+                            tracing::warn!("Invalid Nostr message: {}", e);
                         }
                     }
-                    _ => {} // binary or other
                 }
             }
-            Ok(_event) = broadcast_rx.recv() => {
-                // TODO: Matching logic
+            Some(live_event) = live.recv() => {
+                let missed = live.take_missed();
+                if missed > 0 {
+                    stats.broadcast_dropped.fetch_add(missed, Relaxed);
+                    outbound.send(RelayMessage::notice(format!(
+                        "warning: {} live events were dropped because this connection fell behind", missed
+                    )).as_json());
+                }
+                if state.config().maintenance_mode == Some(MaintenanceMode::Paused) {
+                    continue;
+                }
+                if !state.policy.load().can_deliver(&live_event.event, authed_pubkey.as_ref()) {
+                    continue;
+                }
+                for sub_id in live_event.subscriptions {
+                    outbound.send(RelayMessage::event(sub_id, live_event.event.as_ref().clone()).as_json());
+                }
+            }
+            _ = connection.kicked() => {
+                tracing::info!(ip = %client_ip, relay = %state.relay_id, "Connection {} closed by the admin", connection.id);
+                outbound.send(RelayMessage::notice("closed by the relay operator").as_json());
+                break;
             }
         }
     }
+
+    // Let the writer flush anything still queued before the socket closes
+    drop(outbound);
+    let _ = writer.await;
 }
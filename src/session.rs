@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+const SESSION_TTL_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub pubkey: String,
+    pub created_at: u64,
+}
+
+impl SessionInfo {
+    pub fn is_expired(&self) -> bool {
+        now().saturating_sub(self.created_at) > SESSION_TTL_SECS
+    }
+}
+
+/// Admin login sessions, persisted to disk so they survive restarts.
+pub struct SessionStore {
+    sessions: RwLock<HashMap<String, SessionInfo>>,
+    path: Option<PathBuf>,
+}
+
+impl SessionStore {
+    /// Load sessions from `path`, dropping any that have expired.
+    pub async fn load(path: PathBuf) -> Self {
+        let sessions = match tokio::fs::read(&path).await {
+            Ok(data) => serde_json::from_slice::<HashMap<String, SessionInfo>>(&data)
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Failed to parse sessions file {}: {}", path.display(), e);
+                    HashMap::new()
+                })
+                .into_iter()
+                .filter(|(_, s)| !s.is_expired())
+                .collect(),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            sessions: RwLock::new(sessions),
+            path: Some(path),
+        }
+    }
+
+    /// A session store that is never written to disk.
+    pub fn in_memory() -> Self {
+        Self {
+            sessions: RwLock::new(HashMap::new()),
+            path: None,
+        }
+    }
+
+    /// Look up a session, removing it if it has expired.
+    pub async fn get(&self, token: &str) -> Option<SessionInfo> {
+        let session = self.sessions.read().await.get(token).cloned()?;
+        if session.is_expired() {
+            self.remove(token).await;
+            return None;
+        }
+        Some(session)
+    }
+
+    pub async fn insert(&self, token: String, session: SessionInfo) {
+        let mut sessions = self.sessions.write().await;
+        sessions.retain(|_, s| !s.is_expired());
+        sessions.insert(token, session);
+        self.persist(&sessions).await;
+    }
+
+    pub async fn remove(&self, token: &str) {
+        let mut sessions = self.sessions.write().await;
+        if sessions.remove(token).is_some() {
+            self.persist(&sessions).await;
+        }
+    }
+
+    async fn persist(&self, sessions: &HashMap<String, SessionInfo>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = tokio::fs::create_dir_all(parent).await;
+        }
+        if let Err(e) = write_private(path, sessions).await {
+            tracing::warn!("Failed to save sessions to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Write `sessions` readable only by the owner, since the tokens in it log
+/// in as the admin.
+async fn write_private(path: &std::path::Path, sessions: &HashMap<String, SessionInfo>) -> Result<(), anyhow::Error> {
+    use tokio::io::AsyncWriteExt;
+    let data = serde_json::to_vec(sessions)?;
+    let mut options = tokio::fs::OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+    }
+    file.write_all(&data).await?;
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sessions_survive_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sessions.json");
+
+        let store = SessionStore::load(path.clone()).await;
+        store
+            .insert("live".into(), SessionInfo { pubkey: "a".into(), created_at: now() })
+            .await;
+        store
+            .insert("old".into(), SessionInfo { pubkey: "b".into(), created_at: now() - SESSION_TTL_SECS - 1 })
+            .await;
+
+        let reloaded = SessionStore::load(path.clone()).await;
+        assert_eq!(reloaded.get("live").await.unwrap().pubkey, "a");
+        assert!(reloaded.get("old").await.is_none());
+
+        reloaded.remove("live").await;
+        let reloaded = SessionStore::load(path.clone()).await;
+        assert!(reloaded.get("live").await.is_none());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn sessions_from_the_future_are_not_expired() {
+        let session = SessionInfo { pubkey: "a".into(), created_at: now() + 3600 };
+        assert!(!session.is_expired());
+    }
+}
@@ -1,10 +1,16 @@
+use nostr::PublicKey;
 use serde::Serialize;
+use std::collections::HashSet;
 use std::sync::atomic::{AtomicI64, AtomicU64, Ordering::Relaxed};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 
+use crate::config::AlertConfig;
+use crate::daily_stats::{Counters, DailyStatsStore};
 use crate::storage::NostrStore;
+use crate::webhook::{disk_near_full, rejected_spike, Alert, AlertKind, Latch, Notifier};
+use std::collections::HashMap;
 
 // ---------------------------------------------------------------------------
 // Per-relay atomic counters (lock-free hot path)
@@ -15,11 +21,42 @@ pub struct RelayStats {
     pub total_connections: AtomicU64,
     pub events_saved: AtomicU64,
     pub events_rejected: AtomicU64,
+    /// Rejected events that tripped a content filter (blocked words or
+    /// patterns, emoji or script heuristics); also counted in `events_rejected`.
+    pub spam_rejected: AtomicU64,
     pub queries_served: AtomicU64,
     pub bytes_rx: AtomicU64,
     pub bytes_tx: AtomicU64,
     pub event_count: AtomicU64,
     pub storage_bytes: AtomicU64,
+    /// Unix timestamp of the most recently accepted event (0 = none yet).
+    pub last_event_at: AtomicU64,
+    /// Connections closed because their outbound queue grew too large.
+    pub slow_clients_closed: AtomicU64,
+    /// Live events a lagging connection missed because the broadcast buffer
+    /// overflowed, summed over all connections.
+    pub broadcast_dropped: AtomicU64,
+    /// Storage queries run from client connections, and their summed latency.
+    pub storage_reads: AtomicU64,
+    pub storage_read_micros: AtomicU64,
+    /// Storage writes run from client connections, and their summed latency.
+    pub storage_writes: AtomicU64,
+    pub storage_write_micros: AtomicU64,
+    /// REQs answered from the query cache, and those that had to query.
+    pub query_cache_hits: AtomicU64,
+    pub query_cache_misses: AtomicU64,
+    /// Authors of events saved since the daily stats were last flushed.
+    authors: Mutex<HashSet<PublicKey>>,
+    /// Authenticated pubkeys served a REQ since then.
+    readers: Mutex<HashSet<PublicKey>>,
+    /// Unix timestamp at which this relay was started.
+    pub started_at: u64,
+}
+
+impl Default for RelayStats {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RelayStats {
@@ -29,13 +66,52 @@ impl RelayStats {
             total_connections: AtomicU64::new(0),
             events_saved: AtomicU64::new(0),
             events_rejected: AtomicU64::new(0),
+            spam_rejected: AtomicU64::new(0),
             queries_served: AtomicU64::new(0),
             bytes_rx: AtomicU64::new(0),
             bytes_tx: AtomicU64::new(0),
             event_count: AtomicU64::new(0),
             storage_bytes: AtomicU64::new(0),
+            last_event_at: AtomicU64::new(0),
+            slow_clients_closed: AtomicU64::new(0),
+            broadcast_dropped: AtomicU64::new(0),
+            storage_reads: AtomicU64::new(0),
+            storage_read_micros: AtomicU64::new(0),
+            storage_writes: AtomicU64::new(0),
+            storage_write_micros: AtomicU64::new(0),
+            query_cache_hits: AtomicU64::new(0),
+            query_cache_misses: AtomicU64::new(0),
+            authors: Mutex::new(HashSet::new()),
+            readers: Mutex::new(HashSet::new()),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
         }
     }
+
+    /// Count a saved event: its author goes into the daily unique writers.
+    pub fn event_saved(&self, author: &PublicKey) {
+        self.events_saved.fetch_add(1, Relaxed);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        self.last_event_at.store(now, Relaxed);
+        self.authors.lock().unwrap().insert(*author);
+    }
+
+    /// Authors recorded since the last call.
+    pub fn take_authors(&self) -> HashSet<PublicKey> {
+        std::mem::take(&mut *self.authors.lock().unwrap())
+    }
+
+    /// Note an authenticated pubkey reading, for the daily unique readers.
+    pub fn reader_served(&self, reader: &PublicKey) {
+        self.readers.lock().unwrap().insert(*reader);
+    }
+
+    /// Readers recorded since the last call.
+    pub fn take_readers(&self) -> HashSet<PublicKey> {
+        std::mem::take(&mut *self.readers.lock().unwrap())
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -64,6 +140,12 @@ pub struct TimeSeriesRing {
     len: usize,
 }
 
+impl Default for TimeSeriesRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TimeSeriesRing {
     pub fn new() -> Self {
         Self {
@@ -95,6 +177,69 @@ impl TimeSeriesRing {
             result
         }
     }
+
+    /// Per-second rates derived from consecutive buckets, for buckets taken
+    /// at or after `since` (unix seconds). The oldest bucket in the ring only
+    /// serves as a baseline and never yields a point of its own.
+    pub fn rates_since(&self, since: u64) -> Vec<TimeSeriesPoint> {
+        let entries = self.entries();
+        entries
+            .windows(2)
+            .filter(|w| w[1].timestamp >= since)
+            .map(|w| {
+                let (prev, cur) = (&w[0], &w[1]);
+                let secs = cur.timestamp.saturating_sub(prev.timestamp).max(1) as f64;
+                let rate = |a: u64, b: u64| b.saturating_sub(a) as f64 / secs;
+                TimeSeriesPoint {
+                    timestamp: cur.timestamp,
+                    connections: cur.active_connections,
+                    events_per_sec: rate(prev.events_saved, cur.events_saved),
+                    rejected_per_sec: rate(prev.events_rejected, cur.events_rejected),
+                    queries_per_sec: rate(prev.queries_served, cur.queries_served),
+                    bytes_rx_per_sec: rate(prev.bytes_rx, cur.bytes_rx),
+                    bytes_tx_per_sec: rate(prev.bytes_tx, cur.bytes_tx),
+                }
+            })
+            .collect()
+    }
+}
+
+/// One chartable sample: connection count at the sample time and activity
+/// rates averaged over the preceding interval.
+#[derive(Clone, Serialize)]
+pub struct TimeSeriesPoint {
+    pub timestamp: u64,
+    pub connections: i64,
+    pub events_per_sec: f64,
+    pub rejected_per_sec: f64,
+    pub queries_per_sec: f64,
+    pub bytes_rx_per_sec: f64,
+    pub bytes_tx_per_sec: f64,
+}
+
+/// Seconds between samples written by `stats_background_loop`.
+pub const SAMPLE_INTERVAL_SECS: u64 = 60;
+
+/// Longest range the ring can answer.
+pub const MAX_RANGE_SECS: u64 = RING_CAPACITY as u64 * SAMPLE_INTERVAL_SECS;
+
+/// Parse a history range such as `"30m"`, `"6h"` or `"1d"` into seconds.
+/// Ranges longer than the ring holds are clamped to `MAX_RANGE_SECS`.
+pub fn parse_range(range: &str) -> Option<u64> {
+    let range = range.trim();
+    let (num, unit_secs) = if let Some(num) = range.strip_suffix('m') {
+        (num, 60)
+    } else if let Some(num) = range.strip_suffix('h') {
+        (num, 3600)
+    } else {
+        (range.strip_suffix('d')?, 86400)
+    };
+    let n: u64 = num.parse().ok()?;
+    let secs = n.checked_mul(unit_secs)?;
+    if secs == 0 {
+        return None;
+    }
+    Some(secs.min(MAX_RANGE_SECS))
 }
 
 fn snapshot(stats: &RelayStats) -> TimeBucket {
@@ -135,27 +280,60 @@ pub type SharedSystemStats = Arc<RwLock<SystemStats>>;
 // Background task — runs every 60s
 // ---------------------------------------------------------------------------
 
+#[allow(clippy::type_complexity)]
 pub async fn stats_background_loop(
     relay_stats: Vec<(String, Arc<RelayStats>, Arc<RwLock<TimeSeriesRing>>, Arc<dyn NostrStore>, String)>,
     system_stats: SharedSystemStats,
+    daily_stats: Option<Arc<DailyStatsStore>>,
+    notifier: Arc<Notifier>,
+    alerts: AlertConfig,
 ) {
     use sysinfo::{Disks, System};
 
+    let mut counters: HashMap<String, Counters> = HashMap::new();
+    // Saved and rejected counts at the last sample, for the spike check
+    let mut writes: HashMap<String, (u64, u64)> = HashMap::new();
+    let mut spiking: HashMap<String, Latch> = HashMap::new();
+    let mut disk_full = Latch::default();
+
     let mut sys = System::new();
     let disks = Disks::new_with_refreshed_list();
 
-    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(SAMPLE_INTERVAL_SECS));
     interval.tick().await; // first tick is immediate — skip it
 
     loop {
         interval.tick().await;
 
         // Update per-relay stats
-        for (_, stats, ring, store, db_path) in &relay_stats {
+        for (relay_id, stats, ring, store, db_path) in &relay_stats {
             // Snapshot into ring buffer
             let bucket = snapshot(stats);
+            let now = bucket.timestamp;
+            let (saved, rejected) = (bucket.events_saved, bucket.events_rejected);
             ring.write().await.push(bucket);
 
+            let (saved_before, rejected_before) = writes.insert(relay_id.clone(), (saved, rejected)).unwrap_or_default();
+            let (saved, rejected) = (saved.saturating_sub(saved_before), rejected.saturating_sub(rejected_before));
+            if spiking.entry(relay_id.clone()).or_default().update(rejected_spike(&alerts, saved, rejected)) {
+                notifier.notify(Alert::new(
+                    AlertKind::RejectedSpike,
+                    format!("Relay '{}' rejected {} of {} events in the last minute", relay_id, rejected, saved + rejected),
+                    serde_json::json!({ "relay_id": relay_id, "saved": saved, "rejected": rejected }),
+                ));
+            }
+
+            // Add the minute's activity to today's persisted totals
+            if let Some(daily) = &daily_stats {
+                let previous = counters.get(relay_id).copied().unwrap_or_default();
+                match daily.flush(relay_id, stats, previous, now) {
+                    Ok(current) => {
+                        counters.insert(relay_id.clone(), current);
+                    }
+                    Err(e) => tracing::warn!(relay = %relay_id, "Failed to save daily stats: {}", e),
+                }
+            }
+
             // Update event count from DB metadata
             if let Ok(count) = store.event_count() {
                 stats.event_count.store(count, Relaxed);
@@ -182,6 +360,14 @@ pub async fn stats_background_loop(
             disk_used += disk.total_space() - disk.available_space();
         }
 
+        if disk_full.update(disk_near_full(&alerts, disk_used, disk_total)) {
+            notifier.notify(Alert::new(
+                AlertKind::DiskNearFull,
+                format!("Disk usage is at {}%", disk_used * 100 / disk_total.max(1)),
+                serde_json::json!({ "used_bytes": disk_used, "total_bytes": disk_total }),
+            ));
+        }
+
         let mut ss = system_stats.write().await;
         ss.cpu_usage_percent = cpu;
         ss.memory_used_bytes = mem_used;
@@ -190,3 +376,84 @@ pub async fn stats_background_loop(
         ss.disk_total_bytes = disk_total;
     }
 }
+
+/// How often the read watchdog looks at open read transactions.
+const READ_WATCHDOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Report read transactions open longer than `max` on every relay store and
+/// stop the scans among them.
+pub fn start_read_watchdog(stores: Vec<Arc<dyn NostrStore>>, max: std::time::Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(READ_WATCHDOG_INTERVAL);
+        loop {
+            interval.tick().await;
+            for store in &stores {
+                store.stop_slow_reads(max);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(timestamp: u64, events_saved: u64, bytes_tx: u64, active_connections: i64) -> TimeBucket {
+        TimeBucket {
+            timestamp,
+            active_connections,
+            total_connections: 0,
+            events_saved,
+            events_rejected: 0,
+            queries_served: 0,
+            bytes_rx: 0,
+            bytes_tx,
+            event_count: 0,
+            storage_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn rates_are_derived_from_consecutive_samples() {
+        let mut ring = TimeSeriesRing::new();
+        ring.push(bucket(1000, 0, 0, 1));
+        ring.push(bucket(1060, 120, 6000, 3));
+        ring.push(bucket(1120, 180, 6000, 2));
+
+        let points = ring.rates_since(0);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].timestamp, 1060);
+        assert_eq!(points[0].events_per_sec, 2.0);
+        assert_eq!(points[0].bytes_tx_per_sec, 100.0);
+        assert_eq!(points[0].connections, 3);
+        assert_eq!(points[1].events_per_sec, 1.0);
+        assert_eq!(points[1].bytes_tx_per_sec, 0.0);
+
+        assert_eq!(ring.rates_since(1100).len(), 1);
+    }
+
+    #[test]
+    fn saving_an_event_records_when() {
+        let stats = RelayStats::new();
+        assert_eq!(stats.last_event_at.load(Relaxed), 0);
+        stats.event_saved(&nostr::Keys::generate().public_key());
+        assert_eq!(stats.events_saved.load(Relaxed), 1);
+        assert!(stats.last_event_at.load(Relaxed) > 0);
+    }
+
+    #[test]
+    fn parse_range_accepts_units_and_clamps() {
+        assert_eq!(parse_range("30m"), Some(1800));
+        assert_eq!(parse_range("6h"), Some(6 * 3600));
+        assert_eq!(parse_range("24h"), Some(MAX_RANGE_SECS));
+        assert_eq!(parse_range("7d"), Some(MAX_RANGE_SECS));
+        assert_eq!(parse_range("0h"), None);
+        assert_eq!(parse_range("h"), None);
+        assert_eq!(parse_range("12"), None);
+        assert_eq!(parse_range(""), None);
+        assert_eq!(parse_range("1é"), None);
+        assert_eq!(parse_range("é"), None);
+        assert_eq!(parse_range(&format!("{}d", u64::MAX)), None);
+        assert_eq!(parse_range(&format!("{}h", u64::MAX / 3600 + 1)), None);
+    }
+}
@@ -0,0 +1,181 @@
+//! Paging through a relay's events for the admin event browser. Pages are
+//! newest first and resume from a cursor naming the last event looked at,
+//! so events stored meanwhile don't shift later pages. Free-text search
+//! isn't indexed; it is matched against content while scanning.
+
+use super::NostrStore;
+use crate::error::Result;
+use nostr::{Event, EventId, Filter, Timestamp};
+use std::cmp::Reverse;
+use std::time::{Duration, Instant};
+
+/// Events fetched from the store per scan step.
+const SCAN_BATCH: usize = 500;
+/// A search stops after this long and hands back a cursor to resume from.
+const SCAN_BUDGET: Duration = Duration::from_secs(2);
+
+/// Where a page ended: the created_at and id of the last event scanned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventCursor {
+    created_at: Timestamp,
+    id: EventId,
+}
+
+impl EventCursor {
+    pub fn parse(cursor: &str) -> Option<Self> {
+        let (created_at, id) = cursor.split_once(':')?;
+        Some(Self {
+            created_at: Timestamp::from(created_at.parse::<u64>().ok()?),
+            id: EventId::from_hex(id).ok()?,
+        })
+    }
+
+    fn of(event: &Event) -> Self {
+        Self {
+            created_at: event.created_at,
+            id: event.id,
+        }
+    }
+
+    /// Position in page order: newest first, then by id.
+    fn key(&self) -> (Reverse<Timestamp>, EventId) {
+        (Reverse(self.created_at), self.id)
+    }
+}
+
+impl std::fmt::Display for EventCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.created_at.as_u64(), self.id.to_hex())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventPage {
+    pub events: Vec<Event>,
+    /// Where the next page starts, if there may be more.
+    pub next_cursor: Option<EventCursor>,
+}
+
+/// Up to `limit` events matching `filter` (whose own limit is ignored) and
+/// containing `search` (case-insensitively), after `cursor`.
+pub fn browse(
+    store: &dyn NostrStore,
+    filter: &Filter,
+    search: Option<&str>,
+    cursor: Option<EventCursor>,
+    limit: usize,
+) -> Result<EventPage> {
+    let search = search.map(str::to_lowercase);
+    let limit = limit.max(1);
+    let started = Instant::now();
+
+    let batch_size = store.max_limit().map_or(SCAN_BATCH, |max| SCAN_BATCH.min(max));
+
+    let mut events = Vec::new();
+    let mut position = cursor;
+    loop {
+        let mut batch_filter = filter.clone();
+        batch_filter.limit = Some(batch_size);
+        if let Some(position) = position {
+            batch_filter.until = Some(position.created_at);
+        }
+        let mut batch = store.query(&batch_filter)?;
+        let exhausted = batch.len() < batch_size;
+        batch.sort_unstable_by_key(|e| EventCursor::of(e).key());
+
+        let mut progressed = false;
+        for event in batch {
+            let at = EventCursor::of(&event);
+            if position.is_some_and(|p| at.key() <= p.key()) {
+                continue;
+            }
+            progressed = true;
+            position = Some(at);
+            if search.as_ref().is_none_or(|s| event.content.to_lowercase().contains(s)) {
+                events.push(event);
+                if events.len() == limit {
+                    return Ok(EventPage {
+                        events,
+                        next_cursor: position,
+                    });
+                }
+            }
+        }
+
+        if exhausted {
+            return Ok(EventPage {
+                events,
+                next_cursor: None,
+            });
+        }
+        if !progressed {
+            // A whole batch shares one timestamp and was already seen; step
+            // past it rather than fetch the same batch again
+            match position {
+                Some(p) if p.created_at.as_u64() > 0 => {
+                    position = Some(EventCursor {
+                        created_at: Timestamp::from(p.created_at.as_u64() - 1),
+                        id: EventId::all_zeros(),
+                    });
+                }
+                _ => {
+                    return Ok(EventPage {
+                        events,
+                        next_cursor: None,
+                    })
+                }
+            }
+        }
+        if started.elapsed() >= SCAN_BUDGET {
+            return Ok(EventPage {
+                events,
+                next_cursor: position,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::lmdb::LmdbStore;
+    use nostr::{EventBuilder, Keys, Kind};
+
+    #[test]
+    fn pages_resume_from_the_cursor_and_search_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        for i in 0..7u64 {
+            let content = if i % 2 == 0 { format!("GM number {}", i) } else { format!("note {}", i) };
+            let event = EventBuilder::text_note(content, [])
+                // Two events per second, so pages split within a timestamp
+                .custom_created_at(Timestamp::from(1000 + i / 2))
+                .to_event(&keys)
+                .unwrap();
+            store.save_event(&event).unwrap();
+        }
+        let notes = Filter::new().kind(Kind::TextNote);
+
+        let mut seen = std::collections::HashSet::new();
+        let mut cursor = None;
+        loop {
+            let page = browse(&store, &notes, None, cursor, 3).unwrap();
+            assert!(page.events.len() <= 3);
+            for event in &page.events {
+                assert!(seen.insert(event.id));
+            }
+            match page.next_cursor {
+                Some(next) => cursor = Some(EventCursor::parse(&next.to_string()).unwrap()),
+                None => break,
+            }
+        }
+        assert_eq!(seen.len(), 7);
+
+        let gm = browse(&store, &notes, Some("gm"), None, 10).unwrap();
+        assert_eq!(gm.events.len(), 4);
+        assert!(gm.events.windows(2).all(|w| w[0].created_at >= w[1].created_at));
+        assert!(gm.next_cursor.is_none());
+        assert!(EventCursor::parse("12:nope").is_none());
+    }
+}
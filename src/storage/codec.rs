@@ -0,0 +1,178 @@
+//! Compact binary encoding of events for the LMDB primary store. Decoding
+//! skips JSON parsing and hex decoding entirely, which dominates the cost
+//! of reading events from an index hit.
+//!
+//! Layout: a version byte, then id(32), pubkey(32), sig(64), created_at
+//! (BE 8), kind (BE 2), the content, and the tags. Strings are a varint
+//! length followed by UTF-8 bytes; the tag list and each tag are prefixed
+//! by their varint length.
+//!
+//! Values written as JSON by earlier versions start with `{` and are still
+//! read, so stores can be converted in place.
+
+use crate::error::{Error, Result};
+use dashmap::DashMap;
+use nostr::secp256k1::schnorr::Signature;
+use nostr::{Event, EventId, Kind, PublicKey, Tag, Timestamp};
+use once_cell::sync::Lazy;
+
+const VERSION: u8 = 1;
+
+/// Parsed authors, cleared when it outgrows this. Validating a public key
+/// is most of the cost of decoding an event, and authors repeat a lot.
+const PUBKEY_CACHE_SIZE: usize = 65_536;
+
+static PUBKEYS: Lazy<DashMap<[u8; 32], PublicKey>> = Lazy::new(DashMap::new);
+
+fn parse_pubkey(bytes: &[u8]) -> Result<PublicKey> {
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| corrupt("bad public key length".to_string()))?;
+    if let Some(pubkey) = PUBKEYS.get(&bytes) {
+        return Ok(*pubkey);
+    }
+    let pubkey = PublicKey::from_slice(&bytes).map_err(|e| corrupt(e.to_string()))?;
+    if PUBKEYS.len() >= PUBKEY_CACHE_SIZE {
+        PUBKEYS.clear();
+    }
+    PUBKEYS.insert(bytes, pubkey);
+    Ok(pubkey)
+}
+
+/// Whether `raw` is a legacy JSON value rather than a binary one.
+pub fn is_json(raw: &[u8]) -> bool {
+    raw.first() == Some(&b'{')
+}
+
+pub fn encode_event(event: &Event) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(1 + 32 + 32 + 64 + 8 + 2 + 4 + event.content.len() + event.tags.len() * 72);
+    buf.push(VERSION);
+    buf.extend_from_slice(event.id.as_bytes());
+    buf.extend_from_slice(&event.pubkey.to_bytes());
+    buf.extend_from_slice(event.sig.as_ref());
+    buf.extend_from_slice(&event.created_at.as_u64().to_be_bytes());
+    buf.extend_from_slice(&event.kind.as_u16().to_be_bytes());
+    put_str(&mut buf, &event.content);
+    put_varint(&mut buf, event.tags.len() as u64);
+    for tag in event.tags.iter() {
+        let fields = tag.as_vec();
+        put_varint(&mut buf, fields.len() as u64);
+        for field in fields {
+            put_str(&mut buf, field);
+        }
+    }
+    buf
+}
+
+/// Decode a stored event, binary or legacy JSON.
+pub fn decode_event(raw: &[u8]) -> Result<Event> {
+    if is_json(raw) {
+        return Ok(serde_json::from_slice(raw)?);
+    }
+    let mut r = Reader { buf: raw, pos: 0 };
+    let version = r.bytes(1)?[0];
+    if version != VERSION {
+        return Err(corrupt(format!("unknown encoding version {}", version)));
+    }
+    let id = EventId::from_slice(r.bytes(32)?).map_err(|e| corrupt(e.to_string()))?;
+    let pubkey = parse_pubkey(r.bytes(32)?)?;
+    let sig = Signature::from_slice(r.bytes(64)?).map_err(|e| corrupt(e.to_string()))?;
+    let created_at = Timestamp::from(u64::from_be_bytes(r.bytes(8)?.try_into().unwrap()));
+    let kind = Kind::from(u16::from_be_bytes(r.bytes(2)?.try_into().unwrap()));
+    let content = r.str()?;
+
+    let tag_count = r.varint()? as usize;
+    let mut tags = Vec::with_capacity(tag_count.min(r.remaining()));
+    let mut fields = Vec::new();
+    for _ in 0..tag_count {
+        let field_count = r.varint()? as usize;
+        fields.clear();
+        for _ in 0..field_count {
+            fields.push(r.str()?);
+        }
+        tags.push(Tag::parse(&fields).map_err(|e| corrupt(e.to_string()))?);
+    }
+    if r.remaining() != 0 {
+        return Err(corrupt("trailing bytes".to_string()));
+    }
+    Ok(Event::new(id, pubkey, created_at, kind, tags, content, sig))
+}
+
+fn corrupt(reason: String) -> Error {
+    Error::CorruptEvent(reason)
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_varint(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(corrupt("truncated value".to_string()));
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.bytes(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(corrupt("varint too long".to_string()))
+    }
+
+    fn str(&mut self) -> Result<&'a str> {
+        let len = self.varint()? as usize;
+        std::str::from_utf8(self.bytes(len)?).map_err(|e| corrupt(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, JsonUtil, Keys};
+
+    #[test]
+    fn round_trips_and_reads_legacy_json() {
+        let keys = Keys::generate();
+        let event = EventBuilder::text_note(
+            "héllo ✨",
+            [Tag::hashtag("moar"), Tag::parse(&["e", &"a".repeat(64), "", "root"]).unwrap()],
+        )
+        .to_event(&keys)
+        .unwrap();
+
+        let encoded = encode_event(&event);
+        assert!(!is_json(&encoded));
+        assert!(encoded.len() < event.as_json().len());
+        let decoded = decode_event(&encoded).unwrap();
+        assert_eq!(decoded, event);
+        assert!(decoded.verify().is_ok());
+
+        assert_eq!(decode_event(event.as_json().as_bytes()).unwrap(), event);
+        assert!(decode_event(&encoded[..encoded.len() - 1]).is_err());
+    }
+}
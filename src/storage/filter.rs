@@ -0,0 +1,149 @@
+//! REQ filters. nostr's `Filter` only keeps tag conditions named by a single
+//! letter and silently drops the rest (`#proxy`, `#client`), which would
+//! widen a query instead of narrowing it, so [`ReqFilter`] keeps them
+//! alongside.
+
+use nostr::{Event, Filter};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Deref;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReqFilter {
+    pub filter: Filter,
+    /// Conditions on tags named by more than one letter: tag name (without
+    /// the `#`) → accepted values.
+    pub long_tags: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl From<Filter> for ReqFilter {
+    fn from(filter: Filter) -> Self {
+        Self {
+            filter,
+            long_tags: BTreeMap::new(),
+        }
+    }
+}
+
+impl Deref for ReqFilter {
+    type Target = Filter;
+
+    fn deref(&self) -> &Filter {
+        &self.filter
+    }
+}
+
+impl ReqFilter {
+    /// The filters of the REQ message `text`, which nostr already parsed
+    /// into `filters`, with their long tag conditions read back from the
+    /// raw JSON.
+    pub fn from_req(text: &str, filters: Vec<Filter>) -> Vec<Self> {
+        let raw: Vec<serde_json::Value> = serde_json::from_str(text).unwrap_or_default();
+        let mut raw = raw.into_iter().skip(2);
+        filters
+            .into_iter()
+            .map(|filter| Self {
+                filter,
+                long_tags: raw.next().map(|value| long_tags(&value)).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Also require a `name` tag with one of `values`.
+    pub fn long_tag<I, S>(mut self, name: &str, values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.long_tags
+            .entry(name.to_string())
+            .or_default()
+            .extend(values.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn match_event(&self, event: &Event) -> bool {
+        self.filter.match_event(event) && self.matches_long_tags(event)
+    }
+
+    /// Whether `event` has a matching tag for every long tag condition. As
+    /// with single letters, a condition with no values matches nothing.
+    pub fn matches_long_tags(&self, event: &Event) -> bool {
+        self.long_tags.iter().all(|(name, values)| {
+            event.tags.iter().any(|tag| {
+                let tag = tag.as_vec();
+                tag.len() >= 2 && tag[0] == *name && values.contains(&tag[1])
+            })
+        })
+    }
+
+    /// Every tag condition, single-letter ones first, as tag name → values.
+    pub fn tag_conditions(&self) -> Vec<(String, Vec<&str>)> {
+        let mut conditions: Vec<(String, Vec<&str>)> = self
+            .filter
+            .generic_tags
+            .iter()
+            .map(|(letter, values)| (letter.to_string(), values.iter().map(String::as_str).collect()))
+            .collect();
+        conditions.extend(
+            self.long_tags
+                .iter()
+                .map(|(name, values)| (name.clone(), values.iter().map(String::as_str).collect())),
+        );
+        conditions
+    }
+}
+
+/// The `#<name>` conditions of a raw filter object whose name is longer
+/// than one character.
+fn long_tags(filter: &serde_json::Value) -> BTreeMap<String, BTreeSet<String>> {
+    let Some(fields) = filter.as_object() else {
+        return BTreeMap::new();
+    };
+    fields
+        .iter()
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix('#').filter(|name| name.chars().count() > 1)?;
+            let values = value
+                .as_array()?
+                .iter()
+                .filter_map(|v| v.as_str().map(str::to_string))
+                .collect();
+            Some((name.to_string(), values))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{ClientMessage, EventBuilder, JsonUtil, Keys, Kind, Tag, TagKind};
+
+    #[test]
+    fn long_tags_survive_parsing_and_narrow_matches() {
+        let text = r##"["REQ","sub",{"kinds":[1],"#proxy":["https://example.com/1"]},{"#t":["nostr"]}]"##;
+        let ClientMessage::Req { filters, .. } = ClientMessage::from_json(text).unwrap() else {
+            panic!("not a REQ");
+        };
+        let filters = ReqFilter::from_req(text, filters);
+        assert_eq!(filters.len(), 2);
+        assert_eq!(
+            filters[0].long_tags.get("proxy").map(|v| v.len()),
+            Some(1),
+            "nostr's Filter drops #proxy; ReqFilter keeps it"
+        );
+        assert!(filters[1].long_tags.is_empty());
+
+        let keys = Keys::generate();
+        let proxied = |url: &str| {
+            EventBuilder::new(Kind::TextNote, "hi", [Tag::custom(TagKind::Custom("proxy".into()), [url, "web"])])
+                .to_event(&keys)
+                .unwrap()
+        };
+        assert!(filters[0].match_event(&proxied("https://example.com/1")));
+        assert!(!filters[0].match_event(&proxied("https://example.com/2")));
+        assert!(!filters[0].match_event(&EventBuilder::text_note("hi", []).to_event(&keys).unwrap()));
+
+        let conditions = filters[0].tag_conditions();
+        assert_eq!(conditions, vec![("proxy".to_string(), vec!["https://example.com/1"])]);
+    }
+}
@@ -1,13 +1,20 @@
-use super::NostrStore;
-use crate::error::Result;
+use super::filter::ReqFilter;
+use super::readers::{ReadTxnStats, ReaderGuard, ReaderTracker};
+use super::{codec, newest_first, BoxFuture, NostrStore, SaveResult};
+use crate::error::{Error, Result};
 use heed::types::*;
 use heed::{Database, Env, EnvOpenOptions, RwTxn};
-use nostr::{Event, Filter, Kind, PublicKey};
+use nostr::{Alphabet, Event, EventId, Filter, Kind, PublicKey, SingleLetterTag};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs;
 use std::ops::{Bound, RangeBounds};
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::mpsc::{Receiver, SyncSender, TryRecvError};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// A range over borrowed byte slices that implements `RangeBounds<[u8]>`.
 /// Required because heed's `Bytes` codec has `EItem = [u8]` (unsized).
@@ -31,6 +38,74 @@ impl<'a> RangeBounds<[u8]> for ByteRange<'a> {
     }
 }
 
+/// Query deadline shared by all index scans of one query. The clock and the
+/// watchdog's abort flag are only read every few iterations to keep the hot
+/// loop cheap.
+struct ScanDeadline {
+    at: Option<Instant>,
+    abort: Option<Arc<AtomicBool>>,
+    ticks: u32,
+    hit: bool,
+}
+
+impl ScanDeadline {
+    const CHECK_EVERY: u32 = 64;
+
+    fn new(at: Option<Instant>, abort: Option<Arc<AtomicBool>>) -> Self {
+        Self { at, abort, ticks: 0, hit: false }
+    }
+
+    fn expired(&mut self) -> bool {
+        if self.hit {
+            return true;
+        }
+        if self.at.is_none() && self.abort.is_none() {
+            return false;
+        }
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(Self::CHECK_EVERY) {
+            self.hit = self.at.is_some_and(|at| Instant::now() >= at)
+                || self.abort.as_ref().is_some_and(|abort| abort.load(Relaxed));
+        }
+        self.hit
+    }
+}
+
+/// Counts an index scan's matches toward its limit. Index keys put equal
+/// timestamps in ascending id order, so a reverse scan meets the highest ids
+/// of a second first; once the limit is reached the scan still finishes that
+/// second, letting the final sort keep its lowest ids.
+struct ScanLimit {
+    limit: usize,
+    count: usize,
+    /// Timestamp the limit was reached at.
+    boundary: Option<u64>,
+}
+
+impl ScanLimit {
+    fn new(limit: usize) -> Self {
+        Self { limit, count: 0, boundary: None }
+    }
+
+    /// Whether an entry at `created_at` is past everything the scan needs.
+    fn done(&self, created_at: u64) -> bool {
+        self.boundary.is_some_and(|boundary| created_at < boundary)
+    }
+
+    fn matched(&mut self, created_at: u64) {
+        self.count += 1;
+        if self.count >= self.limit && self.boundary.is_none() {
+            self.boundary = Some(created_at);
+        }
+    }
+}
+
+/// Big-endian timestamp at `offset` in an index key.
+#[inline]
+fn key_ts(key: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(key[offset..offset + 8].try_into().unwrap())
+}
+
 // ---------------------------------------------------------------------------
 // Key sizes (all fixed-width indices use stack arrays)
 // ---------------------------------------------------------------------------
@@ -39,6 +114,28 @@ const CREATED_KEY_LEN: usize = 8 + 32; // timestamp(8) + event_id(32)
 const AUTHOR_KEY_LEN: usize = 32 + 8 + 32; // pubkey(32) + timestamp(8) + event_id(32)
 const KIND_KEY_LEN: usize = 2 + 8 + 32; // kind(2) + timestamp(8) + event_id(32)
 const AUTHOR_KIND_KEY_LEN: usize = 32 + 2 + 8 + 32; // pubkey(32) + kind(2) + ts(8) + id(32)
+const ADDRESS_KEY_LEN: usize = 32 + 2 + 32; // pubkey(32) + kind(2) + sha256(d-tag)(32)
+const ADDRESS_VALUE_LEN: usize = 8 + 32; // timestamp(8) + event_id(32)
+/// Most author × kind × d-tag combinations answered from the address index
+/// before a query falls back to scanning.
+const MAX_ADDRESS_LOOKUPS: usize = 1000;
+/// Longest tag name covered by the tag index.
+const MAX_INDEXED_TAG_NAME: usize = 32;
+/// LMDB's key size limit, which caps the tag values that can be indexed.
+const MAX_KEY_LEN: usize = 511;
+/// Marker in the meta database once tags with longer names are indexed.
+const LONG_TAG_INDEX_MARKER: &str = "long_tag_index";
+/// Marker in the meta database once every JSON value has been converted to
+/// the binary codec.
+const BINARY_VALUES_MARKER: &str = "binary_values";
+
+/// Whether a `name` tag with `value` gets a tag index entry. Conditions on
+/// other tags are still applied, just never used to drive a scan.
+fn indexed_tag(name: &str, value: &str) -> bool {
+    (1..=MAX_INDEXED_TAG_NAME).contains(&name.len())
+        && !name.contains('\0')
+        && name.len() + 1 + value.len() + 1 + 40 <= MAX_KEY_LEN
+}
 
 // ---------------------------------------------------------------------------
 // Replaceable event kind ranges (NIP-01)
@@ -59,7 +156,8 @@ fn is_parameterized_replaceable(kind: u16) -> bool {
 #[derive(Clone)]
 pub struct LmdbStore {
     env: Arc<Env>,
-    /// Primary store: EventId(32 bytes) → Event as raw JSON bytes
+    /// Primary store: EventId(32 bytes) → Event in the binary codec (or
+    /// JSON, until converted by the writer thread)
     events_db: Database<Bytes, Bytes>,
     // --- Secondary indices (key-only, value = Unit) ---
     /// Timestamp(BE 8) + EventId(32) = 40 bytes
@@ -72,8 +170,29 @@ pub struct LmdbStore {
     index_tag: Database<Bytes, Unit>,
     /// Pubkey(32) + Kind(BE 2) + Timestamp(BE 8) + EventId(32) = 74 bytes
     index_author_kind: Database<Bytes, Unit>,
+    /// Addressable events: Pubkey(32) + Kind(BE 2) + SHA-256(d-tag)(32) = 66
+    /// bytes → Timestamp(BE 8) + EventId(32) of the latest version
+    index_address: Database<Bytes, Bytes>,
+    /// Markers for one-off index migrations
+    meta: Database<Str, Bytes>,
+    /// What NIP-09 deletions removed, so the events can't be saved again:
+    /// EventId(32) + deleter Pubkey(32) = 64 bytes → empty, and address
+    /// keys (66 bytes) → Timestamp(BE 8) of the latest deletion
+    tombstones: Database<Bytes, Bytes>,
     /// Path to the LMDB directory
     path: String,
+    /// Queue to the writer thread that batches writes. `None` on the
+    /// writer's own copy of the store, which writes directly.
+    writer: Option<Arc<WriterHandle>>,
+    /// Open read transactions, watched for ones held too long.
+    readers: Arc<ReaderTracker>,
+    /// Most events one filter may return, whatever limit it asks for.
+    max_limit: Option<usize>,
+    /// Set by `close`; no write transaction is opened afterwards.
+    closed: Arc<AtomicBool>,
+    /// `moar.lock` in the database directory, locked shared by every open
+    /// store and exclusively by one that must be the only user.
+    _lock: Arc<fs::File>,
 }
 
 // ---------------------------------------------------------------------------
@@ -117,6 +236,30 @@ impl LmdbStore {
         key
     }
 
+    /// The d-tag is hashed so keys are fixed-width whatever its length.
+    #[inline]
+    fn encode_address_key(pubkey: &PublicKey, kind: &Kind, d_tag: &str) -> [u8; ADDRESS_KEY_LEN] {
+        let mut key = [0u8; ADDRESS_KEY_LEN];
+        key[..32].copy_from_slice(pubkey.to_bytes().as_ref());
+        key[32..34].copy_from_slice(&kind.as_u16().to_be_bytes());
+        key[34..66].copy_from_slice(&Sha256::digest(d_tag.as_bytes()));
+        key
+    }
+
+    /// Address index key of a parameterized replaceable event.
+    fn event_address_key(event: &Event) -> Option<[u8; ADDRESS_KEY_LEN]> {
+        is_parameterized_replaceable(event.kind.as_u16()).then(|| {
+            Self::encode_address_key(&event.pubkey, &event.kind, &Self::get_d_tag(event).unwrap_or_default())
+        })
+    }
+
+    fn encode_address_value(event: &Event) -> [u8; ADDRESS_VALUE_LEN] {
+        let mut value = [0u8; ADDRESS_VALUE_LEN];
+        value[..8].copy_from_slice(&event.created_at.as_u64().to_be_bytes());
+        value[8..40].copy_from_slice(event.id.as_bytes());
+        value
+    }
+
     fn encode_tag_key(tag_key: &str, tag_val: &str, event: &Event) -> Vec<u8> {
         let mut key = Vec::with_capacity(tag_key.len() + 1 + tag_val.len() + 1 + 40);
         key.extend_from_slice(tag_key.as_bytes());
@@ -136,6 +279,36 @@ impl LmdbStore {
 impl LmdbStore {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         fs::create_dir_all(&path)?;
+        let lock = Self::lock_file(path.as_ref())?;
+        lock.try_lock_shared().map_err(|e| Self::lock_error(path.as_ref(), e))?;
+        Self::open(path, lock)
+    }
+
+    /// Open a database no other store, in this process or another, has
+    /// open, and keep others out until this store is dropped.
+    pub fn open_exclusive<P: AsRef<Path>>(path: P) -> Result<Self> {
+        fs::create_dir_all(&path)?;
+        let lock = Self::lock_file(path.as_ref())?;
+        lock.try_lock().map_err(|e| Self::lock_error(path.as_ref(), e))?;
+        Self::open(path, lock)
+    }
+
+    fn lock_file(path: &Path) -> Result<fs::File> {
+        Ok(fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path.join("moar.lock"))?)
+    }
+
+    fn lock_error(path: &Path, e: fs::TryLockError) -> Error {
+        match e {
+            fs::TryLockError::WouldBlock => Error::InUse(path.display().to_string()),
+            fs::TryLockError::Error(e) => Error::Io(e),
+        }
+    }
+
+    fn open<P: AsRef<Path>>(path: P, lock: fs::File) -> Result<Self> {
         let path_str = path.as_ref().to_string_lossy().to_string();
 
         let mut env_builder = EnvOpenOptions::new();
@@ -150,9 +323,12 @@ impl LmdbStore {
         let index_kind = env.create_database(&mut wtxn, Some("idx_kind"))?;
         let index_tag = env.create_database(&mut wtxn, Some("idx_tag"))?;
         let index_author_kind = env.create_database(&mut wtxn, Some("idx_author_kind"))?;
+        let index_address = env.create_database(&mut wtxn, Some("idx_address"))?;
+        let meta = env.create_database(&mut wtxn, Some("meta"))?;
+        let tombstones = env.create_database(&mut wtxn, Some("tombstones"))?;
         wtxn.commit()?;
 
-        Ok(Self {
+        let mut store = Self {
             env: Arc::new(env),
             events_db,
             index_created,
@@ -160,9 +336,389 @@ impl LmdbStore {
             index_kind,
             index_tag,
             index_author_kind,
+            index_address,
+            meta,
+            tombstones,
             path: path_str,
+            writer: None,
+            readers: Arc::new(ReaderTracker::default()),
+            max_limit: None,
+            closed: Arc::new(AtomicBool::new(false)),
+            _lock: Arc::new(lock),
+        };
+        store.backfill_address_index()?;
+        store.backfill_long_tag_index()?;
+        store.writer = Some(Arc::new(WriterHandle::spawn(store.clone())?));
+        Ok(store)
+    }
+
+    /// Cap the events any one filter returns at `max`, so a query can't
+    /// scan past it even if its filter wasn't clamped (NIP-11 `max_limit`).
+    pub fn with_max_limit(mut self, max: Option<usize>) -> Self {
+        self.max_limit = max.map(|max| max.max(1));
+        self
+    }
+}
+
+impl LmdbStore {
+    /// Open a read transaction registered with the reader tracker. The
+    /// guard must live as long as the transaction.
+    fn read_txn(&self, label: &'static str, abortable: bool) -> Result<(heed::RoTxn<'_>, ReaderGuard)> {
+        let guard = self.readers.begin(label, abortable);
+        Ok((self.env.read_txn()?, guard))
+    }
+
+    fn write_txn(&self) -> Result<RwTxn<'_>> {
+        if self.closed.load(Relaxed) {
+            return Err(Error::Closed);
+        }
+        Ok(self.env.write_txn()?)
+    }
+
+    /// Databases created before the address index existed have it empty;
+    /// fill it from the stored parameterized replaceable events.
+    fn backfill_address_index(&self) -> Result<()> {
+        let mut wtxn = self.write_txn()?;
+        if !self.index_address.is_empty(&wtxn)? {
+            return Ok(());
+        }
+        let start = 30_000u16.to_be_bytes();
+        let end = 39_999u16.to_be_bytes();
+        let mut end_key = [0xffu8; KIND_KEY_LEN];
+        end_key[..2].copy_from_slice(&end);
+        let mut start_key = [0u8; KIND_KEY_LEN];
+        start_key[..2].copy_from_slice(&start);
+        let range = ByteRange::new(&start_key, &end_key);
+        let ids: Vec<Vec<u8>> = self
+            .index_kind
+            .range(&wtxn, &range)?
+            .map(|r| r.map(|(key, _)| key[10..42].to_vec()))
+            .collect::<std::result::Result<_, _>>()?;
+
+        let mut filled = 0;
+        for id in ids {
+            let Some(raw) = self.events_db.get(&wtxn, &id)? else {
+                continue;
+            };
+            let event = Self::decode_event(raw)?;
+            let Some(key) = Self::event_address_key(&event) else {
+                continue;
+            };
+            let value = Self::encode_address_value(&event);
+            let newer = match self.index_address.get(&wtxn, &key)? {
+                Some(existing) => value.as_slice() > existing,
+                None => true,
+            };
+            if newer {
+                self.index_address.put(&mut wtxn, &key, &value)?;
+                filled += 1;
+            }
+        }
+        wtxn.commit()?;
+        if filled > 0 {
+            tracing::info!("Indexed {} addressable events in {}", filled, self.path);
+        }
+        Ok(())
+    }
+
+    /// Databases written before tags with names longer than one letter were
+    /// indexed lack their entries; add them once.
+    fn backfill_long_tag_index(&self) -> Result<()> {
+        let mut wtxn = self.write_txn()?;
+        if self.meta.get(&wtxn, LONG_TAG_INDEX_MARKER)?.is_some() {
+            return Ok(());
+        }
+        let mut entries = Vec::new();
+        for result in self.events_db.iter(&wtxn)? {
+            let (_, raw) = result?;
+            let event = Self::decode_event(raw)?;
+            for tag in event.tags.iter() {
+                let tag_vec = tag.as_vec();
+                if tag_vec.len() >= 2 && tag_vec[0].len() > 1 && indexed_tag(&tag_vec[0], &tag_vec[1]) {
+                    entries.push(Self::encode_tag_key(&tag_vec[0], &tag_vec[1], &event));
+                }
+            }
+        }
+        for key in &entries {
+            self.index_tag.put(&mut wtxn, key, &())?;
+        }
+        self.meta.put(&mut wtxn, LONG_TAG_INDEX_MARKER, &[1])?;
+        wtxn.commit()?;
+        if !entries.is_empty() {
+            tracing::info!("Indexed {} multi-letter tags in {}", entries.len(), self.path);
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Writer thread — coalesces concurrent writes into one LMDB transaction
+// ---------------------------------------------------------------------------
+
+/// Pending write requests held by the writer queue before senders block.
+const WRITE_QUEUE_CAPACITY: usize = 1024;
+/// Most events committed in one write transaction.
+const MAX_BATCH_EVENTS: usize = 1000;
+
+/// The outcome of each event of a request, in order.
+type WriteResult = Result<Vec<SaveResult>>;
+
+enum WriteReply {
+    Blocking(std::sync::mpsc::SyncSender<WriteResult>),
+    Async(tokio::sync::oneshot::Sender<WriteResult>),
+}
+
+impl WriteReply {
+    fn send(self, result: WriteResult) {
+        match self {
+            WriteReply::Blocking(tx) => {
+                let _ = tx.send(result);
+            }
+            WriteReply::Async(tx) => {
+                let _ = tx.send(result);
+            }
+        }
+    }
+}
+
+struct WriteRequest {
+    events: Vec<Event>,
+    reply: WriteReply,
+}
+
+struct WriterHandle {
+    tx: Option<SyncSender<WriteRequest>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl WriterHandle {
+    fn spawn(store: LmdbStore) -> Result<Self> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(WRITE_QUEUE_CAPACITY);
+        let thread = std::thread::Builder::new()
+            .name("lmdb-writer".into())
+            .spawn(move || run_writer(store, rx))?;
+        Ok(Self {
+            tx: Some(tx),
+            thread: Some(thread),
         })
     }
+
+    fn sender(&self) -> &SyncSender<WriteRequest> {
+        self.tx.as_ref().expect("writer sender is only taken on drop")
+    }
+}
+
+impl Drop for WriterHandle {
+    fn drop(&mut self) {
+        // Closing the queue stops the thread; wait for it so the environment
+        // is fully released before the store can be reopened.
+        drop(self.tx.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run_writer(store: LmdbStore, rx: Receiver<WriteRequest>) {
+    // Convert JSON values left by older versions while there are no writes
+    let converted = store
+        .env
+        .read_txn()
+        .and_then(|rtxn| Ok(store.meta.get(&rtxn, BINARY_VALUES_MARKER)?.is_some()));
+    let mut conversion = (!matches!(converted, Ok(true))).then(JsonConversion::default);
+    loop {
+        let first = match conversion.as_mut() {
+            Some(progress) => match rx.try_recv() {
+                Ok(req) => req,
+                Err(TryRecvError::Empty) => {
+                    match store.convert_json_chunk(progress) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            if progress.converted > 0 {
+                                tracing::info!("Converted {} events to binary in {}", progress.converted, store.path);
+                            }
+                            conversion = None;
+                        }
+                        Err(e) => {
+                            tracing::error!("Converting events to binary in {} failed: {}", store.path, e);
+                            conversion = None;
+                        }
+                    }
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(req) => req,
+                Err(_) => break,
+            },
+        };
+        if store.closed.load(Relaxed) {
+            // Fail whatever is queued and let the environment go
+            first.reply.send(Err(Error::Closed));
+            while let Ok(req) = rx.try_recv() {
+                req.reply.send(Err(Error::Closed));
+            }
+            break;
+        }
+        let mut queued = first.events.len();
+        let mut batch = vec![first];
+        while queued < MAX_BATCH_EVENTS {
+            match rx.try_recv() {
+                Ok(req) => {
+                    queued += req.events.len();
+                    batch.push(req);
+                }
+                Err(_) => break,
+            }
+        }
+
+        if batch.len() == 1 {
+            let req = batch.pop().unwrap();
+            let result = store.write_events(&req.events);
+            req.reply.send(result);
+            continue;
+        }
+
+        match store.write_events(batch.iter().flat_map(|r| r.events.iter())) {
+            Ok(results) => {
+                let mut results = results.into_iter();
+                for req in batch {
+                    req.reply.send(Ok(results.by_ref().take(req.events.len()).collect()));
+                }
+            }
+            Err(_) => {
+                // Retry one request per transaction so a failure is only
+                // reported to the caller it belongs to.
+                for req in batch {
+                    let result = store.write_events(&req.events);
+                    req.reply.send(result);
+                }
+            }
+        }
+    }
+}
+
+/// Values examined per conversion transaction, so queued writes wait at
+/// most one chunk.
+const CONVERSION_CHUNK: usize = 1000;
+
+/// Where the JSON-to-binary conversion has got to.
+#[derive(Default)]
+struct JsonConversion {
+    /// Last event id examined.
+    after: Option<[u8; 32]>,
+    converted: u64,
+}
+
+impl LmdbStore {
+    /// Re-encode the next chunk of legacy JSON values in the binary codec.
+    /// Returns `false` once every value has been examined, and marks the
+    /// database so later opens skip the pass.
+    fn convert_json_chunk(&self, progress: &mut JsonConversion) -> Result<bool> {
+        let mut wtxn = self.write_txn()?;
+        let mut chunk = Vec::new();
+        let mut examined = 0;
+        {
+            let start = progress.after.unwrap_or([0u8; 32]);
+            let end = [0xffu8; 32];
+            let range = ByteRange::new(&start, &end);
+            for result in self.events_db.range(&wtxn, &range)? {
+                let (key, raw) = result?;
+                if progress.after.is_some_and(|after| key == after) {
+                    continue;
+                }
+                examined += 1;
+                let mut id = [0u8; 32];
+                id.copy_from_slice(key);
+                progress.after = Some(id);
+                if codec::is_json(raw) {
+                    chunk.push((id, codec::encode_event(&Self::decode_event(raw)?)));
+                }
+                if examined >= CONVERSION_CHUNK {
+                    break;
+                }
+            }
+        }
+        for (id, raw) in &chunk {
+            self.events_db.put(&mut wtxn, id, raw)?;
+        }
+        let more = examined >= CONVERSION_CHUNK;
+        if !more {
+            self.meta.put(&mut wtxn, BINARY_VALUES_MARKER, &[1])?;
+        }
+        wtxn.commit()?;
+        progress.converted += chunk.len() as u64;
+        Ok(more)
+    }
+
+    /// Store events in a single write transaction, skipping duplicates and
+    /// superseded replaceable events.
+    fn write_events<'a>(&self, events: impl IntoIterator<Item = &'a Event>) -> WriteResult {
+        let mut wtxn = self.write_txn()?;
+        let mut results = Vec::new();
+        for event in events {
+            results.push(self.save_event_txn(&mut wtxn, event)?);
+        }
+        wtxn.commit()?;
+        Ok(results)
+    }
+
+    /// Store events through the writer thread, reporting what happened to
+    /// each.
+    fn save_events_with_results(&self, events: &[Event]) -> WriteResult {
+        let Some(writer) = &self.writer else {
+            return self.write_events(events);
+        };
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        writer
+            .sender()
+            .send(WriteRequest {
+                events: events.to_vec(),
+                reply: WriteReply::Blocking(tx),
+            })
+            .map_err(|_| Error::WriterStopped)?;
+        rx.recv().map_err(|_| Error::WriterStopped)?
+    }
+
+    fn save_event_txn(&self, wtxn: &mut RwTxn, event: &Event) -> Result<SaveResult> {
+        // Duplicate check
+        let id_bytes = event.id.as_bytes();
+        if self.events_db.get(wtxn, id_bytes)?.is_some() {
+            return Ok(SaveResult::Duplicate);
+        }
+        if self.is_tombstoned(wtxn, event)? {
+            return Ok(SaveResult::Deleted);
+        }
+
+        // Replaceable event handling (NIP-01)
+        let result = self.handle_replaceable(wtxn, event)?;
+        if result == SaveResult::RejectedStale {
+            return Ok(result);
+        }
+
+        // Serialize once in the binary codec
+        let raw = codec::encode_event(event);
+        self.events_db.put(wtxn, id_bytes, &raw)?;
+
+        // Write all indices
+        self.insert_indices(wtxn, event)?;
+
+        if event.kind == Kind::EventDeletion {
+            self.apply_deletion(wtxn, event)?;
+        }
+        Ok(result)
+    }
+}
+
+impl LmdbStore {
+    /// Write a compacted copy of the environment to `dest`, omitting free
+    /// pages. `dest` must not already exist.
+    pub fn compact_to<P: AsRef<Path>>(&self, dest: P) -> Result<()> {
+        self.env
+            .copy_to_file(dest, heed::CompactionOption::Enabled)?;
+        Ok(())
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -183,11 +739,14 @@ impl LmdbStore {
 
         for tag in event.tags.iter() {
             let tag_vec = tag.as_vec();
-            if tag_vec.len() >= 2 && tag_vec[0].len() == 1 {
+            if tag_vec.len() >= 2 && indexed_tag(&tag_vec[0], &tag_vec[1]) {
                 let tk = Self::encode_tag_key(&tag_vec[0], &tag_vec[1], event);
                 self.index_tag.put(wtxn, &tk, &())?;
             }
         }
+        if let Some(key) = Self::event_address_key(event) {
+            self.index_address.put(wtxn, &key, &Self::encode_address_value(event))?;
+        }
         Ok(())
     }
 
@@ -204,11 +763,17 @@ impl LmdbStore {
 
         for tag in event.tags.iter() {
             let tag_vec = tag.as_vec();
-            if tag_vec.len() >= 2 && tag_vec[0].len() == 1 {
+            if tag_vec.len() >= 2 && indexed_tag(&tag_vec[0], &tag_vec[1]) {
                 let tk = Self::encode_tag_key(&tag_vec[0], &tag_vec[1], event);
                 self.index_tag.delete(wtxn, &tk)?;
             }
         }
+        if let Some(key) = Self::event_address_key(event) {
+            // Only if it still points at this version
+            if self.index_address.get(wtxn, &key)? == Some(Self::encode_address_value(event).as_slice()) {
+                self.index_address.delete(wtxn, &key)?;
+            }
+        }
         Ok(())
     }
 
@@ -219,12 +784,145 @@ impl LmdbStore {
             Some(r) => r.to_vec(), // copy out before mutating
             None => return Ok(false),
         };
-        let event: Event = serde_json::from_slice(&raw)?;
+        let event = Self::decode_event(&raw)?;
         self.remove_indices(wtxn, &event)?;
         self.events_db.delete(wtxn, id)?;
         Ok(true)
     }
 
+    fn encode_tombstone_key(id: &[u8; 32], deleter: &PublicKey) -> [u8; 64] {
+        let mut key = [0u8; 64];
+        key[..32].copy_from_slice(id);
+        key[32..].copy_from_slice(deleter.to_bytes().as_ref());
+        key
+    }
+
+    /// Address key of a replaceable or parameterized replaceable event, the
+    /// way an `a` tag names it.
+    fn tombstone_address_key(event: &Event) -> Option<[u8; ADDRESS_KEY_LEN]> {
+        if is_replaceable(event.kind.as_u16()) {
+            return Some(Self::encode_address_key(&event.pubkey, &event.kind, ""));
+        }
+        Self::event_address_key(event)
+    }
+
+    /// Whether a NIP-09 deletion by the event's author already removed it.
+    /// Deletions themselves can't be deleted.
+    fn is_tombstoned(&self, wtxn: &RwTxn, event: &Event) -> Result<bool> {
+        if event.kind == Kind::EventDeletion {
+            return Ok(false);
+        }
+        let key = Self::encode_tombstone_key(event.id.as_bytes(), &event.pubkey);
+        if self.tombstones.get(wtxn, &key)?.is_some() {
+            return Ok(true);
+        }
+        let Some(address) = Self::tombstone_address_key(event) else {
+            return Ok(false);
+        };
+        Ok(match self.tombstones.get(wtxn, &address)? {
+            Some(ts) => u64::from_be_bytes(ts[..8].try_into().unwrap()) >= event.created_at.as_u64(),
+            None => false,
+        })
+    }
+
+    /// Apply a NIP-09 deletion: remove the events its `e` tags name and the
+    /// versions up to its own timestamp of the addresses its `a` tags name,
+    /// if its author wrote them, and remember both so they stay deleted
+    /// when they arrive again (or first arrive after it, out of order).
+    fn apply_deletion(&self, wtxn: &mut RwTxn, deletion: &Event) -> Result<()> {
+        let deleted_at = deletion.created_at.as_u64();
+        for tag in deletion.tags.iter() {
+            let tag = tag.as_vec();
+            if tag.len() < 2 {
+                continue;
+            }
+            match tag[0].as_str() {
+                "e" => {
+                    let Ok(id) = EventId::from_hex(&tag[1]) else {
+                        continue;
+                    };
+                    let id = *id.as_bytes();
+                    let target = match self.events_db.get(wtxn, &id)? {
+                        Some(raw) => Some(Self::decode_event(raw)?),
+                        None => None,
+                    };
+                    match target {
+                        Some(target) if target.kind == Kind::EventDeletion => continue,
+                        Some(target) if target.pubkey == deletion.pubkey => {
+                            self.delete_event_txn(wtxn, &id)?;
+                        }
+                        _ => {}
+                    }
+                    let key = Self::encode_tombstone_key(&id, &deletion.pubkey);
+                    self.tombstones.put(wtxn, &key, &[])?;
+                }
+                "a" => {
+                    let mut parts = tag[1].splitn(3, ':');
+                    let (Some(kind), Some(pubkey), Some(d_tag)) = (parts.next(), parts.next(), parts.next()) else {
+                        continue;
+                    };
+                    let (Ok(kind), Ok(pubkey)) = (kind.parse::<u16>(), PublicKey::from_hex(pubkey)) else {
+                        continue;
+                    };
+                    if pubkey != deletion.pubkey
+                        || !(is_replaceable(kind) || is_parameterized_replaceable(kind))
+                    {
+                        continue;
+                    }
+                    let kind = Kind::from(kind);
+                    for id in self.address_versions(wtxn, &pubkey, &kind, d_tag, deleted_at)? {
+                        self.delete_event_txn(wtxn, &id)?;
+                    }
+                    let key = Self::encode_address_key(&pubkey, &kind, d_tag);
+                    let newer = match self.tombstones.get(wtxn, &key)? {
+                        Some(ts) => u64::from_be_bytes(ts[..8].try_into().unwrap()) >= deleted_at,
+                        None => false,
+                    };
+                    if !newer {
+                        self.tombstones.put(wtxn, &key, &deleted_at.to_be_bytes())?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Stored versions of an address created at or before `until`.
+    fn address_versions(
+        &self,
+        wtxn: &RwTxn,
+        pubkey: &PublicKey,
+        kind: &Kind,
+        d_tag: &str,
+        until: u64,
+    ) -> Result<Vec<[u8; 32]>> {
+        let mut ids = Vec::new();
+        if is_parameterized_replaceable(kind.as_u16()) {
+            let key = Self::encode_address_key(pubkey, kind, d_tag);
+            if let Some(value) = self.index_address.get(wtxn, &key)? {
+                if u64::from_be_bytes(value[..8].try_into().unwrap()) <= until {
+                    let mut id = [0u8; 32];
+                    id.copy_from_slice(&value[8..40]);
+                    ids.push(id);
+                }
+            }
+            return Ok(ids);
+        }
+        let start = Self::make_author_kind_range_start(pubkey, kind);
+        let end = Self::make_author_kind_range_end(pubkey, kind);
+        for result in self.index_author_kind.range(wtxn, &ByteRange::new(&start, &end))? {
+            let (key, _) = result?;
+            if key.len() < AUTHOR_KIND_KEY_LEN || u64::from_be_bytes(key[34..42].try_into().unwrap()) > until {
+                continue;
+            }
+            let mut id = [0u8; 32];
+            id.copy_from_slice(&key[42..74]);
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+
     /// Extract the `d` tag value from an event (for parameterized replaceable events).
     fn get_d_tag(event: &Event) -> Option<String> {
         for tag in event.tags.iter() {
@@ -237,9 +935,9 @@ impl LmdbStore {
     }
 
     /// Handle replaceable and parameterized-replaceable events per NIP-01.
-    /// If a newer event already exists, returns `true` (meaning: skip the insert).
-    /// If older events exist, deletes them.
-    fn handle_replaceable(&self, wtxn: &mut RwTxn, event: &Event) -> Result<bool> {
+    /// Returns `RejectedStale` if a newer version already exists (skip the
+    /// insert), and `Replaced` after deleting older ones.
+    fn handle_replaceable(&self, wtxn: &mut RwTxn, event: &Event) -> Result<SaveResult> {
         let kind_u16 = event.kind.as_u16();
 
         if is_replaceable(kind_u16) {
@@ -275,73 +973,40 @@ impl LmdbStore {
             }
 
             if dominated {
-                return Ok(true);
+                return Ok(SaveResult::RejectedStale);
             }
             for id in &to_delete {
                 self.delete_event_txn(wtxn, id)?;
             }
-        } else if is_parameterized_replaceable(kind_u16) {
-            let d_tag = Self::get_d_tag(event).unwrap_or_default();
-            let start = Self::make_author_kind_range_start(&event.pubkey, &event.kind);
-            let end = Self::make_author_kind_range_end(&event.pubkey, &event.kind);
-            let range = ByteRange::new(&start, &end);
-
-            // Collect keys first, then process (avoid borrow conflict)
-            let entries: Vec<_> = self
-                .index_author_kind
-                .range(wtxn, &range)?
-                .filter_map(|r| r.ok())
-                .filter(|(key, _)| key.len() >= AUTHOR_KIND_KEY_LEN)
-                .map(|(key, _)| {
-                    let mut existing_id = [0u8; 32];
-                    existing_id.copy_from_slice(&key[42..74]);
-                    let existing_ts = u64::from_be_bytes(key[34..42].try_into().unwrap());
-                    (existing_id, existing_ts)
-                })
-                .collect();
-
-            let mut to_delete: Vec<[u8; 32]> = Vec::new();
-            let mut dominated = false;
-
-            for (existing_id, existing_ts) in entries {
-                if existing_id == *event.id.as_bytes() {
-                    continue;
-                }
-
-                // Fetch the existing event to check its d-tag
-                if let Some(raw) = self.events_db.get(wtxn, &existing_id)? {
-                    let existing: Event = serde_json::from_slice(raw)?;
-                    let existing_d = Self::get_d_tag(&existing).unwrap_or_default();
-                    if existing_d != d_tag {
-                        continue;
-                    }
-
-                    if existing_ts > event.created_at.as_u64()
-                        || (existing_ts == event.created_at.as_u64()
-                            && existing_id > *event.id.as_bytes())
-                    {
-                        dominated = true;
-                    } else {
-                        to_delete.push(existing_id);
-                    }
-                }
+            if !to_delete.is_empty() {
+                return Ok(SaveResult::Replaced);
             }
+        } else if let Some(key) = Self::event_address_key(event) {
+            // One lookup in the address index finds the current version
+            let existing = self.index_address.get(wtxn, &key)?.map(|v| v.to_vec());
+            if let Some(existing) = existing {
+                let existing_ts = u64::from_be_bytes(existing[..8].try_into().unwrap());
+                let mut existing_id = [0u8; 32];
+                existing_id.copy_from_slice(&existing[8..40]);
 
-            if dominated {
-                return Ok(true);
-            }
-            for id in &to_delete {
-                self.delete_event_txn(wtxn, id)?;
+                if existing_ts > event.created_at.as_u64()
+                    || (existing_ts == event.created_at.as_u64()
+                        && existing_id > *event.id.as_bytes())
+                {
+                    return Ok(SaveResult::RejectedStale);
+                }
+                self.delete_event_txn(wtxn, &existing_id)?;
+                return Ok(SaveResult::Replaced);
             }
         }
 
-        Ok(false)
+        Ok(SaveResult::Saved)
     }
 
-    /// Deserialize raw JSON bytes into an Event.
+    /// Decode a stored event (binary, or JSON from older versions).
     #[inline]
     fn decode_event(raw: &[u8]) -> Result<Event> {
-        Ok(serde_json::from_slice(raw)?)
+        codec::decode_event(raw)
     }
 }
 
@@ -350,33 +1015,36 @@ impl LmdbStore {
 // ---------------------------------------------------------------------------
 
 impl NostrStore for LmdbStore {
-    fn save_event(&self, event: &Event) -> Result<()> {
-        let mut wtxn = self.env.write_txn()?;
-
-        // Duplicate check
-        let id_bytes = event.id.as_bytes();
-        if self.events_db.get(&wtxn, id_bytes)?.is_some() {
-            return Ok(());
-        }
+    fn save_event(&self, event: &Event) -> Result<SaveResult> {
+        let results = self.save_events_with_results(std::slice::from_ref(event))?;
+        Ok(results.first().copied().unwrap_or(SaveResult::Duplicate))
+    }
 
-        // Replaceable event handling (NIP-01)
-        if self.handle_replaceable(&mut wtxn, event)? {
-            return Ok(());
-        }
+    fn save_events(&self, events: &[Event]) -> Result<()> {
+        self.save_events_with_results(events).map(|_| ())
+    }
 
-        // Serialize once, store raw JSON bytes
-        let raw = serde_json::to_vec(event)?;
-        self.events_db.put(&mut wtxn, id_bytes, &raw)?;
-
-        // Write all indices
-        self.insert_indices(&mut wtxn, event)?;
-
-        wtxn.commit()?;
-        Ok(())
-    }
+    fn save_events_batch(&self, events: Vec<Event>) -> BoxFuture<'_, Result<Vec<SaveResult>>> {
+        Box::pin(async move {
+            let Some(writer) = &self.writer else {
+                return self.write_events(&events);
+            };
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let req = WriteRequest {
+                events,
+                reply: WriteReply::Async(tx),
+            };
+            // The queue is bounded; wait for room off the async workers
+            let sender = writer.sender().clone();
+            tokio::task::spawn_blocking(move || sender.send(req))
+                .await?
+                .map_err(|_| Error::WriterStopped)?;
+            rx.await.map_err(|_| Error::WriterStopped)?
+        })
+    }
 
     fn get_event(&self, id: &[u8; 32]) -> Result<Option<Event>> {
-        let rtxn = self.env.read_txn()?;
+        let (rtxn, _reader) = self.read_txn("get_event", false)?;
         match self.events_db.get(&rtxn, id)? {
             Some(raw) => Ok(Some(Self::decode_event(raw)?)),
             None => Ok(None),
@@ -384,14 +1052,14 @@ impl NostrStore for LmdbStore {
     }
 
     fn delete_event(&self, id: &[u8; 32]) -> Result<bool> {
-        let mut wtxn = self.env.write_txn()?;
+        let mut wtxn = self.write_txn()?;
         let deleted = self.delete_event_txn(&mut wtxn, id)?;
         wtxn.commit()?;
         Ok(deleted)
     }
 
     fn iter_all(&self) -> Result<Vec<Event>> {
-        let rtxn = self.env.read_txn()?;
+        let (rtxn, _reader) = self.read_txn("iter_all", false)?;
         let mut events = Vec::new();
         let iter = self.events_db.iter(&rtxn)?;
         for result in iter {
@@ -402,8 +1070,87 @@ impl NostrStore for LmdbStore {
         Ok(events)
     }
 
+    fn for_each_event(&self, f: &mut dyn FnMut(&Event)) -> Result<()> {
+        let (rtxn, _reader) = self.read_txn("for_each_event", false)?;
+        for result in self.events_db.iter(&rtxn)? {
+            let (_key, raw) = result?;
+            f(&Self::decode_event(raw)?);
+        }
+        Ok(())
+    }
+
+    fn delete_author(&self, author: &PublicKey) -> Result<u64> {
+        let (start, end) = Self::author_bounds(author, 0, u64::MAX);
+        let mut wtxn = self.write_txn()?;
+        let mut ids: Vec<[u8; 32]> = Vec::new();
+        for result in self.index_author.range(&wtxn, &ByteRange::new(&start, &end))? {
+            let (key, _) = result?;
+            if key.len() >= AUTHOR_KEY_LEN {
+                ids.push(key[40..72].try_into().unwrap());
+            }
+        }
+        let mut deleted = 0;
+        for id in &ids {
+            if self.delete_event_txn(&mut wtxn, id)? {
+                deleted += 1;
+            }
+        }
+        wtxn.commit()?;
+        Ok(deleted)
+    }
+
+    fn kind_counts(&self) -> Result<Vec<(u16, u64)>> {
+        // Kind index keys start with the kind, so equal kinds are adjacent
+        let (rtxn, _reader) = self.read_txn("kind_counts", false)?;
+        let mut counts: Vec<(u16, u64)> = Vec::new();
+        for result in self.index_kind.iter(&rtxn)? {
+            let (key, _) = result?;
+            if key.len() < KIND_KEY_LEN {
+                continue;
+            }
+            let kind = u16::from_be_bytes([key[0], key[1]]);
+            match counts.last_mut() {
+                Some((last, count)) if *last == kind => *count += 1,
+                _ => counts.push((kind, 1)),
+            }
+        }
+        Ok(counts)
+    }
+
+    fn purge(&self) -> Result<u64> {
+        let before = self.env.non_free_pages_size()?;
+        let mut wtxn = self.write_txn()?;
+        self.events_db.clear(&mut wtxn)?;
+        self.index_created.clear(&mut wtxn)?;
+        self.index_author.clear(&mut wtxn)?;
+        self.index_kind.clear(&mut wtxn)?;
+        self.index_tag.clear(&mut wtxn)?;
+        self.index_author_kind.clear(&mut wtxn)?;
+        self.index_address.clear(&mut wtxn)?;
+        self.tombstones.clear(&mut wtxn)?;
+        wtxn.commit()?;
+        let after = self.env.non_free_pages_size()?;
+        Ok(before.saturating_sub(after))
+    }
+
+    fn snapshot_to(&self, dest: &Path) -> Result<()> {
+        self.compact_to(dest)
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Relaxed);
+        // Wake the writer thread so it sees the flag and exits
+        if let Some(writer) = &self.writer {
+            let (tx, _) = std::sync::mpsc::sync_channel(1);
+            let _ = writer.sender().try_send(WriteRequest {
+                events: Vec::new(),
+                reply: WriteReply::Blocking(tx),
+            });
+        }
+    }
+
     fn event_count(&self) -> Result<u64> {
-        let rtxn = self.env.read_txn()?;
+        let (rtxn, _reader) = self.read_txn("event_count", false)?;
         let stat = self.events_db.stat(&rtxn)?;
         Ok(stat.entries as u64)
     }
@@ -412,9 +1159,31 @@ impl NostrStore for LmdbStore {
         &self.path
     }
 
+    fn read_txns(&self) -> ReadTxnStats {
+        self.readers.stats()
+    }
+
+    fn stop_slow_reads(&self, max: Duration) -> usize {
+        self.readers.enforce(max, &self.path)
+    }
+
+    fn max_limit(&self) -> Option<usize> {
+        self.max_limit
+    }
+
     fn query(&self, filter: &Filter) -> Result<Vec<Event>> {
-        let rtxn = self.env.read_txn()?;
+        Ok(self.query_until(filter, None)?.0)
+    }
+
+    fn query_until(&self, filter: &Filter, deadline: Option<Instant>) -> Result<(Vec<Event>, bool)> {
+        self.query_req(&ReqFilter::from(filter.clone()), deadline)
+    }
+
+    fn query_req(&self, filter: &ReqFilter, deadline: Option<Instant>) -> Result<(Vec<Event>, bool)> {
+        let (rtxn, reader) = self.read_txn("query", true)?;
+        let mut deadline = ScanDeadline::new(deadline, reader.abort_flag());
         let limit = filter.limit.unwrap_or(100);
+        let limit = self.max_limit.map_or(limit, |max| limit.min(max));
         let since_ts = filter.since.map(|s| s.as_u64()).unwrap_or(0);
         let until_ts = filter.until.map(|u| u.as_u64()).unwrap_or(u64::MAX);
 
@@ -431,114 +1200,296 @@ impl NostrStore for LmdbStore {
                     }
                 }
             }
-            events.sort_unstable_by(|a, b| b.created_at.cmp(&a.created_at));
+            events.sort_unstable_by(newest_first);
             events.truncate(limit);
-            return Ok(events);
+            return Ok((events, false));
+        }
+
+        // -----------------------------------------------------------------
+        // 2. Addressable events by author + kind + d-tag
+        // -----------------------------------------------------------------
+        if let Some(keys) = Self::address_lookup_keys(filter) {
+            let mut events = Vec::new();
+            for key in keys {
+                let Some(value) = self.index_address.get(&rtxn, &key)? else {
+                    continue;
+                };
+                if let Some(raw) = self.events_db.get(&rtxn, &value[8..40])? {
+                    let event = Self::decode_event(raw)?;
+                    if self.event_matches_filter(&event, filter) {
+                        events.push(event);
+                    }
+                }
+            }
+            events.sort_unstable_by(newest_first);
+            events.truncate(limit);
+            return Ok((events, false));
         }
 
         let mut candidates: Vec<Event> = Vec::new();
 
         // -----------------------------------------------------------------
-        // 2. Author + Kind compound index (most common Nostr query)
+        // 3. Otherwise drive the scan from the most selective index; the
+        //    remaining filter fields are checked on each candidate.
         // -----------------------------------------------------------------
-        if let (Some(authors), Some(kinds)) = (&filter.authors, &filter.kinds) {
-            for pubkey in authors {
-                for kind in kinds {
-                    self.scan_author_kind_index(
+        let tags = filter.tag_conditions();
+        match self.plan_query(&rtxn, filter, &tags, since_ts, until_ts)? {
+            QueryPlan::AuthorKind => {
+                for pubkey in filter.authors.iter().flatten() {
+                    for kind in filter.kinds.iter().flatten() {
+                        self.scan_author_kind_index(
+                            &rtxn,
+                            pubkey,
+                            kind,
+                            since_ts,
+                            until_ts,
+                            limit,
+                            filter,
+                            &mut candidates,
+                            &mut deadline,
+                        )?;
+                    }
+                }
+            }
+            QueryPlan::Author => {
+                for pubkey in filter.authors.iter().flatten() {
+                    self.scan_author_index(
                         &rtxn,
                         pubkey,
-                        kind,
                         since_ts,
                         until_ts,
                         limit,
                         filter,
                         &mut candidates,
+                        &mut deadline,
                     )?;
                 }
             }
-        }
-        // -----------------------------------------------------------------
-        // 3. Author index
-        // -----------------------------------------------------------------
-        else if let Some(authors) = &filter.authors {
-            for pubkey in authors {
-                self.scan_author_index(
-                    &rtxn,
-                    pubkey,
-                    since_ts,
-                    until_ts,
-                    limit,
-                    filter,
-                    &mut candidates,
-                )?;
-            }
-        }
-        // -----------------------------------------------------------------
-        // 4. Kind index
-        // -----------------------------------------------------------------
-        else if let Some(kinds) = &filter.kinds {
-            for kind in kinds {
-                self.scan_kind_index(
-                    &rtxn,
-                    kind,
-                    since_ts,
-                    until_ts,
-                    limit,
-                    filter,
-                    &mut candidates,
-                )?;
+            QueryPlan::Kind => {
+                for kind in filter.kinds.iter().flatten() {
+                    self.scan_kind_index(
+                        &rtxn,
+                        kind,
+                        since_ts,
+                        until_ts,
+                        limit,
+                        filter,
+                        &mut candidates,
+                        &mut deadline,
+                    )?;
+                }
             }
-        }
-        // -----------------------------------------------------------------
-        // 5. Tag index
-        // -----------------------------------------------------------------
-        else if !filter.generic_tags.is_empty() {
-            if let Some((tag_char, values)) = filter.generic_tags.iter().next() {
-                let tc = tag_char.to_string();
+            QueryPlan::Tag(i) => {
+                let (name, values) = &tags[i];
                 for value in values {
                     self.scan_tag_index(
                         &rtxn,
-                        &tc,
+                        name,
                         value,
                         since_ts,
                         until_ts,
                         limit,
                         filter,
                         &mut candidates,
+                        &mut deadline,
                     )?;
                 }
             }
-        }
-        // -----------------------------------------------------------------
-        // 6. Global scan (index_created)
-        // -----------------------------------------------------------------
-        else {
-            self.scan_created_index(&rtxn, since_ts, until_ts, limit, filter, &mut candidates)?;
+            QueryPlan::TagIntersection(mask) => {
+                self.scan_tag_intersection(&rtxn, &tags, mask, since_ts, until_ts, filter, &mut candidates, &mut deadline)?;
+            }
+            QueryPlan::Created => {
+                self.scan_created_index(&rtxn, since_ts, until_ts, limit, filter, &mut candidates, &mut deadline)?;
+            }
         }
 
-        candidates.sort_unstable_by(|a, b| b.created_at.cmp(&a.created_at));
+        candidates.sort_unstable_by(newest_first);
         candidates.truncate(limit);
-        Ok(candidates)
+        Ok((candidates, deadline.hit))
     }
 }
 
 // ---------------------------------------------------------------------------
-// Query scan helpers — each seeks directly to the `until` boundary
-// Uses rev_range for reverse iteration (heed 0.20 API)
+// Query planning — pick the index with the fewest entries in range
 // ---------------------------------------------------------------------------
 
 impl LmdbStore {
-    fn scan_author_kind_index(
+    /// Address index keys answering `filter`, if it names authors, only
+    /// parameterized replaceable kinds and `#d` values. Events are addressed
+    /// by their first d-tag.
+    fn address_lookup_keys(filter: &Filter) -> Option<Vec<[u8; ADDRESS_KEY_LEN]>> {
+        let authors = filter.authors.as_ref().filter(|a| !a.is_empty())?;
+        let kinds = filter.kinds.as_ref().filter(|k| !k.is_empty())?;
+        let d_tags = filter.generic_tags.get(&SingleLetterTag::lowercase(Alphabet::D))?;
+        if d_tags.is_empty()
+            || !kinds.iter().all(|k| is_parameterized_replaceable(k.as_u16()))
+            || authors.len() * kinds.len() * d_tags.len() > MAX_ADDRESS_LOOKUPS
+        {
+            return None;
+        }
+        let mut keys = Vec::with_capacity(authors.len() * kinds.len() * d_tags.len());
+        for pubkey in authors {
+            for kind in kinds {
+                for d_tag in d_tags {
+                    keys.push(Self::encode_address_key(pubkey, kind, d_tag));
+                }
+            }
+        }
+        Some(keys)
+    }
+}
+
+/// Index a query scan is driven by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QueryPlan {
+    AuthorKind,
+    Author,
+    Kind,
+    /// Position in the filter's tag conditions.
+    Tag(usize),
+    /// Ids read from the tag index for each tag condition whose bit is set,
+    /// intersected before any event is fetched.
+    TagIntersection(u64),
+    Created,
+}
+
+/// Index entries counted per candidate plan before giving up on precision;
+/// beyond this a plan is simply "large".
+const ESTIMATE_CAP: usize = 1000;
+
+impl LmdbStore {
+    /// Choose the index to drive a (non-id) query. Authors and kinds map to
+    /// a single candidate (the compound index when both are present); each
+    /// indexed tag name is another. With more than one candidate, the number of
+    /// index entries in the time range is counted for each (up to
+    /// `ESTIMATE_CAP`) and the smallest wins, so e.g. `{authors, #e}` reads
+    /// only the handful of events referencing the thread root instead of
+    /// all of the authors' events. When the winner is a tag and other tag
+    /// conditions are small enough to read in full too (`{#e, #p}` on a
+    /// thread), their ids are intersected first so only events carrying
+    /// all of them are fetched.
+    fn plan_query(
         &self,
         rtxn: &heed::RoTxn,
+        filter: &Filter,
+        tags: &[(String, Vec<&str>)],
+        since_ts: u64,
+        until_ts: u64,
+    ) -> Result<QueryPlan> {
+        let mut options = Vec::new();
+        match (&filter.authors, &filter.kinds) {
+            (Some(_), Some(_)) => options.push(QueryPlan::AuthorKind),
+            (Some(_), None) => options.push(QueryPlan::Author),
+            (None, Some(_)) => options.push(QueryPlan::Kind),
+            (None, None) => {}
+        }
+        options.extend(
+            tags.iter()
+                .enumerate()
+                .filter(|(_, (name, values))| values.iter().all(|value| indexed_tag(name, value)))
+                .map(|(i, _)| QueryPlan::Tag(i)),
+        );
+
+        if options.len() <= 1 {
+            return Ok(options.pop().unwrap_or(QueryPlan::Created));
+        }
+
+        let mut best = options[0];
+        let mut best_count = ESTIMATE_CAP;
+        for &plan in &options {
+            if best_count == 0 {
+                break;
+            }
+            let count = self.estimate_plan(rtxn, filter, tags, plan, since_ts, until_ts, best_count)?;
+            if count < best_count {
+                best = plan;
+                best_count = count;
+            }
+        }
+
+        if let QueryPlan::Tag(first @ 0..=63) = best {
+            if best_count > 0 && best_count < ESTIMATE_CAP {
+                let mut mask = 1u64 << first;
+                for &plan in &options {
+                    let QueryPlan::Tag(i @ 0..=63) = plan else {
+                        continue;
+                    };
+                    if i != first && self.estimate_plan(rtxn, filter, tags, plan, since_ts, until_ts, ESTIMATE_CAP)? < ESTIMATE_CAP {
+                        mask |= 1 << i;
+                    }
+                }
+                if mask.count_ones() > 1 {
+                    return Ok(QueryPlan::TagIntersection(mask));
+                }
+            }
+        }
+        Ok(best)
+    }
+
+    /// Count index entries `plan` would scan, stopping at `cap`.
+    #[allow(clippy::too_many_arguments)]
+    fn estimate_plan(
+        &self,
+        rtxn: &heed::RoTxn,
+        filter: &Filter,
+        tags: &[(String, Vec<&str>)],
+        plan: QueryPlan,
+        since_ts: u64,
+        until_ts: u64,
+        cap: usize,
+    ) -> Result<usize> {
+        let mut total = 0;
+        match plan {
+            QueryPlan::AuthorKind => {
+                for pubkey in filter.authors.iter().flatten() {
+                    for kind in filter.kinds.iter().flatten() {
+                        let (start, end) = Self::author_kind_bounds(pubkey, kind, since_ts, until_ts);
+                        total += count_keys(&self.index_author_kind, rtxn, &start, &end, cap - total)?;
+                        if total >= cap {
+                            return Ok(total);
+                        }
+                    }
+                }
+            }
+            QueryPlan::Author => {
+                for pubkey in filter.authors.iter().flatten() {
+                    let (start, end) = Self::author_bounds(pubkey, since_ts, until_ts);
+                    total += count_keys(&self.index_author, rtxn, &start, &end, cap - total)?;
+                    if total >= cap {
+                        return Ok(total);
+                    }
+                }
+            }
+            QueryPlan::Kind => {
+                for kind in filter.kinds.iter().flatten() {
+                    let (start, end) = Self::kind_bounds(kind, since_ts, until_ts);
+                    total += count_keys(&self.index_kind, rtxn, &start, &end, cap - total)?;
+                    if total >= cap {
+                        return Ok(total);
+                    }
+                }
+            }
+            QueryPlan::Tag(i) => {
+                let (name, values) = &tags[i];
+                for value in values {
+                    let (start, end) = Self::tag_bounds(name, value, since_ts, until_ts);
+                    total += count_keys(&self.index_tag, rtxn, &start, &end, cap - total)?;
+                    if total >= cap {
+                        return Ok(total);
+                    }
+                }
+            }
+            QueryPlan::TagIntersection(_) | QueryPlan::Created => return Ok(cap),
+        }
+        Ok(total)
+    }
+
+    fn author_kind_bounds(
         pubkey: &PublicKey,
         kind: &Kind,
         since_ts: u64,
         until_ts: u64,
-        limit: usize,
-        filter: &Filter,
-        candidates: &mut Vec<Event>,
-    ) -> Result<()> {
+    ) -> ([u8; AUTHOR_KIND_KEY_LEN], [u8; AUTHOR_KIND_KEY_LEN]) {
         let mut start = [0u8; AUTHOR_KIND_KEY_LEN];
         start[..32].copy_from_slice(pubkey.to_bytes().as_ref());
         start[32..34].copy_from_slice(&kind.as_u16().to_be_bytes());
@@ -548,31 +1499,124 @@ impl LmdbStore {
         end[..32].copy_from_slice(pubkey.to_bytes().as_ref());
         end[32..34].copy_from_slice(&kind.as_u16().to_be_bytes());
         end[34..42].copy_from_slice(&until_ts.to_be_bytes());
+        (start, end)
+    }
+
+    fn author_bounds(
+        pubkey: &PublicKey,
+        since_ts: u64,
+        until_ts: u64,
+    ) -> ([u8; AUTHOR_KEY_LEN], [u8; AUTHOR_KEY_LEN]) {
+        let mut start = [0u8; AUTHOR_KEY_LEN];
+        start[..32].copy_from_slice(pubkey.to_bytes().as_ref());
+        start[32..40].copy_from_slice(&since_ts.to_be_bytes());
 
+        let mut end = [0xffu8; AUTHOR_KEY_LEN];
+        end[..32].copy_from_slice(pubkey.to_bytes().as_ref());
+        end[32..40].copy_from_slice(&until_ts.to_be_bytes());
+        (start, end)
+    }
+
+    fn kind_bounds(kind: &Kind, since_ts: u64, until_ts: u64) -> ([u8; KIND_KEY_LEN], [u8; KIND_KEY_LEN]) {
+        let mut start = [0u8; KIND_KEY_LEN];
+        start[..2].copy_from_slice(&kind.as_u16().to_be_bytes());
+        start[2..10].copy_from_slice(&since_ts.to_be_bytes());
+
+        let mut end = [0xffu8; KIND_KEY_LEN];
+        end[..2].copy_from_slice(&kind.as_u16().to_be_bytes());
+        end[2..10].copy_from_slice(&until_ts.to_be_bytes());
+        (start, end)
+    }
+
+    fn tag_bounds(tag_key: &str, tag_val: &str, since_ts: u64, until_ts: u64) -> (Vec<u8>, Vec<u8>) {
+        let mut start = Vec::with_capacity(tag_key.len() + 1 + tag_val.len() + 1 + 40);
+        start.extend_from_slice(tag_key.as_bytes());
+        start.push(0);
+        start.extend_from_slice(tag_val.as_bytes());
+        start.push(0);
+        start.extend_from_slice(&since_ts.to_be_bytes());
+        start.extend_from_slice(&[0u8; 32]);
+
+        let mut end = Vec::with_capacity(tag_key.len() + 1 + tag_val.len() + 1 + 40);
+        end.extend_from_slice(tag_key.as_bytes());
+        end.push(0);
+        end.extend_from_slice(tag_val.as_bytes());
+        end.push(0);
+        end.extend_from_slice(&until_ts.to_be_bytes());
+        end.extend_from_slice(&[0xffu8; 32]);
+        (start, end)
+    }
+}
+
+/// Number of keys in `[start, end]`, counting at most `cap`.
+fn count_keys(
+    db: &Database<Bytes, Unit>,
+    rtxn: &heed::RoTxn,
+    start: &[u8],
+    end: &[u8],
+    cap: usize,
+) -> Result<usize> {
+    let range = ByteRange::new(start, end);
+    let mut count = 0;
+    for result in db.range(rtxn, &range)? {
+        result?;
+        count += 1;
+        if count >= cap {
+            break;
+        }
+    }
+    Ok(count)
+}
+
+// ---------------------------------------------------------------------------
+// Query scan helpers — each seeks directly to the `until` boundary
+// Uses rev_range for reverse iteration (heed 0.20 API)
+// ---------------------------------------------------------------------------
+
+impl LmdbStore {
+    #[allow(clippy::too_many_arguments)]
+    fn scan_author_kind_index(
+        &self,
+        rtxn: &heed::RoTxn,
+        pubkey: &PublicKey,
+        kind: &Kind,
+        since_ts: u64,
+        until_ts: u64,
+        limit: usize,
+        filter: &ReqFilter,
+        candidates: &mut Vec<Event>,
+        deadline: &mut ScanDeadline,
+    ) -> Result<()> {
+        let (start, end) = Self::author_kind_bounds(pubkey, kind, since_ts, until_ts);
         let range = ByteRange::new(&start, &end);
         let iter = self.index_author_kind.rev_range(rtxn, &range)?;
-        let mut count = 0;
+        let mut limit = ScanLimit::new(limit);
 
         for result in iter {
+            if deadline.expired() {
+                break;
+            }
             let (key, _) = result?;
             if key.len() < AUTHOR_KIND_KEY_LEN {
                 continue;
             }
+            let created_at = key_ts(key, 34);
+            if limit.done(created_at) {
+                break;
+            }
             let id_bytes = &key[42..74];
             if let Some(raw) = self.events_db.get(rtxn, id_bytes)? {
                 let event = Self::decode_event(raw)?;
                 if self.event_matches_tags_only(&event, filter) {
                     candidates.push(event);
-                    count += 1;
+                    limit.matched(created_at);
                 }
             }
-            if count >= limit {
-                break;
-            }
         }
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn scan_author_index(
         &self,
         rtxn: &heed::RoTxn,
@@ -580,41 +1624,40 @@ impl LmdbStore {
         since_ts: u64,
         until_ts: u64,
         limit: usize,
-        filter: &Filter,
+        filter: &ReqFilter,
         candidates: &mut Vec<Event>,
+        deadline: &mut ScanDeadline,
     ) -> Result<()> {
-        let mut start = [0u8; AUTHOR_KEY_LEN];
-        start[..32].copy_from_slice(pubkey.to_bytes().as_ref());
-        start[32..40].copy_from_slice(&since_ts.to_be_bytes());
-
-        let mut end = [0xffu8; AUTHOR_KEY_LEN];
-        end[..32].copy_from_slice(pubkey.to_bytes().as_ref());
-        end[32..40].copy_from_slice(&until_ts.to_be_bytes());
-
+        let (start, end) = Self::author_bounds(pubkey, since_ts, until_ts);
         let range = ByteRange::new(&start, &end);
         let iter = self.index_author.rev_range(rtxn, &range)?;
-        let mut count = 0;
+        let mut limit = ScanLimit::new(limit);
 
         for result in iter {
+            if deadline.expired() {
+                break;
+            }
             let (key, _) = result?;
             if key.len() < AUTHOR_KEY_LEN {
                 continue;
             }
+            let created_at = key_ts(key, 32);
+            if limit.done(created_at) {
+                break;
+            }
             let id_bytes = &key[40..72];
             if let Some(raw) = self.events_db.get(rtxn, id_bytes)? {
                 let event = Self::decode_event(raw)?;
                 if self.event_matches_no_author(&event, filter) {
                     candidates.push(event);
-                    count += 1;
+                    limit.matched(created_at);
                 }
             }
-            if count >= limit {
-                break;
-            }
         }
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn scan_kind_index(
         &self,
         rtxn: &heed::RoTxn,
@@ -622,41 +1665,40 @@ impl LmdbStore {
         since_ts: u64,
         until_ts: u64,
         limit: usize,
-        filter: &Filter,
+        filter: &ReqFilter,
         candidates: &mut Vec<Event>,
+        deadline: &mut ScanDeadline,
     ) -> Result<()> {
-        let mut start = [0u8; KIND_KEY_LEN];
-        start[..2].copy_from_slice(&kind.as_u16().to_be_bytes());
-        start[2..10].copy_from_slice(&since_ts.to_be_bytes());
-
-        let mut end = [0xffu8; KIND_KEY_LEN];
-        end[..2].copy_from_slice(&kind.as_u16().to_be_bytes());
-        end[2..10].copy_from_slice(&until_ts.to_be_bytes());
-
+        let (start, end) = Self::kind_bounds(kind, since_ts, until_ts);
         let range = ByteRange::new(&start, &end);
         let iter = self.index_kind.rev_range(rtxn, &range)?;
-        let mut count = 0;
+        let mut limit = ScanLimit::new(limit);
 
         for result in iter {
+            if deadline.expired() {
+                break;
+            }
             let (key, _) = result?;
             if key.len() < KIND_KEY_LEN {
                 continue;
             }
+            let created_at = key_ts(key, 2);
+            if limit.done(created_at) {
+                break;
+            }
             let id_bytes = &key[10..42];
             if let Some(raw) = self.events_db.get(rtxn, id_bytes)? {
                 let event = Self::decode_event(raw)?;
                 if self.event_matches_no_kind(&event, filter) {
                     candidates.push(event);
-                    count += 1;
+                    limit.matched(created_at);
                 }
             }
-            if count >= limit {
-                break;
-            }
         }
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn scan_tag_index(
         &self,
         rtxn: &heed::RoTxn,
@@ -665,57 +1707,105 @@ impl LmdbStore {
         since_ts: u64,
         until_ts: u64,
         limit: usize,
-        filter: &Filter,
+        filter: &ReqFilter,
         candidates: &mut Vec<Event>,
+        deadline: &mut ScanDeadline,
     ) -> Result<()> {
-        let mut start = Vec::with_capacity(tag_key.len() + 1 + tag_val.len() + 1 + 40);
-        start.extend_from_slice(tag_key.as_bytes());
-        start.push(0);
-        start.extend_from_slice(tag_val.as_bytes());
-        start.push(0);
-        start.extend_from_slice(&since_ts.to_be_bytes());
-        start.extend_from_slice(&[0u8; 32]);
-
-        let mut end = Vec::with_capacity(tag_key.len() + 1 + tag_val.len() + 1 + 40);
-        end.extend_from_slice(tag_key.as_bytes());
-        end.push(0);
-        end.extend_from_slice(tag_val.as_bytes());
-        end.push(0);
-        end.extend_from_slice(&until_ts.to_be_bytes());
-        end.extend_from_slice(&[0xffu8; 32]);
-
+        let (start, end) = Self::tag_bounds(tag_key, tag_val, since_ts, until_ts);
         let range = ByteRange::new(&start, &end);
         let iter = self.index_tag.rev_range(rtxn, &range)?;
-        let mut count = 0;
+        let mut limit = ScanLimit::new(limit);
 
         for result in iter {
+            if deadline.expired() {
+                break;
+            }
             let (key, _) = result?;
             if key.len() < 40 {
                 continue;
             }
+            let created_at = key_ts(key, key.len() - 40);
+            if limit.done(created_at) {
+                break;
+            }
             let id_bytes = &key[key.len() - 32..];
             if let Some(raw) = self.events_db.get(rtxn, id_bytes)? {
                 let event = Self::decode_event(raw)?;
                 if self.event_matches_filter(&event, filter) {
                     candidates.push(event);
-                    count += 1;
+                    limit.matched(created_at);
                 }
             }
-            if count >= limit {
+        }
+        Ok(())
+    }
+
+    /// Events carrying every tag condition in `mask`. Each condition's ids
+    /// in the time range are read from the tag index (the planner made sure
+    /// there are few) and narrowed down before any event is fetched.
+    #[allow(clippy::too_many_arguments)]
+    fn scan_tag_intersection(
+        &self,
+        rtxn: &heed::RoTxn,
+        tags: &[(String, Vec<&str>)],
+        mask: u64,
+        since_ts: u64,
+        until_ts: u64,
+        filter: &ReqFilter,
+        candidates: &mut Vec<Event>,
+        deadline: &mut ScanDeadline,
+    ) -> Result<()> {
+        // Event id → created_at of the events matching every tag so far
+        let mut ids: Option<HashMap<[u8; 32], u64>> = None;
+        for (i, (name, values)) in tags.iter().enumerate().take(64) {
+            if mask & (1 << i) == 0 {
+                continue;
+            }
+            let mut found = HashMap::new();
+            for value in values {
+                let (start, end) = Self::tag_bounds(name, value, since_ts, until_ts);
+                for result in self.index_tag.range(rtxn, &ByteRange::new(&start, &end))? {
+                    let (key, _) = result?;
+                    if key.len() < 40 {
+                        continue;
+                    }
+                    let id: [u8; 32] = key[key.len() - 32..].try_into().unwrap();
+                    if ids.as_ref().is_none_or(|ids| ids.contains_key(&id)) {
+                        found.insert(id, key_ts(key, key.len() - 40));
+                    }
+                }
+            }
+            ids = Some(found);
+        }
+
+        // Newest first, so a scan cut short by the deadline keeps the most
+        // recent matches
+        let mut ids: Vec<([u8; 32], u64)> = ids.unwrap_or_default().into_iter().collect();
+        ids.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (id, _) in ids {
+            if deadline.expired() {
                 break;
             }
+            if let Some(raw) = self.events_db.get(rtxn, &id)? {
+                let event = Self::decode_event(raw)?;
+                if self.event_matches_filter(&event, filter) {
+                    candidates.push(event);
+                }
+            }
         }
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn scan_created_index(
         &self,
         rtxn: &heed::RoTxn,
         since_ts: u64,
         until_ts: u64,
         limit: usize,
-        filter: &Filter,
+        filter: &ReqFilter,
         candidates: &mut Vec<Event>,
+        deadline: &mut ScanDeadline,
     ) -> Result<()> {
         let mut start = [0u8; CREATED_KEY_LEN];
         start[..8].copy_from_slice(&since_ts.to_be_bytes());
@@ -725,24 +1815,28 @@ impl LmdbStore {
 
         let range = ByteRange::new(&start, &end);
         let iter = self.index_created.rev_range(rtxn, &range)?;
-        let mut count = 0;
+        let mut limit = ScanLimit::new(limit);
 
         for result in iter {
+            if deadline.expired() {
+                break;
+            }
             let (key, _) = result?;
             if key.len() < CREATED_KEY_LEN {
                 continue;
             }
+            let created_at = key_ts(key, 0);
+            if limit.done(created_at) {
+                break;
+            }
             let id_bytes = &key[8..40];
             if let Some(raw) = self.events_db.get(rtxn, id_bytes)? {
                 let event = Self::decode_event(raw)?;
                 if self.event_matches_filter(&event, filter) {
                     candidates.push(event);
-                    count += 1;
+                    limit.matched(created_at);
                 }
             }
-            if count >= limit {
-                break;
-            }
         }
         Ok(())
     }
@@ -754,7 +1848,7 @@ impl LmdbStore {
 
 impl LmdbStore {
     /// Full filter match (used when no index dimension can be skipped).
-    fn event_matches_filter(&self, event: &Event, filter: &Filter) -> bool {
+    fn event_matches_filter(&self, event: &Event, filter: &ReqFilter) -> bool {
         if let Some(ids) = &filter.ids {
             if !ids.contains(&event.id) {
                 return false;
@@ -784,7 +1878,7 @@ impl LmdbStore {
     }
 
     /// Skips author check (used when scanning author index).
-    fn event_matches_no_author(&self, event: &Event, filter: &Filter) -> bool {
+    fn event_matches_no_author(&self, event: &Event, filter: &ReqFilter) -> bool {
         if let Some(kinds) = &filter.kinds {
             if !kinds.contains(&event.kind) {
                 return false;
@@ -794,7 +1888,7 @@ impl LmdbStore {
     }
 
     /// Skips kind check (used when scanning kind index).
-    fn event_matches_no_kind(&self, event: &Event, filter: &Filter) -> bool {
+    fn event_matches_no_kind(&self, event: &Event, filter: &ReqFilter) -> bool {
         if let Some(authors) = &filter.authors {
             if !authors.contains(&event.pubkey) {
                 return false;
@@ -804,22 +1898,23 @@ impl LmdbStore {
     }
 
     /// Skips author + kind + time checks (compound author_kind index with time in range).
-    fn event_matches_tags_only(&self, event: &Event, filter: &Filter) -> bool {
+    fn event_matches_tags_only(&self, event: &Event, filter: &ReqFilter) -> bool {
         self.check_tags(event, filter)
     }
 
-    /// Check generic_tags portion of the filter.
-    fn check_tags(&self, event: &Event, filter: &Filter) -> bool {
+    /// Check the tag conditions of the filter.
+    fn check_tags(&self, event: &Event, filter: &ReqFilter) -> bool {
+        if !filter.matches_long_tags(event) {
+            return false;
+        }
         for (tag_char, allowed_values) in &filter.generic_tags {
             let char_key = tag_char.to_string();
             let mut found = false;
             for t in &event.tags {
                 let t_vec = t.as_vec();
-                if t_vec.len() >= 2 && t_vec[0] == char_key {
-                    if allowed_values.contains(&t_vec[1]) {
-                        found = true;
-                        break;
-                    }
+                if t_vec.len() >= 2 && t_vec[0] == char_key && allowed_values.contains(&t_vec[1]) {
+                    found = true;
+                    break;
                 }
             }
             if !found {
@@ -851,3 +1946,634 @@ impl LmdbStore {
         key
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{EventBuilder, Keys};
+
+    #[test]
+    fn closed_store_refuses_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        let note = |content: &str| EventBuilder::text_note(content, []).to_event(&keys).unwrap();
+        let kept = note("kept");
+        store.save_event(&kept).unwrap();
+
+        store.close();
+        assert!(matches!(store.save_event(&note("lost")), Err(Error::Closed | Error::WriterStopped)));
+        assert!(matches!(store.delete_event(kept.id.as_bytes()), Err(Error::Closed)));
+        assert_eq!(store.event_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn exclusive_open_waits_for_every_other_user() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        assert!(matches!(LmdbStore::open_exclusive(dir.path()), Err(Error::InUse(_))));
+        drop(store);
+
+        let exclusive = LmdbStore::open_exclusive(dir.path()).unwrap();
+        assert!(matches!(LmdbStore::new(dir.path()), Err(Error::InUse(_))));
+        drop(exclusive);
+        LmdbStore::new(dir.path()).unwrap();
+    }
+
+    #[test]
+    fn query_until_stops_scanning_after_deadline() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        for i in 0..200 {
+            let event = EventBuilder::text_note(format!("note {}", i), [])
+                .to_event(&keys)
+                .unwrap();
+            store.save_event(&event).unwrap();
+        }
+
+        let filter = Filter::new().limit(500);
+        let (events, timed_out) = store.query_until(&filter, None).unwrap();
+        assert_eq!(events.len(), 200);
+        assert!(!timed_out);
+
+        let (events, timed_out) = store.query_until(&filter, Some(Instant::now())).unwrap();
+        assert!(timed_out);
+        assert!(events.len() < 200);
+    }
+
+    #[test]
+    fn save_reports_duplicates_and_replacements() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        let profile = |at: u64| {
+            EventBuilder::new(Kind::Metadata, "{}", [])
+                .custom_created_at(nostr::Timestamp::from(at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        let (old, new) = (profile(1_000), profile(2_000));
+
+        assert_eq!(store.save_event(&old).unwrap(), SaveResult::Saved);
+        assert_eq!(store.save_event(&old).unwrap(), SaveResult::Duplicate);
+        assert_eq!(store.save_event(&new).unwrap(), SaveResult::Replaced);
+        assert_eq!(store.save_event(&profile(1_500)).unwrap(), SaveResult::RejectedStale);
+
+        let list = |d: &str, at: u64| {
+            EventBuilder::new(Kind::from(30000), "", [nostr::Tag::identifier(d)])
+                .custom_created_at(nostr::Timestamp::from(at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        assert_eq!(store.save_event(&list("a", 2_000)).unwrap(), SaveResult::Saved);
+        assert_eq!(store.save_event(&list("b", 1_000)).unwrap(), SaveResult::Saved);
+        assert_eq!(store.save_event(&list("a", 1_000)).unwrap(), SaveResult::RejectedStale);
+        assert_eq!(store.save_event(&list("a", 3_000)).unwrap(), SaveResult::Replaced);
+    }
+
+    #[test]
+    fn query_multi_dedups_and_applies_combined_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        let mut ids = Vec::new();
+        for i in 0..5u64 {
+            let event = EventBuilder::text_note(format!("note {}", i), [])
+                .custom_created_at(nostr::Timestamp::from(1_000 + i))
+                .to_event(&keys)
+                .unwrap();
+            store.save_event(&event).unwrap();
+            ids.push(event.id);
+        }
+
+        let filters = [
+            Filter::new().author(keys.public_key()),
+            Filter::new().kind(Kind::TextNote),
+            Filter::new().kind(Kind::TextNote),
+        ]
+        .map(ReqFilter::from);
+        let result = store.query_multi(&filters, 100, None).unwrap();
+        assert_eq!(result.events.len(), 5);
+        assert!(!result.capped);
+
+        let result = store.query_multi(&filters, 2, None).unwrap();
+        assert!(result.capped);
+        let got: Vec<_> = result.events.iter().map(|e| e.id).collect();
+        assert_eq!(got, vec![ids[4], ids[3]]);
+    }
+
+    #[test]
+    fn max_limit_caps_every_plan_and_until_pages_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap().with_max_limit(Some(3));
+        let keys = Keys::generate();
+        let mut ids = Vec::new();
+        for i in 0..8u64 {
+            let event = EventBuilder::text_note(format!("note {}", i), [])
+                .custom_created_at(nostr::Timestamp::from(1_000 + i))
+                .to_event(&keys)
+                .unwrap();
+            store.save_event(&event).unwrap();
+            ids.push(event.id);
+        }
+
+        for filter in [
+            Filter::new().limit(100),
+            Filter::new().author(keys.public_key()).limit(100),
+            Filter::new().kind(Kind::TextNote).limit(100),
+            Filter::new().ids(ids.clone()).limit(100),
+        ] {
+            assert_eq!(store.query(&filter).unwrap().len(), 3, "{:?}", filter);
+        }
+
+        // A client paging with `until` set to the oldest created_at it has
+        // seen gets every event, with the boundary event repeated
+        let mut seen = Vec::new();
+        let mut until = None;
+        loop {
+            let mut filter = Filter::new().author(keys.public_key()).limit(100);
+            filter.until = until;
+            let page = store.query(&filter).unwrap();
+            let fresh: Vec<_> = page.iter().map(|e| e.id).filter(|id| !seen.contains(id)).collect();
+            if fresh.is_empty() {
+                break;
+            }
+            seen.extend(fresh);
+            until = page.last().map(|e| e.created_at);
+        }
+        ids.reverse();
+        assert_eq!(seen, ids);
+    }
+
+    #[test]
+    fn ties_on_created_at_keep_the_lowest_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        let root = EventBuilder::text_note("root", []).to_event(&keys).unwrap();
+        let mut tied = Vec::new();
+        for i in 0..6u64 {
+            let event = EventBuilder::text_note(format!("tied {}", i), [nostr::Tag::event(root.id)])
+                .custom_created_at(nostr::Timestamp::from(2_000))
+                .to_event(&keys)
+                .unwrap();
+            store.save_event(&event).unwrap();
+            tied.push(event.id);
+        }
+        let older = EventBuilder::text_note("older", [nostr::Tag::event(root.id)])
+            .custom_created_at(nostr::Timestamp::from(1_000))
+            .to_event(&keys)
+            .unwrap();
+        store.save_event(&older).unwrap();
+        tied.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+
+        for filter in [
+            Filter::new(),
+            Filter::new().author(keys.public_key()),
+            Filter::new().kind(Kind::TextNote),
+            Filter::new().author(keys.public_key()).kind(Kind::TextNote),
+            Filter::new().event(root.id),
+        ] {
+            let got: Vec<_> = store.query(&filter.clone().limit(3)).unwrap().iter().map(|e| e.id).collect();
+            assert_eq!(got, tied[..3], "{:?}", filter);
+            let got: Vec<_> = store.query(&filter.clone().limit(7)).unwrap().iter().map(|e| e.id).collect();
+            assert_eq!(got[..6], tied[..], "{:?}", filter);
+            assert_eq!(got[6], older.id);
+        }
+
+        let filters = [Filter::new().kind(Kind::TextNote), Filter::new().author(keys.public_key())].map(ReqFilter::from);
+        let result = store.query_multi(&filters, 4, None).unwrap();
+        let got: Vec<_> = result.events.iter().map(|e| e.id).collect();
+        assert_eq!(got, tied[..4]);
+    }
+
+    #[test]
+    fn planner_prefers_tag_index_for_thread_lookups() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        let root = EventBuilder::text_note("root", []).to_event(&keys).unwrap();
+        store.save_event(&root).unwrap();
+        for i in 0..50 {
+            let note = EventBuilder::text_note(format!("unrelated {}", i), [])
+                .to_event(&keys)
+                .unwrap();
+            store.save_event(&note).unwrap();
+        }
+        let reply = EventBuilder::text_note("reply", [nostr::Tag::event(root.id)])
+            .to_event(&keys)
+            .unwrap();
+        store.save_event(&reply).unwrap();
+
+        let filter = ReqFilter::from(Filter::new().author(keys.public_key()).event(root.id));
+        let tags = filter.tag_conditions();
+        let rtxn = store.env.read_txn().unwrap();
+        let plan = store.plan_query(&rtxn, &filter, &tags, 0, u64::MAX).unwrap();
+        assert!(matches!(plan, QueryPlan::Tag(_)));
+        drop(rtxn);
+
+        let events = store.query(&filter).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, reply.id);
+
+        // Authors + kinds alone keep using the compound index
+        let filter = Filter::new().author(keys.public_key()).kind(Kind::TextNote);
+        let rtxn = store.env.read_txn().unwrap();
+        assert_eq!(store.plan_query(&rtxn, &filter, &[], 0, u64::MAX).unwrap(), QueryPlan::AuthorKind);
+    }
+
+    #[test]
+    fn several_small_tag_conditions_are_intersected() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        let alice = Keys::generate().public_key();
+        let root = EventBuilder::text_note("root", []).to_event(&keys).unwrap();
+        store.save_event(&root).unwrap();
+        let note = |content: String, tags: Vec<nostr::Tag>| {
+            let event = EventBuilder::text_note(content, tags).to_event(&keys).unwrap();
+            store.save_event(&event).unwrap();
+            event
+        };
+        for i in 0..40 {
+            note(format!("reply {}", i), vec![nostr::Tag::event(root.id)]);
+            note(format!("mention {}", i), vec![nostr::Tag::public_key(alice)]);
+        }
+        let both: Vec<_> = (0..3)
+            .map(|i| note(format!("both {}", i), vec![nostr::Tag::event(root.id), nostr::Tag::public_key(alice)]).id)
+            .collect();
+
+        let filter = ReqFilter::from(Filter::new().event(root.id).pubkey(alice));
+        let tags = filter.tag_conditions();
+        let rtxn = store.env.read_txn().unwrap();
+        assert_eq!(store.plan_query(&rtxn, &filter, &tags, 0, u64::MAX).unwrap(), QueryPlan::TagIntersection(0b11));
+        drop(rtxn);
+        let mut got: Vec<_> = store.query(&filter).unwrap().iter().map(|e| e.id).collect();
+        got.sort();
+        let mut want = both.clone();
+        want.sort();
+        assert_eq!(got, want);
+
+        // The limit still applies to the intersection, newest first
+        assert_eq!(store.query(&filter.filter.clone().limit(1)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn truncated_tag_intersection_keeps_the_newest_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        let alice = Keys::generate().public_key();
+        let root = EventBuilder::text_note("root", []).to_event(&keys).unwrap();
+        let events: Vec<Event> = (0..200u64)
+            .map(|i| {
+                EventBuilder::text_note(format!("both {}", i), [nostr::Tag::event(root.id), nostr::Tag::public_key(alice)])
+                    .custom_created_at(nostr::Timestamp::from(1_000 + i))
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect();
+        store.save_events(&events).unwrap();
+
+        let filter = ReqFilter::from(Filter::new().event(root.id).pubkey(alice));
+        let tags = filter.tag_conditions();
+        let rtxn = store.env.read_txn().unwrap();
+        let mut candidates = Vec::new();
+        // Already aborted: the scan stops at its first deadline check
+        let mut deadline = ScanDeadline::new(None, Some(Arc::new(AtomicBool::new(true))));
+        store
+            .scan_tag_intersection(&rtxn, &tags, 0b11, 0, u64::MAX, &filter, &mut candidates, &mut deadline)
+            .unwrap();
+        assert!(!candidates.is_empty() && candidates.len() < events.len());
+        let oldest_kept = 1_000 + events.len() as u64 - candidates.len() as u64;
+        assert!(candidates.iter().all(|e| e.created_at.as_u64() >= oldest_kept));
+    }
+
+    #[test]
+    fn multi_letter_tags_are_indexed_and_backfilled() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        let proxy = |url: &str| nostr::Tag::custom(nostr::TagKind::Custom("proxy".into()), [url, "activitypub"]);
+        let bridged = EventBuilder::text_note("bridged", [proxy("https://example.com/1")])
+            .to_event(&keys)
+            .unwrap();
+        let other = EventBuilder::text_note("other", [proxy("https://example.com/2")])
+            .to_event(&keys)
+            .unwrap();
+        store.save_event(&bridged).unwrap();
+        store.save_event(&other).unwrap();
+        for i in 0..50 {
+            let note = EventBuilder::text_note(format!("plain {}", i), []).to_event(&keys).unwrap();
+            store.save_event(&note).unwrap();
+        }
+
+        let filter = ReqFilter::from(Filter::new().kind(Kind::TextNote)).long_tag("proxy", ["https://example.com/1"]);
+        let tags = filter.tag_conditions();
+        let rtxn = store.env.read_txn().unwrap();
+        assert_eq!(store.plan_query(&rtxn, &filter, &tags, 0, u64::MAX).unwrap(), QueryPlan::Tag(0));
+        drop(rtxn);
+        let (events, _) = store.query_req(&filter, None).unwrap();
+        assert_eq!(events.iter().map(|e| e.id).collect::<Vec<_>>(), vec![bridged.id]);
+
+        // Values too long for a key are stored unindexed, and never drive a scan
+        let huge = "x".repeat(MAX_KEY_LEN);
+        let long = EventBuilder::text_note("long", [proxy(&huge)]).to_event(&keys).unwrap();
+        store.save_event(&long).unwrap();
+        let filter = ReqFilter::from(Filter::new()).long_tag("proxy", [huge]);
+        let (events, _) = store.query_req(&filter, None).unwrap();
+        assert_eq!(events.iter().map(|e| e.id).collect::<Vec<_>>(), vec![long.id]);
+
+        // A database from before the change gets its entries on open
+        let mut wtxn = store.env.write_txn().unwrap();
+        store.index_tag.clear(&mut wtxn).unwrap();
+        store.meta.delete(&mut wtxn, LONG_TAG_INDEX_MARKER).unwrap();
+        wtxn.commit().unwrap();
+        drop(store);
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let filter = ReqFilter::from(Filter::new()).long_tag("proxy", ["https://example.com/2"]);
+        let (events, _) = store.query_req(&filter, None).unwrap();
+        assert_eq!(events.iter().map(|e| e.id).collect::<Vec<_>>(), vec![other.id]);
+    }
+
+    #[tokio::test]
+    async fn batched_writes_keep_replaceable_semantics() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+
+        let old = EventBuilder::metadata(&nostr::Metadata::new().name("old"))
+            .custom_created_at(nostr::Timestamp::from(1_000))
+            .to_event(&keys)
+            .unwrap();
+        let new = EventBuilder::metadata(&nostr::Metadata::new().name("new"))
+            .custom_created_at(nostr::Timestamp::from(2_000))
+            .to_event(&keys)
+            .unwrap();
+        let note = EventBuilder::text_note("hi", []).to_event(&keys).unwrap();
+
+        // Newer metadata first: the older one in the same batch is dropped
+        let results = store
+            .save_events_batch(vec![new.clone(), old.clone(), note.clone(), note.clone()])
+            .await
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![SaveResult::Saved, SaveResult::RejectedStale, SaveResult::Saved, SaveResult::Duplicate]
+        );
+        assert_eq!(store.event_count().unwrap(), 2);
+        assert!(store.get_event(new.id.as_bytes()).unwrap().is_some());
+        assert!(store.get_event(old.id.as_bytes()).unwrap().is_none());
+    }
+
+    #[test]
+    fn addressable_events_are_replaced_and_looked_up_by_address() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        let article = |d: &str, at: u64| {
+            EventBuilder::new(Kind::LongFormTextNote, "text", [nostr::Tag::identifier(d)])
+                .custom_created_at(nostr::Timestamp::from(at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        let v1 = article("intro", 1_000);
+        let v2 = article("intro", 2_000);
+        let other = article("outro", 1_500);
+        store.save_event(&v2).unwrap();
+        store.save_event(&v1).unwrap();
+        store.save_event(&other).unwrap();
+        assert_eq!(store.event_count().unwrap(), 2);
+
+        let filter = Filter::new()
+            .author(keys.public_key())
+            .kind(Kind::LongFormTextNote)
+            .identifiers(["intro".to_string(), "outro".to_string()]);
+        assert!(LmdbStore::address_lookup_keys(&filter).is_some());
+        let found: Vec<_> = store.query(&filter).unwrap().iter().map(|e| e.id).collect();
+        assert_eq!(found, vec![v2.id, other.id]);
+
+        let v3 = article("intro", 3_000);
+        store.save_event(&v3).unwrap();
+        assert!(store.get_event(v2.id.as_bytes()).unwrap().is_none());
+        assert_eq!(store.query(&filter).unwrap()[0].id, v3.id);
+
+        // Stores from before the address index get it rebuilt on open
+        let mut wtxn = store.env.write_txn().unwrap();
+        store.index_address.clear(&mut wtxn).unwrap();
+        wtxn.commit().unwrap();
+        drop(store);
+        let reopened = LmdbStore::new(dir.path()).unwrap();
+        assert_eq!(reopened.query(&filter).unwrap().len(), 2);
+        reopened.save_event(&article("intro", 2_500)).unwrap();
+        assert_eq!(reopened.event_count().unwrap(), 2);
+    }
+
+    #[test]
+    fn deletions_remove_events_and_keep_them_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let (alice, mallory) = (Keys::generate(), Keys::generate());
+        let note = EventBuilder::text_note("oops", []).to_event(&alice).unwrap();
+        let unsaved = EventBuilder::text_note("later", []).to_event(&alice).unwrap();
+        let article = |at: u64| {
+            EventBuilder::new(Kind::LongFormTextNote, "text", [nostr::Tag::identifier("intro")])
+                .custom_created_at(nostr::Timestamp::from(at))
+                .to_event(&alice)
+                .unwrap()
+        };
+        let deletion = |keys: &Keys, tags: &[[String; 2]], at: u64| {
+            let tags = tags.iter().map(|t| nostr::Tag::parse(t).unwrap());
+            EventBuilder::new(Kind::EventDeletion, "", tags)
+                .custom_created_at(nostr::Timestamp::from(at))
+                .to_event(keys)
+                .unwrap()
+        };
+        let e = |event: &Event| ["e".to_string(), event.id.to_hex()];
+        let address = ["a".to_string(), format!("30023:{}:intro", alice.public_key().to_hex())];
+        store.save_event(&note).unwrap();
+        store.save_event(&article(1_000)).unwrap();
+
+        // Someone else can't delete alice's note
+        store.save_event(&deletion(&mallory, &[e(&note)], 2_000)).unwrap();
+        assert!(store.get_event(note.id.as_bytes()).unwrap().is_some());
+
+        let by_alice = deletion(&alice, &[e(&note), e(&unsaved), address.clone()], 2_000);
+        assert_eq!(store.save_event(&by_alice).unwrap(), SaveResult::Saved);
+        assert!(store.get_event(note.id.as_bytes()).unwrap().is_none());
+        assert!(store.get_event(by_alice.id.as_bytes()).unwrap().is_some());
+        assert_eq!(store.query(&Filter::new().kind(Kind::LongFormTextNote)).unwrap().len(), 0);
+
+        // Re-submitted, or arriving after the deletion, they stay deleted
+        assert_eq!(store.save_event(&note).unwrap(), SaveResult::Deleted);
+        assert_eq!(store.save_event(&unsaved).unwrap(), SaveResult::Deleted);
+        assert_eq!(store.save_event(&article(1_500)).unwrap(), SaveResult::Deleted);
+        assert_eq!(store.save_event(&article(3_000)).unwrap(), SaveResult::Saved);
+
+        // Deleting a deletion has no effect
+        store.save_event(&deletion(&alice, &[e(&by_alice)], 4_000)).unwrap();
+        assert!(store.get_event(by_alice.id.as_bytes()).unwrap().is_some());
+        assert_eq!(store.save_event(&note).unwrap(), SaveResult::Deleted);
+    }
+
+    #[test]
+    fn legacy_json_values_are_read_and_converted() {
+        use nostr::JsonUtil;
+
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        let events: Vec<Event> = (0..(CONVERSION_CHUNK + 5))
+            .map(|i| EventBuilder::text_note(format!("note {}", i), []).to_event(&keys).unwrap())
+            .collect();
+        // Write the events the way older versions did
+        let mut wtxn = store.env.write_txn().unwrap();
+        for event in &events {
+            store.events_db.put(&mut wtxn, event.id.as_bytes(), event.as_json().as_bytes()).unwrap();
+            store.insert_indices(&mut wtxn, event).unwrap();
+        }
+        store.meta.delete(&mut wtxn, BINARY_VALUES_MARKER).unwrap();
+        wtxn.commit().unwrap();
+        assert_eq!(store.get_event(events[0].id.as_bytes()).unwrap().unwrap(), events[0]);
+        drop(store);
+
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let all_binary = || {
+            let rtxn = store.env.read_txn().unwrap();
+            let converted = store.events_db.iter(&rtxn).unwrap().all(|r| !codec::is_json(r.unwrap().1));
+            converted
+        };
+        let started = Instant::now();
+        while !all_binary() {
+            assert!(started.elapsed() < std::time::Duration::from_secs(10), "conversion did not finish");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let filter = Filter::new().author(keys.public_key()).limit(10_000);
+        assert_eq!(store.query(&filter).unwrap().len(), events.len());
+
+        // Once converted, later opens don't scan for JSON values again
+        let rtxn = store.env.read_txn().unwrap();
+        assert!(store.meta.get(&rtxn, BINARY_VALUES_MARKER).unwrap().is_some());
+        drop(rtxn);
+        let mut wtxn = store.env.write_txn().unwrap();
+        store.events_db.put(&mut wtxn, events[0].id.as_bytes(), events[0].as_json().as_bytes()).unwrap();
+        wtxn.commit().unwrap();
+        drop(store);
+        let store = LmdbStore::new(dir.path()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let rtxn = store.env.read_txn().unwrap();
+        assert!(codec::is_json(store.events_db.get(&rtxn, events[0].id.as_bytes()).unwrap().unwrap()));
+        drop(rtxn);
+        assert_eq!(store.get_event(events[0].id.as_bytes()).unwrap().unwrap(), events[0]);
+    }
+
+    #[test]
+    fn concurrent_writers_are_all_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    let keys = Keys::generate();
+                    for i in 0..25 {
+                        let event = EventBuilder::text_note(format!("note {}", i), [])
+                            .to_event(&keys)
+                            .unwrap();
+                        store.save_event(&event).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(store.event_count().unwrap(), 200);
+
+        // Dropping the last handle stops the writer so the path can be reopened
+        drop(store);
+        let reopened = LmdbStore::new(dir.path()).unwrap();
+        assert_eq!(reopened.event_count().unwrap(), 200);
+    }
+
+    #[test]
+    fn purge_removes_all_events_and_index_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        for i in 0..100 {
+            let event = EventBuilder::text_note(format!("note {}", i), [nostr::Tag::hashtag("moar")])
+                .to_event(&keys)
+                .unwrap();
+            store.save_event(&event).unwrap();
+        }
+
+        let reclaimed = store.purge().unwrap();
+        assert!(reclaimed > 0);
+        assert_eq!(store.event_count().unwrap(), 0);
+        assert!(store.query(&Filter::new().author(keys.public_key())).unwrap().is_empty());
+        assert!(store.query(&Filter::new().hashtag("moar")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn delete_author_removes_only_their_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let (alice, bob) = (Keys::generate(), Keys::generate());
+        for i in 0..20 {
+            let keys = if i % 2 == 0 { &alice } else { &bob };
+            let event = EventBuilder::text_note(format!("note {}", i), [nostr::Tag::hashtag("moar")])
+                .to_event(keys)
+                .unwrap();
+            store.save_event(&event).unwrap();
+        }
+
+        assert_eq!(store.delete_author(&alice.public_key()).unwrap(), 10);
+        assert_eq!(store.delete_author(&alice.public_key()).unwrap(), 0);
+        assert_eq!(store.event_count().unwrap(), 10);
+        let tagged = store.query(&Filter::new().hashtag("moar")).unwrap();
+        assert!(tagged.len() == 10 && tagged.iter().all(|e| e.pubkey == bob.public_key()));
+    }
+
+    #[test]
+    fn kind_counts_follow_saves_and_deletes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path()).unwrap();
+        let keys = Keys::generate();
+        let mut notes = Vec::new();
+        for i in 0..3 {
+            let note = EventBuilder::text_note(format!("note {}", i), []).to_event(&keys).unwrap();
+            store.save_event(&note).unwrap();
+            notes.push(note);
+        }
+        let reaction = EventBuilder::new(Kind::Reaction, "+", []).to_event(&keys).unwrap();
+        store.save_event(&reaction).unwrap();
+        let long_form = EventBuilder::new(Kind::LongFormTextNote, "post", []).to_event(&keys).unwrap();
+        store.save_event(&long_form).unwrap();
+
+        assert_eq!(store.kind_counts().unwrap(), vec![(1, 3), (7, 1), (30023, 1)]);
+        store.delete_event(notes[0].id.as_bytes()).unwrap();
+        store.delete_event(reaction.id.as_bytes()).unwrap();
+        assert_eq!(store.kind_counts().unwrap(), vec![(1, 2), (30023, 1)]);
+    }
+
+    #[test]
+    fn snapshot_can_be_opened_as_a_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LmdbStore::new(dir.path().join("live")).unwrap();
+        let keys = Keys::generate();
+        for i in 0..10 {
+            let event = EventBuilder::text_note(format!("note {}", i), []).to_event(&keys).unwrap();
+            store.save_event(&event).unwrap();
+        }
+
+        let restore_dir = dir.path().join("restore");
+        fs::create_dir_all(&restore_dir).unwrap();
+        store.snapshot_to(&restore_dir.join("data.mdb")).unwrap();
+
+        let restored = LmdbStore::new(&restore_dir).unwrap();
+        assert_eq!(restored.event_count().unwrap(), 10);
+    }
+}
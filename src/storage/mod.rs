@@ -1,14 +1,197 @@
 use crate::error::Result;
-use nostr::{Event, Filter};
+use filter::ReqFilter;
+use nostr::{Event, EventId, Filter, PublicKey};
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Result of running all filters of one REQ together.
+#[derive(Debug, Clone, Default)]
+pub struct MultiQueryResult {
+    /// Matching events, deduplicated by id and newest first.
+    pub events: Vec<Event>,
+    /// More events matched than the combined limit allowed.
+    pub capped: bool,
+    /// The deadline passed before every filter was fully scanned.
+    pub timed_out: bool,
+}
+
+/// What storing one event did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveResult {
+    Saved,
+    /// Stored, superseding an older version of a replaceable event.
+    Replaced,
+    /// The event was already stored.
+    Duplicate,
+    /// Not stored: a newer version of this replaceable event already is.
+    RejectedStale,
+    /// Not stored: its author deleted it (NIP-09).
+    Deleted,
+}
+
+impl SaveResult {
+    /// Whether the event is newly in the store.
+    pub fn is_stored(self) -> bool {
+        matches!(self, SaveResult::Saved | SaveResult::Replaced)
+    }
+}
+
+/// NIP-01 result order: newest first, ties on `created_at` broken by the
+/// lowest id, so pages split across one second don't skip or repeat events.
+pub fn newest_first(a: &Event, b: &Event) -> std::cmp::Ordering {
+    b.created_at
+        .cmp(&a.created_at)
+        .then_with(|| a.id.as_bytes().cmp(b.id.as_bytes()))
+}
 
 pub trait NostrStore: Send + Sync {
-    fn save_event(&self, event: &Event) -> Result<()>;
+    fn save_event(&self, event: &Event) -> Result<SaveResult>;
+    /// Store several events, in one write transaction where the backend
+    /// supports it.
+    fn save_events(&self, events: &[Event]) -> Result<()> {
+        for event in events {
+            self.save_event(event)?;
+        }
+        Ok(())
+    }
+    /// Async form of `save_events` for bulk ingestion (imports, sync), which
+    /// waits for the write without blocking the runtime and reports what
+    /// happened to each event, in order.
+    fn save_events_batch(&self, events: Vec<Event>) -> BoxFuture<'_, Result<Vec<SaveResult>>> {
+        Box::pin(async move { events.iter().map(|event| self.save_event(event)).collect() })
+    }
     fn get_event(&self, id: &[u8; 32]) -> Result<Option<Event>>;
     fn delete_event(&self, id: &[u8; 32]) -> Result<bool>;
+    /// Most events one filter returns, if the store caps it. Pages are at
+    /// most this long, so callers paging with `until` must expect short ones.
+    fn max_limit(&self) -> Option<usize> {
+        None
+    }
     fn query(&self, filter: &Filter) -> Result<Vec<Event>>;
+    /// Like `query`, but gives up scanning once `deadline` has passed.
+    /// Returns the (newest-first) events found so far and whether the scan
+    /// was cut short.
+    fn query_until(&self, filter: &Filter, _deadline: Option<Instant>) -> Result<(Vec<Event>, bool)> {
+        Ok((self.query(filter)?, false))
+    }
+    /// `query_until` for a REQ filter, long tag conditions included. Stores
+    /// that don't index those tags check them on the results.
+    fn query_req(&self, filter: &ReqFilter, deadline: Option<Instant>) -> Result<(Vec<Event>, bool)> {
+        let (mut events, timed_out) = self.query_until(filter, deadline)?;
+        events.retain(|event| filter.matches_long_tags(event));
+        Ok((events, timed_out))
+    }
+
+    /// Run several filters as one query. Identical filters are only scanned
+    /// once, an event matching more than one filter is returned once, and at
+    /// most `max_total` events (the newest) are returned overall.
+    fn query_multi(
+        &self,
+        filters: &[ReqFilter],
+        max_total: usize,
+        deadline: Option<Instant>,
+    ) -> Result<MultiQueryResult> {
+        let mut planned: Vec<ReqFilter> = Vec::with_capacity(filters.len());
+        for filter in filters {
+            if !planned.contains(filter) {
+                planned.push(filter.clone());
+            }
+        }
+
+        let mut result = MultiQueryResult::default();
+        let mut seen: HashSet<EventId> = HashSet::new();
+        for mut filter in planned {
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                result.timed_out = true;
+                break;
+            }
+            // No single filter can contribute more than the combined limit;
+            // one extra event is enough to tell that the cap was hit.
+            let limit = filter.limit.unwrap_or(100).min(max_total.saturating_add(1));
+            filter.filter.limit = Some(limit);
+
+            let (events, timed_out) = self.query_req(&filter, deadline)?;
+            for event in events {
+                if seen.insert(event.id) {
+                    result.events.push(event);
+                }
+            }
+            if timed_out {
+                result.timed_out = true;
+                break;
+            }
+        }
+
+        result.events.sort_unstable_by(newest_first);
+        if result.events.len() > max_total {
+            result.events.truncate(max_total);
+            result.capped = true;
+        }
+        Ok(result)
+    }
     fn iter_all(&self) -> Result<Vec<Event>>;
+    /// Call `f` with every stored event in turn. Stores that can stream
+    /// them do so from one read transaction instead of loading them all.
+    fn for_each_event(&self, f: &mut dyn FnMut(&Event)) -> Result<()> {
+        for event in self.iter_all()? {
+            f(&event);
+        }
+        Ok(())
+    }
+    /// Delete every event by `author`. Returns how many were deleted.
+    fn delete_author(&self, author: &PublicKey) -> Result<u64> {
+        let mut deleted = 0;
+        for event in self.iter_all()?.into_iter().filter(|e| e.pubkey == *author) {
+            if self.delete_event(event.id.as_bytes())? {
+                deleted += 1;
+            }
+        }
+        Ok(deleted)
+    }
+    /// Number of stored events of each kind, ordered by kind.
+    fn kind_counts(&self) -> Result<Vec<(u16, u64)>> {
+        let mut counts = std::collections::BTreeMap::new();
+        for event in self.iter_all()? {
+            *counts.entry(event.kind.as_u16()).or_insert(0) += 1;
+        }
+        Ok(counts.into_iter().collect())
+    }
+    /// Delete every event and index entry. Returns the number of bytes of
+    /// storage freed for reuse.
+    fn purge(&self) -> Result<u64> {
+        for event in self.iter_all()? {
+            self.delete_event(event.id.as_bytes())?;
+        }
+        Ok(0)
+    }
+    /// Stop taking writes, before the database is deleted. Every write
+    /// afterwards fails with [`crate::error::Error::Closed`].
+    fn close(&self) {}
     fn event_count(&self) -> Result<u64>;
+    /// Write a consistent, compacted snapshot of the database to `dest`
+    /// while the store stays online. `dest` must not already exist.
+    fn snapshot_to(&self, _dest: &Path) -> Result<()> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "snapshots not supported by this store").into())
+    }
     fn db_path(&self) -> &str;
+    /// Read transactions open right now.
+    fn read_txns(&self) -> readers::ReadTxnStats {
+        readers::ReadTxnStats::default()
+    }
+    /// Report read transactions open longer than `max` and stop the scans
+    /// among them. Returns how many scans were stopped.
+    fn stop_slow_reads(&self, _max: Duration) -> usize {
+        0
+    }
 }
 
+pub mod browse;
+pub mod codec;
+pub mod filter;
 pub mod lmdb;
+pub mod readers;
@@ -0,0 +1,146 @@
+//! Bookkeeping for open LMDB read transactions. A reader pins the pages it
+//! can see, so one that stays open keeps LMDB from reusing freed pages and
+//! the file grows for as long as it lives. Every read transaction the store
+//! opens is registered here; the watchdog reports the oldest and stops scans
+//! that have run past the limit.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct Reader {
+    started: Instant,
+    /// What the transaction is for, e.g. `query` or `iter_all`.
+    label: &'static str,
+    /// Set to make a scan give up; `None` for reads that can't stop halfway.
+    abort: Option<Arc<AtomicBool>>,
+    warned: bool,
+}
+
+/// Open read transactions, for the admin API.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReadTxnStats {
+    pub open: usize,
+    /// How long the oldest open transaction has been open, in milliseconds.
+    pub oldest_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oldest_label: Option<&'static str>,
+    /// Scans stopped for running past the limit, since startup.
+    pub aborted: u64,
+}
+
+#[derive(Default)]
+pub struct ReaderTracker {
+    readers: Mutex<HashMap<u64, Reader>>,
+    next_id: AtomicU64,
+    aborted: AtomicU64,
+}
+
+/// Keeps a reader registered until dropped, which should happen together
+/// with its transaction.
+pub struct ReaderGuard {
+    tracker: Arc<ReaderTracker>,
+    id: u64,
+    abort: Option<Arc<AtomicBool>>,
+}
+
+impl ReaderGuard {
+    /// Flag the watchdog sets when this scan should stop.
+    pub fn abort_flag(&self) -> Option<Arc<AtomicBool>> {
+        self.abort.clone()
+    }
+}
+
+impl Drop for ReaderGuard {
+    fn drop(&mut self) {
+        self.tracker.readers.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl ReaderTracker {
+    /// Register a read transaction. `abortable` readers are scans that check
+    /// their abort flag and return what they found so far.
+    pub fn begin(self: &Arc<Self>, label: &'static str, abortable: bool) -> ReaderGuard {
+        let id = self.next_id.fetch_add(1, Relaxed);
+        let abort = abortable.then(|| Arc::new(AtomicBool::new(false)));
+        self.readers.lock().unwrap().insert(
+            id,
+            Reader {
+                started: Instant::now(),
+                label,
+                abort: abort.clone(),
+                warned: false,
+            },
+        );
+        ReaderGuard {
+            tracker: self.clone(),
+            id,
+            abort,
+        }
+    }
+
+    pub fn stats(&self) -> ReadTxnStats {
+        let readers = self.readers.lock().unwrap();
+        let oldest = readers.values().min_by_key(|r| r.started);
+        ReadTxnStats {
+            open: readers.len(),
+            oldest_ms: oldest.map_or(0, |r| r.started.elapsed().as_millis() as u64),
+            oldest_label: oldest.map(|r| r.label),
+            aborted: self.aborted.load(Relaxed),
+        }
+    }
+
+    /// Log readers open longer than `max` (once each) and tell the
+    /// abortable ones to stop. Returns how many were told to stop.
+    pub fn enforce(&self, max: Duration, db_path: &str) -> usize {
+        let mut stopped = 0;
+        for reader in self.readers.lock().unwrap().values_mut() {
+            let age = reader.started.elapsed();
+            if age < max || reader.warned {
+                continue;
+            }
+            reader.warned = true;
+            match &reader.abort {
+                Some(abort) => {
+                    abort.store(true, Relaxed);
+                    stopped += 1;
+                    tracing::warn!(db = %db_path, "Stopping {} scan open for {:?}", reader.label, age);
+                }
+                None => tracing::warn!(db = %db_path, "{} read transaction open for {:?}", reader.label, age),
+            }
+        }
+        self.aborted.fetch_add(stopped as u64, Relaxed);
+        stopped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_scans_are_stopped_and_others_only_reported() {
+        let tracker = Arc::new(ReaderTracker::default());
+        let scan = tracker.begin("query", true);
+        let export = tracker.begin("iter_all", false);
+        assert_eq!(tracker.stats().open, 2);
+        assert_eq!(tracker.stats().oldest_label, Some("query"));
+
+        assert_eq!(tracker.enforce(Duration::from_secs(60), "db"), 0);
+        assert!(!scan.abort_flag().unwrap().load(Relaxed));
+
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(tracker.enforce(Duration::from_millis(1), "db"), 1);
+        assert!(scan.abort_flag().unwrap().load(Relaxed));
+        assert!(export.abort_flag().is_none());
+        // Each reader is only handled once
+        assert_eq!(tracker.enforce(Duration::from_millis(1), "db"), 0);
+
+        drop(scan);
+        drop(export);
+        let stats = tracker.stats();
+        assert_eq!((stats.open, stats.oldest_ms, stats.aborted), (0, 0, 1));
+    }
+}
@@ -0,0 +1,319 @@
+//! Relay-wide index of live subscriptions. Each filter is filed under the
+//! most selective thing it asks for — event ids, authors, a single-letter
+//! tag or kinds — so a newly accepted event is only tested against the
+//! subscriptions that could possibly match it, not every filter of every
+//! connection.
+
+use crate::storage::filter::ReqFilter;
+use nostr::{Event, EventId, Filter, Kind, PublicKey, SingleLetterTag, SubscriptionId};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc;
+
+/// A connection and one of its subscription ids.
+type SubKey = (u64, String);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum IndexKey {
+    Id(EventId),
+    Author(PublicKey),
+    Tag(SingleLetterTag, String),
+    Kind(Kind),
+    /// Filters with none of the above are tested against every event.
+    Any,
+}
+
+/// Where a filter is filed: under its first non-empty field, in order of
+/// how few events are likely to match it.
+fn index_keys(filter: &Filter) -> Vec<IndexKey> {
+    if let Some(ids) = filter.ids.as_ref().filter(|ids| !ids.is_empty()) {
+        return ids.iter().map(|id| IndexKey::Id(*id)).collect();
+    }
+    if let Some(authors) = filter.authors.as_ref().filter(|authors| !authors.is_empty()) {
+        return authors.iter().map(|pk| IndexKey::Author(*pk)).collect();
+    }
+    if let Some((letter, values)) = filter.generic_tags.iter().find(|(_, values)| !values.is_empty()) {
+        return values.iter().map(|v| IndexKey::Tag(*letter, v.clone())).collect();
+    }
+    if let Some(kinds) = filter.kinds.as_ref().filter(|kinds| !kinds.is_empty()) {
+        return kinds.iter().map(|kind| IndexKey::Kind(*kind)).collect();
+    }
+    vec![IndexKey::Any]
+}
+
+/// An event delivered to a connection, with the ids of its subscriptions
+/// it matched.
+pub struct LiveEvent {
+    pub subscriptions: Vec<SubscriptionId>,
+    pub event: Arc<Event>,
+}
+
+struct Connection {
+    tx: mpsc::Sender<LiveEvent>,
+    missed: Arc<AtomicU64>,
+}
+
+struct Subscription {
+    id: SubscriptionId,
+    filters: Vec<ReqFilter>,
+    keys: HashSet<IndexKey>,
+}
+
+#[derive(Default)]
+struct Index {
+    connections: HashMap<u64, Connection>,
+    subscriptions: HashMap<SubKey, Subscription>,
+    postings: HashMap<IndexKey, HashSet<SubKey>>,
+}
+
+impl Index {
+    fn remove(&mut self, key: &SubKey) {
+        let Some(sub) = self.subscriptions.remove(key) else {
+            return;
+        };
+        for index_key in sub.keys {
+            if let Some(subs) = self.postings.get_mut(&index_key) {
+                subs.remove(key);
+                if subs.is_empty() {
+                    self.postings.remove(&index_key);
+                }
+            }
+        }
+    }
+
+    /// Subscriptions filed under something `event` has.
+    fn candidates(&self, event: &Event) -> HashSet<&SubKey> {
+        let mut keys = vec![
+            IndexKey::Id(event.id),
+            IndexKey::Author(event.pubkey),
+            IndexKey::Kind(event.kind),
+            IndexKey::Any,
+        ];
+        for (letter, values) in event.tags_indexes() {
+            keys.extend(values.iter().map(|v| IndexKey::Tag(*letter, v.clone())));
+        }
+        keys.iter()
+            .filter_map(|key| self.postings.get(key))
+            .flatten()
+            .collect()
+    }
+}
+
+pub struct SubscriptionIndex {
+    inner: RwLock<Index>,
+    next_connection: AtomicU64,
+    /// Live events queued per connection before it starts missing them.
+    capacity: usize,
+}
+
+impl SubscriptionIndex {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: RwLock::new(Index::default()),
+            next_connection: AtomicU64::new(0),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Register a connection. Its subscriptions are dropped with the handle.
+    pub fn connect(self: &Arc<Self>) -> LiveSubscriptions {
+        let id = self.next_connection.fetch_add(1, Relaxed);
+        let (tx, rx) = mpsc::channel(self.capacity);
+        let missed = Arc::new(AtomicU64::new(0));
+        self.inner.write().unwrap().connections.insert(
+            id,
+            Connection {
+                tx,
+                missed: missed.clone(),
+            },
+        );
+        LiveSubscriptions {
+            index: self.clone(),
+            connection: id,
+            ids: HashSet::new(),
+            rx,
+            missed,
+        }
+    }
+
+    /// Queue `event` for every connection with a matching subscription.
+    /// Connections whose queue is full miss it and are told so later.
+    pub fn publish(&self, event: &Event) {
+        let inner = self.inner.read().unwrap();
+        let mut matched: HashMap<u64, Vec<SubscriptionId>> = HashMap::new();
+        for key in inner.candidates(event) {
+            let sub = &inner.subscriptions[key];
+            if sub.filters.iter().any(|f| f.match_event(event)) {
+                matched.entry(key.0).or_default().push(sub.id.clone());
+            }
+        }
+        if matched.is_empty() {
+            return;
+        }
+
+        let event = Arc::new(event.clone());
+        for (connection, subscriptions) in matched {
+            let Some(conn) = inner.connections.get(&connection) else {
+                continue;
+            };
+            let live = LiveEvent {
+                subscriptions,
+                event: event.clone(),
+            };
+            if let Err(mpsc::error::TrySendError::Full(_)) = conn.tx.try_send(live) {
+                conn.missed.fetch_add(1, Relaxed);
+            }
+        }
+    }
+
+    fn subscribe(&self, connection: u64, id: SubscriptionId, filters: Vec<ReqFilter>) {
+        let key = (connection, id.to_string());
+        let mut inner = self.inner.write().unwrap();
+        inner.remove(&key);
+        let keys: HashSet<IndexKey> = filters.iter().flat_map(|f| index_keys(f)).collect();
+        for index_key in &keys {
+            inner.postings.entry(index_key.clone()).or_default().insert(key.clone());
+        }
+        inner.subscriptions.insert(key, Subscription { id, filters, keys });
+    }
+
+    fn unsubscribe(&self, connection: u64, id: &str) {
+        self.inner.write().unwrap().remove(&(connection, id.to_string()));
+    }
+
+    fn disconnect(&self, connection: u64, ids: &HashSet<String>) {
+        let mut inner = self.inner.write().unwrap();
+        for id in ids {
+            inner.remove(&(connection, id.clone()));
+        }
+        inner.connections.remove(&connection);
+    }
+}
+
+/// One connection's subscriptions and the queue of live events matching them.
+pub struct LiveSubscriptions {
+    index: Arc<SubscriptionIndex>,
+    connection: u64,
+    ids: HashSet<String>,
+    rx: mpsc::Receiver<LiveEvent>,
+    missed: Arc<AtomicU64>,
+}
+
+impl LiveSubscriptions {
+    /// Start (or replace) subscription `id`.
+    pub fn subscribe(&mut self, id: SubscriptionId, filters: Vec<ReqFilter>) {
+        self.ids.insert(id.to_string());
+        self.index.subscribe(self.connection, id, filters);
+    }
+
+    /// Stop subscription `id`. Returns whether it was active.
+    pub fn unsubscribe(&mut self, id: &SubscriptionId) -> bool {
+        let id = id.to_string();
+        if !self.ids.remove(&id) {
+            return false;
+        }
+        self.index.unsubscribe(self.connection, &id);
+        true
+    }
+
+    /// Number of active subscriptions.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub async fn recv(&mut self) -> Option<LiveEvent> {
+        self.rx.recv().await
+    }
+
+    /// Live events dropped since the last call because the queue was full.
+    pub fn take_missed(&self) -> u64 {
+        self.missed.swap(0, Relaxed)
+    }
+}
+
+impl Drop for LiveSubscriptions {
+    fn drop(&mut self) {
+        self.index.disconnect(self.connection, &self.ids);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::{Alphabet, EventBuilder, Keys, Tag};
+
+    fn sub(id: &str) -> SubscriptionId {
+        SubscriptionId::new(id)
+    }
+
+    #[tokio::test]
+    async fn only_candidate_subscriptions_are_tested() {
+        let index = Arc::new(SubscriptionIndex::new(16));
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let note = EventBuilder::text_note("hi", [Tag::hashtag("nostr")]).to_event(&alice).unwrap();
+
+        let mut a = index.connect();
+        a.subscribe(sub("mine"), vec![Filter::new().author(alice.public_key()).into()]);
+        a.subscribe(sub("tag"), vec![Filter::new().custom_tag(SingleLetterTag::lowercase(Alphabet::T), ["nostr"]).into()]);
+        let mut b = index.connect();
+        b.subscribe(sub("bob"), vec![Filter::new().author(bob.public_key()).into()]);
+        b.subscribe(sub("reactions"), vec![Filter::new().kind(Kind::Reaction).into()]);
+
+        {
+            let inner = index.inner.read().unwrap();
+            let candidates = inner.candidates(&note);
+            assert_eq!(candidates.len(), 2);
+            assert!(candidates.iter().all(|(conn, _)| *conn == 0));
+        }
+
+        index.publish(&note);
+        let live = a.recv().await.unwrap();
+        assert_eq!(live.event.id, note.id);
+        let mut ids: Vec<String> = live.subscriptions.iter().map(|s| s.to_string()).collect();
+        ids.sort();
+        assert_eq!(ids, ["mine", "tag"]);
+        assert!(b.rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn replaced_and_dropped_subscriptions_leave_the_index() {
+        let index = Arc::new(SubscriptionIndex::new(16));
+        let alice = Keys::generate();
+        let note = EventBuilder::text_note("hi", []).to_event(&alice).unwrap();
+
+        let mut a = index.connect();
+        a.subscribe(sub("feed"), vec![Filter::new().author(alice.public_key()).into()]);
+        a.subscribe(sub("feed"), vec![Filter::new().kind(Kind::Reaction).into()]);
+        index.publish(&note);
+        assert!(a.rx.try_recv().is_err());
+        assert_eq!(a.len(), 1);
+
+        let mut b = index.connect();
+        b.subscribe(sub("all"), vec![Filter::new().into()]);
+        drop(b);
+        let inner = index.inner.read().unwrap();
+        assert_eq!(inner.subscriptions.len(), 1);
+        assert!(!inner.postings.contains_key(&IndexKey::Any));
+    }
+
+    #[tokio::test]
+    async fn full_queues_count_missed_events() {
+        let index = Arc::new(SubscriptionIndex::new(1));
+        let keys = Keys::generate();
+        let mut a = index.connect();
+        a.subscribe(sub("all"), vec![Filter::new().into()]);
+
+        for i in 0..3 {
+            index.publish(&EventBuilder::text_note(i.to_string(), []).to_event(&keys).unwrap());
+        }
+        assert_eq!(a.take_missed(), 2);
+        assert!(a.recv().await.is_some());
+        assert_eq!(a.take_missed(), 0);
+    }
+}
@@ -0,0 +1,293 @@
+//! Inbound sync (`sync_from`): keep subscriptions open to upstream relays and
+//! store the events they send, subject to the local relay's own write policy.
+
+use crate::config::SyncSource;
+use crate::plugin::WriteSource;
+use crate::policy::PolicyResult;
+use crate::server::{RelayState, StorageOp};
+use futures_util::{SinkExt, StreamExt};
+use nostr::{Event, Filter, Timestamp};
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+/// How often a relay in maintenance is checked for having left it.
+const MAINTENANCE_POLL: Duration = Duration::from_secs(1);
+
+/// Spawn one sync task per `sync_from` source of the relay.
+pub fn start(state: Arc<RelayState>) {
+    for source in state.config().sync_from.clone() {
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            run_source(state, source).await;
+        });
+    }
+}
+
+async fn run_source(state: Arc<RelayState>, source: SyncSource) {
+    tracing::info!(relay = %state.relay_id, upstream = %source.url, "Starting inbound sync");
+
+    // Newest event of a completed backfill (or live since); used as `since`
+    // when reconnecting so only missed events are fetched again.
+    let mut since: Option<Timestamp> = None;
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        // Imports and compaction expect nothing else to write meanwhile;
+        // upstream keeps the events, and the cursor fetches them afterwards
+        if state.config().maintenance_mode.is_some() {
+            tracing::info!(relay = %state.relay_id, upstream = %source.url, "Relay in maintenance, pausing inbound sync");
+            while state.config().maintenance_mode.is_some() {
+                tokio::time::sleep(MAINTENANCE_POLL).await;
+            }
+            backoff = MIN_BACKOFF;
+        }
+
+        match sync_once(&state, &source, &mut since).await {
+            Ok(()) if state.config().maintenance_mode.is_some() => continue,
+            Ok(()) => {
+                tracing::info!(relay = %state.relay_id, upstream = %source.url, "Upstream closed connection, reconnecting");
+                backoff = MIN_BACKOFF;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    relay = %state.relay_id,
+                    upstream = %source.url,
+                    retry_in_secs = backoff.as_secs(),
+                    "Inbound sync failed: {}",
+                    e
+                );
+            }
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+fn build_filters(source: &SyncSource, since: Option<Timestamp>) -> Vec<Filter> {
+    let filters = if source.filters.is_empty() {
+        vec![Filter::new()]
+    } else {
+        source.filters.clone()
+    };
+    match since {
+        Some(since) => filters
+            .into_iter()
+            .map(|f| match f.since {
+                Some(existing) if existing > since => f,
+                _ => f.since(since),
+            })
+            .collect(),
+        None => filters,
+    }
+}
+
+async fn sync_once(
+    state: &RelayState,
+    source: &SyncSource,
+    since: &mut Option<Timestamp>,
+) -> Result<(), anyhow::Error> {
+    let (mut ws, _) = tokio::time::timeout(
+        Duration::from_secs(10),
+        tokio_tungstenite::connect_async(source.url.as_str()),
+    )
+    .await
+    .map_err(|_| anyhow::anyhow!("Connection timeout"))??;
+
+    let mut req = vec![serde_json::json!("REQ"), serde_json::json!("moar-sync")];
+    for filter in build_filters(source, *since) {
+        req.push(serde_json::to_value(filter)?);
+    }
+    ws.send(Message::Text(serde_json::Value::Array(req).to_string().into()))
+        .await?;
+
+    let mut stored = 0u64;
+    let mut cursor = Cursor::default();
+    while let Some(msg) = ws.next().await {
+        let text = match msg? {
+            Message::Text(t) => t.to_string(),
+            Message::Ping(data) => {
+                ws.send(Message::Pong(data)).await?;
+                continue;
+            }
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        let parsed: serde_json::Value = match serde_json::from_str(&text) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        match parsed[0].as_str() {
+            Some("EVENT") => {
+                let event: Event = match serde_json::from_value(parsed[2].clone()) {
+                    Ok(e) => e,
+                    Err(_) => continue,
+                };
+                // Hang up before the cursor moves past an event left unstored
+                if state.config().maintenance_mode.is_some() {
+                    return Ok(());
+                }
+                cursor.seen(since, event.created_at);
+                if store_event(state, &event, &source.url).await {
+                    stored += 1;
+                }
+            }
+            Some("EOSE") => {
+                cursor.caught_up(since);
+                tracing::info!(
+                    relay = %state.relay_id,
+                    upstream = %source.url,
+                    stored,
+                    "Inbound sync caught up"
+                );
+            }
+            Some("CLOSED") => {
+                return Err(anyhow::anyhow!(
+                    "Subscription closed by upstream: {}",
+                    parsed[2].as_str().unwrap_or("")
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Where a reconnect resumes from. Stored events arrive newest first, so
+/// until EOSE the newest one seen only becomes the resume point once the
+/// rest of the backfill is in; a disconnect midway fetches it all again.
+#[derive(Default)]
+struct Cursor {
+    newest: Option<Timestamp>,
+    caught_up: bool,
+}
+
+impl Cursor {
+    fn seen(&mut self, since: &mut Option<Timestamp>, created_at: Timestamp) {
+        if self.newest.is_none_or(|n| created_at > n) {
+            self.newest = Some(created_at);
+        }
+        if self.caught_up {
+            *since = self.newest;
+        }
+    }
+
+    fn caught_up(&mut self, since: &mut Option<Timestamp>) {
+        self.caught_up = true;
+        if self.newest.is_some() {
+            *since = self.newest;
+        }
+    }
+}
+
+/// Verify and store an upstream event if the local write policy allows it.
+/// Returns `true` if the event was stored.
+async fn store_event(state: &RelayState, event: &Event, upstream: &str) -> bool {
+    if crate::server::verify_event(event).await.is_err() {
+        return false;
+    }
+
+    let source = WriteSource::Stream(upstream.to_string());
+    match state.policy.load_full().check_write(event, None, source.clone()).await {
+        PolicyResult::Allow => {}
+        result => {
+            state.stats.events_rejected.fetch_add(1, Relaxed);
+            let reason = match result {
+                PolicyResult::Deny(reason) => format!("blocked: {}", reason),
+                PolicyResult::AuthRequired => "auth-required".to_string(),
+                _ => "shadow-rejected".to_string(),
+            };
+            state.firehose.rejected(event, &source, &reason);
+            return false;
+        }
+    }
+
+    let to_save = event.clone();
+    match state.with_store(StorageOp::Write, move |store| store.save_event(&to_save)).await {
+        Ok(result) if result.is_stored() => {
+            state.stats.event_saved(&event.pubkey);
+            state.firehose.accepted(event, &source);
+            state.event_stored(event);
+            true
+        }
+        // Already known, or older than what we have
+        Ok(_) => false,
+        Err(e) => {
+            tracing::error!(relay = %state.relay_id, "Failed to save synced event: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::Kind;
+
+    #[test]
+    fn cursor_only_advances_once_backfill_is_complete() {
+        let mut since = Some(Timestamp::from(100));
+        let mut cursor = Cursor::default();
+
+        // Backfill, newest first: a disconnect now must not skip the rest
+        cursor.seen(&mut since, Timestamp::from(500));
+        cursor.seen(&mut since, Timestamp::from(300));
+        assert_eq!(since, Some(Timestamp::from(100)));
+
+        cursor.caught_up(&mut since);
+        assert_eq!(since, Some(Timestamp::from(500)));
+
+        // Live events move it straight away
+        cursor.seen(&mut since, Timestamp::from(600));
+        assert_eq!(since, Some(Timestamp::from(600)));
+        cursor.seen(&mut since, Timestamp::from(550));
+        assert_eq!(since, Some(Timestamp::from(600)));
+
+        // Nothing new: keep the old resume point
+        let mut since = Some(Timestamp::from(100));
+        Cursor::default().caught_up(&mut since);
+        assert_eq!(since, Some(Timestamp::from(100)));
+    }
+
+    #[test]
+    fn reconnect_resumes_from_newest_seen_event() {
+        let source = SyncSource {
+            url: "wss://relay.example.com".into(),
+            filters: vec![
+                Filter::new().kind(Kind::TextNote),
+                Filter::new().kind(Kind::Metadata).since(Timestamp::from(2000)),
+            ],
+        };
+
+        let filters = build_filters(&source, None);
+        assert_eq!(filters[0].since, None);
+
+        let filters = build_filters(&source, Some(Timestamp::from(1000)));
+        assert_eq!(filters[0].since, Some(Timestamp::from(1000)));
+        // A later configured `since` is kept
+        assert_eq!(filters[1].since, Some(Timestamp::from(2000)));
+    }
+
+    #[test]
+    fn sync_source_parses_from_toml() {
+        let source: SyncSource = toml::from_str(
+            r##"
+url = "wss://relay.example.com"
+filters = [{ kinds = [1], authors = ["79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"] }]
+"##,
+        )
+        .unwrap();
+        assert_eq!(source.filters.len(), 1);
+        assert!(source.filters[0].kinds.as_ref().unwrap().contains(&Kind::TextNote));
+
+        // Round-trips through the config file writer
+        let reparsed: SyncSource = toml::from_str(&toml::to_string(&source).unwrap()).unwrap();
+        assert_eq!(reparsed.filters, source.filters);
+    }
+}
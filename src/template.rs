@@ -0,0 +1,250 @@
+//! Relay presets for the admin API. A template names a common kind of relay
+//! and expands into the policy it needs, so creating one doesn't take a
+//! hand-written `PolicyConfig`; whatever `policy` the create payload does
+//! carry is laid over the preset.
+
+use crate::config::{GreylistConfig, MoarConfig, PolicyConfig};
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayTemplate {
+    Outbox,
+    Inbox,
+    Community,
+    Paid,
+    Archive,
+}
+
+/// A template as listed by the admin API.
+#[derive(Debug, Serialize)]
+pub struct TemplateInfo {
+    pub id: &'static str,
+    pub description: &'static str,
+    /// Policy field the payload must set when the config offers no single
+    /// default for it, e.g. `write.wot`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requires: Option<String>,
+    /// The policy the template expands to with the current config.
+    pub policy: PolicyConfig,
+}
+
+/// DMs (NIP-04) and gift wraps (NIP-59).
+const DM_KINDS: [u64; 2] = [4, 1059];
+
+impl RelayTemplate {
+    pub const ALL: [RelayTemplate; 5] = [
+        RelayTemplate::Outbox,
+        RelayTemplate::Inbox,
+        RelayTemplate::Community,
+        RelayTemplate::Paid,
+        RelayTemplate::Archive,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            RelayTemplate::Outbox => "outbox",
+            RelayTemplate::Inbox => "inbox",
+            RelayTemplate::Community => "community",
+            RelayTemplate::Paid => "paid",
+            RelayTemplate::Archive => "archive",
+        }
+    }
+
+    pub fn parse(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.id() == id)
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            RelayTemplate::Outbox => "Personal outbox: only the admin writes, anyone reads",
+            RelayTemplate::Inbox => {
+                "Inbox/DM relay: anyone may write events tagging the admin; DMs are only served to their parties"
+            }
+            RelayTemplate::Community => {
+                "Community relay: pubkeys in a web of trust write, with newcomers greylisted"
+            }
+            RelayTemplate::Paid => "Paid relay: pubkeys with an active paywall subscription write",
+            RelayTemplate::Archive => {
+                "Archive: the admin writes over an authenticated connection without rate limits, anyone reads"
+            }
+        }
+    }
+
+    /// The `write` field the payload must fill in if the config has no
+    /// single value to default it to.
+    fn requires(self) -> Option<&'static str> {
+        match self {
+            RelayTemplate::Community => Some("wot"),
+            RelayTemplate::Paid => Some("paywall"),
+            _ => None,
+        }
+    }
+
+    /// The template's policy. WoT and paywall references default to the
+    /// only one configured, if there is exactly one.
+    pub fn policy(self, config: &MoarConfig) -> PolicyConfig {
+        let admin = nostr::PublicKey::parse(&config.admin_pubkey)
+            .map(|pk| pk.to_hex())
+            .unwrap_or_else(|_| config.admin_pubkey.clone());
+        let mut policy = PolicyConfig::default();
+        match self {
+            RelayTemplate::Outbox => {
+                policy.write.allowed_pubkeys = Some(vec![admin]);
+            }
+            RelayTemplate::Inbox => {
+                policy.write.tagged_pubkeys = Some(vec![admin]);
+                policy.read.protected_kinds = Some(DM_KINDS.to_vec());
+                policy.rate_limit.pubkey_writes_per_minute = Some(10);
+                policy.greylist = Some(GreylistConfig::default());
+            }
+            RelayTemplate::Community => {
+                policy.write.wot = only_key(config.wots.keys());
+                policy.rate_limit.pubkey_writes_per_minute = Some(30);
+                policy.greylist = Some(GreylistConfig::default());
+            }
+            RelayTemplate::Paid => {
+                policy.write.paywall = only_key(config.paywalls.keys());
+            }
+            RelayTemplate::Archive => {
+                policy.write.require_auth = true;
+                policy.write.allowed_pubkeys = Some(vec![admin]);
+                policy.rate_limit.writes_per_minute = None;
+            }
+        }
+        policy
+    }
+
+    pub fn info(self, config: &MoarConfig) -> TemplateInfo {
+        TemplateInfo {
+            id: self.id(),
+            description: self.description(),
+            requires: self.requires().map(|field| format!("write.{}", field)),
+            policy: self.policy(config),
+        }
+    }
+}
+
+fn only_key<'a>(mut keys: impl Iterator<Item = &'a String>) -> Option<String> {
+    let first = keys.next()?;
+    keys.next().is_none().then(|| first.clone())
+}
+
+/// Expand the `template` field of a relay create payload, if it has one,
+/// into its `policy`, keeping any policy fields the payload sets itself.
+pub fn apply(payload: &mut Value, config: &MoarConfig) -> Result<(), String> {
+    let Some(fields) = payload.as_object_mut() else {
+        return Ok(());
+    };
+    let Some(id) = fields.remove("template") else {
+        return Ok(());
+    };
+    let template = id
+        .as_str()
+        .and_then(RelayTemplate::parse)
+        .ok_or_else(|| format!("Unknown template {}; see GET /api/relay-templates", id))?;
+
+    let mut policy = serde_json::to_value(template.policy(config)).map_err(|e| e.to_string())?;
+    if let Some(overrides) = fields.remove("policy") {
+        merge(&mut policy, overrides);
+    }
+    if let Some(field) = template.requires() {
+        let id = policy["write"][field].as_str().unwrap_or_default();
+        let known = match field {
+            "wot" => config.wots.contains_key(id),
+            _ => config.paywalls.contains_key(id),
+        };
+        if !known {
+            return Err(format!(
+                "Template '{}' needs policy.write.{} to name a configured {}",
+                template.id(),
+                field,
+                field
+            ));
+        }
+    }
+    fields.insert("policy".to_string(), policy);
+    Ok(())
+}
+
+/// Lay `overrides` over `base`, merging objects key by key.
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RelayConfig;
+    use serde_json::json;
+
+    fn config(wots: &[&str]) -> MoarConfig {
+        let mut toml = String::from(
+            r#"
+domain = "example.com"
+port = 8080
+admin_pubkey = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+
+[relays]
+"#,
+        );
+        for id in wots {
+            toml.push_str(&format!("\n[wots.{}]\nseed = \"npub\"\n", id));
+        }
+        toml::from_str(&toml).unwrap()
+    }
+
+    fn create(payload: Value, config: &MoarConfig) -> Result<RelayConfig, String> {
+        let mut payload = payload;
+        apply(&mut payload, config)?;
+        serde_json::from_value(payload).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn templates_expand_under_payload_policy() {
+        let config = config(&["friends"]);
+        let base = json!({"name": "Inbox", "subdomain": "inbox", "db_path": "data/inbox.mdb"});
+
+        let mut payload = base.clone();
+        payload["template"] = json!("inbox");
+        payload["policy"] = json!({"rate_limit": {"pubkey_writes_per_minute": 5}});
+        let relay = create(payload, &config).unwrap();
+        assert_eq!(relay.policy.write.tagged_pubkeys, Some(vec![config.admin_pubkey.clone()]));
+        assert_eq!(relay.policy.read.protected_kinds, Some(vec![4, 1059]));
+        assert_eq!(relay.policy.rate_limit.pubkey_writes_per_minute, Some(5));
+        // Fields the payload leaves alone keep the preset's values
+        assert_eq!(relay.policy.rate_limit.reads_per_minute, Some(60));
+
+        // The only WoT is the default
+        let mut payload = base.clone();
+        payload["template"] = json!("community");
+        assert_eq!(create(payload, &config).unwrap().policy.write.wot.as_deref(), Some("friends"));
+
+        let mut payload = base.clone();
+        payload["template"] = json!("paid");
+        assert!(create(payload, &config).unwrap_err().contains("paywall"));
+
+        let mut payload = base.clone();
+        payload["template"] = json!("blog");
+        assert!(create(payload, &config).is_err());
+
+        // Without a template the payload is taken as is
+        assert!(create(base, &config).unwrap().policy.write.allowed_pubkeys.is_none());
+    }
+
+    #[test]
+    fn several_wots_must_be_chosen_between() {
+        let config = config(&["friends", "family"]);
+        assert_eq!(RelayTemplate::Community.policy(&config).write.wot, None);
+        let mut payload = json!({"template": "community", "policy": {"write": {"wot": "family"}}});
+        apply(&mut payload, &config).unwrap();
+        assert_eq!(payload["policy"]["write"]["wot"], "family");
+    }
+}
@@ -0,0 +1,225 @@
+//! Built-in HTTPS for the gateway: static PEM certificates chosen by SNI, or
+//! certificates obtained from an ACME CA via TLS-ALPN-01.
+
+use crate::config::{MoarConfig, RoutingMode, TlsConfig};
+use crate::error::{Error, Result};
+use axum::Router;
+use futures_util::StreamExt;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use rustls_acme::caches::DirCache;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Picks a certificate by the SNI hostname, falling back to the default one.
+#[derive(Debug)]
+struct SniResolver {
+    default: Option<Arc<CertifiedKey>>,
+    by_host: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        hello
+            .server_name()
+            .and_then(|name| self.by_host.get(&name.to_ascii_lowercase()))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn load_certified_key(provider: &CryptoProvider, cert_path: &str, key_path: &str) -> Result<Arc<CertifiedKey>> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|iter| iter.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| Error::Tls(format!("failed to read certificates from {}: {}", cert_path, e)))?;
+    if certs.is_empty() {
+        return Err(Error::Tls(format!("no certificates found in {}", cert_path)));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| Error::Tls(format!("failed to read private key from {}: {}", key_path, e)))?;
+    let key = provider
+        .key_provider
+        .load_private_key(key)
+        .map_err(|e| Error::Tls(format!("unsupported private key in {}: {}", key_path, e)))?;
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
+
+fn server_config(provider: Arc<CryptoProvider>, resolver: Arc<dyn ResolvesServerCert>) -> Result<Arc<ServerConfig>> {
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| Error::Tls(e.to_string()))?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    // WebSocket upgrades need HTTP/1.1
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Server config for the static certificates in `tls`.
+pub fn static_server_config(tls: &TlsConfig) -> Result<Arc<ServerConfig>> {
+    let provider = provider();
+    let default = match (&tls.cert_path, &tls.key_path) {
+        (Some(cert), Some(key)) => Some(load_certified_key(&provider, cert, key)?),
+        _ => None,
+    };
+    let mut by_host = HashMap::new();
+    for (host, cert) in &tls.certs {
+        by_host.insert(
+            host.to_ascii_lowercase(),
+            load_certified_key(&provider, &cert.cert_path, &cert.key_path)?,
+        );
+    }
+    server_config(provider, Arc::new(SniResolver { default, by_host }))
+}
+
+/// Every hostname the gateway answers on: the domain itself plus each relay
+/// and blossom subdomain (unless they are reached by path) and the admin
+/// subdomain.
+pub fn hostnames(config: &MoarConfig) -> Vec<String> {
+    let mut hosts = vec![config.domain.clone()];
+    let by_subdomain = config.routing == RoutingMode::Subdomain;
+    let subdomains = config
+        .relays
+        .values()
+        .map(|r| &r.subdomain)
+        .chain(config.blossoms.values().map(|b| &b.subdomain))
+        .filter(|_| by_subdomain)
+        .chain(&config.admin.subdomain);
+    for sub in subdomains {
+        let host = format!("{}.{}", sub, config.domain);
+        if !hosts.contains(&host) {
+            hosts.push(host);
+        }
+    }
+    hosts
+}
+
+/// Serve `app` over HTTPS on `tls.port` of `ip` until the listener fails.
+pub async fn serve(tls: TlsConfig, ip: IpAddr, hosts: Vec<String>, acme_cache: PathBuf, app: Router) -> Result<()> {
+    let addr = SocketAddr::new(ip, tls.port);
+    let server = axum_server::bind(addr);
+
+    match &tls.acme {
+        Some(acme) => {
+            let cache_dir = acme.cache_dir.as_ref().map(PathBuf::from).unwrap_or(acme_cache);
+            tracing::info!("Requesting ACME certificates for {}", hosts.join(", "));
+            let mut state = rustls_acme::AcmeConfig::new(&hosts)
+                .contact(acme.contact.iter().map(|email| format!("mailto:{}", email)))
+                .cache(DirCache::new(cache_dir))
+                .directory_lets_encrypt(!acme.staging)
+                .state();
+            let acceptor = state.axum_acceptor(server_config(provider(), state.resolver())?);
+            tokio::spawn(async move {
+                while let Some(event) = state.next().await {
+                    match event {
+                        Ok(ok) => tracing::info!("ACME: {:?}", ok),
+                        Err(e) => tracing::error!("ACME error: {:?}", e),
+                    }
+                }
+            });
+            tracing::info!("Gateway listening on https://{} (ACME)", addr);
+            server
+                .acceptor(acceptor)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+        None => {
+            let config = axum_server::tls_rustls::RustlsConfig::from_config(static_server_config(&tls)?);
+            tracing::info!("Gateway listening on https://{}", addr);
+            server
+                .acceptor(axum_server::tls_rustls::RustlsAcceptor::new(config))
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_domain_and_service_subdomains() {
+        let config: MoarConfig = toml::from_str(
+            r#"
+domain = "example.com"
+port = 8080
+admin_pubkey = "npub"
+
+[admin]
+subdomain = "admin"
+
+[relays.outbox]
+name = "Outbox"
+subdomain = "outbox"
+db_path = "data/outbox.mdb"
+
+[blossoms.media]
+name = "Media"
+subdomain = "media"
+storage_path = "data/media"
+"#,
+        )
+        .unwrap();
+        let mut hosts = hostnames(&config);
+        hosts.sort();
+        assert_eq!(
+            hosts,
+            vec!["admin.example.com", "example.com", "media.example.com", "outbox.example.com"]
+        );
+
+        // Relays and blossom servers reached by path need no certificates
+        let mut config = config;
+        config.routing = RoutingMode::Path;
+        let mut hosts = hostnames(&config);
+        hosts.sort();
+        assert_eq!(hosts, vec!["admin.example.com", "example.com"]);
+        assert_eq!(
+            config.relay_url("outbox", &config.relays["outbox"]),
+            "https://example.com/relay/outbox"
+        );
+        assert_eq!(
+            config.blossom_url("media", &config.blossoms["media"]),
+            "https://example.com/blossom/media"
+        );
+
+        // A proxy on a nonstandard port is advertised through public_url
+        let public_url_problems =
+            |config: &MoarConfig| config.validate().iter().filter(|p| p.contains("public_url")).count();
+        config.routing = RoutingMode::Subdomain;
+        config.public_url = Some("https://example.com:8443".into());
+        assert_eq!(public_url_problems(&config), 0);
+        assert_eq!(config.origin(None), "https://example.com:8443");
+        assert_eq!(
+            config.relay_url("outbox", &config.relays["outbox"]),
+            "https://outbox.example.com:8443"
+        );
+        config.public_url = Some("https://other.com/x".into());
+        assert_eq!(public_url_problems(&config), 1);
+    }
+
+    #[test]
+    fn missing_certificate_files_are_reported() {
+        let tls: TlsConfig = toml::from_str(
+            r#"
+cert_path = "/nonexistent/cert.pem"
+key_path = "/nonexistent/key.pem"
+"#,
+        )
+        .unwrap();
+        assert_eq!(tls.port, 443);
+        let err = static_server_config(&tls).unwrap_err().to_string();
+        assert!(err.contains("/nonexistent/cert.pem"), "{}", err);
+    }
+}
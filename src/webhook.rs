@@ -0,0 +1,372 @@
+//! Operator notifications. Notable events — a paywall payment, a failed WoT
+//! build, a relay rejecting much of what it is sent, a nearly full disk, an
+//! upload over a blossom server's size limit, a request for write access —
+//! are POSTed as JSON to every webhook subscribed to them. Critical ones (a
+//! restart, a full LMDB map, NWC trouble) can also be sent to the admin as
+//! NIP-17 DMs. Failed deliveries are retried with doubling backoff in the
+//! background, so callers never wait on an endpoint.
+
+use crate::config::{AlertConfig, DmAlertConfig, WebhookConfig};
+use hmac::{Hmac, Mac};
+use nostr::{Keys, PublicKey};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// First retry delay; each further retry waits twice as long.
+const RETRY_BASE: Duration = Duration::from_secs(5);
+const RETRY_MAX: Duration = Duration::from_secs(600);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Retries of a DM no relay accepted.
+const DM_RETRIES: u32 = 3;
+/// How long a throttled alert stays quiet after being sent.
+const THROTTLE: Duration = Duration::from_secs(3600);
+
+/// Header carrying `sha256=<hex HMAC-SHA256 of the body>` when a webhook
+/// has a secret.
+pub const SIGNATURE_HEADER: &str = "x-moar-signature";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    PaymentCompleted,
+    WotBuildFailed,
+    RejectedSpike,
+    DiskNearFull,
+    BlossomQuotaExceeded,
+    /// The process started, after a restart or an update.
+    Started,
+    /// A relay's LMDB map is full; nothing more can be stored.
+    MapFull,
+    /// A paywall's NWC wallet could not be reached.
+    NwcFailed,
+    /// A paywall stopped watching an invoice, so its payment may be missed.
+    PaywallWatcherFailed,
+    /// Someone asked for write access on a relay's home page.
+    AccessRequested,
+    /// Sent by the admin test endpoint, to every webhook regardless of `events`.
+    Test,
+}
+
+impl AlertKind {
+    pub const ALL: [AlertKind; 11] = [
+        AlertKind::PaymentCompleted,
+        AlertKind::WotBuildFailed,
+        AlertKind::RejectedSpike,
+        AlertKind::DiskNearFull,
+        AlertKind::BlossomQuotaExceeded,
+        AlertKind::Started,
+        AlertKind::MapFull,
+        AlertKind::NwcFailed,
+        AlertKind::PaywallWatcherFailed,
+        AlertKind::AccessRequested,
+        AlertKind::Test,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AlertKind::PaymentCompleted => "payment_completed",
+            AlertKind::WotBuildFailed => "wot_build_failed",
+            AlertKind::RejectedSpike => "rejected_spike",
+            AlertKind::DiskNearFull => "disk_near_full",
+            AlertKind::BlossomQuotaExceeded => "blossom_quota_exceeded",
+            AlertKind::Started => "started",
+            AlertKind::MapFull => "map_full",
+            AlertKind::NwcFailed => "nwc_failed",
+            AlertKind::PaywallWatcherFailed => "paywall_watcher_failed",
+            AlertKind::AccessRequested => "access_requested",
+            AlertKind::Test => "test",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+/// The JSON body of a webhook delivery.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub event: AlertKind,
+    pub message: String,
+    /// Event-specific details, such as the relay or paywall involved.
+    pub data: serde_json::Value,
+    pub timestamp: u64,
+}
+
+impl Alert {
+    pub fn new(event: AlertKind, message: impl Into<String>, data: serde_json::Value) -> Self {
+        Self {
+            event,
+            message: message.into(),
+            data,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        }
+    }
+}
+
+/// DMs to the admin, sent from the instance's key.
+struct DmChannel {
+    keys: Keys,
+    admin: PublicKey,
+    config: DmAlertConfig,
+}
+
+#[derive(Default)]
+pub struct Notifier {
+    webhooks: HashMap<String, WebhookConfig>,
+    dm: Option<DmChannel>,
+    client: reqwest::Client,
+    /// When each throttled alert was last sent, by key.
+    throttled: Mutex<HashMap<String, Instant>>,
+}
+
+impl Notifier {
+    pub fn new(webhooks: HashMap<String, WebhookConfig>) -> Self {
+        Self {
+            webhooks,
+            client: reqwest::Client::new(),
+            ..Default::default()
+        }
+    }
+
+    /// Also DM the events in `config.events` to `admin`, from `keys`.
+    pub fn with_dm(mut self, keys: Keys, admin: PublicKey, config: DmAlertConfig) -> Self {
+        self.dm = Some(DmChannel { keys, admin, config });
+        self
+    }
+
+    /// [`Self::notify`], unless an alert with the same `key` was sent in
+    /// the last hour; for conditions that recur on every write.
+    pub fn notify_throttled(&self, key: &str, alert: Alert) {
+        let mut throttled = self.throttled.lock().unwrap();
+        if throttled.get(key).is_some_and(|sent| sent.elapsed() < THROTTLE) {
+            return;
+        }
+        throttled.insert(key.to_string(), Instant::now());
+        drop(throttled);
+        self.notify(alert);
+    }
+
+    /// Send `alert` to every webhook subscribed to it, in the background.
+    pub fn notify(&self, alert: Alert) {
+        tracing::info!(event = alert.event.name(), "{}", alert.message);
+        let body = match serde_json::to_vec(&alert) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("Failed to encode alert: {}", e);
+                return;
+            }
+        };
+        for (id, webhook) in &self.webhooks {
+            if !subscribed(webhook, alert.event) {
+                continue;
+            }
+            let client = self.client.clone();
+            let id = id.clone();
+            let webhook = webhook.clone();
+            let body = body.clone();
+            tokio::spawn(async move {
+                let channel = format!("webhook '{}'", id);
+                with_retries(&channel, webhook.max_retries, || deliver(&client, &webhook, &body)).await;
+            });
+        }
+
+        if let Some(dm) = &self.dm {
+            if dm.config.events.iter().any(|e| e == alert.event.name()) {
+                let keys = dm.keys.clone();
+                let admin = dm.admin;
+                let relays = dm.config.relays.clone();
+                let content = format!("[{}] {}", alert.event.name(), alert.message);
+                tokio::spawn(async move {
+                    with_retries("admin DM", DM_RETRIES, || async {
+                        crate::dm::send_private_dm(&keys, admin, &content, &relays)
+                            .await
+                            .map(|_| ())
+                            .map_err(|e| e.to_string())
+                    })
+                    .await;
+                });
+            }
+        }
+    }
+
+    /// Deliver a test alert to webhook `id` once, reporting how it went.
+    /// `None` if there is no such webhook.
+    pub async fn test(&self, id: &str) -> Option<Result<(), String>> {
+        let webhook = self.webhooks.get(id)?;
+        let alert = Alert::new(AlertKind::Test, format!("Test notification for webhook '{}'", id), serde_json::json!({}));
+        let body = serde_json::to_vec(&alert).map_err(|e| e.to_string());
+        Some(match body {
+            Ok(body) => deliver(&self.client, webhook, &body).await,
+            Err(e) => Err(e),
+        })
+    }
+}
+
+fn subscribed(webhook: &WebhookConfig, kind: AlertKind) -> bool {
+    kind == AlertKind::Test || webhook.events.is_empty() || webhook.events.iter().any(|e| e == kind.name())
+}
+
+/// Call `send` until it succeeds or has been retried `max_retries` times.
+async fn with_retries<F, Fut>(channel: &str, max_retries: u32, mut send: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let mut attempt = 0;
+    while let Err(e) = send().await {
+        if attempt == max_retries {
+            tracing::warn!("Giving up on alert delivery to {}: {}", channel, e);
+            return;
+        }
+        let delay = backoff(attempt);
+        tracing::debug!("Alert delivery to {} failed, retrying in {:?}: {}", channel, delay, e);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+fn backoff(attempt: u32) -> Duration {
+    RETRY_BASE.saturating_mul(2u32.saturating_pow(attempt)).min(RETRY_MAX)
+}
+
+/// The signature header value for `body`.
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", crate::blossom::handlers::hex::encode(mac.finalize().into_bytes()))
+}
+
+async fn deliver(client: &reqwest::Client, webhook: &WebhookConfig, body: &[u8]) -> Result<(), String> {
+    let mut request = client
+        .post(&webhook.url)
+        .timeout(DELIVERY_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_vec());
+    if let Some(secret) = &webhook.secret {
+        request = request.header(SIGNATURE_HEADER, signature(secret, body));
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("endpoint answered {}", response.status()));
+    }
+    Ok(())
+}
+
+/// A condition that alerts when it starts holding, and not again until it
+/// has cleared, so a full disk isn't reported every minute.
+#[derive(Debug, Default)]
+pub struct Latch(bool);
+
+impl Latch {
+    /// Whether `holds` just became true.
+    pub fn update(&mut self, holds: bool) -> bool {
+        let fire = holds && !self.0;
+        self.0 = holds;
+        fire
+    }
+}
+
+/// Whether a minute's `saved` and `rejected` counts make a rejection spike.
+pub fn rejected_spike(config: &AlertConfig, saved: u64, rejected: u64) -> bool {
+    rejected >= config.rejected_spike_min.max(1)
+        && rejected * 100 >= (saved + rejected) * u64::from(config.rejected_spike_percent)
+}
+
+/// Whether `used` of `total` bytes crosses the disk alert threshold.
+pub fn disk_near_full(config: &AlertConfig, used: u64, total: u64) -> bool {
+    total > 0 && u128::from(used) * 100 >= u128::from(total) * u128::from(config.disk_full_percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+    use std::sync::{Arc, Mutex};
+
+    fn webhook(url: String, events: &[&str]) -> WebhookConfig {
+        WebhookConfig {
+            url,
+            events: events.iter().map(|e| e.to_string()).collect(),
+            secret: Some("hunter2".into()),
+            max_retries: 0,
+        }
+    }
+
+    #[test]
+    fn thresholds_and_latches() {
+        let config = AlertConfig::default();
+        assert!(!rejected_spike(&config, 0, 10));
+        assert!(!rejected_spike(&config, 100, 60));
+        assert!(rejected_spike(&config, 50, 50));
+        assert!(disk_near_full(&config, 95, 100));
+        assert!(!disk_near_full(&config, 50, 100));
+        assert!(!disk_near_full(&config, 0, 0));
+
+        let mut latch = Latch::default();
+        assert!(latch.update(true));
+        assert!(!latch.update(true));
+        assert!(!latch.update(false));
+        assert!(latch.update(true));
+
+        assert_eq!(backoff(0), RETRY_BASE);
+        assert_eq!(backoff(2), RETRY_BASE * 4);
+        assert_eq!(backoff(40), RETRY_MAX);
+        assert_eq!(AlertKind::parse("disk_near_full"), Some(AlertKind::DiskNearFull));
+        assert!(AlertKind::parse("nope").is_none());
+    }
+
+    type Deliveries = Arc<Mutex<Vec<(HeaderMap, Vec<u8>)>>>;
+
+    #[tokio::test]
+    async fn deliveries_are_filtered_and_signed() {
+        let received: Deliveries = Arc::default();
+        let sink = received.clone();
+        let app = axum::Router::new().route(
+            "/hook",
+            axum::routing::post(move |headers: HeaderMap, body: axum::body::Bytes| async move {
+                sink.lock().unwrap().push((headers, body.to_vec()));
+                "ok"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let notifier = Notifier::new(HashMap::from([
+            ("payments".to_string(), webhook(url.clone(), &["payment_completed"])),
+            ("missing".to_string(), webhook(format!("{}-missing", url), &[])),
+        ]));
+        notifier.notify(Alert::new(AlertKind::DiskNearFull, "disk", serde_json::json!({})));
+        notifier.notify(Alert::new(AlertKind::PaymentCompleted, "paid", serde_json::json!({"sats": 21})));
+        for _ in 0..3 {
+            notifier.notify_throttled("again", Alert::new(AlertKind::PaymentCompleted, "again", serde_json::json!({})));
+        }
+        assert_eq!(notifier.test("payments").await, Some(Ok(())));
+        assert!(notifier.test("missing").await.unwrap().is_err());
+        assert!(notifier.test("unknown").await.is_none());
+
+        for _ in 0..50 {
+            if received.lock().unwrap().len() >= 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        let mut events: Vec<String> = received
+            .iter()
+            .map(|(headers, body)| {
+                assert_eq!(headers[SIGNATURE_HEADER].to_str().unwrap(), signature("hunter2", body));
+                let alert: serde_json::Value = serde_json::from_slice(body).unwrap();
+                alert["event"].as_str().unwrap().to_string()
+            })
+            .collect();
+        events.sort();
+        assert_eq!(events, ["payment_completed", "payment_completed", "test"]);
+    }
+}
@@ -1,11 +1,13 @@
-use crate::config::WotConfig;
+use crate::config::{WotConfig, WotMutes};
+use crate::policy::SetDirectory;
+use crate::webhook::{Alert, AlertKind, Notifier};
 use futures_util::{SinkExt, StreamExt};
 use nostr::PublicKey;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::SystemTime;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{Notify, RwLock};
 use tokio::task::JoinHandle;
 use tokio_tungstenite::tungstenite::Message;
 
@@ -13,27 +15,50 @@ use tokio_tungstenite::tungstenite::Message;
 // WotSet — shared pubkey set used by PolicyEngine
 // ---------------------------------------------------------------------------
 
+/// Depth recorded for members loaded from a file written before depths were
+/// kept; reported as unknown.
+const UNKNOWN_DEPTH: u8 = u8::MAX;
+
+/// Magic prefix of WoT files that store a depth byte after each pubkey.
+const DISK_MAGIC: &[u8; 4] = b"WOT2";
+
 #[derive(Clone)]
 pub struct WotSet {
-    inner: Arc<std::sync::RwLock<HashSet<PublicKey>>>,
+    /// Member pubkey → depth at which it entered the WoT (0 for the seed)
+    inner: Arc<std::sync::RwLock<HashMap<PublicKey, u8>>>,
 }
 
 impl WotSet {
     fn new() -> Self {
         Self {
-            inner: Arc::new(std::sync::RwLock::new(HashSet::new())),
+            inner: Arc::new(std::sync::RwLock::new(HashMap::new())),
         }
     }
 
     pub fn contains(&self, pk: &PublicKey) -> bool {
-        self.inner.read().unwrap().contains(pk)
+        self.inner.read().unwrap().contains_key(pk)
+    }
+
+    /// Depth at which `pk` entered the WoT, if it is a member and the depth
+    /// is known.
+    pub fn depth(&self, pk: &PublicKey) -> Option<u8> {
+        self.inner
+            .read()
+            .unwrap()
+            .get(pk)
+            .copied()
+            .filter(|d| *d != UNKNOWN_DEPTH)
     }
 
     pub fn len(&self) -> usize {
         self.inner.read().unwrap().len()
     }
 
-    fn replace(&self, set: HashSet<PublicKey>) {
+    pub fn is_empty(&self) -> bool {
+        self.inner.read().unwrap().is_empty()
+    }
+
+    fn replace(&self, set: HashMap<PublicKey, u8>) {
         *self.inner.write().unwrap() = set;
     }
 }
@@ -56,6 +81,24 @@ pub enum WotStatus {
     },
 }
 
+// ---------------------------------------------------------------------------
+// WotProgress — detail of the current or most recent build
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct WotProgress {
+    /// Whether this is a from-scratch build rather than an incremental refresh.
+    pub full: bool,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub depth_progress: u8,
+    pub total_depth: u8,
+    /// Pubkeys reached so far, before mutes and exclusions are subtracted.
+    pub pubkeys_found: usize,
+    /// Relay URL → last error seen from it during this build.
+    pub relay_errors: BTreeMap<String, String>,
+}
+
 // ---------------------------------------------------------------------------
 // WotEntry
 // ---------------------------------------------------------------------------
@@ -65,42 +108,64 @@ struct WotEntry {
     set: WotSet,
     status: Arc<RwLock<WotStatus>>,
     last_updated: Arc<RwLock<Option<u64>>>,
+    progress: Arc<RwLock<WotProgress>>,
+    /// Wakes the builder for an immediate full rebuild.
+    rebuild: Arc<Notify>,
     handle: Option<JoinHandle<()>>,
 }
 
+impl WotEntry {
+    fn new(config: WotConfig) -> Self {
+        Self {
+            config,
+            set: WotSet::new(),
+            status: Arc::new(RwLock::new(WotStatus::Pending)),
+            last_updated: Arc::new(RwLock::new(None)),
+            progress: Arc::new(RwLock::new(WotProgress::default())),
+            rebuild: Arc::new(Notify::new()),
+            handle: None,
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // WotManager
 // ---------------------------------------------------------------------------
 
 pub struct WotManager {
     entries: RwLock<HashMap<String, WotEntry>>,
+    /// Each entry's set by id, for relay policies to resolve at check time.
+    sets: Arc<SetDirectory<WotSet>>,
     discovery_relays: RwLock<Vec<String>>,
+    relay_health: RelayHealth,
     data_dir: PathBuf,
+    notifier: Arc<Notifier>,
 }
 
 impl WotManager {
-    pub fn new(discovery_relays: Vec<String>, wots: HashMap<String, WotConfig>) -> Arc<Self> {
-        let data_dir = PathBuf::from("data/wot");
+    /// WoT sets are persisted under `data_dir`.
+    pub fn new(
+        data_dir: PathBuf,
+        discovery_relays: Vec<String>,
+        wots: HashMap<String, WotConfig>,
+        notifier: Arc<Notifier>,
+    ) -> Arc<Self> {
         let mut entries = HashMap::new();
+        let sets = Arc::new(SetDirectory::default());
 
         for (id, config) in wots {
-            let set = WotSet::new();
-            entries.insert(
-                id,
-                WotEntry {
-                    config,
-                    set,
-                    status: Arc::new(RwLock::new(WotStatus::Pending)),
-                    last_updated: Arc::new(RwLock::new(None)),
-                    handle: None,
-                },
-            );
+            let entry = WotEntry::new(config);
+            sets.insert(&id, entry.set.clone());
+            entries.insert(id, entry);
         }
 
         Arc::new(Self {
             entries: RwLock::new(entries),
+            sets,
             discovery_relays: RwLock::new(discovery_relays),
+            relay_health: RelayHealth::default(),
             data_dir,
+            notifier,
         })
     }
 
@@ -156,28 +221,31 @@ impl WotManager {
         let set = entry.set.clone();
         let status = Arc::clone(&entry.status);
         let last_updated = Arc::clone(&entry.last_updated);
+        let progress = Arc::clone(&entry.progress);
+        let rebuild = Arc::clone(&entry.rebuild);
         let disk_path = self.data_dir.join(format!("{}.bin", id));
 
         let handle = tokio::spawn(async move {
+            // Skip the first build if the set loaded from disk is fresh
+            let mut skip_build = matches!(*status.read().await, WotStatus::Ready);
+            let mut graph = WotGraph::new();
+
             loop {
-                // Skip build if already fresh (loaded from disk on first iteration)
-                let should_build = {
-                    let s = status.read().await;
-                    !matches!(*s, WotStatus::Ready)
-                };
+                if !skip_build {
+                    if now_secs().saturating_sub(graph.built_at) >= config.full_rebuild_hours * 3600 {
+                        tracing::info!("WoT '{}' due for a full rebuild", wot_id);
+                        graph = WotGraph::new();
+                    }
 
-                if should_build {
                     let relays = manager.discovery_relays.read().await.clone();
-                    match build_wot(&config, &relays, &set, &status).await {
-                        Ok(()) => {
-                            let now = SystemTime::now()
-                                .duration_since(SystemTime::UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_secs();
-                            *last_updated.write().await = Some(now);
+                    match update_wot(&config, &relays, &manager.relay_health, &mut graph, &status, &progress).await {
+                        Ok(members) => {
+                            set.replace(members);
+                            *status.write().await = WotStatus::Ready;
+                            *last_updated.write().await = Some(now_secs());
 
                             // Save to disk
-                            let pubkeys: HashSet<PublicKey> =
+                            let pubkeys: HashMap<PublicKey, u8> =
                                 set.inner.read().unwrap().clone();
                             if let Err(e) = save_pubkeys_to_disk(&disk_path, &pubkeys).await {
                                 tracing::warn!("Failed to save WoT '{}' to disk: {}", wot_id, e);
@@ -185,15 +253,24 @@ impl WotManager {
                         }
                         Err(e) => {
                             tracing::error!("WoT '{}' build failed: {}", wot_id, e);
+                            manager.notifier.notify(Alert::new(
+                                AlertKind::WotBuildFailed,
+                                format!("WoT '{}' build failed: {}", wot_id, e),
+                                serde_json::json!({ "wot_id": wot_id, "error": e.to_string() }),
+                            ));
                             *status.write().await = WotStatus::Error {
                                 message: e.to_string(),
                             };
                             // Retry after 5 minutes on total failure
-                            tokio::time::sleep(std::time::Duration::from_secs(300)).await;
+                            let retry = tokio::time::sleep(std::time::Duration::from_secs(300));
+                            if wait_or_rebuild(retry, &rebuild).await {
+                                graph = WotGraph::new();
+                            }
                             continue;
                         }
                     }
                 }
+                skip_build = false;
 
                 // Sleep until next refresh
                 let sleep_hours = config.update_interval_hours.max(1);
@@ -202,16 +279,22 @@ impl WotManager {
                     wot_id,
                     sleep_hours
                 );
-                tokio::time::sleep(std::time::Duration::from_secs(sleep_hours * 3600)).await;
-
-                // Mark as needing rebuild for next iteration
-                *status.write().await = WotStatus::Pending;
+                let sleep = tokio::time::sleep(std::time::Duration::from_secs(sleep_hours * 3600));
+                if wait_or_rebuild(sleep, &rebuild).await {
+                    tracing::info!("WoT '{}' rebuild requested", wot_id);
+                    graph = WotGraph::new();
+                }
             }
         });
 
         entry.handle = Some(handle);
     }
 
+    /// Every WoT's set by id, kept current as WoTs are added and removed.
+    pub fn sets(&self) -> Arc<SetDirectory<WotSet>> {
+        self.sets.clone()
+    }
+
     pub async fn get_set(&self, id: &str) -> Option<WotSet> {
         self.entries.read().await.get(id).map(|e| e.set.clone())
     }
@@ -223,6 +306,37 @@ impl WotManager {
         Some(status)
     }
 
+    pub async fn get_progress(&self, id: &str) -> Option<WotProgress> {
+        let entries = self.entries.read().await;
+        let entry = entries.get(id)?;
+        let progress = entry.progress.read().await.clone();
+        Some(progress)
+    }
+
+    /// Start a full rebuild now instead of waiting for the next refresh.
+    pub async fn request_rebuild(&self, id: &str) -> Result<(), String> {
+        let entries = self.entries.read().await;
+        let entry = entries.get(id).ok_or_else(|| format!("WoT '{}' not found", id))?;
+        entry.rebuild.notify_one();
+        Ok(())
+    }
+
+    /// Membership and entry depth of each of `pubkeys`, or `None` if the WoT
+    /// doesn't exist.
+    pub async fn lookup(&self, id: &str, pubkeys: &[PublicKey]) -> Option<Vec<WotMembership>> {
+        let set = self.get_set(id).await?;
+        Some(
+            pubkeys
+                .iter()
+                .map(|pk| WotMembership {
+                    pubkey: pk.to_hex(),
+                    member: set.contains(pk),
+                    depth: set.depth(pk),
+                })
+                .collect(),
+        )
+    }
+
     pub async fn list_wots(&self) -> Vec<WotInfo> {
         let entries = self.entries.read().await;
         let mut result = Vec::new();
@@ -243,16 +357,9 @@ impl WotManager {
         if entries.contains_key(&id) {
             return Err(format!("WoT '{}' already exists", id));
         }
-        entries.insert(
-            id.clone(),
-            WotEntry {
-                config,
-                set: WotSet::new(),
-                status: Arc::new(RwLock::new(WotStatus::Pending)),
-                last_updated: Arc::new(RwLock::new(None)),
-                handle: None,
-            },
-        );
+        let entry = WotEntry::new(config);
+        self.sets.insert(&id, entry.set.clone());
+        entries.insert(id.clone(), entry);
         drop(entries);
         self.start_builder(&id).await;
         Ok(())
@@ -284,6 +391,7 @@ impl WotManager {
         let mut entry = entries
             .remove(id)
             .ok_or_else(|| format!("WoT '{}' not found", id))?;
+        self.sets.remove(id);
 
         if let Some(handle) = entry.handle.take() {
             handle.abort();
@@ -304,6 +412,12 @@ impl WotManager {
         *self.discovery_relays.write().await = relays;
     }
 
+    /// How each configured discovery relay has been doing.
+    pub async fn discovery_relay_health(&self) -> Vec<RelayHealthReport> {
+        let relays = self.discovery_relays.read().await.clone();
+        self.relay_health.report(&relays)
+    }
+
     pub async fn wot_ids_referencing(&self, wot_id: &str) -> bool {
         self.entries.read().await.contains_key(wot_id)
     }
@@ -322,16 +436,88 @@ pub struct WotInfo {
     pub last_updated: Option<u64>,
 }
 
+#[derive(Debug, serde::Serialize)]
+pub struct WotMembership {
+    pub pubkey: String,
+    pub member: bool,
+    /// Depth at which the pubkey entered the WoT (0 is the seed); `None` for
+    /// non-members and sets loaded from files that predate depth tracking.
+    pub depth: Option<u8>,
+}
+
 // ---------------------------------------------------------------------------
 // Background WoT builder
 // ---------------------------------------------------------------------------
 
-async fn build_wot(
+/// Contact lists of every WoT member that is close enough to the seed for
+/// its follows to count, kept between refreshes so later ones only need the
+/// lists that changed.
+struct WotGraph {
+    /// Author → (created_at of its newest kind 3, followed pubkeys). Authors
+    /// without a known contact list have an empty entry.
+    follows: HashMap<PublicKey, (u64, HashSet<PublicKey>)>,
+    /// NIP-65 write relays of the authors in `follows`.
+    write_relays: HashMap<PublicKey, Vec<String>>,
+    /// When the last successful refresh started; `None` until the first one.
+    synced_at: Option<u64>,
+    /// When the graph was last built from scratch.
+    built_at: u64,
+}
+
+impl WotGraph {
+    fn new() -> Self {
+        Self {
+            follows: HashMap::new(),
+            write_relays: HashMap::new(),
+            synced_at: None,
+            built_at: now_secs(),
+        }
+    }
+
+    /// Distance from the seed of every pubkey within `max_depth` hops.
+    fn depths(&self, seed: PublicKey, max_depth: u8) -> HashMap<PublicKey, u8> {
+        let mut depths = HashMap::from([(seed, 0)]);
+        let mut layer = vec![seed];
+        for depth in 1..=max_depth {
+            let mut next = Vec::new();
+            for pk in &layer {
+                for followed in self.follows.get(pk).map(|(_, f)| f.iter()).into_iter().flatten() {
+                    if !depths.contains_key(followed) {
+                        depths.insert(*followed, depth);
+                        next.push(*followed);
+                    }
+                }
+            }
+            layer = next;
+        }
+        depths
+    }
+
+    /// Keep `lists` that are newer than what the graph already has.
+    fn apply(&mut self, lists: HashMap<PublicKey, (u64, HashSet<PublicKey>)>) -> usize {
+        let mut changed = 0;
+        for (author, (created_at, followed)) in lists {
+            if self.follows.get(&author).is_none_or(|(t, _)| created_at > *t) {
+                self.follows.insert(author, (created_at, followed));
+                changed += 1;
+            }
+        }
+        changed
+    }
+}
+
+/// Bring `graph` up to date and return the resulting WoT members. An empty
+/// graph is built from scratch depth by depth; otherwise only contact lists
+/// published since the last refresh are fetched, plus the lists of pubkeys
+/// that those changes moved close enough to the seed to matter.
+async fn update_wot(
     config: &WotConfig,
     discovery_relays: &[String],
-    set: &WotSet,
+    health: &RelayHealth,
+    graph: &mut WotGraph,
     status: &Arc<RwLock<WotStatus>>,
-) -> Result<(), anyhow::Error> {
+    progress: &Arc<RwLock<WotProgress>>,
+) -> Result<HashMap<PublicKey, u8>, anyhow::Error> {
     if discovery_relays.is_empty() {
         return Err(anyhow::anyhow!("No discovery relays configured"));
     }
@@ -339,26 +525,50 @@ async fn build_wot(
     let seed = PublicKey::parse(&config.seed)
         .map_err(|e| anyhow::anyhow!("Invalid seed pubkey: {}", e))?;
     let max_depth = config.depth.clamp(1, 4);
+    let started = now_secs();
+    let full = graph.synced_at.is_none();
 
-    *status.write().await = WotStatus::Building {
-        depth_progress: 0,
+    *progress.write().await = WotProgress {
+        full,
+        started_at: Some(started),
         total_depth: max_depth,
+        pubkeys_found: graph.depths(seed, max_depth).len(),
+        ..Default::default()
     };
 
-    let mut all_pubkeys: HashSet<PublicKey> = HashSet::new();
-    all_pubkeys.insert(seed);
-    let mut current_layer: HashSet<PublicKey> = HashSet::new();
-    current_layer.insert(seed);
-    let mut queried: HashSet<PublicKey> = HashSet::new();
+    if full {
+        *status.write().await = WotStatus::Building {
+            depth_progress: 0,
+            total_depth: max_depth,
+        };
+    }
+
+    if let Some(since) = graph.synced_at {
+        let interior: Vec<PublicKey> = graph.follows.keys().copied().collect();
+        // Allow for relays and clients with skewed clocks
+        let since = since.saturating_sub(600);
+        let lists = fetch_contact_lists(
+            config,
+            discovery_relays,
+            health,
+            &interior,
+            Some(since),
+            &mut graph.write_relays,
+            progress,
+        )
+        .await?;
+        let changed = graph.apply(lists);
+        tracing::info!("WoT refresh: {} of {} contact lists changed", changed, interior.len());
+    }
 
     for depth in 1..=max_depth {
-        let to_query: Vec<PublicKey> = current_layer
-            .iter()
-            .filter(|pk| !queried.contains(pk))
-            .copied()
+        let missing: Vec<PublicKey> = graph
+            .depths(seed, max_depth)
+            .into_iter()
+            .filter(|(pk, d)| *d < max_depth && !graph.follows.contains_key(pk))
+            .map(|(pk, _)| pk)
             .collect();
-
-        if to_query.is_empty() {
+        if missing.is_empty() {
             break;
         }
 
@@ -366,96 +576,420 @@ async fn build_wot(
             "WoT depth {}/{}: querying {} pubkeys across {} relays",
             depth,
             max_depth,
-            to_query.len(),
+            missing.len(),
             discovery_relays.len()
         );
+        let lists = fetch_contact_lists(
+            config,
+            discovery_relays,
+            health,
+            &missing,
+            None,
+            &mut graph.write_relays,
+            progress,
+        )
+        .await?;
+        graph.apply(lists);
+        for pk in missing {
+            graph.follows.entry(pk).or_insert_with(|| (0, HashSet::new()));
+        }
 
-        // Chunk into batches of 300
-        let batches: Vec<Vec<String>> = to_query
-            .chunks(300)
-            .map(|chunk| chunk.iter().map(|pk| pk.to_hex()).collect())
-            .collect();
+        let found = graph.depths(seed, max_depth).len();
+        {
+            let mut p = progress.write().await;
+            p.depth_progress = depth;
+            p.pubkeys_found = found;
+        }
 
-        // Distribute batches round-robin across relays
-        let mut relay_batches: HashMap<usize, Vec<Vec<String>>> = HashMap::new();
-        for (i, batch) in batches.iter().enumerate() {
-            let relay_idx = i % discovery_relays.len();
-            relay_batches
-                .entry(relay_idx)
-                .or_default()
-                .push(batch.clone());
-        }
-
-        // Query all relays concurrently
-        let mut handles = Vec::new();
-        for (relay_idx, batches) in relay_batches {
-            let relay_url = discovery_relays[relay_idx].clone();
-            handles.push(tokio::spawn(async move {
-                query_relay_batches(&relay_url, batches).await
-            }));
-        }
-
-        let mut next_layer: HashSet<PublicKey> = HashSet::new();
-        let mut any_success = false;
-
-        for handle in handles {
-            match handle.await {
-                Ok(Ok(followed_pks)) => {
-                    any_success = true;
-                    for pk in followed_pks {
-                        if all_pubkeys.insert(pk) {
-                            next_layer.insert(pk);
-                        }
-                    }
-                }
-                Ok(Err(e)) => {
-                    tracing::warn!("Relay query failed: {}", e);
-                }
+        if full {
+            *status.write().await = WotStatus::Building {
+                depth_progress: depth,
+                total_depth: max_depth,
+            };
+        }
+    }
+
+    // Forget lists of pubkeys that no longer influence the WoT
+    let depths = graph.depths(seed, max_depth);
+    graph.follows.retain(|pk, _| depths.get(pk).is_some_and(|d| *d < max_depth));
+    graph.write_relays.retain(|pk, _| graph.follows.contains_key(pk));
+    graph.synced_at = Some(started);
+
+    let depths_len = depths.len();
+    let mut members = depths;
+    let direct_follows = graph.follows.get(&seed).map(|(_, f)| f.clone()).unwrap_or_default();
+    let excluded = excluded_pubkeys(config, seed, &direct_follows, discovery_relays, health).await;
+    let before = members.len();
+    members.retain(|pk, _| *pk == seed || !excluded.contains(pk));
+    if before > members.len() {
+        tracing::info!("WoT: removed {} muted or excluded pubkeys", before - members.len());
+    }
+
+    {
+        let mut p = progress.write().await;
+        p.depth_progress = max_depth;
+        p.pubkeys_found = depths_len;
+        p.finished_at = Some(now_secs());
+    }
+
+    tracing::info!("WoT {} complete: {} pubkeys", if full { "build" } else { "refresh" }, members.len());
+    Ok(members)
+}
+
+/// Newest contact list of each of `authors` (published after `since`, if
+/// given), from the discovery relays and, with outbox enabled, the authors'
+/// own write relays. Relay lists seen along the way are recorded in
+/// `write_relays`.
+async fn fetch_contact_lists(
+    config: &WotConfig,
+    discovery_relays: &[String],
+    health: &RelayHealth,
+    authors: &[PublicKey],
+    since: Option<u64>,
+    write_relays: &mut HashMap<PublicKey, Vec<String>>,
+    progress: &Arc<RwLock<WotProgress>>,
+) -> Result<HashMap<PublicKey, (u64, HashSet<PublicKey>)>, anyhow::Error> {
+    let batches: Vec<Vec<String>> = authors
+        .chunks(300)
+        .map(|chunk| chunk.iter().map(|pk| pk.to_hex()).collect())
+        .collect();
+
+    // Contact lists and NIP-65 relay lists from the discovery relays
+    let kinds: &[u16] = if config.outbox.enabled { &[3, 10002] } else { &[3] };
+    let result = query_discovery_relays(discovery_relays, health, &batches, kinds, since).await;
+    let mut events = result.events;
+    progress.write().await.relay_errors.extend(result.errors);
+    if result.all_failed {
+        return Err(anyhow::anyhow!("All discovery relays failed"));
+    }
+
+    // Contact lists from each author's own write relays
+    if config.outbox.enabled {
+        write_relays.extend(parse_write_relays(&events));
+        let wanted: HashMap<PublicKey, Vec<String>> = authors
+            .iter()
+            .filter_map(|pk| write_relays.get(pk).map(|r| (*pk, r.clone())))
+            .collect();
+        let plan = plan_outbox_queries(&wanted, discovery_relays, config.outbox.max_relays);
+        tracing::info!("WoT: querying {} outbox relays", plan.len());
+        let results: Vec<_> = futures_util::stream::iter(plan)
+            .map(|(relay_url, authors)| async move {
+                let batches = authors.chunks(300).map(|c| c.to_vec()).collect();
+                let result = query_relay_batches(&relay_url, batches, &[3], since).await;
+                (relay_url, result)
+            })
+            .buffer_unordered(config.outbox.concurrency.max(1))
+            .collect()
+            .await;
+        let mut p = progress.write().await;
+        for (relay_url, result) in results {
+            match result {
+                Ok(received) => events.extend(received),
                 Err(e) => {
-                    tracing::warn!("Relay query task panicked: {}", e);
+                    tracing::debug!("Outbox relay {} failed: {}", relay_url, e);
+                    p.relay_errors.insert(relay_url, e.to_string());
                 }
             }
         }
+    }
+
+    Ok(contact_lists(&events))
+}
+
+/// Wait for `sleep` to finish or a rebuild to be requested; returns whether
+/// a rebuild was requested.
+async fn wait_or_rebuild(sleep: tokio::time::Sleep, rebuild: &Notify) -> bool {
+    tokio::select! {
+        _ = sleep => false,
+        _ = rebuild.notified() => true,
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
 
-        if !any_success {
-            return Err(anyhow::anyhow!("All relays failed at depth {}", depth));
+/// Pubkeys to subtract from a finished WoT: the configured `exclude_pubkeys`
+/// plus those on the public part of the configured mute lists. A mute list
+/// that can't be fetched is skipped rather than failing the build.
+async fn excluded_pubkeys(
+    config: &WotConfig,
+    seed: PublicKey,
+    direct_follows: &HashSet<PublicKey>,
+    discovery_relays: &[String],
+    health: &RelayHealth,
+) -> HashSet<PublicKey> {
+    let mut excluded: HashSet<PublicKey> = config
+        .exclude_pubkeys
+        .iter()
+        .filter_map(|pk| PublicKey::parse(pk).ok())
+        .collect();
+
+    let muters: Vec<PublicKey> = match config.mutes {
+        WotMutes::None => Vec::new(),
+        WotMutes::Seed => vec![seed],
+        WotMutes::SeedAndFollows => std::iter::once(seed).chain(direct_follows.iter().copied()).collect(),
+    };
+    if !muters.is_empty() {
+        let batches: Vec<Vec<String>> = muters
+            .chunks(300)
+            .map(|chunk| chunk.iter().map(|pk| pk.to_hex()).collect())
+            .collect();
+        let result = query_discovery_relays(discovery_relays, health, &batches, &[10000], None).await;
+        if result.all_failed {
+            tracing::warn!("WoT: could not fetch mute lists");
         }
+        excluded.extend(tagged_pubkeys(&result.events, 10000));
+    }
+    excluded
+}
+
+// ---------------------------------------------------------------------------
+// Discovery relay health
+// ---------------------------------------------------------------------------
+
+/// Consecutive failures after which a relay is quarantined.
+const QUARANTINE_AFTER: u32 = 2;
+/// First quarantine; doubled for each further failure, up to the maximum.
+const QUARANTINE_BASE: Duration = Duration::from_secs(60);
+const QUARANTINE_MAX: Duration = Duration::from_secs(3600);
+/// Weight of the newest sample in the latency average.
+const LATENCY_WEIGHT: f64 = 0.3;
+
+#[derive(Default)]
+struct HealthEntry {
+    successes: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    /// Moving average of per-batch latency, in milliseconds.
+    latency_ms: Option<f64>,
+    quarantined_until: Option<SystemTime>,
+    last_error: Option<String>,
+}
 
-        for pk in &to_query {
-            queried.insert(*pk);
+impl HealthEntry {
+    fn success_rate(&self) -> f64 {
+        match self.successes + self.failures {
+            // Untried relays get a chance ahead of ones known to be flaky
+            0 => 1.0,
+            total => self.successes as f64 / total as f64,
         }
+    }
 
-        tracing::info!(
-            "WoT depth {}/{}: found {} new pubkeys, total {}",
-            depth,
-            max_depth,
-            next_layer.len(),
-            all_pubkeys.len()
-        );
+    fn quarantined(&self, now: SystemTime) -> bool {
+        self.quarantined_until.is_some_and(|until| until > now)
+    }
+}
 
-        current_layer = next_layer;
+/// Success rate and latency of each discovery relay. Builds give their
+/// batches to the healthiest relays and skip ones that keep failing until
+/// their quarantine runs out.
+#[derive(Default)]
+pub struct RelayHealth {
+    entries: std::sync::Mutex<HashMap<String, HealthEntry>>,
+}
 
-        *status.write().await = WotStatus::Building {
-            depth_progress: depth,
-            total_depth: max_depth,
-        };
+#[derive(Debug, serde::Serialize)]
+pub struct RelayHealthReport {
+    pub url: String,
+    pub successes: u64,
+    pub failures: u64,
+    pub success_rate: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_latency_ms: Option<u64>,
+    /// Unix time the relay is skipped until.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantined_until: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl RelayHealth {
+    fn record_success(&self, url: &str, latency: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(url.to_string()).or_default();
+        entry.successes += 1;
+        entry.consecutive_failures = 0;
+        entry.quarantined_until = None;
+        let sample = latency.as_secs_f64() * 1000.0;
+        entry.latency_ms = Some(match entry.latency_ms {
+            Some(avg) => avg + LATENCY_WEIGHT * (sample - avg),
+            None => sample,
+        });
     }
 
-    set.replace(all_pubkeys.clone());
-    *status.write().await = WotStatus::Ready;
+    fn record_failure(&self, url: &str, error: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(url.to_string()).or_default();
+        entry.failures += 1;
+        entry.consecutive_failures += 1;
+        entry.last_error = Some(error.to_string());
+        if entry.consecutive_failures >= QUARANTINE_AFTER {
+            let doublings = (entry.consecutive_failures - QUARANTINE_AFTER).min(6);
+            let quarantine = (QUARANTINE_BASE * 2u32.pow(doublings)).min(QUARANTINE_MAX);
+            entry.quarantined_until = Some(SystemTime::now() + quarantine);
+            tracing::info!("Quarantining discovery relay {} for {}s: {}", url, quarantine.as_secs(), error);
+        }
+    }
 
-    tracing::info!("WoT build complete: {} pubkeys", all_pubkeys.len());
-    Ok(())
+    /// `relays` that aren't quarantined, healthiest first: by success rate,
+    /// then latency. If every relay is quarantined, all of them, soonest
+    /// released first, so builds slow down rather than stop.
+    fn ranked(&self, relays: &[String]) -> Vec<String> {
+        let entries = self.entries.lock().unwrap();
+        let now = SystemTime::now();
+        let default = HealthEntry::default();
+        let entry = |url: &String| entries.get(url).unwrap_or(&default);
+
+        let mut available: Vec<&String> = relays.iter().filter(|url| !entry(url).quarantined(now)).collect();
+        if available.is_empty() {
+            let mut all: Vec<&String> = relays.iter().collect();
+            all.sort_by_key(|url| entry(url).quarantined_until);
+            return all.into_iter().cloned().collect();
+        }
+        available.sort_by(|a, b| {
+            let (a, b) = (entry(a), entry(b));
+            b.success_rate()
+                .total_cmp(&a.success_rate())
+                .then(a.latency_ms.unwrap_or(0.0).total_cmp(&b.latency_ms.unwrap_or(0.0)))
+        });
+        available.into_iter().cloned().collect()
+    }
+
+    fn report(&self, relays: &[String]) -> Vec<RelayHealthReport> {
+        let entries = self.entries.lock().unwrap();
+        let now = SystemTime::now();
+        relays
+            .iter()
+            .map(|url| {
+                let default = HealthEntry::default();
+                let entry = entries.get(url).unwrap_or(&default);
+                RelayHealthReport {
+                    url: url.clone(),
+                    successes: entry.successes,
+                    failures: entry.failures,
+                    success_rate: entry.success_rate(),
+                    avg_latency_ms: entry.latency_ms.map(|ms| ms.round() as u64),
+                    quarantined_until: entry
+                        .quarantined_until
+                        .filter(|_| entry.quarantined(now))
+                        .and_then(|until| until.duration_since(SystemTime::UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs()),
+                    last_error: entry.last_error.clone(),
+                }
+            })
+            .collect()
+    }
 }
 
 // ---------------------------------------------------------------------------
 // Relay querying
 // ---------------------------------------------------------------------------
 
+/// Outbox relays consulted per pubkey, so a popular relay can't be the only
+/// source for everyone who lists it.
+const OUTBOX_RELAYS_PER_PUBKEY: usize = 2;
+
+/// What a round of discovery relay queries returned.
+struct DiscoveryResult {
+    events: Vec<serde_json::Value>,
+    /// The error of each relay that failed.
+    errors: Vec<(String, String)>,
+    /// There were batches to run and no relay answered any of them.
+    all_failed: bool,
+}
+
+/// Run every batch against the discovery relays, healthiest first, spread
+/// round-robin and queried concurrently. Quarantined relays sit out, and
+/// the batches of a relay that fails are retried once on the healthiest
+/// relay that answered.
+async fn query_discovery_relays(
+    discovery_relays: &[String],
+    health: &RelayHealth,
+    batches: &[Vec<String>],
+    kinds: &[u16],
+    since: Option<u64>,
+) -> DiscoveryResult {
+    let relays = health.ranked(discovery_relays);
+    let used = relays.len().min(batches.len());
+    let mut relay_batches: Vec<Vec<Vec<String>>> = vec![Vec::new(); used];
+    for (i, batch) in batches.iter().enumerate() {
+        relay_batches[i % used].push(batch.clone());
+    }
+
+    let queries = relays
+        .iter()
+        .zip(relay_batches)
+        .map(|(relay_url, batches)| async move {
+            let result = timed_query(health, relay_url, batches.clone(), kinds, since).await;
+            (relay_url, batches, result)
+        });
+    let results = futures_util::future::join_all(queries).await;
+
+    let mut events = Vec::new();
+    let mut errors = Vec::new();
+    let mut answered = Vec::new();
+    let mut retry = Vec::new();
+    for (relay_url, batches, result) in results {
+        match result {
+            Ok(received) => {
+                events.extend(received);
+                answered.push(relay_url);
+            }
+            Err(e) => {
+                tracing::warn!("Relay query failed: {}", e);
+                errors.push((relay_url.clone(), e.to_string()));
+                retry.extend(batches);
+            }
+        }
+    }
+
+    // `relays` is ranked, so the first to answer is the healthiest
+    if let (Some(relay_url), false) = (answered.first(), retry.is_empty()) {
+        match timed_query(health, relay_url, retry, kinds, since).await {
+            Ok(received) => events.extend(received),
+            Err(e) => {
+                tracing::warn!("Relay query retry failed: {}", e);
+                errors.push(((*relay_url).clone(), e.to_string()));
+            }
+        }
+    }
+
+    DiscoveryResult {
+        events,
+        errors,
+        all_failed: used > 0 && answered.is_empty(),
+    }
+}
+
+/// `query_relay_batches`, recording the outcome in `health`.
+async fn timed_query(
+    health: &RelayHealth,
+    relay_url: &str,
+    batches: Vec<Vec<String>>,
+    kinds: &[u16],
+    since: Option<u64>,
+) -> Result<Vec<serde_json::Value>, anyhow::Error> {
+    let count = batches.len().max(1) as u32;
+    let started = Instant::now();
+    let result = query_relay_batches(relay_url, batches, kinds, since).await;
+    match &result {
+        // Per batch, so relays given more work don't look slower
+        Ok(_) => health.record_success(relay_url, started.elapsed() / count),
+        Err(e) => health.record_failure(relay_url, &e.to_string()),
+    }
+    result
+}
+
 async fn query_relay_batches(
     relay_url: &str,
     batches: Vec<Vec<String>>,
-) -> Result<HashSet<PublicKey>, anyhow::Error> {
+    kinds: &[u16],
+    since: Option<u64>,
+) -> Result<Vec<serde_json::Value>, anyhow::Error> {
     let (mut ws, _): (WsStream, _) = tokio::time::timeout(
         std::time::Duration::from_secs(10),
         tokio_tungstenite::connect_async(relay_url),
@@ -464,16 +998,20 @@ async fn query_relay_batches(
     .map_err(|_| anyhow::anyhow!("Connection timeout to {}", relay_url))?
     .map_err(|e| anyhow::anyhow!("WS connect to {} failed: {}", relay_url, e))?;
 
-    let mut all_followed = HashSet::new();
+    let mut all_events = Vec::new();
 
     for (i, batch) in batches.iter().enumerate() {
         let sub_id = format!("wot-{}", i);
-        let req = serde_json::json!(["REQ", sub_id, {"authors": batch, "kinds": [3]}]);
+        let mut filter = serde_json::json!({"authors": batch, "kinds": kinds});
+        if let Some(since) = since {
+            filter["since"] = since.into();
+        }
+        let req = serde_json::json!(["REQ", sub_id, filter]);
 
         ws.send(Message::Text(req.to_string().into())).await?;
 
-        let followed = read_until_eose(&mut ws, &sub_id).await?;
-        all_followed.extend(followed);
+        let events = read_until_eose(&mut ws, &sub_id).await?;
+        all_events.extend(events);
 
         // Send CLOSE
         let close = serde_json::json!(["CLOSE", sub_id]);
@@ -488,7 +1026,7 @@ async fn query_relay_batches(
     // Close WS
     let _ = ws.close(None).await;
 
-    Ok(all_followed)
+    Ok(all_events)
 }
 
 type WsStream = tokio_tungstenite::WebSocketStream<
@@ -498,8 +1036,8 @@ type WsStream = tokio_tungstenite::WebSocketStream<
 async fn read_until_eose(
     ws: &mut WsStream,
     sub_id: &str,
-) -> Result<HashSet<PublicKey>, anyhow::Error> {
-    let mut followed = HashSet::new();
+) -> Result<Vec<serde_json::Value>, anyhow::Error> {
+    let mut events = Vec::new();
     let timeout = tokio::time::timeout(std::time::Duration::from_secs(30), async {
         while let Some(msg) = ws.next().await {
             let msg = msg?;
@@ -513,10 +1051,9 @@ async fn read_until_eose(
                 Ok(v) => v,
                 Err(_) => continue,
             };
-
-            let arr = match parsed.as_array() {
-                Some(a) => a,
-                None => continue,
+            let mut arr = match parsed {
+                serde_json::Value::Array(a) => a,
+                _ => continue,
             };
 
             if arr.is_empty() {
@@ -524,38 +1061,14 @@ async fn read_until_eose(
             }
 
             let msg_type = arr[0].as_str().unwrap_or("");
+            let sid = arr.get(1).and_then(|v| v.as_str());
 
-            if msg_type == "EOSE" {
-                if let Some(sid) = arr.get(1).and_then(|v| v.as_str()) {
-                    if sid == sub_id {
-                        break;
-                    }
-                }
+            if msg_type == "EOSE" && sid == Some(sub_id) {
+                break;
             }
 
-            if msg_type == "EVENT" && arr.len() >= 3 {
-                if let Some(sid) = arr.get(1).and_then(|v| v.as_str()) {
-                    if sid != sub_id {
-                        continue;
-                    }
-                }
-                if let Some(event_obj) = arr.get(2) {
-                    if let Some(tags) = event_obj.get("tags").and_then(|t| t.as_array()) {
-                        for tag in tags {
-                            if let Some(tag_arr) = tag.as_array() {
-                                if tag_arr.len() >= 2
-                                    && tag_arr[0].as_str() == Some("p")
-                                {
-                                    if let Some(hex) = tag_arr[1].as_str() {
-                                        if let Ok(pk) = PublicKey::parse(hex) {
-                                            followed.insert(pk);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+            if msg_type == "EVENT" && arr.len() >= 3 && sid == Some(sub_id) {
+                events.push(arr.swap_remove(2));
             }
         }
         Ok::<_, anyhow::Error>(())
@@ -571,7 +1084,101 @@ async fn read_until_eose(
         }
     }
 
-    Ok(followed)
+    Ok(events)
+}
+
+fn event_tags(event: &serde_json::Value) -> impl Iterator<Item = Vec<&str>> {
+    event
+        .get("tags")
+        .and_then(|t| t.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|tag| tag.as_array())
+        .map(|tag| tag.iter().filter_map(|v| v.as_str()).collect())
+}
+
+/// The newest kind-3 contact list of each author among `events`, as its
+/// `created_at` and the pubkeys it `p`-tags.
+fn contact_lists(events: &[serde_json::Value]) -> HashMap<PublicKey, (u64, HashSet<PublicKey>)> {
+    newest_by_author(events, 3)
+        .into_iter()
+        .map(|(author, (created_at, event))| {
+            (author, (created_at, tagged_pubkeys(std::slice::from_ref(event), 3)))
+        })
+        .collect()
+}
+
+/// The newest event of `kind` from each author among `events`.
+fn newest_by_author(events: &[serde_json::Value], kind: u64) -> HashMap<PublicKey, (u64, &serde_json::Value)> {
+    let mut newest: HashMap<PublicKey, (u64, &serde_json::Value)> = HashMap::new();
+    for event in events.iter().filter(|e| e.get("kind").and_then(|k| k.as_u64()) == Some(kind)) {
+        let author = event.get("pubkey").and_then(|p| p.as_str()).and_then(|p| PublicKey::parse(p).ok());
+        let created_at = event.get("created_at").and_then(|c| c.as_u64()).unwrap_or(0);
+        if let Some(author) = author {
+            if newest.get(&author).is_none_or(|(t, _)| created_at > *t) {
+                newest.insert(author, (created_at, event));
+            }
+        }
+    }
+    newest
+}
+
+/// Pubkeys `p`-tagged by the events of `kind` among `events`.
+fn tagged_pubkeys(events: &[serde_json::Value], kind: u64) -> HashSet<PublicKey> {
+    let mut followed = HashSet::new();
+    for event in events.iter().filter(|e| e.get("kind").and_then(|k| k.as_u64()) == Some(kind)) {
+        for tag in event_tags(event) {
+            if tag.len() >= 2 && tag[0] == "p" {
+                if let Ok(pk) = PublicKey::parse(tag[1]) {
+                    followed.insert(pk);
+                }
+            }
+        }
+    }
+    followed
+}
+
+/// Write relays (unmarked or `write` `r` tags) from the newest kind-10002
+/// relay list of each author among `events`.
+fn parse_write_relays(events: &[serde_json::Value]) -> HashMap<PublicKey, Vec<String>> {
+    newest_by_author(events, 10002)
+        .into_iter()
+        .map(|(author, (_, event))| {
+            let relays = event_tags(event)
+                .filter(|tag| tag.len() >= 2 && tag[0] == "r" && tag.get(2).is_none_or(|m| *m == "write"))
+                .map(|tag| tag[1].trim_end_matches('/').to_string())
+                .filter(|url| url.starts_with("wss://") || url.starts_with("ws://"))
+                .collect();
+            (author, relays)
+        })
+        .collect()
+}
+
+/// Which outbox relays to ask for whose contact lists: each pubkey goes to
+/// at most `OUTBOX_RELAYS_PER_PUBKEY` of its write relays (the discovery
+/// relays already cover it), and only the `max_relays` relays serving the
+/// most pubkeys are kept.
+fn plan_outbox_queries(
+    write_relays: &HashMap<PublicKey, Vec<String>>,
+    discovery_relays: &[String],
+    max_relays: usize,
+) -> Vec<(String, Vec<String>)> {
+    let discovery: HashSet<&str> = discovery_relays.iter().map(|r| r.trim_end_matches('/')).collect();
+    let mut by_relay: HashMap<String, Vec<String>> = HashMap::new();
+    for (pubkey, relays) in write_relays {
+        for relay in relays
+            .iter()
+            .filter(|r| !discovery.contains(r.as_str()))
+            .take(OUTBOX_RELAYS_PER_PUBKEY)
+        {
+            by_relay.entry(relay.clone()).or_default().push(pubkey.to_hex());
+        }
+    }
+
+    let mut plan: Vec<(String, Vec<String>)> = by_relay.into_iter().collect();
+    plan.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+    plan.truncate(max_relays);
+    plan
 }
 
 // ---------------------------------------------------------------------------
@@ -580,30 +1187,159 @@ async fn read_until_eose(
 
 async fn save_pubkeys_to_disk(
     path: &Path,
-    pubkeys: &HashSet<PublicKey>,
+    pubkeys: &HashMap<PublicKey, u8>,
 ) -> Result<(), anyhow::Error> {
     if let Some(parent) = path.parent() {
         tokio::fs::create_dir_all(parent).await?;
     }
-    let mut buf = Vec::with_capacity(pubkeys.len() * 32);
-    for pk in pubkeys {
+    tokio::fs::write(path, encode_pubkeys(pubkeys)).await?;
+    Ok(())
+}
+
+async fn load_pubkeys_from_disk(path: &Path) -> Result<HashMap<PublicKey, u8>, anyhow::Error> {
+    decode_pubkeys(&tokio::fs::read(path).await?)
+}
+
+/// `WOT2` followed by 33-byte records: pubkey, then depth.
+fn encode_pubkeys(pubkeys: &HashMap<PublicKey, u8>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(DISK_MAGIC.len() + pubkeys.len() * 33);
+    buf.extend_from_slice(DISK_MAGIC);
+    for (pk, depth) in pubkeys {
         buf.extend_from_slice(pk.to_bytes().as_slice());
+        buf.push(*depth);
     }
-    tokio::fs::write(path, buf).await?;
-    Ok(())
+    buf
 }
 
-async fn load_pubkeys_from_disk(path: &Path) -> Result<HashSet<PublicKey>, anyhow::Error> {
-    let data = tokio::fs::read(path).await?;
-    if data.len() % 32 != 0 {
+/// Decode either format; files from before depths were kept are bare 32-byte
+/// pubkeys.
+fn decode_pubkeys(data: &[u8]) -> Result<HashMap<PublicKey, u8>, anyhow::Error> {
+    let (records, record_len) = match data.strip_prefix(DISK_MAGIC.as_slice()) {
+        Some(records) => (records, 33),
+        None => (data, 32),
+    };
+    if records.len() % record_len != 0 {
         return Err(anyhow::anyhow!("Invalid WoT file size"));
     }
-    let mut set = HashSet::new();
-    for chunk in data.chunks_exact(32) {
-        let bytes: [u8; 32] = chunk.try_into().unwrap();
-        if let Ok(pk) = PublicKey::from_slice(&bytes) {
-            set.insert(pk);
+    let mut set = HashMap::new();
+    for chunk in records.chunks_exact(record_len) {
+        let depth = chunk.get(32).copied().unwrap_or(UNKNOWN_DEPTH);
+        if let Ok(pk) = PublicKey::from_slice(&chunk[..32]) {
+            set.insert(pk, depth);
         }
     }
     Ok(set)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::Keys;
+
+    #[test]
+    fn failing_relays_are_ranked_last_then_quarantined() {
+        let health = RelayHealth::default();
+        let relays: Vec<String> = ["wss://a", "wss://b", "wss://c"].map(String::from).to_vec();
+        health.record_success("wss://a", Duration::from_millis(300));
+        health.record_success("wss://b", Duration::from_millis(100));
+        health.record_failure("wss://c", "timeout");
+        assert_eq!(health.ranked(&relays), ["wss://b", "wss://a", "wss://c"]);
+
+        health.record_failure("wss://c", "timeout");
+        assert_eq!(health.ranked(&relays), ["wss://b", "wss://a"]);
+        let report = health.report(&relays);
+        assert!(report[2].quarantined_until.is_some());
+        assert_eq!(report[2].last_error.as_deref(), Some("timeout"));
+        assert_eq!(report[1].avg_latency_ms, Some(100));
+
+        // With nothing healthy left, quarantined relays are still tried
+        let only_c = vec!["wss://c".to_string()];
+        assert_eq!(health.ranked(&only_c), only_c);
+        health.record_success("wss://c", Duration::from_millis(50));
+        assert!(health.report(&only_c)[0].quarantined_until.is_none());
+    }
+
+    #[test]
+    fn plans_outbox_queries_from_newest_relay_lists() {
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+        let events = vec![
+            serde_json::json!({"kind": 10002, "pubkey": alice.to_hex(), "created_at": 1,
+                "tags": [["r", "wss://old.example.com"]]}),
+            serde_json::json!({"kind": 10002, "pubkey": alice.to_hex(), "created_at": 2,
+                "tags": [["r", "wss://shared.example.com/"], ["r", "wss://inbox.example.com", "read"],
+                         ["r", "wss://alice.example.com", "write"], ["r", "wss://third.example.com"]]}),
+            serde_json::json!({"kind": 10002, "pubkey": bob.to_hex(), "created_at": 1,
+                "tags": [["r", "wss://discovery.example.com"], ["r", "wss://shared.example.com"]]}),
+            serde_json::json!({"kind": 3, "pubkey": bob.to_hex(), "created_at": 1,
+                "tags": [["p", alice.to_hex()]]}),
+            serde_json::json!({"kind": 10000, "pubkey": alice.to_hex(), "created_at": 1,
+                "tags": [["p", bob.to_hex()], ["t", "spam"]]}),
+        ];
+
+        let relays = parse_write_relays(&events);
+        assert_eq!(
+            relays[&alice],
+            vec!["wss://shared.example.com", "wss://alice.example.com", "wss://third.example.com"]
+        );
+        assert_eq!(contact_lists(&events)[&bob], (1, HashSet::from([alice])));
+        assert_eq!(tagged_pubkeys(&events, 10000), HashSet::from([bob]));
+
+        let discovery = vec!["wss://discovery.example.com/".to_string()];
+        let plan = plan_outbox_queries(&relays, &discovery, 10);
+        let relay_names: Vec<&str> = plan.iter().map(|(r, _)| r.as_str()).collect();
+        assert_eq!(relay_names, vec!["wss://shared.example.com", "wss://alice.example.com"]);
+        assert_eq!(plan[0].1.len(), 2);
+
+        assert_eq!(plan_outbox_queries(&relays, &discovery, 1).len(), 1);
+    }
+
+    #[test]
+    fn graph_depths_follow_current_contact_lists() {
+        let [seed, a, b, c] = [(); 4].map(|_| Keys::generate().public_key());
+        let mut graph = WotGraph::new();
+        graph.apply(HashMap::from([
+            (seed, (1, HashSet::from([a]))),
+            (a, (1, HashSet::from([b]))),
+            (b, (1, HashSet::from([c]))),
+        ]));
+        assert_eq!(graph.depths(seed, 2), HashMap::from([(seed, 0), (a, 1), (b, 2)]));
+
+        // An older list is ignored; a newer unfollow drops a's follows
+        assert_eq!(graph.apply(HashMap::from([(seed, (0, HashSet::new()))])), 0);
+        graph.apply(HashMap::from([(seed, (2, HashSet::from([b])))]));
+        assert_eq!(graph.depths(seed, 2), HashMap::from([(seed, 0), (b, 1), (c, 2)]));
+    }
+
+    #[tokio::test]
+    async fn rebuild_request_cuts_the_wait_short() {
+        let rebuild = Notify::new();
+        let short = tokio::time::sleep(std::time::Duration::from_millis(1));
+        assert!(!wait_or_rebuild(short, &rebuild).await);
+
+        // A request made while a build is running is kept for the next wait
+        rebuild.notify_one();
+        let long = tokio::time::sleep(std::time::Duration::from_secs(3600));
+        assert!(wait_or_rebuild(long, &rebuild).await);
+    }
+
+    #[test]
+    fn disk_format_keeps_depths_and_reads_legacy_files() {
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+        let set = HashMap::from([(alice, 0), (bob, 2)]);
+        assert_eq!(decode_pubkeys(&encode_pubkeys(&set)).unwrap(), set);
+
+        let legacy = alice.to_bytes().to_vec();
+        let loaded = decode_pubkeys(&legacy).unwrap();
+        assert_eq!(loaded, HashMap::from([(alice, UNKNOWN_DEPTH)]));
+
+        let wot = WotSet::new();
+        wot.replace(loaded);
+        assert!(wot.contains(&alice));
+        assert_eq!(wot.depth(&alice), None);
+        wot.replace(set);
+        assert_eq!(wot.depth(&bob), Some(2));
+        assert!(decode_pubkeys(&legacy[..31]).is_err());
+    }
+}
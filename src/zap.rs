@@ -0,0 +1,365 @@
+//! Paywall access paid with zaps (NIP-57). Users zap the paywall's lightning
+//! address from the account they want let in. The zap provider then
+//! publishes a receipt (kind 9735) that embeds the zap request the user
+//! signed, so the receipt shows who paid and how much.
+
+use crate::config::PaywallZapConfig;
+use futures_util::{SinkExt, StreamExt};
+use nostr::{Event, JsonUtil, Kind, PublicKey};
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// A paywall's zap settings, parsed.
+#[derive(Debug, Clone)]
+pub struct ZapTerms {
+    pub lightning_address: String,
+    /// Who zaps must be addressed to.
+    pub recipient: PublicKey,
+    /// Who signs the receipts, when configured rather than looked up.
+    pub receipt_signer: Option<PublicKey>,
+    pub relays: Vec<String>,
+}
+
+impl ZapTerms {
+    pub fn parse(config: &PaywallZapConfig) -> Result<Self, String> {
+        if split_address(&config.lightning_address).is_none() {
+            return Err(format!(
+                "invalid zap lightning address '{}'",
+                config.lightning_address
+            ));
+        }
+        let recipient = PublicKey::parse(&config.pubkey)
+            .map_err(|e| format!("invalid zap pubkey: {}", e))?;
+        let receipt_signer = config
+            .receipt_pubkey
+            .as_deref()
+            .map(PublicKey::parse)
+            .transpose()
+            .map_err(|e| format!("invalid zap receipt_pubkey: {}", e))?;
+        for relay in &config.relays {
+            if !relay.starts_with("ws://") && !relay.starts_with("wss://") {
+                return Err(format!("invalid zap relay URL '{}'", relay));
+            }
+        }
+        Ok(Self {
+            lightning_address: config.lightning_address.clone(),
+            recipient,
+            receipt_signer,
+            relays: config.relays.clone(),
+        })
+    }
+}
+
+/// A verified zap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zap {
+    /// Who signed the zap request. This is the account that gets access.
+    pub payer: PublicKey,
+    pub amount_msats: u64,
+    /// The invoice's payment hash, which identifies the payment however
+    /// many receipts are issued for it.
+    pub payment_hash: [u8; 32],
+    /// When the receipt was issued.
+    pub paid_at: u64,
+}
+
+/// Check `receipt` the way NIP-57 (appendix F) asks. It must be signed by
+/// `signer`, the zap provider behind the lightning address. The zap request
+/// it embeds must be signed and addressed to `recipient`. The invoice must
+/// commit to that request and be for the amount it asked for.
+pub fn verify_receipt(
+    receipt: &Event,
+    recipient: &PublicKey,
+    signer: &PublicKey,
+) -> Result<Zap, String> {
+    if receipt.kind != Kind::ZapReceipt {
+        return Err("not a zap receipt".to_string());
+    }
+    if receipt.pubkey != *signer {
+        return Err("receipt not signed by the zap provider".to_string());
+    }
+    receipt
+        .verify()
+        .map_err(|_| "invalid receipt signature".to_string())?;
+    if tag_value(receipt, "p").and_then(|p| PublicKey::from_hex(p).ok()) != Some(*recipient) {
+        return Err("receipt is for someone else".to_string());
+    }
+
+    let invoice = tag_value(receipt, "bolt11")
+        .and_then(crate::bolt11::parse)
+        .ok_or("receipt has no readable invoice")?;
+    let amount_msats = invoice.amount_msats.ok_or("invoice has no amount")?;
+    let payment_hash = invoice.payment_hash.ok_or("invoice has no payment hash")?;
+    let description = tag_value(receipt, "description").ok_or("receipt has no zap request")?;
+    let hash: [u8; 32] = Sha256::digest(description.as_bytes()).into();
+    if invoice.description_hash != Some(hash) {
+        return Err("invoice is not for the embedded zap request".to_string());
+    }
+
+    let request = Event::from_json(description).map_err(|_| "unreadable zap request")?;
+    if request.kind != Kind::ZapRequest {
+        return Err("embedded event is not a zap request".to_string());
+    }
+    request
+        .verify()
+        .map_err(|_| "invalid zap request signature".to_string())?;
+    if tag_value(&request, "p").and_then(|p| PublicKey::from_hex(p).ok()) != Some(*recipient) {
+        return Err("zap request is for someone else".to_string());
+    }
+    if let Some(amount) = tag_value(&request, "amount") {
+        if amount.parse::<u64>().ok() != Some(amount_msats) {
+            return Err("invoice amount differs from the zap request".to_string());
+        }
+    }
+
+    Ok(Zap {
+        payer: request.pubkey,
+        amount_msats,
+        payment_hash,
+        paid_at: receipt.created_at.as_u64(),
+    })
+}
+
+fn tag_value<'a>(event: &'a Event, name: &str) -> Option<&'a str> {
+    event.tags.iter().find_map(|tag| match tag.as_vec() {
+        [n, value, ..] if n == name => Some(value.as_str()),
+        _ => None,
+    })
+}
+
+fn split_address(address: &str) -> Option<(&str, &str)> {
+    address
+        .split_once('@')
+        .filter(|(name, domain)| !name.is_empty() && domain.contains('.'))
+}
+
+/// Ask the lightning address's LNURL server who signs its zap receipts.
+pub async fn lookup_receipt_signer(address: &str) -> Result<PublicKey, anyhow::Error> {
+    let (name, domain) =
+        split_address(address).ok_or_else(|| anyhow::anyhow!("invalid lightning address"))?;
+    let url = format!("https://{}/.well-known/lnurlp/{}", domain, name);
+    let body = reqwest::Client::new()
+        .get(&url)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let info: serde_json::Value = serde_json::from_slice(&body)?;
+    if info["allowsNostr"] != true {
+        anyhow::bail!("{} does not support zaps", address);
+    }
+    let pubkey = info["nostrPubkey"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("{} has no nostrPubkey", address))?;
+    Ok(PublicKey::from_hex(pubkey)?)
+}
+
+/// Follow zap receipts for `recipient` on each relay, passing them to
+/// `receipts` as they arrive. Each relay reconnects with exponential backoff
+/// and asks again for anything issued since it last connected. Returns once
+/// the receiver is dropped.
+pub async fn watch_relays(
+    relays: Vec<String>,
+    recipient: PublicKey,
+    receipts: mpsc::UnboundedSender<Event>,
+) {
+    let since = now();
+    let watchers = relays
+        .into_iter()
+        .map(|relay| watch_relay(relay, recipient, since, receipts.clone()));
+    futures_util::future::join_all(watchers).await;
+}
+
+async fn watch_relay(
+    relay: String,
+    recipient: PublicKey,
+    mut since: u64,
+    receipts: mpsc::UnboundedSender<Event>,
+) {
+    let mut backoff = MIN_BACKOFF;
+    while !receipts.is_closed() {
+        let connected_at = now();
+        if let Err(e) = follow(&relay, recipient, since, &receipts).await {
+            if now() - connected_at > MAX_BACKOFF.as_secs() {
+                backoff = MIN_BACKOFF;
+            }
+            tracing::warn!(
+                relay = %relay,
+                retry_in_secs = backoff.as_secs(),
+                "Zap receipt subscription failed: {}",
+                e
+            );
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            since = connected_at;
+        }
+    }
+}
+
+/// One connection's worth of [`watch_relay`]. Returns `Ok` once the receiver
+/// is dropped.
+async fn follow(
+    relay: &str,
+    recipient: PublicKey,
+    since: u64,
+    receipts: &mpsc::UnboundedSender<Event>,
+) -> Result<(), anyhow::Error> {
+    let (mut ws, _) = tokio::time::timeout(
+        Duration::from_secs(10),
+        tokio_tungstenite::connect_async(relay),
+    )
+    .await
+    .map_err(|_| anyhow::anyhow!("Connection timeout"))??;
+
+    let req = serde_json::json!(["REQ", "moar-zaps", {
+        "kinds": [9735],
+        "#p": [recipient.to_hex()],
+        "since": since,
+    }]);
+    ws.send(Message::Text(req.to_string().into())).await?;
+
+    loop {
+        let msg = tokio::select! {
+            msg = ws.next() => msg,
+            _ = receipts.closed() => return Ok(()),
+        };
+        let text = match msg {
+            Some(Ok(Message::Text(t))) => t.to_string(),
+            Some(Ok(Message::Close(_))) | None => anyhow::bail!("Connection closed"),
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(e.into()),
+        };
+        let Ok(serde_json::Value::Array(mut msg)) = serde_json::from_str(&text) else {
+            continue;
+        };
+        if msg.len() < 3 || msg[0] != "EVENT" {
+            continue;
+        }
+        if let Ok(event) = Event::from_value(msg.swap_remove(2)) {
+            if receipts.send(event).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use nostr::{Event, EventBuilder, JsonUtil, Keys, Kind, PublicKey, Tag};
+    use sha2::{Digest, Sha256};
+
+    const CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+    /// An invoice for `amount` (BOLT 11 units) committing to `description`.
+    /// The signature and checksum are left blank.
+    pub fn invoice(amount: &str, description: &str) -> String {
+        let hash = Sha256::digest(description.as_bytes());
+        let mut words: Vec<u8> = (0..7).rev().map(|i| (1_700_000_000u64 >> (5 * i)) as u8 & 31).collect();
+        // A payment hash (p) unique to the description, then the description hash (h)
+        for (field, value) in [(1, Sha256::digest(hash)), (23, hash)] {
+            words.extend([field, 1, 20]);
+            let (mut acc, mut bits) = (0u32, 0);
+            for byte in value {
+                acc = acc << 8 | byte as u32;
+                bits += 8;
+                while bits >= 5 {
+                    bits -= 5;
+                    words.push((acc >> bits) as u8 & 31);
+                }
+            }
+            words.push((acc << (5 - bits)) as u8 & 31);
+        }
+        let data: String = words.iter().map(|&w| CHARSET[w as usize] as char).collect();
+        format!("lnbc{}1{}{}", amount, data, "q".repeat(104 + 6))
+    }
+
+    pub fn zap_request(payer: &Keys, recipient: &PublicKey, amount_msats: Option<u64>) -> String {
+        let mut tags = vec![
+            Tag::public_key(*recipient),
+            Tag::parse(&["relays", "wss://relay.example.com"]).unwrap(),
+        ];
+        if let Some(amount) = amount_msats {
+            tags.push(Tag::parse(&["amount", &amount.to_string()]).unwrap());
+        }
+        EventBuilder::new(Kind::ZapRequest, "", tags)
+            .to_event(payer)
+            .unwrap()
+            .as_json()
+    }
+
+    pub fn receipt(provider: &Keys, recipient: &PublicKey, bolt11: &str, request: &str) -> Event {
+        EventBuilder::new(
+            Kind::ZapReceipt,
+            "",
+            [
+                Tag::public_key(*recipient),
+                Tag::parse(&["bolt11", bolt11]).unwrap(),
+                Tag::parse(&["description", request]).unwrap(),
+            ],
+        )
+        .to_event(provider)
+        .unwrap()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::{invoice, receipt, zap_request};
+    use super::*;
+    use nostr::Keys;
+
+    #[test]
+    fn verifies_receipts_against_their_zap_request() {
+        let (payer, provider) = (Keys::generate(), Keys::generate());
+        let recipient = Keys::generate().public_key();
+        let request = zap_request(&payer, &recipient, Some(21_000));
+
+        let valid = receipt(&provider, &recipient, &invoice("210n", &request), &request);
+        let zap = verify_receipt(&valid, &recipient, &provider.public_key()).unwrap();
+        assert_eq!(zap.payer, payer.public_key());
+        assert_eq!(zap.amount_msats, 21_000);
+        assert_eq!(zap.paid_at, valid.created_at.as_u64());
+
+        // Only the zap provider's receipts count
+        let forged = receipt(&Keys::generate(), &recipient, &invoice("210n", &request), &request);
+        assert!(verify_receipt(&forged, &recipient, &provider.public_key()).is_err());
+        assert!(verify_receipt(&valid, &Keys::generate().public_key(), &provider.public_key()).is_err());
+
+        // The invoice must commit to the zap request and be for its amount
+        let other = zap_request(&payer, &recipient, Some(21_000));
+        let swapped = receipt(&provider, &recipient, &invoice("210n", &other), &request);
+        assert!(verify_receipt(&swapped, &recipient, &provider.public_key()).is_err());
+        let underpaid = receipt(&provider, &recipient, &invoice("10n", &request), &request);
+        assert!(verify_receipt(&underpaid, &recipient, &provider.public_key()).is_err());
+
+        // Without an amount tag the invoice amount is what was paid
+        let open = zap_request(&payer, &recipient, None);
+        let zap = verify_receipt(
+            &receipt(&provider, &recipient, &invoice("5u", &open), &open),
+            &recipient,
+            &provider.public_key(),
+        )
+        .unwrap();
+        assert_eq!(zap.amount_msats, 500_000);
+
+        // The zap request must be addressed to the recipient too
+        let elsewhere = zap_request(&payer, &Keys::generate().public_key(), Some(21_000));
+        let misdirected = receipt(&provider, &recipient, &invoice("210n", &elsewhere), &elsewhere);
+        assert!(verify_receipt(&misdirected, &recipient, &provider.public_key()).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+use moar::config::MoarConfig;
+use moar::storage::lmdb::LmdbStore;
+use moar::storage::NostrStore;
+use nostr::{EventBuilder, Keys};
+
+fn config(db_path: &std::path::Path) -> MoarConfig {
+    toml::from_str(&format!(
+        r#"
+domain = "example.com"
+port = 8080
+admin_pubkey = "{}"
+
+[relays.outbox]
+name = "Outbox"
+subdomain = "outbox"
+db_path = "{}"
+"#,
+        Keys::generate().public_key().to_hex(),
+        db_path.display()
+    ))
+    .unwrap()
+}
+
+#[test]
+fn compact_refuses_while_the_database_is_open() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = config(dir.path());
+    let server = LmdbStore::new(dir.path()).unwrap();
+    let event = EventBuilder::text_note("hello", []).to_event(&Keys::generate()).unwrap();
+    server.save_event(&event).unwrap();
+
+    let err = moar::cli::compact(&config, "outbox").unwrap_err();
+    assert!(err.to_string().contains("in use"), "{}", err);
+    drop(server);
+
+    moar::cli::compact(&config, "outbox").unwrap();
+    assert_eq!(LmdbStore::new(dir.path()).unwrap().event_count().unwrap(), 1);
+}
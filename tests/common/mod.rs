@@ -1,8 +1,10 @@
-use moar::config::{PolicyConfig, RelayConfig};
+#![allow(dead_code)]
+
+use moar::config::{Nip11Config, PolicyConfig, RelayConfig};
 use moar::policy::PolicyEngine;
 use moar::server::{create_relay_router, RelayState};
 use moar::stats::RelayStats;
-use moar::storage::NostrStore;
+use moar::storage::{NostrStore, SaveResult};
 use nostr::{Event, Filter, JsonUtil, RelayMessage};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
@@ -27,10 +29,12 @@ impl MockStore {
 }
 
 impl NostrStore for MockStore {
-    fn save_event(&self, event: &Event) -> moar::error::Result<()> {
+    fn save_event(&self, event: &Event) -> moar::error::Result<SaveResult> {
         let mut events = self.events.lock().unwrap();
-        events.insert(*event.id.as_bytes(), event.clone());
-        Ok(())
+        match events.insert(*event.id.as_bytes(), event.clone()) {
+            Some(_) => Ok(SaveResult::Duplicate),
+            None => Ok(SaveResult::Saved),
+        }
     }
 
     fn get_event(&self, id: &[u8; 32]) -> moar::error::Result<Option<Event>> {
@@ -94,7 +98,7 @@ impl NostrStore for MockStore {
             .cloned()
             .collect();
 
-        results.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        results.sort_by(moar::storage::newest_first);
         results.truncate(limit);
         Ok(results)
     }
@@ -105,16 +109,37 @@ impl NostrStore for MockStore {
 // ---------------------------------------------------------------------------
 
 pub async fn spawn_relay(policy: PolicyConfig) -> (u16, Arc<MockStore>) {
+    spawn_relay_with_nip11(policy, Nip11Config::default()).await
+}
+
+pub async fn spawn_relay_with_nip11(policy: PolicyConfig, nip11: Nip11Config) -> (u16, Arc<MockStore>) {
+    let (port, store, _state) = spawn_relay_with_state(policy, nip11).await;
+    (port, store)
+}
+
+/// Like `spawn_relay_with_nip11`, also handing back the relay's state.
+pub async fn spawn_relay_with_state(policy: PolicyConfig, nip11: Nip11Config) -> (u16, Arc<MockStore>, Arc<RelayState>) {
     let store = Arc::new(MockStore::new());
     let store_dyn: Arc<dyn NostrStore> = store.clone();
-    let policy_engine = Arc::new(PolicyEngine::new(policy.clone(), Default::default(), None, None, None, None));
+    let policy_engine = Arc::new(PolicyEngine::new(policy.clone(), nip11.clone(), None, None, None, None));
+    let greylist = policy
+        .greylist
+        .clone()
+        .map(|g| Arc::new(moar::greylist::Greylist::in_memory(g)));
     let config = RelayConfig {
         name: "test".into(),
         description: None,
         subdomain: "test".into(),
         db_path: "/tmp/moar-test-unused".into(),
         policy,
-        nip11: Default::default(),
+        nip11,
+        signing_key: false,
+        mirror_to: Vec::new(),
+        sync_from: Vec::new(),
+        broadcast_capacity: None,
+        query_cache: None,
+        skip_verify: false,
+        maintenance_mode: None,
     };
     let state = Arc::new(RelayState::new(
         config,
@@ -128,16 +153,22 @@ pub async fn spawn_relay(policy: PolicyConfig) -> (u16, Arc<MockStore>) {
         None,
         Arc::new(RelayStats::new()),
         Arc::new(moar::rate_limit::IpTracker::new()),
+        None,
+        None,
+        greylist,
+        Arc::new(moar::auth::ReplayGuard::new()),
     ));
-    let app = create_relay_router(state);
+    let app = create_relay_router(state.clone());
 
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let port = listener.local_addr().unwrap().port();
     tokio::spawn(async move {
-        axum::serve(listener, app).await.unwrap();
+        axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+            .await
+            .unwrap();
     });
 
-    (port, store)
+    (port, store, state)
 }
 
 // ---------------------------------------------------------------------------
@@ -220,6 +251,19 @@ impl WsTestClient {
         }
     }
 
+    /// Wait for a CLOSED and return its message.
+    pub async fn expect_closed(&mut self, sub_id: &str) -> String {
+        let text = self.recv_text().await;
+        let msg = RelayMessage::from_json(&text).expect("failed to parse relay message");
+        match msg {
+            RelayMessage::Closed { subscription_id, message } => {
+                assert_eq!(subscription_id.to_string(), sub_id);
+                message
+            }
+            other => panic!("expected CLOSED, got: {:?}", other),
+        }
+    }
+
     pub async fn expect_eose(&mut self) {
         let text = self.recv_text().await;
         let msg = RelayMessage::from_json(&text).expect("failed to parse relay message");
@@ -229,6 +273,25 @@ impl WsTestClient {
         }
     }
 
+    /// Wait for an AUTH challenge and return it.
+    pub async fn expect_auth(&mut self) -> String {
+        let text = self.recv_text().await;
+        let msg = RelayMessage::from_json(&text).expect("failed to parse relay message");
+        match msg {
+            RelayMessage::Auth { challenge } => challenge,
+            other => panic!("expected AUTH, got: {:?}", other),
+        }
+    }
+
+    /// Complete NIP-42 AUTH for `keys` against `challenge`.
+    pub async fn authenticate(&mut self, keys: &nostr::Keys, challenge: &str, port: u16) {
+        let relay = nostr::Url::parse(&format!("ws://127.0.0.1:{}/", port)).unwrap();
+        let event = nostr::EventBuilder::auth(challenge, relay)
+            .to_event(keys)
+            .unwrap();
+        self.send_text(&format!(r#"["AUTH",{}]"#, event.as_json())).await;
+    }
+
     pub async fn expect_event(&mut self) -> Event {
         let text = self.recv_text().await;
         let msg = RelayMessage::from_json(&text).expect("failed to parse relay message");
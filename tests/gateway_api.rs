@@ -0,0 +1,267 @@
+use moar::blossom::store::BlobStore;
+use moar::config::MoarConfig;
+use moar::gateway::start_gateway;
+use moar::invite::InviteManager;
+use moar::paywall::PaywallManager;
+use moar::policy::PolicyEngine;
+use moar::stats::{RelayStats, TimeSeriesRing};
+use moar::storage::lmdb::LmdbStore;
+use moar::storage::NostrStore;
+use moar::webhook::Notifier;
+use moar::wot::WotManager;
+use base64::Engine;
+use nostr::{EventBuilder, JsonUtil, Keys, Kind, Tag};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// Start a gateway for `sections` (relay and blossom tables) on a free port,
+/// wired up the way `moar start` does it.
+async fn spawn_gateway(dir: &std::path::Path, admin: &Keys, sections: &str) -> u16 {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let config_path = dir.join("moar.toml");
+    let toml = format!(
+        r#"
+domain = "localhost"
+port = {port}
+bind_address = "127.0.0.1"
+admin_pubkey = "{}"
+data_dir = "{}"
+pages_dir = "{}"
+{}
+{sections}
+"#,
+        admin.public_key().to_hex(),
+        dir.join("data").display(),
+        dir.join("pages").display(),
+        if sections.contains("[relays.") { "" } else { "relays = {}" },
+    );
+    std::fs::write(&config_path, &toml).unwrap();
+    let config: MoarConfig = toml::from_str(&toml).unwrap();
+
+    let notifier = Arc::new(Notifier::default());
+    let wot_manager = WotManager::new(config.data_path("wot"), Vec::new(), config.wots.clone(), notifier.clone());
+    let paywall_manager =
+        PaywallManager::new(config.data_path("paywall"), config.paywalls.clone(), notifier.clone()).unwrap();
+    let invites = Arc::new(InviteManager::new(config.data_path("invites")));
+
+    let mut relays = HashMap::new();
+    for (key, relay_conf) in config.relays.clone() {
+        let store: Arc<dyn NostrStore> = Arc::new(LmdbStore::new(&relay_conf.db_path).unwrap());
+        let stats = Arc::new(RelayStats::new());
+        let policy =
+            PolicyEngine::for_relay(&key, &relay_conf, &wot_manager, &paywall_manager, &invites, stats.clone()).await;
+        let ts_ring = Arc::new(RwLock::new(TimeSeriesRing::new()));
+        relays.insert(key, (relay_conf, store, Arc::new(policy), stats, ts_ring));
+    }
+    let mut blossoms = HashMap::new();
+    for (key, blossom_conf) in config.blossoms.clone() {
+        let store = Arc::new(BlobStore::from_config(&blossom_conf).unwrap());
+        blossoms.insert(key, (blossom_conf, store));
+    }
+
+    tokio::spawn(start_gateway(
+        port,
+        config.domain.clone(),
+        relays,
+        blossoms,
+        HashMap::new(),
+        config,
+        config_path,
+        wot_manager,
+        paywall_manager,
+        invites,
+        notifier,
+    ));
+
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            return port;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("gateway did not start on port {}", port);
+}
+
+async fn get(port: u16, path: &str) -> (u16, String) {
+    let resp = reqwest::get(format!("http://localhost:{}{}", port, path)).await.unwrap();
+    (resp.status().as_u16(), resp.text().await.unwrap())
+}
+
+/// Send a JSON body with a NIP-98 header signed by `admin`.
+async fn send_as_admin(admin: &Keys, port: u16, method: &str, path: &str, body: &str) -> (u16, String) {
+    let url = format!("http://localhost:{}{}", port, path);
+    send_signed(admin, port, method, path, &url, body, body).await
+}
+
+/// Send `body` with a NIP-98 header that signs `signed_url` and the hash
+/// of `signed_body`, which may differ from the actual request.
+async fn send_signed(
+    keys: &Keys,
+    port: u16,
+    method: &str,
+    path: &str,
+    signed_url: &str,
+    signed_body: &str,
+    body: &str,
+) -> (u16, String) {
+    let event = EventBuilder::new(
+        Kind::from(27235u16),
+        "",
+        [
+            Tag::parse(&["u", signed_url]).unwrap(),
+            Tag::parse(&["method", method]).unwrap(),
+            Tag::parse(&["payload", &format!("{:x}", Sha256::digest(signed_body.as_bytes()))]).unwrap(),
+        ],
+    )
+    .to_event(keys)
+    .unwrap();
+    let auth = base64::engine::general_purpose::STANDARD.encode(event.as_json());
+    let resp = reqwest::Client::new()
+        .request(method.parse().unwrap(), format!("http://localhost:{}{}", port, path))
+        .header("Authorization", format!("Nostr {}", auth))
+        .header("Content-Type", "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .unwrap();
+    (resp.status().as_u16(), resp.text().await.unwrap())
+}
+
+#[tokio::test]
+async fn blossom_listing_never_shows_the_s3_secret() {
+    let dir = tempfile::tempdir().unwrap();
+    let port = spawn_gateway(
+        dir.path(),
+        &Keys::generate(),
+        &format!(
+            r#"
+[blossoms.media]
+name = "Media"
+subdomain = "media"
+storage_path = "{}"
+
+[blossoms.media.s3]
+endpoint = "https://s3.example.com"
+bucket = "media"
+access_key = "AKIDEXAMPLE"
+secret_key = "super-secret-key"
+"#,
+            dir.path().join("media").display()
+        ),
+    )
+    .await;
+
+    for path in ["/api/blossoms", "/api/blossoms/media"] {
+        let (status, body) = get(port, path).await;
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.contains("s3.example.com"), "{}", body);
+        assert!(!body.contains("super-secret-key"), "{} leaked the secret: {}", path, body);
+    }
+}
+
+#[tokio::test]
+async fn relay_listing_hides_moderation_lists_from_the_public() {
+    let dir = tempfile::tempdir().unwrap();
+    let banned = Keys::generate().public_key().to_hex();
+    let blocked = Keys::generate().public_key().to_hex();
+    let port = spawn_gateway(
+        dir.path(),
+        &Keys::generate(),
+        &format!(
+            r#"
+[relays.public]
+name = "Public"
+subdomain = "public"
+db_path = "{}"
+
+[relays.public.policy.write]
+blocked_pubkeys = ["{blocked}"]
+shadow_banned_pubkeys = ["{banned}"]
+"#,
+            dir.path().join("public").display()
+        ),
+    )
+    .await;
+
+    for path in ["/api/relays", "/api/relays/public"] {
+        let (status, body) = get(port, path).await;
+        assert_eq!(status, 200, "{}", body);
+        assert!(body.contains("\"subdomain\":\"public\""), "{}", body);
+        assert!(!body.contains(&banned), "{} shows the shadow ban: {}", path, body);
+        assert!(!body.contains(&blocked), "{} shows the block list: {}", path, body);
+    }
+}
+
+#[tokio::test]
+async fn write_plugins_can_only_be_set_in_the_config_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let admin = Keys::generate();
+    let port = spawn_gateway(
+        dir.path(),
+        &admin,
+        &format!(
+            r#"
+[relays.public]
+name = "Public"
+subdomain = "public"
+db_path = "{}"
+
+[relays.public.policy.write.plugin]
+command = "/usr/local/bin/spam-filter"
+"#,
+            dir.path().join("public").display()
+        ),
+    )
+    .await;
+
+    let (status, body) = get(port, "/api/relays/public").await;
+    assert_eq!(status, 200, "{}", body);
+    assert!(!body.contains("spam-filter"), "{}", body);
+
+    let create = format!(
+        r#"{{"id": "other", "name": "Other", "subdomain": "other", "db_path": "{}",
+            "policy": {{"write": {{"plugin": {{"command": "/bin/sh"}}}}}}}}"#,
+        dir.path().join("other").display()
+    );
+    let (status, body) = send_as_admin(&admin, port, "POST", "/api/relays", &create).await;
+    assert_eq!(status, 400, "{}", body);
+    assert!(body.contains("config file"), "{}", body);
+
+    // Edits that leave the plugin out keep the configured one
+    let update = format!(
+        r#"{{"name": "Renamed", "subdomain": "public", "db_path": "{}"}}"#,
+        dir.path().join("public").display()
+    );
+    let (status, body) = send_as_admin(&admin, port, "PUT", "/api/relays/public", &update).await;
+    assert_eq!(status, 200, "{}", body);
+    let saved: MoarConfig = toml::from_str(&std::fs::read_to_string(dir.path().join("moar.toml")).unwrap()).unwrap();
+    assert_eq!(saved.relays["public"].name, "Renamed");
+    assert_eq!(saved.relays["public"].policy.write.plugin.as_ref().unwrap().command, "/usr/local/bin/spam-filter");
+}
+
+#[tokio::test]
+async fn admin_headers_must_sign_this_instance_and_the_body() {
+    let dir = tempfile::tempdir().unwrap();
+    let admin = Keys::generate();
+    let port = spawn_gateway(dir.path(), &admin, "").await;
+    let url = format!("http://localhost:{}/api/relays", port);
+    let body = format!(
+        r#"{{"id": "new", "name": "New", "subdomain": "new", "db_path": "{}"}}"#,
+        dir.path().join("new").display()
+    );
+
+    // Signed by the same admin key, but for another instance
+    let (status, _) =
+        send_signed(&admin, port, "POST", "/api/relays", "https://other.example.com/api/relays", &body, &body).await;
+    assert_eq!(status, 401);
+
+    // The signed payload doesn't match what was sent
+    let (status, _) = send_signed(&admin, port, "POST", "/api/relays", &url, "{}", &body).await;
+    assert_eq!(status, 401);
+
+    let (status, resp) = send_signed(&admin, port, "POST", "/api/relays", &url, &body, &body).await;
+    assert_eq!(status, 201, "{}", resp);
+}
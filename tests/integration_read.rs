@@ -1,9 +1,9 @@
 mod common;
 
-use common::{spawn_relay, WsTestClient};
-use moar::config::{PolicyConfig, ReadPolicy};
+use common::{spawn_relay, spawn_relay_with_nip11, WsTestClient};
+use moar::config::{Nip11Config, Nip11Fee, Nip11Fees, PolicyConfig, ReadPolicy};
 use moar::storage::NostrStore;
-use nostr::{EventBuilder, Filter, Keys};
+use nostr::{EventBuilder, Filter, Keys, Kind, Tag, TagKind};
 
 fn make_event(keys: &Keys, content: &str) -> nostr::Event {
     EventBuilder::text_note(content, [])
@@ -43,7 +43,7 @@ async fn req_on_empty_store_returns_only_eose() {
 }
 
 #[tokio::test]
-async fn read_require_auth_returns_notice_auth_required() {
+async fn read_require_auth_returns_closed_auth_required() {
     let policy = PolicyConfig {
         read: ReadPolicy {
             require_auth: true,
@@ -57,16 +57,16 @@ async fn read_require_auth_returns_notice_auth_required() {
     let filter = Filter::new();
     client.send_req("sub1", vec![filter]).await;
 
-    let notice = client.expect_notice().await;
+    let reason = client.expect_closed("sub1").await;
     assert!(
-        notice.contains("auth-required"),
-        "notice should contain 'auth-required': {}",
-        notice
+        reason.starts_with("auth-required:"),
+        "CLOSED should start with 'auth-required:': {}",
+        reason
     );
 }
 
 #[tokio::test]
-async fn read_allow_list_without_auth_returns_notice_blocked() {
+async fn read_allow_list_without_auth_returns_closed_blocked() {
     let keys = Keys::generate();
     let policy = PolicyConfig {
         read: ReadPolicy {
@@ -74,6 +74,7 @@ async fn read_allow_list_without_auth_returns_notice_blocked() {
             allowed_pubkeys: Some(vec![keys.public_key().to_string()]),
             wot: None,
             paywall: None,
+            protected_kinds: None,
         },
         ..Default::default()
     };
@@ -83,10 +84,181 @@ async fn read_allow_list_without_auth_returns_notice_blocked() {
     let filter = Filter::new();
     client.send_req("sub1", vec![filter]).await;
 
-    let notice = client.expect_notice().await;
+    let reason = client.expect_closed("sub1").await;
     assert!(
-        notice.contains("blocked"),
-        "notice should contain 'blocked': {}",
-        notice
+        reason.starts_with("blocked:"),
+        "CLOSED should start with 'blocked:': {}",
+        reason
     );
 }
+
+#[tokio::test]
+async fn protected_kinds_only_delivered_to_authed_participants() {
+    let policy = PolicyConfig {
+        read: ReadPolicy {
+            protected_kinds: Some(vec![4]),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (port, store) = spawn_relay(policy).await;
+
+    let sender = Keys::generate();
+    let recipient = Keys::generate();
+    let outsider = Keys::generate();
+    let dm = EventBuilder::new(Kind::EncryptedDirectMessage, "ciphertext", [Tag::public_key(recipient.public_key())])
+        .to_event(&sender)
+        .unwrap();
+    store.save_event(&dm).unwrap();
+
+    // Anonymous: a query for only protected kinds asks for AUTH
+    let mut client = WsTestClient::connect(port).await;
+    client.send_req("dms", vec![Filter::new().kind(Kind::EncryptedDirectMessage)]).await;
+    let reason = client.expect_closed("dms").await;
+    assert!(reason.starts_with("auth-required:"), "unexpected CLOSED: {}", reason);
+    let challenge = client.expect_auth().await;
+
+    // Anonymous: broad queries silently omit protected events
+    client.send_req("all", vec![Filter::new()]).await;
+    client.expect_eose().await;
+
+    // Authenticated outsider still sees nothing
+    client.authenticate(&outsider, &challenge, port).await;
+    assert!(client.expect_ok().await.0);
+    client.send_req("all", vec![Filter::new()]).await;
+    client.expect_eose().await;
+
+    // Authenticated recipient receives the DM
+    let mut client = WsTestClient::connect(port).await;
+    client.send_req("dms", vec![Filter::new().kind(Kind::EncryptedDirectMessage)]).await;
+    client.expect_closed("dms").await;
+    let challenge = client.expect_auth().await;
+    client.authenticate(&recipient, &challenge, port).await;
+    assert!(client.expect_ok().await.0);
+    client.send_req("dms", vec![Filter::new().kind(Kind::EncryptedDirectMessage)]).await;
+    assert_eq!(client.expect_event().await.id, dm.id);
+    client.expect_eose().await;
+}
+
+#[tokio::test]
+async fn req_results_are_capped_across_filters_with_notice() {
+    let nip11 = Nip11Config {
+        max_events_per_req: Some(3),
+        ..Default::default()
+    };
+    let (port, store) = spawn_relay_with_nip11(PolicyConfig::default(), nip11).await;
+
+    let keys = Keys::generate();
+    for i in 0..5 {
+        store.save_event(&make_event(&keys, &format!("note {}", i))).unwrap();
+    }
+
+    let mut client = WsTestClient::connect(port).await;
+    client
+        .send_req(
+            "sub1",
+            vec![
+                Filter::new().kind(Kind::TextNote).limit(2),
+                Filter::new().author(keys.public_key()),
+            ],
+        )
+        .await;
+
+    for _ in 0..3 {
+        client.expect_event().await;
+    }
+    let notice = client.expect_notice().await;
+    assert!(notice.contains("truncated"), "unexpected notice: {}", notice);
+    client.expect_eose().await;
+}
+
+#[tokio::test]
+async fn overlapping_filters_return_each_event_once() {
+    let (port, store) = spawn_relay(PolicyConfig::default()).await;
+
+    let keys = Keys::generate();
+    let event = make_event(&keys, "matches both filters");
+    store.save_event(&event).unwrap();
+
+    let mut client = WsTestClient::connect(port).await;
+    client
+        .send_req(
+            "sub1",
+            vec![
+                Filter::new().author(keys.public_key()),
+                Filter::new().kind(Kind::TextNote),
+            ],
+        )
+        .await;
+
+    let received = client.expect_event().await;
+    assert_eq!(received.id, event.id);
+    client.expect_eose().await;
+}
+
+#[tokio::test]
+async fn nip11_document_includes_community_fields() {
+    let nip11 = Nip11Config {
+        relay_countries: Some(vec!["DE".to_string()]),
+        language_tags: Some(vec!["en".to_string()]),
+        posting_policy: Some("https://relay.example.com/policy".to_string()),
+        fees: Some(Nip11Fees {
+            admission: vec![Nip11Fee {
+                amount: 21000,
+                unit: "msats".to_string(),
+                period: None,
+                kinds: None,
+            }],
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (port, _store) = spawn_relay_with_nip11(PolicyConfig::default(), nip11).await;
+
+    let body = reqwest::Client::new()
+        .get(format!("http://127.0.0.1:{}/", port))
+        .header("Accept", "application/nostr+json")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let doc: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(doc["relay_countries"], serde_json::json!(["DE"]));
+    assert_eq!(doc["language_tags"], serde_json::json!(["en"]));
+    assert_eq!(doc["posting_policy"], "https://relay.example.com/policy");
+    assert_eq!(doc["fees"]["admission"][0]["amount"], 21000);
+    assert!(doc.get("payments_url").is_none());
+}
+
+#[tokio::test]
+async fn req_on_multi_letter_tag_returns_only_matching_events() {
+    let (port, store) = spawn_relay(PolicyConfig::default()).await;
+    let keys = Keys::generate();
+    let proxied = |url: &str, content: &str| {
+        EventBuilder::text_note(content, [Tag::custom(TagKind::Custom("proxy".into()), [url, "activitypub"])])
+            .to_event(&keys)
+            .unwrap()
+    };
+    let wanted = proxied("https://example.com/1", "stored");
+    store.save_event(&wanted).unwrap();
+    store.save_event(&proxied("https://example.com/2", "stored")).unwrap();
+    store.save_event(&make_event(&keys, "plain")).unwrap();
+
+    let mut client = WsTestClient::connect(port).await;
+    client
+        .send_text(r##"["REQ","proxy",{"kinds":[1],"#proxy":["https://example.com/1"]}]"##)
+        .await;
+    assert_eq!(client.expect_event().await.id, wanted.id);
+    client.expect_eose().await;
+
+    // Live events are held to the same condition
+    let mut writer = WsTestClient::connect(port).await;
+    writer.send_event(&make_event(&keys, "not bridged")).await;
+    assert!(writer.expect_ok().await.0);
+    let live = proxied("https://example.com/1", "live");
+    writer.send_event(&live).await;
+    assert!(writer.expect_ok().await.0);
+    assert_eq!(client.expect_event().await.id, live.id);
+}
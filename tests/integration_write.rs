@@ -1,8 +1,10 @@
 mod common;
 
-use common::{spawn_relay, WsTestClient};
-use moar::config::{EventPolicy, PolicyConfig, WritePolicy};
-use nostr::{EventBuilder, Keys, Kind};
+use common::{spawn_relay, spawn_relay_with_state, WsTestClient};
+use moar::config::{EventPolicy, GreylistConfig, MaintenanceMode, PolicyConfig, RateLimitConfig, WritePolicy};
+use moar::policy::PolicyEngine;
+use moar::storage::NostrStore;
+use nostr::{EventBuilder, Filter, JsonUtil, Keys, Kind, ToBech32};
 
 fn make_event(keys: &Keys, content: &str) -> nostr::Event {
     EventBuilder::text_note(content, [])
@@ -10,6 +12,7 @@ fn make_event(keys: &Keys, content: &str) -> nostr::Event {
         .unwrap()
 }
 
+#[allow(dead_code)]
 fn make_event_kind(keys: &Keys, kind: u16, content: &str) -> nostr::Event {
     EventBuilder::new(Kind::from(kind), content, [])
         .to_event(keys)
@@ -29,6 +32,79 @@ async fn open_relay_accepts_event() {
     assert!(status, "open relay should accept event");
 }
 
+#[tokio::test]
+async fn forged_events_are_rejected() {
+    let (port, store) = spawn_relay(PolicyConfig::default()).await;
+    let mut client = WsTestClient::connect(port).await;
+    let keys = Keys::generate();
+
+    // Content changed after signing: the id no longer matches
+    let mut tampered: serde_json::Value = serde_json::from_str(&make_event(&keys, "original").as_json()).unwrap();
+    tampered["content"] = "forged".into();
+    client.send_text(&format!(r#"["EVENT",{}]"#, tampered)).await;
+    let (status, msg) = client.expect_ok().await;
+    assert!(!status);
+    assert!(msg.starts_with("invalid:"), "got {:?}", msg);
+
+    // Signature from another key
+    let mut stolen: serde_json::Value = serde_json::from_str(&make_event(&keys, "mine").as_json()).unwrap();
+    let other: serde_json::Value = serde_json::from_str(&make_event(&Keys::generate(), "mine").as_json()).unwrap();
+    stolen["sig"] = other["sig"].clone();
+    client.send_text(&format!(r#"["EVENT",{}]"#, stolen)).await;
+    let (status, msg) = client.expect_ok().await;
+    assert!(!status);
+    assert_eq!(msg, "invalid: bad signature");
+
+    assert_eq!(store.event_count().unwrap(), 0);
+}
+
+#[tokio::test]
+async fn shadow_banned_events_are_acknowledged_but_dropped() {
+    let spammer = Keys::generate();
+    let policy = PolicyConfig {
+        write: WritePolicy {
+            shadow_banned_pubkeys: Some(vec![spammer.public_key().to_bech32().unwrap()]),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (port, store) = spawn_relay(policy).await;
+    let mut watcher = WsTestClient::connect(port).await;
+    watcher.send_req("live", vec![Filter::new().kind(Kind::TextNote)]).await;
+    watcher.expect_eose().await;
+
+    let mut client = WsTestClient::connect(port).await;
+    client.send_event(&make_event(&spammer, "buy now")).await;
+    let (status, msg) = client.expect_ok().await;
+    assert!(status);
+    assert_eq!(msg, "");
+
+    let honest = make_event(&Keys::generate(), "hello");
+    client.send_event(&honest).await;
+    assert!(client.expect_ok().await.0);
+
+    // The watcher only ever sees the honest event
+    assert_eq!(watcher.expect_event().await.id, honest.id);
+    assert_eq!(store.event_count().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn resent_event_is_acknowledged_as_duplicate() {
+    let (port, _store) = spawn_relay(PolicyConfig::default()).await;
+    let mut client = WsTestClient::connect(port).await;
+
+    let event = make_event(&Keys::generate(), "hello again");
+    client.send_event(&event).await;
+    let (status, msg) = client.expect_ok().await;
+    assert!(status);
+    assert_eq!(msg, "");
+
+    client.send_event(&event).await;
+    let (status, msg) = client.expect_ok().await;
+    assert!(status, "duplicates are still OK");
+    assert!(msg.starts_with("duplicate:"), "got {:?}", msg);
+}
+
 #[tokio::test]
 async fn allow_list_rejects_unknown_pubkey() {
     let allowed_keys = Keys::generate();
@@ -113,6 +189,55 @@ async fn content_too_long_returns_ok_false() {
     assert!(msg.contains("blocked"), "message should contain 'blocked': {}", msg);
 }
 
+#[tokio::test]
+async fn pubkey_write_limit_is_per_author() {
+    let policy = PolicyConfig {
+        rate_limit: RateLimitConfig {
+            pubkey_writes_per_minute: Some(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (port, _store) = spawn_relay(policy).await;
+    let mut client = WsTestClient::connect(port).await;
+
+    let alice = Keys::generate();
+    client.send_event(&make_event(&alice, "one")).await;
+    assert!(client.expect_ok().await.0);
+    client.send_event(&make_event(&alice, "two")).await;
+    let (status, msg) = client.expect_ok().await;
+    assert!(!status);
+    assert!(msg.starts_with("rate-limited"), "{}", msg);
+
+    // Another author on the same connection has its own budget
+    client.send_event(&make_event(&Keys::generate(), "three")).await;
+    assert!(client.expect_ok().await.0);
+}
+
+#[tokio::test]
+async fn greylisted_pubkey_is_throttled() {
+    let policy = PolicyConfig {
+        greylist: Some(GreylistConfig {
+            first_events: 5,
+            first_secs: 0,
+            writes_per_minute: Some(1),
+            min_pow: None,
+        }),
+        ..Default::default()
+    };
+    let (port, _store) = spawn_relay(policy).await;
+    let mut client = WsTestClient::connect(port).await;
+
+    let keys = Keys::generate();
+    client.send_event(&make_event(&keys, "first")).await;
+    assert!(client.expect_ok().await.0);
+
+    client.send_event(&make_event(&keys, "second")).await;
+    let (status, msg) = client.expect_ok().await;
+    assert!(!status);
+    assert!(msg.contains("greylisted"), "{}", msg);
+}
+
 #[tokio::test]
 async fn auth_required_returns_ok_false() {
     let policy = PolicyConfig {
@@ -137,3 +262,141 @@ async fn auth_required_returns_ok_false() {
         msg
     );
 }
+
+#[tokio::test]
+async fn accepted_event_is_delivered_to_matching_live_subscriptions() {
+    let (port, _store) = spawn_relay(PolicyConfig::default()).await;
+    let author = Keys::generate();
+
+    let mut subscriber = WsTestClient::connect(port).await;
+    subscriber
+        .send_req("notes", vec![nostr::Filter::new().author(author.public_key())])
+        .await;
+    subscriber.expect_eose().await;
+
+    let mut publisher = WsTestClient::connect(port).await;
+    let other = make_event(&Keys::generate(), "not for this subscription");
+    publisher.send_event(&other).await;
+    assert!(publisher.expect_ok().await.0);
+    let event = make_event(&author, "live");
+    publisher.send_event(&event).await;
+    assert!(publisher.expect_ok().await.0);
+
+    let received = subscriber.expect_event().await;
+    assert_eq!(received.id, event.id);
+}
+
+#[tokio::test]
+async fn req_with_existing_id_replaces_its_filters() {
+    let (port, _store) = spawn_relay(PolicyConfig::default()).await;
+    let old_author = Keys::generate();
+    let new_author = Keys::generate();
+
+    let mut subscriber = WsTestClient::connect(port).await;
+    subscriber
+        .send_req("feed", vec![nostr::Filter::new().author(old_author.public_key())])
+        .await;
+    subscriber.expect_eose().await;
+    subscriber
+        .send_req("feed", vec![nostr::Filter::new().author(new_author.public_key())])
+        .await;
+    subscriber.expect_eose().await;
+
+    let mut publisher = WsTestClient::connect(port).await;
+    publisher.send_event(&make_event(&old_author, "no longer followed")).await;
+    assert!(publisher.expect_ok().await.0);
+    let event = make_event(&new_author, "followed");
+    publisher.send_event(&event).await;
+    assert!(publisher.expect_ok().await.0);
+
+    assert_eq!(subscriber.expect_event().await.id, event.id);
+}
+
+#[tokio::test]
+async fn reloaded_policy_applies_to_open_connections() {
+    let (port, store, state) = spawn_relay_with_state(PolicyConfig::default(), Default::default()).await;
+    let mut client = WsTestClient::connect(port).await;
+    let keys = Keys::generate();
+
+    client.send_event(&make_event(&keys, "before")).await;
+    assert!(client.expect_ok().await.0);
+
+    let mut config = (*state.config()).clone();
+    config.policy.events = EventPolicy {
+        blocked_kinds: Some(vec![1]),
+        ..Default::default()
+    };
+    let policy = PolicyEngine::new(config.policy.clone(), config.nip11.clone(), None, None, None, None);
+    state.reload(config, policy);
+
+    // Same socket, new rules
+    client.send_event(&make_event(&keys, "after")).await;
+    let (status, msg) = client.expect_ok().await;
+    assert!(!status, "blocked kind accepted after reload: {msg}");
+    assert_eq!(store.event_count().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn maintenance_mode_refuses_writes_and_optionally_reads() {
+    let (port, store, state) = spawn_relay_with_state(PolicyConfig::default(), Default::default()).await;
+    let mut client = WsTestClient::connect(port).await;
+    let keys = Keys::generate();
+
+    state.set_maintenance(Some(MaintenanceMode::ReadOnly));
+    client.send_event(&make_event(&keys, "during import")).await;
+    let (status, msg) = client.expect_ok().await;
+    assert!(!status);
+    assert_eq!(msg, "blocked: relay in maintenance");
+    client.send_req("reads", vec![Filter::new()]).await;
+    client.expect_eose().await;
+
+    state.set_maintenance(Some(MaintenanceMode::Paused));
+    client.send_req("paused", vec![Filter::new()]).await;
+    assert_eq!(client.expect_closed("paused").await, "blocked: relay in maintenance");
+
+    state.set_maintenance(None);
+    client.send_event(&make_event(&keys, "after")).await;
+    assert!(client.expect_ok().await.0);
+    assert_eq!(store.event_count().unwrap(), 1);
+}
+
+/// Poll until `store` holds `count` events, failing after a few seconds.
+async fn wait_for_events(store: &common::MockStore, count: usize) {
+    for _ in 0..100 {
+        if store.event_count().unwrap() as usize == count {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("expected {} events, have {}", count, store.event_count().unwrap());
+}
+
+#[tokio::test]
+async fn inbound_sync_waits_out_maintenance() {
+    let (upstream_port, upstream, _) = spawn_relay_with_state(PolicyConfig::default(), Default::default()).await;
+    let (_, store, state) = spawn_relay_with_state(PolicyConfig::default(), Default::default()).await;
+    let keys = Keys::generate();
+    upstream.save_event(&make_event(&keys, "backfilled")).unwrap();
+
+    let mut config = (*state.config()).clone();
+    config.sync_from = vec![moar::config::SyncSource {
+        url: format!("ws://127.0.0.1:{}", upstream_port),
+        filters: Vec::new(),
+    }];
+    let policy = PolicyEngine::new(config.policy.clone(), config.nip11.clone(), None, None, None, None);
+    state.reload(config, policy);
+    moar::sync::start(state.clone());
+    wait_for_events(&store, 1).await;
+
+    // Live upstream events are left alone while the relay is in maintenance
+    state.set_maintenance(Some(MaintenanceMode::ReadOnly));
+    let mut publisher = WsTestClient::connect(upstream_port).await;
+    publisher.send_event(&make_event(&keys, "during import")).await;
+    assert!(publisher.expect_ok().await.0);
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    assert_eq!(store.event_count().unwrap(), 1);
+
+    // and fetched once it ends
+    state.set_maintenance(None);
+    wait_for_events(&store, 2).await;
+}